@@ -455,7 +455,7 @@ fn clarity2_implicit_cast(type_sig: &TypeSignature, value: &Value) -> Result<Val
                 type_signature.get_max_len(),
             )?;
             Value::Sequence(SequenceData::List(ListData {
-                data: values,
+                data: values.into(),
                 type_signature: cast_list_type_data,
             }))
         }
@@ -481,7 +481,7 @@ fn clarity2_implicit_cast(type_sig: &TypeSignature, value: &Value) -> Result<Val
             }
             Value::Tuple(TupleData {
                 type_signature: tuple_type.clone(),
-                data_map: cast_data_map,
+                data_map: cast_data_map.into(),
             })
         }
         (
@@ -613,7 +613,7 @@ mod test {
                 TypeSignature::PrincipalType,
             )])
             .unwrap(),
-            data_map,
+            data_map: data_map.into(),
         });
         let cast_tuple = clarity2_implicit_cast(&tuple_ty, &tuple_contract).unwrap();
         let cast_trait = cast_tuple
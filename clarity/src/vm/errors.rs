@@ -60,6 +60,8 @@ pub enum InterpreterError {
     BadFileName,
     FailedToCreateDataDirectory,
     MarfFailure(String),
+    /// The backing store has discarded (pruned) the state at this block
+    PrunedState(String),
     FailureConstructingTupleWithType,
     FailureConstructingListWithType,
     InsufficientBalance,
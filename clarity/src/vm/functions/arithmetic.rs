@@ -67,7 +67,9 @@ impl UTF8Ops {
 
 impl BuffOps {
     fn make_value(x: Vec<u8>) -> InterpreterResult<Value> {
-        Ok(Value::Sequence(SequenceData::Buffer(BuffData { data: x })))
+        Ok(Value::Sequence(SequenceData::Buffer(BuffData {
+            data: x.into(),
+        })))
     }
 }
 
@@ -121,7 +123,7 @@ macro_rules! type_force_binary_comparison_v2 {
             (
                 Value::Sequence(SequenceData::Buffer(BuffData { data: x })),
                 Value::Sequence(SequenceData::Buffer(BuffData { data: y })),
-            ) => BuffOps::$function(x, y),
+            ) => BuffOps::$function(x.into_inner(), y.into_inner()),
             (x, _) => Err(CheckErrors::UnionTypeValueError(
                 vec![
                     TypeSignature::IntType,
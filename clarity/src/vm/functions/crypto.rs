@@ -44,7 +44,7 @@ macro_rules! native_hash_func {
             let bytes = match input {
                 Value::Int(value) => Ok(value.to_le_bytes().to_vec()),
                 Value::UInt(value) => Ok(value.to_le_bytes().to_vec()),
-                Value::Sequence(SequenceData::Buffer(value)) => Ok(value.data),
+                Value::Sequence(SequenceData::Buffer(value)) => Ok(value.data.into_inner()),
                 _ => Err(CheckErrors::UnionTypeValueError(
                     vec![
                         TypeSignature::IntType,
@@ -447,7 +447,7 @@ pub fn special_at_block(
     let bhh = match eval(&args[0], env, context)? {
         Value::Sequence(SequenceData::Buffer(BuffData { data })) => {
             if data.len() != 32 {
-                return Err(RuntimeErrorType::BadBlockHash(data).into());
+                return Err(RuntimeErrorType::BadBlockHash(data.into_inner()).into());
             } else {
                 StacksBlockId::from(data.as_slice())
             }
@@ -765,7 +765,7 @@ pub fn special_get_block_info(
                 .database
                 .get_block_vrf_seed(height_value)?;
             Value::Sequence(SequenceData::Buffer(BuffData {
-                data: vrf_seed.as_bytes().to_vec(),
+                data: vrf_seed.as_bytes().to_vec().into(),
             }))
         }
         BlockInfoProperty::HeaderHash => {
@@ -774,7 +774,7 @@ pub fn special_get_block_info(
                 .database
                 .get_block_header_hash(height_value)?;
             Value::Sequence(SequenceData::Buffer(BuffData {
-                data: header_hash.as_bytes().to_vec(),
+                data: header_hash.as_bytes().to_vec().into(),
             }))
         }
         BlockInfoProperty::BurnchainHeaderHash => {
@@ -783,7 +783,7 @@ pub fn special_get_block_info(
                 .database
                 .get_burnchain_block_header_hash(height_value)?;
             Value::Sequence(SequenceData::Buffer(BuffData {
-                data: burnchain_header_hash.as_bytes().to_vec(),
+                data: burnchain_header_hash.as_bytes().to_vec().into(),
             }))
         }
         BlockInfoProperty::IdentityHeaderHash => {
@@ -792,7 +792,7 @@ pub fn special_get_block_info(
                 .database
                 .get_index_block_header_hash(height_value)?;
             Value::Sequence(SequenceData::Buffer(BuffData {
-                data: id_header_hash.as_bytes().to_vec(),
+                data: id_header_hash.as_bytes().to_vec().into(),
             }))
         }
         BlockInfoProperty::MinerAddress => {
@@ -882,7 +882,7 @@ pub fn special_get_burn_block_info(
             match burnchain_header_hash_opt {
                 Some(burnchain_header_hash) => {
                     Value::some(Value::Sequence(SequenceData::Buffer(BuffData {
-                        data: burnchain_header_hash.as_bytes().to_vec(),
+                        data: burnchain_header_hash.as_bytes().to_vec().into(),
                     })))
                 }
                 None => Ok(Value::none()),
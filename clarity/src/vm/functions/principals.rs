@@ -91,13 +91,13 @@ fn create_principal_destruct_tuple(
             (
                 "version".into(),
                 Value::Sequence(SequenceData::Buffer(BuffData {
-                    data: vec![version],
+                    data: vec![version].into(),
                 })),
             ),
             (
                 "hash-bytes".into(),
                 Value::Sequence(SequenceData::Buffer(BuffData {
-                    data: hash_bytes.to_vec(),
+                    data: hash_bytes.to_vec().into(),
                 })),
             ),
             (
@@ -92,11 +92,11 @@ pub fn special_fold(
     let function_name = args[0].match_atom().ok_or(CheckErrors::ExpectedName)?;
 
     let function = lookup_function(function_name, env)?;
-    let mut sequence = eval(&args[1], env, context)?;
+    let sequence = eval(&args[1], env, context)?;
     let initial = eval(&args[2], env, context)?;
 
     match sequence {
-        Value::Sequence(ref mut sequence_data) => sequence_data
+        Value::Sequence(ref sequence_data) => sequence_data
             .atom_values()?
             .into_iter()
             .try_fold(initial, |acc, x| {
@@ -129,9 +129,9 @@ pub fn special_map(
     let mut mapped_func_args = vec![];
     let mut min_args_len = usize::MAX;
     for map_arg in args[1..].iter() {
-        let mut sequence = eval(map_arg, env, context)?;
+        let sequence = eval(map_arg, env, context)?;
         match sequence {
-            Value::Sequence(ref mut sequence_data) => {
+            Value::Sequence(ref sequence_data) => {
                 min_args_len = min_args_len.min(sequence_data.len());
                 for (apply_index, value) in sequence_data.atom_values()?.into_iter().enumerate() {
                     if apply_index > min_args_len {
@@ -41,6 +41,8 @@ pub mod version;
 
 pub mod coverage;
 
+pub mod mutation;
+
 pub mod events;
 
 #[cfg(any(test, feature = "testing"))]
@@ -0,0 +1,274 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2023 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Mutant generation for mutation testing of Clarity contracts.
+//!
+//! Each mutant is a copy of the contract source with a single small change that a good test
+//! suite should notice.  Mutants are generated from the lexer's token stream, so source spans
+//! are available without the `developer-mode` feature, and mutated sources keep the original
+//! formatting and comments.
+
+use std::fmt;
+
+use crate::vm::ast::parser::v2::lexer::error::LexerError;
+use crate::vm::ast::parser::v2::lexer::token::{PlacedToken, Token};
+use crate::vm::ast::parser::v2::lexer::Lexer;
+use crate::vm::representations::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MutationOperator {
+    /// `<` to `>=`, `<=` to `>`, `>` to `<=`, and `>=` to `<`
+    FlipComparison,
+    /// `+` to `-` and `-` to `+`
+    SwapArithmetic,
+    /// the condition of an `asserts!` to `true`
+    RemoveAssertion,
+    /// a `map-set` call to `true`
+    DropMapSet,
+    /// `ok` to `err`
+    OkToErr,
+}
+
+impl fmt::Display for MutationOperator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            MutationOperator::FlipComparison => "flip-comparison",
+            MutationOperator::SwapArithmetic => "swap-arithmetic",
+            MutationOperator::RemoveAssertion => "remove-assertion",
+            MutationOperator::DropMapSet => "drop-map-set",
+            MutationOperator::OkToErr => "ok-to-err",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mutant {
+    pub operator: MutationOperator,
+    /// span of the replaced code in the original source (1-based, inclusive)
+    pub span: Span,
+    pub original: String,
+    pub replacement: String,
+    /// the mutated contract source
+    pub source: String,
+}
+
+/// A token or parenthesized expression.  Tuple literals (`{ ... }`) are not mutated, but are
+/// nested so that their contents are still visited.
+enum Node {
+    Token(PlacedToken),
+    List { span: Span, children: Vec<Node> },
+}
+
+impl Node {
+    fn span(&self) -> &Span {
+        match self {
+            Node::Token(placed) => &placed.span,
+            Node::List { span, .. } => span,
+        }
+    }
+}
+
+fn read_nodes(source: &str) -> Result<Vec<Node>, LexerError> {
+    let mut lexer = Lexer::new(source, true)?;
+    // stack of (open delimiter span, children)
+    let mut stack: Vec<(Span, Vec<Node>)> = vec![(Span::ZERO, vec![])];
+    loop {
+        let placed = lexer.read_token()?;
+        match placed.token {
+            Token::Eof => break,
+            Token::Whitespace | Token::Comment(_) => {}
+            Token::Lparen | Token::Lbrace => stack.push((placed.span, vec![])),
+            Token::Rparen | Token::Rbrace => {
+                // unbalanced input will fail to parse anyway; just skip the stray delimiter
+                if stack.len() > 1 {
+                    let (open, children) = stack.pop().expect("BUG: empty node stack");
+                    let span = Span {
+                        start_line: open.start_line,
+                        start_column: open.start_column,
+                        end_line: placed.span.end_line,
+                        end_column: placed.span.end_column,
+                    };
+                    if let Some((_, parent)) = stack.last_mut() {
+                        parent.push(Node::List { span, children });
+                    }
+                }
+            }
+            _ => {
+                if let Some((_, parent)) = stack.last_mut() {
+                    parent.push(Node::Token(placed));
+                }
+            }
+        }
+    }
+    Ok(stack.swap_remove(0).1)
+}
+
+/// Maps lexer spans to byte ranges in the source.  The lexer only accepts ASCII input, so
+/// columns and bytes line up.
+struct SourceMap<'a> {
+    source: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> SourceMap<'a> {
+    fn new(source: &'a str) -> SourceMap<'a> {
+        let mut line_starts = vec![0];
+        for (i, ch) in source.char_indices() {
+            if ch == '\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        SourceMap {
+            source,
+            line_starts,
+        }
+    }
+
+    fn range(&self, span: &Span) -> Option<(usize, usize)> {
+        let start = self
+            .line_starts
+            .get((span.start_line as usize).checked_sub(1)?)?
+            + (span.start_column as usize).checked_sub(1)?;
+        let end = self
+            .line_starts
+            .get((span.end_line as usize).checked_sub(1)?)?
+            + span.end_column as usize;
+        if start < end && end <= self.source.len() {
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+
+    fn mutant(&self, operator: MutationOperator, span: &Span, replacement: &str) -> Option<Mutant> {
+        let (start, end) = self.range(span)?;
+        let original = self.source.get(start..end)?;
+        let mut source = String::with_capacity(self.source.len() + replacement.len());
+        source.push_str(&self.source[..start]);
+        source.push_str(replacement);
+        source.push_str(&self.source[end..]);
+        Some(Mutant {
+            operator,
+            span: span.clone(),
+            original: original.to_string(),
+            replacement: replacement.to_string(),
+            source,
+        })
+    }
+}
+
+fn visit(node: &Node, source_map: &SourceMap, mutants: &mut Vec<Mutant>) {
+    let Node::List { span, children } = node else {
+        return;
+    };
+    if let Some(Node::Token(head)) = children.first() {
+        let mutant = match &head.token {
+            Token::Less => source_map.mutant(MutationOperator::FlipComparison, &head.span, ">="),
+            Token::LessEqual => {
+                source_map.mutant(MutationOperator::FlipComparison, &head.span, ">")
+            }
+            Token::Greater => source_map.mutant(MutationOperator::FlipComparison, &head.span, "<="),
+            Token::GreaterEqual => {
+                source_map.mutant(MutationOperator::FlipComparison, &head.span, "<")
+            }
+            Token::Plus => source_map.mutant(MutationOperator::SwapArithmetic, &head.span, "-"),
+            Token::Minus => source_map.mutant(MutationOperator::SwapArithmetic, &head.span, "+"),
+            Token::Ident(name) if name == "asserts!" => children.get(1).and_then(|condition| {
+                source_map.mutant(MutationOperator::RemoveAssertion, condition.span(), "true")
+            }),
+            Token::Ident(name) if name == "map-set" => {
+                source_map.mutant(MutationOperator::DropMapSet, span, "true")
+            }
+            Token::Ident(name) if name == "ok" => {
+                source_map.mutant(MutationOperator::OkToErr, &head.span, "err")
+            }
+            _ => None,
+        };
+        mutants.extend(mutant);
+    }
+    for child in children.iter() {
+        visit(child, source_map, mutants);
+    }
+}
+
+/// Generate all the mutants of a contract, in source order.
+pub fn generate_mutants(source: &str) -> Result<Vec<Mutant>, LexerError> {
+    let nodes = read_nodes(source)?;
+    let source_map = SourceMap::new(source);
+    let mut mutants = vec![];
+    for node in nodes.iter() {
+        visit(node, &source_map, &mut mutants);
+    }
+    Ok(mutants)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_mutants() {
+        let source = "(define-map m uint uint)
+;; (map-set m u0 u0) is a comment
+(define-public (f (a uint) (b uint))
+  (begin
+    (asserts! (< a b) (err u1))
+    (map-set m a { x: (+ a b) })
+    (ok (- b a))))";
+        let mutants = generate_mutants(source).unwrap();
+        let summary: Vec<_> = mutants
+            .iter()
+            .map(|m| {
+                (
+                    m.operator,
+                    m.original.as_str(),
+                    m.replacement.as_str(),
+                    m.span.start_line,
+                    m.span.start_column,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (MutationOperator::RemoveAssertion, "(< a b)", "true", 5, 15),
+                (MutationOperator::FlipComparison, "<", ">=", 5, 16),
+                (
+                    MutationOperator::DropMapSet,
+                    "(map-set m a { x: (+ a b) })",
+                    "true",
+                    6,
+                    5
+                ),
+                (MutationOperator::SwapArithmetic, "+", "-", 6, 24),
+                (MutationOperator::OkToErr, "ok", "err", 7, 6),
+                (MutationOperator::SwapArithmetic, "-", "+", 7, 10),
+            ]
+        );
+        assert_eq!(
+            mutants[0].source,
+            source.replace("(asserts! (< a b)", "(asserts! true")
+        );
+        assert_eq!(mutants[5].source, source.replace("(- b a)", "(+ b a)"));
+    }
+
+    #[test]
+    fn test_generate_mutants_lexer_error() {
+        assert!(generate_mutants("(ok \"unterminated)").is_err());
+    }
+}
@@ -343,7 +343,7 @@ fn test_get_list_max_len() {
             data,
             type_signature,
         })) => {
-            assert_eq!(vec![Value::Int(1), Value::Int(2), Value::Int(3)], data);
+            assert_eq!(vec![Value::Int(1), Value::Int(2), Value::Int(3)], *data);
             assert_eq!(
                 "(list 10 int)",
                 &format!("{}", TypeSignature::from(type_signature))
@@ -309,13 +309,13 @@ fn create_principal_destruct_tuple_from_strings(
             (
                 "version".into(),
                 Value::Sequence(SequenceData::Buffer(BuffData {
-                    data: hex_bytes(version).unwrap(),
+                    data: hex_bytes(version).unwrap().into(),
                 })),
             ),
             (
                 "hash-bytes".into(),
                 Value::Sequence(SequenceData::Buffer(BuffData {
-                    data: hex_bytes(hash_bytes).unwrap(),
+                    data: hex_bytes(hash_bytes).unwrap().into(),
                 })),
             ),
             (
@@ -952,7 +952,7 @@ fn test_principal_construct_check_errors() {
         Err(CheckErrors::TypeValueError(
             BUFF_1.clone(),
             Value::Sequence(SequenceData::Buffer(BuffData {
-                data: hex_bytes("590493").unwrap()
+                data: hex_bytes("590493").unwrap().into()
             }))
         )
         .into()),
@@ -994,7 +994,9 @@ fn test_principal_construct_check_errors() {
         CheckErrors::TypeValueError(
             BUFF_20.clone(),
             Value::Sequence(SequenceData::Buffer(BuffData {
-                data: hex_bytes("010203040506070809101112131415161718192021").unwrap()
+                data: hex_bytes("010203040506070809101112131415161718192021")
+                    .unwrap()
+                    .into()
             }))
         )
         .into()
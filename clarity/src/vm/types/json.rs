@@ -0,0 +1,481 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2023 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Typed JSON encoding of Clarity values.
+//!
+//! This is a human-readable alternative to the consensus serialization, intended for RPC clients.
+//! It is *not* self-describing: decoding requires the expected type, which determines how each
+//! JSON value is interpreted.
+//!
+//! | Clarity type                 | JSON encoding                                         |
+//! |------------------------------|-------------------------------------------------------|
+//! | `int`, `uint`                | decimal string (JSON numbers are accepted on decode)  |
+//! | `bool`                       | `true` / `false`                                      |
+//! | `principal`                  | string, e.g. `"SP000...0002Q6VF78"` or `"SP...foo"`   |
+//! | `(buff N)`                   | `0x`-prefixed hex string                              |
+//! | `(string-ascii N)`           | string                                                |
+//! | `(string-utf8 N)`            | string                                                |
+//! | `(list N T)`                 | array                                                 |
+//! | `(tuple ...)`                | object with exactly the tuple's fields                |
+//! | `(optional T)`               | `null` for `none`, `{"some": v}` for `(some v)`       |
+//! | `(response A B)`             | `{"ok": v}` or `{"err": v}`                           |
+//! | trait references / callables | contract principal string                             |
+//!
+//! Integers are encoded as strings because 128-bit integers cannot be represented as JSON
+//! numbers by most clients.
+
+use serde_json::{Map as JSONMap, Value as JSONValue};
+use stacks_common::types::StacksEpochId;
+use stacks_common::util::hash::{hex_bytes, to_hex};
+
+use crate::vm::types::serialization::SerializationError;
+use crate::vm::types::signatures::CallableSubtype;
+use crate::vm::types::{
+    BufferLength, CallableData, CharType, OptionalData, PrincipalData, ResponseData, SequenceData,
+    SequenceSubtype, StringSubtype, StringUTF8Length, TupleData, TypeSignature, Value,
+};
+
+fn json_err(msg: &str, json: &JSONValue) -> SerializationError {
+    SerializationError::DeserializationError(format!("{}: {}", msg, json))
+}
+
+/// Decode a JSON string or number into an i128-compatible decimal literal
+fn json_integer_literal(json: &JSONValue) -> Result<String, SerializationError> {
+    match json {
+        JSONValue::String(s) => Ok(s.clone()),
+        // `arbitrary_precision` is enabled, so this preserves all digits
+        JSONValue::Number(n) => Ok(n.to_string()),
+        _ => Err(json_err("Expected integer", json)),
+    }
+}
+
+impl Value {
+    /// Encode this value as typed JSON.  See the module documentation for the encoding.
+    pub fn to_typed_json(&self) -> Result<JSONValue, SerializationError> {
+        let json = match self {
+            Value::Int(i) => JSONValue::String(i.to_string()),
+            Value::UInt(u) => JSONValue::String(u.to_string()),
+            Value::Bool(b) => JSONValue::Bool(*b),
+            Value::Principal(p) => JSONValue::String(p.to_string()),
+            Value::CallableContract(CallableData {
+                contract_identifier,
+                ..
+            }) => JSONValue::String(contract_identifier.to_string()),
+            Value::Sequence(SequenceData::Buffer(b)) => {
+                JSONValue::String(format!("0x{}", to_hex(&b.data)))
+            }
+            Value::Sequence(SequenceData::String(CharType::ASCII(s))) => JSONValue::String(
+                String::from_utf8(s.data.clone())
+                    .map_err(|_| SerializationError::SerializationError("Bad ASCII".into()))?,
+            ),
+            Value::Sequence(SequenceData::String(CharType::UTF8(s))) => JSONValue::String(
+                String::from_utf8(s.data.concat())
+                    .map_err(|_| SerializationError::SerializationError("Bad UTF-8".into()))?,
+            ),
+            Value::Sequence(SequenceData::List(l)) => JSONValue::Array(
+                l.data
+                    .iter()
+                    .map(|item| item.to_typed_json())
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Tuple(TupleData { data_map, .. }) => {
+                let mut obj = JSONMap::new();
+                for (name, value) in data_map.iter() {
+                    obj.insert(name.to_string(), value.to_typed_json()?);
+                }
+                JSONValue::Object(obj)
+            }
+            Value::Optional(OptionalData { data: None }) => JSONValue::Null,
+            Value::Optional(OptionalData { data: Some(v) }) => {
+                let mut obj = JSONMap::new();
+                obj.insert("some".into(), v.to_typed_json()?);
+                JSONValue::Object(obj)
+            }
+            Value::Response(ResponseData { committed, data }) => {
+                let mut obj = JSONMap::new();
+                let tag = if *committed { "ok" } else { "err" };
+                obj.insert(tag.into(), data.to_typed_json()?);
+                JSONValue::Object(obj)
+            }
+        };
+        Ok(json)
+    }
+
+    /// Decode a typed JSON value into a Clarity value of the `expected` type.
+    /// The returned value is admitted by `expected`.
+    pub fn from_typed_json(
+        json: &JSONValue,
+        expected: &TypeSignature,
+    ) -> Result<Value, SerializationError> {
+        let mismatch = |_| SerializationError::DeserializeExpected(expected.clone());
+        let value = match expected {
+            TypeSignature::IntType => {
+                let literal = json_integer_literal(json)?;
+                Value::Int(
+                    literal
+                        .parse::<i128>()
+                        .map_err(|_| json_err("Invalid int", json))?,
+                )
+            }
+            TypeSignature::UIntType => {
+                let literal = json_integer_literal(json)?;
+                Value::UInt(
+                    literal
+                        .parse::<u128>()
+                        .map_err(|_| json_err("Invalid uint", json))?,
+                )
+            }
+            TypeSignature::BoolType => Value::Bool(
+                json.as_bool()
+                    .ok_or_else(|| json_err("Expected bool", json))?,
+            ),
+            TypeSignature::PrincipalType
+            | TypeSignature::CallableType(CallableSubtype::Principal(_))
+            | TypeSignature::CallableType(CallableSubtype::Trait(_))
+            | TypeSignature::ListUnionType(_)
+            | TypeSignature::TraitReferenceType(_) => {
+                let s = json
+                    .as_str()
+                    .ok_or_else(|| json_err("Expected principal string", json))?;
+                let principal =
+                    PrincipalData::parse(s).map_err(|_| json_err("Invalid principal", json))?;
+                if !matches!(expected, TypeSignature::PrincipalType) {
+                    // trait-typed arguments are passed as contract principals
+                    if !matches!(principal, PrincipalData::Contract(_)) {
+                        return Err(json_err("Expected contract principal", json));
+                    }
+                }
+                Value::Principal(principal)
+            }
+            TypeSignature::SequenceType(SequenceSubtype::BufferType(max_len)) => {
+                let s = json
+                    .as_str()
+                    .ok_or_else(|| json_err("Expected hex string", json))?;
+                let bytes = hex_bytes(s.strip_prefix("0x").unwrap_or(s))
+                    .map_err(|_| json_err("Invalid hex string", json))?;
+                if bytes.len() > u32::from(max_len) as usize {
+                    return Err(SerializationError::DeserializeExpected(expected.clone()));
+                }
+                Value::buff_from(bytes).map_err(mismatch)?
+            }
+            TypeSignature::SequenceType(SequenceSubtype::StringType(StringSubtype::ASCII(
+                max_len,
+            ))) => {
+                let s = json
+                    .as_str()
+                    .ok_or_else(|| json_err("Expected string", json))?;
+                if s.len() > u32::from(max_len) as usize {
+                    return Err(SerializationError::DeserializeExpected(expected.clone()));
+                }
+                Value::string_ascii_from_bytes(s.as_bytes().to_vec()).map_err(mismatch)?
+            }
+            TypeSignature::SequenceType(SequenceSubtype::StringType(StringSubtype::UTF8(
+                max_len,
+            ))) => {
+                let s = json
+                    .as_str()
+                    .ok_or_else(|| json_err("Expected string", json))?;
+                if s.chars().count() > u32::from(max_len) as usize {
+                    return Err(SerializationError::DeserializeExpected(expected.clone()));
+                }
+                Value::string_utf8_from_bytes(s.as_bytes().to_vec()).map_err(mismatch)?
+            }
+            TypeSignature::SequenceType(SequenceSubtype::ListType(list_type)) => {
+                let items = json
+                    .as_array()
+                    .ok_or_else(|| json_err("Expected array", json))?;
+                if items.len() > list_type.get_max_len() as usize {
+                    return Err(SerializationError::DeserializeExpected(expected.clone()));
+                }
+                if items.is_empty() {
+                    return Value::list_with_type(
+                        &StacksEpochId::latest(),
+                        vec![],
+                        list_type.clone(),
+                    )
+                    .map_err(mismatch);
+                }
+                let item_type = list_type.get_list_item_type();
+                let values = items
+                    .iter()
+                    .map(|item| Value::from_typed_json(item, item_type))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Value::list_with_type(&StacksEpochId::latest(), values, list_type.clone())
+                    .map_err(mismatch)?
+            }
+            TypeSignature::TupleType(tuple_type) => {
+                let obj = json
+                    .as_object()
+                    .ok_or_else(|| json_err("Expected object", json))?;
+                let type_map = tuple_type.get_type_map();
+                if obj.len() != type_map.len() {
+                    return Err(SerializationError::DeserializeExpected(expected.clone()));
+                }
+                let mut fields = Vec::with_capacity(type_map.len());
+                for (name, field_type) in type_map.iter() {
+                    let field = obj
+                        .get(name.as_str())
+                        .ok_or_else(|| SerializationError::DeserializeExpected(expected.clone()))?;
+                    fields.push((name.clone(), Value::from_typed_json(field, field_type)?));
+                }
+                Value::Tuple(
+                    TupleData::from_data_typed(&StacksEpochId::latest(), fields, tuple_type)
+                        .map_err(mismatch)?,
+                )
+            }
+            TypeSignature::OptionalType(inner_type) => {
+                if json.is_null() {
+                    Value::none()
+                } else {
+                    let inner = single_field(json, &["some"])?.1;
+                    Value::some(Value::from_typed_json(inner, inner_type)?).map_err(mismatch)?
+                }
+            }
+            TypeSignature::ResponseType(inner_types) => {
+                let (tag, inner) = single_field(json, &["ok", "err"])?;
+                if tag == "ok" {
+                    Value::okay(Value::from_typed_json(inner, &inner_types.0)?).map_err(mismatch)?
+                } else {
+                    Value::error(Value::from_typed_json(inner, &inner_types.1)?)
+                        .map_err(mismatch)?
+                }
+            }
+            TypeSignature::NoType => {
+                return Err(SerializationError::DeserializationError(
+                    "Cannot decode a value of indeterminate type".into(),
+                ));
+            }
+        };
+        Ok(value)
+    }
+}
+
+/// Unwrap a single-field JSON object whose key must be one of `tags`
+fn single_field<'a>(
+    json: &'a JSONValue,
+    tags: &[&'static str],
+) -> Result<(&'static str, &'a JSONValue), SerializationError> {
+    let obj = json
+        .as_object()
+        .ok_or_else(|| json_err("Expected object", json))?;
+    if obj.len() != 1 {
+        return Err(json_err("Expected a single-field object", json));
+    }
+    for tag in tags.iter() {
+        if let Some(inner) = obj.get(*tag) {
+            return Ok((tag, inner));
+        }
+    }
+    Err(json_err(&format!("Expected one of {:?}", tags), json))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::vm::types::{ListTypeData, QualifiedContractIdentifier, TupleTypeSignature};
+
+    fn check_round_trip(value: Value, expected: &TypeSignature, json: JSONValue) {
+        assert_eq!(value.to_typed_json().unwrap(), json);
+        let decoded = Value::from_typed_json(&json, expected).unwrap();
+        assert_eq!(decoded, value);
+        assert!(expected.admits(&StacksEpochId::latest(), &decoded).unwrap());
+    }
+
+    #[test]
+    fn test_typed_json_round_trip() {
+        check_round_trip(
+            Value::Int(-170141183460469231731687303715884105728),
+            &TypeSignature::IntType,
+            json!("-170141183460469231731687303715884105728"),
+        );
+        check_round_trip(
+            Value::UInt(u128::MAX),
+            &TypeSignature::UIntType,
+            json!("340282366920938463463374607431768211455"),
+        );
+        check_round_trip(Value::Bool(true), &TypeSignature::BoolType, json!(true));
+
+        let principal = PrincipalData::parse("SP000000000000000000002Q6VF78").unwrap();
+        check_round_trip(
+            Value::Principal(principal),
+            &TypeSignature::PrincipalType,
+            json!("SP000000000000000000002Q6VF78"),
+        );
+        let contract = PrincipalData::parse("SP000000000000000000002Q6VF78.pox").unwrap();
+        check_round_trip(
+            Value::Principal(contract),
+            &TypeSignature::PrincipalType,
+            json!("SP000000000000000000002Q6VF78.pox"),
+        );
+
+        check_round_trip(
+            Value::buff_from(vec![0xde, 0xad, 0xbe, 0xef]).unwrap(),
+            &TypeSignature::SequenceType(SequenceSubtype::BufferType(
+                BufferLength::try_from(4u32).unwrap(),
+            )),
+            json!("0xdeadbeef"),
+        );
+        check_round_trip(
+            Value::string_ascii_from_bytes(b"hello world".to_vec()).unwrap(),
+            &TypeSignature::SequenceType(SequenceSubtype::StringType(StringSubtype::ASCII(
+                BufferLength::try_from(11u32).unwrap(),
+            ))),
+            json!("hello world"),
+        );
+        check_round_trip(
+            Value::string_utf8_from_bytes("héllo ☃".as_bytes().to_vec()).unwrap(),
+            &TypeSignature::SequenceType(SequenceSubtype::StringType(StringSubtype::UTF8(
+                StringUTF8Length::try_from(7u32).unwrap(),
+            ))),
+            json!("héllo ☃"),
+        );
+
+        let list_type = ListTypeData::new_list(TypeSignature::UIntType, 3).unwrap();
+        check_round_trip(
+            Value::list_with_type(
+                &StacksEpochId::latest(),
+                vec![Value::UInt(1), Value::UInt(2)],
+                list_type.clone(),
+            )
+            .unwrap(),
+            &TypeSignature::SequenceType(SequenceSubtype::ListType(list_type.clone())),
+            json!(["1", "2"]),
+        );
+        check_round_trip(
+            Value::list_with_type(&StacksEpochId::latest(), vec![], list_type.clone()).unwrap(),
+            &TypeSignature::SequenceType(SequenceSubtype::ListType(list_type)),
+            json!([]),
+        );
+
+        let tuple_type = TupleTypeSignature::try_from(vec![
+            ("a".into(), TypeSignature::IntType),
+            ("b".into(), TypeSignature::BoolType),
+        ])
+        .unwrap();
+        check_round_trip(
+            Value::Tuple(
+                TupleData::from_data(vec![
+                    ("a".into(), Value::Int(1)),
+                    ("b".into(), Value::Bool(false)),
+                ])
+                .unwrap(),
+            ),
+            &TypeSignature::TupleType(tuple_type),
+            json!({"a": "1", "b": false}),
+        );
+
+        let opt_type = TypeSignature::new_option(TypeSignature::IntType).unwrap();
+        check_round_trip(Value::none(), &opt_type, json!(null));
+        check_round_trip(
+            Value::some(Value::Int(2)).unwrap(),
+            &opt_type,
+            json!({"some": "2"}),
+        );
+
+        let resp_type =
+            TypeSignature::new_response(TypeSignature::BoolType, TypeSignature::UIntType).unwrap();
+        check_round_trip(
+            Value::okay(Value::Bool(true)).unwrap(),
+            &resp_type,
+            json!({"ok": true}),
+        );
+        check_round_trip(
+            Value::error(Value::UInt(3)).unwrap(),
+            &resp_type,
+            json!({"err": "3"}),
+        );
+    }
+
+    #[test]
+    fn test_typed_json_callable() {
+        let contract_id =
+            QualifiedContractIdentifier::parse("SP000000000000000000002Q6VF78.pox").unwrap();
+        let callable = Value::CallableContract(CallableData {
+            contract_identifier: contract_id.clone(),
+            trait_identifier: None,
+        });
+        assert_eq!(
+            callable.to_typed_json().unwrap(),
+            json!("SP000000000000000000002Q6VF78.pox")
+        );
+        assert_eq!(
+            Value::from_typed_json(
+                &json!("SP000000000000000000002Q6VF78.pox"),
+                &TypeSignature::CallableType(CallableSubtype::Principal(contract_id))
+            )
+            .unwrap(),
+            Value::Principal(PrincipalData::parse("SP000000000000000000002Q6VF78.pox").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_typed_json_decode_errors() {
+        // numbers are accepted for integers
+        assert_eq!(
+            Value::from_typed_json(&json!(12), &TypeSignature::IntType).unwrap(),
+            Value::Int(12)
+        );
+        assert!(Value::from_typed_json(&json!("-1"), &TypeSignature::UIntType).is_err());
+        assert!(Value::from_typed_json(&json!("1.5"), &TypeSignature::IntType).is_err());
+        assert!(Value::from_typed_json(&json!(1), &TypeSignature::BoolType).is_err());
+
+        // too long
+        let buff_type = TypeSignature::SequenceType(SequenceSubtype::BufferType(
+            BufferLength::try_from(1u32).unwrap(),
+        ));
+        assert!(Value::from_typed_json(&json!("0x0102"), &buff_type).is_err());
+        assert!(Value::from_typed_json(&json!("0xzz"), &buff_type).is_err());
+        let list_type = TypeSignature::SequenceType(SequenceSubtype::ListType(
+            ListTypeData::new_list(TypeSignature::IntType, 1).unwrap(),
+        ));
+        assert!(Value::from_typed_json(&json!(["1", "2"]), &list_type).is_err());
+
+        // missing and extra tuple fields
+        let tuple_type = TypeSignature::TupleType(
+            TupleTypeSignature::try_from(vec![("a".into(), TypeSignature::IntType)]).unwrap(),
+        );
+        assert!(Value::from_typed_json(&json!({}), &tuple_type).is_err());
+        assert!(Value::from_typed_json(&json!({"a": "1", "b": "2"}), &tuple_type).is_err());
+        assert!(Value::from_typed_json(&json!({"b": "1"}), &tuple_type).is_err());
+
+        // bad variant tags
+        let resp_type =
+            TypeSignature::new_response(TypeSignature::IntType, TypeSignature::IntType).unwrap();
+        assert!(Value::from_typed_json(&json!({"okay": "1"}), &resp_type).is_err());
+        assert!(Value::from_typed_json(&json!({"ok": "1", "err": "1"}), &resp_type).is_err());
+        let opt_type = TypeSignature::new_option(TypeSignature::IntType).unwrap();
+        assert!(Value::from_typed_json(&json!("1"), &opt_type).is_err());
+
+        // non-ASCII in an ASCII string
+        let ascii_type = TypeSignature::SequenceType(SequenceSubtype::StringType(
+            StringSubtype::ASCII(BufferLength::try_from(10u32).unwrap()),
+        ));
+        assert!(Value::from_typed_json(&json!("☃"), &ascii_type).is_err());
+
+        // trait arguments must be contracts
+        let contract_id =
+            QualifiedContractIdentifier::parse("SP000000000000000000002Q6VF78.pox").unwrap();
+        assert!(Value::from_typed_json(
+            &json!("SP000000000000000000002Q6VF78"),
+            &TypeSignature::CallableType(CallableSubtype::Principal(contract_id))
+        )
+        .is_err());
+
+        assert!(Value::from_typed_json(&json!(null), &TypeSignature::NoType).is_err());
+    }
+}
@@ -14,8 +14,11 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+#[allow(clippy::result_large_err)]
+pub mod json;
 #[allow(clippy::result_large_err)]
 pub mod serialization;
+pub mod shared;
 #[allow(clippy::result_large_err)]
 pub mod signatures;
 
@@ -35,6 +38,7 @@ use crate::vm::errors::{
 use crate::vm::representations::{
     ClarityName, ContractName, SymbolicExpression, SymbolicExpressionType,
 };
+pub use crate::vm::types::shared::SharedData;
 pub use crate::vm::types::signatures::{
     parse_name_type_pairs, AssetIdentifier, BufferLength, FixedFunction, FunctionArg,
     FunctionSignature, FunctionType, ListTypeData, SequenceSubtype, StringSubtype,
@@ -55,17 +59,17 @@ pub const WRAPPER_VALUE_SIZE: u32 = 1;
 pub struct TupleData {
     // todo: remove type_signature
     pub type_signature: TupleTypeSignature,
-    pub data_map: BTreeMap<ClarityName, Value>,
+    pub data_map: SharedData<BTreeMap<ClarityName, Value>>,
 }
 
 #[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct BuffData {
-    pub data: Vec<u8>,
+    pub data: SharedData<Vec<u8>>,
 }
 
 #[derive(Debug, Clone, Eq, Serialize, Deserialize)]
 pub struct ListData {
-    pub data: Vec<Value>,
+    pub data: SharedData<Vec<Value>>,
     // todo: remove type_signature
     pub type_signature: ListTypeData,
 }
@@ -245,12 +249,12 @@ pub enum SequenceData {
 }
 
 impl SequenceData {
-    pub fn atom_values(&mut self) -> Result<Vec<SymbolicExpression>> {
+    pub fn atom_values(&self) -> Result<Vec<SymbolicExpression>> {
         match self {
-            SequenceData::Buffer(ref mut data) => data.atom_values(),
-            SequenceData::List(ref mut data) => data.atom_values(),
-            SequenceData::String(CharType::ASCII(ref mut data)) => data.atom_values(),
-            SequenceData::String(CharType::UTF8(ref mut data)) => data.atom_values(),
+            SequenceData::Buffer(ref data) => data.atom_values(),
+            SequenceData::List(ref data) => data.atom_values(),
+            SequenceData::String(CharType::ASCII(ref data)) => data.atom_values(),
+            SequenceData::String(CharType::UTF8(ref data)) => data.atom_values(),
         }
     }
 
@@ -279,7 +283,7 @@ impl SequenceData {
         }
         let result = match self {
             SequenceData::Buffer(data) => Value::buff_from_byte(data.data[index]),
-            SequenceData::List(mut data) => data.data.remove(index),
+            SequenceData::List(data) => data.data[index].clone(),
             SequenceData::String(CharType::ASCII(data)) => {
                 Value::string_ascii_from_bytes(vec![data.data[index]]).map_err(|_| {
                     InterpreterError::Expect(
@@ -422,24 +426,22 @@ impl SequenceData {
     where
         F: FnMut(SymbolicExpression) -> Result<bool>,
     {
-        // Note: this macro can probably get removed once
-        // ```Vec::drain_filter<F>(&mut self, filter: F) -> DrainFilter<T, F>```
-        // is available in rust stable channel (experimental at this point).
+        // The items are only read while the filter runs, so shared contents are not copied.  If
+        // any item is dropped, the kept ones are collected into new storage in a single pass.
         macro_rules! drain_filter {
             ($data:expr, $seq_type:ident) => {
-                let mut i = 0;
-                while i != $data.data.len() {
-                    let atom_value =
-                        SymbolicExpression::atom_value($seq_type::to_value(&$data.data[i])?);
-                    match filter(atom_value) {
-                        Ok(res) if res == false => {
-                            $data.data.remove(i);
-                        }
-                        Ok(_) => {
-                            i += 1;
-                        }
-                        Err(err) => return Err(err),
-                    }
+                let mut keep = Vec::with_capacity($data.data.len());
+                for item in $data.data.iter() {
+                    let atom_value = SymbolicExpression::atom_value($seq_type::to_value(item)?);
+                    keep.push(filter(atom_value)?);
+                }
+                if keep.contains(&false) {
+                    $data.data = $data
+                        .data
+                        .iter()
+                        .zip(keep.into_iter())
+                        .filter_map(|(item, keep)| if keep { Some(item.clone()) } else { None })
+                        .collect();
                 }
             };
         }
@@ -604,8 +606,8 @@ pub trait SequencedValue<T> {
 
     fn to_value(v: &T) -> Result<Value>;
 
-    fn atom_values(&mut self) -> Result<Vec<SymbolicExpression>> {
-        self.drained_items()
+    fn atom_values(&self) -> Result<Vec<SymbolicExpression>> {
+        self.items()
             .iter()
             .map(|item| Ok(SymbolicExpression::atom_value(Self::to_value(&item)?)))
             .collect()
@@ -618,7 +620,7 @@ impl SequencedValue<Value> for ListData {
     }
 
     fn drained_items(&mut self) -> Vec<Value> {
-        self.data.drain(..).collect()
+        std::mem::take(&mut self.data).into_inner()
     }
 
     fn type_signature(&self) -> std::result::Result<TypeSignature, CheckErrors> {
@@ -638,7 +640,7 @@ impl SequencedValue<u8> for BuffData {
     }
 
     fn drained_items(&mut self) -> Vec<u8> {
-        self.data.drain(..).collect()
+        std::mem::take(&mut self.data).into_inner()
     }
 
     fn type_signature(&self) -> std::result::Result<TypeSignature, CheckErrors> {
@@ -925,7 +927,7 @@ impl Value {
         }
 
         Ok(Value::Sequence(SequenceData::List(ListData {
-            data: list_data,
+            data: list_data.into(),
             type_signature: expected_type,
         })))
     }
@@ -933,7 +935,7 @@ impl Value {
     pub fn cons_list_unsanitized(list_data: Vec<Value>) -> Result<Value> {
         let type_sig = TypeSignature::construct_parent_list_type(&list_data)?;
         Ok(Value::Sequence(SequenceData::List(ListData {
-            data: list_data,
+            data: list_data.into(),
             type_signature: type_sig,
         })))
     }
@@ -967,12 +969,14 @@ impl Value {
         BufferLength::try_from(buff_data.len())?;
         // construct the buffer
         Ok(Value::Sequence(SequenceData::Buffer(BuffData {
-            data: buff_data,
+            data: buff_data.into(),
         })))
     }
 
     pub fn buff_from_byte(byte: u8) -> Value {
-        Value::Sequence(SequenceData::Buffer(BuffData { data: vec![byte] }))
+        Value::Sequence(SequenceData::Buffer(BuffData {
+            data: vec![byte].into(),
+        }))
     }
 
     pub fn string_ascii_from_bytes(bytes: Vec<u8>) -> Result<Value> {
@@ -1079,7 +1083,7 @@ impl Value {
     pub fn expect_buff(self, sz: usize) -> Result<Vec<u8>> {
         if let Value::Sequence(SequenceData::Buffer(buffdata)) = self {
             if buffdata.data.len() <= sz {
-                Ok(buffdata.data)
+                Ok(buffdata.data.into_inner())
             } else {
                 error!(
                     "Value buffer has len {}, expected {}",
@@ -1096,7 +1100,7 @@ impl Value {
 
     pub fn expect_list(self) -> Result<Vec<Value>> {
         if let Value::Sequence(SequenceData::List(listdata)) = self {
-            Ok(listdata.data)
+            Ok(listdata.data.into_inner())
         } else {
             error!("Value '{:?}' is not a list", &self);
             Err(InterpreterError::Expect("Expected list".into()).into())
@@ -1216,12 +1220,16 @@ impl BuffData {
     }
 
     fn append(&mut self, other_seq: &mut BuffData) -> Result<()> {
-        self.data.append(&mut other_seq.data);
+        // `other_seq` may share its contents, so copy rather than drain them.
+        let other_data = std::mem::take(&mut other_seq.data);
+        self.data.extend_from_slice(&other_data);
         Ok(())
     }
 
     pub fn empty() -> Self {
-        Self { data: Vec::new() }
+        Self {
+            data: SharedData::default(),
+        }
     }
 }
 
@@ -1506,7 +1514,7 @@ impl TupleData {
     ) -> Result<TupleData> {
         let t = TupleData {
             type_signature,
-            data_map,
+            data_map: data_map.into(),
         };
         Ok(t)
     }
@@ -1562,7 +1570,13 @@ impl TupleData {
     }
 
     pub fn get_owned(mut self, name: &str) -> Result<Value> {
-        self.data_map.remove(name).ok_or_else(|| {
+        // only take the field out if the map isn't shared; otherwise, removing it would copy the
+        // whole map just to discard it.
+        let value = match self.data_map.unshared_mut() {
+            Some(data_map) => data_map.remove(name),
+            None => self.data_map.get(name).cloned(),
+        };
+        value.ok_or_else(|| {
             CheckErrors::NoSuchTupleField(name.to_string(), self.type_signature.clone()).into()
         })
     }
@@ -1754,7 +1768,7 @@ mod test {
     #[test]
     fn expect_buff() {
         let buff = Value::Sequence(SequenceData::Buffer(BuffData {
-            data: vec![1, 2, 3, 4, 5],
+            data: vec![1, 2, 3, 4, 5].into(),
         }));
         assert_eq!(buff.clone().expect_buff(5).unwrap(), vec![1, 2, 3, 4, 5]);
         assert_eq!(buff.clone().expect_buff(6).unwrap(), vec![1, 2, 3, 4, 5]);
@@ -1773,8 +1787,45 @@ mod test {
     #[should_panic]
     fn expect_buff_too_small() {
         let buff = Value::Sequence(SequenceData::Buffer(BuffData {
-            data: vec![1, 2, 3, 4, 5],
+            data: vec![1, 2, 3, 4, 5].into(),
         }));
         let _ = buff.expect_buff(4).unwrap();
     }
+
+    #[test]
+    fn test_reads_do_not_copy_shared_contents() {
+        let list = Value::cons_list_unsanitized((0..10).map(Value::UInt).collect()).unwrap();
+        let Value::Sequence(SequenceData::List(original)) = list.clone() else {
+            panic!("expected a list");
+        };
+
+        let Value::Sequence(mut seq) = list.clone() else {
+            panic!("expected a sequence");
+        };
+        assert_eq!(seq.atom_values().unwrap().len(), 10);
+
+        // a filter that keeps everything leaves the contents shared
+        seq.filter(&mut |_| Ok(true)).unwrap();
+        let SequenceData::List(ref kept) = seq else {
+            panic!("expected a list");
+        };
+        assert!(kept.data.ptr_eq(&original.data));
+
+        // a filter that drops items makes a new list, and leaves the original alone
+        seq.filter(&mut |atom| {
+            Ok(atom.match_atom_value() != Some(&Value::UInt(3)))
+        })
+        .unwrap();
+        let SequenceData::List(ref filtered) = seq else {
+            panic!("expected a list");
+        };
+        assert_eq!(filtered.data.len(), 9);
+        assert_eq!(original.data.len(), 10);
+
+        let tuple = TupleData::from_data(vec![("items".into(), list.clone())]).unwrap();
+        let shared = tuple.clone();
+        assert_eq!(tuple.get_owned("items").unwrap(), list);
+        assert_eq!(shared.get("items").unwrap(), &list);
+        assert!(shared.get_owned("nope").is_err());
+    }
 }
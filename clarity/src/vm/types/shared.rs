@@ -0,0 +1,202 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2023 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Reference-counted, copy-on-write storage for the contents of Clarity values.
+//!
+//! Lists, buffers, and tuples are passed around by value all over the interpreter (variable
+//! lookups, function arguments, `get`, `map`/`filter`/`fold`, ...), and most of those copies are
+//! never written to.  `SharedData<T>` makes cloning a value O(1): the contents are only copied
+//! when a holder mutates them while another holder still references them.
+//!
+//! `SharedData<T>` dereferences to `T`, so read access is unchanged.  Mutable access through
+//! `DerefMut` (or `make_mut()`) un-shares the contents first.  Equality, ordering, hashing,
+//! `Debug` and serde all delegate to `T`, so nothing observable changes -- in particular, the
+//! consensus serialization and the costs charged for values do not depend on how they are
+//! stored.
+
+use std::collections::{btree_map, BTreeMap};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SharedData<T>(Arc<T>);
+
+impl<T> SharedData<T> {
+    pub fn new(data: T) -> SharedData<T> {
+        SharedData(Arc::new(data))
+    }
+
+    /// Get a mutable reference to the contents if no other holder references them.
+    pub fn unshared_mut(&mut self) -> Option<&mut T> {
+        Arc::get_mut(&mut self.0)
+    }
+
+    /// Do `self` and `other` reference the same storage?
+    pub fn ptr_eq(&self, other: &SharedData<T>) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T: Clone> SharedData<T> {
+    /// Get a mutable reference to the contents, copying them first if they are shared.
+    pub fn make_mut(&mut self) -> &mut T {
+        Arc::make_mut(&mut self.0)
+    }
+
+    /// Take the contents, copying them only if they are shared.
+    pub fn into_inner(self) -> T {
+        Arc::try_unwrap(self.0).unwrap_or_else(|shared| (*shared).clone())
+    }
+}
+
+impl<T> Deref for SharedData<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Clone> DerefMut for SharedData<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.make_mut()
+    }
+}
+
+impl<T> AsRef<T> for SharedData<T> {
+    fn as_ref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> From<T> for SharedData<T> {
+    fn from(data: T) -> SharedData<T> {
+        SharedData::new(data)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SharedData<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<T: Serialize> Serialize for SharedData<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for SharedData<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<SharedData<T>, D::Error> {
+        T::deserialize(deserializer).map(SharedData::new)
+    }
+}
+
+impl<T: Clone> IntoIterator for SharedData<Vec<T>> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_inner().into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a SharedData<Vec<T>> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl<K: Clone, V: Clone> IntoIterator for SharedData<BTreeMap<K, V>> {
+    type Item = (K, V);
+    type IntoIter = btree_map::IntoIter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_inner().into_iter()
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a SharedData<BTreeMap<K, V>> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = btree_map::Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl<T> FromIterator<T> for SharedData<Vec<T>> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> SharedData<Vec<T>> {
+        SharedData::new(iter.into_iter().collect())
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, V)> for SharedData<BTreeMap<K, V>> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> SharedData<BTreeMap<K, V>> {
+        SharedData::new(iter.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_on_write() {
+        let original = SharedData::new(vec![1u8, 2, 3]);
+        let mut copy = original.clone();
+        assert!(copy.ptr_eq(&original));
+
+        copy.push(4);
+        assert!(!copy.ptr_eq(&original));
+        assert_eq!(*original, vec![1, 2, 3]);
+        assert_eq!(*copy, vec![1, 2, 3, 4]);
+
+        // unshared contents are moved out without copying
+        let before = copy.as_ptr();
+        let data = copy.into_inner();
+        assert_eq!(data, vec![1, 2, 3, 4]);
+        assert_eq!(data.as_ptr(), before);
+    }
+
+    #[test]
+    fn test_unshared_mut() {
+        let mut data = SharedData::new(vec![1u8, 2]);
+        data.unshared_mut().unwrap().push(3);
+
+        let copy = data.clone();
+        assert!(data.unshared_mut().is_none());
+        drop(copy);
+        assert_eq!(data.unshared_mut(), Some(&mut vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn test_delegates_to_contents() {
+        let data = SharedData::new(vec![1u8, 2]);
+        assert_eq!(format!("{:?}", &data), "[1, 2]");
+        assert_eq!(serde_json::to_string(&data).unwrap(), "[1,2]");
+        let parsed: SharedData<Vec<u8>> = serde_json::from_str("[1,2]").unwrap();
+        assert_eq!(parsed, data);
+        assert!(SharedData::new(vec![1u8]) < data);
+    }
+}
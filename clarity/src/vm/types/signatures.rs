@@ -32,7 +32,7 @@ use crate::vm::representations::{
     CONTRACT_MAX_NAME_LENGTH,
 };
 use crate::vm::types::{
-    CharType, PrincipalData, QualifiedContractIdentifier, SequenceData, SequencedValue,
+    CharType, PrincipalData, QualifiedContractIdentifier, SequenceData, SequencedValue, SharedData,
     StandardPrincipalData, TraitIdentifier, Value, MAX_TYPE_DEPTH, MAX_VALUE_SIZE,
     WRAPPER_VALUE_SIZE,
 };
@@ -75,7 +75,7 @@ impl AssetIdentifier {
 
 #[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TupleTypeSignature {
-    type_map: BTreeMap<ClarityName, TypeSignature>,
+    type_map: SharedData<BTreeMap<ClarityName, TypeSignature>>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
@@ -785,7 +785,7 @@ impl TypeSignature {
                     canonicalized_fields.insert(field_name.clone(), field_type.canonicalize_v2_1());
                 }
                 TypeSignature::from(TupleTypeSignature {
-                    type_map: canonicalized_fields,
+                    type_map: canonicalized_fields.into(),
                 })
             }
             TraitReferenceType(trait_id) => CallableType(CallableSubtype::Trait(trait_id.clone())),
@@ -867,7 +867,9 @@ impl TryFrom<BTreeMap<ClarityName, TypeSignature>> for TupleTypeSignature {
                 return Err(CheckErrors::TypeSignatureTooDeep);
             }
         }
-        let result = TupleTypeSignature { type_map };
+        let result = TupleTypeSignature {
+            type_map: type_map.into(),
+        };
         let would_be_size = result
             .inner_size()?
             .ok_or_else(|| CheckErrors::ValueTooLarge)?;
@@ -0,0 +1,689 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Batch MARF proofs, and a verifier for them that light clients can use.
+//!
+//! A `MarfBatchProof` proves the values (or absence) of any number of keys in the MARF at a
+//! given block.  It reveals, for each trie it passes through, the part of that trie which the
+//! keys' paths visit -- each node once, no matter how many keys share it -- along with the
+//! hashes of the subtrees it does not reveal.  Given the state root of the block (i.e. the
+//! `state_index_root` of its header), the verifier recomputes every revealed trie's root hash
+//! and then answers lookups from the revealed nodes alone.
+//!
+//! When a path leaves the block's trie through a back-pointer, the trie it enters is only
+//! committed to by its block ID.  The proof binds that block ID to the trie's root hash by
+//! carrying the block's serialized header and consensus hash, from which the verifier
+//! recomputes the ID and reads the header's state root.  The boot block's ID is not derived
+//! from its header, so a trie for the boot block can only be checked against a root hash that
+//! the caller already trusts.
+
+extern crate stacks_common;
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::{error, fmt};
+
+use stacks_common::codec::{
+    read_next, read_next_at_most, write_next, Error as CodecError, StacksMessageCodec,
+};
+use stacks_common::types::chainstate::{BlockHeaderHash, ConsensusHash, StacksBlockId, TrieHash};
+use stacks_common::util::hash::Sha512Trunc256Sum;
+
+#[cfg(test)]
+mod tests;
+
+/// Length of a key's path through the trie
+pub const TRIE_PATH_LEN: usize = 32;
+/// Length of a value stored in a leaf
+pub const MARF_VALUE_LEN: usize = 40;
+/// Length of a consensus-serialized Stacks block header
+pub const STACKS_BLOCK_HEADER_LEN: usize = 247;
+/// Offset of the `total_work.work` field in a serialized Stacks block header
+const HEADER_WORK_OFFSET: usize = 9;
+/// Offset of the `state_index_root` field in a serialized Stacks block header
+const HEADER_STATE_ROOT_OFFSET: usize = 195;
+
+/// Maximum number of tries a proof may pass through
+pub const MAX_PROOF_TRIES: u32 = 4096;
+/// Maximum number of nodes a proof may reveal in one trie
+pub const MAX_PROOF_TRIE_NODES: u32 = 65536;
+/// Maximum number of ancestor root hashes mixed into a trie's root hash
+pub const MAX_ANCESTOR_HASHES: u32 = 32;
+
+/// Trie node IDs, as used in the MARF
+pub const NODE_ID_EMPTY: u8 = 0;
+pub const NODE_ID_LEAF: u8 = 1;
+pub const NODE_ID_NODE4: u8 = 2;
+pub const NODE_ID_NODE16: u8 = 3;
+pub const NODE_ID_NODE48: u8 = 4;
+pub const NODE_ID_NODE256: u8 = 5;
+/// Bit set in a child pointer's node ID if the child lives in an ancestor trie
+pub const NODE_ID_BACKPTR_BIT: u8 = 0x80;
+
+/// Clarity store type for data variables
+const STORE_TYPE_DATA_VAR: u8 = 0x01;
+/// Clarity store type for data map entries
+const STORE_TYPE_DATA_MAP: u8 = 0x00;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The proof is not well-formed
+    MalformedProof(String),
+    /// A trie's root hash does not match the root hash it is committed to
+    RootHashMismatch(StacksBlockId, TrieHash, TrieHash),
+    /// A trie's root hash could not be bound to its block ID
+    UnboundTrie(StacksBlockId),
+    /// The proof does not reveal enough of the MARF to look up this key
+    KeyNotCovered(String),
+    /// A value does not match what the proof says is stored
+    ValueMismatch(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::MalformedProof(ref s) => write!(f, "Malformed proof: {}", s),
+            Error::RootHashMismatch(ref block_id, ref expected, ref actual) => write!(
+                f,
+                "Trie for {} has root hash {}, but expected {}",
+                block_id, actual, expected
+            ),
+            Error::UnboundTrie(ref block_id) => {
+                write!(f, "No header or trusted root hash for trie {}", block_id)
+            }
+            Error::KeyNotCovered(ref key) => write!(f, "Proof does not cover key '{}'", key),
+            Error::ValueMismatch(ref key) => write!(f, "Value mismatch for key '{}'", key),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&dyn error::Error> {
+        None
+    }
+}
+
+/// The child of a revealed node, as seen through one of its pointers
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProofChild {
+    /// The pointer is empty
+    Empty,
+    /// The child is in the same trie, but is not revealed.  This is its hash.
+    Hash(TrieHash),
+    /// The child is in the same trie, and is revealed.  It is the next node in pre-order.
+    Revealed,
+    /// The child lives in the trie of this ancestor block
+    Back(StacksBlockId),
+}
+
+/// A child pointer of a revealed node
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProofPtr {
+    /// The child's node ID, including the back-pointer bit
+    pub id: u8,
+    /// The path character at which the child resides
+    pub chr: u8,
+    pub child: ProofChild,
+}
+
+/// A revealed trie node
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProofNode {
+    Leaf {
+        path: Vec<u8>,
+        value: [u8; MARF_VALUE_LEN],
+    },
+    Node {
+        id: u8,
+        path: Vec<u8>,
+        /// One entry per pointer slot, including the empty ones
+        ptrs: Vec<ProofPtr>,
+    },
+}
+
+/// A block header, which binds a block ID to the root hash of the block's trie
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProofHeader {
+    /// The consensus-serialized Stacks block header
+    pub header: Vec<u8>,
+    /// The consensus hash of the sortition that selected the block
+    pub consensus_hash: ConsensusHash,
+}
+
+/// The revealed part of one block's trie
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProofTrie {
+    pub block_id: StacksBlockId,
+    /// Binds `block_id` to this trie's root hash.  Unused for the first trie of a proof.
+    pub header: Option<ProofHeader>,
+    /// Root hashes of the ancestor tries that this trie's root hash commits to
+    pub ancestor_hashes: Vec<TrieHash>,
+    /// The revealed nodes, in pre-order, starting with the root
+    pub nodes: Vec<ProofNode>,
+}
+
+/// A proof of the values of a set of keys in the MARF at a given block.
+/// The first trie is the block's own trie; the rest are ancestor tries that the keys' paths
+/// enter via back-pointers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarfBatchProof {
+    pub tries: Vec<ProofTrie>,
+}
+
+/// Number of child pointers of a node with the given ID
+fn node_ptr_count(id: u8) -> Option<usize> {
+    match id {
+        NODE_ID_NODE4 => Some(4),
+        NODE_ID_NODE16 => Some(16),
+        NODE_ID_NODE48 => Some(48),
+        NODE_ID_NODE256 => Some(256),
+        _ => None,
+    }
+}
+
+fn is_backptr(id: u8) -> bool {
+    id & NODE_ID_BACKPTR_BIT != 0
+}
+
+fn write_path<W: Write>(fd: &mut W, path: &[u8]) -> Result<(), CodecError> {
+    if path.len() > TRIE_PATH_LEN {
+        return Err(CodecError::SerializeError(format!(
+            "Path has {} bytes",
+            path.len()
+        )));
+    }
+    write_next(fd, &(path.len() as u8))?;
+    fd.write_all(path).map_err(CodecError::WriteError)
+}
+
+fn read_path<R: Read>(fd: &mut R) -> Result<Vec<u8>, CodecError> {
+    let len: u8 = read_next(fd)?;
+    if usize::from(len) > TRIE_PATH_LEN {
+        return Err(CodecError::DeserializeError(format!(
+            "Path has {} bytes",
+            len
+        )));
+    }
+    let mut path = vec![0u8; usize::from(len)];
+    fd.read_exact(&mut path).map_err(CodecError::ReadError)?;
+    Ok(path)
+}
+
+impl StacksMessageCodec for ProofPtr {
+    fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), CodecError> {
+        write_next(fd, &self.id)?;
+        write_next(fd, &self.chr)?;
+        match self.child {
+            ProofChild::Empty => write_next(fd, &0u8),
+            ProofChild::Hash(ref hash) => {
+                write_next(fd, &1u8)?;
+                write_next(fd, hash)
+            }
+            ProofChild::Revealed => write_next(fd, &2u8),
+            ProofChild::Back(ref block_id) => {
+                write_next(fd, &3u8)?;
+                write_next(fd, block_id)
+            }
+        }
+    }
+
+    fn consensus_deserialize<R: Read>(fd: &mut R) -> Result<ProofPtr, CodecError> {
+        let id: u8 = read_next(fd)?;
+        let chr: u8 = read_next(fd)?;
+        let child = match read_next::<u8, _>(fd)? {
+            0 => ProofChild::Empty,
+            1 => ProofChild::Hash(read_next(fd)?),
+            2 => ProofChild::Revealed,
+            3 => ProofChild::Back(read_next(fd)?),
+            x => {
+                return Err(CodecError::DeserializeError(format!(
+                    "Unknown proof child type {}",
+                    x
+                )))
+            }
+        };
+        Ok(ProofPtr { id, chr, child })
+    }
+}
+
+impl StacksMessageCodec for ProofNode {
+    fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), CodecError> {
+        match self {
+            ProofNode::Leaf { path, value } => {
+                write_next(fd, &NODE_ID_LEAF)?;
+                write_path(fd, path)?;
+                fd.write_all(value).map_err(CodecError::WriteError)
+            }
+            ProofNode::Node { id, path, ptrs } => {
+                if node_ptr_count(*id) != Some(ptrs.len()) {
+                    return Err(CodecError::SerializeError(format!(
+                        "Node with ID {} has {} pointers",
+                        id,
+                        ptrs.len()
+                    )));
+                }
+                write_next(fd, id)?;
+                write_path(fd, path)?;
+                for ptr in ptrs.iter() {
+                    write_next(fd, ptr)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn consensus_deserialize<R: Read>(fd: &mut R) -> Result<ProofNode, CodecError> {
+        let id: u8 = read_next(fd)?;
+        let path = read_path(fd)?;
+        if id == NODE_ID_LEAF {
+            let mut value = [0u8; MARF_VALUE_LEN];
+            fd.read_exact(&mut value).map_err(CodecError::ReadError)?;
+            return Ok(ProofNode::Leaf { path, value });
+        }
+
+        let num_ptrs = node_ptr_count(id)
+            .ok_or_else(|| CodecError::DeserializeError(format!("Unknown proof node ID {}", id)))?;
+        let mut ptrs = Vec::with_capacity(num_ptrs);
+        for _ in 0..num_ptrs {
+            ptrs.push(read_next(fd)?);
+        }
+        Ok(ProofNode::Node { id, path, ptrs })
+    }
+}
+
+impl StacksMessageCodec for ProofTrie {
+    fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), CodecError> {
+        write_next(fd, &self.block_id)?;
+        match self.header {
+            Some(ref header) => {
+                if header.header.len() != STACKS_BLOCK_HEADER_LEN {
+                    return Err(CodecError::SerializeError(format!(
+                        "Block header has {} bytes",
+                        header.header.len()
+                    )));
+                }
+                write_next(fd, &1u8)?;
+                fd.write_all(&header.header)
+                    .map_err(CodecError::WriteError)?;
+                write_next(fd, &header.consensus_hash)?;
+            }
+            None => write_next(fd, &0u8)?,
+        }
+        write_next(fd, &self.ancestor_hashes)?;
+        write_next(fd, &self.nodes)
+    }
+
+    fn consensus_deserialize<R: Read>(fd: &mut R) -> Result<ProofTrie, CodecError> {
+        let block_id: StacksBlockId = read_next(fd)?;
+        let header = match read_next::<u8, _>(fd)? {
+            0 => None,
+            1 => {
+                let mut header = vec![0u8; STACKS_BLOCK_HEADER_LEN];
+                fd.read_exact(&mut header).map_err(CodecError::ReadError)?;
+                let consensus_hash: ConsensusHash = read_next(fd)?;
+                Some(ProofHeader {
+                    header,
+                    consensus_hash,
+                })
+            }
+            x => {
+                return Err(CodecError::DeserializeError(format!(
+                    "Invalid header flag {}",
+                    x
+                )))
+            }
+        };
+        let ancestor_hashes = read_next_at_most(fd, MAX_ANCESTOR_HASHES)?;
+        let nodes = read_next_at_most(fd, MAX_PROOF_TRIE_NODES)?;
+        Ok(ProofTrie {
+            block_id,
+            header,
+            ancestor_hashes,
+            nodes,
+        })
+    }
+}
+
+impl StacksMessageCodec for MarfBatchProof {
+    fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), CodecError> {
+        write_next(fd, &self.tries)
+    }
+
+    fn consensus_deserialize<R: Read>(fd: &mut R) -> Result<MarfBatchProof, CodecError> {
+        let tries = read_next_at_most(fd, MAX_PROOF_TRIES)?;
+        Ok(MarfBatchProof { tries })
+    }
+}
+
+fn hash_bytes(data: &[u8]) -> TrieHash {
+    TrieHash(Sha512Trunc256Sum::from_data(data).0)
+}
+
+/// The path of a key through the trie
+pub fn key_path(key: &str) -> [u8; TRIE_PATH_LEN] {
+    Sha512Trunc256Sum::from_data(key.as_bytes()).0
+}
+
+/// The value stored in the MARF for a value in the Clarity side store.  For Clarity data,
+/// `value` is the hex string of the consensus-serialized Clarity value, without a `0x` prefix.
+pub fn marf_value(value: &str) -> [u8; MARF_VALUE_LEN] {
+    let mut ret = [0u8; MARF_VALUE_LEN];
+    ret[0..32].copy_from_slice(&Sha512Trunc256Sum::from_data(value.as_bytes()).0);
+    ret
+}
+
+/// The MARF key of a Clarity data variable.
+/// `contract_id` is the fully-qualified contract identifier, like `SP000...000.my-contract`.
+pub fn data_var_key(contract_id: &str, var_name: &str) -> String {
+    format!("vm::{}::{}::{}", contract_id, STORE_TYPE_DATA_VAR, var_name)
+}
+
+/// The MARF key of a Clarity data map entry.
+/// `key_hex` is the hex string of the consensus-serialized Clarity key, without a `0x` prefix.
+pub fn data_map_entry_key(contract_id: &str, map_name: &str, key_hex: &str) -> String {
+    format!(
+        "vm::{}::{}::{}::{}",
+        contract_id, STORE_TYPE_DATA_MAP, map_name, key_hex
+    )
+}
+
+/// Read the index block hash and state root out of a serialized block header
+fn header_commitments(header: &ProofHeader) -> Option<(StacksBlockId, TrieHash)> {
+    if header.header.len() != STACKS_BLOCK_HEADER_LEN {
+        return None;
+    }
+    let mut work = [0u8; 8];
+    work.copy_from_slice(&header.header[HEADER_WORK_OFFSET..HEADER_WORK_OFFSET + 8]);
+    if u64::from_be_bytes(work) == 0 {
+        // the boot block's hash is a constant, so it does not commit to the header
+        return None;
+    }
+    let block_hash = BlockHeaderHash(Sha512Trunc256Sum::from_data(&header.header).0);
+    let block_id = StacksBlockId::new(&header.consensus_hash, &block_hash);
+
+    let mut state_root = [0u8; 32];
+    state_root
+        .copy_from_slice(&header.header[HEADER_STATE_ROOT_OFFSET..HEADER_STATE_ROOT_OFFSET + 32]);
+    Some((block_id, TrieHash(state_root)))
+}
+
+/// Hash the subtree rooted at `nodes[*next]`, which sits `level` nodes below the trie's root.
+/// Records where each revealed child ended up in `links`, which is indexed like `nodes`.
+fn hash_subtree(
+    nodes: &[ProofNode],
+    next: &mut usize,
+    level: usize,
+    links: &mut Vec<Vec<Option<usize>>>,
+) -> Result<TrieHash, Error> {
+    // every node below the root consumes at least one byte of the path
+    if level > TRIE_PATH_LEN {
+        return Err(Error::MalformedProof("Trie is too deep".into()));
+    }
+    let idx = *next;
+    let node = nodes
+        .get(idx)
+        .ok_or_else(|| Error::MalformedProof("Revealed node is missing".into()))?;
+    *next += 1;
+    links.push(vec![]);
+
+    let (id, path, ptrs) = match node {
+        ProofNode::Leaf { path, value } => {
+            let mut buf = vec![NODE_ID_LEAF, path.len() as u8];
+            buf.extend_from_slice(path);
+            buf.extend_from_slice(value);
+            return Ok(hash_bytes(&buf));
+        }
+        ProofNode::Node { id, path, ptrs } => (*id, path, ptrs),
+    };
+    if node_ptr_count(id) != Some(ptrs.len()) {
+        return Err(Error::MalformedProof(format!(
+            "Node with ID {} has {} pointers",
+            id,
+            ptrs.len()
+        )));
+    }
+
+    let mut buf = vec![id];
+    let mut child_hashes = Vec::with_capacity(ptrs.len());
+    let mut child_links = Vec::with_capacity(ptrs.len());
+    for ptr in ptrs.iter() {
+        buf.push(ptr.id);
+        buf.push(ptr.chr);
+        let is_empty = ptr.id == NODE_ID_EMPTY;
+        let (back_block, child_hash, link) = match ptr.child {
+            ProofChild::Empty if is_empty => (None, hash_bytes(&[]), None),
+            ProofChild::Hash(ref hash) if !is_empty && !is_backptr(ptr.id) => {
+                (None, hash.clone(), None)
+            }
+            ProofChild::Revealed if !is_empty && !is_backptr(ptr.id) => {
+                let child_idx = *next;
+                let hash = hash_subtree(nodes, next, level + 1, links)?;
+                (None, hash, Some(child_idx))
+            }
+            ProofChild::Back(ref block_id) if is_backptr(ptr.id) => {
+                (Some(block_id), TrieHash(block_id.0.clone()), None)
+            }
+            _ => {
+                return Err(Error::MalformedProof(format!(
+                    "Pointer with ID {} has child {:?}",
+                    ptr.id, &ptr.child
+                )))
+            }
+        };
+        match back_block {
+            Some(block_id) => buf.extend_from_slice(&block_id.0),
+            None => buf.extend_from_slice(&[0u8; 32]),
+        }
+        child_hashes.push(child_hash);
+        child_links.push(link);
+    }
+    buf.push(path.len() as u8);
+    buf.extend_from_slice(path);
+    for child_hash in child_hashes.iter() {
+        buf.extend_from_slice(child_hash.as_bytes());
+    }
+    links[idx] = child_links;
+    Ok(hash_bytes(&buf))
+}
+
+/// A revealed trie whose root hash has been checked
+struct VerifiedTrie {
+    nodes: Vec<ProofNode>,
+    /// For each node, the index of each revealed child, by pointer slot
+    links: Vec<Vec<Option<usize>>>,
+}
+
+impl ProofTrie {
+    /// Recompute this trie's root hash from its revealed nodes and ancestor hashes
+    fn root_hash(&self) -> Result<(TrieHash, Vec<Vec<Option<usize>>>), Error> {
+        match self.nodes.first() {
+            Some(ProofNode::Node { id, .. }) if *id == NODE_ID_NODE256 => {}
+            _ => {
+                return Err(Error::MalformedProof(format!(
+                    "Trie {} does not start with a root node",
+                    &self.block_id
+                )))
+            }
+        }
+        let mut next = 0;
+        let mut links = Vec::with_capacity(self.nodes.len());
+        let root_node_hash = hash_subtree(&self.nodes, &mut next, 0, &mut links)?;
+        if next != self.nodes.len() {
+            return Err(Error::MalformedProof(format!(
+                "Trie {} has {} unreachable nodes",
+                &self.block_id,
+                self.nodes.len() - next
+            )));
+        }
+
+        if self.ancestor_hashes.is_empty() {
+            return Ok((root_node_hash, links));
+        }
+        let mut buf = root_node_hash.as_bytes().to_vec();
+        for ancestor_hash in self.ancestor_hashes.iter() {
+            buf.extend_from_slice(ancestor_hash.as_bytes());
+        }
+        Ok((hash_bytes(&buf), links))
+    }
+}
+
+/// The part of the MARF at a block that a `MarfBatchProof` has proven
+pub struct VerifiedMarfState {
+    tries: Vec<VerifiedTrie>,
+    trie_index: HashMap<StacksBlockId, usize>,
+}
+
+impl MarfBatchProof {
+    /// Verify the proof against the state root of the block it was generated for.  Tries for
+    /// ancestor blocks are checked against their headers, unless their root hash is given in
+    /// `trusted_roots` (which is required for the boot block's trie).
+    pub fn verify(
+        &self,
+        state_root: &TrieHash,
+        trusted_roots: &HashMap<StacksBlockId, TrieHash>,
+    ) -> Result<VerifiedMarfState, Error> {
+        if self.tries.is_empty() {
+            return Err(Error::MalformedProof("Proof has no tries".into()));
+        }
+
+        let mut tries = Vec::with_capacity(self.tries.len());
+        let mut trie_index = HashMap::new();
+        for (i, trie) in self.tries.iter().enumerate() {
+            if trie_index.insert(trie.block_id.clone(), i).is_some() {
+                return Err(Error::MalformedProof(format!(
+                    "Trie {} appears more than once",
+                    &trie.block_id
+                )));
+            }
+
+            let expected_root = if i == 0 {
+                state_root.clone()
+            } else if let Some(root) = trusted_roots.get(&trie.block_id) {
+                root.clone()
+            } else {
+                let (block_id, root) = trie
+                    .header
+                    .as_ref()
+                    .and_then(header_commitments)
+                    .ok_or_else(|| Error::UnboundTrie(trie.block_id.clone()))?;
+                if block_id != trie.block_id {
+                    return Err(Error::MalformedProof(format!(
+                        "Header for trie {} has block ID {}",
+                        &trie.block_id, &block_id
+                    )));
+                }
+                root
+            };
+
+            let (root, links) = trie.root_hash()?;
+            if root != expected_root {
+                return Err(Error::RootHashMismatch(
+                    trie.block_id.clone(),
+                    expected_root,
+                    root,
+                ));
+            }
+            tries.push(VerifiedTrie {
+                nodes: trie.nodes.clone(),
+                links,
+            });
+        }
+
+        Ok(VerifiedMarfState { tries, trie_index })
+    }
+}
+
+impl VerifiedMarfState {
+    /// Look up a key.  Returns `Ok(None)` if the proof shows the key is not in the MARF, and
+    /// `Err(Error::KeyNotCovered(..))` if the proof does not reveal the key's path.
+    pub fn get(&self, key: &str) -> Result<Option<[u8; MARF_VALUE_LEN]>, Error> {
+        let path = key_path(key);
+        let not_covered = || Error::KeyNotCovered(key.to_string());
+
+        let mut trie = &self.tries[0];
+        let mut node_idx = 0;
+        let mut depth = 0;
+        // each hop into an ancestor trie starts over from that trie's root
+        let mut hops = 0;
+        loop {
+            match &trie.nodes[node_idx] {
+                ProofNode::Leaf {
+                    path: leaf_path,
+                    value,
+                } => {
+                    if &path[depth..] == &leaf_path[..] {
+                        return Ok(Some(value.clone()));
+                    }
+                    return Ok(None);
+                }
+                ProofNode::Node {
+                    path: node_path,
+                    ptrs,
+                    ..
+                } => {
+                    if !path[depth..].starts_with(node_path) {
+                        return Ok(None);
+                    }
+                    depth += node_path.len();
+                    if depth >= TRIE_PATH_LEN {
+                        return Err(Error::MalformedProof(
+                            "Intermediate node at the end of a path".into(),
+                        ));
+                    }
+
+                    let chr = path[depth];
+                    let Some(slot) = ptrs
+                        .iter()
+                        .position(|ptr| ptr.id != NODE_ID_EMPTY && ptr.chr == chr)
+                    else {
+                        return Ok(None);
+                    };
+                    match ptrs[slot].child {
+                        ProofChild::Revealed => {
+                            node_idx = trie.links[node_idx][slot].ok_or_else(not_covered)?;
+                            depth += 1;
+                        }
+                        ProofChild::Back(ref block_id) => {
+                            hops += 1;
+                            if hops >= self.tries.len() {
+                                return Err(Error::MalformedProof(
+                                    "Back-pointers form a cycle".into(),
+                                ));
+                            }
+                            let trie_idx =
+                                *self.trie_index.get(block_id).ok_or_else(not_covered)?;
+                            trie = &self.tries[trie_idx];
+                            node_idx = 0;
+                            depth = 0;
+                        }
+                        _ => return Err(not_covered()),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Check a value from the Clarity side store against the proof.  `value` is the hex string
+    /// of the consensus-serialized Clarity value (with or without a `0x` prefix), or `None` if
+    /// the key is claimed to be absent.
+    pub fn check_value(&self, key: &str, value: Option<&str>) -> Result<(), Error> {
+        let expected = value.map(|value| marf_value(value.trim_start_matches("0x")));
+        if self.get(key)? != expected {
+            return Err(Error::ValueMismatch(key.to_string()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use stacks_common::codec::StacksMessageCodec;
+use stacks_common::types::chainstate::{BlockHeaderHash, ConsensusHash, StacksBlockId, TrieHash};
+use stacks_common::util::hash::Sha512Trunc256Sum;
+
+use crate::*;
+
+fn empty_ptrs(count: usize) -> Vec<ProofPtr> {
+    (0..count)
+        .map(|i| ProofPtr {
+            id: NODE_ID_EMPTY,
+            chr: if count == 256 { i as u8 } else { 0 },
+            child: ProofChild::Empty,
+        })
+        .collect()
+}
+
+fn set_ptr(ptrs: &mut Vec<ProofPtr>, slot: usize, id: u8, chr: u8, child: ProofChild) {
+    ptrs[slot] = ProofPtr { id, chr, child };
+}
+
+fn leaf(key: &str, depth: usize, value: &str) -> ProofNode {
+    ProofNode::Leaf {
+        path: key_path(key)[depth..].to_vec(),
+        value: marf_value(value),
+    }
+}
+
+/// Find two keys whose paths share their first byte, but not their second
+fn sibling_keys() -> (String, String) {
+    let keys: Vec<String> = (0..1000).map(|i| format!("key-{}", i)).collect();
+    for (i, a) in keys.iter().enumerate() {
+        for b in keys[i + 1..].iter() {
+            let (pa, pb) = (key_path(a), key_path(b));
+            if pa[0] == pb[0] && pa[1] != pb[1] {
+                return (a.clone(), b.clone());
+            }
+        }
+    }
+    panic!("no sibling keys");
+}
+
+fn make_header(state_root: &TrieHash, work: u64) -> Vec<u8> {
+    let mut header = vec![0u8; STACKS_BLOCK_HEADER_LEN];
+    header[0] = 1;
+    header[HEADER_WORK_OFFSET..HEADER_WORK_OFFSET + 8].copy_from_slice(&work.to_be_bytes());
+    header[HEADER_STATE_ROOT_OFFSET..HEADER_STATE_ROOT_OFFSET + 32]
+        .copy_from_slice(state_root.as_bytes());
+    header
+}
+
+/// Build a proof over a tip trie with two sibling keys under a Node4 and a key in an ancestor
+/// trie.  Returns the proof, the tip's state root, and the keys.
+fn make_proof(with_header: bool) -> (MarfBatchProof, TrieHash, Vec<String>) {
+    let (key_a, key_b) = sibling_keys();
+    let key_c = (0..)
+        .map(|i| format!("other-key-{}", i))
+        .find(|key| key_path(key)[0] != key_path(&key_a)[0])
+        .unwrap();
+    let (path_a, path_b, path_c) = (key_path(&key_a), key_path(&key_b), key_path(&key_c));
+
+    // ancestor trie, holding key_c
+    let mut ancestor_ptrs = empty_ptrs(256);
+    set_ptr(
+        &mut ancestor_ptrs,
+        path_c[0] as usize,
+        NODE_ID_LEAF,
+        path_c[0],
+        ProofChild::Revealed,
+    );
+    let mut ancestor = ProofTrie {
+        block_id: StacksBlockId([0; 32]),
+        header: None,
+        ancestor_hashes: vec![TrieHash([0x11; 32])],
+        nodes: vec![
+            ProofNode::Node {
+                id: NODE_ID_NODE256,
+                path: vec![],
+                ptrs: ancestor_ptrs,
+            },
+            leaf(&key_c, 1, "value-c"),
+        ],
+    };
+    let (ancestor_root, _) = ancestor.root_hash().unwrap();
+    let consensus_hash = ConsensusHash([0x22; 20]);
+    let header = make_header(&ancestor_root, 2);
+    ancestor.block_id = StacksBlockId::new(
+        &consensus_hash,
+        &BlockHeaderHash(Sha512Trunc256Sum::from_data(&header).0),
+    );
+    if with_header {
+        ancestor.header = Some(ProofHeader {
+            header,
+            consensus_hash,
+        });
+    }
+
+    // tip trie: key_a is revealed, key_b is only hashed, and key_c is in the ancestor
+    let mut node4_ptrs = empty_ptrs(4);
+    set_ptr(
+        &mut node4_ptrs,
+        0,
+        NODE_ID_LEAF,
+        path_a[1],
+        ProofChild::Revealed,
+    );
+    set_ptr(
+        &mut node4_ptrs,
+        1,
+        NODE_ID_LEAF,
+        path_b[1],
+        ProofChild::Hash(TrieHash([0x33; 32])),
+    );
+    let mut root_ptrs = empty_ptrs(256);
+    set_ptr(
+        &mut root_ptrs,
+        path_a[0] as usize,
+        NODE_ID_NODE4,
+        path_a[0],
+        ProofChild::Revealed,
+    );
+    set_ptr(
+        &mut root_ptrs,
+        path_c[0] as usize,
+        NODE_ID_LEAF | NODE_ID_BACKPTR_BIT,
+        path_c[0],
+        ProofChild::Back(ancestor.block_id.clone()),
+    );
+    let tip = ProofTrie {
+        block_id: StacksBlockId([0x44; 32]),
+        header: None,
+        ancestor_hashes: vec![ancestor_root.clone()],
+        nodes: vec![
+            ProofNode::Node {
+                id: NODE_ID_NODE256,
+                path: vec![],
+                ptrs: root_ptrs,
+            },
+            ProofNode::Node {
+                id: NODE_ID_NODE4,
+                path: vec![],
+                ptrs: node4_ptrs,
+            },
+            leaf(&key_a, 2, "value-a"),
+        ],
+    };
+    let (state_root, _) = tip.root_hash().unwrap();
+
+    let proof = MarfBatchProof {
+        tries: vec![tip, ancestor],
+    };
+    (proof, state_root, vec![key_a, key_b, key_c])
+}
+
+#[test]
+fn test_batch_proof_codec() {
+    let (proof, _, _) = make_proof(true);
+    let bytes = proof.serialize_to_vec();
+    let decoded = MarfBatchProof::consensus_deserialize(&mut &bytes[..]).unwrap();
+    assert_eq!(decoded, proof);
+
+    // truncated proofs don't decode
+    assert!(MarfBatchProof::consensus_deserialize(&mut &bytes[..bytes.len() - 1]).is_err());
+}
+
+#[test]
+fn test_batch_proof_verify() {
+    let (proof, state_root, keys) = make_proof(true);
+    let state = proof.verify(&state_root, &HashMap::new()).unwrap();
+
+    assert_eq!(state.get(&keys[0]).unwrap(), Some(marf_value("value-a")));
+    state.check_value(&keys[0], Some("value-a")).unwrap();
+    state.check_value(&keys[2], Some("0xvalue-c")).unwrap();
+    assert!(matches!(
+        state.check_value(&keys[0], Some("value-b")),
+        Err(Error::ValueMismatch(_))
+    ));
+    assert!(matches!(
+        state.check_value(&keys[0], None),
+        Err(Error::ValueMismatch(_))
+    ));
+
+    // key_b's leaf is only hashed
+    assert!(matches!(state.get(&keys[1]), Err(Error::KeyNotCovered(_))));
+
+    // a key whose first byte has an empty pointer in the root is provably absent
+    let absent = (0..)
+        .map(|i| format!("absent-key-{}", i))
+        .find(|key| {
+            let path = key_path(key);
+            path[0] != key_path(&keys[0])[0] && path[0] != key_path(&keys[2])[0]
+        })
+        .unwrap();
+    assert_eq!(state.get(&absent).unwrap(), None);
+    state.check_value(&absent, None).unwrap();
+}
+
+#[test]
+fn test_batch_proof_reject() {
+    let (proof, state_root, _) = make_proof(true);
+
+    // wrong state root
+    assert!(matches!(
+        proof.verify(&TrieHash([0; 32]), &HashMap::new()),
+        Err(Error::RootHashMismatch(..))
+    ));
+
+    // tampered leaf
+    let mut bad_proof = proof.clone();
+    bad_proof.tries[0].nodes[2] = leaf("nope", 2, "value-a");
+    assert!(matches!(
+        bad_proof.verify(&state_root, &HashMap::new()),
+        Err(Error::RootHashMismatch(..))
+    ));
+
+    // tampered ancestor trie
+    let mut bad_proof = proof.clone();
+    bad_proof.tries[1].ancestor_hashes[0] = TrieHash([0x12; 32]);
+    assert!(matches!(
+        bad_proof.verify(&state_root, &HashMap::new()),
+        Err(Error::RootHashMismatch(..))
+    ));
+
+    // header for some other block
+    let mut bad_proof = proof.clone();
+    bad_proof.tries[1].header.as_mut().unwrap().consensus_hash = ConsensusHash([0x23; 20]);
+    assert!(matches!(
+        bad_proof.verify(&state_root, &HashMap::new()),
+        Err(Error::MalformedProof(_))
+    ));
+
+    // left-over nodes
+    let mut bad_proof = proof.clone();
+    bad_proof.tries[0].nodes.push(leaf("nope", 2, "value-a"));
+    assert!(matches!(
+        bad_proof.verify(&state_root, &HashMap::new()),
+        Err(Error::MalformedProof(_))
+    ));
+
+    // pointer ID doesn't match the child
+    let mut bad_proof = proof.clone();
+    if let ProofNode::Node { ref mut ptrs, .. } = bad_proof.tries[0].nodes[1] {
+        ptrs[1].id = NODE_ID_LEAF | NODE_ID_BACKPTR_BIT;
+    }
+    assert!(matches!(
+        bad_proof.verify(&state_root, &HashMap::new()),
+        Err(Error::MalformedProof(_))
+    ));
+}
+
+#[test]
+fn test_batch_proof_trusted_roots() {
+    let (proof, state_root, keys) = make_proof(false);
+    let ancestor_id = proof.tries[1].block_id.clone();
+
+    // no header, so the ancestor trie can't be checked
+    assert!(matches!(
+        proof.verify(&state_root, &HashMap::new()),
+        Err(Error::UnboundTrie(_))
+    ));
+
+    // ...unless its root is already known
+    let (ancestor_root, _) = proof.tries[1].root_hash().unwrap();
+    let trusted_roots = HashMap::from([(ancestor_id.clone(), ancestor_root)]);
+    let state = proof.verify(&state_root, &trusted_roots).unwrap();
+    state.check_value(&keys[2], Some("value-c")).unwrap();
+
+    // the boot block's header doesn't commit to its state root
+    let mut boot_proof = proof.clone();
+    boot_proof.tries[1].header = Some(ProofHeader {
+        header: make_header(&ancestor_root, 0),
+        consensus_hash: ConsensusHash([0x22; 20]),
+    });
+    assert!(matches!(
+        boot_proof.verify(&state_root, &HashMap::new()),
+        Err(Error::UnboundTrie(_))
+    ));
+}
+
+#[test]
+fn test_clarity_keys() {
+    assert_eq!(
+        data_var_key("SP000000000000000000002Q6VF78.foo", "bar"),
+        "vm::SP000000000000000000002Q6VF78.foo::1::bar"
+    );
+    assert_eq!(
+        data_map_entry_key("SP000000000000000000002Q6VF78.foo", "baz", "0100"),
+        "vm::SP000000000000000000002Q6VF78.foo::0::baz::0100"
+    );
+}
@@ -30,7 +30,33 @@ pub mod vrf;
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::{error, fmt, thread, time};
 
+#[cfg(any(test, feature = "testing"))]
+thread_local! {
+    static MOCK_EPOCH_TIME_MS: std::cell::Cell<Option<u128>> = const { std::cell::Cell::new(None) };
+}
+
+/// Override the clock read by `get_epoch_time_secs()` and `get_epoch_time_ms()` on the calling
+/// thread.  Pass `None` to go back to the system clock.  Used by simulations that need a
+/// virtual clock.
+#[cfg(any(test, feature = "testing"))]
+pub fn set_mock_epoch_time_ms(time_ms: Option<u128>) {
+    MOCK_EPOCH_TIME_MS.with(|mock| mock.set(time_ms));
+}
+
+#[cfg(any(test, feature = "testing"))]
+fn get_mock_epoch_time_ms() -> Option<u128> {
+    MOCK_EPOCH_TIME_MS.with(|mock| mock.get())
+}
+
+#[cfg(not(any(test, feature = "testing")))]
+fn get_mock_epoch_time_ms() -> Option<u128> {
+    None
+}
+
 pub fn get_epoch_time_secs() -> u64 {
+    if let Some(time_ms) = get_mock_epoch_time_ms() {
+        return (time_ms / 1000) as u64;
+    }
     let start = SystemTime::now();
     let since_the_epoch = start
         .duration_since(UNIX_EPOCH)
@@ -39,6 +65,9 @@ pub fn get_epoch_time_secs() -> u64 {
 }
 
 pub fn get_epoch_time_ms() -> u128 {
+    if let Some(time_ms) = get_mock_epoch_time_ms() {
+        return time_ms;
+    }
     let start = SystemTime::now();
     let since_the_epoch = start
         .duration_since(UNIX_EPOCH)
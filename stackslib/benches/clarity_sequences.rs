@@ -0,0 +1,156 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2023 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Benchmarks of list-, buffer-, and tuple-heavy Clarity code, which is dominated by the cost of
+//! copying sequence values around the interpreter.
+//!
+//! Run with `cargo bench -p stackslib --bench clarity_sequences`.
+
+use clarity::vm::ast::ASTRules;
+use clarity::vm::{execute_with_parameters, ClarityVersion};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use stacks_common::types::StacksEpochId;
+
+const LIST_LEN: usize = 1000;
+const ITERATIONS: usize = 100;
+/// Length of the lists of large buffers
+const LARGE_LIST_LEN: usize = 200;
+/// Size of each large buffer
+const LARGE_BUFF_LEN: usize = 4096;
+
+fn uint_list(len: usize) -> String {
+    let items: Vec<_> = (0..len).map(|i| format!("u{}", i)).collect();
+    format!("(list {})", items.join(" "))
+}
+
+/// Call `body` (an expression over `x`) `ITERATIONS` times on the same large value.
+fn repeated(definitions: &str, value: &str, body: &str) -> String {
+    let calls: Vec<_> = (0..ITERATIONS).map(|_| "(f x)".to_string()).collect();
+    format!(
+        "{definitions}
+         (define-private (f (v (list {LIST_LEN} uint))) {body})
+         (define-constant x {value})
+         (list {calls})",
+        calls = calls.join(" "),
+    )
+}
+
+/// Call `body` (an expression over `x`) `ITERATIONS` times on a list of `LARGE_LIST_LEN`
+/// buffers of `LARGE_BUFF_LEN` bytes each, so that copying the items dominates.
+fn repeated_large(definitions: &str, body: &str) -> String {
+    let calls: Vec<_> = (0..ITERATIONS).map(|_| "(f x)".to_string()).collect();
+    format!(
+        "(define-constant b 0x{buff})
+         (define-private (mk (i uint)) b)
+         (define-constant x (map mk {indexes}))
+         {definitions}
+         (define-private (f (v (list {LARGE_LIST_LEN} (buff {LARGE_BUFF_LEN})))) {body})
+         (list {calls})",
+        buff = "00".repeat(LARGE_BUFF_LEN),
+        indexes = uint_list(LARGE_LIST_LEN),
+        calls = calls.join(" "),
+    )
+}
+
+fn programs() -> Vec<(&'static str, String)> {
+    let list = uint_list(LIST_LEN);
+    let calls: Vec<_> = (0..ITERATIONS).map(|i| format!("(g u{})", i)).collect();
+    vec![
+        ("list_argument", repeated("", &list, "(len v)")),
+        (
+            "list_map_fold",
+            repeated(
+                "(define-private (square (i uint)) (* i i))",
+                &list,
+                "(fold + (map square v) u0)",
+            ),
+        ),
+        (
+            "list_filter",
+            repeated(
+                "(define-private (even (i uint)) (is-eq (mod i u2) u0))",
+                &list,
+                "(len (filter even v))",
+            ),
+        ),
+        (
+            "list_element_at",
+            format!(
+                "(define-constant x {list})
+                 (define-private (g (i uint)) (element-at x i))
+                 (list {calls})",
+                calls = calls.join(" "),
+            ),
+        ),
+        (
+            "tuple_get",
+            format!(
+                "(define-constant x {{ items: {list}, buff: 0x{buff} }})
+                 (define-private (g (i uint)) (len (get items x)))
+                 (list {calls})",
+                buff = "00".repeat(4096),
+                calls = calls.join(" "),
+            ),
+        ),
+        (
+            "buffer_concat",
+            format!(
+                "(define-constant x 0x{buff})
+                 (define-private (g (i uint)) (len (concat x x)))
+                 (list {calls})",
+                buff = "00".repeat(4096),
+                calls = calls.join(" "),
+            ),
+        ),
+        (
+            "large_map_fold",
+            repeated_large("", "(fold + (map len v) u0)"),
+        ),
+        (
+            "large_filter",
+            repeated_large(
+                "(define-private (non-empty (i (buff 4096))) (> (len i) u0))",
+                "(len (filter non-empty v))",
+            ),
+        ),
+        (
+            "large_tuple_get",
+            repeated_large("", "(len (get items { items: v }))"),
+        ),
+    ]
+}
+
+fn clarity_sequences(c: &mut Criterion) {
+    let mut group = c.benchmark_group("clarity_sequences");
+    for (name, program) in programs() {
+        group.bench_with_input(BenchmarkId::from_parameter(name), &program, |b, program| {
+            b.iter(|| {
+                execute_with_parameters(
+                    program,
+                    ClarityVersion::Clarity2,
+                    StacksEpochId::Epoch24,
+                    ASTRules::PrecheckSize,
+                    false,
+                )
+                .expect("benchmark program failed")
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, clarity_sequences);
+criterion_main!(benches);
@@ -0,0 +1,104 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Benchmarks of sealing a large MARF block in deferred hashing mode, with the trie's node hashes
+//! calculated on different numbers of threads.
+//!
+//! Run with `cargo bench -p stackslib --bench marf_hashing`.
+
+use std::time::{Duration, Instant};
+
+use blockstack_lib::chainstate::stacks::index::marf::{MARFOpenOpts, MARF};
+use blockstack_lib::chainstate::stacks::index::storage::{
+    TrieFileStorage, TrieHashCalculationMode,
+};
+use blockstack_lib::chainstate::stacks::index::{ClarityMarfTrieId, MARFValue};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use stacks_common::types::chainstate::StacksBlockId;
+
+/// Writes in each block.  The second block overwrites half of the first block's keys, so that
+/// its trie has back-pointers as well as new nodes.
+const BLOCK_WRITES: usize = 20_000;
+
+fn block_hash(i: u64) -> StacksBlockId {
+    let mut bytes = [0u8; 32];
+    bytes[0..8].copy_from_slice(&i.to_be_bytes());
+    StacksBlockId(bytes)
+}
+
+fn block_data(block: usize) -> (Vec<String>, Vec<MARFValue>) {
+    let start = block * BLOCK_WRITES / 2;
+    let keys: Vec<String> = (start..start + BLOCK_WRITES)
+        .map(|i| format!("key-{}", i))
+        .collect();
+    let values = (0..BLOCK_WRITES)
+        .map(|i| {
+            let mut value = [0u8; 40];
+            value[0..8].copy_from_slice(&(i as u64).to_be_bytes());
+            value[8] = block as u8;
+            MARFValue(value)
+        })
+        .collect();
+    (keys, values)
+}
+
+/// Make a MARF with one committed block
+fn make_marf(hash_threads: usize) -> MARF<StacksBlockId> {
+    let opts = MARFOpenOpts::new(TrieHashCalculationMode::Deferred, "noop", false)
+        .with_hash_threads(hash_threads);
+    let mut marf = MARF::from_storage(TrieFileStorage::open(":memory:", opts).unwrap());
+    let (keys, values) = block_data(0);
+    let mut tx = marf.begin_tx().unwrap();
+    tx.begin(&StacksBlockId::sentinel(), &block_hash(0))
+        .unwrap();
+    tx.insert_batch(&keys, values).unwrap();
+    tx.commit().unwrap();
+    marf
+}
+
+fn marf_hashing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("marf_seal");
+    group.sample_size(10);
+    let (keys, values) = block_data(1);
+    for hash_threads in [1, 2, 4, 8] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(hash_threads),
+            &hash_threads,
+            |b, &hash_threads| {
+                b.iter_custom(|iters| {
+                    let mut total = Duration::ZERO;
+                    for _ in 0..iters {
+                        let mut marf = make_marf(hash_threads);
+                        let mut tx = marf.begin_tx().unwrap();
+                        tx.begin(&block_hash(0), &block_hash(1)).unwrap();
+                        tx.insert_batch(&keys, values.clone()).unwrap();
+
+                        let start = Instant::now();
+                        black_box(tx.seal().unwrap());
+                        total += start.elapsed();
+
+                        tx.drop_current();
+                    }
+                    total
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, marf_hashing);
+criterion_main!(benches);
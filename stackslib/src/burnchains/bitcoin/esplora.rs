@@ -0,0 +1,1074 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A burnchain indexer that reads Bitcoin headers and blocks from an Esplora-compatible REST
+//! API (i.e. Blockstream's `electrs` fork, or anything that speaks the same protocol) instead
+//! of from the Bitcoin peer network.
+//!
+//! Headers are validated and stored in the same SPV headers DB that the `BitcoinIndexer` uses,
+//! so anything that only reads headers (such as the chains coordinator) can keep using a
+//! `BitcoinIndexer` pointed at the same file.  Blocks are checked against their stored headers
+//! before they are handed to the `BitcoinBlockParser`.
+//!
+//! Only plain `http://` endpoints are supported.
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use stacks_common::deps_common::bitcoin::blockdata::block::{Block, BlockHeader, LoneBlockHeader};
+use stacks_common::deps_common::bitcoin::network::encodable::VarInt;
+use stacks_common::deps_common::bitcoin::network::serialize::{
+    deserialize as btc_deserialize, BitcoinHash,
+};
+use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
+use stacks_common::types::chainstate::BurnchainHeaderHash;
+use stacks_common::util::get_epoch_time_secs;
+use url::Url;
+
+use crate::burnchains::bitcoin::blocks::{BitcoinBlockIPC, BitcoinBlockParser, BitcoinHeaderIPC};
+use crate::burnchains::bitcoin::indexer::{get_bitcoin_stacks_epochs, USER_AGENT};
+use crate::burnchains::bitcoin::spv::SpvClient;
+use crate::burnchains::bitcoin::{BitcoinNetworkType, Error as btc_error, PeerMessage};
+use crate::burnchains::db::BurnchainHeaderReader;
+use crate::burnchains::indexer::{
+    BurnHeaderIPC, BurnchainBlockDownloader, BurnchainBlockParser, BurnchainIndexer,
+};
+use crate::burnchains::{
+    BurnchainBlock, BurnchainBlockHeader, Error as burnchain_error, MagicBytes,
+};
+use crate::core::{StacksEpoch, StacksEpochId};
+use crate::util_lib::db::Error as DBError;
+
+/// Number of blocks an Esplora server returns from `GET /blocks/:start_height`
+pub const ESPLORA_BLOCKS_PAGE_SIZE: u64 = 10;
+
+/// Largest HTTP response we will accept from an Esplora server (a block can be up to 4MB, and
+/// we leave some room for the HTTP framing)
+const ESPLORA_MAX_RESPONSE_SIZE: u64 = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EsploraIndexerConfig {
+    /// Base URL of the Esplora API, e.g. `http://127.0.0.1:3002/api`
+    pub url: String,
+    /// Connect, read, and write timeout for each request, in seconds
+    pub timeout: u64,
+    pub spv_headers_path: String,
+    pub first_block: u64,
+    pub magic_bytes: MagicBytes,
+    pub epochs: Option<Vec<StacksEpoch>>,
+    pub network_id: BitcoinNetworkType,
+}
+
+/// Block metadata as returned by `GET /blocks/:start_height`.  Esplora returns more fields than
+/// this; we only need the ones that make up the block header.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct EsploraBlockInfo {
+    pub id: String,
+    pub height: u64,
+    pub version: u32,
+    pub timestamp: u32,
+    pub merkle_root: String,
+    pub previousblockhash: Option<String>,
+    pub nonce: u32,
+    pub bits: u32,
+}
+
+impl EsploraBlockInfo {
+    /// Reconstruct the block header, and verify that it hashes to the block ID the server
+    /// claimed for it.
+    pub fn to_header(&self) -> Result<BlockHeader, btc_error> {
+        let prev_blockhash = match self.previousblockhash {
+            Some(ref hash) => Sha256dHash::from_hex(hash).map_err(btc_error::HashError)?,
+            None => Sha256dHash::default(),
+        };
+        let header = BlockHeader {
+            version: self.version,
+            prev_blockhash,
+            merkle_root: Sha256dHash::from_hex(&self.merkle_root).map_err(btc_error::HashError)?,
+            time: self.timestamp,
+            bits: self.bits,
+            nonce: self.nonce,
+        };
+        let id = Sha256dHash::from_hex(&self.id).map_err(btc_error::HashError)?;
+        if header.bitcoin_hash() != id {
+            warn!(
+                "Esplora block {} at height {} does not hash to its ID (got {})",
+                &self.id,
+                self.height,
+                header.bitcoin_hash()
+            );
+            return Err(btc_error::InvalidReply);
+        }
+        Ok(header)
+    }
+}
+
+/// Minimal blocking HTTP/1.1 client for the handful of Esplora endpoints we use.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EsploraClient {
+    host: String,
+    port: u16,
+    path_prefix: String,
+    timeout: Duration,
+}
+
+impl EsploraClient {
+    pub fn new(url: &str, timeout: u64) -> Result<EsploraClient, btc_error> {
+        let url = Url::parse(url)
+            .map_err(|e| btc_error::ConfigError(format!("Invalid Esplora URL {}: {}", url, e)))?;
+        if url.scheme() != "http" {
+            return Err(btc_error::ConfigError(format!(
+                "Unsupported Esplora URL scheme '{}': only http:// is supported",
+                url.scheme()
+            )));
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| btc_error::ConfigError(format!("No host in Esplora URL {}", url)))?
+            .to_string();
+        let port = url.port_or_known_default().unwrap_or(80);
+        let path_prefix = url.path().trim_end_matches('/').to_string();
+        Ok(EsploraClient {
+            host,
+            port,
+            path_prefix,
+            timeout: Duration::from_secs(timeout.max(1)),
+        })
+    }
+
+    fn connect(&self) -> Result<TcpStream, btc_error> {
+        let addrs = (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .map_err(|e| {
+                warn!("Failed to resolve Esplora host {}: {:?}", &self.host, &e);
+                btc_error::ConnectionError
+            })?;
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, self.timeout) {
+                Ok(sock) => {
+                    sock.set_read_timeout(Some(self.timeout))
+                        .map_err(btc_error::Io)?;
+                    sock.set_write_timeout(Some(self.timeout))
+                        .map_err(btc_error::Io)?;
+                    return Ok(sock);
+                }
+                Err(e) => {
+                    debug!("Failed to connect to Esplora server at {}: {:?}", &addr, &e);
+                }
+            }
+        }
+        Err(btc_error::ConnectionError)
+    }
+
+    /// Send `GET <prefix><path>` and return the response body.  Any status other than 200 is
+    /// an `InvalidReply`.
+    pub fn get(&self, path: &str) -> Result<Vec<u8>, btc_error> {
+        let mut sock = self.connect()?;
+        let request = format!(
+            "GET {}{} HTTP/1.1\r\nHost: {}:{}\r\nUser-Agent: {}\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+            &self.path_prefix, path, &self.host, self.port, USER_AGENT
+        );
+        sock.write_all(request.as_bytes()).map_err(Self::io_error)?;
+
+        let mut response = vec![];
+        sock.take(ESPLORA_MAX_RESPONSE_SIZE + 1)
+            .read_to_end(&mut response)
+            .map_err(Self::io_error)?;
+        if response.len() as u64 > ESPLORA_MAX_RESPONSE_SIZE {
+            warn!("Esplora response to GET {} is too big", path);
+            return Err(btc_error::InvalidReply);
+        }
+
+        let (status, body) = Self::parse_response(&response)?;
+        if status != 200 {
+            debug!("Esplora server replied {} to GET {}", status, path);
+            return Err(btc_error::InvalidReply);
+        }
+        Ok(body)
+    }
+
+    fn io_error(e: std::io::Error) -> btc_error {
+        match e.kind() {
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => btc_error::TimedOut,
+            _ => btc_error::Io(e),
+        }
+    }
+
+    /// Split a complete HTTP/1.x response into its status code and (de-chunked) body
+    fn parse_response(response: &[u8]) -> Result<(u16, Vec<u8>), btc_error> {
+        let header_end = response
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .ok_or(btc_error::InvalidReply)?;
+        let preamble =
+            std::str::from_utf8(&response[..header_end]).map_err(|_| btc_error::InvalidReply)?;
+        let body = &response[header_end + 4..];
+
+        let mut lines = preamble.split("\r\n");
+        let status_line = lines.next().ok_or(btc_error::InvalidReply)?;
+        let mut status_parts = status_line.split_whitespace();
+        match status_parts.next() {
+            Some(version) if version.starts_with("HTTP/1.") => {}
+            _ => return Err(btc_error::InvalidReply),
+        }
+        let status = status_parts
+            .next()
+            .and_then(|s| s.parse::<u16>().ok())
+            .ok_or(btc_error::InvalidReply)?;
+
+        let mut content_length = None;
+        let mut chunked = false;
+        for line in lines {
+            let (name, value) = match line.split_once(':') {
+                Some(x) => x,
+                None => return Err(btc_error::InvalidReply),
+            };
+            let value = value.trim();
+            match name.trim().to_lowercase().as_str() {
+                "content-length" => {
+                    content_length = Some(
+                        value
+                            .parse::<usize>()
+                            .map_err(|_| btc_error::InvalidReply)?,
+                    );
+                }
+                "transfer-encoding" => {
+                    chunked = value.to_lowercase().contains("chunked");
+                }
+                _ => {}
+            }
+        }
+
+        if chunked {
+            return Ok((status, Self::decode_chunked(body)?));
+        }
+        match content_length {
+            Some(len) => {
+                if body.len() < len {
+                    return Err(btc_error::InvalidReply);
+                }
+                Ok((status, body[..len].to_vec()))
+            }
+            None => Ok((status, body.to_vec())),
+        }
+    }
+
+    fn decode_chunked(mut body: &[u8]) -> Result<Vec<u8>, btc_error> {
+        let mut ret = vec![];
+        loop {
+            let line_end = body
+                .windows(2)
+                .position(|w| w == b"\r\n")
+                .ok_or(btc_error::InvalidReply)?;
+            let size_str =
+                std::str::from_utf8(&body[..line_end]).map_err(|_| btc_error::InvalidReply)?;
+            let size_str = size_str.split(';').next().unwrap_or("").trim();
+            let size = usize::from_str_radix(size_str, 16).map_err(|_| btc_error::InvalidReply)?;
+            body = &body[line_end + 2..];
+            if size == 0 {
+                return Ok(ret);
+            }
+            if body.len() < size + 2 {
+                return Err(btc_error::InvalidReply);
+            }
+            ret.extend_from_slice(&body[..size]);
+            body = &body[size + 2..];
+        }
+    }
+
+    fn get_text(&self, path: &str) -> Result<String, btc_error> {
+        let body = self.get(path)?;
+        String::from_utf8(body)
+            .map(|s| s.trim().to_string())
+            .map_err(|_| btc_error::InvalidReply)
+    }
+
+    /// `GET /blocks/tip/height`
+    pub fn get_tip_height(&self) -> Result<u64, btc_error> {
+        self.get_text("/blocks/tip/height")?
+            .parse::<u64>()
+            .map_err(|_| btc_error::InvalidReply)
+    }
+
+    /// `GET /blocks/:start_height`: up to `ESPLORA_BLOCKS_PAGE_SIZE` blocks, from `start_height`
+    /// downwards
+    pub fn get_blocks(&self, start_height: u64) -> Result<Vec<EsploraBlockInfo>, btc_error> {
+        let body = self.get(&format!("/blocks/{}", start_height))?;
+        serde_json::from_slice(&body).map_err(|e| {
+            warn!(
+                "Failed to decode Esplora blocks at {}: {:?}",
+                start_height, &e
+            );
+            btc_error::InvalidReply
+        })
+    }
+
+    /// `GET /block/:hash/raw`
+    pub fn get_raw_block(&self, block_hash: &Sha256dHash) -> Result<Block, btc_error> {
+        let body = self.get(&format!("/block/{}/raw", block_hash.be_hex_string()))?;
+        btc_deserialize(&body).map_err(btc_error::SerializationError)
+    }
+}
+
+pub struct EsploraIndexer {
+    pub config: EsploraIndexerConfig,
+    pub should_keep_running: Option<Arc<AtomicBool>>,
+    client: EsploraClient,
+}
+
+pub struct EsploraBlockDownloader {
+    client: EsploraClient,
+}
+
+/// Parses blocks fetched by an `EsploraBlockDownloader`.  The blocks are ordinary Bitcoin
+/// blocks, so this just defers to `BitcoinBlockParser`.
+pub struct EsploraBlockParser {
+    parser: BitcoinBlockParser,
+}
+
+impl EsploraIndexer {
+    pub fn new(
+        config: EsploraIndexerConfig,
+        should_keep_running: Option<Arc<AtomicBool>>,
+    ) -> Result<EsploraIndexer, btc_error> {
+        let client = EsploraClient::new(&config.url, config.timeout)?;
+        Ok(EsploraIndexer {
+            config,
+            should_keep_running,
+            client,
+        })
+    }
+
+    fn open_spv_client(&self, readwrite: bool) -> Result<SpvClient, btc_error> {
+        SpvClient::new(
+            &self.config.spv_headers_path,
+            0,
+            None,
+            self.config.network_id,
+            readwrite,
+            false,
+        )
+    }
+
+    fn check_keep_running(&self) -> Result<(), burnchain_error> {
+        if let Some(ref should_keep_running) = self.should_keep_running {
+            if !should_keep_running.load(Ordering::SeqCst) {
+                return Err(burnchain_error::CoordinatorClosed);
+            }
+        }
+        Ok(())
+    }
+
+    fn map_btc_error(e: btc_error) -> burnchain_error {
+        match e {
+            btc_error::TimedOut => burnchain_error::TrySyncAgain,
+            x => burnchain_error::Bitcoin(x),
+        }
+    }
+
+    /// Fetch the headers in `(start_height, end_height]` from the Esplora server, validate them,
+    /// and append them to the headers DB after `start_height`.
+    fn fetch_headers(
+        &self,
+        spv_client: &mut SpvClient,
+        start_height: u64,
+        end_height: u64,
+    ) -> Result<(), burnchain_error> {
+        let mut cur_height = start_height;
+        while cur_height < end_height {
+            self.check_keep_running()?;
+
+            let batch_end = (cur_height + ESPLORA_BLOCKS_PAGE_SIZE).min(end_height);
+            let mut infos = self
+                .client
+                .get_blocks(batch_end)
+                .map_err(Self::map_btc_error)?;
+            infos.retain(|info| info.height > cur_height && info.height <= batch_end);
+            infos.sort_by_key(|info| info.height);
+
+            if infos.len() as u64 != batch_end - cur_height {
+                warn!(
+                    "Esplora server returned {} blocks for heights {}-{}",
+                    infos.len(),
+                    cur_height + 1,
+                    batch_end
+                );
+                return Err(burnchain_error::TrySyncAgain);
+            }
+
+            let mut headers = Vec::with_capacity(infos.len());
+            for (i, info) in infos.iter().enumerate() {
+                if info.height != cur_height + 1 + (i as u64) {
+                    warn!("Esplora server returned non-contiguous block heights");
+                    return Err(burnchain_error::Bitcoin(btc_error::NoncontiguousHeader));
+                }
+                headers.push(LoneBlockHeader {
+                    header: info.to_header().map_err(Self::map_btc_error)?,
+                    tx_count: VarInt(0),
+                });
+            }
+
+            spv_client
+                .handle_headers(cur_height, headers)
+                .map_err(Self::map_btc_error)?;
+            cur_height = batch_end;
+
+            if end_height - start_height > 2000 && cur_height % 2000 == 0 {
+                info!(
+                    "Syncing Bitcoin headers from Esplora: {:.1}% ({} out of {})",
+                    (cur_height - start_height) as f32 / (end_height - start_height) as f32 * 100.,
+                    cur_height,
+                    end_height
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Verify that the last block header we have is within 2 hours of now.
+    /// Return burnchain_error::TrySyncAgain if not, and delete the offending header
+    pub fn check_chain_tip_timestamp(&mut self) -> Result<(), burnchain_error> {
+        let highest_header_height = self.get_highest_header_height()?;
+        if highest_header_height == 0 {
+            return Err(burnchain_error::TrySyncAgain);
+        }
+
+        let highest_header = self
+            .read_headers(highest_header_height, highest_header_height + 1)?
+            .pop()
+            .expect("FATAL: no header at highest known height");
+        let now = get_epoch_time_secs();
+        let header_time = highest_header.block_header.header.time as u64;
+        if now - 2 * 60 * 60 <= header_time && header_time <= now + 2 * 60 * 60 {
+            return Ok(());
+        }
+        warn!(
+            "Header at height {} is not within 2 hours of now (is at {})",
+            highest_header_height, header_time
+        );
+        self.drop_headers(highest_header_height.saturating_sub(1))?;
+        Err(burnchain_error::TrySyncAgain)
+    }
+}
+
+impl BurnchainBlockDownloader for EsploraBlockDownloader {
+    type H = BitcoinHeaderIPC;
+    type B = BitcoinBlockIPC;
+
+    fn download(&mut self, header: &BitcoinHeaderIPC) -> Result<BitcoinBlockIPC, burnchain_error> {
+        let block_hash = header.block_header.header.bitcoin_hash();
+        let block = self
+            .client
+            .get_raw_block(&block_hash)
+            .map_err(|e| match e {
+                btc_error::TimedOut => burnchain_error::TrySyncAgain,
+                x => burnchain_error::DownloadError(x),
+            })?;
+
+        if !BitcoinBlockParser::check_block(&block, &header.block_header) {
+            warn!(
+                "Esplora server returned block {} for requested block {} at height {}",
+                block.bitcoin_hash(),
+                &block_hash,
+                header.block_height
+            );
+            return Err(burnchain_error::DownloadError(btc_error::InvalidReply));
+        }
+
+        debug!("Got block {}: {}", header.block_height, &block_hash);
+        Ok(BitcoinBlockIPC {
+            header_data: header.clone(),
+            block_message: PeerMessage::Block(block),
+        })
+    }
+}
+
+impl BurnchainBlockParser for EsploraBlockParser {
+    type D = EsploraBlockDownloader;
+
+    fn parse(
+        &mut self,
+        block: &BitcoinBlockIPC,
+        epoch_id: StacksEpochId,
+    ) -> Result<BurnchainBlock, burnchain_error> {
+        self.parser.parse(block, epoch_id)
+    }
+}
+
+impl BurnchainIndexer for EsploraIndexer {
+    type P = EsploraBlockParser;
+
+    /// There is no persistent connection to set up, but make sure the server is reachable
+    fn connect(&mut self) -> Result<(), burnchain_error> {
+        self.client
+            .get_tip_height()
+            .map(|_| ())
+            .map_err(burnchain_error::Bitcoin)
+    }
+
+    fn get_first_block_height(&self) -> u64 {
+        self.config.first_block
+    }
+
+    fn get_first_block_header_hash(&self) -> Result<BurnchainHeaderHash, burnchain_error> {
+        let spv_client = self.open_spv_client(false)?;
+        let first_header = spv_client
+            .read_block_header(self.config.first_block)?
+            .expect("BUG: no first block header hash");
+        Ok(BurnchainHeaderHash::from_bitcoin_hash(
+            &first_header.header.bitcoin_hash(),
+        ))
+    }
+
+    fn get_first_block_header_timestamp(&self) -> Result<u64, burnchain_error> {
+        let spv_client = self.open_spv_client(false)?;
+        let first_header = spv_client
+            .read_block_header(self.config.first_block)?
+            .expect("BUG: no first block header timestamp");
+        Ok(first_header.header.time as u64)
+    }
+
+    /// Same rules as the `BitcoinIndexer`: custom epochs if configured (never on mainnet),
+    /// otherwise the network's defaults.
+    fn get_stacks_epochs(&self) -> Vec<StacksEpoch> {
+        match self.config.epochs {
+            Some(ref epochs) => {
+                assert!(self.config.network_id != BitcoinNetworkType::Mainnet);
+                epochs.clone()
+            }
+            None => get_bitcoin_stacks_epochs(self.config.network_id),
+        }
+    }
+
+    fn get_headers_path(&self) -> String {
+        self.config.spv_headers_path.clone()
+    }
+
+    fn get_headers_height(&self) -> Result<u64, burnchain_error> {
+        self.open_spv_client(false)?
+            .get_headers_height()
+            .map_err(burnchain_error::Bitcoin)
+    }
+
+    fn get_highest_header_height(&self) -> Result<u64, burnchain_error> {
+        self.open_spv_client(false)?
+            .get_highest_header_height()
+            .map_err(burnchain_error::Bitcoin)
+    }
+
+    /// Walk back from our highest header until our header hashes agree with the Esplora
+    /// server's, and drop everything above that point.  If the server is merely behind us (i.e.
+    /// its tip is one of our headers), then there is no reorg.
+    /// Returns the height of the highest header in common.
+    fn find_chain_reorg(&mut self) -> Result<u64, burnchain_error> {
+        let mut spv_client = self.open_spv_client(true)?;
+        let highest_header_height = spv_client.get_highest_header_height()?;
+        let remote_tip_height = self.client.get_tip_height().map_err(Self::map_btc_error)?;
+
+        let mut search_height = highest_header_height.min(remote_tip_height);
+        let mut common_height = 0;
+        'search: while search_height > 0 {
+            self.check_keep_running()?;
+
+            let infos = self
+                .client
+                .get_blocks(search_height)
+                .map_err(Self::map_btc_error)?;
+            let mut lowest_height = search_height;
+            for info in infos.iter() {
+                if info.height > search_height {
+                    continue;
+                }
+                lowest_height = lowest_height.min(info.height);
+                let remote_hash = Sha256dHash::from_hex(&info.id).map_err(btc_error::HashError)?;
+                if let Some(local_header) = spv_client.read_block_header(info.height)? {
+                    if local_header.header.bitcoin_hash() == remote_hash {
+                        common_height = info.height;
+                        break 'search;
+                    }
+                }
+            }
+            if infos.is_empty() {
+                return Err(burnchain_error::TrySyncAgain);
+            }
+            search_height = lowest_height.saturating_sub(1);
+        }
+
+        if common_height == remote_tip_height.min(highest_header_height)
+            && common_height < highest_header_height
+        {
+            // the server is behind us, but on our fork
+            debug!(
+                "Esplora server tip {} is behind our headers at {}",
+                remote_tip_height, highest_header_height
+            );
+            return Ok(highest_header_height);
+        }
+
+        if common_height < highest_header_height {
+            warn!(
+                "Esplora server reports a Bitcoin reorg: highest common ancestor at {}",
+                common_height
+            );
+            spv_client.drop_headers(common_height)?;
+        }
+        Ok(common_height)
+    }
+
+    /// Download and store all headers between two block heights.
+    /// end_height, if given, is inclusive.
+    /// Returns the height of the last header fetched
+    fn sync_headers(
+        &mut self,
+        start_height: u64,
+        end_height: Option<u64>,
+    ) -> Result<u64, burnchain_error> {
+        if end_height.is_some() && end_height <= Some(start_height) {
+            return Ok(end_height.unwrap());
+        }
+
+        let mut spv_client = self.open_spv_client(true)?;
+        let highest_header_height = spv_client.get_highest_header_height()?;
+        if let Some(end_height) = end_height {
+            if end_height <= highest_header_height {
+                debug!("SPV client has all headers up to {}", highest_header_height);
+                return Ok(highest_header_height);
+            }
+        }
+
+        let remote_tip_height = self.client.get_tip_height().map_err(Self::map_btc_error)?;
+        let target_height = end_height
+            .map(|h| h.min(remote_tip_height))
+            .unwrap_or(remote_tip_height);
+
+        // can only append to a header we have
+        let start_height = start_height.min(highest_header_height);
+        if target_height > start_height {
+            debug!(
+                "Sync headers {}-{} from Esplora server",
+                start_height, target_height
+            );
+            self.fetch_headers(&mut spv_client, start_height, target_height)?;
+        }
+
+        // make sure the headers are up-to-date if we have no target height
+        if end_height.is_none() {
+            self.check_chain_tip_timestamp()?;
+        }
+        Ok(spv_client.get_highest_header_height()?)
+    }
+
+    fn drop_headers(&mut self, new_height: u64) -> Result<(), burnchain_error> {
+        self.open_spv_client(true)?
+            .drop_headers(new_height)
+            .map_err(burnchain_error::Bitcoin)
+    }
+
+    fn read_headers(
+        &self,
+        start_block: u64,
+        end_block: u64,
+    ) -> Result<Vec<BitcoinHeaderIPC>, burnchain_error> {
+        let headers = self
+            .open_spv_client(false)?
+            .read_block_headers(start_block, end_block)?;
+        Ok(headers
+            .into_iter()
+            .enumerate()
+            .map(|(i, block_header)| BitcoinHeaderIPC {
+                block_header,
+                block_height: (i as u64) + start_block,
+            })
+            .collect())
+    }
+
+    fn downloader(&self) -> EsploraBlockDownloader {
+        EsploraBlockDownloader {
+            client: self.client.clone(),
+        }
+    }
+
+    fn parser(&self) -> EsploraBlockParser {
+        EsploraBlockParser {
+            parser: BitcoinBlockParser::new(self.config.network_id, self.config.magic_bytes),
+        }
+    }
+
+    fn reader(&self) -> EsploraIndexer {
+        EsploraIndexer {
+            config: self.config.clone(),
+            should_keep_running: self.should_keep_running.clone(),
+            client: self.client.clone(),
+        }
+    }
+}
+
+impl BurnchainHeaderReader for EsploraIndexer {
+    fn read_burnchain_headers(
+        &self,
+        start_height: u64,
+        end_height: u64,
+    ) -> Result<Vec<BurnchainBlockHeader>, DBError> {
+        let hdrs = self
+            .read_headers(start_height, end_height)
+            .map_err(|e| DBError::Other(format!("Burnchain error: {:?}", &e)))?;
+
+        Ok(hdrs
+            .into_iter()
+            .map(|hdr| BurnchainBlockHeader {
+                block_height: hdr.block_height,
+                block_hash: BurnchainHeaderHash::from_bitcoin_hash(&Sha256dHash(hdr.header_hash())),
+                parent_block_hash: BurnchainHeaderHash::from_bitcoin_hash(
+                    &hdr.block_header.header.prev_blockhash,
+                ),
+                num_txs: hdr.block_header.tx_count.0,
+                timestamp: hdr.block_header.header.time as u64,
+            })
+            .collect())
+    }
+
+    fn get_burnchain_headers_height(&self) -> Result<u64, DBError> {
+        self.get_headers_height()
+            .map_err(|e| DBError::Other(format!("Burnchain error: {:?}", &e)))
+    }
+
+    fn find_burnchain_header_height(
+        &self,
+        burn_header_hash: &BurnchainHeaderHash,
+    ) -> Result<Option<u64>, DBError> {
+        self.open_spv_client(false)
+            .and_then(|spv_client| spv_client.find_block_header_height(burn_header_hash))
+            .map_err(|e| DBError::Other(format!("Burnchain error: {:?}", &e)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::Mutex;
+    use std::{fs, thread};
+
+    use stacks_common::deps_common::bitcoin::blockdata::constants::genesis_block;
+    use stacks_common::deps_common::bitcoin::blockdata::script::Builder;
+    use stacks_common::deps_common::bitcoin::blockdata::transaction::{
+        OutPoint, Transaction, TxIn, TxOut,
+    };
+    use stacks_common::deps_common::bitcoin::network::constants::Network;
+    use stacks_common::deps_common::bitcoin::network::serialize::serialize;
+    use stacks_common::deps_common::bitcoin::util::hash::bitcoin_merkle_root;
+
+    use super::*;
+    use crate::burnchains::BLOCKSTACK_MAGIC_MAINNET;
+
+    /// Serves a regtest chain over the Esplora endpoints the indexer uses.  Index `i` of the
+    /// chain is the block at height `i`.
+    struct MockEsplora {
+        chain: Arc<Mutex<Vec<Block>>>,
+        port: u16,
+    }
+
+    impl MockEsplora {
+        fn start(chain: Vec<Block>) -> MockEsplora {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let chain = Arc::new(Mutex::new(chain));
+            let server_chain = chain.clone();
+            thread::spawn(move || {
+                for sock in listener.incoming() {
+                    let Ok(sock) = sock else {
+                        continue;
+                    };
+                    MockEsplora::serve(&server_chain, sock);
+                }
+            });
+            MockEsplora { chain, port }
+        }
+
+        fn serve(chain: &Mutex<Vec<Block>>, mut sock: TcpStream) {
+            let mut reader = BufReader::new(sock.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                    break;
+                }
+            }
+            let path = request_line.split_whitespace().nth(1).unwrap().to_string();
+            let path = path.strip_prefix("/api").unwrap();
+
+            let chain = chain.lock().unwrap();
+            let body: Option<Vec<u8>> = if path == "/blocks/tip/height" {
+                Some(format!("{}", chain.len() - 1).into_bytes())
+            } else if let Some(height) = path.strip_prefix("/blocks/") {
+                let height: usize = height.parse().unwrap();
+                let infos: Vec<_> = (0..=height.min(chain.len() - 1))
+                    .rev()
+                    .take(ESPLORA_BLOCKS_PAGE_SIZE as usize)
+                    .map(|h| MockEsplora::block_info(&chain[h], h as u64))
+                    .collect();
+                Some(serde_json::to_vec(&infos).unwrap())
+            } else if let Some(rest) = path.strip_prefix("/block/") {
+                let hash = rest.strip_suffix("/raw").unwrap();
+                chain
+                    .iter()
+                    .find(|blk| blk.bitcoin_hash().be_hex_string() == hash)
+                    .map(|blk| serialize(blk).unwrap())
+            } else {
+                None
+            };
+
+            // exercise both framings
+            let response = match body {
+                Some(body) if path.ends_with("/raw") => {
+                    let mut response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n",
+                        body.len()
+                    )
+                    .into_bytes();
+                    response.extend_from_slice(&body);
+                    response.extend_from_slice(b"\r\n0\r\n\r\n");
+                    response
+                }
+                Some(body) => {
+                    let mut response =
+                        format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len())
+                            .into_bytes();
+                    response.extend_from_slice(&body);
+                    response
+                }
+                None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec(),
+            };
+            sock.write_all(&response).unwrap();
+        }
+
+        fn block_info(block: &Block, height: u64) -> serde_json::Value {
+            let mut info = serde_json::json!({
+                "id": block.bitcoin_hash().be_hex_string(),
+                "height": height,
+                "version": block.header.version,
+                "timestamp": block.header.time,
+                "tx_count": block.txdata.len(),
+                "merkle_root": block.header.merkle_root.be_hex_string(),
+                "nonce": block.header.nonce,
+                "bits": block.header.bits,
+            });
+            if height > 0 {
+                info["previousblockhash"] =
+                    serde_json::json!(block.header.prev_blockhash.be_hex_string());
+            }
+            info
+        }
+
+        fn set_chain(&self, chain: Vec<Block>) {
+            *self.chain.lock().unwrap() = chain;
+        }
+    }
+
+    /// Make a regtest chain of `len` blocks after genesis, forking off of `base` (if given).
+    /// `salt` makes coinbases (and thus block hashes) differ between forks.
+    fn make_chain(base: &[Block], len: usize, salt: u8) -> Vec<Block> {
+        let mut chain = if base.is_empty() {
+            vec![genesis_block(Network::Regtest)]
+        } else {
+            base.to_vec()
+        };
+        let now = get_epoch_time_secs() as u32;
+        for _ in 0..len {
+            let height = chain.len();
+            let coinbase = Transaction {
+                version: 1,
+                lock_time: 0,
+                input: vec![TxIn {
+                    previous_output: OutPoint::null(),
+                    script_sig: Builder::new()
+                        .push_int(height as i64)
+                        .push_slice(&[salt])
+                        .into_script(),
+                    sequence: 0xffffffff,
+                    witness: vec![],
+                }],
+                output: vec![TxOut {
+                    value: 50_0000_0000,
+                    script_pubkey: Builder::new().push_slice(&[salt; 20]).into_script(),
+                }],
+            };
+            let header = BlockHeader {
+                version: 0x20000000,
+                prev_blockhash: chain[height - 1].bitcoin_hash(),
+                merkle_root: bitcoin_merkle_root(vec![coinbase.txid()]),
+                // keep the tip within 2 hours of now
+                time: now - 600 + height as u32,
+                bits: 0x207fffff,
+                nonce: 0,
+            };
+            chain.push(Block {
+                header,
+                txdata: vec![coinbase],
+            });
+        }
+        chain
+    }
+
+    fn make_indexer(test_name: &str, port: u16) -> EsploraIndexer {
+        let working_dir = format!("/tmp/stacks-esplora-tests/{}", test_name);
+        if fs::metadata(&working_dir).is_ok() {
+            fs::remove_dir_all(&working_dir).unwrap();
+        }
+        fs::create_dir_all(&working_dir).unwrap();
+
+        EsploraIndexer::new(
+            EsploraIndexerConfig {
+                url: format!("http://127.0.0.1:{}/api/", port),
+                timeout: 30,
+                spv_headers_path: format!("{}/headers.sqlite", &working_dir),
+                first_block: 0,
+                magic_bytes: BLOCKSTACK_MAGIC_MAINNET.clone(),
+                epochs: None,
+                network_id: BitcoinNetworkType::Regtest,
+            },
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_esplora_client_url() {
+        let client = EsploraClient::new("http://example.com/api/", 10).unwrap();
+        assert_eq!(client.host, "example.com");
+        assert_eq!(client.port, 80);
+        assert_eq!(client.path_prefix, "/api");
+
+        let client = EsploraClient::new("http://127.0.0.1:3002", 10).unwrap();
+        assert_eq!(client.port, 3002);
+        assert_eq!(client.path_prefix, "");
+
+        assert!(EsploraClient::new("https://blockstream.info/api", 10).is_err());
+        assert!(EsploraClient::new("not a url", 10).is_err());
+    }
+
+    #[test]
+    fn test_esplora_parse_response() {
+        let (status, body) =
+            EsploraClient::parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello")
+                .unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(body, b"hello");
+
+        let (status, body) = EsploraClient::parse_response(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nhel\r\n2;x=y\r\nlo\r\n0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(body, b"hello");
+
+        let (status, _) = EsploraClient::parse_response(b"HTTP/1.0 404 Not Found\r\n\r\n").unwrap();
+        assert_eq!(status, 404);
+
+        // truncated
+        assert!(EsploraClient::parse_response(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello"
+        )
+        .is_err());
+        assert!(EsploraClient::parse_response(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n10\r\nhello"
+        )
+        .is_err());
+        assert!(EsploraClient::parse_response(b"garbage").is_err());
+    }
+
+    #[test]
+    fn test_esplora_sync_headers_and_blocks() {
+        let chain = make_chain(&[], 25, 0);
+        let server = MockEsplora::start(chain.clone());
+        let mut indexer = make_indexer(function_name!(), server.port);
+
+        indexer.connect().unwrap();
+
+        // bounded sync
+        assert_eq!(indexer.sync_headers(0, Some(7)).unwrap(), 7);
+        assert_eq!(indexer.get_highest_header_height().unwrap(), 7);
+
+        // sync to the tip
+        assert_eq!(indexer.sync_headers(7, None).unwrap(), 25);
+        assert_eq!(indexer.get_highest_header_height().unwrap(), 25);
+        assert_eq!(indexer.find_chain_reorg().unwrap(), 25);
+
+        let headers = indexer.read_headers(1, 26).unwrap();
+        assert_eq!(headers.len(), 25);
+        for hdr in headers.iter() {
+            assert_eq!(
+                hdr.block_header.header,
+                chain[hdr.block_height as usize].header
+            );
+        }
+
+        // blocks come back verified and parseable
+        let mut downloader = indexer.downloader();
+        let mut parser = indexer.parser();
+        for hdr in headers.iter() {
+            let ipc_block = downloader.download(hdr).unwrap();
+            let block = parser.parse(&ipc_block, StacksEpochId::Epoch21).unwrap();
+            assert_eq!(block.block_height(), hdr.block_height);
+            assert_eq!(
+                block.block_hash(),
+                BurnchainHeaderHash::from_bitcoin_hash(
+                    &chain[hdr.block_height as usize].bitcoin_hash()
+                )
+            );
+        }
+
+        // a header the server doesn't have can't be downloaded
+        let mut bogus = headers[3].clone();
+        bogus.block_header.header.nonce += 1;
+        assert!(downloader.download(&bogus).is_err());
+    }
+
+    #[test]
+    fn test_esplora_reorg() {
+        let chain = make_chain(&[], 30, 0);
+        let server = MockEsplora::start(chain.clone());
+        let mut indexer = make_indexer(function_name!(), server.port);
+
+        assert_eq!(indexer.sync_headers(0, None).unwrap(), 30);
+
+        // server falls behind on the same fork -- not a reorg
+        server.set_chain(chain[..=20].to_vec());
+        assert_eq!(indexer.find_chain_reorg().unwrap(), 30);
+        assert_eq!(indexer.get_highest_header_height().unwrap(), 30);
+
+        // server switches to a longer fork that branches off at 12
+        let fork = make_chain(&chain[..=12], 23, 1);
+        server.set_chain(fork.clone());
+        assert_eq!(indexer.find_chain_reorg().unwrap(), 12);
+        assert_eq!(indexer.get_highest_header_height().unwrap(), 12);
+
+        assert_eq!(indexer.sync_headers(12, None).unwrap(), 35);
+        let headers = indexer.read_headers(1, 36).unwrap();
+        assert_eq!(headers.len(), 35);
+        for hdr in headers.iter() {
+            assert_eq!(
+                hdr.block_header.header,
+                fork[hdr.block_height as usize].header
+            );
+        }
+        assert_eq!(indexer.find_chain_reorg().unwrap(), 35);
+    }
+}
@@ -15,7 +15,7 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::convert::TryFrom;
-use std::net::Shutdown;
+use std::net::{Shutdown, SocketAddr};
 use std::ops::{Deref, DerefMut};
 use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
@@ -32,6 +32,7 @@ use stacks_common::deps_common::bitcoin::network::serialize::{
 };
 use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
 use stacks_common::types::chainstate::BurnchainHeaderHash;
+use stacks_common::types::net::PeerHost;
 use stacks_common::util::{get_epoch_time_secs, log};
 
 use crate::burnchains::bitcoin::blocks::{
@@ -48,6 +49,7 @@ use crate::burnchains::{
 use crate::core::{
     StacksEpoch, STACKS_EPOCHS_MAINNET, STACKS_EPOCHS_REGTEST, STACKS_EPOCHS_TESTNET,
 };
+use crate::net::socks5;
 use crate::util_lib::db::Error as DBError;
 
 pub const USER_AGENT: &'static str = "Stacks/2.1";
@@ -89,9 +91,9 @@ impl TryFrom<u32> for BitcoinNetworkType {
 }
 
 /// Get the default epochs definitions for the given BitcoinNetworkType.
-/// Should *not* be used except by the BitcoinIndexer when no epochs vector
+/// Should *not* be used except by the BitcoinIndexer (or EsploraIndexer) when no epochs vector
 /// was specified.
-fn get_bitcoin_stacks_epochs(network_id: BitcoinNetworkType) -> Vec<StacksEpoch> {
+pub(crate) fn get_bitcoin_stacks_epochs(network_id: BitcoinNetworkType) -> Vec<StacksEpoch> {
     match network_id {
         BitcoinNetworkType::Mainnet => STACKS_EPOCHS_MAINNET.to_vec(),
         BitcoinNetworkType::Testnet => STACKS_EPOCHS_TESTNET.to_vec(),
@@ -113,6 +115,8 @@ pub struct BitcoinIndexerConfig {
     pub first_block: u64,
     pub magic_bytes: MagicBytes,
     pub epochs: Option<Vec<StacksEpoch>>,
+    /// SOCKS5 proxy to connect to the peer through, if any
+    pub socks5_proxy: Option<SocketAddr>,
 }
 
 #[derive(Debug)]
@@ -148,6 +152,7 @@ impl BitcoinIndexerConfig {
             first_block,
             magic_bytes: BLOCKSTACK_MAGIC_MAINNET.clone(),
             epochs: None,
+            socks5_proxy: None,
         }
     }
 
@@ -164,6 +169,7 @@ impl BitcoinIndexerConfig {
             first_block: 0,
             magic_bytes: BLOCKSTACK_MAGIC_MAINNET.clone(),
             epochs: None,
+            socks5_proxy: None,
         }
     }
 
@@ -181,6 +187,7 @@ impl BitcoinIndexerConfig {
             first_block: 0,
             magic_bytes: BLOCKSTACK_MAGIC_MAINNET.clone(),
             epochs: None,
+            socks5_proxy: None,
         }
     }
 }
@@ -261,7 +268,11 @@ impl BitcoinIndexer {
     /// Bitcoin peer.  If we fail to connect, this method sets the socket
     /// to None.
     fn reconnect_peer(&mut self) -> Result<(), btc_error> {
-        match net::TcpStream::connect((self.config.peer_host.as_str(), self.config.peer_port)) {
+        match socks5::connect_tcp(
+            self.config.socks5_proxy.as_ref(),
+            &PeerHost::from_host_port(self.config.peer_host.clone(), self.config.peer_port),
+            Duration::from_secs(self.runtime.timeout),
+        ) {
             Ok(s) => {
                 // Disable Nagle algorithm
                 s.set_nodelay(true).map_err(|_e| {
@@ -1608,6 +1619,7 @@ mod test {
             first_block: 0,
             magic_bytes: MagicBytes([105, 100]),
             epochs: None,
+            socks5_proxy: None,
         };
 
         if fs::metadata(&indexer_conf.spv_headers_path).is_ok() {
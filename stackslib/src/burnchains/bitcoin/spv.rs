@@ -812,7 +812,7 @@ impl SpvClient {
     /// -- store them
     /// Can error if there has been a reorg, or if the headers don't correspond to headers we asked
     /// for, or if the new chain has less total work than the old chain.
-    fn handle_headers(
+    pub(crate) fn handle_headers(
         &mut self,
         insert_height: u64,
         block_headers: Vec<LoneBlockHeader>,
@@ -213,6 +213,10 @@ pub trait ConsensusHashExtensions {
         pox_id: &PoxId,
     ) -> ConsensusHash;
 
+    /// Get the heights of the previous consensus hashes that must be hashed to find the *next*
+    /// consensus hash at a particular block, from most-recent to least-recent.
+    fn get_prev_consensus_hash_heights(block_height: u64, first_block_height: u64) -> Vec<u64>;
+
     /// Get the previous consensus hashes that must be hashed to find
     /// the *next* consensus hash at a particular block.
     fn get_prev_consensus_hashes(
@@ -298,6 +302,26 @@ impl ConsensusHashExtensions for ConsensusHash {
         ConsensusHash(ch_bytes)
     }
 
+    /// Get the heights of the previous consensus hashes that must be hashed to find the *next*
+    /// consensus hash at a particular block, from most-recent to least-recent.
+    fn get_prev_consensus_hash_heights(block_height: u64, first_block_height: u64) -> Vec<u64> {
+        let mut i = 0;
+        let mut heights = vec![];
+        while i < 64 && block_height - (((1 as u64) << i) - 1) >= first_block_height {
+            heights.push(block_height - (((1 as u64) << i) - 1));
+            i += 1;
+
+            if block_height < (((1 as u64) << i) - 1) {
+                break;
+            }
+        }
+        if i == 64 {
+            // won't happen for a long, long time
+            panic!("FATAL ERROR: numeric overflow when calculating a consensus hash for {} from genesis block height {}", block_height, first_block_height);
+        }
+        heights
+    }
+
     /// Get the previous consensus hashes that must be hashed to find
     /// the *next* consensus hash at a particular block.
     fn get_prev_consensus_hashes(
@@ -305,10 +329,10 @@ impl ConsensusHashExtensions for ConsensusHash {
         block_height: u64,
         first_block_height: u64,
     ) -> Result<Vec<ConsensusHash>, db_error> {
-        let mut i = 0;
         let mut prev_chs = vec![];
-        while i < 64 && block_height - (((1 as u64) << i) - 1) >= first_block_height {
-            let prev_block: u64 = block_height - (((1 as u64) << i) - 1);
+        for prev_block in
+            ConsensusHash::get_prev_consensus_hash_heights(block_height, first_block_height)
+        {
             let prev_ch = sort_tx
                 .get_consensus_at(prev_block)
                 .expect(&format!(
@@ -319,17 +343,7 @@ impl ConsensusHashExtensions for ConsensusHash {
 
             debug!("Consensus at {}: {}", prev_block, &prev_ch);
             prev_chs.push(prev_ch.clone());
-            i += 1;
-
-            if block_height < (((1 as u64) << i) - 1) {
-                break;
-            }
         }
-        if i == 64 {
-            // won't happen for a long, long time
-            panic!("FATAL ERROR: numeric overflow when calculating a consensus hash for {} from genesis block height {}", block_height, first_block_height);
-        }
-
         Ok(prev_chs)
     }
 
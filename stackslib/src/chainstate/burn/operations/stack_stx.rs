@@ -146,7 +146,6 @@ impl PreStxOp {
 }
 
 impl StackStxOp {
-    #[cfg(test)]
     pub fn new(
         sender: &StacksAddress,
         reward_addr: &PoxAddress,
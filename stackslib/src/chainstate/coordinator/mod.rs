@@ -171,6 +171,11 @@ pub struct ChainsCoordinatorConfig {
     /// true: always wait for canonical anchor blocks, even if it stalls the chain
     /// false: proceed to process new chain history even if we're missing an anchor block.
     pub require_affirmed_anchor_blocks: bool,
+    /// If set, prune the Clarity state and block data of all but this many of the most recent
+    /// reward cycles whenever a new reward cycle starts
+    pub prune_reward_cycles: Option<u64>,
+    /// Deepest Stacks reorg that pruning must leave the node able to process
+    pub prune_max_reorg_depth: u64,
 }
 
 impl ChainsCoordinatorConfig {
@@ -178,6 +183,8 @@ impl ChainsCoordinatorConfig {
         ChainsCoordinatorConfig {
             always_use_affirmation_maps: false,
             require_affirmed_anchor_blocks: true,
+            prune_reward_cycles: None,
+            prune_max_reorg_depth: 0,
         }
     }
 }
@@ -205,6 +212,8 @@ pub struct ChainsCoordinator<
     atlas_config: AtlasConfig,
     config: ChainsCoordinatorConfig,
     burnchain_indexer: B,
+    /// Reward cycle in which the chainstate was last pruned
+    last_pruned_reward_cycle: Option<u64>,
 }
 
 #[derive(Debug)]
@@ -388,6 +397,7 @@ impl<
             atlas_db: Some(atlas_db),
             config,
             burnchain_indexer,
+            last_pruned_reward_cycle: None,
         };
 
         loop {
@@ -406,6 +416,7 @@ impl<
                         }
                     }
                     Err(e) => {
+                        halt_on_pruned_state_error(&e);
                         warn!("Error processing new stacks block: {:?}", e);
                     }
                 }
@@ -425,9 +436,13 @@ impl<
                         }
                     }
                     Err(e) => {
+                        halt_on_pruned_state_error(&e);
                         warn!("Error processing new burn block: {:?}", e);
                     }
                 }
+                if let Err(e) = inst.prune_chainstate_at_reward_cycle_start() {
+                    warn!("Error pruning chainstate: {:?}", e);
+                }
                 signal_mining_ready(miner_status.clone());
             }
             if (bits & (CoordinatorEvents::STOP as u8)) != 0 {
@@ -519,6 +534,7 @@ impl<'a, T: BlockEventDispatcher, U: RewardSetProvider, B: BurnchainHeaderReader
             atlas_db: Some(atlas_db),
             config: ChainsCoordinatorConfig::new(),
             burnchain_indexer,
+            last_pruned_reward_cycle: None,
         }
     }
 }
@@ -709,6 +725,21 @@ fn dispatcher_announce_burn_ops<T: BlockEventDispatcher>(
     );
 }
 
+/// A pruned node cannot process a block that reads state it has pruned, nor any of that block's
+/// descendants, so it would silently stop following the chain.  Halt instead.
+fn halt_on_pruned_state_error(e: &Error) {
+    if let Error::ChainstateError(ChainstateError::PrunedStateError(msg)) = e {
+        error!(
+            "This node has pruned the state that a new Stacks block reads, and can no longer follow the chain. Re-sync it without `node.prune_reward_cycles`, or import a snapshot of an unpruned node with `stacks-node start --import-snapshot`.";
+            "error" => %msg
+        );
+        panic!(
+            "FATAL: cannot process a block that reads pruned state: {}",
+            msg
+        );
+    }
+}
+
 /// Forget that all Stacks blocks that were mined on descendants of `burn_header` are orphaned.
 /// They may be valid again, after a PoX reorg.
 fn forget_orphan_stacks_blocks(
@@ -872,6 +903,36 @@ impl<
         B: BurnchainHeaderReader,
     > ChainsCoordinator<'a, T, N, U, CE, FE, B>
 {
+    /// If pruning is enabled, and the canonical burnchain tip has entered a reward cycle in which
+    /// the chainstate has not been pruned yet, then prune the state that has fallen out of the
+    /// window of kept reward cycles.  Other threads have the Clarity MARF open, so its blobs file
+    /// is not compacted here; that happens every time the node boots.
+    pub fn prune_chainstate_at_reward_cycle_start(&mut self) -> Result<(), Error> {
+        let Some(prune_reward_cycles) = self.config.prune_reward_cycles else {
+            return Ok(());
+        };
+        let burn_tip = SortitionDB::get_canonical_burn_chain_tip(self.sortition_db.conn())?;
+        let Some(reward_cycle) = self
+            .burnchain
+            .block_height_to_reward_cycle(burn_tip.block_height)
+        else {
+            return Ok(());
+        };
+        if self.last_pruned_reward_cycle == Some(reward_cycle) {
+            return Ok(());
+        }
+
+        self.chain_state_db.prune_historical_state(
+            &self.sortition_db,
+            &self.burnchain,
+            prune_reward_cycles,
+            self.config.prune_max_reorg_depth,
+            false,
+        )?;
+        self.last_pruned_reward_cycle = Some(reward_cycle);
+        Ok(())
+    }
+
     /// Process new Stacks blocks.  If we get stuck for want of a missing PoX anchor block, return
     /// its hash.
     pub fn handle_new_stacks_block(&mut self) -> Result<Option<BlockHeaderHash>, Error> {
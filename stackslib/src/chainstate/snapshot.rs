@@ -0,0 +1,1282 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Chainstate snapshots, for bootstrapping a node without syncing from genesis.
+//!
+//! A snapshot is a directory with the same layout as a node's network directory
+//! (`$working_dir/$mode`): the burnchain DB, the sortition DB, the SPV headers DB, the headers
+//! index and Clarity MARFs (with external trie blobs), and the block files, plus a
+//! `manifest.json`.  The manifest pins the snapshot to the first sortition of a reward cycle,
+//! records the MARF root hashes at that point, and lists every file with its SHA-256 hash.
+//!
+//! A snapshot can be exported from a running node.  Each database is copied in a single read
+//! transaction, and the copy is then trimmed to the boundary: the sortitions and burnchain blocks
+//! up to the boundary's burn block, and the Stacks tip as of that sortition with its ancestors.
+//! Nothing at or below the boundary changes once the burnchain has moved past it, so the trimmed
+//! copies agree with each other no matter when each one was made.  The trie blobs of the kept
+//! tries, and the kept blocks, are then written out anew.
+//!
+//! Importing a snapshot checks every file against the manifest, copies the files into place, and
+//! then checks the chain state: the sortition MARF root must match the manifest, and every Stacks
+//! block from the manifest's Stacks tip back to genesis must hash to its index block hash, be the
+//! parent its child names, and have a headers index root and a Clarity state root that match its
+//! `StacksHeaderInfo`.  If the importer also has a trusted index block hash for the tip, obtained
+//! from somewhere other than the snapshot, this authenticates the Stacks chain and its Clarity
+//! state.
+
+use std::collections::HashSet;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::{error, fmt, fs, io};
+
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, Error as sqlite_error, OpenFlags};
+use sha2::{Digest, Sha256};
+use stacks_common::types::chainstate::{
+    BurnchainHeaderHash, ConsensusHash, SortitionId, StacksBlockId, TrieHash,
+};
+use stacks_common::util::hash::Sha256Sum;
+
+use crate::burnchains::PoxConstants;
+use crate::chainstate::burn::db::sortdb::SortitionDB;
+use crate::chainstate::stacks::db::blockstore::StacksBlockStore;
+use crate::chainstate::stacks::db::{DBConfig, StacksChainState};
+use crate::chainstate::stacks::index::file::TrieFile;
+use crate::chainstate::stacks::index::marf::MARFOpenOpts;
+use crate::chainstate::stacks::index::{trie_sql, Error as marf_error};
+use crate::chainstate::stacks::Error as chainstate_error;
+use crate::util_lib::db::{
+    sql_pragma, sqlite_open, table_exists, tx_begin_immediate, u64_to_sql, Error as db_error,
+};
+
+/// Version of the snapshot format
+pub const SNAPSHOT_VERSION: u32 = 2;
+/// Name of the manifest file in a snapshot directory
+pub const SNAPSHOT_MANIFEST: &str = "manifest.json";
+
+#[derive(Debug)]
+pub enum Error {
+    /// Filesystem error
+    IOError(io::Error),
+    /// Database error
+    DBError(db_error),
+    /// Chainstate error
+    ChainstateError(chainstate_error),
+    /// MARF error
+    MARFError(marf_error),
+    /// Manifest (de)serialization error
+    SerdeError(serde_json::Error),
+    /// The snapshot cannot be created, or does not match the chain it claims to be
+    InvalidSnapshot(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::IOError(ref e) => fmt::Display::fmt(e, f),
+            Error::DBError(ref e) => fmt::Display::fmt(e, f),
+            Error::ChainstateError(ref e) => fmt::Display::fmt(e, f),
+            Error::MARFError(ref e) => fmt::Display::fmt(e, f),
+            Error::SerdeError(ref e) => fmt::Display::fmt(e, f),
+            Error::InvalidSnapshot(ref msg) => write!(f, "Invalid snapshot: {}", msg),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&dyn error::Error> {
+        match self {
+            Error::IOError(ref e) => Some(e),
+            Error::DBError(ref e) => Some(e),
+            Error::ChainstateError(ref e) => Some(e),
+            Error::MARFError(ref e) => Some(e),
+            Error::SerdeError(ref e) => Some(e),
+            Error::InvalidSnapshot(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::IOError(e)
+    }
+}
+
+impl From<db_error> for Error {
+    fn from(e: db_error) -> Error {
+        Error::DBError(e)
+    }
+}
+
+impl From<sqlite_error> for Error {
+    fn from(e: sqlite_error) -> Error {
+        Error::DBError(db_error::SqliteError(e))
+    }
+}
+
+impl From<chainstate_error> for Error {
+    fn from(e: chainstate_error) -> Error {
+        Error::ChainstateError(e)
+    }
+}
+
+impl From<marf_error> for Error {
+    fn from(e: marf_error) -> Error {
+        Error::MARFError(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::SerdeError(e)
+    }
+}
+
+/// A file in a snapshot, with its size and hash
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotFile {
+    /// Path relative to the snapshot directory
+    pub path: String,
+    pub size: u64,
+    pub sha256: Sha256Sum,
+}
+
+/// Describes a snapshot, and what it must be consistent with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub version: u32,
+    pub mainnet: bool,
+    pub chain_id: u32,
+    /// The snapshot is pinned to the first sortition of this reward cycle
+    pub reward_cycle: u64,
+    pub burn_block_height: u64,
+    pub consensus_hash: ConsensusHash,
+    /// Root hash of the sortition MARF at that sortition
+    pub sortition_root: TrieHash,
+    /// Canonical Stacks tip as of that sortition
+    pub stacks_tip: StacksBlockId,
+    pub stacks_block_height: u64,
+    /// Root hash of the headers index MARF at the Stacks tip
+    pub headers_root: TrieHash,
+    /// Root hash of the Clarity MARF at the Stacks tip
+    pub clarity_root: TrieHash,
+    /// Every file in the snapshot but the manifest
+    pub files: Vec<SnapshotFile>,
+}
+
+impl SnapshotManifest {
+    pub fn load(snapshot_dir: &str) -> Result<SnapshotManifest, Error> {
+        let path = Path::new(snapshot_dir).join(SNAPSHOT_MANIFEST);
+        let manifest = serde_json::from_reader(io::BufReader::new(fs::File::open(&path)?))?;
+        Ok(manifest)
+    }
+
+    pub fn store(&self, snapshot_dir: &str) -> Result<(), Error> {
+        let path = Path::new(snapshot_dir).join(SNAPSHOT_MANIFEST);
+        let mut fd = fs::File::create(&path)?;
+        serde_json::to_writer_pretty(&mut fd, self)?;
+        fd.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Where a node keeps the databases that go into a snapshot
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotPaths {
+    /// Chainstate directory (headers index, Clarity MARF, and blocks)
+    pub chainstate: String,
+    /// Burnchain directory (sortition DB and burnchain DB)
+    pub burnchain: String,
+    /// SPV headers DB
+    pub spv_headers: Option<String>,
+}
+
+impl SnapshotPaths {
+    /// The layout of a node's network directory (`$working_dir/$mode`), which is also the layout
+    /// of a snapshot directory.
+    pub fn from_network_dir(network_dir: &str) -> SnapshotPaths {
+        let dir = Path::new(network_dir);
+        SnapshotPaths {
+            chainstate: dir.join("chainstate").to_string_lossy().to_string(),
+            burnchain: dir.join("burnchain").to_string_lossy().to_string(),
+            spv_headers: Some(dir.join("headers.sqlite").to_string_lossy().to_string()),
+        }
+    }
+
+    /// Path to the sortition DB
+    pub fn sortdb_path(&self) -> String {
+        Path::new(&self.burnchain)
+            .join("sortition")
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// Load the chainstate's DB config, to find out which network it is for
+    pub fn load_db_config(&self) -> Result<DBConfig, Error> {
+        let path = SnapshotItem::HeadersIndex.path(self).unwrap_or_default();
+        let conn = sqlite_open(&path, OpenFlags::SQLITE_OPEN_READ_ONLY, false)?;
+        Ok(StacksChainState::load_db_config(&conn)?)
+    }
+}
+
+/// The parts of a node's state that make up a snapshot
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SnapshotItem {
+    BurnchainDB,
+    SpvHeaders,
+    SortitionDB,
+    HeadersIndex,
+    ClarityState,
+    Blocks,
+}
+
+impl SnapshotItem {
+    const ALL: [SnapshotItem; 6] = [
+        SnapshotItem::BurnchainDB,
+        SnapshotItem::SpvHeaders,
+        SnapshotItem::SortitionDB,
+        SnapshotItem::HeadersIndex,
+        SnapshotItem::ClarityState,
+        SnapshotItem::Blocks,
+    ];
+
+    fn path(&self, paths: &SnapshotPaths) -> Option<PathBuf> {
+        let chainstate = PathBuf::from(&paths.chainstate);
+        match self {
+            SnapshotItem::BurnchainDB => Some(Path::new(&paths.burnchain).join("burnchain.sqlite")),
+            SnapshotItem::SpvHeaders => paths.spv_headers.as_ref().map(PathBuf::from),
+            SnapshotItem::SortitionDB => Some(Path::new(&paths.sortdb_path()).join("marf.sqlite")),
+            SnapshotItem::HeadersIndex => {
+                Some(StacksChainState::header_index_root_path(chainstate))
+            }
+            SnapshotItem::ClarityState => {
+                Some(StacksChainState::vm_state_index_marf_path(chainstate))
+            }
+            SnapshotItem::Blocks => Some(StacksChainState::blocks_path(chainstate)),
+        }
+    }
+
+    /// Does the node keep this item's tries in an external blob file?
+    fn has_external_blobs(&self) -> bool {
+        match self {
+            SnapshotItem::HeadersIndex | SnapshotItem::ClarityState => true,
+            _ => false,
+        }
+    }
+
+    fn is_optional(&self) -> bool {
+        *self == SnapshotItem::SpvHeaders
+    }
+
+    fn is_db(&self) -> bool {
+        *self != SnapshotItem::Blocks
+    }
+}
+
+fn blobs_path(db_path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.blobs", db_path.display()))
+}
+
+fn mkdirs_for(path: &Path) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+/// Copy a live sqlite DB in a single read transaction
+fn vacuum_into(src: &Path, dest: &Path) -> Result<(), Error> {
+    mkdirs_for(dest)?;
+    let conn = sqlite_open(src, OpenFlags::SQLITE_OPEN_READ_ONLY, false)?;
+    conn.execute(
+        "VACUUM INTO ?1",
+        &[&dest.to_string_lossy().to_string() as &dyn ToSql],
+    )?;
+    Ok(())
+}
+
+fn copy_dir_all(src: &Path, dest: &Path) -> Result<(), Error> {
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let dest_path = dest.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_all(&entry.path(), &dest_path)?;
+        } else {
+            fs::copy(entry.path(), &dest_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Find all the files under `dir`
+fn list_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            list_files(&entry.path(), files)?;
+        } else {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+fn hash_file(path: &Path) -> Result<Sha256Sum, Error> {
+    let mut fd = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut fd, &mut hasher)?;
+    Ok(Sha256Sum(hasher.finalize().into()))
+}
+
+/// What a snapshot keeps of the node's state
+struct SnapshotBoundary {
+    /// Sortitions and burnchain blocks up to this height are kept
+    burn_block_height: u64,
+    /// The burnchain block and sortition at that height
+    burn_header_hash: BurnchainHeaderHash,
+    sortition_id: SortitionId,
+    /// The Stacks tip as of that sortition, and its ancestors down to the boot block
+    block_ids: Vec<StacksBlockId>,
+}
+
+/// Get `tip` and its ancestors, from `tip` down to the boot block
+fn get_ancestor_block_ids(
+    conn: &Connection,
+    tip: &StacksBlockId,
+) -> Result<Vec<StacksBlockId>, Error> {
+    let mut block_ids = vec![];
+    let mut cur_block_id = tip.clone();
+    loop {
+        let header = StacksChainState::get_stacks_block_header_info_by_index_block_hash(
+            conn,
+            &cur_block_id,
+        )?
+        .ok_or_else(|| Error::InvalidSnapshot(format!("No header for {}", &cur_block_id)))?;
+        block_ids.push(cur_block_id.clone());
+        if header.stacks_block_height == 0 {
+            break;
+        }
+        cur_block_id = StacksChainState::get_parent_block_id(conn, &cur_block_id)?
+            .ok_or_else(|| Error::InvalidSnapshot(format!("No parent of {}", &cur_block_id)))?;
+    }
+    Ok(block_ids)
+}
+
+/// Make a temporary table of the Stacks blocks a snapshot keeps, for trimming a DB to them
+fn make_snapshot_blocks_table(conn: &Connection, block_ids: &[StacksBlockId]) -> Result<(), Error> {
+    conn.execute_batch("CREATE TEMP TABLE snapshot_blocks(index_block_hash TEXT PRIMARY KEY);")?;
+    let mut stmt = conn.prepare("INSERT INTO snapshot_blocks (index_block_hash) VALUES (?1)")?;
+    for block_id in block_ids.iter() {
+        let args: &[&dyn ToSql] = &[block_id];
+        stmt.execute(args)?;
+    }
+    Ok(())
+}
+
+/// Drop what a miner keeps on the side of a MARF
+fn trim_marf_side_tables(conn: &Connection) -> Result<(), Error> {
+    for table in ["mined_blocks", "block_extension_locks"].iter() {
+        if table_exists(conn, table)? {
+            conn.execute_batch(&format!("DELETE FROM {};", table))?;
+        }
+    }
+    Ok(())
+}
+
+/// Trim a copy of the burnchain DB to the burnchain blocks up to the boundary
+fn trim_burnchain_db(conn: &Connection, boundary: &SnapshotBoundary) -> Result<(), Error> {
+    let args: &[&dyn ToSql] = &[
+        &u64_to_sql(boundary.burn_block_height)?,
+        &boundary.burn_header_hash,
+    ];
+    conn.execute(
+        "DELETE FROM burnchain_db_block_headers WHERE block_height > ?1 OR (block_height = ?1 AND block_hash != ?2)",
+        args,
+    )?;
+    conn.execute_batch(
+        "DELETE FROM burnchain_db_block_ops WHERE block_hash NOT IN (SELECT block_hash FROM burnchain_db_block_headers);
+         DELETE FROM block_commit_metadata WHERE burn_block_hash NOT IN (SELECT block_hash FROM burnchain_db_block_headers);
+         DELETE FROM anchor_blocks WHERE reward_cycle NOT IN (SELECT anchor_block FROM block_commit_metadata WHERE anchor_block IS NOT NULL);",
+    )?;
+    Ok(())
+}
+
+/// Trim a copy of the SPV headers DB to the headers up to the boundary.  Like the SPV client
+/// does on a reorg, this keeps the chain work totals.
+fn trim_spv_headers(conn: &Connection, boundary: &SnapshotBoundary) -> Result<(), Error> {
+    let args: &[&dyn ToSql] = &[&u64_to_sql(boundary.burn_block_height)?];
+    conn.execute("DELETE FROM headers WHERE height > ?1", args)?;
+    Ok(())
+}
+
+/// Trim a copy of the sortition DB to the sortitions up to the boundary.  At the boundary's
+/// height, only its own sortition is kept, so that it is the canonical burnchain tip.
+fn trim_sortition_db(conn: &Connection, boundary: &SnapshotBoundary) -> Result<(), Error> {
+    let height = u64_to_sql(boundary.burn_block_height)?;
+    conn.execute_batch("CREATE TEMP TABLE trimmed_sortitions(sortition_id TEXT PRIMARY KEY);")?;
+    let args: &[&dyn ToSql] = &[&height, &boundary.sortition_id];
+    conn.execute(
+        "INSERT INTO trimmed_sortitions SELECT sortition_id FROM snapshots WHERE block_height > ?1 OR (block_height = ?1 AND sortition_id != ?2)",
+        args,
+    )?;
+
+    let by_sortition = [
+        ("snapshots", "sortition_id"),
+        ("snapshot_transition_ops", "sortition_id"),
+        ("snapshot_burn_distributions", "sortition_id"),
+        ("leader_keys", "sortition_id"),
+        ("block_commits", "sortition_id"),
+        ("block_commit_parents", "block_commit_sortition_id"),
+        ("user_burn_support", "sortition_id"),
+        ("missed_commits", "intended_sortition_id"),
+        ("marf_data", "block_hash"),
+    ];
+    for (table, column) in by_sortition.iter() {
+        if table_exists(conn, table)? {
+            conn.execute_batch(&format!(
+                "DELETE FROM {} WHERE {} IN (SELECT sortition_id FROM trimmed_sortitions);",
+                table, column
+            ))?;
+        }
+    }
+
+    let args: &[&dyn ToSql] = &[&height, &boundary.burn_header_hash];
+    for table in ["stack_stx", "transfer_stx", "delegate_stx"].iter() {
+        conn.execute(
+            &format!(
+                "DELETE FROM {} WHERE block_height > ?1 OR (block_height = ?1 AND burn_header_hash != ?2)",
+                table
+            ),
+            args,
+        )?;
+    }
+    trim_marf_side_tables(conn)
+}
+
+/// Trim a copy of the chainstate DB, which also holds the headers index MARF, to the Stacks
+/// blocks the snapshot keeps
+fn trim_headers_index(conn: &Connection, boundary: &SnapshotBoundary) -> Result<(), Error> {
+    make_snapshot_blocks_table(conn, &boundary.block_ids)?;
+    conn.execute_batch(
+        "DELETE FROM block_headers WHERE index_block_hash NOT IN (SELECT index_block_hash FROM snapshot_blocks);
+         DELETE FROM payments WHERE index_block_hash NOT IN (SELECT index_block_hash FROM snapshot_blocks);
+         DELETE FROM transactions WHERE index_block_hash NOT IN (SELECT index_block_hash FROM snapshot_blocks);
+         DELETE FROM burnchain_txids WHERE index_block_hash NOT IN (SELECT index_block_hash FROM snapshot_blocks);
+         DELETE FROM epoch_transitions WHERE block_id NOT IN (SELECT index_block_hash FROM snapshot_blocks);
+         DELETE FROM matured_rewards WHERE child_index_block_hash NOT IN (SELECT index_block_hash FROM snapshot_blocks);
+         DELETE FROM user_supporters WHERE (consensus_hash, block_hash) NOT IN (SELECT consensus_hash, block_hash FROM block_headers);
+         DELETE FROM staging_blocks WHERE index_block_hash NOT IN (SELECT index_block_hash FROM snapshot_blocks);
+         DELETE FROM staging_user_burn_support WHERE (consensus_hash, anchored_block_hash) NOT IN (SELECT consensus_hash, block_hash FROM block_headers);
+         DELETE FROM staging_microblocks WHERE index_block_hash NOT IN (SELECT index_block_hash FROM snapshot_blocks);
+         DELETE FROM staging_microblocks_data WHERE block_hash NOT IN (SELECT microblock_hash FROM staging_microblocks);
+         DELETE FROM invalidated_microblocks_data;
+         DELETE FROM marf_data WHERE unconfirmed != 0 OR block_hash NOT IN (SELECT index_block_hash FROM snapshot_blocks);",
+    )?;
+    trim_marf_side_tables(conn)
+}
+
+/// Trim a copy of the Clarity MARF and its side store to the Stacks blocks the snapshot keeps
+fn trim_clarity_state(conn: &Connection, boundary: &SnapshotBoundary) -> Result<(), Error> {
+    make_snapshot_blocks_table(conn, &boundary.block_ids)?;
+    conn.execute_batch(
+        "DELETE FROM marf_data WHERE unconfirmed != 0 OR block_hash NOT IN (SELECT index_block_hash FROM snapshot_blocks);
+         DELETE FROM metadata_table WHERE blockhash NOT IN (SELECT index_block_hash FROM snapshot_blocks);",
+    )?;
+    if table_exists(conn, "data_map_keys")? {
+        conn.execute_batch(
+            "DELETE FROM data_map_keys WHERE index_block_hash NOT IN (SELECT index_block_hash FROM snapshot_blocks);",
+        )?;
+    }
+    trim_marf_side_tables(conn)
+}
+
+/// Read the kept tries of a trimmed MARF copy at `dest` back out of the node's blob file (if it
+/// has one), and write them to a new blob file for `dest`.  The node's blob file is append-only,
+/// so it still has every trie the copy refers to.
+fn export_marf_blobs(src: &Path, dest: &Path, db: &mut Connection) -> Result<(), Error> {
+    let dest_str = dest.to_string_lossy().to_string();
+    if TrieFile::exists(&src.to_string_lossy())? {
+        let mut src_blobs = fs::File::open(blobs_path(src))?;
+        let tx = tx_begin_immediate(db)?;
+        for (block_id, offset, length) in trie_sql::get_external_trie_blobs(&tx)?.into_iter() {
+            let mut trie_blob = vec![0u8; length as usize];
+            src_blobs.seek(SeekFrom::Start(offset))?;
+            src_blobs.read_exact(&mut trie_blob)?;
+            let args: &[&dyn ToSql] = &[&trie_blob, &block_id];
+            tx.execute(
+                "UPDATE marf_data SET data = ?1, external_offset = 0, external_length = 0 WHERE block_id = ?2",
+                args,
+            )?;
+        }
+        tx.commit()?;
+    }
+    let mut blobs = TrieFile::from_db_path(&dest_str, false)?;
+    blobs.export_trie_blobs::<StacksBlockId>(db, &dest_str)?;
+    Ok(())
+}
+
+/// Copy a DB out of a node in a single read transaction, and trim the copy to `boundary`.  A
+/// MARF's kept tries are then written to a new blob file.
+fn export_db(
+    item: SnapshotItem,
+    src: &Path,
+    dest: &Path,
+    boundary: &SnapshotBoundary,
+) -> Result<(), Error> {
+    vacuum_into(src, dest)?;
+    let mut db = sqlite_open(dest, OpenFlags::SQLITE_OPEN_READ_WRITE, false)?;
+    // sqlite enforces foreign keys by default, but the trim deletes parent rows first
+    sql_pragma(&db, "foreign_keys", &false)?;
+    let tx = tx_begin_immediate(&mut db)?;
+    match item {
+        SnapshotItem::BurnchainDB => trim_burnchain_db(&tx, boundary)?,
+        SnapshotItem::SpvHeaders => trim_spv_headers(&tx, boundary)?,
+        SnapshotItem::SortitionDB => trim_sortition_db(&tx, boundary)?,
+        SnapshotItem::HeadersIndex => trim_headers_index(&tx, boundary)?,
+        SnapshotItem::ClarityState => trim_clarity_state(&tx, boundary)?,
+        SnapshotItem::Blocks => unreachable!("BUG: blocks are not kept in a DB"),
+    }
+    tx.commit()?;
+
+    if item.has_external_blobs() {
+        // this vacuums the DB once the blobs are out of it
+        export_marf_blobs(src, dest, &mut db)?;
+    } else {
+        db.execute_batch("VACUUM;")?;
+    }
+    Ok(())
+}
+
+/// Copy the kept Stacks blocks into a new block store at `dest`, in the same layout as the
+/// node's.  The boot block, last in `block_ids`, has no block data.
+fn export_blocks(src: &Path, dest: &Path, block_ids: &[StacksBlockId]) -> Result<(), Error> {
+    let src_dir = src.to_string_lossy().to_string();
+    let dest_dir = dest.to_string_lossy().to_string();
+    let src_store = StacksChainState::open_block_store(&src_dir)?;
+    fs::create_dir_all(dest)?;
+    if let Some(compress) = StacksChainState::is_packed_block_store_compressed(&src_dir)? {
+        StacksChainState::create_packed_block_store(&dest_dir, compress)?;
+    }
+    let mut dest_store = StacksChainState::open_block_store(&dest_dir)?;
+
+    let num_blocks = block_ids.len().saturating_sub(1);
+    for block_id in block_ids[..num_blocks].iter() {
+        let bytes = src_store.load_block_bytes(block_id)?;
+        if bytes.is_empty() {
+            return Err(Error::InvalidSnapshot(format!(
+                "Block {} is invalid or pruned",
+                block_id
+            )));
+        }
+        dest_store.store_block_bytes(block_id, &bytes)?;
+    }
+    Ok(())
+}
+
+/// Find the sortition at the start of `reward_cycle` (or the latest reward cycle that started
+/// before the burnchain tip), the MARF roots a snapshot pinned to it must match, and what the
+/// snapshot keeps.
+fn make_manifest(
+    paths: &SnapshotPaths,
+    pox_constants: &PoxConstants,
+    reward_cycle: Option<u64>,
+) -> Result<(SnapshotManifest, SnapshotBoundary), Error> {
+    let db_config = paths.load_db_config()?;
+    let mut sortdb = SortitionDB::open(&paths.sortdb_path(), false, pox_constants.clone())?;
+    let burn_tip = SortitionDB::get_canonical_burn_chain_tip(sortdb.conn())?;
+
+    let reward_cycle = match reward_cycle {
+        Some(rc) => rc,
+        None => {
+            let rc = pox_constants
+                .block_height_to_reward_cycle(sortdb.first_block_height, burn_tip.block_height)
+                .ok_or_else(|| {
+                    Error::InvalidSnapshot("Burnchain tip is before the first reward cycle".into())
+                })?;
+            if pox_constants.reward_cycle_to_block_height(sortdb.first_block_height, rc)
+                >= burn_tip.block_height
+            {
+                rc.saturating_sub(1)
+            } else {
+                rc
+            }
+        }
+    };
+
+    // the boundary's sortition must be in the past, so that its Stacks tip no longer moves
+    let burn_block_height =
+        pox_constants.reward_cycle_to_block_height(sortdb.first_block_height, reward_cycle);
+    if burn_block_height >= burn_tip.block_height {
+        return Err(Error::InvalidSnapshot(format!(
+            "Reward cycle {} starts at burn height {}, but the burnchain tip is at {}",
+            reward_cycle, burn_block_height, burn_tip.block_height
+        )));
+    }
+
+    let sn = SortitionDB::get_ancestor_snapshot(
+        &sortdb.index_conn(),
+        burn_block_height,
+        &burn_tip.sortition_id,
+    )?
+    .ok_or_else(|| {
+        Error::InvalidSnapshot(format!("No sortition at burn height {}", burn_block_height))
+    })?;
+    let sortition_root = sortdb.marf.get_root_hash_at(&sn.sortition_id)?;
+    let stacks_tip = StacksBlockId::new(
+        &sn.canonical_stacks_tip_consensus_hash,
+        &sn.canonical_stacks_tip_hash,
+    );
+
+    let (mut chainstate, _) = StacksChainState::open(
+        db_config.mainnet,
+        db_config.chain_id,
+        &paths.chainstate,
+        None,
+    )?;
+    if let Some(height) = StacksChainState::get_pruned_below_height(chainstate.db())? {
+        return Err(Error::InvalidSnapshot(format!(
+            "Chainstate was pruned below Stacks height {}",
+            height
+        )));
+    }
+    let header = StacksChainState::get_stacks_block_header_info_by_index_block_hash(
+        chainstate.db(),
+        &stacks_tip,
+    )?
+    .ok_or_else(|| Error::InvalidSnapshot(format!("No header for Stacks tip {}", &stacks_tip)))?;
+    let headers_root = chainstate.state_index.get_root_hash_at(&stacks_tip)?;
+    let clarity_root = chainstate.with_clarity_marf(|marf| marf.get_root_hash_at(&stacks_tip))?;
+    let block_ids = get_ancestor_block_ids(chainstate.db(), &stacks_tip)?;
+
+    let manifest = SnapshotManifest {
+        version: SNAPSHOT_VERSION,
+        mainnet: db_config.mainnet,
+        chain_id: db_config.chain_id,
+        reward_cycle,
+        burn_block_height,
+        consensus_hash: sn.consensus_hash,
+        sortition_root,
+        stacks_tip,
+        stacks_block_height: header.stacks_block_height,
+        headers_root,
+        clarity_root,
+        files: vec![],
+    };
+    let boundary = SnapshotBoundary {
+        burn_block_height,
+        burn_header_hash: sn.burn_header_hash,
+        sortition_id: sn.sortition_id,
+        block_ids,
+    };
+    Ok((manifest, boundary))
+}
+
+/// Export a snapshot of the node state at `paths` to `out_dir`, pinned to the start of
+/// `reward_cycle` (or of the latest reward cycle that started before the burnchain tip).
+/// The node may keep running while this happens.
+pub fn export_snapshot(
+    paths: &SnapshotPaths,
+    out_dir: &str,
+    pox_constants: &PoxConstants,
+    reward_cycle: Option<u64>,
+) -> Result<SnapshotManifest, Error> {
+    if let Ok(mut dir) = fs::read_dir(out_dir) {
+        if dir.next().is_some() {
+            return Err(Error::InvalidSnapshot(format!("{} is not empty", out_dir)));
+        }
+    }
+    fs::create_dir_all(out_dir)?;
+
+    let (mut manifest, boundary) = make_manifest(paths, pox_constants, reward_cycle)?;
+    info!(
+        "Export snapshot at reward cycle {} to {}",
+        manifest.reward_cycle, out_dir;
+        "burn_block_height" => manifest.burn_block_height,
+        "stacks_tip" => %manifest.stacks_tip,
+        "stacks_block_height" => manifest.stacks_block_height
+    );
+
+    let out_paths = SnapshotPaths::from_network_dir(out_dir);
+    for item in SnapshotItem::ALL.iter() {
+        let Some(src) = item.path(paths) else {
+            continue;
+        };
+        let dest = item
+            .path(&out_paths)
+            .expect("BUG: snapshot layout is missing an item");
+        if !src.exists() {
+            if item.is_optional() {
+                continue;
+            }
+            return Err(Error::InvalidSnapshot(format!("Missing {}", src.display())));
+        }
+
+        debug!(
+            "Export {:?} from {} to {}",
+            item,
+            src.display(),
+            dest.display()
+        );
+        if item.is_db() {
+            export_db(*item, &src, &dest, &boundary)?;
+        } else {
+            export_blocks(&src, &dest, &boundary.block_ids)?;
+        }
+    }
+
+    let mut files = vec![];
+    list_files(Path::new(out_dir), &mut files)?;
+    files.sort();
+    for path in files.into_iter() {
+        let rel_path = path.strip_prefix(out_dir).map_err(|_| {
+            Error::InvalidSnapshot(format!("{} escapes {}", path.display(), out_dir))
+        })?;
+        manifest.files.push(SnapshotFile {
+            path: rel_path.to_string_lossy().to_string(),
+            size: fs::metadata(&path)?.len(),
+            sha256: hash_file(&path)?,
+        });
+    }
+
+    manifest.store(out_dir)?;
+    Ok(manifest)
+}
+
+/// Check that the node state at `paths` is consistent with `manifest`, and that the manifest's
+/// Stacks tip is `trusted_tip` if one is given.  Walks the Stacks chain from the manifest's tip
+/// back to genesis, and checks that each block's header hashes to its index block hash and is
+/// the parent that its child's header names, and that its MARF roots match its header.
+/// Returns the number of blocks checked.
+pub fn verify_snapshot(
+    manifest: &SnapshotManifest,
+    paths: &SnapshotPaths,
+    pox_constants: &PoxConstants,
+    marf_opts: Option<MARFOpenOpts>,
+    trusted_tip: Option<&StacksBlockId>,
+) -> Result<u64, Error> {
+    if let Some(trusted_tip) = trusted_tip {
+        if *trusted_tip != manifest.stacks_tip {
+            return Err(Error::InvalidSnapshot(format!(
+                "Snapshot Stacks tip is {}, but the trusted tip is {}",
+                &manifest.stacks_tip, trusted_tip
+            )));
+        }
+    }
+
+    let mut sortdb = SortitionDB::open(&paths.sortdb_path(), false, pox_constants.clone())?;
+    let sn = SortitionDB::get_block_snapshot_consensus(sortdb.conn(), &manifest.consensus_hash)?
+        .ok_or_else(|| {
+            Error::InvalidSnapshot(format!("No sortition for {}", &manifest.consensus_hash))
+        })?;
+    if sn.block_height != manifest.burn_block_height {
+        return Err(Error::InvalidSnapshot(format!(
+            "Sortition {} is at burn height {}, not {}",
+            &manifest.consensus_hash, sn.block_height, manifest.burn_block_height
+        )));
+    }
+    let sortition_root = sortdb.marf.get_root_hash_at(&sn.sortition_id)?;
+    if sortition_root != manifest.sortition_root || sn.index_root != manifest.sortition_root {
+        return Err(Error::InvalidSnapshot(format!(
+            "Sortition MARF root is {}, but the manifest says {}",
+            &sortition_root, &manifest.sortition_root
+        )));
+    }
+    let stacks_tip = StacksBlockId::new(
+        &sn.canonical_stacks_tip_consensus_hash,
+        &sn.canonical_stacks_tip_hash,
+    );
+    if stacks_tip != manifest.stacks_tip {
+        return Err(Error::InvalidSnapshot(format!(
+            "Stacks tip at sortition {} is {}, but the manifest says {}",
+            &manifest.consensus_hash, &stacks_tip, &manifest.stacks_tip
+        )));
+    }
+
+    let (mut chainstate, _) = StacksChainState::open(
+        manifest.mainnet,
+        manifest.chain_id,
+        &paths.chainstate,
+        marf_opts,
+    )?;
+
+    let mut num_checked = 0;
+    let mut cur_block_id = manifest.stacks_tip.clone();
+    // the parent block hash named by the last-checked block's header
+    let mut expected_block_hash = None;
+    loop {
+        let header = StacksChainState::get_stacks_block_header_info_by_index_block_hash(
+            chainstate.db(),
+            &cur_block_id,
+        )?
+        .ok_or_else(|| Error::InvalidSnapshot(format!("No header for {}", &cur_block_id)))?;
+
+        let block_hash = header.anchored_header.block_hash();
+        if StacksBlockId::new(&header.consensus_hash, &block_hash) != cur_block_id {
+            return Err(Error::InvalidSnapshot(format!(
+                "Header of {} hashes to {}/{}",
+                &cur_block_id, &header.consensus_hash, &block_hash
+            )));
+        }
+        if let Some(expected_block_hash) = expected_block_hash.as_ref() {
+            if block_hash != *expected_block_hash {
+                return Err(Error::InvalidSnapshot(format!(
+                    "Block {} is not the parent its child names ({})",
+                    &cur_block_id, expected_block_hash
+                )));
+            }
+        }
+
+        let headers_root = chainstate.state_index.get_root_hash_at(&cur_block_id)?;
+        let clarity_root =
+            chainstate.with_clarity_marf(|marf| marf.get_root_hash_at(&cur_block_id))?;
+
+        if headers_root != header.index_root {
+            return Err(Error::InvalidSnapshot(format!(
+                "Headers index root at {} is {}, but its header says {}",
+                &cur_block_id, &headers_root, &header.index_root
+            )));
+        }
+        // the boot block's header does not commit to the boot code's state
+        if header.stacks_block_height > 0 && clarity_root != header.anchored_header.state_index_root
+        {
+            return Err(Error::InvalidSnapshot(format!(
+                "Clarity state root at {} is {}, but its header says {}",
+                &cur_block_id, &clarity_root, &header.anchored_header.state_index_root
+            )));
+        }
+        if cur_block_id == manifest.stacks_tip
+            && (headers_root != manifest.headers_root || clarity_root != manifest.clarity_root)
+        {
+            return Err(Error::InvalidSnapshot(format!(
+                "MARF roots at {} do not match the manifest",
+                &cur_block_id
+            )));
+        }
+
+        num_checked += 1;
+        if num_checked % 10_000 == 0 {
+            info!(
+                "Verified {} of {} snapshot blocks",
+                num_checked,
+                manifest.stacks_block_height + 1
+            );
+        }
+        if header.stacks_block_height == 0 {
+            break;
+        }
+        expected_block_hash = Some(header.anchored_header.parent_block.clone());
+        cur_block_id = StacksChainState::get_parent_block_id(chainstate.db(), &cur_block_id)?
+            .ok_or_else(|| Error::InvalidSnapshot(format!("No parent of {}", &cur_block_id)))?;
+    }
+    Ok(num_checked)
+}
+
+/// Check that the files in `snapshot_dir` are exactly the ones its manifest lists, with the
+/// listed sizes and hashes
+fn check_snapshot_files(snapshot_dir: &str, manifest: &SnapshotManifest) -> Result<(), Error> {
+    let listed: HashSet<&str> = manifest.files.iter().map(|f| f.path.as_str()).collect();
+    let mut files = vec![];
+    list_files(Path::new(snapshot_dir), &mut files)?;
+    for path in files.iter() {
+        let rel_path = path
+            .strip_prefix(snapshot_dir)
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if rel_path != SNAPSHOT_MANIFEST && !listed.contains(rel_path.as_str()) {
+            return Err(Error::InvalidSnapshot(format!(
+                "{} is not in the manifest",
+                path.display()
+            )));
+        }
+    }
+
+    for file in manifest.files.iter() {
+        let path = Path::new(snapshot_dir).join(&file.path);
+        let size = fs::metadata(&path)?.len();
+        if size != file.size {
+            return Err(Error::InvalidSnapshot(format!(
+                "{} is {} bytes, but the manifest says {}",
+                path.display(),
+                size,
+                file.size
+            )));
+        }
+        let sha256 = hash_file(&path)?;
+        if sha256 != file.sha256 {
+            return Err(Error::InvalidSnapshot(format!(
+                "{} has SHA-256 hash {}, but the manifest says {}",
+                path.display(),
+                &sha256,
+                &file.sha256
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Import the snapshot in `snapshot_dir` into a fresh node at `paths`, and verify it, against
+/// `trusted_tip` too if one is given.
+/// Nothing is imported if any of the node's databases already exist.  If the snapshot fails
+/// verification, everything that was imported is removed again.
+pub fn import_snapshot(
+    snapshot_dir: &str,
+    paths: &SnapshotPaths,
+    mainnet: bool,
+    chain_id: u32,
+    pox_constants: &PoxConstants,
+    marf_opts: Option<MARFOpenOpts>,
+    trusted_tip: Option<&StacksBlockId>,
+) -> Result<SnapshotManifest, Error> {
+    let manifest = SnapshotManifest::load(snapshot_dir)?;
+    if manifest.version != SNAPSHOT_VERSION {
+        return Err(Error::InvalidSnapshot(format!(
+            "Unsupported snapshot version {}",
+            manifest.version
+        )));
+    }
+    if manifest.mainnet != mainnet || manifest.chain_id != chain_id {
+        return Err(Error::InvalidSnapshot(format!(
+            "Snapshot is for mainnet = {}, chain ID {:08x}",
+            manifest.mainnet, manifest.chain_id
+        )));
+    }
+    if let Some(trusted_tip) = trusted_tip {
+        if *trusted_tip != manifest.stacks_tip {
+            return Err(Error::InvalidSnapshot(format!(
+                "Snapshot Stacks tip is {}, but the trusted tip is {}",
+                &manifest.stacks_tip, trusted_tip
+            )));
+        }
+    }
+    check_snapshot_files(snapshot_dir, &manifest)?;
+
+    let snapshot_paths = SnapshotPaths::from_network_dir(snapshot_dir);
+    let mut items = vec![];
+    for item in SnapshotItem::ALL.iter() {
+        let (Some(src), Some(dest)) = (item.path(&snapshot_paths), item.path(paths)) else {
+            continue;
+        };
+        if !src.exists() {
+            if item.is_optional() {
+                continue;
+            }
+            return Err(Error::InvalidSnapshot(format!("Missing {}", src.display())));
+        }
+        if dest.exists() {
+            return Err(Error::InvalidSnapshot(format!(
+                "{} already exists; refusing to overwrite it",
+                dest.display()
+            )));
+        }
+        items.push((*item, src, dest));
+    }
+
+    // directories created by the import, which get removed if it fails
+    let new_dirs: Vec<PathBuf> = [&paths.chainstate, &paths.burnchain]
+        .iter()
+        .map(PathBuf::from)
+        .filter(|dir| !dir.exists())
+        .collect();
+
+    info!(
+        "Import snapshot at reward cycle {} from {}",
+        manifest.reward_cycle, snapshot_dir;
+        "stacks_tip" => %manifest.stacks_tip,
+        "stacks_block_height" => manifest.stacks_block_height
+    );
+
+    let res = import_items(&items).and_then(|_| {
+        verify_snapshot(&manifest, paths, pox_constants, marf_opts, trusted_tip).map(
+            |num_checked| {
+                info!("Verified MARF roots of {} snapshot blocks", num_checked);
+            },
+        )
+    });
+    if let Err(e) = res {
+        error!("Failed to import snapshot from {}: {}", snapshot_dir, &e);
+        for (_, _, dest) in items.iter() {
+            let _ = fs::remove_dir_all(dest).or_else(|_| fs::remove_file(dest));
+            let _ = fs::remove_file(blobs_path(dest));
+        }
+        for dir in new_dirs.iter() {
+            let _ = fs::remove_dir_all(dir);
+        }
+        return Err(e);
+    }
+    Ok(manifest)
+}
+
+fn import_items(items: &[(SnapshotItem, PathBuf, PathBuf)]) -> Result<(), Error> {
+    for (item, src, dest) in items.iter() {
+        debug!(
+            "Import {:?} from {} to {}",
+            item,
+            src.display(),
+            dest.display()
+        );
+        if !item.is_db() {
+            copy_dir_all(src, dest)?;
+            continue;
+        }
+        mkdirs_for(dest)?;
+        fs::copy(src, dest)?;
+        if item.has_external_blobs() {
+            fs::copy(blobs_path(src), blobs_path(dest))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use rusqlite::NO_PARAMS;
+
+    use super::*;
+    use crate::net::test::{TestPeer, TestPeerConfig};
+
+    fn make_snapshot_peer<'a>(test_name: &str, port: u16) -> TestPeer<'a> {
+        let peer_config = TestPeerConfig::new(test_name, port, port + 1);
+        let mut peer = TestPeer::new(peer_config);
+        let mut coinbase_nonce = 0;
+        for _ in 0..12 {
+            peer.tenure_with_txs(&[], &mut coinbase_nonce);
+        }
+        peer
+    }
+
+    fn snapshot_paths(peer: &TestPeer) -> SnapshotPaths {
+        SnapshotPaths {
+            chainstate: peer.chainstate_path.clone(),
+            burnchain: peer.config.burnchain.working_dir.clone(),
+            spv_headers: None,
+        }
+    }
+
+    fn fresh_dir(path: &str) -> String {
+        if fs::metadata(path).is_ok() {
+            fs::remove_dir_all(path).unwrap();
+        }
+        path.to_string()
+    }
+
+    #[test]
+    fn test_export_import_snapshot() {
+        let peer = make_snapshot_peer(function_name!(), 21000);
+        let pox_constants = peer.config.burnchain.pox_constants.clone();
+        let paths = snapshot_paths(&peer);
+        let snapshot_dir = fresh_dir(&format!("/tmp/{}.snapshot", function_name!()));
+
+        let manifest = export_snapshot(&paths, &snapshot_dir, &pox_constants, None).unwrap();
+        assert!(manifest.stacks_block_height > 0);
+        assert_eq!(manifest, SnapshotManifest::load(&snapshot_dir).unwrap());
+        assert!(manifest
+            .files
+            .iter()
+            .any(|f| f.path.ends_with("clarity/marf.sqlite.blobs")));
+        assert!(manifest.files.iter().any(|f| f.path.contains("/blocks/")));
+
+        // can't export on top of an existing snapshot
+        assert!(export_snapshot(&paths, &snapshot_dir, &pox_constants, None).is_err());
+
+        let import_dir = fresh_dir(&format!("/tmp/{}.import", function_name!()));
+        let import_paths = SnapshotPaths::from_network_dir(&import_dir);
+        let imported = import_snapshot(
+            &snapshot_dir,
+            &import_paths,
+            manifest.mainnet,
+            manifest.chain_id,
+            &pox_constants,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(imported, manifest);
+
+        // imported state is readable
+        let num_checked = verify_snapshot(
+            &manifest,
+            &import_paths,
+            &pox_constants,
+            None,
+            Some(&manifest.stacks_tip),
+        )
+        .unwrap();
+        assert_eq!(num_checked, manifest.stacks_block_height + 1);
+
+        // nothing past the boundary was exported
+        let (chainstate, _) = StacksChainState::open(
+            manifest.mainnet,
+            manifest.chain_id,
+            &import_paths.chainstate,
+            None,
+        )
+        .unwrap();
+        let max_height: u64 = chainstate
+            .db()
+            .query_row(
+                "SELECT MAX(block_height) FROM block_headers",
+                NO_PARAMS,
+                |row| row.get::<_, i64>(0),
+            )
+            .map(|height| height as u64)
+            .unwrap();
+        assert_eq!(max_height, manifest.stacks_block_height);
+        let sortdb =
+            SortitionDB::open(&import_paths.sortdb_path(), false, pox_constants.clone()).unwrap();
+        let burn_tip = SortitionDB::get_canonical_burn_chain_tip(sortdb.conn()).unwrap();
+        assert_eq!(burn_tip.consensus_hash, manifest.consensus_hash);
+
+        // won't clobber an existing node
+        assert!(import_snapshot(
+            &snapshot_dir,
+            &import_paths,
+            manifest.mainnet,
+            manifest.chain_id,
+            &pox_constants,
+            None,
+            None,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_import_bad_snapshot() {
+        let peer = make_snapshot_peer(function_name!(), 21010);
+        let pox_constants = peer.config.burnchain.pox_constants.clone();
+        let paths = snapshot_paths(&peer);
+        let snapshot_dir = fresh_dir(&format!("/tmp/{}.snapshot", function_name!()));
+
+        let mut manifest = export_snapshot(&paths, &snapshot_dir, &pox_constants, None).unwrap();
+        manifest.clarity_root = TrieHash([0x01; 32]);
+        manifest.store(&snapshot_dir).unwrap();
+
+        let import_dir = fresh_dir(&format!("/tmp/{}.import", function_name!()));
+        let import_paths = SnapshotPaths::from_network_dir(&import_dir);
+        match import_snapshot(
+            &snapshot_dir,
+            &import_paths,
+            manifest.mainnet,
+            manifest.chain_id,
+            &pox_constants,
+            None,
+            None,
+        ) {
+            Err(Error::InvalidSnapshot(_)) => {}
+            x => panic!("Expected InvalidSnapshot, got {:?}", &x),
+        }
+
+        // nothing was left behind
+        assert!(!Path::new(&import_paths.chainstate).exists());
+        assert!(!Path::new(&import_paths.burnchain)
+            .join("burnchain.sqlite")
+            .exists());
+
+        // wrong network
+        assert!(import_snapshot(
+            &snapshot_dir,
+            &import_paths,
+            !manifest.mainnet,
+            manifest.chain_id,
+            &pox_constants,
+            None,
+            None,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_import_tampered_snapshot() {
+        let peer = make_snapshot_peer(function_name!(), 21020);
+        let pox_constants = peer.config.burnchain.pox_constants.clone();
+        let paths = snapshot_paths(&peer);
+        let snapshot_dir = fresh_dir(&format!("/tmp/{}.snapshot", function_name!()));
+        let manifest = export_snapshot(&paths, &snapshot_dir, &pox_constants, None).unwrap();
+
+        let import_dir = fresh_dir(&format!("/tmp/{}.import", function_name!()));
+        let import_paths = SnapshotPaths::from_network_dir(&import_dir);
+
+        // wrong trusted tip
+        match import_snapshot(
+            &snapshot_dir,
+            &import_paths,
+            manifest.mainnet,
+            manifest.chain_id,
+            &pox_constants,
+            None,
+            Some(&StacksBlockId([0x01; 32])),
+        ) {
+            Err(Error::InvalidSnapshot(_)) => {}
+            x => panic!("Expected InvalidSnapshot, got {:?}", &x),
+        }
+
+        // flip a byte in the Clarity MARF's blobs, keeping the file's size
+        let file = manifest
+            .files
+            .iter()
+            .find(|f| f.path.ends_with("clarity/marf.sqlite.blobs"))
+            .unwrap();
+        let path = Path::new(&snapshot_dir).join(&file.path);
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[0] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+        match import_snapshot(
+            &snapshot_dir,
+            &import_paths,
+            manifest.mainnet,
+            manifest.chain_id,
+            &pox_constants,
+            None,
+            None,
+        ) {
+            Err(Error::InvalidSnapshot(_)) => {}
+            x => panic!("Expected InvalidSnapshot, got {:?}", &x),
+        }
+        bytes[0] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        // a file the manifest doesn't list
+        let extra = Path::new(&snapshot_dir).join("chainstate/extra.sqlite");
+        fs::write(&extra, b"extra").unwrap();
+        match import_snapshot(
+            &snapshot_dir,
+            &import_paths,
+            manifest.mainnet,
+            manifest.chain_id,
+            &pox_constants,
+            None,
+            None,
+        ) {
+            Err(Error::InvalidSnapshot(_)) => {}
+            x => panic!("Expected InvalidSnapshot, got {:?}", &x),
+        }
+        fs::remove_file(&extra).unwrap();
+
+        // nothing was imported by the failed attempts
+        assert!(!Path::new(&import_paths.chainstate).exists());
+
+        import_snapshot(
+            &snapshot_dir,
+            &import_paths,
+            manifest.mainnet,
+            manifest.chain_id,
+            &pox_constants,
+            None,
+            Some(&manifest.stacks_tip),
+        )
+        .unwrap();
+    }
+}
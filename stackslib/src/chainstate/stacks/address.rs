@@ -433,7 +433,12 @@ impl PoxAddress {
 
     /// Try instantiating a PoxAddress from a Bitcoin tx output
     pub fn try_from_bitcoin_output(o: &BitcoinTxOutput) -> Option<PoxAddress> {
-        match &o.address {
+        PoxAddress::try_from_bitcoin_address(&o.address)
+    }
+
+    /// Try instantiating a PoxAddress from a Bitcoin address
+    pub fn try_from_bitcoin_address(address: &BitcoinAddress) -> Option<PoxAddress> {
+        match address {
             BitcoinAddress::Legacy(ref legacy_addr) => {
                 let addr = StacksAddress::from_legacy_bitcoin_address(legacy_addr);
                 let pox_addr = PoxAddress::Standard(addr, None);
@@ -684,7 +689,7 @@ mod test {
                 ),
                 (
                     ClarityName::try_from("hashbytes".to_owned()).unwrap(),
-                    Value::Sequence(SequenceData::Buffer(BuffData { data: bytes })),
+                    Value::Sequence(SequenceData::Buffer(BuffData { data: bytes.into() })),
                 ),
             ])
             .unwrap(),
@@ -838,13 +843,13 @@ mod test {
                     (
                         ClarityName::try_from("version".to_owned()).unwrap(),
                         Value::Sequence(SequenceData::Buffer(BuffData {
-                            data: vec![0x01, 0x02],
+                            data: vec![0x01, 0x02].into(),
                         }))
                     ),
                     (
                         ClarityName::try_from("hashbytes".to_owned()).unwrap(),
                         Value::Sequence(SequenceData::Buffer(BuffData {
-                            data: vec![0x0e; 20]
+                            data: vec![0x0e; 20].into()
                         })),
                     ),
                 ])
@@ -859,12 +864,14 @@ mod test {
                 TupleData::from_data(vec![
                     (
                         ClarityName::try_from("version".to_owned()).unwrap(),
-                        Value::Sequence(SequenceData::Buffer(BuffData { data: vec![] }))
+                        Value::Sequence(SequenceData::Buffer(BuffData {
+                            data: vec![].into()
+                        }))
                     ),
                     (
                         ClarityName::try_from("hashbytes".to_owned()).unwrap(),
                         Value::Sequence(SequenceData::Buffer(BuffData {
-                            data: vec![0x0e; 20]
+                            data: vec![0x0e; 20].into()
                         })),
                     ),
                 ])
@@ -879,12 +886,14 @@ mod test {
                 TupleData::from_data(vec![
                     (
                         ClarityName::try_from("version-nope".to_owned()).unwrap(),
-                        Value::Sequence(SequenceData::Buffer(BuffData { data: vec![0x01] }))
+                        Value::Sequence(SequenceData::Buffer(BuffData {
+                            data: vec![0x01].into()
+                        }))
                     ),
                     (
                         ClarityName::try_from("hashbytes".to_owned()).unwrap(),
                         Value::Sequence(SequenceData::Buffer(BuffData {
-                            data: vec![0x0e; 20]
+                            data: vec![0x0e; 20].into()
                         })),
                     ),
                 ])
@@ -899,12 +908,14 @@ mod test {
                 TupleData::from_data(vec![
                     (
                         ClarityName::try_from("version".to_owned()).unwrap(),
-                        Value::Sequence(SequenceData::Buffer(BuffData { data: vec![0x01] }))
+                        Value::Sequence(SequenceData::Buffer(BuffData {
+                            data: vec![0x01].into()
+                        }))
                     ),
                     (
                         ClarityName::try_from("hashbytes-nope".to_owned()).unwrap(),
                         Value::Sequence(SequenceData::Buffer(BuffData {
-                            data: vec![0x0e; 20]
+                            data: vec![0x0e; 20].into()
                         })),
                     ),
                 ])
@@ -1490,7 +1490,7 @@ pub mod test {
                 (
                     ClarityName::try_from("hashbytes".to_owned()).unwrap(),
                     Value::Sequence(SequenceData::Buffer(BuffData {
-                        data: addr_bytes.as_bytes().to_vec(),
+                        data: addr_bytes.as_bytes().to_vec().into(),
                     })),
                 ),
             ])
@@ -1711,7 +1711,7 @@ pub mod test {
                 (
                     ClarityName::try_from("hashbytes".to_owned()).unwrap(),
                     Value::Sequence(SequenceData::Buffer(BuffData {
-                        data: addr_bytes.as_bytes().to_vec(),
+                        data: addr_bytes.as_bytes().to_vec().into(),
                     })),
                 ),
             ])
@@ -4359,7 +4359,7 @@ fn test_pox_2_delegate_stx_addr_validation() {
             (
                 ClarityName::try_from("hashbytes".to_owned()).unwrap(),
                 Value::Sequence(SequenceData::Buffer(BuffData {
-                    data: bob_address.bytes.as_bytes().to_vec(),
+                    data: bob_address.bytes.as_bytes().to_vec().into(),
                 })),
             ),
         ])
@@ -4333,7 +4333,7 @@ fn pox_3_delegate_stx_addr_validation() {
             (
                 ClarityName::try_from("hashbytes".to_owned()).unwrap(),
                 Value::Sequence(SequenceData::Buffer(BuffData {
-                    data: bob_address.bytes.as_bytes().to_vec(),
+                    data: bob_address.bytes.as_bytes().to_vec().into(),
                 })),
             ),
         ])
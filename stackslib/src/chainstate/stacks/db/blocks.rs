@@ -52,6 +52,7 @@ use crate::chainstate::burn::BlockSnapshot;
 use crate::chainstate::coordinator::BlockEventDispatcher;
 use crate::chainstate::stacks::address::{PoxAddress, StacksAddressExtensions};
 use crate::chainstate::stacks::db::accounts::MinerReward;
+use crate::chainstate::stacks::db::prune::is_pruned_state_error;
 use crate::chainstate::stacks::db::transactions::TransactionNonceMismatch;
 use crate::chainstate::stacks::db::*;
 use crate::chainstate::stacks::index::MarfTrieId;
@@ -444,7 +445,7 @@ impl StacksChainState {
         block_path
     }
 
-    /// Get the path to a block in the chunk store
+    /// Get the path to a block in the chunk store, if it uses the file layout
     pub fn get_index_block_path(
         blocks_dir: &str,
         index_block_hash: &StacksBlockId,
@@ -458,7 +459,8 @@ impl StacksChainState {
         Ok(blocks_path_str)
     }
 
-    /// Get the path to a block in the chunk store, given the burn header hash and block hash.
+    /// Get the path to a block in the chunk store, given the burn header hash and block hash, if it
+    /// uses the file layout.
     pub fn get_block_path(
         blocks_dir: &str,
         consensus_hash: &ConsensusHash,
@@ -468,29 +470,6 @@ impl StacksChainState {
         StacksChainState::get_index_block_path(blocks_dir, &index_block_hash)
     }
 
-    /// Make a directory tree for storing this block to the chunk store, and return the block's path
-    fn make_block_dir(
-        blocks_dir: &str,
-        consensus_hash: &ConsensusHash,
-        block_hash: &BlockHeaderHash,
-    ) -> Result<String, Error> {
-        let index_block_hash = StacksBlockHeader::make_index_block_hash(consensus_hash, block_hash);
-        let block_hash_bytes = index_block_hash.as_bytes();
-        let mut block_path = PathBuf::from(blocks_dir);
-
-        block_path.push(to_hex(&block_hash_bytes[0..2]));
-        block_path.push(to_hex(&block_hash_bytes[2..4]));
-
-        let _ = StacksChainState::mkdirs(&block_path)?;
-
-        block_path.push(format!("{}", to_hex(block_hash_bytes)));
-        let blocks_path_str = block_path
-            .to_str()
-            .ok_or_else(|| Error::DBError(db_error::ParseError))?
-            .to_string();
-        Ok(blocks_path_str)
-    }
-
     pub fn atomic_file_store<F>(
         path: &str,
         delete_on_error: bool,
@@ -581,17 +560,8 @@ impl StacksChainState {
         blocks_dir: &str,
         index_block_hash: &StacksBlockId,
     ) -> Result<bool, Error> {
-        let block_path = StacksChainState::get_index_block_path(blocks_dir, index_block_hash)?;
-        match fs::metadata(block_path) {
-            Ok(_) => Ok(true),
-            Err(e) => {
-                if e.kind() == io::ErrorKind::NotFound {
-                    Ok(false)
-                } else {
-                    Err(Error::DBError(db_error::IOError(e)))
-                }
-            }
-        }
+        let store = StacksChainState::open_block_store(blocks_dir)?;
+        Ok(store.get_block_size(index_block_hash)?.is_some())
     }
 
     /// Do we have a stored a block in the chunk store?
@@ -600,17 +570,8 @@ impl StacksChainState {
         blocks_dir: &str,
         index_block_hash: &StacksBlockId,
     ) -> Result<bool, Error> {
-        let block_path = StacksChainState::get_index_block_path(blocks_dir, index_block_hash)?;
-        match fs::metadata(block_path) {
-            Ok(md) => Ok(md.len() > 0),
-            Err(e) => {
-                if e.kind() == io::ErrorKind::NotFound {
-                    Ok(false)
-                } else {
-                    Err(Error::DBError(db_error::IOError(e)))
-                }
-            }
-        }
+        let store = StacksChainState::open_block_store(blocks_dir)?;
+        Ok(store.get_block_size(index_block_hash)?.unwrap_or(0) > 0)
     }
 
     /// Have we processed and stored a particular block?
@@ -657,17 +618,16 @@ impl StacksChainState {
         block: &StacksBlock,
     ) -> Result<(), Error> {
         let block_hash = block.block_hash();
-        let block_path = StacksChainState::make_block_dir(blocks_dir, consensus_hash, &block_hash)?;
+        let index_block_hash = StacksBlockId::new(consensus_hash, &block_hash);
 
         test_debug!(
-            "Store {}/{} to {}",
+            "Store {}/{} ({})",
             consensus_hash,
             &block_hash,
-            &block_path
+            &index_block_hash
         );
-        StacksChainState::atomic_file_store(&block_path, true, |ref mut fd| {
-            block.consensus_serialize(fd).map_err(Error::CodecError)
-        })
+        let mut store = StacksChainState::open_block_store(blocks_dir)?;
+        store.store_block_bytes(&index_block_hash, &block.serialize_to_vec())
     }
 
     /// Store an empty block to the chunk store, named by its hash.
@@ -677,65 +637,23 @@ impl StacksChainState {
         consensus_hash: &ConsensusHash,
         block_hash: &BlockHeaderHash,
     ) -> Result<(), Error> {
-        let block_path =
-            StacksChainState::make_block_dir(blocks_path, consensus_hash, &block_hash)?;
-        StacksChainState::atomic_file_write(&block_path, &vec![])
+        let mut store = StacksChainState::open_block_store(blocks_path)?;
+        store.store_block_bytes(&StacksBlockId::new(consensus_hash, block_hash), &[])
     }
 
-    /// Mark a block in the filesystem as invalid
+    /// Mark a block in the block store as invalid
     fn free_block(
         blocks_dir: &str,
         consensus_hash: &ConsensusHash,
         block_header_hash: &BlockHeaderHash,
     ) -> () {
-        let block_path =
-            StacksChainState::make_block_dir(blocks_dir, consensus_hash, &block_header_hash)
-                .expect("FATAL: failed to create block directory");
-
-        let sz = fs::metadata(&block_path)
-            .expect(&format!("FATAL: failed to stat '{}'", &block_path))
-            .len();
-
-        if sz > 0 {
-            // try make this thread-safe. It's okay if this block gets copied more than once; we
-            // only care that at least one copy survives for further analysis.
-            let random_bytes = thread_rng().gen::<[u8; 8]>();
-            let random_bytes_str = to_hex(&random_bytes);
-            let index_block_hash = StacksBlockId::new(consensus_hash, block_header_hash);
-            let mut invalid_path =
-                StacksChainState::get_index_block_pathbuf(blocks_dir, &index_block_hash);
-            invalid_path
-                .file_name()
-                .expect("FATAL: index block path did not have file name");
-            invalid_path.set_extension(&format!("invalid-{}", &random_bytes_str));
-
-            fs::copy(&block_path, &invalid_path).expect(&format!(
-                "FATAL: failed to copy '{}' to '{}'",
-                &block_path,
-                &invalid_path.to_string_lossy(),
+        let index_block_hash = StacksBlockId::new(consensus_hash, block_header_hash);
+        StacksChainState::open_block_store(blocks_dir)
+            .and_then(|mut store| store.invalidate_block(&index_block_hash))
+            .expect(&format!(
+                "FATAL: failed to mark block {} as invalid",
+                &index_block_hash
             ));
-
-            // already freed?
-            let sz = fs::metadata(&invalid_path)
-                .expect(&format!(
-                    "FATAL: failed to stat '{}'",
-                    &invalid_path.to_string_lossy()
-                ))
-                .len();
-
-            if sz > 0 {
-                // truncate the original
-                fs::OpenOptions::new()
-                    .read(false)
-                    .write(true)
-                    .truncate(true)
-                    .open(&block_path)
-                    .expect(&format!(
-                        "FATAL: Failed to mark block path '{}' as free",
-                        &block_path
-                    ));
-            }
-        }
     }
 
     /// Free up all state for an invalid block
@@ -802,8 +720,11 @@ impl StacksChainState {
         consensus_hash: &ConsensusHash,
         block_hash: &BlockHeaderHash,
     ) -> Result<Option<Vec<u8>>, Error> {
-        let block_path = StacksChainState::get_block_path(blocks_dir, consensus_hash, block_hash)?;
-        let sz = StacksChainState::get_file_size(&block_path)?;
+        let index_block_hash = StacksBlockId::new(consensus_hash, block_hash);
+        let store = StacksChainState::open_block_store(blocks_dir)?;
+        let sz = store
+            .get_block_size(&index_block_hash)?
+            .ok_or(Error::DBError(db_error::NotFoundError))?;
         if sz == 0 {
             debug!("Zero-sized block {}", block_hash);
             return Ok(None);
@@ -813,21 +734,7 @@ impl StacksChainState {
             return Ok(None);
         }
 
-        let mut fd = fs::OpenOptions::new()
-            .read(true)
-            .write(false)
-            .open(&block_path)
-            .map_err(|e| {
-                if e.kind() == io::ErrorKind::NotFound {
-                    Error::DBError(db_error::NotFoundError)
-                } else {
-                    Error::DBError(db_error::IOError(e))
-                }
-            })?;
-
-        let mut ret = vec![];
-        fd.read_to_end(&mut ret)
-            .map_err(|e| Error::DBError(db_error::IOError(e)))?;
+        let ret = store.load_block_bytes(&index_block_hash)?;
         Ok(Some(ret))
     }
 
@@ -840,28 +747,19 @@ impl StacksChainState {
         consensus_hash: &ConsensusHash,
         block_hash: &BlockHeaderHash,
     ) -> Result<Option<StacksBlock>, Error> {
-        let block_path = StacksChainState::get_block_path(blocks_dir, consensus_hash, block_hash)?;
-        let sz = StacksChainState::get_file_size(&block_path)?;
-        if sz == 0 {
+        let index_block_hash = StacksBlockId::new(consensus_hash, block_hash);
+        let store = StacksChainState::open_block_store(blocks_dir)?;
+        let bytes = store.load_block_bytes(&index_block_hash)?;
+        if bytes.is_empty() {
             debug!("Zero-sized block {}", &block_hash);
             return Ok(None);
         }
 
-        let block: StacksBlock = StacksChainState::consensus_load(&block_path)?;
+        let block =
+            StacksBlock::consensus_deserialize(&mut &bytes[..]).map_err(Error::CodecError)?;
         Ok(Some(block))
     }
 
-    fn inner_load_block_header(block_path: &str) -> Result<Option<StacksBlockHeader>, Error> {
-        let sz = StacksChainState::get_file_size(block_path)?;
-        if sz == 0 {
-            debug!("Zero-sized block {}", &block_path);
-            return Ok(None);
-        }
-
-        let block_header: StacksBlockHeader = StacksChainState::consensus_load(block_path)?;
-        Ok(Some(block_header))
-    }
-
     /// Load up an anchored block header from the chunk store.
     /// Returns Ok(Some(blockheader)) if found.
     /// Returns Ok(None) if this block was found, but is known to be invalid
@@ -871,8 +769,8 @@ impl StacksChainState {
         consensus_hash: &ConsensusHash,
         block_hash: &BlockHeaderHash,
     ) -> Result<Option<StacksBlockHeader>, Error> {
-        let block_path = StacksChainState::get_block_path(blocks_dir, consensus_hash, block_hash)?;
-        StacksChainState::inner_load_block_header(&block_path)
+        let index_block_hash = StacksBlockId::new(consensus_hash, block_hash);
+        StacksChainState::load_block_header_indexed(blocks_dir, &index_block_hash)
     }
 
     /// Load up an anchored block header from the chunk store, given the index block hash
@@ -883,8 +781,8 @@ impl StacksChainState {
         blocks_dir: &str,
         index_block_hash: &StacksBlockId,
     ) -> Result<Option<StacksBlockHeader>, Error> {
-        let block_path = StacksChainState::get_index_block_path(blocks_dir, index_block_hash)?;
-        StacksChainState::inner_load_block_header(&block_path)
+        let store = StacksChainState::open_block_store(blocks_dir)?;
+        store.load_block_header(index_block_hash)
     }
 
     /// Closure for defaulting to an empty microblock stream if a microblock stream file is not found
@@ -2109,6 +2007,12 @@ impl StacksChainState {
         let (start_height, end_height) =
             self.get_min_max_stacks_block_heights_in_reward_cycle(burnchain, reward_cycle)?;
 
+        // don't advertise blocks we've pruned
+        let start_height = match StacksChainState::get_pruned_below_height(self.db())? {
+            Some(pruned_below_height) => start_height.max(pruned_below_height),
+            None => start_height,
+        };
+
         test_debug!(
             "Search for accepted blocks and microblocks in [{},{}] for reward cycle {}",
             start_height,
@@ -2377,16 +2281,8 @@ impl StacksChainState {
         }
 
         // mark the block as invalid if we haven't already
-        let block_path =
-            StacksChainState::get_block_path(blocks_path, consensus_hash, anchored_block_hash)?;
-        match fs::metadata(&block_path) {
-            Ok(_) => {
-                StacksChainState::free_block(blocks_path, consensus_hash, anchored_block_hash);
-            }
-            Err(_) => {
-                StacksChainState::atomic_file_write(&block_path, &vec![])?;
-            }
-        }
+        let mut store = StacksChainState::open_block_store(blocks_path)?;
+        store.invalidate_block(&StacksBlockId::new(consensus_hash, anchored_block_hash))?;
 
         Ok(())
     }
@@ -2601,16 +2497,8 @@ impl StacksChainState {
         .map_err(|e| Error::DBError(db_error::SqliteError(e)))?;
 
         // mark the block as empty if we haven't already
-        let block_path =
-            StacksChainState::get_block_path(blocks_path, consensus_hash, anchored_block_hash)?;
-        match fs::metadata(&block_path) {
-            Ok(_) => {
-                StacksChainState::free_block(blocks_path, consensus_hash, anchored_block_hash);
-            }
-            Err(_) => {
-                StacksChainState::atomic_file_write(&block_path, &vec![])?;
-            }
-        }
+        let mut store = StacksChainState::open_block_store(blocks_path)?;
+        store.invalidate_block(&StacksBlockId::new(consensus_hash, anchored_block_hash))?;
 
         Ok(())
     }
@@ -4379,7 +4267,7 @@ impl StacksChainState {
                                 &sender.into(),
                                 &recipient.into(),
                                 transfered_ustx,
-                                &BuffData { data: memo },
+                                &BuffData { data: memo.into() },
                             )
                         });
                         match result {
@@ -5083,15 +4971,22 @@ impl StacksChainState {
             ) {
                 Ok((fees, burns, events)) => (fees, burns, events),
                 Err((e, mblock_header_hash)) => {
+                    if miner_id_opt.is_none() {
+                        clarity_tx.rollback_block();
+                    }
+                    if is_pruned_state_error(&e) {
+                        // the microblocks may well be valid; this node just can't tell
+                        return Err(Error::PrunedStateError(format!(
+                            "Cannot validate Stacks microblocks {},{} (at {}): {:?}",
+                            parent_consensus_hash, parent_header_hash, mblock_header_hash, &e
+                        )));
+                    }
+
                     let msg = format!(
                         "Invalid Stacks microblocks {},{} (offender {}): {:?}",
                         parent_consensus_hash, parent_header_hash, mblock_header_hash, &e
                     );
                     warn!("{}", &msg);
-
-                    if miner_id_opt.is_none() {
-                        clarity_tx.rollback_block();
-                    }
                     return Err(Error::InvalidStacksMicroblock(msg, mblock_header_hash));
                 }
             };
@@ -5508,10 +5403,18 @@ impl StacksChainState {
                     ast_rules,
                 ) {
                     Err(e) => {
+                        clarity_tx.rollback_block();
+                        if is_pruned_state_error(&e) {
+                            // the block may well be valid; this node just can't tell
+                            return Err(Error::PrunedStateError(format!(
+                                "Cannot validate Stacks block {}: {:?}",
+                                block.block_hash(),
+                                &e
+                            )));
+                        }
+
                         let msg = format!("Invalid Stacks block {}: {:?}", block.block_hash(), &e);
                         warn!("{}", &msg);
-
-                        clarity_tx.rollback_block();
                         return Err(Error::InvalidStacksBlock(msg));
                     }
                     Ok((block_fees, block_burns, txs_receipts)) => {
@@ -6050,6 +5953,18 @@ impl StacksChainState {
             block_am.weight(),
         ) {
             Ok(next_chain_tip_info) => next_chain_tip_info,
+            Err(Error::PrunedStateError(msg)) => {
+                // this node pruned state that the block (or its parent microblocks) reads, so it
+                // cannot tell whether the block is valid.  Leave it unprocessed rather than
+                // marking it invalid; the caller decides what to do about it.
+                error!(
+                    "Pruned node cannot process {}/{}: {}",
+                    &next_staging_block.consensus_hash,
+                    &block.block_hash(),
+                    &msg
+                );
+                return Err(Error::PrunedStateError(msg));
+            }
             Err(e) => {
                 // something's wrong with this epoch -- either a microblock was invalid, or the
                 // anchored block was invalid.  Either way, the anchored block will _never be_
@@ -0,0 +1,1000 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Storage of anchored block data.
+//!
+//! Anchored blocks are kept in one of two layouts under the chainstate's `blocks/` directory:
+//!
+//! * the "file" layout, where each block is its own file at `blocks/xx/yy/<index block hash>`.
+//! An empty file means the block is known to be invalid (or was pruned).
+//! * the "packed" layout, where all blocks are appended to a single file, `blocks/packed/blocks.dat`,
+//! and located through a SQLite index, `blocks/packed/index.sqlite`.  Blocks can optionally be
+//! compressed with zstd.  This uses a handful of inodes instead of one per block, and is much
+//! easier to back up.
+//!
+//! A chainstate uses the packed layout if and only if `blocks/packed/` exists.  Existing
+//! chainstates can be converted with `StacksChainState::migrate_block_store()`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use rand::{thread_rng, Rng};
+use rusqlite::types::ToSql;
+use rusqlite::{OpenFlags, OptionalExtension, NO_PARAMS};
+use stacks_common::codec::{StacksMessageCodec, MAX_MESSAGE_LEN};
+use stacks_common::types::chainstate::StacksBlockId;
+use stacks_common::util::hash::to_hex;
+
+use crate::chainstate::stacks::db::StacksChainState;
+use crate::chainstate::stacks::{Error, StacksBlockHeader};
+use crate::util_lib::db::{sqlite_open, tx_begin_immediate, u64_to_sql, DBConn, Error as db_error};
+
+/// Name of the directory in `blocks/` that holds a packed block store
+pub const PACKED_BLOCK_STORE_DIR: &str = "packed";
+/// Name of the directory in `blocks/` into which a packed block store is built during migration
+pub const PACKED_BLOCK_STORE_MIGRATING_DIR: &str = "packed.migrating";
+const PACKED_BLOCK_INDEX: &str = "index.sqlite";
+const PACKED_BLOCK_DATA: &str = "blocks.dat";
+
+const PACKED_BLOCK_STORE_VERSION: u32 = 1;
+/// zstd compression level for blocks (0 means "zstd's default")
+const PACKED_BLOCK_COMPRESSION_LEVEL: i32 = 0;
+
+lazy_static! {
+    /// Packed block stores that have been opened, by directory.  Opening one opens its index
+    /// and reads its config, which is too slow to do on every block access.
+    static ref PACKED_BLOCK_STORES: Mutex<HashMap<PathBuf, Arc<Mutex<PackedBlockStore>>>> =
+        Mutex::new(HashMap::new());
+}
+
+const PACKED_BLOCK_STORE_SCHEMA: &[&str] = &[
+    r#"
+    CREATE TABLE block_store_config(
+        version INTEGER NOT NULL,
+        -- "zstd" or "none"
+        compression TEXT NOT NULL
+    );"#,
+    r#"
+    CREATE TABLE blocks(
+        index_block_hash TEXT PRIMARY KEY NOT NULL,
+        -- where the (possibly-compressed) block is in blocks.dat
+        offset INTEGER NOT NULL,
+        length INTEGER NOT NULL,
+        -- length of the block once decompressed.  0 means the block is invalid or was pruned.
+        raw_length INTEGER NOT NULL,
+        compressed INTEGER NOT NULL,
+        -- 1 if the block was found to be invalid.  Its data is kept for later analysis.
+        invalid INTEGER NOT NULL
+    );"#,
+];
+
+/// Interface to the storage of anchored blocks, keyed by index block hash.
+///
+/// A block can be in one of three states: absent, present but known to be invalid (or pruned),
+/// and present.  Invalid blocks have a size of 0 and load as an empty byte string.
+pub trait StacksBlockStore {
+    /// Get the size of a stored block.
+    /// Returns Ok(None) if the block was never stored, and Ok(Some(0)) if it is invalid.
+    fn get_block_size(&self, index_block_hash: &StacksBlockId) -> Result<Option<u64>, Error>;
+
+    /// Load a block's bytes.  Invalid blocks load as an empty byte string.
+    /// Returns Err(DBError(NotFoundError)) if the block was never stored.
+    fn load_block_bytes(&self, index_block_hash: &StacksBlockId) -> Result<Vec<u8>, Error>;
+
+    /// Store a block's bytes, replacing whatever was stored for this block before.
+    /// Storing an empty byte string records the block as invalid (or pruned).
+    fn store_block_bytes(
+        &mut self,
+        index_block_hash: &StacksBlockId,
+        bytes: &[u8],
+    ) -> Result<(), Error>;
+
+    /// Mark a block as invalid, keeping a copy of its data for later analysis if the store can.
+    /// If the block was never stored, it is recorded as invalid anyway.
+    fn invalidate_block(&mut self, index_block_hash: &StacksBlockId) -> Result<(), Error>;
+
+    /// Load a block's header.
+    /// Returns Ok(None) if the block is invalid, and Err(DBError(NotFoundError)) if the block
+    /// was never stored.
+    fn load_block_header(
+        &self,
+        index_block_hash: &StacksBlockId,
+    ) -> Result<Option<StacksBlockHeader>, Error> {
+        let bytes = self.load_block_bytes(index_block_hash)?;
+        if bytes.is_empty() {
+            return Ok(None);
+        }
+        let header =
+            StacksBlockHeader::consensus_deserialize(&mut &bytes[..]).map_err(Error::CodecError)?;
+        Ok(Some(header))
+    }
+}
+
+/// The file-per-block layout
+pub struct FileBlockStore {
+    blocks_dir: String,
+}
+
+impl FileBlockStore {
+    pub fn new(blocks_dir: &str) -> FileBlockStore {
+        FileBlockStore {
+            blocks_dir: blocks_dir.to_string(),
+        }
+    }
+
+    fn block_path(&self, index_block_hash: &StacksBlockId) -> Result<String, Error> {
+        StacksChainState::get_index_block_path(&self.blocks_dir, index_block_hash)
+    }
+
+    /// Make the directory tree for this block, and return the block's path
+    fn make_block_dir(&self, index_block_hash: &StacksBlockId) -> Result<String, Error> {
+        let block_hash_bytes = index_block_hash.as_bytes();
+        let mut block_dir = PathBuf::from(&self.blocks_dir);
+        block_dir.push(to_hex(&block_hash_bytes[0..2]));
+        block_dir.push(to_hex(&block_hash_bytes[2..4]));
+        StacksChainState::mkdirs(&block_dir)?;
+        self.block_path(index_block_hash)
+    }
+}
+
+impl StacksBlockStore for FileBlockStore {
+    fn get_block_size(&self, index_block_hash: &StacksBlockId) -> Result<Option<u64>, Error> {
+        let block_path = self.block_path(index_block_hash)?;
+        match StacksChainState::get_file_size(&block_path) {
+            Ok(sz) => Ok(Some(sz)),
+            Err(Error::DBError(db_error::NotFoundError)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn load_block_bytes(&self, index_block_hash: &StacksBlockId) -> Result<Vec<u8>, Error> {
+        let block_path = self.block_path(index_block_hash)?;
+        let mut fd = fs::OpenOptions::new()
+            .read(true)
+            .write(false)
+            .open(&block_path)
+            .map_err(|e| {
+                if e.kind() == io::ErrorKind::NotFound {
+                    Error::DBError(db_error::NotFoundError)
+                } else {
+                    Error::DBError(db_error::IOError(e))
+                }
+            })?;
+
+        let mut ret = vec![];
+        fd.read_to_end(&mut ret)
+            .map_err(|e| Error::DBError(db_error::IOError(e)))?;
+        Ok(ret)
+    }
+
+    fn store_block_bytes(
+        &mut self,
+        index_block_hash: &StacksBlockId,
+        bytes: &[u8],
+    ) -> Result<(), Error> {
+        let block_path = self.make_block_dir(index_block_hash)?;
+        test_debug!("Store {} to {}", index_block_hash, &block_path);
+        StacksChainState::atomic_file_store(&block_path, true, |ref mut fd| {
+            fd.write_all(bytes)
+                .map_err(|e| Error::DBError(db_error::IOError(e)))
+        })
+    }
+
+    fn invalidate_block(&mut self, index_block_hash: &StacksBlockId) -> Result<(), Error> {
+        let block_path = self.make_block_dir(index_block_hash)?;
+        let sz = match StacksChainState::get_file_size(&block_path) {
+            Ok(sz) => sz,
+            Err(Error::DBError(db_error::NotFoundError)) => {
+                return StacksChainState::atomic_file_write(&block_path, &[]);
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        };
+        if sz == 0 {
+            // already freed
+            return Ok(());
+        }
+
+        // try make this thread-safe. It's okay if this block gets copied more than once; we
+        // only care that at least one copy survives for further analysis.
+        let random_bytes = thread_rng().gen::<[u8; 8]>();
+        let mut invalid_path = PathBuf::from(&block_path);
+        invalid_path.set_extension(&format!("invalid-{}", &to_hex(&random_bytes)));
+
+        fs::copy(&block_path, &invalid_path).map_err(|e| Error::DBError(db_error::IOError(e)))?;
+
+        // only truncate the original once the copy is known to be intact
+        let sz = fs::metadata(&invalid_path)
+            .map_err(|e| Error::DBError(db_error::IOError(e)))?
+            .len();
+
+        if sz > 0 {
+            fs::OpenOptions::new()
+                .read(false)
+                .write(true)
+                .truncate(true)
+                .open(&block_path)
+                .map_err(|e| Error::DBError(db_error::IOError(e)))?;
+        }
+        Ok(())
+    }
+
+    fn load_block_header(
+        &self,
+        index_block_hash: &StacksBlockId,
+    ) -> Result<Option<StacksBlockHeader>, Error> {
+        // only read as much of the file as we need
+        let block_path = self.block_path(index_block_hash)?;
+        let sz = StacksChainState::get_file_size(&block_path)?;
+        if sz == 0 {
+            debug!("Zero-sized block {}", &block_path);
+            return Ok(None);
+        }
+        let header: StacksBlockHeader = StacksChainState::consensus_load(&block_path)?;
+        Ok(Some(header))
+    }
+}
+
+/// Location of a block in a packed block store
+struct PackedBlockEntry {
+    offset: u64,
+    length: u64,
+    raw_length: u64,
+    compressed: bool,
+    invalid: bool,
+}
+
+/// The packed layout: an append-only data file with a SQLite index.
+///
+/// Blocks are only ever appended.  A block is written and synced to the data file before its
+/// index row is committed, and both happen while holding the index's write lock, so a crash
+/// can at worst leave unreferenced bytes at the end of the data file.  Replacing or pruning a
+/// block only updates its index row; the space it used in the data file is not reclaimed.
+pub struct PackedBlockStore {
+    data_path: PathBuf,
+    index: DBConn,
+    compress: bool,
+}
+
+impl PackedBlockStore {
+    fn index_path(store_dir: &Path) -> PathBuf {
+        store_dir.join(PACKED_BLOCK_INDEX)
+    }
+
+    fn data_path(store_dir: &Path) -> PathBuf {
+        store_dir.join(PACKED_BLOCK_DATA)
+    }
+
+    /// Does a packed block store exist in this directory?
+    pub fn exists(store_dir: &Path) -> bool {
+        PackedBlockStore::index_path(store_dir).exists()
+    }
+
+    /// Create a new, empty packed block store in `store_dir`.
+    pub fn create(store_dir: &Path, compress: bool) -> Result<PackedBlockStore, Error> {
+        if PackedBlockStore::exists(store_dir) {
+            error!(
+                "Packed block store already exists in {}",
+                store_dir.display()
+            );
+            return Err(Error::DBError(db_error::ExistsError));
+        }
+        StacksChainState::mkdirs(&store_dir.to_path_buf())?;
+        fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(PackedBlockStore::data_path(store_dir))
+            .map_err(|e| Error::DBError(db_error::IOError(e)))?;
+
+        let mut index = sqlite_open(
+            PackedBlockStore::index_path(store_dir),
+            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE,
+            false,
+        )
+        .map_err(|e| Error::DBError(db_error::SqliteError(e)))?;
+
+        let tx = tx_begin_immediate(&mut index)?;
+        for cmd in PACKED_BLOCK_STORE_SCHEMA {
+            tx.execute_batch(cmd)?;
+        }
+        let compression = if compress { "zstd" } else { "none" };
+        let args: &[&dyn ToSql] = &[&PACKED_BLOCK_STORE_VERSION, &compression];
+        tx.execute(
+            "INSERT INTO block_store_config (version, compression) VALUES (?1, ?2)",
+            args,
+        )?;
+        tx.commit()?;
+
+        // any store opened here before was deleted since
+        PackedBlockStore::forget(store_dir);
+        PackedBlockStore::open(store_dir)
+    }
+
+    /// Open an existing packed block store in `store_dir`.
+    pub fn open(store_dir: &Path) -> Result<PackedBlockStore, Error> {
+        let index = sqlite_open(
+            PackedBlockStore::index_path(store_dir),
+            OpenFlags::SQLITE_OPEN_READ_WRITE,
+            false,
+        )
+        .map_err(|e| Error::DBError(db_error::SqliteError(e)))?;
+
+        let (version, compression): (u32, String) = index.query_row(
+            "SELECT version, compression FROM block_store_config",
+            NO_PARAMS,
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        if version != PACKED_BLOCK_STORE_VERSION {
+            error!(
+                "Unsupported packed block store version {} in {}",
+                version,
+                store_dir.display()
+            );
+            return Err(Error::DBError(db_error::Corruption));
+        }
+        let compress = match compression.as_str() {
+            "zstd" => true,
+            "none" => false,
+            _ => {
+                error!(
+                    "Unsupported block compression '{}' in {}",
+                    &compression,
+                    store_dir.display()
+                );
+                return Err(Error::DBError(db_error::Corruption));
+            }
+        };
+
+        Ok(PackedBlockStore {
+            data_path: PackedBlockStore::data_path(store_dir),
+            index,
+            compress,
+        })
+    }
+
+    /// Get the shared handle to the packed block store in `store_dir`, opening it if need be.
+    fn open_shared(store_dir: &Path) -> Result<Arc<Mutex<PackedBlockStore>>, Error> {
+        let mut stores = PACKED_BLOCK_STORES
+            .lock()
+            .expect("FATAL: packed block store cache lock poisoned");
+        if let Some(store) = stores.get(store_dir) {
+            return Ok(store.clone());
+        }
+        let store = Arc::new(Mutex::new(PackedBlockStore::open(store_dir)?));
+        stores.insert(store_dir.to_path_buf(), store.clone());
+        Ok(store)
+    }
+
+    /// Drop the shared handle to the packed block store in `store_dir`, if there is one, so the
+    /// next access reopens whatever store is there now.
+    fn forget(store_dir: &Path) {
+        PACKED_BLOCK_STORES
+            .lock()
+            .expect("FATAL: packed block store cache lock poisoned")
+            .remove(store_dir);
+    }
+
+    /// Are newly-stored blocks compressed?
+    pub fn is_compressed(&self) -> bool {
+        self.compress
+    }
+
+    /// Size of the data file
+    pub fn get_data_size(&self) -> Result<u64, Error> {
+        StacksChainState::get_file_size(&self.data_path.to_string_lossy())
+    }
+
+    fn get_entry(
+        &self,
+        index_block_hash: &StacksBlockId,
+    ) -> Result<Option<PackedBlockEntry>, Error> {
+        let sql = "SELECT offset, length, raw_length, compressed, invalid FROM blocks WHERE index_block_hash = ?1";
+        let args: &[&dyn ToSql] = &[index_block_hash];
+        let entry = self
+            .index
+            .query_row(sql, args, |row| {
+                Ok(PackedBlockEntry {
+                    offset: row.get::<_, i64>(0)? as u64,
+                    length: row.get::<_, i64>(1)? as u64,
+                    raw_length: row.get::<_, i64>(2)? as u64,
+                    compressed: row.get(3)?,
+                    invalid: row.get(4)?,
+                })
+            })
+            .optional()?;
+        Ok(entry)
+    }
+}
+
+impl StacksBlockStore for PackedBlockStore {
+    fn get_block_size(&self, index_block_hash: &StacksBlockId) -> Result<Option<u64>, Error> {
+        Ok(self
+            .get_entry(index_block_hash)?
+            .map(|entry| if entry.invalid { 0 } else { entry.raw_length }))
+    }
+
+    fn load_block_bytes(&self, index_block_hash: &StacksBlockId) -> Result<Vec<u8>, Error> {
+        let entry = self
+            .get_entry(index_block_hash)?
+            .ok_or(Error::DBError(db_error::NotFoundError))?;
+        if entry.invalid || entry.raw_length == 0 {
+            return Ok(vec![]);
+        }
+        if entry.length > MAX_MESSAGE_LEN as u64 || entry.raw_length > MAX_MESSAGE_LEN as u64 {
+            error!(
+                "Corrupt packed block store entry for {}: {} bytes ({} raw)",
+                index_block_hash, entry.length, entry.raw_length
+            );
+            return Err(Error::DBError(db_error::Corruption));
+        }
+
+        let mut fd =
+            fs::File::open(&self.data_path).map_err(|e| Error::DBError(db_error::IOError(e)))?;
+        fd.seek(SeekFrom::Start(entry.offset))
+            .map_err(|e| Error::DBError(db_error::IOError(e)))?;
+        let mut data = vec![0u8; entry.length as usize];
+        fd.read_exact(&mut data)
+            .map_err(|e| Error::DBError(db_error::IOError(e)))?;
+
+        if !entry.compressed {
+            return Ok(data);
+        }
+        let raw = zstd::bulk::decompress(&data, entry.raw_length as usize).map_err(|e| {
+            error!("Failed to decompress block {}: {:?}", index_block_hash, &e);
+            Error::DBError(db_error::Corruption)
+        })?;
+        if raw.len() as u64 != entry.raw_length {
+            error!(
+                "Decompressed block {} has {} bytes, expected {}",
+                index_block_hash,
+                raw.len(),
+                entry.raw_length
+            );
+            return Err(Error::DBError(db_error::Corruption));
+        }
+        Ok(raw)
+    }
+
+    fn store_block_bytes(
+        &mut self,
+        index_block_hash: &StacksBlockId,
+        bytes: &[u8],
+    ) -> Result<(), Error> {
+        // only keep the compressed form if it's actually smaller
+        let compressed = if self.compress && !bytes.is_empty() {
+            zstd::bulk::compress(bytes, PACKED_BLOCK_COMPRESSION_LEVEL)
+                .map_err(|e| Error::DBError(db_error::IOError(e)))
+                .map(|data| {
+                    if data.len() < bytes.len() {
+                        Some(data)
+                    } else {
+                        None
+                    }
+                })?
+        } else {
+            None
+        };
+        let data = compressed.as_deref().unwrap_or(bytes);
+
+        // the write lock on the index serializes appends to the data file
+        let tx = tx_begin_immediate(&mut self.index)?;
+
+        let mut fd = fs::OpenOptions::new()
+            .append(true)
+            .open(&self.data_path)
+            .map_err(|e| Error::DBError(db_error::IOError(e)))?;
+        let offset = fd
+            .seek(SeekFrom::End(0))
+            .map_err(|e| Error::DBError(db_error::IOError(e)))?;
+        fd.write_all(data)
+            .map_err(|e| Error::DBError(db_error::IOError(e)))?;
+        fd.sync_data()
+            .map_err(|e| Error::DBError(db_error::IOError(e)))?;
+
+        let sql = "INSERT OR REPLACE INTO blocks (index_block_hash, offset, length, raw_length, compressed, invalid) VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
+        let args: &[&dyn ToSql] = &[
+            index_block_hash,
+            &u64_to_sql(offset)?,
+            &u64_to_sql(data.len() as u64)?,
+            &u64_to_sql(bytes.len() as u64)?,
+            &compressed.is_some(),
+            &false,
+        ];
+        tx.execute(sql, args)?;
+        tx.commit()?;
+
+        test_debug!(
+            "Store {} ({} bytes, {} on disk) at offset {}",
+            index_block_hash,
+            bytes.len(),
+            data.len(),
+            offset
+        );
+        Ok(())
+    }
+
+    fn invalidate_block(&mut self, index_block_hash: &StacksBlockId) -> Result<(), Error> {
+        let args: &[&dyn ToSql] = &[index_block_hash];
+        let updated = self.index.execute(
+            "UPDATE blocks SET invalid = 1 WHERE index_block_hash = ?1",
+            args,
+        )?;
+        if updated == 0 {
+            self.store_block_bytes(index_block_hash, &[])?;
+        }
+        Ok(())
+    }
+}
+
+/// A packed block store shared by every caller that opens the same directory
+struct SharedPackedBlockStore(Arc<Mutex<PackedBlockStore>>);
+
+impl SharedPackedBlockStore {
+    fn lock(&self) -> MutexGuard<PackedBlockStore> {
+        self.0
+            .lock()
+            .expect("FATAL: packed block store lock poisoned")
+    }
+}
+
+impl StacksBlockStore for SharedPackedBlockStore {
+    fn get_block_size(&self, index_block_hash: &StacksBlockId) -> Result<Option<u64>, Error> {
+        self.lock().get_block_size(index_block_hash)
+    }
+
+    fn load_block_bytes(&self, index_block_hash: &StacksBlockId) -> Result<Vec<u8>, Error> {
+        self.lock().load_block_bytes(index_block_hash)
+    }
+
+    fn store_block_bytes(
+        &mut self,
+        index_block_hash: &StacksBlockId,
+        bytes: &[u8],
+    ) -> Result<(), Error> {
+        self.lock().store_block_bytes(index_block_hash, bytes)
+    }
+
+    fn invalidate_block(&mut self, index_block_hash: &StacksBlockId) -> Result<(), Error> {
+        self.lock().invalidate_block(index_block_hash)
+    }
+}
+
+/// Outcome of migrating a chainstate's blocks to the packed layout
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BlockStoreMigrationStats {
+    /// Number of block files moved into the packed store
+    pub blocks_migrated: u64,
+    /// How many of them were empty (i.e. invalid or pruned blocks)
+    pub empty_blocks_migrated: u64,
+    /// Total size of the migrated block files
+    pub bytes_before: u64,
+    /// Size of the packed store's data file
+    pub bytes_after: u64,
+}
+
+impl StacksChainState {
+    /// Open the block store in the given blocks directory, in whichever layout it uses.
+    /// A packed block store is only opened once per process; later calls share it.
+    pub fn open_block_store(blocks_dir: &str) -> Result<Box<dyn StacksBlockStore>, Error> {
+        let packed_dir = Path::new(blocks_dir).join(PACKED_BLOCK_STORE_DIR);
+        if PackedBlockStore::exists(&packed_dir) {
+            Ok(Box::new(SharedPackedBlockStore(
+                PackedBlockStore::open_shared(&packed_dir)?,
+            )))
+        } else {
+            Ok(Box::new(FileBlockStore::new(blocks_dir)))
+        }
+    }
+
+    /// Does this blocks directory use the packed layout?
+    pub fn has_packed_block_store(blocks_dir: &str) -> bool {
+        PackedBlockStore::exists(&Path::new(blocks_dir).join(PACKED_BLOCK_STORE_DIR))
+    }
+
+    /// Does this blocks directory's packed block store compress new blocks?
+    /// Returns Ok(None) if it uses the file layout.
+    pub fn is_packed_block_store_compressed(blocks_dir: &str) -> Result<Option<bool>, Error> {
+        let packed_dir = Path::new(blocks_dir).join(PACKED_BLOCK_STORE_DIR);
+        if !PackedBlockStore::exists(&packed_dir) {
+            return Ok(None);
+        }
+        let store = PackedBlockStore::open_shared(&packed_dir)?;
+        let compress = store
+            .lock()
+            .expect("FATAL: packed block store lock poisoned")
+            .is_compressed();
+        Ok(Some(compress))
+    }
+
+    /// Switch a blocks directory with no blocks in it to the packed layout.
+    /// Does nothing if it already uses the packed layout.  Fails if it already holds blocks in
+    /// the file layout; those must be moved with `migrate_block_store()` instead.
+    pub fn create_packed_block_store(blocks_dir: &str, compress: bool) -> Result<(), Error> {
+        if StacksChainState::has_packed_block_store(blocks_dir) {
+            return Ok(());
+        }
+        if !StacksChainState::list_block_files(blocks_dir)?.is_empty() {
+            error!(
+                "Blocks directory {} already has blocks stored as files; migrate it first",
+                blocks_dir
+            );
+            return Err(Error::DBError(db_error::ExistsError));
+        }
+        let packed_dir = Path::new(blocks_dir).join(PACKED_BLOCK_STORE_DIR);
+        PackedBlockStore::create(&packed_dir, compress)?;
+        Ok(())
+    }
+
+    /// Find the block files in a file-layout blocks directory, skipping temporary files and
+    /// copies of invalid blocks.
+    fn list_block_files(blocks_dir: &str) -> Result<Vec<(StacksBlockId, PathBuf)>, Error> {
+        // only look at the xx/yy/ directories
+        fn list_hex_dirs(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+            let mut dirs = vec![];
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    return Ok(dirs);
+                }
+                Err(e) => {
+                    return Err(Error::DBError(db_error::IOError(e)));
+                }
+            };
+            for entry in entries {
+                let entry = entry.map_err(|e| Error::DBError(db_error::IOError(e)))?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                if name.len() == 4
+                    && name.chars().all(|c| c.is_ascii_hexdigit())
+                    && entry.path().is_dir()
+                {
+                    dirs.push(entry.path());
+                }
+            }
+            Ok(dirs)
+        }
+
+        let mut block_files = vec![];
+        for dir in list_hex_dirs(Path::new(blocks_dir))? {
+            for subdir in list_hex_dirs(&dir)? {
+                for entry in
+                    fs::read_dir(&subdir).map_err(|e| Error::DBError(db_error::IOError(e)))?
+                {
+                    let entry = entry.map_err(|e| Error::DBError(db_error::IOError(e)))?;
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    // `.tmp` and `.invalid-*` files have extensions, so they don't parse
+                    if let Ok(index_block_hash) = StacksBlockId::from_hex(&name) {
+                        block_files.push((index_block_hash, entry.path()));
+                    }
+                }
+            }
+        }
+        Ok(block_files)
+    }
+
+    /// Move all blocks stored in the file layout into a new packed block store.
+    ///
+    /// The packed store is built in `blocks/packed.migrating/` and then renamed into place, so
+    /// the node sees either the old layout or the complete new one.  Only then are the old
+    /// block files deleted.  Copies of invalid blocks (`.invalid-*` files) are left alone.  If
+    /// the migration is interrupted, it can simply be run again.
+    pub fn migrate_block_store(
+        blocks_dir: &str,
+        compress: bool,
+    ) -> Result<BlockStoreMigrationStats, Error> {
+        let packed_dir = Path::new(blocks_dir).join(PACKED_BLOCK_STORE_DIR);
+        let migrating_dir = Path::new(blocks_dir).join(PACKED_BLOCK_STORE_MIGRATING_DIR);
+        let block_files = StacksChainState::list_block_files(blocks_dir)?;
+        let mut stats = BlockStoreMigrationStats::default();
+
+        if !PackedBlockStore::exists(&packed_dir) {
+            if migrating_dir.exists() {
+                info!(
+                    "Discarding incomplete block store migration in {}",
+                    migrating_dir.display()
+                );
+                fs::remove_dir_all(&migrating_dir)
+                    .map_err(|e| Error::DBError(db_error::IOError(e)))?;
+            }
+
+            let mut store = PackedBlockStore::create(&migrating_dir, compress)?;
+            for (i, (index_block_hash, path)) in block_files.iter().enumerate() {
+                let bytes = fs::read(path).map_err(|e| Error::DBError(db_error::IOError(e)))?;
+                store.store_block_bytes(index_block_hash, &bytes)?;
+
+                stats.blocks_migrated += 1;
+                stats.bytes_before += bytes.len() as u64;
+                if bytes.is_empty() {
+                    stats.empty_blocks_migrated += 1;
+                }
+                if (i + 1) % 10_000 == 0 {
+                    info!("Migrated {} of {} blocks", i + 1, block_files.len());
+                }
+            }
+            stats.bytes_after = store.get_data_size()?;
+            drop(store);
+
+            fs::rename(&migrating_dir, &packed_dir)
+                .map_err(|e| Error::DBError(db_error::IOError(e)))?;
+            PackedBlockStore::forget(&packed_dir);
+        } else if !block_files.is_empty() {
+            // finish an interrupted migration.  These files were all migrated before the
+            // rename, since nothing writes block files once the packed store exists.
+            info!(
+                "Finishing block store migration: {} block files left to remove",
+                block_files.len()
+            );
+        }
+
+        for (_, path) in block_files.iter() {
+            fs::remove_file(path).map_err(|e| Error::DBError(db_error::IOError(e)))?;
+            // clean up the xx/yy and xx directories once empty.  remove_dir() fails on
+            // non-empty directories, which is what we want.
+            if let Some(subdir) = path.parent() {
+                if fs::remove_dir(subdir).is_ok() {
+                    if let Some(dir) = subdir.parent() {
+                        let _ = fs::remove_dir(dir);
+                    }
+                }
+            }
+        }
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_blocks_dir(name: &str) -> String {
+        let path = format!("/tmp/stacks-node-tests/block-store/{}", name);
+        if fs::metadata(&path).is_ok() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn block_id(i: u8) -> StacksBlockId {
+        StacksBlockId([i; 32])
+    }
+
+    fn block_bytes(i: u8) -> Vec<u8> {
+        // compressible
+        (0..1000u32).map(|j| ((j / 10) as u8) ^ i).collect()
+    }
+
+    fn check_store(store: &mut dyn StacksBlockStore) {
+        assert_eq!(store.get_block_size(&block_id(1)).unwrap(), None);
+        match store.load_block_bytes(&block_id(1)) {
+            Err(Error::DBError(db_error::NotFoundError)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        for i in 1..=3 {
+            store
+                .store_block_bytes(&block_id(i), &block_bytes(i))
+                .unwrap();
+        }
+        for i in 1..=3 {
+            assert_eq!(
+                store.get_block_size(&block_id(i)).unwrap(),
+                Some(block_bytes(i).len() as u64)
+            );
+            assert_eq!(
+                store.load_block_bytes(&block_id(i)).unwrap(),
+                block_bytes(i)
+            );
+        }
+
+        // invalidating a stored block empties it
+        store.invalidate_block(&block_id(2)).unwrap();
+        assert_eq!(store.get_block_size(&block_id(2)).unwrap(), Some(0));
+        assert!(store.load_block_bytes(&block_id(2)).unwrap().is_empty());
+        assert!(store.load_block_header(&block_id(2)).unwrap().is_none());
+
+        // invalidating an absent block records it
+        store.invalidate_block(&block_id(4)).unwrap();
+        assert_eq!(store.get_block_size(&block_id(4)).unwrap(), Some(0));
+
+        // pruning replaces the block with an empty one
+        store.store_block_bytes(&block_id(3), &[]).unwrap();
+        assert_eq!(store.get_block_size(&block_id(3)).unwrap(), Some(0));
+
+        // untouched
+        assert_eq!(
+            store.load_block_bytes(&block_id(1)).unwrap(),
+            block_bytes(1)
+        );
+    }
+
+    #[test]
+    fn test_file_block_store() {
+        let blocks_dir = temp_blocks_dir("test_file_block_store");
+        check_store(&mut FileBlockStore::new(&blocks_dir));
+
+        // a copy of the invalidated block was kept
+        let block_path = StacksChainState::get_index_block_path(&blocks_dir, &block_id(2)).unwrap();
+        let parent = Path::new(&block_path).parent().unwrap();
+        let copies: Vec<_> = fs::read_dir(parent)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| name.contains(".invalid-"))
+            .collect();
+        assert_eq!(copies.len(), 1);
+    }
+
+    #[test]
+    fn test_packed_block_store() {
+        for compress in [false, true] {
+            let blocks_dir = temp_blocks_dir(&format!("test_packed_block_store_{}", compress));
+            let packed_dir = Path::new(&blocks_dir).join(PACKED_BLOCK_STORE_DIR);
+            let mut store = PackedBlockStore::create(&packed_dir, compress).unwrap();
+            assert!(PackedBlockStore::create(&packed_dir, compress).is_err());
+            check_store(&mut store);
+
+            let data_size = store.get_data_size().unwrap();
+            let raw_size: u64 = (1..=3).map(|i| block_bytes(i).len() as u64).sum();
+            if compress {
+                assert!(data_size < raw_size);
+            } else {
+                assert_eq!(data_size, raw_size);
+            }
+
+            // reopens through the chainstate with the same contents
+            let store = StacksChainState::open_block_store(&blocks_dir).unwrap();
+            assert_eq!(
+                store.load_block_bytes(&block_id(1)).unwrap(),
+                block_bytes(1)
+            );
+            assert_eq!(store.get_block_size(&block_id(2)).unwrap(), Some(0));
+        }
+    }
+
+    #[test]
+    fn test_packed_block_store_ignores_torn_tail() {
+        let blocks_dir = temp_blocks_dir("test_packed_block_store_ignores_torn_tail");
+        let packed_dir = Path::new(&blocks_dir).join(PACKED_BLOCK_STORE_DIR);
+        let mut store = PackedBlockStore::create(&packed_dir, false).unwrap();
+        store
+            .store_block_bytes(&block_id(1), &block_bytes(1))
+            .unwrap();
+
+        // simulate a crash part-way through appending a block
+        let mut fd = fs::OpenOptions::new()
+            .append(true)
+            .open(packed_dir.join(PACKED_BLOCK_DATA))
+            .unwrap();
+        fd.write_all(&block_bytes(2)[0..100]).unwrap();
+        drop(fd);
+
+        let mut store = PackedBlockStore::open(&packed_dir).unwrap();
+        assert_eq!(store.get_block_size(&block_id(2)).unwrap(), None);
+        store
+            .store_block_bytes(&block_id(2), &block_bytes(2))
+            .unwrap();
+        assert_eq!(
+            store.load_block_bytes(&block_id(1)).unwrap(),
+            block_bytes(1)
+        );
+        assert_eq!(
+            store.load_block_bytes(&block_id(2)).unwrap(),
+            block_bytes(2)
+        );
+    }
+
+    #[test]
+    fn test_migrate_block_store() {
+        let blocks_dir = temp_blocks_dir("test_migrate_block_store");
+        let mut file_store = FileBlockStore::new(&blocks_dir);
+        for i in 1..=5 {
+            file_store
+                .store_block_bytes(&block_id(i), &block_bytes(i))
+                .unwrap();
+        }
+        file_store.invalidate_block(&block_id(5)).unwrap();
+
+        // can't start a fresh packed store on top of existing block files
+        assert!(StacksChainState::create_packed_block_store(&blocks_dir, true).is_err());
+
+        // leftovers of an earlier, interrupted migration are discarded
+        let migrating_dir = Path::new(&blocks_dir).join(PACKED_BLOCK_STORE_MIGRATING_DIR);
+        PackedBlockStore::create(&migrating_dir, true).unwrap();
+
+        let stats = StacksChainState::migrate_block_store(&blocks_dir, true).unwrap();
+        assert_eq!(stats.blocks_migrated, 5);
+        assert_eq!(stats.empty_blocks_migrated, 1);
+        assert!(stats.bytes_after < stats.bytes_before);
+        assert!(!migrating_dir.exists());
+        assert!(StacksChainState::has_packed_block_store(&blocks_dir));
+
+        let store = StacksChainState::open_block_store(&blocks_dir).unwrap();
+        for i in 1..=4 {
+            assert_eq!(
+                store.load_block_bytes(&block_id(i)).unwrap(),
+                block_bytes(i)
+            );
+        }
+        assert_eq!(store.get_block_size(&block_id(5)).unwrap(), Some(0));
+
+        // block files are gone, but the copy of the invalid block is kept
+        assert!(StacksChainState::list_block_files(&blocks_dir)
+            .unwrap()
+            .is_empty());
+        let block_path = StacksChainState::get_index_block_path(&blocks_dir, &block_id(5)).unwrap();
+        let parent = Path::new(&block_path).parent().unwrap();
+        assert_eq!(fs::read_dir(parent).unwrap().count(), 1);
+        let block_path = StacksChainState::get_index_block_path(&blocks_dir, &block_id(1)).unwrap();
+        assert!(!Path::new(&block_path).parent().unwrap().exists());
+
+        // running it again finishes an interrupted cleanup, and is otherwise a no-op
+        let stray_path = PathBuf::from(
+            StacksChainState::get_index_block_path(&blocks_dir, &block_id(1)).unwrap(),
+        );
+        fs::create_dir_all(stray_path.parent().unwrap()).unwrap();
+        fs::write(&stray_path, block_bytes(1)).unwrap();
+        let stats = StacksChainState::migrate_block_store(&blocks_dir, true).unwrap();
+        assert_eq!(stats, BlockStoreMigrationStats::default());
+        assert!(!stray_path.exists());
+
+        let store = StacksChainState::open_block_store(&blocks_dir).unwrap();
+        assert_eq!(
+            store.load_block_bytes(&block_id(1)).unwrap(),
+            block_bytes(1)
+        );
+
+        // creating a packed store is a no-op once one exists
+        StacksChainState::create_packed_block_store(&blocks_dir, false).unwrap();
+    }
+
+    #[test]
+    fn test_create_packed_block_store() {
+        let blocks_dir = temp_blocks_dir("test_create_packed_block_store");
+        assert!(!StacksChainState::has_packed_block_store(&blocks_dir));
+        StacksChainState::create_packed_block_store(&blocks_dir, true).unwrap();
+        assert!(StacksChainState::has_packed_block_store(&blocks_dir));
+
+        let mut store = StacksChainState::open_block_store(&blocks_dir).unwrap();
+        store
+            .store_block_bytes(&block_id(1), &block_bytes(1))
+            .unwrap();
+        assert!(StacksChainState::list_block_files(&blocks_dir)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            store.load_block_bytes(&block_id(1)).unwrap(),
+            block_bytes(1)
+        );
+
+        // other handles share the store
+        let other_store = StacksChainState::open_block_store(&blocks_dir).unwrap();
+        assert_eq!(
+            other_store.get_block_size(&block_id(1)).unwrap(),
+            Some(block_bytes(1).len() as u64)
+        );
+
+        // a store created in place of a deleted one starts out empty
+        drop(store);
+        drop(other_store);
+        let blocks_dir = temp_blocks_dir("test_create_packed_block_store");
+        StacksChainState::create_packed_block_store(&blocks_dir, true).unwrap();
+        let store = StacksChainState::open_block_store(&blocks_dir).unwrap();
+        assert_eq!(store.get_block_size(&block_id(1)).unwrap(), None);
+    }
+}
@@ -83,8 +83,10 @@ use crate::util_lib::db::{
 
 pub mod accounts;
 pub mod blocks;
+pub mod blockstore;
 pub mod contracts;
 pub mod headers;
+pub mod prune;
 pub mod transactions;
 pub mod unconfirmed;
 
@@ -562,7 +564,7 @@ impl<'a> DerefMut for ChainstateTx<'a> {
     }
 }
 
-pub const CHAINSTATE_VERSION: &'static str = "3";
+pub const CHAINSTATE_VERSION: &'static str = "4";
 
 const CHAINSTATE_INITIAL_SCHEMA: &'static [&'static str] = &[
     "PRAGMA foreign_keys = ON;",
@@ -767,6 +769,19 @@ const CHAINSTATE_SCHEMA_3: &'static [&'static str] = &[
     "#,
 ];
 
+const CHAINSTATE_SCHEMA_4: &'static [&'static str] = &[
+    // track how much historical state a pruned node has discarded
+    r#"
+    -- At most one row.  Clarity state and block data of Stacks blocks below this height have been
+    -- pruned.  There is no row if the node has never pruned.
+    CREATE TABLE pruned_state(
+        pruned_below_height INTEGER NOT NULL
+    );"#,
+    r#"
+    UPDATE db_config SET version = "4";
+    "#,
+];
+
 const CHAINSTATE_INDEXES: &'static [&'static str] = &[
     "CREATE INDEX IF NOT EXISTS index_block_hash_to_primary_key ON block_headers(index_block_hash,consensus_hash,block_hash);",
     "CREATE INDEX IF NOT EXISTS block_headers_hash_index ON block_headers(block_hash,block_height);",
@@ -979,6 +994,13 @@ impl StacksChainState {
                         }
                     }
                     "3" => {
+                        // migrate to 4
+                        info!("Migrating chainstate schema from version 3 to 4");
+                        for cmd in CHAINSTATE_SCHEMA_4.iter() {
+                            tx.execute_batch(cmd)?;
+                        }
+                    }
+                    "4" => {
                         // done
                         break;
                     }
@@ -1923,6 +1945,14 @@ impl StacksChainState {
         self.clarity_state.with_marf(f)
     }
 
+    /// Is the Clarity state MARF maintaining the (non-consensus) data map key index?
+    pub fn indexes_data_map_keys(&self) -> bool {
+        self.marf_opts
+            .as_ref()
+            .map(|opts| opts.index_data_map_keys)
+            .unwrap_or(false)
+    }
+
     /// Run to_do on the state of the Clarity VM at the given chain tip.
     /// Returns Some(x: R) if the given parent_tip exists.
     /// Returns None if not
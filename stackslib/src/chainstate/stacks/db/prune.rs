@@ -0,0 +1,587 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Pruning of historical chainstate.
+//!
+//! A pruned node only keeps the Clarity state and block data of Stacks blocks at or above a
+//! given height (the "prune height").  Every block at or above this height -- on every fork --
+//! is kept, so the node can still process any reorg that does not go below the prune height.
+//! Headers, receipts, and the block index are never pruned.
+//!
+//! A pruned node cannot validate a block with a transaction that uses `at-block` to read pruned
+//! state.  Such a block is neither accepted nor marked invalid, and since none of its descendants
+//! can be processed either, the chains coordinator halts the node rather than let it fall behind
+//! the chain unnoticed.  The node must then be re-synced without pruning.  A pruned miner does not
+//! mine such transactions, since it cannot evaluate them.
+
+use std::collections::HashSet;
+
+use clarity::vm::errors::{Error as clarity_interpreter_error, InterpreterError};
+use rusqlite::types::ToSql;
+use rusqlite::OptionalExtension;
+use stacks_common::types::chainstate::StacksBlockId;
+
+use crate::burnchains::Burnchain;
+use crate::chainstate::burn::db::sortdb::SortitionDB;
+use crate::chainstate::stacks::db::*;
+use crate::chainstate::stacks::index::storage::TriePruneStats;
+use crate::chainstate::stacks::index::Error as marf_error;
+use crate::chainstate::stacks::{Error, *};
+use crate::clarity_vm::clarity::Error as clarity_error;
+use crate::util_lib::db::{query_row_columns, u64_to_sql};
+
+/// Outcome of pruning the chainstate
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainstatePruneStats {
+    /// State and block data of Stacks blocks below this height are gone
+    pub pruned_below_height: u64,
+    /// What happened to the Clarity MARF
+    pub tries: TriePruneStats,
+    /// Number of anchored blocks whose data was discarded
+    pub blocks_pruned: u64,
+    /// Number of microblocks whose data was deleted
+    pub microblocks_pruned: u64,
+}
+
+/// Did this error come from reading Clarity state that this node has pruned?
+pub fn is_pruned_state_error(e: &Error) -> bool {
+    match e {
+        Error::PrunedStateError(_) | Error::MARFError(marf_error::PrunedError) => true,
+        Error::ClarityError(clarity_error::Interpreter(
+            clarity_interpreter_error::Interpreter(InterpreterError::PrunedState(_)),
+        )) => true,
+        _ => false,
+    }
+}
+
+impl StacksChainState {
+    /// Get the height below which this node has pruned its state, if it has ever pruned.
+    pub fn get_pruned_below_height(conn: &DBConn) -> Result<Option<u64>, Error> {
+        let sql = "SELECT MAX(pruned_below_height) FROM pruned_state";
+        let height: Option<i64> = conn
+            .query_row(sql, rusqlite::NO_PARAMS, |row| row.get(0))
+            .optional()?
+            .flatten();
+        Ok(height.map(|h| h as u64))
+    }
+
+    /// Has the Clarity state (and block data) of the given block been pruned?
+    /// Unknown blocks are not considered pruned.
+    pub fn is_state_pruned(&self, index_block_hash: &StacksBlockId) -> Result<bool, Error> {
+        let Some(pruned_below_height) = StacksChainState::get_pruned_below_height(self.db())?
+        else {
+            return Ok(false);
+        };
+        let header = StacksChainState::get_stacks_block_header_info_by_index_block_hash(
+            self.db(),
+            index_block_hash,
+        )?;
+        Ok(header
+            .map(|hdr| hdr.stacks_block_height < pruned_below_height)
+            .unwrap_or(false))
+    }
+
+    /// Find the height below which state can be pruned, such that the state of every Stacks block
+    /// mined in the last `reward_cycles` reward cycles is kept, and such that a reorg of up to
+    /// `max_reorg_depth` blocks from the canonical Stacks tip can still be processed.
+    /// Returns None if there is nothing (new) to prune.
+    pub fn get_prune_height(
+        &self,
+        sortdb: &SortitionDB,
+        burnchain: &Burnchain,
+        reward_cycles: u64,
+        max_reorg_depth: u64,
+    ) -> Result<Option<u64>, Error> {
+        if reward_cycles == 0 {
+            // nothing would be left to build on
+            return Ok(None);
+        }
+        let burn_tip = SortitionDB::get_canonical_burn_chain_tip(sortdb.conn())?;
+        let Some(cur_reward_cycle) = burnchain.block_height_to_reward_cycle(burn_tip.block_height)
+        else {
+            return Ok(None);
+        };
+        if cur_reward_cycle + 1 < reward_cycles {
+            return Ok(None);
+        }
+        let oldest_reward_cycle = cur_reward_cycle + 1 - reward_cycles;
+        // the oldest kept reward cycle's reward set is read from the state at its anchor block,
+        // which is mined in the prepare phase before the cycle starts
+        let start_burn_height = burnchain
+            .reward_cycle_to_block_height(oldest_reward_cycle)
+            .saturating_sub(burnchain.pox_constants.prepare_length.into());
+
+        let sql = "SELECT MIN(block_height) FROM block_headers WHERE burn_header_height >= ?1";
+        let args: &[&dyn ToSql] = &[&u64_to_sql(start_burn_height)?];
+        let Some(cycle_height) = self
+            .db()
+            .query_row(sql, args, |row| row.get::<_, Option<i64>>(0))
+            .optional()?
+            .flatten()
+            .map(|h| h as u64)
+        else {
+            return Ok(None);
+        };
+
+        let Some(stacks_tip) = self.get_stacks_chain_tip(sortdb)? else {
+            return Ok(None);
+        };
+        let reorg_height = stacks_tip.height.saturating_sub(max_reorg_depth);
+
+        let prune_height = cycle_height.min(reorg_height);
+        let pruned_below_height =
+            StacksChainState::get_pruned_below_height(self.db())?.unwrap_or(0);
+        if prune_height <= pruned_below_height {
+            return Ok(None);
+        }
+        Ok(Some(prune_height))
+    }
+
+    /// Get the index block hashes of all known Stacks blocks at or above `height`, on every fork
+    fn get_block_ids_at_or_above(&self, height: u64) -> Result<HashSet<StacksBlockId>, Error> {
+        let sql = "SELECT index_block_hash FROM block_headers WHERE block_height >= ?1";
+        let args: &[&dyn ToSql] = &[&u64_to_sql(height)?];
+        Ok(
+            query_row_columns::<StacksBlockId, _>(self.db(), sql, args, "index_block_hash")?
+                .into_iter()
+                .collect(),
+        )
+    }
+
+    /// Discard the Clarity state and the block data of every Stacks block below `height`.
+    /// If `compact` is set, the Clarity MARF's blobs file is compacted, so no other handle to it
+    /// may be open while this runs.  Otherwise, the pruned tries keep taking up space until the
+    /// next compaction.
+    /// Once this returns, the state of a pruned block can never be read again.
+    pub fn prune_below_height(
+        &mut self,
+        height: u64,
+        compact: bool,
+    ) -> Result<ChainstatePruneStats, Error> {
+        // record the prune height first, so an interrupted prune is never mistaken for a
+        // complete chainstate
+        {
+            let tx = self.db_tx_begin()?;
+            tx.execute("DELETE FROM pruned_state", rusqlite::NO_PARAMS)?;
+            tx.execute(
+                "INSERT INTO pruned_state (pruned_below_height) VALUES (?1)",
+                &[&u64_to_sql(height)?],
+            )?;
+            tx.commit()?;
+        }
+
+        let retain = self.get_block_ids_at_or_above(height)?;
+        let tries = self.with_clarity_marf(|marf| marf.prune(&retain, compact))?;
+
+        let args: &[&dyn ToSql] = &[&u64_to_sql(height)?];
+        let sql = "SELECT consensus_hash, anchored_block_hash FROM staging_blocks WHERE processed = 1 AND height < ?1";
+        let pruned_blocks: Vec<(ConsensusHash, BlockHeaderHash)> = {
+            let mut stmt = self.db().prepare(sql)?;
+            let mut rows = stmt.query(args)?;
+            let mut blocks = vec![];
+            while let Some(row) = rows.next()? {
+                blocks.push((row.get_unwrap(0), row.get_unwrap(1)));
+            }
+            blocks
+        };
+
+        let mut blocks_pruned = 0;
+        let mut block_store = StacksChainState::open_block_store(&self.blocks_path)?;
+        for (consensus_hash, block_hash) in pruned_blocks.iter() {
+            let index_block_hash = StacksBlockId::new(consensus_hash, block_hash);
+            match block_store.get_block_size(&index_block_hash)? {
+                Some(0) | None => {}
+                Some(_) => {
+                    block_store.store_block_bytes(&index_block_hash, &[])?;
+                    blocks_pruned += 1;
+                }
+            }
+        }
+
+        // a microblock stream is only needed to process the child of the block that produced it
+        let microblocks_pruned = {
+            let tx = self.db_tx_begin()?;
+            let sql = "DELETE FROM staging_microblocks_data WHERE block_hash IN \
+                       (SELECT staging_microblocks.microblock_hash FROM staging_microblocks JOIN staging_blocks \
+                       ON staging_microblocks.index_block_hash = staging_blocks.index_block_hash \
+                       WHERE staging_microblocks.processed = 1 AND staging_blocks.height + 1 < ?1)";
+            let num_deleted = tx.execute(sql, args)?;
+            tx.commit()?;
+            num_deleted as u64
+        };
+
+        let stats = ChainstatePruneStats {
+            pruned_below_height: height,
+            tries,
+            blocks_pruned,
+            microblocks_pruned,
+        };
+        info!("Pruned chainstate below Stacks height {}", height;
+              "tries_pruned" => stats.tries.tries_pruned,
+              "tries_kept" => stats.tries.tries_kept,
+              "trie_nodes_kept" => stats.tries.nodes_kept,
+              "blocks_pruned" => stats.blocks_pruned,
+              "microblocks_pruned" => stats.microblocks_pruned);
+        Ok(stats)
+    }
+
+    /// Compact the Clarity MARF's blobs file, reclaiming the space taken up by the tries that
+    /// were pruned without compacting it.  No other handle to the Clarity MARF may be open while
+    /// this runs.
+    /// Returns None if this chainstate has never been pruned.
+    pub fn compact_pruned_state(&mut self) -> Result<Option<TriePruneStats>, Error> {
+        let Some(height) = StacksChainState::get_pruned_below_height(self.db())? else {
+            return Ok(None);
+        };
+        let retain = self.get_block_ids_at_or_above(height)?;
+        let tries = self.with_clarity_marf(|marf| marf.prune(&retain, true))?;
+        Ok(Some(tries))
+    }
+
+    /// Prune everything but the last `reward_cycles` reward cycles of state, without breaking
+    /// the node's ability to process a reorg of up to `max_reorg_depth` blocks.  See
+    /// `prune_below_height()` for what `compact` does.
+    /// Returns None if there was nothing to prune.
+    pub fn prune_historical_state(
+        &mut self,
+        sortdb: &SortitionDB,
+        burnchain: &Burnchain,
+        reward_cycles: u64,
+        max_reorg_depth: u64,
+        compact: bool,
+    ) -> Result<Option<ChainstatePruneStats>, Error> {
+        let Some(height) =
+            self.get_prune_height(sortdb, burnchain, reward_cycles, max_reorg_depth)?
+        else {
+            debug!("No chainstate to prune");
+            return Ok(None);
+        };
+        self.prune_below_height(height, compact).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clarity::vm::types::{PrincipalData, StacksAddressExtensions};
+    use stacks_common::util::hash::Hash160;
+    use stacks_common::util::vrf::VRFProof;
+
+    use super::*;
+    use crate::chainstate::coordinator::Error as CoordinatorError;
+    use crate::chainstate::stacks::boot::test::{get_parent_tip, key_to_stacks_addr};
+    use crate::chainstate::stacks::db::test::instantiate_chainstate;
+    use crate::chainstate::stacks::tests::{
+        make_coinbase, make_user_coinbase, make_user_contract_publish, make_user_stacks_transfer,
+    };
+    use crate::net::relay::Relayer;
+    use crate::net::test::{TestPeer, TestPeerConfig};
+
+    /// Have `miner` mine a tenure with `txs` and process it, and relay it to `follower`, which
+    /// stores the new Stacks block but does not process it yet.
+    fn mine_and_stage(
+        miner: &mut TestPeer,
+        follower: &mut TestPeer,
+        txs: &[StacksTransaction],
+    ) -> (ConsensusHash, StacksBlock) {
+        let microblock_pubkeyhash =
+            Hash160::from_node_public_key(&StacksPublicKey::from_private(&StacksPrivateKey::new()));
+        let tip = SortitionDB::get_canonical_burn_chain_tip(miner.sortdb().conn()).unwrap();
+        let (mut burn_ops, stacks_block, microblocks) = miner.make_tenure(
+            |ref mut miner, ref mut sortdb, ref mut chainstate, vrf_proof, ref parent_opt, _| {
+                let parent_tip = get_parent_tip(parent_opt, chainstate, sortdb);
+                let mut block_txs = vec![make_coinbase(miner, tip.block_height as usize)];
+                block_txs.extend_from_slice(txs);
+
+                let block_builder = StacksBlockBuilder::make_regtest_block_builder(
+                    &parent_tip,
+                    vrf_proof,
+                    tip.total_burn,
+                    microblock_pubkeyhash,
+                )
+                .unwrap();
+                let (anchored_block, _size, _cost) =
+                    StacksBlockBuilder::make_anchored_block_from_txs(
+                        block_builder,
+                        chainstate,
+                        &sortdb.index_conn(),
+                        block_txs,
+                    )
+                    .unwrap();
+                (anchored_block, vec![])
+            },
+        );
+
+        let (_, burn_header_hash, consensus_hash) = miner.next_burnchain_block(burn_ops.clone());
+        miner.process_stacks_epoch_at_tip(&stacks_block, &microblocks);
+
+        TestPeer::set_ops_burn_header_hash(&mut burn_ops, &burn_header_hash);
+        follower.next_burnchain_block_raw(burn_ops);
+        let sortdb = follower.sortdb.as_ref().unwrap();
+        let chainstate = &mut follower.stacks_node.as_mut().unwrap().chainstate;
+        Relayer::process_new_anchored_block(
+            &sortdb.index_conn(),
+            chainstate,
+            &consensus_hash,
+            &stacks_block,
+            0,
+        )
+        .unwrap();
+
+        (consensus_hash, stacks_block)
+    }
+
+    /// Have `miner` mine a tenure with `txs` and process it, and relay it to `follower`, which
+    /// processes it too.
+    fn mine_and_relay(
+        miner: &mut TestPeer,
+        follower: &mut TestPeer,
+        txs: &[StacksTransaction],
+    ) -> (ConsensusHash, StacksBlock) {
+        let (consensus_hash, stacks_block) = mine_and_stage(miner, follower, txs);
+        follower.coord.handle_new_stacks_block().unwrap();
+        (consensus_hash, stacks_block)
+    }
+
+    fn get_account_at(
+        peer: &mut TestPeer,
+        tip: &StacksBlockId,
+        principal: &PrincipalData,
+    ) -> StacksAccount {
+        let sortdb = peer.sortdb.as_ref().unwrap();
+        peer.stacks_node
+            .as_mut()
+            .unwrap()
+            .chainstate
+            .with_read_only_clarity_tx(&sortdb.index_conn(), tip, |conn| {
+                StacksChainState::get_account(conn, principal)
+            })
+            .unwrap()
+    }
+
+    fn get_canonical_tip(peer: &mut TestPeer) -> StacksBlockId {
+        let (consensus_hash, block_hash) =
+            SortitionDB::get_canonical_stacks_chain_tip_hash(peer.sortdb().conn()).unwrap();
+        StacksBlockId::new(&consensus_hash, &block_hash)
+    }
+
+    /// Build (but do not process) a block on top of `parent`, and return its state root
+    fn build_block_on(
+        peer: &mut TestPeer,
+        parent: &StacksBlockId,
+        txs: Vec<StacksTransaction>,
+    ) -> TrieHash {
+        let sortdb = peer.sortdb.as_ref().unwrap();
+        let chainstate = &peer.stacks_node.as_ref().unwrap().chainstate;
+        let parent_tip = StacksChainState::get_stacks_block_header_info_by_index_block_hash(
+            chainstate.db(),
+            parent,
+        )
+        .unwrap()
+        .unwrap();
+        let block_builder = StacksBlockBuilder::make_regtest_block_builder(
+            &parent_tip,
+            VRFProof::empty(),
+            0,
+            Hash160([0x01; 20]),
+        )
+        .unwrap();
+        let (block, _size, _cost) = StacksBlockBuilder::make_anchored_block_from_txs(
+            block_builder,
+            chainstate,
+            &sortdb.index_conn(),
+            txs,
+        )
+        .unwrap();
+        block.header.state_index_root
+    }
+
+    #[test]
+    fn test_pruned_below_height_bookkeeping() {
+        let mut chainstate = instantiate_chainstate(false, 0x80000000, function_name!());
+        assert_eq!(
+            StacksChainState::get_pruned_below_height(chainstate.db()).unwrap(),
+            None
+        );
+
+        assert_eq!(chainstate.compact_pruned_state().unwrap(), None);
+
+        let stats = chainstate.prune_below_height(1, true).unwrap();
+        assert_eq!(stats.pruned_below_height, 1);
+        assert_eq!(stats.blocks_pruned, 0);
+        assert_eq!(stats.microblocks_pruned, 0);
+        assert_eq!(
+            StacksChainState::get_pruned_below_height(chainstate.db()).unwrap(),
+            Some(1)
+        );
+
+        // compacting again finds nothing more to reclaim
+        let tries = chainstate.compact_pruned_state().unwrap().unwrap();
+        assert_eq!(tries.tries_pruned, 0);
+        assert_eq!(tries.blobs_size_before, tries.blobs_size_after);
+
+        // the boot block is at height 0, and is gone
+        let boot_block_id = StacksBlockHeader::make_index_block_hash(
+            &FIRST_BURNCHAIN_CONSENSUS_HASH,
+            &FIRST_STACKS_BLOCK_HASH,
+        );
+        assert!(chainstate.is_state_pruned(&boot_block_id).unwrap());
+
+        // unknown blocks are not pruned
+        assert!(!chainstate
+            .is_state_pruned(&StacksBlockId([0x11; 32]))
+            .unwrap());
+    }
+
+    /// Prune a follower that is fed real blocks, and check that it still agrees with the
+    /// unpruned miner on the retained state, on new blocks, and on a reorg of up to
+    /// `max_reorg_depth` blocks.
+    #[test]
+    fn test_prune_follower() {
+        let privk = StacksPrivateKey::new();
+        let addr = key_to_stacks_addr(&privk);
+        let principal = addr.to_account_principal();
+        let recipient = PrincipalData::from(key_to_stacks_addr(&StacksPrivateKey::new()));
+
+        let mut miner_config = TestPeerConfig::new(function_name!(), 4400, 4401);
+        let mut pruned_config = TestPeerConfig::new(function_name!(), 4402, 4403);
+        miner_config.initial_balances = vec![(principal.clone(), 1_000_000_000)];
+        pruned_config.initial_balances = miner_config.initial_balances.clone();
+
+        let mut miner = TestPeer::new(miner_config);
+        let mut pruned = TestPeer::new(pruned_config);
+
+        let num_blocks = 10;
+        let mut nonce = 0;
+        let mut block_ids = vec![];
+        for _ in 0..num_blocks {
+            let tx = make_user_stacks_transfer(&privk, nonce, 0, &recipient, 1);
+            nonce += 1;
+            let (consensus_hash, block) = mine_and_relay(&mut miner, &mut pruned, &[tx]);
+            block_ids.push(StacksBlockId::new(&consensus_hash, &block.block_hash()));
+        }
+        let tip = block_ids.last().unwrap().clone();
+        assert_eq!(get_canonical_tip(&mut pruned), tip);
+
+        let tip_height = StacksChainState::get_stacks_block_header_info_by_index_block_hash(
+            pruned.chainstate().db(),
+            &tip,
+        )
+        .unwrap()
+        .unwrap()
+        .stacks_block_height;
+        let max_reorg_depth = 3;
+        let prune_height = tip_height - max_reorg_depth;
+
+        // the peer's coordinator has its own handle on the Clarity MARF, so (like a running node)
+        // it can only prune without compacting
+        let stats = pruned
+            .chainstate()
+            .prune_below_height(prune_height, false)
+            .unwrap();
+        assert!(stats.tries.tries_pruned > 0);
+        assert!(stats.blocks_pruned > 0);
+
+        // the retained blocks' state is intact, and matches the unpruned miner's
+        for (i, block_id) in block_ids.iter().enumerate() {
+            let height = tip_height - (num_blocks - 1 - i) as u64;
+            assert_eq!(
+                pruned.chainstate().is_state_pruned(block_id).unwrap(),
+                height < prune_height
+            );
+            if height < prune_height {
+                continue;
+            }
+            assert_eq!(
+                get_account_at(&mut pruned, block_id, &principal),
+                get_account_at(&mut miner, block_id, &principal)
+            );
+            assert_eq!(
+                get_account_at(&mut pruned, block_id, &recipient),
+                get_account_at(&mut miner, block_id, &recipient)
+            );
+            assert_eq!(
+                pruned
+                    .chainstate()
+                    .with_clarity_marf(|marf| marf.get_root_hash_at(block_id))
+                    .unwrap(),
+                miner
+                    .chainstate()
+                    .with_clarity_marf(|marf| marf.get_root_hash_at(block_id))
+                    .unwrap()
+            );
+        }
+
+        // a reorg of max_reorg_depth blocks builds on the oldest retained block, and the pruned
+        // node computes the same state for it as the unpruned miner
+        let fork_parent = &block_ids[num_blocks - 1 - max_reorg_depth as usize];
+        let fork_nonce = get_account_at(&mut miner, fork_parent, &principal).nonce;
+        let fork_txs = vec![
+            make_user_coinbase(&privk, fork_nonce, 0),
+            make_user_stacks_transfer(&privk, fork_nonce + 1, 0, &recipient, 2),
+        ];
+        assert_eq!(
+            build_block_on(&mut pruned, fork_parent, fork_txs.clone()),
+            build_block_on(&mut miner, fork_parent, fork_txs)
+        );
+
+        // the pruned node keeps processing new blocks, and ends up with the same state
+        let tx = make_user_stacks_transfer(&privk, nonce, 0, &recipient, 1);
+        nonce += 1;
+        let (consensus_hash, block) = mine_and_relay(&mut miner, &mut pruned, &[tx]);
+        let tip = StacksBlockId::new(&consensus_hash, &block.block_hash());
+        assert_eq!(get_canonical_tip(&mut pruned), tip);
+        assert_eq!(get_canonical_tip(&mut miner), tip);
+        assert_eq!(
+            pruned
+                .chainstate()
+                .with_clarity_marf(|marf| marf.get_root_hash_at(&tip))
+                .unwrap(),
+            miner
+                .chainstate()
+                .with_clarity_marf(|marf| marf.get_root_hash_at(&tip))
+                .unwrap()
+        );
+        assert_eq!(
+            get_account_at(&mut pruned, &tip, &recipient),
+            get_account_at(&mut miner, &tip, &recipient)
+        );
+
+        // a block that reads pruned state with `at-block` can't be validated, so it is left
+        // unprocessed instead of being marked invalid, and the coordinator reports why
+        let contract = format!(
+            "(define-constant old-height (at-block 0x{} block-height))",
+            &block_ids[0]
+        );
+        let tx = make_user_contract_publish(&privk, nonce, 0, "read-old-state", &contract);
+        let (consensus_hash, block) = mine_and_stage(&mut miner, &mut pruned, &[tx]);
+        match pruned.coord.handle_new_stacks_block() {
+            Err(CoordinatorError::ChainstateError(Error::PrunedStateError(_))) => {}
+            x => panic!("Expected PrunedStateError, got {:?}", &x),
+        }
+        let at_block_id = StacksBlockId::new(&consensus_hash, &block.block_hash());
+        assert_eq!(get_canonical_tip(&mut miner), at_block_id);
+        assert_eq!(get_canonical_tip(&mut pruned), tip);
+        assert_eq!(
+            StacksChainState::get_staging_block_status(
+                pruned.chainstate().db(),
+                &consensus_hash,
+                &block.block_hash()
+            )
+            .unwrap(),
+            Some(false)
+        );
+    }
+}
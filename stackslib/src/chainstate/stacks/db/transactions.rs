@@ -905,7 +905,7 @@ impl StacksChainState {
         };
 
         let hash_data = BuffData {
-            data: pubkh.as_bytes().to_vec(),
+            data: pubkh.as_bytes().to_vec().into(),
         };
         let tuple_data = TupleData::from_data(vec![
             (
@@ -968,7 +968,7 @@ impl StacksChainState {
                         addr,
                         *amount as u128,
                         &BuffData {
-                            data: Vec::from(memo.0.clone()),
+                            data: Vec::from(memo.0.clone()).into(),
                         },
                     )
                     .map_err(Error::ClarityError)?;
@@ -0,0 +1,181 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Generation of batch MARF proofs (see `libmarfproof`).
+//!
+//! Each key's path is walked from the root of the block's trie.  Whenever the walk crosses a
+//! back-pointer, it starts over from the root of the ancestor trie the back-pointer refers to,
+//! so that every revealed node can be checked against the root hash of the trie it lives in.
+//! The nodes visited by all the keys are then written out once per trie, in pre-order.
+
+use std::collections::HashSet;
+
+use libmarfproof::{MarfBatchProof, ProofChild, ProofNode, ProofPtr, ProofTrie};
+use stacks_common::types::chainstate::StacksBlockId;
+
+use crate::chainstate::stacks::index::node::{
+    is_backptr, TrieNodeID, TrieNodeType, TriePath, TriePtr, TRIEPATH_MAX_LEN,
+};
+use crate::chainstate::stacks::index::storage::TrieStorageConnection;
+use crate::chainstate::stacks::index::trie::Trie;
+use crate::chainstate::stacks::index::{Error, MarfTrieId};
+
+/// The nodes of one block's trie that a batch proof reveals
+struct RevealedTrie<T: MarfTrieId> {
+    block: T,
+    block_id: u32,
+    ptrs: HashSet<u32>,
+}
+
+/// Walk `path` from the root of `block`'s trie, recording every node visited in `tries`.
+fn reveal_path<T: MarfTrieId>(
+    storage: &mut TrieStorageConnection<T>,
+    block: &T,
+    path: &TriePath,
+    tries: &mut Vec<RevealedTrie<T>>,
+) -> Result<(), Error> {
+    let path = path.as_bytes();
+    let mut block = block.clone();
+    loop {
+        storage.open_block(&block)?;
+        let block_id = storage.get_cur_block_identifier()?;
+        let trie_idx = match tries.iter().position(|trie| trie.block_id == block_id) {
+            Some(idx) => idx,
+            None => {
+                tries.push(RevealedTrie {
+                    block: block.clone(),
+                    block_id,
+                    ptrs: HashSet::new(),
+                });
+                tries.len() - 1
+            }
+        };
+
+        let mut ptr = storage.root_trieptr();
+        let mut depth = 0;
+        loop {
+            let (node, _) = storage.read_nodetype(&ptr)?;
+            tries[trie_idx].ptrs.insert(ptr.ptr());
+            if node.is_leaf() || !path[depth..].starts_with(node.path_bytes()) {
+                return Ok(());
+            }
+            depth += node.path_bytes().len();
+            if depth >= TRIEPATH_MAX_LEN {
+                return Err(Error::CorruptionError(
+                    "Intermediate node at the end of a path".to_string(),
+                ));
+            }
+
+            match node.walk(path[depth]) {
+                None => return Ok(()),
+                Some(child) if is_backptr(child.id()) => {
+                    block = storage.get_block_from_local_id(child.back_block())?.clone();
+                    break;
+                }
+                Some(child) => {
+                    ptr = child;
+                    depth += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Write out the revealed nodes of the subtree at `ptr` in pre-order.
+/// The trie containing `ptr` must be open.
+fn write_revealed_nodes<T: MarfTrieId>(
+    storage: &mut TrieStorageConnection<T>,
+    ptr: &TriePtr,
+    revealed: &HashSet<u32>,
+    nodes: &mut Vec<ProofNode>,
+) -> Result<(), Error> {
+    let (node, _) = storage.read_nodetype(ptr)?;
+    if let TrieNodeType::Leaf(ref leaf) = node {
+        nodes.push(ProofNode::Leaf {
+            path: leaf.path.clone(),
+            value: leaf.data.0.clone(),
+        });
+        return Ok(());
+    }
+
+    let mut ptrs = Vec::with_capacity(node.ptrs().len());
+    let mut revealed_children = vec![];
+    for child in node.ptrs().iter() {
+        let proof_child = if child.id() == TrieNodeID::Empty as u8 {
+            ProofChild::Empty
+        } else if is_backptr(child.id()) {
+            let back_block = storage.get_block_from_local_id(child.back_block())?;
+            ProofChild::Back(StacksBlockId(back_block.clone().to_bytes()))
+        } else if revealed.contains(&child.ptr()) {
+            revealed_children.push(child.clone());
+            ProofChild::Revealed
+        } else {
+            ProofChild::Hash(storage.read_node_hash_bytes(child)?)
+        };
+        ptrs.push(ProofPtr {
+            id: child.id(),
+            chr: child.chr(),
+            child: proof_child,
+        });
+    }
+    nodes.push(ProofNode::Node {
+        id: node.id(),
+        path: node.path_bytes().clone(),
+        ptrs,
+    });
+
+    for child in revealed_children.iter() {
+        write_revealed_nodes(storage, child, revealed, nodes)?;
+    }
+    Ok(())
+}
+
+/// Generate a proof of the values of `keys` in the MARF at `block`.
+/// The proof does not include block headers; the caller must add them for ancestor tries
+/// if the proof is to be checked against `block`'s state root alone.
+pub fn make_batch_proof<T: MarfTrieId>(
+    storage: &mut TrieStorageConnection<T>,
+    block: &T,
+    keys: &[String],
+) -> Result<MarfBatchProof, Error> {
+    // the tip's trie always comes first, even if no key is given
+    storage.open_block(block)?;
+    let mut revealed = vec![RevealedTrie {
+        block: block.clone(),
+        block_id: storage.get_cur_block_identifier()?,
+        ptrs: HashSet::from([storage.root_trieptr().ptr()]),
+    }];
+    for key in keys.iter() {
+        reveal_path(storage, block, &TriePath::from_key(key), &mut revealed)?;
+    }
+
+    let mut tries = Vec::with_capacity(revealed.len());
+    for trie in revealed.into_iter() {
+        storage.open_block_known_id(&trie.block, trie.block_id)?;
+        let ancestor_hashes = Trie::get_trie_ancestor_hashes_bytes(storage)?;
+
+        let mut nodes = vec![];
+        let root_ptr = storage.root_trieptr();
+        write_revealed_nodes(storage, &root_ptr, &trie.ptrs, &mut nodes)?;
+        tries.push(ProofTrie {
+            block_id: StacksBlockId(trie.block.to_bytes()),
+            header: None,
+            ancestor_hashes,
+            nodes,
+        });
+    }
+    Ok(MarfBatchProof { tries })
+}
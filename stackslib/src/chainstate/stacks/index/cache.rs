@@ -195,6 +195,11 @@ impl<T: MarfTrieId> TrieCache<T> {
         }
     }
 
+    /// Forget everything that has been cached
+    pub fn clear(&mut self) {
+        *self.state_mut() = TrieCacheState::new();
+    }
+
     /// Get the inner trie cache state, as an immutable reference
     fn state_ref(&self) -> &TrieCacheState<T> {
         match self {
@@ -0,0 +1,358 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Differences between the MARF state at two blocks.
+//!
+//! The two tries are walked side by side from their roots.  A subtree is skipped as soon as both
+//! sides resolve to the same stored node, or to nodes with the same hash, so the cost of a diff is
+//! proportional to the number of keys that differ (and their depth), not to the size of the
+//! state.  The two blocks need not be on the same fork.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::chainstate::stacks::index::marf::{
+    BLOCK_HASH_TO_HEIGHT_MAPPING_KEY, BLOCK_HEIGHT_TO_HASH_MAPPING_KEY, MARF, OWN_BLOCK_HEIGHT_KEY,
+};
+use crate::chainstate::stacks::index::node::{
+    is_backptr, TrieNodeID, TrieNodeType, TriePath, TriePtr, TRIEPATH_MAX_LEN,
+};
+use crate::chainstate::stacks::index::storage::TrieStorageConnection;
+use crate::chainstate::stacks::index::{Error, MARFValue, MarfTrieId, TrieHash};
+
+/// A key whose value differs between two blocks' tries
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrieLeafDiff {
+    /// The hashed key
+    pub path: TriePath,
+    /// The key itself, if it could be recovered
+    pub key: Option<String>,
+    /// The value in the first block's trie, if the key is present there
+    pub from: Option<MARFValue>,
+    /// The value in the second block's trie, if the key is present there
+    pub to: Option<MARFValue>,
+}
+
+impl fmt::Display for TrieLeafDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.key {
+            Some(ref key) => write!(f, "{}", key)?,
+            None => write!(f, "{}", self.path)?,
+        }
+        match self.from {
+            Some(ref value) => write!(f, ": {}", value)?,
+            None => write!(f, ": (none)")?,
+        }
+        match self.to {
+            Some(ref value) => write!(f, " -> {}", value),
+            None => write!(f, " -> (none)"),
+        }
+    }
+}
+
+/// A node of one of the tries being compared, along with the trie that physically stores it
+struct DiffNode<T: MarfTrieId> {
+    block: T,
+    block_id: u32,
+    ptr: TriePtr,
+    node: TrieNodeType,
+    hash: TrieHash,
+}
+
+impl<T: MarfTrieId> DiffNode<T> {
+    /// Is this the same stored node as `other`, or a copy of it?
+    fn same_as(&self, other: &DiffNode<T>) -> bool {
+        (self.block_id == other.block_id && self.ptr.ptr() == other.ptr.ptr())
+            || self.hash == other.hash
+    }
+
+    /// The non-empty child pointers of this node
+    fn children(&self) -> impl Iterator<Item = &TriePtr> {
+        self.node
+            .ptrs()
+            .iter()
+            .filter(|ptr| ptr.id() != TrieNodeID::Empty as u8)
+    }
+}
+
+/// Read the root node of a block's trie
+fn read_root<T: MarfTrieId>(
+    storage: &mut TrieStorageConnection<T>,
+    block: &T,
+) -> Result<DiffNode<T>, Error> {
+    storage.open_block(block)?;
+    let block_id = storage.get_cur_block_identifier()?;
+    let ptr = storage.root_trieptr();
+    let (node, hash) = storage.read_nodetype(&ptr)?;
+    Ok(DiffNode {
+        block: block.clone(),
+        block_id,
+        ptr,
+        node,
+        hash,
+    })
+}
+
+/// Read the child of `parent` at `ptr`, following it into an ancestor trie if it is a
+/// back-pointer.
+fn read_child<T: MarfTrieId>(
+    storage: &mut TrieStorageConnection<T>,
+    parent: &DiffNode<T>,
+    ptr: &TriePtr,
+) -> Result<DiffNode<T>, Error> {
+    storage.open_block_known_id(&parent.block, parent.block_id)?;
+    if !is_backptr(ptr.id()) {
+        let (node, hash) = storage.read_nodetype(ptr)?;
+        return Ok(DiffNode {
+            block: parent.block.clone(),
+            block_id: parent.block_id,
+            ptr: ptr.clone(),
+            node,
+            hash,
+        });
+    }
+
+    let block_id = ptr.back_block();
+    let block = storage.get_block_from_local_id(block_id)?.clone();
+    storage.open_block_known_id(&block, block_id)?;
+    let ptr = ptr.from_backptr();
+    let (node, hash) = storage.read_nodetype(&ptr)?;
+    Ok(DiffNode {
+        block,
+        block_id,
+        ptr,
+        node,
+        hash,
+    })
+}
+
+/// Collect every leaf under `node`, keyed by its full path.
+/// `prefix` is the part of the path consumed before reaching `node`.
+fn collect_leaves<T: MarfTrieId>(
+    storage: &mut TrieStorageConnection<T>,
+    prefix: &[u8],
+    node: &DiffNode<T>,
+    leaves: &mut BTreeMap<TriePath, MARFValue>,
+) -> Result<(), Error> {
+    let mut path = prefix.to_vec();
+    path.extend_from_slice(node.node.path_bytes());
+    if let TrieNodeType::Leaf(ref leaf) = node.node {
+        let path = TriePath::from_bytes(&path)
+            .ok_or_else(|| Error::CorruptionError(format!("Leaf path has {} bytes", path.len())))?;
+        leaves.insert(path, leaf.data.clone());
+        return Ok(());
+    }
+    if path.len() >= TRIEPATH_MAX_LEN {
+        return Err(Error::CorruptionError(
+            "Intermediate node at the end of a path".to_string(),
+        ));
+    }
+
+    for ptr in node.children() {
+        let child = read_child(storage, node, ptr)?;
+        path.push(ptr.chr());
+        collect_leaves(storage, &path, &child, leaves)?;
+        path.pop();
+    }
+    Ok(())
+}
+
+/// Find the leaves that differ between the children of `from` and `to`, both of whose children
+/// are reached after consuming `path` (plus the child pointer's character).
+fn diff_children<T: MarfTrieId>(
+    storage: &mut TrieStorageConnection<T>,
+    path: &mut Vec<u8>,
+    from: &DiffNode<T>,
+    to: &DiffNode<T>,
+    diffs: &mut Vec<TrieLeafDiff>,
+) -> Result<(), Error> {
+    let mut children: BTreeMap<u8, (Option<TriePtr>, Option<TriePtr>)> = BTreeMap::new();
+    for ptr in from.children() {
+        children.entry(ptr.chr()).or_default().0 = Some(ptr.clone());
+    }
+    for ptr in to.children() {
+        children.entry(ptr.chr()).or_default().1 = Some(ptr.clone());
+    }
+
+    for (chr, (from_ptr, to_ptr)) in children.into_iter() {
+        let from_child = match from_ptr {
+            Some(ptr) => Some(read_child(storage, from, &ptr)?),
+            None => None,
+        };
+        let to_child = match to_ptr {
+            Some(ptr) => Some(read_child(storage, to, &ptr)?),
+            None => None,
+        };
+        path.push(chr);
+        diff_nodes(storage, path, from_child.as_ref(), to_child.as_ref(), diffs)?;
+        path.pop();
+    }
+    Ok(())
+}
+
+/// Find the leaves that differ between the subtrees at `from` and `to`, both of which are
+/// reached after consuming `prefix`.
+fn diff_nodes<T: MarfTrieId>(
+    storage: &mut TrieStorageConnection<T>,
+    prefix: &[u8],
+    from: Option<&DiffNode<T>>,
+    to: Option<&DiffNode<T>>,
+    diffs: &mut Vec<TrieLeafDiff>,
+) -> Result<(), Error> {
+    if let (Some(from), Some(to)) = (from, to) {
+        if from.same_as(to) {
+            return Ok(());
+        }
+        if !from.node.is_leaf()
+            && !to.node.is_leaf()
+            && from.node.path_bytes() == to.node.path_bytes()
+        {
+            // same shape -- compare the children pairwise
+            let mut path = prefix.to_vec();
+            path.extend_from_slice(from.node.path_bytes());
+            return diff_children(storage, &mut path, from, to, diffs);
+        }
+    }
+
+    // the subtrees are shaped differently (or one is missing), so compare their leaves.  This
+    // only happens near the keys that were inserted, where subtrees are small.
+    let mut from_leaves = BTreeMap::new();
+    if let Some(from) = from {
+        collect_leaves(storage, prefix, from, &mut from_leaves)?;
+    }
+    let mut to_leaves = BTreeMap::new();
+    if let Some(to) = to {
+        collect_leaves(storage, prefix, to, &mut to_leaves)?;
+    }
+
+    // N.B. TriePath's Ord is little-endian, so the leaves are put in path order by their bytes
+    let mut paths: Vec<&TriePath> = from_leaves
+        .keys()
+        .chain(
+            to_leaves
+                .keys()
+                .filter(|path| !from_leaves.contains_key(path)),
+        )
+        .collect();
+    paths.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+
+    for path in paths.into_iter() {
+        let from_value = from_leaves.get(path);
+        let to_value = to_leaves.get(path);
+        if from_value != to_value {
+            diffs.push(TrieLeafDiff {
+                path: path.clone(),
+                key: None,
+                from: from_value.cloned(),
+                to: to_value.cloned(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Find every key whose value differs between the tries of `from` and `to`, in order of their
+/// paths.  The keys the MARF uses for its own block height bookkeeping are recovered; all other
+/// keys are left for the caller to fill in with `resolve_keys()`.
+///
+/// The storage connection is left pointing at an arbitrary block.
+pub fn diff_tries<T: MarfTrieId>(
+    storage: &mut TrieStorageConnection<T>,
+    from: &T,
+    to: &T,
+) -> Result<Vec<TrieLeafDiff>, Error> {
+    let from_root = read_root(storage, from)?;
+    let to_root = read_root(storage, to)?;
+
+    // N.B. the root hash also commits to the ancestor tries' root hashes, so identical state at
+    // two different blocks still has different root hashes.  Only the root's children can be
+    // compared by hash.
+    let mut diffs = vec![];
+    if from_root.block_id != to_root.block_id {
+        diff_children(storage, &mut vec![], &from_root, &to_root, &mut diffs)?;
+    }
+
+    resolve_block_height_keys(storage, from, to, &mut diffs)?;
+    Ok(diffs)
+}
+
+/// Recover the keys of the MARF's block height bookkeeping, using the values stored under them.
+fn resolve_block_height_keys<T: MarfTrieId>(
+    storage: &mut TrieStorageConnection<T>,
+    from: &T,
+    to: &T,
+    diffs: &mut [TrieLeafDiff],
+) -> Result<(), Error> {
+    let own_height_path = TriePath::from_key(OWN_BLOCK_HEIGHT_KEY);
+    for diff in diffs.iter_mut() {
+        if diff.path == own_height_path {
+            diff.key = Some(OWN_BLOCK_HEIGHT_KEY.to_string());
+            continue;
+        }
+
+        let values = [(from, diff.from.clone()), (to, diff.to.clone())];
+        for (tip, value) in values.iter() {
+            let Some(value) = value else {
+                continue;
+            };
+            let mut candidates = vec![];
+            if value.as_bytes()[4..].iter().all(|b| *b == 0) {
+                // might be a height, stored under the hash of the block at that height
+                let height = u32::from(value.clone());
+                if let Some(block) = MARF::get_block_at_height(storage, height, tip)? {
+                    candidates.push(format!("{}::{}", BLOCK_HASH_TO_HEIGHT_MAPPING_KEY, block));
+                }
+            } else {
+                // might be a block hash, stored under that block's height
+                let block = T::from(value.clone());
+                if let Some(height) = MARF::get_block_height(storage, &block, tip)? {
+                    candidates.push(format!("{}::{}", BLOCK_HEIGHT_TO_HASH_MAPPING_KEY, height));
+                }
+            }
+            if let Some(key) = candidates
+                .into_iter()
+                .find(|key| TriePath::from_key(key) == diff.path)
+            {
+                diff.key = Some(key);
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fill in the keys of `diffs` from a list of candidate keys, stopping as soon as every key is
+/// known.  Returns the number of keys filled in.
+pub fn resolve_keys<I: IntoIterator<Item = String>>(diffs: &mut [TrieLeafDiff], keys: I) -> usize {
+    let mut unresolved: HashMap<TriePath, usize> = diffs
+        .iter()
+        .enumerate()
+        .filter(|(_, diff)| diff.key.is_none())
+        .map(|(i, diff)| (diff.path.clone(), i))
+        .collect();
+
+    let mut resolved = 0;
+    for key in keys {
+        if unresolved.is_empty() {
+            break;
+        }
+        if let Some(i) = unresolved.remove(&TriePath::from_key(&key)) {
+            diffs[i].key = Some(key);
+            resolved += 1;
+        }
+    }
+    resolved
+}
@@ -324,6 +324,169 @@ impl TrieFile {
     }
 }
 
+impl TrieFile {
+    /// Find the nodes of non-retained tries that are needed to read the state of the tries in
+    /// `retained`.  These are the nodes that a retained trie reaches through a back-pointer,
+    /// directly or through other such nodes.  Every node of a retained trie is needed, so those
+    /// are not listed.
+    /// Returns the block ID and pointer of each such node.
+    /// `root_ptr` points to the root node of a stored trie.
+    pub fn find_live_nodes(
+        &mut self,
+        db: &Connection,
+        retained: &HashSet<u32>,
+        root_ptr: &TriePtr,
+    ) -> Result<Vec<(u32, TriePtr)>, Error> {
+        let mut live = vec![];
+
+        // nodes in non-retained tries that have been walked already
+        let mut visited: HashSet<(u32, u32)> = HashSet::new();
+        let mut frontier: Vec<(u32, TriePtr)> = retained
+            .iter()
+            .map(|block_id| (*block_id, root_ptr.clone()))
+            .collect();
+
+        while let Some((block_id, ptr)) = frontier.pop() {
+            let node = self.read_node_type_nohash(db, block_id, &ptr)?;
+            for child_ptr in node.ptrs().iter() {
+                if child_ptr.id() == TrieNodeID::Empty as u8 {
+                    continue;
+                }
+                let (child_block_id, next_ptr) = if is_backptr(child_ptr.id()) {
+                    (child_ptr.back_block(), child_ptr.from_backptr())
+                } else {
+                    (block_id, child_ptr.clone())
+                };
+                if retained.contains(&child_block_id) {
+                    if child_block_id != block_id {
+                        // will be walked from its own trie's root
+                        continue;
+                    }
+                } else {
+                    if !visited.insert((child_block_id, next_ptr.ptr())) {
+                        continue;
+                    }
+                    live.push((child_block_id, next_ptr.clone()));
+                }
+                if next_ptr.id() != TrieNodeID::Leaf as u8 {
+                    frontier.push((child_block_id, next_ptr));
+                }
+            }
+        }
+        Ok(live)
+    }
+
+    /// Path to the scratch file that a compaction of the TrieFile at `path` is written to
+    fn compaction_path(path: &str) -> String {
+        format!("{}.compact", path)
+    }
+
+    /// Copy the given trie blobs, in the given order, and then the given nodes of pruned tries,
+    /// into a new TrieFile.  `blobs` lists each trie's block ID, offset, and length in this
+    /// TrieFile, and `nodes` lists each node's block ID and pointer.
+    /// Returns the compacted TrieFile, each trie's offset in it, and each node's block ID,
+    /// pointer, offset, and length in it.  The compacted TrieFile does not replace this one until
+    /// it is passed to `install_compaction()`.
+    pub fn write_compaction(
+        &mut self,
+        db: &Connection,
+        blobs: &[(u32, u64, u64)],
+        nodes: &[(u32, TriePtr)],
+    ) -> Result<(TrieFile, Vec<(u32, u64)>, Vec<(u32, u32, u64, u64)>), Error> {
+        let mut compacted = match self {
+            TrieFile::RAM(_) => TrieFile::new_ram(false),
+            TrieFile::Disk(ref disk) => {
+                let compacted_path = TrieFile::compaction_path(&disk.path);
+                if fs::metadata(&compacted_path).is_ok() {
+                    fs::remove_file(&compacted_path)?;
+                }
+                TrieFile::new_disk(&compacted_path, false)?
+            }
+        };
+
+        let mut offsets = Vec::with_capacity(blobs.len());
+        let mut buf = vec![];
+        let mut next_offset = 0;
+        for (block_id, offset, length) in blobs.iter() {
+            buf.resize(*length as usize, 0);
+            self.seek(SeekFrom::Start(*offset))?;
+            self.read_exact(&mut buf)?;
+            compacted.write_all(&buf)?;
+
+            offsets.push((*block_id, next_offset));
+            next_offset += *length;
+        }
+
+        let mut node_offsets = Vec::with_capacity(nodes.len());
+        for (block_id, ptr) in nodes.iter() {
+            let (_, node_bytes) = self.read_node_bytes(db, *block_id, ptr)?;
+            compacted.write_all(&node_bytes)?;
+
+            let length = node_bytes.len() as u64;
+            node_offsets.push((*block_id, ptr.ptr(), next_offset, length));
+            next_offset += length;
+        }
+
+        compacted.flush()?;
+        if let TrieFile::Disk(ref disk) = compacted {
+            disk.fd.sync_all()?;
+        }
+        Ok((compacted, offsets, node_offsets))
+    }
+
+    /// Move a compacted blobs file over the blobs file at `path`, if it has not been moved already
+    fn move_compaction_into_place(path: &str) -> Result<(), Error> {
+        let compacted_path = TrieFile::compaction_path(path);
+        if fs::metadata(&compacted_path).is_ok() {
+            fs::rename(&compacted_path, path)?;
+        }
+        Ok(())
+    }
+
+    /// Replace this TrieFile with a compacted TrieFile from `write_compaction()`.  The trie
+    /// offsets in the DB must already refer to the compacted TrieFile.
+    pub fn install_compaction(&mut self, compacted: TrieFile) -> Result<(), Error> {
+        let path = match self {
+            TrieFile::RAM(_) => {
+                *self = compacted;
+                return Ok(());
+            }
+            TrieFile::Disk(ref disk) => disk.path.clone(),
+        };
+        drop(compacted);
+        TrieFile::move_compaction_into_place(&path)?;
+        *self = TrieFile::new_disk(&path, false)?;
+        Ok(())
+    }
+
+    /// Finish or discard a compaction of this TrieFile that was interrupted.  If the DB already
+    /// refers to the compacted blobs file, then it is moved into place.  Otherwise, it is
+    /// deleted.
+    pub fn recover_compaction(&mut self, db: &Connection, readonly: bool) -> Result<(), Error> {
+        let path = match self {
+            TrieFile::RAM(_) => {
+                return Ok(());
+            }
+            TrieFile::Disk(ref disk) => disk.path.clone(),
+        };
+        let compacted_path = TrieFile::compaction_path(&path);
+        if trie_sql::get_pending_blob_compaction(db)?.is_some() {
+            if readonly {
+                error!("Compaction of {} was interrupted and must be finished by opening the MARF read/write", &path);
+                return Err(Error::InProgressError);
+            }
+            info!("Finish interrupted compaction of {}", &path);
+            TrieFile::move_compaction_into_place(&path)?;
+            *self = TrieFile::new_disk(&path, false)?;
+            trie_sql::clear_pending_blob_compaction(db)?;
+        } else if !readonly && fs::metadata(&compacted_path).is_ok() {
+            warn!("Discard interrupted compaction of {}", &path);
+            fs::remove_file(&compacted_path)?;
+        }
+        Ok(())
+    }
+}
+
 /// NodeHashReader for TrieFile
 pub struct TrieFileNodeHashReader<'a> {
     db: &'a Connection,
@@ -343,9 +506,8 @@ impl<'a> TrieFileNodeHashReader<'a> {
 
 impl NodeHashReader for TrieFileNodeHashReader<'_> {
     fn read_node_hash_bytes<W: Write>(&mut self, ptr: &TriePtr, w: &mut W) -> Result<(), Error> {
-        let trie_offset = self.file.get_trie_offset(self.db, self.block_id)?;
-        self.file
-            .seek(SeekFrom::Start(trie_offset + (ptr.ptr() as u64)))?;
+        let offset = self.file.get_node_offset(self.db, self.block_id, ptr)?;
+        self.file.seek(SeekFrom::Start(offset))?;
         let hash_buff = read_hash_bytes(self.file)?;
         w.write_all(&hash_buff).map_err(|e| e.into())
     }
@@ -362,7 +524,11 @@ impl TrieFile {
         match offset_opt {
             Some(offset) => Ok(*offset),
             None => {
-                let (offset, _length) = trie_sql::get_external_trie_offset_length(db, block_id)?;
+                let (offset, length) = trie_sql::get_external_trie_offset_length(db, block_id)?;
+                if length == 0 {
+                    // every stored trie has at least a root node, so this one was pruned
+                    return Err(Error::PrunedError);
+                }
                 match self {
                     TrieFile::RAM(ref mut ram) => ram.trie_offsets.insert(block_id, offset),
                     TrieFile::Disk(ref mut disk) => disk.trie_offsets.insert(block_id, offset),
@@ -372,6 +538,51 @@ impl TrieFile {
         }
     }
 
+    /// Forget the cached trie offsets, e.g. because some of the tries were pruned
+    pub fn clear_trie_offsets(&mut self) {
+        match self {
+            TrieFile::RAM(ref mut ram) => ram.trie_offsets.clear(),
+            TrieFile::Disk(ref mut disk) => disk.trie_offsets.clear(),
+        }
+    }
+
+    /// Determine the file offset in the TrieFile where a node is stored, given its block ID and
+    /// pointer.  Of a pruned trie, only the nodes that unpruned tries still reach can be found.
+    fn get_node_offset(
+        &mut self,
+        db: &Connection,
+        block_id: u32,
+        ptr: &TriePtr,
+    ) -> Result<u64, Error> {
+        match self.get_trie_offset(db, block_id) {
+            Ok(offset) => Ok(offset + (ptr.ptr() as u64)),
+            Err(Error::PrunedError) => {
+                trie_sql::get_pruned_trie_node_offset(db, block_id, ptr.ptr())?
+                    .ok_or(Error::PrunedError)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read a node's stored bytes -- its hash, followed by the node itself -- given its block ID
+    /// and pointer.  Returns the node's offset in the TrieFile, and its bytes.
+    pub fn read_node_bytes(
+        &mut self,
+        db: &Connection,
+        block_id: u32,
+        ptr: &TriePtr,
+    ) -> Result<(u64, Vec<u8>), Error> {
+        let offset = self.get_node_offset(db, block_id, ptr)?;
+        self.seek(SeekFrom::Start(offset))?;
+        read_nodetype_at_head(self, ptr.id())?;
+        let end = self.seek(SeekFrom::Current(0))?;
+
+        let mut buf = vec![0; (end - offset) as usize];
+        self.seek(SeekFrom::Start(offset))?;
+        self.read_exact(&mut buf)?;
+        Ok((offset, buf))
+    }
+
     /// Obtain a TrieHash for a node, given its block ID and pointer
     pub fn get_node_hash_bytes(
         &mut self,
@@ -379,8 +590,8 @@ impl TrieFile {
         block_id: u32,
         ptr: &TriePtr,
     ) -> Result<TrieHash, Error> {
-        let offset = self.get_trie_offset(db, block_id)?;
-        self.seek(SeekFrom::Start(offset + (ptr.ptr() as u64)))?;
+        let offset = self.get_node_offset(db, block_id, ptr)?;
+        self.seek(SeekFrom::Start(offset))?;
         let hash_buff = read_hash_bytes(self)?;
         Ok(TrieHash(hash_buff))
     }
@@ -393,8 +604,8 @@ impl TrieFile {
         block_id: u32,
         ptr: &TriePtr,
     ) -> Result<(TrieNodeType, TrieHash), Error> {
-        let offset = self.get_trie_offset(db, block_id)?;
-        self.seek(SeekFrom::Start(offset + (ptr.ptr() as u64)))?;
+        let offset = self.get_node_offset(db, block_id, ptr)?;
+        self.seek(SeekFrom::Start(offset))?;
         read_nodetype_at_head(self, ptr.id())
     }
 
@@ -405,8 +616,8 @@ impl TrieFile {
         block_id: u32,
         ptr: &TriePtr,
     ) -> Result<TrieNodeType, Error> {
-        let offset = self.get_trie_offset(db, block_id)?;
-        self.seek(SeekFrom::Start(offset + (ptr.ptr() as u64)))?;
+        let offset = self.get_node_offset(db, block_id, ptr)?;
+        self.seek(SeekFrom::Start(offset))?;
         read_nodetype_at_head_nohash(self, ptr.id())
     }
 
@@ -14,24 +14,29 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::collections::HashSet;
 use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 use std::ops::DerefMut;
 use std::path::PathBuf;
 use std::{error, fmt, fs, io};
 
+use libmarfproof::MarfBatchProof;
 use rusqlite::{Connection, Transaction};
 use sha2::Digest;
 use stacks_common::types::chainstate::{BlockHeaderHash, TrieHash, TRIEHASH_ENCODED_SIZE};
 use stacks_common::util::hash::Sha512Trunc256Sum;
 use stacks_common::util::log;
 
+use crate::chainstate::stacks::index::batch_proof::make_batch_proof;
 use crate::chainstate::stacks::index::bits::{get_leaf_hash, get_node_hash, read_root_hash};
+use crate::chainstate::stacks::index::diff::{diff_tries, TrieLeafDiff};
 use crate::chainstate::stacks::index::node::{
     clear_backptr, is_backptr, set_backptr, CursorError, TrieCursor, TrieNode, TrieNode16,
     TrieNode256, TrieNode4, TrieNode48, TrieNodeID, TrieNodeType, TriePath, TriePtr, TRIEPTR_SIZE,
 };
 use crate::chainstate::stacks::index::storage::{
-    TrieFileStorage, TrieHashCalculationMode, TrieStorageConnection, TrieStorageTransaction,
+    TrieFileStorage, TrieHashCalculationMode, TriePruneStats, TrieStorageConnection,
+    TrieStorageTransaction,
 };
 use crate::chainstate::stacks::index::trie::Trie;
 use crate::chainstate::stacks::index::{
@@ -71,6 +76,13 @@ pub struct MARFOpenOpts {
     pub external_blobs: bool,
     /// unconditionally do a DB migration (used for testing)
     pub force_db_migrate: bool,
+    /// maintain a non-consensus index of the Clarity data map keys written in each block.
+    /// Only meaningful for the Clarity state MARF.
+    pub index_data_map_keys: bool,
+    /// number of threads to use when calculating a trie's node hashes in deferred hashing mode.
+    /// Independent subtries of the trie root are hashed in parallel; the root hash is the same
+    /// regardless.
+    pub hash_threads: usize,
 }
 
 impl MARFOpenOpts {
@@ -80,6 +92,8 @@ impl MARFOpenOpts {
             cache_strategy: "noop".to_string(),
             external_blobs: false,
             force_db_migrate: false,
+            index_data_map_keys: false,
+            hash_threads: 1,
         }
     }
 
@@ -93,6 +107,8 @@ impl MARFOpenOpts {
             cache_strategy: cache_strategy.to_string(),
             external_blobs,
             force_db_migrate: false,
+            index_data_map_keys: false,
+            hash_threads: 1,
         }
     }
 
@@ -107,8 +123,18 @@ impl MARFOpenOpts {
             MARFOpenOpts::new(TrieHashCalculationMode::Deferred, "everything", false),
             MARFOpenOpts::new(TrieHashCalculationMode::Immediate, "everything", true),
             MARFOpenOpts::new(TrieHashCalculationMode::Deferred, "everything", true),
+            MARFOpenOpts::new(TrieHashCalculationMode::Deferred, "noop", false)
+                .with_hash_threads(4),
+            MARFOpenOpts::new(TrieHashCalculationMode::Deferred, "everything", true)
+                .with_hash_threads(4),
         ]
     }
+
+    /// Hash tries with `hash_threads` worker threads
+    pub fn with_hash_threads(mut self, hash_threads: usize) -> MARFOpenOpts {
+        self.hash_threads = hash_threads;
+        self
+    }
 }
 
 ///
@@ -191,7 +217,13 @@ pub trait MarfConnection<T: MarfTrieId> {
             conn.open_block(&cur_block_hash)
                 .map_err(|e| Error::RestoreMarfBlockError(Box::new(e)))?;
 
-            result
+            result?;
+
+            // the block is in this fork, but its state may be gone
+            if conn.is_pruned(bhh)? {
+                return Err(Error::PrunedError);
+            }
+            Ok(())
         })
     }
 }
@@ -1538,6 +1570,50 @@ impl<T: MarfTrieId> MARF<T> {
         self.storage.connection().get_root_hash_at(block_hash)
     }
 
+    /// Find every key whose value differs between the state at `from` and the state at `to`.
+    /// The blocks need not be on the same fork.
+    pub fn diff(&mut self, from: &T, to: &T) -> Result<Vec<TrieLeafDiff>, Error> {
+        let mut conn = self.storage.connection();
+        let (cur_block_hash, cur_block_id) = conn.get_cur_block_and_id();
+        let result = diff_tries(&mut conn, from, to);
+
+        // restore
+        conn.open_block_maybe_id(&cur_block_hash, cur_block_id)?;
+        result
+    }
+
+    /// Generate a single proof of the values (or absence) of all of `keys` at `block`.
+    /// Trie nodes shared by several keys' paths are only included once.
+    pub fn get_batch_proof(&mut self, block: &T, keys: &[String]) -> Result<MarfBatchProof, Error> {
+        let mut conn = self.storage.connection();
+        let (cur_block_hash, cur_block_id) = conn.get_cur_block_and_id();
+        let result = make_batch_proof(&mut conn, block, keys);
+
+        // restore
+        conn.open_block_maybe_id(&cur_block_hash, cur_block_id)?;
+        result
+    }
+
+    /// Prune the state of every block except what is needed to read the state at the blocks in
+    /// `retain`.  The retained blocks can still be extended.
+    /// If `compact` is set, the blobs file is compacted, and no other handle to this MARF may be
+    /// open while this runs.
+    pub fn prune(&mut self, retain: &HashSet<T>, compact: bool) -> Result<TriePruneStats, Error> {
+        if self.open_chain_tip.is_some() {
+            error!(
+                "MARF at {} is already in the process of writing",
+                &self.storage.db_path
+            );
+            return Err(Error::InProgressError);
+        }
+        self.storage.prune_tries(retain, compact)
+    }
+
+    /// Has the state at this block been pruned?
+    pub fn is_pruned(&self, block_hash: &T) -> Result<bool, Error> {
+        self.storage.is_pruned(block_hash)
+    }
+
     /// Convert to the inner sqlite connection
     pub fn into_sqlite_conn(self) -> Connection {
         self.storage.into_sqlite_conn()
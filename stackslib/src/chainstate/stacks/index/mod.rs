@@ -28,8 +28,10 @@ use stacks_common::util::log;
 
 use crate::util_lib::db::Error as db_error;
 
+pub mod batch_proof;
 pub mod bits;
 pub mod cache;
+pub mod diff;
 pub mod file;
 pub mod marf;
 pub mod node;
@@ -97,6 +99,8 @@ pub trait MarfTrieId:
     + PartialEq
     + Eq
     + Hash
+    + Send
+    + Sync
 {
 }
 
@@ -330,6 +334,7 @@ pub enum Error {
     CursorError(node::CursorError),
     RestoreMarfBlockError(Box<Error>),
     NonMatchingForks([u8; 32], [u8; 32]),
+    PrunedError,
 }
 
 impl From<io::Error> for Error {
@@ -395,6 +400,7 @@ impl fmt::Display for Error {
             Error::RequestedIdentifierForExtensionTrie => {
                 write!(f, "BUG: MARF requested the identifier for a RAM trie")
             }
+            Error::PrunedError => write!(f, "Trie data has been pruned"),
         }
     }
 }
@@ -68,6 +68,14 @@ pub struct TrieBenchmark {
     /// pointed to a node in an ancestor trie
     total_write_children_hashes_ancestor_block_time_ns: u128,
 
+    /// Total number of tries whose node hashes were calculated on more than one thread
+    total_write_children_hashes_parallel: u128,
+    /// Total number of nanoseconds spent calculating the node hashes of tries on more than one
+    /// thread
+    total_write_children_hashes_parallel_time_ns: u128,
+    /// Total number of worker threads used to calculate trie node hashes in parallel
+    total_write_children_hashes_parallel_threads: u128,
+
     /// Total number of naonseconds spent in calls to the inner loop of MARF::walk_from(), which
     /// handles walking down a MARF path.  Does not include the time taken to load the trie root or
     /// open the trie to walk from.
@@ -119,6 +127,10 @@ impl TrieBenchmark {
             total_write_children_hashes_same_block_time_ns: 0,
             total_write_children_hashes_ancestor_block_time_ns: 0,
 
+            total_write_children_hashes_parallel: 0,
+            total_write_children_hashes_parallel_time_ns: 0,
+            total_write_children_hashes_parallel_threads: 0,
+
             total_marf_walk_from_time_ns: 0,
             total_marf_walk_backptr_time_ns: 0,
             total_marf_walk_find_backptr_node_time_ns: 0,
@@ -160,6 +172,10 @@ impl TrieBenchmark {
         self.total_write_children_hashes_same_block_time_ns = 0;
         self.total_write_children_hashes_ancestor_block_time_ns = 0;
 
+        self.total_write_children_hashes_parallel = 0;
+        self.total_write_children_hashes_parallel_time_ns = 0;
+        self.total_write_children_hashes_parallel_threads = 0;
+
         self.total_marf_walk_from_time_ns = 0;
         self.total_marf_walk_backptr_time_ns = 0;
         self.total_marf_walk_find_backptr_node_time_ns = 0;
@@ -196,6 +212,12 @@ impl TrieBenchmark {
         self.total_write_children_hashes_ancestor_block_time_ns +=
             other.total_write_children_hashes_ancestor_block_time_ns;
 
+        self.total_write_children_hashes_parallel += other.total_write_children_hashes_parallel;
+        self.total_write_children_hashes_parallel_time_ns +=
+            other.total_write_children_hashes_parallel_time_ns;
+        self.total_write_children_hashes_parallel_threads +=
+            other.total_write_children_hashes_parallel_threads;
+
         self.total_marf_walk_from_time_ns += other.total_marf_walk_from_time_ns;
         self.total_marf_walk_backptr_time_ns += other.total_marf_walk_backptr_time_ns;
         self.total_marf_walk_find_backptr_node_time_ns +=
@@ -326,6 +348,24 @@ impl TrieBenchmark {
         }
     }
 
+    /// Finish measuring the calculation of a trie's node hashes on `num_threads` threads.  The
+    /// `start_time` comes from write_children_hashes_start().
+    pub fn write_children_hashes_parallel_finish(
+        &mut self,
+        start_time: SystemTime,
+        num_threads: usize,
+    ) {
+        if let Ok(elapsed) = start_time.elapsed() {
+            let total_time = elapsed.as_nanos();
+
+            self.total_write_children_hashes_parallel += 1;
+            self.total_write_children_hashes_parallel_time_ns += total_time;
+            self.total_write_children_hashes_parallel_threads += num_threads as u128;
+        } else {
+            self.time_errors += 1;
+        }
+    }
+
     /// Start measuring the runtime of a call to open_block()
     pub fn open_block_start(&mut self) {
         self.open_block_start_time = SystemTime::now();
@@ -437,6 +477,10 @@ impl TrieBenchmark {
             total_write_children_hashes_same_block_time_ns: 0,
             total_write_children_hashes_ancestor_block_time_ns: 0,
 
+            total_write_children_hashes_parallel: 0,
+            total_write_children_hashes_parallel_time_ns: 0,
+            total_write_children_hashes_parallel_threads: 0,
+
             total_marf_walk_from_time_ns: 0,
             total_marf_walk_backptr_time_ns: 0,
             total_marf_walk_find_backptr_node_time_ns: 0,
@@ -489,6 +533,13 @@ impl TrieBenchmark {
 
     pub fn write_children_hashes_ancestor_block_finish(&mut self, _start_time: SystemTime) {}
 
+    pub fn write_children_hashes_parallel_finish(
+        &mut self,
+        _start_time: SystemTime,
+        _num_threads: usize,
+    ) {
+    }
+
     pub fn open_block_start(&mut self) {}
 
     pub fn open_block_finish(&mut self, _in_ram: bool) {}
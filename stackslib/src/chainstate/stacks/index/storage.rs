@@ -23,8 +23,9 @@ use std::iter::FromIterator;
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::SystemTime;
-use std::{cmp, env, error, fmt, fs, io, os};
+use std::{cmp, env, error, fmt, fs, io, os, thread};
 
 use rusqlite::types::{FromSql, ToSql};
 use rusqlite::{
@@ -371,6 +372,81 @@ impl<T: MarfTrieId> UncommittedState<T> {
     }
 }
 
+/// Minimum number of nodes a `TrieRAM` must have before its hashes are calculated on more than
+/// one thread.  Smaller tries hash faster than the workers can be started.
+pub const MIN_PARALLEL_HASH_NODES: usize = 1024;
+
+/// Block map used by trie hashing threads.  It holds the block hashes of all of a trie's
+/// back-pointers, which are resolved before hashing starts.
+struct ResolvedBlockMap<'a, T: MarfTrieId> {
+    block_hashes: &'a HashMap<u32, T>,
+}
+
+impl<T: MarfTrieId> BlockMap for ResolvedBlockMap<'_, T> {
+    type TrieId = T;
+
+    fn get_block_hash(&self, id: u32) -> Result<T, Error> {
+        self.block_hashes
+            .get(&id)
+            .cloned()
+            .ok_or(Error::NotFoundError)
+    }
+
+    fn get_block_hash_caching(&mut self, id: u32) -> Result<&T, Error> {
+        self.block_hashes.get(&id).ok_or(Error::NotFoundError)
+    }
+
+    fn is_block_hash_cached(&self, id: u32) -> bool {
+        self.block_hashes.contains_key(&id)
+    }
+
+    fn get_block_id(&self, _block_hash: &T) -> Result<u32, Error> {
+        Err(Error::NotFoundError)
+    }
+
+    fn get_block_id_caching(&mut self, block_hash: &T) -> Result<u32, Error> {
+        self.get_block_id(block_hash)
+    }
+}
+
+/// Recursively calculate the hash of the node at `node_ptr` in a `TrieRAM`'s `data`.  If
+/// `store_hashes` is set, the hashes of its non-leaf descendants are added to `node_hashes`, so
+/// the caller can store them.
+fn hash_subtrie<M: BlockMap>(
+    data: &[(TrieNodeType, TrieHash)],
+    block_map: &mut M,
+    node_ptr: u32,
+    store_hashes: bool,
+    node_hashes: &mut Vec<(u32, TrieHash)>,
+) -> Result<TrieHash, Error> {
+    let (node, node_hash) = data.get(node_ptr as usize).ok_or(Error::NotFoundError)?;
+    if node.is_leaf() {
+        return Ok(node_hash.clone());
+    }
+
+    let mut hasher = TrieHasher::new();
+    let empty_node_hash = TrieHash::from_data(&[]);
+    node.write_consensus_bytes(block_map, &mut hasher)?;
+    for ptr in node.ptrs().iter() {
+        if ptr.id() == TrieNodeID::Empty as u8 {
+            hasher.write_all(empty_node_hash.as_bytes())?;
+        } else if !is_backptr(ptr.id()) {
+            let child_hash = hash_subtrie(data, block_map, ptr.ptr(), store_hashes, node_hashes)?;
+            hasher.write_all(child_hash.as_bytes())?;
+            if store_hashes && ptr.id() != TrieNodeID::Leaf as u8 {
+                node_hashes.push((ptr.ptr(), child_hash));
+            }
+        } else {
+            let block_hash = block_map.get_block_hash_caching(ptr.back_block())?;
+            hasher.write_all(block_hash.as_bytes())?;
+        }
+    }
+
+    let mut buf = [0u8; 32];
+    buf.copy_from_slice(hasher.finalize().as_slice());
+    Ok(TrieHash(buf))
+}
+
 /// In-RAM trie storage.
 /// Used by TrieFileStorage to buffer the next trie being built.
 #[derive(Clone)]
@@ -637,7 +713,12 @@ impl<T: MarfTrieId> TrieRAM<T> {
     ) -> Result<TrieHash, Error> {
         // find trie root hash
         debug!("Calculate trie root hash");
-        let root_trie_hash = self.calculate_node_hashes(storage_tx, 0)?;
+        let hash_threads = storage_tx.deref().hash_threads;
+        let root_trie_hash = if hash_threads > 1 && self.data.len() >= MIN_PARALLEL_HASH_NODES {
+            self.calculate_node_hashes_parallel(storage_tx, hash_threads)?
+        } else {
+            self.calculate_node_hashes(storage_tx, 0)?
+        };
 
         // find marf root hash -- the hash of the trie root node hash, and the hashes of the
         // geometric series of ancestor tries.  Because the trie is already in the process of
@@ -819,6 +900,130 @@ impl<T: MarfTrieId> TrieRAM<T> {
         }
     }
 
+    /// Calculate all node hashes in this `TrieRAM` like `calculate_node_hashes()`, but hash the
+    /// subtries under the root node on `num_threads` worker threads.  The root node's children
+    /// don't depend on each other, so each worker takes the next unhashed child until there are
+    /// none left.  Back-pointer block hashes are looked up beforehand, since the workers can't
+    /// share `storage_tx`.  Returns the trie root hash, which is identical to the one
+    /// `calculate_node_hashes()` would calculate.
+    fn calculate_node_hashes_parallel(
+        &mut self,
+        storage_tx: &mut TrieStorageTransaction<T>,
+        num_threads: usize,
+    ) -> Result<TrieHash, Error> {
+        let start_time = storage_tx.bench.write_children_hashes_start();
+        let store_hashes =
+            TrieHashCalculationMode::Deferred == storage_tx.deref().hash_calculation_mode;
+
+        // resolve every back-pointer in the trie
+        let mut block_hashes = HashMap::new();
+        let mut frontier = vec![0u32];
+        while let Some(node_ptr) = frontier.pop() {
+            let (node, _) = self.get_nodetype(node_ptr)?;
+            if node.is_leaf() {
+                continue;
+            }
+            for ptr in node.ptrs().iter() {
+                if ptr.id() == TrieNodeID::Empty as u8 {
+                    continue;
+                }
+                if is_backptr(ptr.id()) {
+                    if !block_hashes.contains_key(&ptr.back_block()) {
+                        let block_hash = storage_tx.get_block_hash_caching(ptr.back_block())?;
+                        block_hashes.insert(ptr.back_block(), block_hash.clone());
+                    }
+                } else {
+                    frontier.push(ptr.ptr());
+                }
+            }
+        }
+
+        let (root, _) = self.get_nodetype(0)?.to_owned();
+        let children: Vec<TriePtr> = root
+            .ptrs()
+            .iter()
+            .filter(|ptr| ptr.id() != TrieNodeID::Empty as u8 && !is_backptr(ptr.id()))
+            .cloned()
+            .collect();
+
+        // hash each of the root's subtries
+        let next_child = AtomicUsize::new(0);
+        let data = &self.data;
+        let results: Vec<Result<_, Error>> = thread::scope(|s| {
+            let workers: Vec<_> = (0..num_threads.min(children.len()))
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut block_map = ResolvedBlockMap {
+                            block_hashes: &block_hashes,
+                        };
+                        let mut child_hashes = vec![];
+                        let mut node_hashes = vec![];
+                        loop {
+                            let i = next_child.fetch_add(1, Ordering::Relaxed);
+                            let Some(child) = children.get(i) else {
+                                return Ok((child_hashes, node_hashes));
+                            };
+                            let child_hash = hash_subtrie(
+                                data,
+                                &mut block_map,
+                                child.ptr(),
+                                store_hashes,
+                                &mut node_hashes,
+                            )?;
+                            if store_hashes && child.id() != TrieNodeID::Leaf as u8 {
+                                node_hashes.push((child.ptr(), child_hash.clone()));
+                            }
+                            child_hashes.push((child.ptr(), child_hash));
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("FATAL: trie hashing thread panicked"))
+                .collect()
+        });
+
+        let mut child_hashes = HashMap::with_capacity(children.len());
+        for result in results.into_iter() {
+            let (worker_child_hashes, node_hashes) = result?;
+            child_hashes.extend(worker_child_hashes);
+            for (node_ptr, node_hash) in node_hashes.into_iter() {
+                // need to store this hash too, since we deferred calculation
+                self.write_node_hash(node_ptr, node_hash)?;
+            }
+        }
+
+        // hash the root from its children
+        let mut block_map = ResolvedBlockMap {
+            block_hashes: &block_hashes,
+        };
+        let mut hasher = TrieHasher::new();
+        let empty_node_hash = TrieHash::from_data(&[]);
+        root.write_consensus_bytes(&mut block_map, &mut hasher)?;
+        for ptr in root.ptrs().iter() {
+            if ptr.id() == TrieNodeID::Empty as u8 {
+                hasher.write_all(empty_node_hash.as_bytes())?;
+            } else if !is_backptr(ptr.id()) {
+                let child_hash = child_hashes.get(&ptr.ptr()).ok_or_else(|| {
+                    Error::CorruptionError(format!("No hash for root child {}", ptr.ptr()))
+                })?;
+                hasher.write_all(child_hash.as_bytes())?;
+            } else {
+                let block_hash = block_map.get_block_hash_caching(ptr.back_block())?;
+                hasher.write_all(block_hash.as_bytes())?;
+            }
+        }
+
+        storage_tx
+            .bench
+            .write_children_hashes_parallel_finish(start_time, num_threads);
+
+        let mut buf = [0u8; 32];
+        buf.copy_from_slice(hasher.finalize().as_slice());
+        Ok(TrieHash(buf))
+    }
+
     /// Walk through the buffered TrieNodes and dump them to f.
     /// This consumes this TrieRAM instance.
     fn dump_consume<F: Write + Seek>(mut self, f: &mut F) -> Result<u64, Error> {
@@ -1221,6 +1426,8 @@ pub struct TrieStorageConnection<'a, T: MarfTrieId> {
     cache: &'a mut TrieCache<T>,
     bench: &'a mut TrieBenchmark,
     pub hash_calculation_mode: TrieHashCalculationMode,
+    /// number of threads to use when calculating node hashes at seal time
+    pub hash_threads: usize,
 
     /// row ID of a trie that represents unconfirmed state (i.e. trie state that will never become
     /// part of the MARF, but nevertheless represents a persistent scratch space).  If this field
@@ -1275,6 +1482,21 @@ pub struct TrieStorageTransientData<T: MarfTrieId> {
     unconfirmed: bool,
 }
 
+/// Outcome of pruning a MARF's tries
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriePruneStats {
+    /// Number of tries whose blobs were kept
+    pub tries_kept: u64,
+    /// Number of tries whose blobs were pruned
+    pub tries_pruned: u64,
+    /// Number of nodes of pruned tries that were kept, because unpruned tries still reach them
+    pub nodes_kept: u64,
+    /// Size of the blobs file before pruning
+    pub blobs_size_before: u64,
+    /// Size of the blobs file after pruning
+    pub blobs_size_after: u64,
+}
+
 // disk-backed Trie.
 // Keeps the last-extended Trie in-RAM and flushes it to disk on either a call to flush() or a call
 // to extend_to_block() with a different block header hash.
@@ -1287,6 +1509,7 @@ pub struct TrieFileStorage<T: MarfTrieId> {
     cache: TrieCache<T>,
     bench: TrieBenchmark,
     hash_calculation_mode: TrieHashCalculationMode,
+    hash_threads: usize,
 
     // used in testing in order to short-circuit block-height lookups
     //   when the trie struct is tested outside of marf.rs usage
@@ -1333,6 +1556,7 @@ impl<T: MarfTrieId> TrieFileStorage<T> {
             cache: &mut self.cache,
             bench: &mut self.bench,
             hash_calculation_mode: self.hash_calculation_mode,
+            hash_threads: self.hash_threads,
             unconfirmed_block_id: None,
 
             #[cfg(test)]
@@ -1354,6 +1578,7 @@ impl<T: MarfTrieId> TrieFileStorage<T> {
             cache: &mut self.cache,
             bench: &mut self.bench,
             hash_calculation_mode: self.hash_calculation_mode,
+            hash_threads: self.hash_threads,
             unconfirmed_block_id: None,
 
             #[cfg(test)]
@@ -1427,7 +1652,9 @@ impl<T: MarfTrieId> TrieFileStorage<T> {
         };
 
         let prev_schema_version = trie_sql::migrate_tables_if_needed::<T>(&mut db)?;
-        if prev_schema_version != trie_sql::SQL_MARF_SCHEMA_VERSION || marf_opts.force_db_migrate {
+        if prev_schema_version < trie_sql::SQL_MARF_EXTERNAL_BLOBS_SCHEMA_VERSION
+            || marf_opts.force_db_migrate
+        {
             if let Some(blobs) = blobs.as_mut() {
                 if TrieFile::exists(&db_path)? {
                     // migrate blobs out of the old DB
@@ -1438,6 +1665,9 @@ impl<T: MarfTrieId> TrieFileStorage<T> {
         if trie_sql::detect_partial_migration(&db)? {
             panic!("PARTIAL MIGRATION DETECTED! This is an irrecoverable error. You will need to restart your node from genesis.");
         }
+        if let Some(blobs) = blobs.as_mut() {
+            blobs.recover_compaction(&db, readonly)?;
+        }
 
         debug!(
             "Opened TrieFileStorage {}; external blobs: {}",
@@ -1454,6 +1684,7 @@ impl<T: MarfTrieId> TrieFileStorage<T> {
             blobs,
             bench: TrieBenchmark::new(),
             hash_calculation_mode: marf_opts.hash_calculation_mode,
+            hash_threads: marf_opts.hash_threads,
 
             data: TrieStorageTransientData {
                 uncommitted_writes: None,
@@ -1484,6 +1715,168 @@ impl<T: MarfTrieId> TrieFileStorage<T> {
         Ok(ret)
     }
 
+    /// Prune every trie that is not in `retain`.  Of a pruned trie, only the nodes that the
+    /// retained tries still reach are kept, so the state of the retained tries can still be read,
+    /// but the state of a pruned trie cannot.  The root hashes of pruned tries are kept too, so
+    /// the retained tries can still be extended.
+    ///
+    /// If `compact` is set, the blobs file is replaced by a compacted copy without the pruned
+    /// nodes (or the nodes pruned by earlier calls), so no other handle to this MARF may be open
+    /// while this runs.  Otherwise, the pruned nodes are only marked as such, and keep taking up
+    /// space until the next compaction.
+    pub fn prune_tries(
+        &mut self,
+        retain: &HashSet<T>,
+        compact: bool,
+    ) -> Result<TriePruneStats, Error> {
+        if self.readonly() {
+            return Err(Error::ReadOnlyError);
+        }
+        if self.data.uncommitted_writes.is_some() {
+            return Err(Error::InProgressError);
+        }
+        if trie_sql::detect_partial_migration(&self.db)? {
+            panic!("PARTIAL MIGRATION DETECTED! This is an irrecoverable error. You will need to restart your node from genesis.");
+        }
+        let blobs = match self.blobs.as_mut() {
+            Some(blobs) => blobs,
+            None => {
+                return Err(Error::CorruptionError(format!(
+                    "Cannot prune {}: trie blobs are not stored externally",
+                    &self.db_path
+                )));
+            }
+        };
+        let root_ptr = TriePtr::new(
+            TrieNodeID::Node256 as u8,
+            0,
+            TrieStorageConnection::<T>::root_ptr_disk(),
+        );
+
+        let mut retained = HashSet::new();
+        for bhh in retain.iter() {
+            if let Some(block_id) = trie_sql::get_confirmed_block_identifier(&self.db, bhh)? {
+                retained.insert(block_id);
+            }
+        }
+
+        let (kept, dropped): (Vec<_>, Vec<_>) = trie_sql::get_external_trie_blobs(&self.db)?
+            .into_iter()
+            .partition(|(block_id, _, _)| retained.contains(block_id));
+
+        let blobs_size_before = trie_sql::get_external_blobs_length(&self.db)?;
+        if dropped.is_empty() {
+            // nothing new to prune, but there may be space to reclaim from earlier prunes
+            let (num_nodes, nodes_size) = trie_sql::get_pruned_trie_nodes_size(&self.db)?;
+            let blobs_size_after =
+                kept.iter().map(|(_, _, length)| *length).sum::<u64>() + nodes_size;
+            if !compact || blobs_size_after == blobs_size_before {
+                debug!("No tries to prune in {}", &self.db_path);
+                return Ok(TriePruneStats {
+                    tries_kept: kept.len() as u64,
+                    tries_pruned: 0,
+                    nodes_kept: num_nodes,
+                    blobs_size_before,
+                    blobs_size_after: blobs_size_before,
+                });
+            }
+        }
+
+        let live_nodes = blobs.find_live_nodes(&self.db, &retained, &root_ptr)?;
+        let mut stats = TriePruneStats {
+            tries_kept: kept.len() as u64,
+            tries_pruned: dropped.len() as u64,
+            nodes_kept: live_nodes.len() as u64,
+            blobs_size_before,
+            blobs_size_after: blobs_size_before,
+        };
+
+        let mut dropped_roots = Vec::with_capacity(dropped.len());
+        for (block_id, _, _) in dropped.iter() {
+            let root_hash = blobs.get_node_hash_bytes(&self.db, *block_id, &root_ptr)?;
+            dropped_roots.push((*block_id, root_hash));
+        }
+
+        if !compact {
+            // the kept nodes of the newly-pruned tries stay where they are
+            let dropped_ids: HashSet<u32> =
+                dropped.iter().map(|(block_id, _, _)| *block_id).collect();
+            let mut node_offsets = vec![];
+            for (block_id, ptr) in live_nodes.iter() {
+                if dropped_ids.contains(block_id) {
+                    let (offset, node_bytes) = blobs.read_node_bytes(&self.db, *block_id, ptr)?;
+                    node_offsets.push((*block_id, ptr.ptr(), offset, node_bytes.len() as u64));
+                }
+            }
+
+            let tx = tx_begin_immediate(&mut self.db)?;
+            for (block_id, ptr, offset, length) in node_offsets.iter() {
+                trie_sql::set_pruned_trie_node(&tx, *block_id, *ptr, *offset, *length)?;
+            }
+            for (block_id, root_hash) in dropped_roots.iter() {
+                trie_sql::prune_external_trie_blob(&tx, *block_id, root_hash)?;
+            }
+            trie_sql::set_migrated(&tx)?;
+            tx.commit()?;
+            blobs.clear_trie_offsets();
+            self.cache.clear();
+
+            info!(
+                "Pruned {} of {} tries in {} without compacting it",
+                stats.tries_pruned,
+                stats.tries_pruned + stats.tries_kept,
+                &self.db_path;
+                "nodes_kept" => stats.nodes_kept,
+            );
+            return Ok(stats);
+        }
+
+        let (compacted, offsets, node_offsets) =
+            blobs.write_compaction(&self.db, &kept, &live_nodes)?;
+        stats.blobs_size_after = kept.iter().map(|(_, _, length)| *length).sum::<u64>()
+            + node_offsets
+                .iter()
+                .map(|(_, _, _, length)| *length)
+                .sum::<u64>();
+
+        // once this commits, the compacted blobs file is authoritative, even if we crash before
+        // moving it into place
+        let tx = tx_begin_immediate(&mut self.db)?;
+        for (block_id, offset) in offsets.iter() {
+            trie_sql::set_external_trie_offset(&tx, *block_id, *offset)?;
+        }
+        for (block_id, root_hash) in dropped_roots.iter() {
+            trie_sql::prune_external_trie_blob(&tx, *block_id, root_hash)?;
+        }
+        trie_sql::clear_pruned_trie_nodes(&tx)?;
+        for (block_id, ptr, offset, length) in node_offsets.iter() {
+            trie_sql::set_pruned_trie_node(&tx, *block_id, *ptr, *offset, *length)?;
+        }
+        trie_sql::set_pending_blob_compaction(&tx, &blobs.get_path())?;
+        trie_sql::set_migrated(&tx)?;
+        tx.commit()?;
+
+        blobs.install_compaction(compacted)?;
+        trie_sql::clear_pending_blob_compaction(&self.db)?;
+        self.cache.clear();
+
+        info!(
+            "Pruned {} of {} tries in {}",
+            stats.tries_pruned,
+            stats.tries_pruned + stats.tries_kept,
+            &self.db_path;
+            "nodes_kept" => stats.nodes_kept,
+            "blobs_size_before" => stats.blobs_size_before,
+            "blobs_size_after" => stats.blobs_size_after,
+        );
+        Ok(stats)
+    }
+
+    /// Has this block's trie been pruned?
+    pub fn is_pruned(&self, bhh: &T) -> Result<bool, Error> {
+        trie_sql::is_pruned_block(&self.db, bhh)
+    }
+
     #[cfg(test)]
     pub fn new_memory(marf_opts: MARFOpenOpts) -> Result<TrieFileStorage<T>, Error> {
         TrieFileStorage::open(":memory:", marf_opts)
@@ -1543,6 +1936,7 @@ impl<T: MarfTrieId> TrieFileStorage<T> {
             cache: cache,
             bench: TrieBenchmark::new(),
             hash_calculation_mode: self.hash_calculation_mode,
+            hash_threads: self.hash_threads,
 
             data: TrieStorageTransientData {
                 uncommitted_writes: self.data.uncommitted_writes.clone(),
@@ -1612,6 +2006,7 @@ impl<'a, T: MarfTrieId> TrieStorageTransaction<'a, T> {
             cache: cache,
             bench: TrieBenchmark::new(),
             hash_calculation_mode: self.hash_calculation_mode,
+            hash_threads: self.hash_threads,
 
             data: TrieStorageTransientData {
                 uncommitted_writes: None,
@@ -1959,6 +2354,11 @@ impl<'a, T: MarfTrieId> TrieStorageTransaction<'a, T> {
 }
 
 impl<'a, T: MarfTrieId> TrieStorageConnection<'a, T> {
+    /// Has this block's trie been pruned?
+    pub fn is_pruned(&self, bhh: &T) -> Result<bool, Error> {
+        trie_sql::is_pruned_block(&self.db, bhh)
+    }
+
     pub fn readonly(&self) -> bool {
         self.data.readonly
     }
@@ -2479,7 +2879,14 @@ impl<'a, T: MarfTrieId> TrieStorageConnection<'a, T> {
             return trie_sql::get_node_hash_bytes(&self.db, block_id, ptr);
         }
         let node_hash = match self.blobs.as_mut() {
-            Some(blobs) => blobs.get_node_hash_bytes(&self.db, block_id, ptr),
+            Some(blobs) => match blobs.get_node_hash_bytes(&self.db, block_id, ptr) {
+                Err(Error::PrunedError) if ptr.ptr() == Self::root_ptr_disk() => {
+                    // a pruned trie's root hash is still needed to hash its descendants
+                    trie_sql::get_pruned_trie_root_hash(&self.db, block_id)?
+                        .ok_or(Error::PrunedError)
+                }
+                res => res,
+            },
             None => trie_sql::get_node_hash_bytes(&self.db, block_id, ptr),
         }?;
         Ok(node_hash)
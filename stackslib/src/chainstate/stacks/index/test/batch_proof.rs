@@ -0,0 +1,141 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use libmarfproof::MarfBatchProof;
+use stacks_common::codec::StacksMessageCodec;
+
+use super::*;
+use crate::chainstate::stacks::index::cache::test::make_test_insert_data;
+use crate::chainstate::stacks::index::*;
+
+fn batch_proof_test_block_hash(i: usize) -> BlockHeaderHash {
+    let mut block_hash_bytes = [0u8; 32];
+    block_hash_bytes[0..8].copy_from_slice(&(i as u64).to_be_bytes());
+    BlockHeaderHash(block_hash_bytes)
+}
+
+/// Verify a batch proof, trusting the root hashes of every ancestor trie it includes
+fn verify_batch_proof(
+    marf: &mut MARF<BlockHeaderHash>,
+    block: &BlockHeaderHash,
+    proof: &MarfBatchProof,
+) -> libmarfproof::VerifiedMarfState {
+    let mut trusted_roots = HashMap::new();
+    for trie in proof.tries.iter().skip(1) {
+        let ancestor = BlockHeaderHash(trie.block_id.0.clone());
+        trusted_roots.insert(
+            trie.block_id.clone(),
+            marf.get_root_hash_at(&ancestor).unwrap(),
+        );
+    }
+    let state_root = marf.get_root_hash_at(block).unwrap();
+    proof.verify(&state_root, &trusted_roots).unwrap()
+}
+
+#[test]
+fn test_marf_batch_proof() {
+    let data = make_test_insert_data(64, 32);
+    for marf_opts in MARFOpenOpts::all().into_iter() {
+        let f = TrieFileStorage::new_memory(marf_opts).unwrap();
+        let mut marf = MARF::from_storage(f);
+
+        // blocks 0-23 form a chain, and blocks 24-31 fork off of block 11.  Every block also
+        // overwrites some older keys, so that keys are spread over many tries.
+        for i in 0..32 {
+            let parent = match i {
+                0 => BlockHeaderHash::sentinel(),
+                24 => batch_proof_test_block_hash(11),
+                _ => batch_proof_test_block_hash(i - 1),
+            };
+            marf.begin(&parent, &batch_proof_test_block_hash(i))
+                .unwrap();
+            for (key, value) in data[i].iter() {
+                marf.insert(key, value.clone()).unwrap();
+            }
+            for (key, _) in data[i / 2].iter().step_by(8) {
+                marf.insert(key, data[i][0].1.clone()).unwrap();
+            }
+            marf.commit().unwrap();
+        }
+
+        for tip in [0, 11, 23, 31] {
+            let block = batch_proof_test_block_hash(tip);
+            let mut keys: Vec<String> = data.iter().flatten().map(|(key, _)| key.clone()).collect();
+            keys.extend((0..16).map(|i| format!("missing-key-{}", i)));
+
+            let proof = marf.get_batch_proof(&block, &keys).unwrap();
+            assert_eq!(
+                proof.tries[0].block_id.0, block.0,
+                "first trie is the tip's trie"
+            );
+
+            let bytes = proof.serialize_to_vec();
+            let decoded = MarfBatchProof::consensus_deserialize(&mut &bytes[..]).unwrap();
+            assert_eq!(decoded, proof);
+
+            let state = verify_batch_proof(&mut marf, &block, &decoded);
+            for key in keys.iter() {
+                let expected = marf.get(&block, key).unwrap().map(|value| value.0);
+                assert_eq!(state.get(key).unwrap(), expected, "key {}", key);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_marf_batch_proof_shares_nodes() {
+    let data = make_test_insert_data(128, 8);
+    let f = TrieFileStorage::new_memory(MARFOpenOpts::default()).unwrap();
+    let mut marf = MARF::from_storage(f);
+    for i in 0..8 {
+        let parent = match i {
+            0 => BlockHeaderHash::sentinel(),
+            _ => batch_proof_test_block_hash(i - 1),
+        };
+        marf.begin(&parent, &batch_proof_test_block_hash(i))
+            .unwrap();
+        for (key, value) in data[i].iter() {
+            marf.insert(key, value.clone()).unwrap();
+        }
+        marf.commit().unwrap();
+    }
+
+    let block = batch_proof_test_block_hash(7);
+    let keys: Vec<String> = data[7].iter().map(|(key, _)| key.clone()).collect();
+    let proof = marf.get_batch_proof(&block, &keys).unwrap();
+
+    // the keys were all written in the tip, so they only need its trie
+    assert_eq!(proof.tries.len(), 1);
+
+    // ...and the batch proof is smaller than the individual proofs put together
+    let mut separate_len = 0;
+    for key in keys.iter() {
+        let (_, proof) = marf.get_with_proof(&block, key).unwrap().unwrap();
+        separate_len += proof.serialize_to_vec().len();
+    }
+    assert!(proof.serialize_to_vec().len() < separate_len);
+
+    let state = verify_batch_proof(&mut marf, &block, &proof);
+    for (key, value) in data[7].iter() {
+        assert_eq!(state.get(key).unwrap(), Some(value.0.clone()));
+    }
+
+    // an empty batch reveals just the root
+    let proof = marf.get_batch_proof(&block, &[]).unwrap();
+    assert_eq!(proof.tries.len(), 1);
+    assert_eq!(proof.tries[0].nodes.len(), 1);
+    verify_batch_proof(&mut marf, &block, &proof);
+}
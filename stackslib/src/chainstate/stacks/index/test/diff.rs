@@ -0,0 +1,196 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::*;
+use crate::chainstate::stacks::index::cache::test::make_test_insert_data;
+use crate::chainstate::stacks::index::diff::*;
+use crate::chainstate::stacks::index::marf::*;
+use crate::chainstate::stacks::index::storage::*;
+use crate::chainstate::stacks::index::*;
+
+fn diff_test_block_hash(i: usize) -> BlockHeaderHash {
+    let mut block_hash_bytes = [0u8; 32];
+    block_hash_bytes[0..8].copy_from_slice(&(i as u64).to_be_bytes());
+    BlockHeaderHash(block_hash_bytes)
+}
+
+fn make_diff(key: &str, from: Option<MARFValue>, to: Option<MARFValue>) -> TrieLeafDiff {
+    TrieLeafDiff {
+        path: TriePath::from_key(key),
+        key: None,
+        from,
+        to,
+    }
+}
+
+fn make_known_diff(key: &str, from: Option<MARFValue>, to: Option<MARFValue>) -> TrieLeafDiff {
+    TrieLeafDiff {
+        key: Some(key.to_string()),
+        ..make_diff(key, from, to)
+    }
+}
+
+#[test]
+fn test_marf_diff() {
+    for marf_opts in MARFOpenOpts::all().into_iter() {
+        let f = TrieFileStorage::new_memory(marf_opts).unwrap();
+        let mut marf = MARF::from_storage(f);
+
+        let value = |s: &str| MARFValue::from_value(s);
+        let b1 = diff_test_block_hash(1);
+        let b2 = diff_test_block_hash(2);
+        let b3 = diff_test_block_hash(3);
+
+        marf.begin(&BlockHeaderHash::sentinel(), &b1).unwrap();
+        for i in 0..256 {
+            marf.insert(&format!("key-{}", i), value(&format!("value-{}", i)))
+                .unwrap();
+        }
+        marf.commit().unwrap();
+
+        marf.begin(&b1, &b2).unwrap();
+        marf.insert("key-5", value("changed")).unwrap();
+        marf.insert("new-key", value("new")).unwrap();
+        // unchanged
+        marf.insert("key-6", value("value-6")).unwrap();
+        marf.commit().unwrap();
+
+        // sibling of b2
+        marf.begin(&b1, &b3).unwrap();
+        marf.insert("key-7", value("changed")).unwrap();
+        marf.commit().unwrap();
+
+        assert!(marf.diff(&b1, &b1).unwrap().is_empty());
+
+        let mut expected = vec![
+            make_diff("key-5", Some(value("value-5")), Some(value("changed"))),
+            make_diff("new-key", None, Some(value("new"))),
+            make_known_diff(
+                OWN_BLOCK_HEIGHT_KEY,
+                Some(MARFValue::from(0u32)),
+                Some(MARFValue::from(1u32)),
+            ),
+            make_known_diff(
+                &format!("{}::1", BLOCK_HEIGHT_TO_HASH_MAPPING_KEY),
+                None,
+                Some(MARFValue::from(b2.clone())),
+            ),
+            make_known_diff(
+                &format!("{}::{}", BLOCK_HASH_TO_HEIGHT_MAPPING_KEY, &b2),
+                None,
+                Some(MARFValue::from(1u32)),
+            ),
+        ];
+        expected.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+
+        let mut diffs = marf.diff(&b1, &b2).unwrap();
+        assert_eq!(diffs, expected);
+
+        // the diff in the other direction is the mirror image
+        let reversed: Vec<_> = marf
+            .diff(&b2, &b1)
+            .unwrap()
+            .into_iter()
+            .map(|diff| TrieLeafDiff {
+                from: diff.to,
+                to: diff.from,
+                ..diff
+            })
+            .collect();
+        assert_eq!(reversed, expected);
+
+        // keys are filled in from the candidates that match
+        let candidates = ["key-4", "key-5", "new-key", "key-6"].map(|key| key.to_string());
+        assert_eq!(resolve_keys(&mut diffs, candidates), 2);
+        for diff in diffs.iter() {
+            assert!(diff.key.is_some());
+        }
+
+        // blocks on different forks
+        let mut expected = vec![
+            make_diff("key-5", Some(value("changed")), Some(value("value-5"))),
+            make_diff("key-7", Some(value("value-7")), Some(value("changed"))),
+            make_diff("new-key", Some(value("new")), None),
+            make_known_diff(
+                &format!("{}::1", BLOCK_HEIGHT_TO_HASH_MAPPING_KEY),
+                Some(MARFValue::from(b2.clone())),
+                Some(MARFValue::from(b3.clone())),
+            ),
+            make_known_diff(
+                &format!("{}::{}", BLOCK_HASH_TO_HEIGHT_MAPPING_KEY, &b2),
+                Some(MARFValue::from(1u32)),
+                None,
+            ),
+            make_known_diff(
+                &format!("{}::{}", BLOCK_HASH_TO_HEIGHT_MAPPING_KEY, &b3),
+                None,
+                Some(MARFValue::from(1u32)),
+            ),
+        ];
+        expected.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+        assert_eq!(marf.diff(&b2, &b3).unwrap(), expected);
+    }
+}
+
+#[test]
+fn test_marf_diff_matches_reads() {
+    let data = make_test_insert_data(64, 48);
+    let f = TrieFileStorage::new_memory(MARFOpenOpts::default()).unwrap();
+    let mut marf = MARF::from_storage(f);
+
+    // blocks 0-31 form a chain, and blocks 32-47 fork off of block 15.  Every block also
+    // overwrites some older keys.
+    for i in 0..48 {
+        let parent = match i {
+            0 => BlockHeaderHash::sentinel(),
+            32 => diff_test_block_hash(15),
+            _ => diff_test_block_hash(i - 1),
+        };
+        marf.begin(&parent, &diff_test_block_hash(i)).unwrap();
+        for (key, value) in data[i].iter() {
+            marf.insert(key, value.clone()).unwrap();
+        }
+        for (key, _) in data[i / 2].iter().step_by(8) {
+            marf.insert(key, data[i][0].1.clone()).unwrap();
+        }
+        marf.commit().unwrap();
+    }
+
+    for (from, to) in [(15, 31), (31, 15), (20, 40), (47, 31), (0, 47), (31, 32)] {
+        let from = diff_test_block_hash(from);
+        let to = diff_test_block_hash(to);
+        let diffs = marf.diff(&from, &to).unwrap();
+
+        let mut expected = vec![];
+        for (key, _) in data.iter().flatten() {
+            let from_value = marf.get(&from, key).unwrap();
+            let to_value = marf.get(&to, key).unwrap();
+            if from_value != to_value {
+                expected.push(make_diff(key, from_value, to_value));
+            }
+        }
+        expected.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+
+        // everything else is block height bookkeeping, which is recognized as such
+        let (unknown, known): (Vec<_>, Vec<_>) =
+            diffs.into_iter().partition(|diff| diff.key.is_none());
+        assert_eq!(unknown, expected);
+        assert!(!known.is_empty());
+        for diff in known.iter() {
+            assert!(diff.key.as_ref().unwrap().starts_with("__MARF_BLOCK_"));
+        }
+    }
+}
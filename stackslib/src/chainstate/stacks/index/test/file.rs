@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::collections::HashSet;
 use std::fs;
 
 use rusqlite::{Connection, OpenFlags};
@@ -162,3 +163,173 @@ fn test_migrate_existing_trie_blobs() {
         }
     }
 }
+
+fn make_pruning_test_marf(
+    test_file: &str,
+    data: &[Vec<(String, MARFValue)>],
+) -> MARF<BlockHeaderHash> {
+    let test_blobs_file = format!("{}.blobs", test_file);
+    if fs::metadata(&test_file).is_ok() {
+        fs::remove_file(&test_file).unwrap();
+    }
+    if fs::metadata(&test_blobs_file).is_ok() {
+        fs::remove_file(&test_blobs_file).unwrap();
+    }
+
+    let marf_opts = MARFOpenOpts::new(TrieHashCalculationMode::Deferred, "noop", true);
+    let f = TrieFileStorage::open(&test_file, marf_opts).unwrap();
+    let mut marf = MARF::from_storage(f);
+    extend_pruning_test_marf(&mut marf, 0, data);
+    marf
+}
+
+fn pruning_test_block_hash(i: usize) -> BlockHeaderHash {
+    let mut block_hash_bytes = [0u8; 32];
+    block_hash_bytes[0..8].copy_from_slice(&(i as u64).to_be_bytes());
+    BlockHeaderHash(block_hash_bytes)
+}
+
+fn extend_pruning_test_marf(
+    marf: &mut MARF<BlockHeaderHash>,
+    start: usize,
+    data: &[Vec<(String, MARFValue)>],
+) {
+    for (i, block_data) in data.iter().enumerate().skip(start) {
+        let parent = if i == 0 {
+            BlockHeaderHash::sentinel()
+        } else {
+            pruning_test_block_hash(i - 1)
+        };
+        marf.begin(&parent, &pruning_test_block_hash(i)).unwrap();
+        for (key, value) in block_data.iter() {
+            marf.insert(key, value.clone()).unwrap();
+        }
+        marf.commit().unwrap();
+    }
+}
+
+#[test]
+fn test_prune_trie_blobs() {
+    let test_file = "/tmp/test_prune_trie_blobs.sqlite";
+    let test_blobs_file = "/tmp/test_prune_trie_blobs.sqlite.blobs";
+    let control_file = "/tmp/test_prune_trie_blobs_control.sqlite";
+
+    let data = make_test_insert_data(32, 72);
+
+    // two identical MARFs, one of which gets pruned
+    let mut marf = make_pruning_test_marf(test_file, &data[0..64]);
+    let mut control_marf = make_pruning_test_marf(control_file, &data[0..64]);
+
+    let retain: HashSet<BlockHeaderHash> = (48..64).map(pruning_test_block_hash).collect();
+    let stats = marf.prune(&retain, true).unwrap();
+    assert_eq!(stats.tries_kept, retain.len() as u64);
+    assert_eq!(stats.tries_pruned, 48);
+    assert!(stats.nodes_kept > 0);
+    assert!(stats.blobs_size_after < stats.blobs_size_before);
+    assert_eq!(
+        fs::metadata(&test_blobs_file).unwrap().len(),
+        stats.blobs_size_after
+    );
+    assert!(fs::metadata(&format!("{}.compact", test_blobs_file)).is_err());
+
+    let check_marf = |marf: &mut MARF<BlockHeaderHash>| {
+        // all state as of a retained block is still readable
+        for i in 48..64 {
+            let tip = pruning_test_block_hash(i);
+            assert!(!marf.is_pruned(&tip).unwrap());
+            for block_data in [&data[0], &data[i / 2], &data[i]] {
+                for (key, value) in block_data.iter() {
+                    assert_eq!(marf.get(&tip, key).unwrap(), Some(value.clone()));
+                }
+            }
+        }
+
+        // state as of a pruned block is gone
+        let tip = pruning_test_block_hash(10);
+        assert!(marf.is_pruned(&tip).unwrap());
+        match marf.get(&tip, &data[10][0].0) {
+            Err(crate::chainstate::stacks::index::Error::PrunedError) => {}
+            x => panic!("Expected PrunedError, got {:?}", &x),
+        }
+    };
+
+    check_marf(&mut marf);
+
+    // pruned MARF can still be extended, and computes the same root hashes as the unpruned one
+    extend_pruning_test_marf(&mut marf, 64, &data);
+    extend_pruning_test_marf(&mut control_marf, 64, &data);
+    for i in 48..72 {
+        let tip = pruning_test_block_hash(i);
+        assert_eq!(
+            marf.get_root_hash_at(&tip).unwrap(),
+            control_marf.get_root_hash_at(&tip).unwrap()
+        );
+    }
+
+    // compaction survives reopening, and stale compactions get cleaned up
+    drop(marf);
+    fs::write(&format!("{}.compact", test_blobs_file), &[1, 2, 3]).unwrap();
+
+    let marf_opts = MARFOpenOpts::new(TrieHashCalculationMode::Deferred, "noop", true);
+    let f = TrieFileStorage::open(&test_file, marf_opts).unwrap();
+    let mut marf = MARF::from_storage(f);
+    assert!(fs::metadata(&format!("{}.compact", test_blobs_file)).is_err());
+
+    check_marf(&mut marf);
+    for (key, value) in data[71].iter() {
+        assert_eq!(
+            marf.get(&pruning_test_block_hash(71), key).unwrap(),
+            Some(value.clone())
+        );
+    }
+}
+
+#[test]
+fn test_prune_trie_blobs_without_compaction() {
+    let test_file = "/tmp/test_prune_trie_blobs_without_compaction.sqlite";
+    let test_blobs_file = "/tmp/test_prune_trie_blobs_without_compaction.sqlite.blobs";
+
+    let data = make_test_insert_data(32, 64);
+    let mut marf = make_pruning_test_marf(test_file, &data);
+    let blobs_size = fs::metadata(&test_blobs_file).unwrap().len();
+
+    // a reader that was open before pruning can still read what it could read before
+    let marf_opts = MARFOpenOpts::new(TrieHashCalculationMode::Deferred, "noop", true);
+    let mut reader = MARF::from_storage(TrieFileStorage::open(&test_file, marf_opts).unwrap());
+
+    let retain: HashSet<BlockHeaderHash> = (48..64).map(pruning_test_block_hash).collect();
+    let stats = marf.prune(&retain, false).unwrap();
+    assert!(stats.tries_pruned > 0);
+    assert_eq!(stats.blobs_size_after, stats.blobs_size_before);
+    assert_eq!(fs::metadata(&test_blobs_file).unwrap().len(), blobs_size);
+
+    for marf in [&mut marf, &mut reader] {
+        assert!(marf.is_pruned(&pruning_test_block_hash(10)).unwrap());
+        for (key, value) in data[63].iter() {
+            assert_eq!(
+                marf.get(&pruning_test_block_hash(63), key).unwrap(),
+                Some(value.clone())
+            );
+        }
+    }
+    match marf.get(&pruning_test_block_hash(10), &data[10][0].0) {
+        Err(crate::chainstate::stacks::index::Error::PrunedError) => {}
+        x => panic!("Expected PrunedError, got {:?}", &x),
+    }
+
+    // pruning again with the same blocks retained compacts away what was pruned before
+    drop(reader);
+    let stats = marf.prune(&retain, true).unwrap();
+    assert_eq!(stats.tries_pruned, 0);
+    assert!(stats.blobs_size_after < stats.blobs_size_before);
+    assert_eq!(
+        fs::metadata(&test_blobs_file).unwrap().len(),
+        stats.blobs_size_after
+    );
+    for (key, value) in data[63].iter() {
+        assert_eq!(
+            marf.get(&pruning_test_block_hash(63), key).unwrap(),
+            Some(value.clone())
+        );
+    }
+}
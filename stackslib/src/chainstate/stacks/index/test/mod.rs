@@ -36,7 +36,9 @@ use crate::chainstate::stacks::index::{
 };
 use crate::chainstate::stacks::{BlockHeaderHash, TrieHash};
 
+pub mod batch_proof;
 pub mod cache;
+pub mod diff;
 pub mod file;
 pub mod marf;
 pub mod node;
@@ -18,8 +18,10 @@ use std::collections::VecDeque;
 use std::fs;
 
 use rand::{thread_rng, Rng};
+use stacks_common::codec::StacksMessageCodec;
 
 use super::*;
+use crate::chainstate::stacks::index::cache::test::make_test_insert_data;
 use crate::chainstate::stacks::index::marf::*;
 use crate::chainstate::stacks::index::node::*;
 use crate::chainstate::stacks::index::*;
@@ -335,3 +337,72 @@ fn load_store_trie_4_256_same() {
 fn load_store_trie_4_256_unique() {
     load_store_trie_m_n_same(4, 256, false);
 }
+
+/// Hashing a trie on several threads must give the same root hash and intermediate node hashes
+/// as hashing it on one thread.
+#[test]
+fn test_parallel_trie_hashing() {
+    let data = make_test_insert_data(2048, 4);
+    let mut all_opts = vec![];
+    for external_blobs in [false, true] {
+        for hash_threads in [1, 2, 4] {
+            for mode in [
+                TrieHashCalculationMode::Immediate,
+                TrieHashCalculationMode::Deferred,
+                TrieHashCalculationMode::All,
+            ] {
+                all_opts.push(
+                    MARFOpenOpts::new(mode, "noop", external_blobs).with_hash_threads(hash_threads),
+                );
+            }
+        }
+    }
+
+    let mut expected_roots = None;
+    let mut expected_proofs = None;
+    for marf_opts in all_opts.into_iter() {
+        let f = TrieFileStorage::new_memory(marf_opts.clone()).unwrap();
+        let mut marf = MARF::from_storage(f);
+        let mut last_block = BlockHeaderHash::sentinel();
+        let mut roots = vec![];
+        for (i, block_data) in data.iter().enumerate() {
+            let mut block_hash_bytes = [0u8; 32];
+            block_hash_bytes[0..8].copy_from_slice(&(i as u64).to_be_bytes());
+            let block = BlockHeaderHash(block_hash_bytes);
+
+            // every block after the first also overwrites some of the first block's keys, so its
+            // trie has back-pointers to hash
+            marf.begin(&last_block, &block).unwrap();
+            let mut keys: Vec<String> = block_data.iter().map(|(key, _)| key.clone()).collect();
+            let mut values: Vec<MARFValue> =
+                block_data.iter().map(|(_, value)| value.clone()).collect();
+            if i > 0 {
+                for (key, _) in data[0].iter().step_by(4) {
+                    keys.push(key.clone());
+                    values.push(block_data[0].1.clone());
+                }
+            }
+            marf.insert_batch(&keys, values).unwrap();
+            marf.commit().unwrap();
+            roots.push(marf.get_root_hash_at(&block).unwrap());
+            last_block = block;
+        }
+
+        let mut proofs = vec![];
+        for (key, _) in data.iter().flatten().step_by(64) {
+            let (_, proof) = marf.get_with_proof(&last_block, key).unwrap().unwrap();
+            proofs.push(proof.serialize_to_vec());
+        }
+
+        eprintln!(
+            "MARF bench ({:?}): {:#?}",
+            &marf_opts,
+            &marf.borrow_storage_backend().get_benchmarks()
+        );
+
+        let expected_roots = expected_roots.get_or_insert_with(|| roots.clone());
+        assert_eq!(expected_roots, &roots, "root hashes with {:?}", &marf_opts);
+        let expected_proofs = expected_proofs.get_or_insert_with(|| proofs.clone());
+        assert_eq!(expected_proofs, &proofs, "proofs with {:?}", &marf_opts);
+    }
+}
@@ -25,7 +25,7 @@ use std::iter::FromIterator;
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
-use std::{error, fmt, fs, io, os};
+use std::{cmp, error, fmt, fs, io, os};
 
 use regex::Regex;
 use rusqlite::blob::Blob;
@@ -96,7 +96,38 @@ INSERT OR REPLACE INTO schema_version (version) VALUES (2);
 INSERT OR REPLACE INTO migrated_version (version) VALUES (1);
 ";
 
-pub static SQL_MARF_SCHEMA_VERSION: u64 = 2;
+static SQL_MARF_DATA_TABLE_SCHEMA_3: &str = "
+-- root hashes of tries whose blobs were pruned.  Descendant tries still mix them into their own
+-- root hashes.  A pruned trie's marf_data row has external_offset and external_length set to 0.
+CREATE TABLE IF NOT EXISTS pruned_trie_roots (
+    block_id INTEGER PRIMARY KEY,
+    root_hash TEXT NOT NULL
+);
+-- nodes of pruned tries that unpruned tries still reach, and where they are stored in the .blobs
+-- file.  `ptr` is the node's pointer within its trie.
+CREATE TABLE IF NOT EXISTS pruned_trie_nodes (
+    block_id INTEGER NOT NULL,
+    ptr INTEGER NOT NULL,
+    external_offset INTEGER NOT NULL,
+    external_length INTEGER NOT NULL,
+    PRIMARY KEY(block_id, ptr)
+);
+CREATE INDEX IF NOT EXISTS index_pruned_trie_nodes_offset ON pruned_trie_nodes(external_offset);
+-- compacted .blobs file that the marf_data offsets already refer to, but which has not yet been
+-- moved into place.
+CREATE TABLE IF NOT EXISTS pending_blob_compaction (
+    path TEXT PRIMARY KEY
+);
+
+-- the external blob migration, if it was finished, is still finished.
+UPDATE migrated_version SET version = 3 WHERE version = 2;
+UPDATE schema_version SET version = 3;
+";
+
+pub static SQL_MARF_SCHEMA_VERSION: u64 = 3;
+
+/// First schema version that supported external trie blobs
+pub static SQL_MARF_EXTERNAL_BLOBS_SCHEMA_VERSION: u64 = 2;
 
 pub fn create_tables_if_needed(conn: &mut Connection) -> Result<(), Error> {
     let tx = tx_begin_immediate(conn)?;
@@ -148,6 +179,14 @@ pub fn migrate_tables_if_needed<T: MarfTrieId>(conn: &mut Connection) -> Result<
                 tx.execute_batch(SQL_MARF_DATA_TABLE_SCHEMA_2)?;
                 tx.commit()?;
             }
+            2 => {
+                debug!("Migrate MARF data from schema 2 to schema 3");
+
+                // add pruning state
+                let tx = tx_begin_immediate(conn)?;
+                tx.execute_batch(SQL_MARF_DATA_TABLE_SCHEMA_3)?;
+                tx.commit()?;
+            }
             x if x == SQL_MARF_SCHEMA_VERSION => {
                 // done
                 debug!("Migrated MARF data to schema {}", &SQL_MARF_SCHEMA_VERSION);
@@ -536,12 +575,163 @@ pub fn get_external_trie_offset_length_by_bhh<T: MarfTrieId>(
     Ok((offset, length))
 }
 
-/// Determine the offset in the blobs file at which the last trie ends.  This is also the offset at
-/// which the next trie will be appended.
+/// Get the block ID, offset, and length of each confirmed trie blob in the blobs file, in the order
+/// in which they are stored.  Pruned tries are not included.
+pub fn get_external_trie_blobs(conn: &Connection) -> Result<Vec<(u32, u64, u64)>, Error> {
+    let qry = "SELECT block_id, external_offset, external_length FROM marf_data WHERE unconfirmed = 0 AND external_length > 0 ORDER BY external_offset";
+    let mut stmt = conn.prepare(qry)?;
+    let rows = stmt.query_and_then(NO_PARAMS, |row| {
+        let block_id: u32 = row.get("block_id")?;
+        let offset: i64 = row.get("external_offset")?;
+        let length: i64 = row.get("external_length")?;
+        Ok((block_id, offset as u64, length as u64))
+    })?;
+    rows.collect()
+}
+
+/// Move a trie blob to a new offset in the blobs file (i.e. the blobs file was compacted)
+pub fn set_external_trie_offset(
+    conn: &Connection,
+    block_id: u32,
+    offset: u64,
+) -> Result<(), Error> {
+    let args: &[&dyn ToSql] = &[&u64_to_sql(offset)?, &block_id];
+    conn.execute(
+        "UPDATE marf_data SET external_offset = ?1 WHERE block_id = ?2",
+        args,
+    )?;
+    Ok(())
+}
+
+/// Mark a trie's blob as pruned.  Its row stays, so its block ID stays reserved, but only its root
+/// hash, and the nodes recorded with `set_pruned_trie_node()`, can still be read.
+pub fn prune_external_trie_blob(
+    conn: &Connection,
+    block_id: u32,
+    root_hash: &TrieHash,
+) -> Result<(), Error> {
+    conn.execute(
+        "UPDATE marf_data SET external_offset = 0, external_length = 0 WHERE block_id = ?1",
+        &[&block_id],
+    )?;
+    let args: &[&dyn ToSql] = &[&block_id, root_hash];
+    conn.execute(
+        "INSERT OR REPLACE INTO pruned_trie_roots (block_id, root_hash) VALUES (?1, ?2)",
+        args,
+    )?;
+    Ok(())
+}
+
+/// Get the root hash of a pruned trie.  Returns None if the trie was not pruned.
+pub fn get_pruned_trie_root_hash(
+    conn: &Connection,
+    block_id: u32,
+) -> Result<Option<TrieHash>, Error> {
+    conn.query_row(
+        "SELECT root_hash FROM pruned_trie_roots WHERE block_id = ?1",
+        &[&block_id],
+        |row| row.get("root_hash"),
+    )
+    .optional()
+    .map_err(|e| e.into())
+}
+
+/// Record where a node of a pruned trie, which unpruned tries still reach, is stored
+pub fn set_pruned_trie_node(
+    conn: &Connection,
+    block_id: u32,
+    ptr: u32,
+    offset: u64,
+    length: u64,
+) -> Result<(), Error> {
+    let args: &[&dyn ToSql] = &[&block_id, &ptr, &u64_to_sql(offset)?, &u64_to_sql(length)?];
+    conn.execute(
+        "INSERT OR REPLACE INTO pruned_trie_nodes (block_id, ptr, external_offset, external_length) VALUES (?1, ?2, ?3, ?4)",
+        args,
+    )?;
+    Ok(())
+}
+
+/// Forget where every node of every pruned trie is stored (i.e. the blobs file was compacted)
+pub fn clear_pruned_trie_nodes(conn: &Connection) -> Result<(), Error> {
+    conn.execute("DELETE FROM pruned_trie_nodes", NO_PARAMS)?;
+    Ok(())
+}
+
+/// Get the number and the total size of the kept nodes of pruned tries
+pub fn get_pruned_trie_nodes_size(conn: &Connection) -> Result<(u64, u64), Error> {
+    let (num_nodes, size): (i64, i64) = conn.query_row(
+        "SELECT COUNT(*), IFNULL(SUM(external_length), 0) FROM pruned_trie_nodes",
+        NO_PARAMS,
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    Ok((num_nodes as u64, size as u64))
+}
+
+/// Get the offset of a node of a pruned trie in the blobs file.  Returns None if the node was
+/// pruned along with its trie.
+pub fn get_pruned_trie_node_offset(
+    conn: &Connection,
+    block_id: u32,
+    ptr: u32,
+) -> Result<Option<u64>, Error> {
+    let args: &[&dyn ToSql] = &[&block_id, &ptr];
+    let offset: Option<i64> = conn
+        .query_row(
+            "SELECT external_offset FROM pruned_trie_nodes WHERE block_id = ?1 AND ptr = ?2",
+            args,
+            |row| row.get("external_offset"),
+        )
+        .optional()?;
+    Ok(offset.map(|offset| offset as u64))
+}
+
+/// Has this block's trie been pruned?
+pub fn is_pruned_block<T: MarfTrieId>(conn: &Connection, bhh: &T) -> Result<bool, Error> {
+    let qry = "SELECT 1 FROM marf_data JOIN pruned_trie_roots ON marf_data.block_id = pruned_trie_roots.block_id WHERE marf_data.block_hash = ?1";
+    let res = conn
+        .query_row(qry, &[bhh], |_row| Ok(()))
+        .optional()?
+        .is_some();
+    Ok(res)
+}
+
+/// Record that the trie offsets now refer to the compacted blobs file at `path`
+pub fn set_pending_blob_compaction(conn: &Connection, path: &str) -> Result<(), Error> {
+    conn.execute(
+        "INSERT OR REPLACE INTO pending_blob_compaction (path) VALUES (?1)",
+        &[&path],
+    )?;
+    Ok(())
+}
+
+/// Get the compacted blobs file that still needs to be moved into place, if there is one
+pub fn get_pending_blob_compaction(conn: &Connection) -> Result<Option<String>, Error> {
+    conn.query_row(
+        "SELECT path FROM pending_blob_compaction LIMIT 1",
+        NO_PARAMS,
+        |row| row.get("path"),
+    )
+    .optional()
+    .map_err(|e| e.into())
+}
+
+/// Record that the compacted blobs file has been moved into place
+pub fn clear_pending_blob_compaction(conn: &Connection) -> Result<(), Error> {
+    conn.execute("DELETE FROM pending_blob_compaction", NO_PARAMS)?;
+    Ok(())
+}
+
+/// Determine the offset in the blobs file at which the last trie (or the last kept node of a pruned
+/// trie) ends.  This is also the offset at which the next trie will be appended.
 pub fn get_external_blobs_length(conn: &Connection) -> Result<u64, Error> {
     let qry = "SELECT (external_offset + external_length) AS blobs_length FROM marf_data ORDER BY external_offset DESC LIMIT 1";
     let max_len = query_row(conn, qry, NO_PARAMS)?.unwrap_or(0);
-    Ok(max_len)
+
+    // the kept nodes of pruned tries may be stored after the last trie
+    let qry = "SELECT (external_offset + external_length) AS blobs_length FROM pruned_trie_nodes ORDER BY external_offset DESC LIMIT 1";
+    let max_node_len = query_row(conn, qry, NO_PARAMS)?.unwrap_or(0);
+    Ok(cmp::max(max_len, max_node_len))
 }
 
 /// Do we have a partially-migrated database?
@@ -120,6 +120,8 @@ pub enum Error {
     ProblematicTransaction(Txid),
     MinerAborted,
     ChannelClosed(String),
+    /// A block could not be validated because it reads Clarity state that this node has pruned
+    PrunedStateError(String),
 }
 
 impl From<marf_error> for Error {
@@ -205,6 +207,7 @@ impl fmt::Display for Error {
             Error::PoxInvalidIncrease => write!(f, "PoX increase was invalid"),
             Error::MinerAborted => write!(f, "Mining attempt aborted by signal"),
             Error::ChannelClosed(ref s) => write!(f, "Channel '{}' closed", s),
+            Error::PrunedStateError(ref s) => fmt::Display::fmt(s, f),
         }
     }
 }
@@ -245,6 +248,7 @@ impl error::Error for Error {
             Error::PoxInvalidIncrease => None,
             Error::MinerAborted => None,
             Error::ChannelClosed(ref _s) => None,
+            Error::PrunedStateError(ref _s) => None,
         }
     }
 }
@@ -285,6 +289,7 @@ impl Error {
             Error::PoxInvalidIncrease => "PoxInvalidIncrease",
             Error::MinerAborted => "MinerAborted",
             Error::ChannelClosed(ref _s) => "ChannelClosed",
+            Error::PrunedStateError(ref _s) => "PrunedStateError",
         }
     }
 
@@ -0,0 +1,700 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Integrity checks of a node's sortition DB and chainstate.
+//!
+//! The verifier walks the canonical burnchain fork and the canonical Stacks fork, and re-derives
+//! everything it can from the stored data:
+//!
+//! * every sortition builds on its parent, has the sortition ID and consensus hash that its
+//! data hashes to, and has the sortition MARF root that it claims;
+//! * every Stacks block header builds on its parent and was chosen by a canonical sortition;
+//! * every Stacks block's headers index root and Clarity state root match its header;
+//! * every Stacks block's data decodes (and so matches its transaction Merkle root) and carries
+//! the header the chainstate has for it;
+//! * every confirmed microblock stream is stored, processed, and connected from the parent block
+//! to the microblock its child confirms.
+//!
+//! Checks run from the first block up, so the reported inconsistency is the lowest one: the
+//! data below it can be trusted.  The state and block data of pruned blocks are not checked.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use stacks_common::codec::StacksMessageCodec;
+use stacks_common::types::chainstate::{
+    BlockHeaderHash, BurnchainHeaderHash, ConsensusHash, PoxId, SortitionId, StacksBlockId,
+    TrieHash,
+};
+
+use crate::chainstate::burn::db::sortdb::SortitionDB;
+use crate::chainstate::burn::{ConsensusHashExtensions, OpsHash};
+use crate::chainstate::stacks::db::{StacksChainState, StacksHeaderInfo};
+use crate::chainstate::stacks::{Error, StacksMicroblock};
+use crate::core::EMPTY_MICROBLOCK_PARENT_HASH;
+use crate::util_lib::db::Error as db_error;
+
+/// The first problem found by the verifier
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inconsistency {
+    /// A sortition does not build on the burnchain block before it
+    SortitionParent {
+        sortition_id: SortitionId,
+        block_height: u64,
+    },
+    /// A sortition's ID is not the one its burnchain block and PoX fork hash to
+    SortitionId {
+        sortition_id: SortitionId,
+        computed: SortitionId,
+    },
+    /// A sortition's consensus hash is not the one its data hashes to
+    ConsensusHash {
+        sortition_id: SortitionId,
+        stored: ConsensusHash,
+        computed: ConsensusHash,
+    },
+    /// A sortition's MARF root is not the sortition MARF's root at that sortition
+    SortitionRoot {
+        sortition_id: SortitionId,
+        stored: TrieHash,
+        computed: TrieHash,
+    },
+    /// A Stacks block's header is missing, or does not link to its parent or its sortition
+    HeaderChain {
+        block_id: StacksBlockId,
+        reason: String,
+    },
+    /// A Stacks block's headers index root does not match its header
+    HeadersRoot {
+        block_id: StacksBlockId,
+        stored: TrieHash,
+        computed: TrieHash,
+    },
+    /// A Stacks block's Clarity state root does not match its header
+    StateRoot {
+        block_id: StacksBlockId,
+        stored: TrieHash,
+        computed: TrieHash,
+    },
+    /// A Stacks block's data is missing, does not decode, or does not match its header
+    BlockData {
+        block_id: StacksBlockId,
+        reason: String,
+    },
+    /// The microblock stream a Stacks block confirms is missing or not connected
+    MicroblockStream {
+        block_id: StacksBlockId,
+        reason: String,
+    },
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Inconsistency::SortitionParent {
+                sortition_id,
+                block_height,
+            } => write!(
+                f,
+                "sortition {} at burn height {} does not build on its parent",
+                sortition_id, block_height
+            ),
+            Inconsistency::SortitionId {
+                sortition_id,
+                computed,
+            } => write!(f, "sortition {} should have ID {}", sortition_id, computed),
+            Inconsistency::ConsensusHash {
+                sortition_id,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "sortition {} has consensus hash {}, but its data hashes to {}",
+                sortition_id, stored, computed
+            ),
+            Inconsistency::SortitionRoot {
+                sortition_id,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "sortition {} has MARF root {}, but the sortition MARF has {}",
+                sortition_id, stored, computed
+            ),
+            Inconsistency::HeaderChain { block_id, reason } => {
+                write!(f, "header of Stacks block {}: {}", block_id, reason)
+            }
+            Inconsistency::HeadersRoot {
+                block_id,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "Stacks block {} has headers index root {}, but the headers MARF has {}",
+                block_id, stored, computed
+            ),
+            Inconsistency::StateRoot {
+                block_id,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "Stacks block {} has state root {}, but the Clarity MARF has {}",
+                block_id, stored, computed
+            ),
+            Inconsistency::BlockData { block_id, reason } => {
+                write!(f, "data of Stacks block {}: {}", block_id, reason)
+            }
+            Inconsistency::MicroblockStream { block_id, reason } => write!(
+                f,
+                "microblock stream confirmed by Stacks block {}: {}",
+                block_id, reason
+            ),
+        }
+    }
+}
+
+/// Outcome of verifying a node's state
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ChainstateVerification {
+    /// Number of sortitions checked
+    pub sortitions_checked: u64,
+    /// Number of Stacks blocks whose headers were checked
+    pub blocks_checked: u64,
+    /// Number of Stacks blocks whose state and data were skipped because they were pruned
+    pub blocks_pruned: u64,
+    /// Number of confirmed microblocks checked
+    pub microblocks_checked: u64,
+    /// The lowest inconsistency found, if any.  Nothing above it was checked.
+    pub inconsistency: Option<Inconsistency>,
+}
+
+/// What the verifier needs to re-derive a sortition's consensus hash
+struct SortitionData {
+    sortition_id: SortitionId,
+    parent_sortition_id: SortitionId,
+    burn_header_hash: BurnchainHeaderHash,
+    parent_burn_header_hash: BurnchainHeaderHash,
+    block_height: u64,
+    consensus_hash: ConsensusHash,
+    ops_hash: OpsHash,
+    total_burn: u64,
+    index_root: TrieHash,
+}
+
+/// Check the canonical burnchain fork and the canonical Stacks fork of a node.  See the module
+/// docs for what is checked.  Returns Err(..) only if the verifier itself could not run.
+pub fn verify_chainstate(
+    sortdb: &mut SortitionDB,
+    chainstate: &mut StacksChainState,
+) -> Result<ChainstateVerification, Error> {
+    let mut report = ChainstateVerification::default();
+    if let Some(inconsistency) = verify_sortitions(sortdb, &mut report)? {
+        report.inconsistency = Some(inconsistency);
+        return Ok(report);
+    }
+    report.inconsistency = verify_stacks_chain(sortdb, chainstate, &mut report)?;
+    Ok(report)
+}
+
+/// Check the canonical burnchain fork, from the first sortition up.
+fn verify_sortitions(
+    sortdb: &mut SortitionDB,
+    report: &mut ChainstateVerification,
+) -> Result<Option<Inconsistency>, Error> {
+    let first_block_height = sortdb.first_block_height;
+    let tip = SortitionDB::get_canonical_burn_chain_tip(sortdb.conn())?;
+
+    // load the fork back to the first sortition
+    let mut fork = vec![];
+    let mut cur_sn = tip;
+    loop {
+        let parent_sortition_id = cur_sn.parent_sortition_id.clone();
+        let at_first = cur_sn.block_height <= first_block_height;
+        fork.push(SortitionData {
+            sortition_id: cur_sn.sortition_id,
+            parent_sortition_id: cur_sn.parent_sortition_id,
+            burn_header_hash: cur_sn.burn_header_hash,
+            parent_burn_header_hash: cur_sn.parent_burn_header_hash,
+            block_height: cur_sn.block_height,
+            consensus_hash: cur_sn.consensus_hash,
+            ops_hash: cur_sn.ops_hash,
+            total_burn: cur_sn.total_burn,
+            index_root: cur_sn.index_root,
+        });
+        if at_first {
+            break;
+        }
+        match SortitionDB::get_block_snapshot(sortdb.conn(), &parent_sortition_id)? {
+            Some(sn) => {
+                cur_sn = sn;
+            }
+            None => {
+                // everything from here up is unverifiable
+                let sn = fork.pop().expect("unreachable: fork is non-empty");
+                return Ok(Some(Inconsistency::SortitionParent {
+                    sortition_id: sn.sortition_id,
+                    block_height: sn.block_height,
+                }));
+            }
+        }
+    }
+    fork.reverse();
+
+    if fork[0].block_height != first_block_height {
+        return Ok(Some(Inconsistency::SortitionParent {
+            sortition_id: fork[0].sortition_id.clone(),
+            block_height: fork[0].block_height,
+        }));
+    }
+
+    for (i, sn) in fork.iter().enumerate() {
+        report.sortitions_checked += 1;
+        if i > 0 {
+            let parent = &fork[i - 1];
+            if parent.sortition_id != sn.parent_sortition_id
+                || parent.burn_header_hash != sn.parent_burn_header_hash
+                || parent.block_height + 1 != sn.block_height
+            {
+                return Ok(Some(Inconsistency::SortitionParent {
+                    sortition_id: sn.sortition_id.clone(),
+                    block_height: sn.block_height,
+                }));
+            }
+
+            // the first sortition's ID and consensus hash are constants
+            let pox_id: PoxId = sortdb.index_handle(&sn.sortition_id).get_pox_id()?;
+            let computed_sortition_id = SortitionId::new(&sn.burn_header_hash, &pox_id);
+            if computed_sortition_id != sn.sortition_id {
+                return Ok(Some(Inconsistency::SortitionId {
+                    sortition_id: sn.sortition_id.clone(),
+                    computed: computed_sortition_id,
+                }));
+            }
+
+            // the consensus hash commits to the consensus hashes of the fork's ancestors
+            let prev_consensus_hashes: Vec<ConsensusHash> =
+                ConsensusHash::get_prev_consensus_hash_heights(
+                    parent.block_height,
+                    first_block_height,
+                )
+                .into_iter()
+                .map(|height| {
+                    fork[(height - first_block_height) as usize]
+                        .consensus_hash
+                        .clone()
+                })
+                .collect();
+            let computed_consensus_hash = ConsensusHash::from_ops(
+                &sn.burn_header_hash,
+                &sn.ops_hash,
+                sn.total_burn,
+                &prev_consensus_hashes,
+                &pox_id,
+            );
+            if computed_consensus_hash != sn.consensus_hash {
+                return Ok(Some(Inconsistency::ConsensusHash {
+                    sortition_id: sn.sortition_id.clone(),
+                    stored: sn.consensus_hash.clone(),
+                    computed: computed_consensus_hash,
+                }));
+            }
+        }
+
+        let sortition_root = sortdb.marf.get_root_hash_at(&sn.sortition_id)?;
+        if sortition_root != sn.index_root {
+            return Ok(Some(Inconsistency::SortitionRoot {
+                sortition_id: sn.sortition_id.clone(),
+                stored: sn.index_root.clone(),
+                computed: sortition_root,
+            }));
+        }
+
+        if report.sortitions_checked % 10_000 == 0 {
+            info!(
+                "Verified {} of {} sortitions",
+                report.sortitions_checked,
+                fork.len()
+            );
+        }
+    }
+    Ok(None)
+}
+
+/// Check the canonical Stacks fork, from the boot block up.
+fn verify_stacks_chain(
+    sortdb: &SortitionDB,
+    chainstate: &mut StacksChainState,
+    report: &mut ChainstateVerification,
+) -> Result<Option<Inconsistency>, Error> {
+    let tip = SortitionDB::get_canonical_burn_chain_tip(sortdb.conn())?;
+    let tip_block_id = StacksBlockId::new(
+        &tip.canonical_stacks_tip_consensus_hash,
+        &tip.canonical_stacks_tip_hash,
+    );
+    let pruned_below_height =
+        StacksChainState::get_pruned_below_height(chainstate.db())?.unwrap_or(0);
+
+    // load the fork back to the boot block
+    let mut fork = vec![];
+    let mut cur_block_id = tip_block_id;
+    loop {
+        let Some(header) = StacksChainState::get_stacks_block_header_info_by_index_block_hash(
+            chainstate.db(),
+            &cur_block_id,
+        )?
+        else {
+            // everything from here up is unverifiable
+            return Ok(Some(Inconsistency::HeaderChain {
+                block_id: cur_block_id,
+                reason: "no header".into(),
+            }));
+        };
+        let height = header.stacks_block_height;
+        fork.push(cur_block_id.clone());
+        if height == 0 {
+            break;
+        }
+        match StacksChainState::get_parent_block_id(chainstate.db(), &cur_block_id)? {
+            Some(parent_block_id) => {
+                cur_block_id = parent_block_id;
+            }
+            None => {
+                return Ok(Some(Inconsistency::HeaderChain {
+                    block_id: cur_block_id,
+                    reason: "no parent".into(),
+                }));
+            }
+        }
+    }
+    fork.reverse();
+
+    let mut parent_header: Option<StacksHeaderInfo> = None;
+    for block_id in fork.iter() {
+        let header = StacksChainState::get_stacks_block_header_info_by_index_block_hash(
+            chainstate.db(),
+            block_id,
+        )?
+        .ok_or(Error::DBError(db_error::NotFoundError))?;
+        report.blocks_checked += 1;
+
+        if let Some(reason) = check_header_chain(sortdb, &header, parent_header.as_ref())? {
+            return Ok(Some(Inconsistency::HeaderChain {
+                block_id: block_id.clone(),
+                reason,
+            }));
+        }
+
+        let headers_root = chainstate.state_index.get_root_hash_at(block_id)?;
+        if headers_root != header.index_root {
+            return Ok(Some(Inconsistency::HeadersRoot {
+                block_id: block_id.clone(),
+                stored: header.index_root.clone(),
+                computed: headers_root,
+            }));
+        }
+
+        let height = header.stacks_block_height;
+        // the boot block has no data, and its header does not commit to the boot code's state
+        if height > 0 && height < pruned_below_height {
+            report.blocks_pruned += 1;
+        } else if height > 0 {
+            let state_root =
+                chainstate.with_clarity_marf(|marf| marf.get_root_hash_at(block_id))?;
+            if state_root != header.anchored_header.state_index_root {
+                return Ok(Some(Inconsistency::StateRoot {
+                    block_id: block_id.clone(),
+                    stored: header.anchored_header.state_index_root.clone(),
+                    computed: state_root,
+                }));
+            }
+
+            if let Some(reason) = check_block_data(&chainstate.blocks_path, &header)? {
+                return Ok(Some(Inconsistency::BlockData {
+                    block_id: block_id.clone(),
+                    reason,
+                }));
+            }
+
+            let parent = parent_header
+                .as_ref()
+                .expect("unreachable: non-boot block has parent");
+            match check_microblock_stream(chainstate, parent, &header)? {
+                Ok(num_microblocks) => {
+                    report.microblocks_checked += num_microblocks;
+                }
+                Err(reason) => {
+                    return Ok(Some(Inconsistency::MicroblockStream {
+                        block_id: block_id.clone(),
+                        reason,
+                    }));
+                }
+            }
+        }
+
+        if report.blocks_checked % 10_000 == 0 {
+            info!(
+                "Verified {} of {} Stacks blocks",
+                report.blocks_checked,
+                fork.len()
+            );
+        }
+        parent_header = Some(header);
+    }
+    Ok(None)
+}
+
+/// Check that a Stacks block header builds on its parent and was chosen by its sortition.
+/// Returns Some(reason) if not.
+fn check_header_chain(
+    sortdb: &SortitionDB,
+    header: &StacksHeaderInfo,
+    parent_header: Option<&StacksHeaderInfo>,
+) -> Result<Option<String>, Error> {
+    let Some(parent_header) = parent_header else {
+        if header.stacks_block_height != 0 {
+            return Ok(Some(format!(
+                "lowest block is at height {}, not 0",
+                header.stacks_block_height
+            )));
+        }
+        return Ok(None);
+    };
+
+    if parent_header.stacks_block_height + 1 != header.stacks_block_height {
+        return Ok(Some(format!(
+            "height {} does not follow parent height {}",
+            header.stacks_block_height, parent_header.stacks_block_height
+        )));
+    }
+    let parent_block_hash = parent_header.anchored_header.block_hash();
+    if header.anchored_header.parent_block != parent_block_hash {
+        return Ok(Some(format!(
+            "builds on {}, not its parent {}",
+            &header.anchored_header.parent_block, &parent_block_hash
+        )));
+    }
+
+    let block_hash = header.anchored_header.block_hash();
+    match SortitionDB::get_block_snapshot_consensus(sortdb.conn(), &header.consensus_hash)? {
+        Some(sn) => {
+            if !sn.sortition || sn.winning_stacks_block_hash != block_hash {
+                return Ok(Some(format!(
+                    "sortition {} did not choose block {}",
+                    &sn.sortition_id, &block_hash
+                )));
+            }
+            if sn.burn_header_hash != header.burn_header_hash {
+                return Ok(Some(format!(
+                    "sortition {} is in burnchain block {}, not {}",
+                    &sn.sortition_id, &sn.burn_header_hash, &header.burn_header_hash
+                )));
+            }
+        }
+        None => {
+            return Ok(Some(format!(
+                "no sortition with consensus hash {}",
+                &header.consensus_hash
+            )));
+        }
+    }
+    Ok(None)
+}
+
+/// Check that a Stacks block's data is stored, decodes, and has the header the chainstate has
+/// for it.  Returns Some(reason) if not.
+fn check_block_data(blocks_path: &str, header: &StacksHeaderInfo) -> Result<Option<String>, Error> {
+    let block_hash = header.anchored_header.block_hash();
+    // decoding the block checks its transactions against its header's Merkle root
+    match StacksChainState::load_block(blocks_path, &header.consensus_hash, &block_hash) {
+        Ok(Some(block)) => {
+            if block.header != header.anchored_header {
+                return Ok(Some(format!(
+                    "stored block has hash {}, not {}",
+                    &block.block_hash(),
+                    &block_hash
+                )));
+            }
+            Ok(None)
+        }
+        Ok(None) => Ok(Some("block is marked invalid".into())),
+        Err(Error::DBError(db_error::NotFoundError)) => Ok(Some("block is missing".into())),
+        Err(Error::CodecError(e)) => Ok(Some(format!("block does not decode: {}", &e))),
+        Err(e) => Err(e),
+    }
+}
+
+/// Check that the microblock stream confirmed by a Stacks block is stored, processed, and
+/// connected from its parent block to the confirmed tip.  Returns the number of microblocks
+/// in the stream, or Err(reason) if it is broken.
+fn check_microblock_stream(
+    chainstate: &StacksChainState,
+    parent_header: &StacksHeaderInfo,
+    header: &StacksHeaderInfo,
+) -> Result<Result<u64, String>, Error> {
+    let tip_hash = &header.anchored_header.parent_microblock;
+    let tip_sequence = header.anchored_header.parent_microblock_sequence;
+    if *tip_hash == EMPTY_MICROBLOCK_PARENT_HASH {
+        if tip_sequence != 0 {
+            return Ok(Err(format!(
+                "confirms no microblocks, but with sequence {}",
+                tip_sequence
+            )));
+        }
+        return Ok(Ok(0));
+    }
+
+    let parent_block_id = parent_header.index_block_hash();
+    let parent_block_hash = parent_header.anchored_header.block_hash();
+    let mut expected_hash: BlockHeaderHash = tip_hash.clone();
+    let mut expected_sequence = Some(tip_sequence);
+    let mut num_microblocks = 0;
+    let mut seen = HashSet::new();
+    loop {
+        let Some(sequence) = expected_sequence else {
+            return Ok(Err(format!(
+                "stream continues below sequence 0 at {}",
+                &expected_hash
+            )));
+        };
+        if !seen.insert(expected_hash.clone()) {
+            return Ok(Err(format!("stream has a cycle at {}", &expected_hash)));
+        }
+        let Some(staging_microblock) = StacksChainState::load_staging_microblock_indexed(
+            chainstate.db(),
+            &parent_block_id,
+            &expected_hash,
+        )?
+        else {
+            return Ok(Err(format!("microblock {} is missing", &expected_hash)));
+        };
+        if !staging_microblock.processed {
+            return Ok(Err(format!(
+                "microblock {} is not processed",
+                &expected_hash
+            )));
+        }
+        let microblock = match StacksMicroblock::consensus_deserialize(
+            &mut &staging_microblock.block_data[..],
+        ) {
+            Ok(microblock) => microblock,
+            Err(e) => {
+                return Ok(Err(format!(
+                    "microblock {} does not decode: {}",
+                    &expected_hash, &e
+                )));
+            }
+        };
+        if microblock.block_hash() != expected_hash || microblock.header.sequence != sequence {
+            return Ok(Err(format!(
+                "expected microblock {} with sequence {}, but found {} with sequence {}",
+                &expected_hash,
+                sequence,
+                &microblock.block_hash(),
+                microblock.header.sequence
+            )));
+        }
+        num_microblocks += 1;
+
+        if sequence == 0 {
+            if microblock.header.prev_block != parent_block_hash {
+                return Ok(Err(format!(
+                    "first microblock {} builds on {}, not the parent block {}",
+                    &expected_hash, &microblock.header.prev_block, &parent_block_hash
+                )));
+            }
+            return Ok(Ok(num_microblocks));
+        }
+        expected_hash = microblock.header.prev_block;
+        expected_sequence = sequence.checked_sub(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::chainstate::stacks::db::blockstore::StacksBlockStore;
+    use crate::net::test::*;
+
+    #[test]
+    fn test_verify_chainstate() {
+        let peer_config = TestPeerConfig::new(function_name!(), 4330, 4331);
+        let mut peer = TestPeer::new(peer_config);
+
+        let mut block_ids = vec![];
+        for _ in 0..6 {
+            let (burn_ops, stacks_block, microblocks) = peer.make_default_tenure();
+            let (_, _, consensus_hash) = peer.next_burnchain_block(burn_ops);
+            peer.process_stacks_epoch_at_tip(&stacks_block, &microblocks);
+            block_ids.push(StacksBlockId::new(
+                &consensus_hash,
+                &stacks_block.block_hash(),
+            ));
+        }
+
+        let mut sortdb = peer.sortdb.take().unwrap();
+        let report = verify_chainstate(&mut sortdb, peer.chainstate()).unwrap();
+        assert_eq!(report.inconsistency, None);
+        assert!(report.sortitions_checked > 6);
+        // boot block, plus the mined blocks
+        assert_eq!(report.blocks_checked, 7);
+        assert_eq!(report.blocks_pruned, 0);
+
+        // swap in another block's data.  The lowest bad block is reported.
+        let blocks_path = peer.chainstate().blocks_path.clone();
+        let mut store = StacksChainState::open_block_store(&blocks_path).unwrap();
+        let block_2 = store.load_block_bytes(&block_ids[2]).unwrap();
+        let block_4 = store.load_block_bytes(&block_ids[4]).unwrap();
+        store.store_block_bytes(&block_ids[4], &block_2).unwrap();
+        store.store_block_bytes(&block_ids[2], &block_4).unwrap();
+
+        let report = verify_chainstate(&mut sortdb, peer.chainstate()).unwrap();
+        match report.inconsistency {
+            Some(Inconsistency::BlockData { ref block_id, .. }) => {
+                assert_eq!(block_id, &block_ids[2]);
+            }
+            ref x => panic!("unexpected inconsistency {:?}", x),
+        }
+        assert_eq!(report.blocks_checked, 4);
+
+        // put the data back, and corrupt a sortition instead
+        store.store_block_bytes(&block_ids[2], &block_2).unwrap();
+        store.store_block_bytes(&block_ids[4], &block_4).unwrap();
+        let report = verify_chainstate(&mut sortdb, peer.chainstate()).unwrap();
+        assert_eq!(report.inconsistency, None);
+
+        let tip = SortitionDB::get_canonical_burn_chain_tip(sortdb.conn()).unwrap();
+        sortdb
+            .conn()
+            .execute(
+                "UPDATE snapshots SET ops_hash = ?1 WHERE sortition_id = ?2",
+                rusqlite::params![OpsHash([0x11; 32]).to_hex(), tip.parent_sortition_id],
+            )
+            .unwrap();
+        let report = verify_chainstate(&mut sortdb, peer.chainstate()).unwrap();
+        match report.inconsistency {
+            Some(Inconsistency::ConsensusHash {
+                ref sortition_id, ..
+            }) => {
+                assert_eq!(sortition_id, &tip.parent_sortition_id);
+            }
+            ref x => panic!("unexpected inconsistency {:?}", x),
+        }
+        assert_eq!(report.blocks_checked, 0);
+
+        peer.sortdb = Some(sortdb);
+    }
+}
@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::{TryFrom, TryInto};
 use std::ffi::OsStr;
 use std::io::{Read, Write};
@@ -22,7 +23,10 @@ use std::path::PathBuf;
 use std::str::FromStr;
 use std::{env, fs, io, process};
 
+use clarity::vm::clarity::ClarityConnection;
 use clarity::vm::coverage::CoverageReporter;
+use clarity::vm::diagnostic::DiagnosableError;
+use clarity::vm::mutation::generate_mutants;
 use rand::Rng;
 use rusqlite::types::ToSql;
 use rusqlite::{Connection, OpenFlags, Row, Transaction, NO_PARAMS};
@@ -44,6 +48,8 @@ use crate::chainstate::stacks::boot::{
     BOOT_CODE_GENESIS, BOOT_CODE_LOCKUP, BOOT_CODE_POX_MAINNET, BOOT_CODE_POX_TESTNET,
     POX_2_MAINNET_CODE, POX_2_TESTNET_CODE,
 };
+use crate::chainstate::stacks::db::StacksChainState;
+use crate::chainstate::stacks::index::marf::{MARFOpenOpts, MarfConnection};
 use crate::chainstate::stacks::index::storage::TrieFileStorage;
 use crate::chainstate::stacks::index::{ClarityMarfTrieId, MarfTrieId};
 use crate::clarity::vm::analysis::contract_interface_builder::build_contract_interface;
@@ -53,19 +59,23 @@ use crate::clarity::vm::ast::{build_ast_with_rules, ASTRules};
 use crate::clarity::vm::contexts::{AssetMap, GlobalContext, OwnedEnvironment};
 use crate::clarity::vm::costs::{ExecutionCost, LimitedCostTracker};
 use crate::clarity::vm::database::{
-    BurnStateDB, ClarityDatabase, HeadersDB, STXBalance, SqliteConnection, NULL_BURN_STATE_DB,
+    BurnStateDB, ClarityDatabase, ClarityDeserializable, HeadersDB, STXBalance, SqliteConnection,
+    StoreType, NULL_BURN_STATE_DB, NULL_HEADER_DB,
 };
 use crate::clarity::vm::errors::{Error, InterpreterResult, RuntimeErrorType};
-use crate::clarity::vm::types::{OptionalData, PrincipalData, QualifiedContractIdentifier};
+use crate::clarity::vm::types::{
+    OptionalData, PrincipalData, QualifiedContractIdentifier, TypeSignature,
+};
 use crate::clarity::vm::{
     analysis, ast, eval_all, ClarityVersion, ContractContext, ContractName, SymbolicExpression,
     SymbolicExpressionType, Value,
 };
+use crate::clarity_vm::database::map_keys::DataMapKeyIndex;
 use crate::clarity_vm::database::marf::{MarfedKV, WritableMarfStore};
 use crate::clarity_vm::database::MemoryBackingStore;
 use crate::core::{StacksEpochId, BLOCK_LIMIT_MAINNET_205, HELIUM_BLOCK_LIMIT_20};
 use crate::util_lib::boot::{boot_code_addr, boot_code_id};
-use crate::util_lib::db::{sqlite_open, FromColumn};
+use crate::util_lib::db::{sqlite_open, Error as DBError, FromColumn};
 use crate::util_lib::strings::StacksString;
 
 lazy_static! {
@@ -123,6 +133,9 @@ where command is one of:
   repl               to typecheck and evaluate expressions in a stdin/stdout loop.
   execute            to execute a public function of a defined contract.
   generate_address   to generate a random Stacks public address for testing purposes.
+  export_snapshot    to dump the state of a set of contracts to a JSON snapshot.
+  import_snapshot    to load a JSON snapshot into a local VM state database.
+  mutate             to run a test script against mutants of a contract and report the survivors.
 ",
         invoked_by
     );
@@ -403,6 +416,15 @@ where
     result
 }
 
+/// MARF options for the CLI's VM state database.
+/// The data map key index is always maintained, so the state can be exported with
+/// `export_snapshot`.
+fn cli_marf_opts() -> MARFOpenOpts {
+    let mut opts = MARFOpenOpts::default();
+    opts.index_data_map_keys = true;
+    opts
+}
+
 fn default_chain_id(mainnet: bool) -> u32 {
     let chain_id = if mainnet {
         CHAIN_ID_MAINNET
@@ -765,7 +787,7 @@ fn get_eval_input(invoked_by: &str, args: &[String]) -> EvalInput {
     );
 
     let marf_kv = friendly_expect(
-        MarfedKV::open(vm_filename, None, None),
+        MarfedKV::open(vm_filename, None, Some(cli_marf_opts())),
         "Failed to open VM database.",
     );
     // return (marf_kv, contract_identifier, vm_filename, content);
@@ -782,6 +804,50 @@ struct InitialAllocation {
     amount: u64,
 }
 
+/// Snapshot of the state of a set of contracts, produced by `export_snapshot` and loaded by
+/// `import_snapshot`.  Clarity values are in the typed JSON encoding (see
+/// `clarity::vm::types::json`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct StateSnapshot {
+    /// index block hash of the state that was exported
+    block: String,
+    contracts: Vec<ContractSnapshot>,
+    /// STX balances of the contracts, of their token holders, and of any other requested
+    /// principals
+    stx_balances: BTreeMap<String, STXBalance>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ContractSnapshot {
+    contract_id: String,
+    clarity_version: ClarityVersion,
+    source: String,
+    data_vars: BTreeMap<String, serde_json::Value>,
+    maps: BTreeMap<String, Vec<MapEntrySnapshot>>,
+    fungible_tokens: BTreeMap<String, FungibleTokenSnapshot>,
+    non_fungible_tokens: BTreeMap<String, Vec<NonFungibleTokenSnapshot>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct MapEntrySnapshot {
+    key: serde_json::Value,
+    value: serde_json::Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct FungibleTokenSnapshot {
+    /// decimal-encoded u128
+    supply: String,
+    /// holder principal to decimal-encoded u128 balance
+    balances: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct NonFungibleTokenSnapshot {
+    id: serde_json::Value,
+    owner: String,
+}
+
 fn consume_arg(
     args: &mut Vec<String>,
     argnames: &[&str],
@@ -949,6 +1015,580 @@ pub fn add_serialized_output(result: &mut serde_json::Value, value: Value) {
     result["output_serialized"] = serde_json::to_value(result_raw.as_str()).unwrap();
 }
 
+fn to_snapshot_json(value: &Value) -> Result<serde_json::Value, String> {
+    value
+        .to_typed_json()
+        .map_err(|e| format!("Failed to encode {} as JSON: {:?}", value, &e))
+}
+
+fn from_snapshot_json(json: &serde_json::Value, expected: &TypeSignature) -> Result<Value, String> {
+    Value::from_typed_json(json, expected)
+        .map_err(|e| format!("Failed to decode {} as {}: {:?}", json, expected, &e))
+}
+
+/// Decode a key recorded in the data map key index.  Token balances are keyed by the holder's
+/// JSON encoding (see `ClarityDatabase::get_ft_balance()`); everything else by a hex-encoded
+/// Clarity value.
+fn decode_indexed_key(store_type: u8, key: &str) -> Result<Value, String> {
+    let value = if store_type == StoreType::FungibleToken as u8 {
+        PrincipalData::deserialize(key)
+            .map(Value::Principal)
+            .map_err(|e| format!("{:?}", &e))
+    } else {
+        Value::try_deserialize_hex_untyped(key).map_err(|e| format!("{:?}", &e))
+    };
+    value.map_err(|e| format!("Corrupt key {} in data map key index: {}", key, e))
+}
+
+/// Read the state of a contract into a snapshot.
+/// `indexed_keys` are the data map keys, token holders, and NFT identifiers recorded for the
+/// contract in the data map key index (see `DataMapKeyIndex::get_contract_keys()`).
+/// Token holders and NFT owners are added to `holders`.
+fn export_contract_snapshot(
+    db: &mut ClarityDatabase,
+    contract_id: &QualifiedContractIdentifier,
+    indexed_keys: &[(u8, String, String)],
+    holders: &mut BTreeSet<String>,
+) -> Result<ContractSnapshot, String> {
+    let epoch = db.get_clarity_epoch_version().map_err(|e| e.to_string())?;
+    let contract = db
+        .get_contract(contract_id)
+        .map_err(|e| format!("Failed to load contract {}: {}", contract_id, e))?;
+    let source = db
+        .get_contract_src(contract_id)
+        .ok_or_else(|| format!("No source code stored for contract {}", contract_id))?;
+    let context = contract.contract_context;
+
+    let mut snapshot = ContractSnapshot {
+        contract_id: contract_id.to_string(),
+        clarity_version: *context.get_clarity_version(),
+        source,
+        data_vars: BTreeMap::new(),
+        maps: BTreeMap::new(),
+        fungible_tokens: BTreeMap::new(),
+        non_fungible_tokens: BTreeMap::new(),
+    };
+
+    for var_name in context.meta_data_var.keys() {
+        let value = db
+            .lookup_variable_unknown_descriptor(contract_id, var_name, &epoch)
+            .map_err(|e| e.to_string())?;
+        snapshot
+            .data_vars
+            .insert(var_name.to_string(), to_snapshot_json(&value)?);
+    }
+    for map_name in context.meta_data_map.keys() {
+        snapshot.maps.insert(map_name.to_string(), vec![]);
+    }
+    for token_name in context.meta_ft.keys() {
+        let supply = db
+            .get_ft_supply(contract_id, token_name)
+            .map_err(|e| e.to_string())?;
+        snapshot.fungible_tokens.insert(
+            token_name.to_string(),
+            FungibleTokenSnapshot {
+                supply: supply.to_string(),
+                balances: BTreeMap::new(),
+            },
+        );
+    }
+    for token_name in context.meta_nft.keys() {
+        snapshot
+            .non_fungible_tokens
+            .insert(token_name.to_string(), vec![]);
+    }
+
+    for (store_type, name, key) in indexed_keys.iter() {
+        let key = decode_indexed_key(*store_type, key)?;
+        if *store_type == StoreType::DataMap as u8 {
+            let Some(entries) = snapshot.maps.get_mut(name.as_str()) else {
+                continue;
+            };
+            let entry = db
+                .fetch_entry_unknown_descriptor(contract_id, name, &key, &epoch)
+                .map_err(|e| e.to_string())?;
+            // keys which were deleted (or written on another fork) read as `none`
+            if let Value::Optional(OptionalData { data: Some(value) }) = entry {
+                entries.push(MapEntrySnapshot {
+                    key: to_snapshot_json(&key)?,
+                    value: to_snapshot_json(&value)?,
+                });
+            }
+        } else if *store_type == StoreType::FungibleToken as u8 {
+            let Some(token) = snapshot.fungible_tokens.get_mut(name.as_str()) else {
+                continue;
+            };
+            let Value::Principal(holder) = key else {
+                continue;
+            };
+            let balance = db
+                .get_ft_balance(contract_id, name, &holder, None)
+                .map_err(|e| e.to_string())?;
+            if balance > 0 {
+                token
+                    .balances
+                    .insert(holder.to_string(), balance.to_string());
+                holders.insert(holder.to_string());
+            }
+        } else if *store_type == StoreType::NonFungibleToken as u8 {
+            let (Some(tokens), Some(metadata)) = (
+                snapshot.non_fungible_tokens.get_mut(name.as_str()),
+                context.meta_nft.get(name.as_str()),
+            ) else {
+                continue;
+            };
+            match db.get_nft_owner(contract_id, name, &key, &metadata.key_type) {
+                Ok(owner) => {
+                    tokens.push(NonFungibleTokenSnapshot {
+                        id: to_snapshot_json(&key)?,
+                        owner: owner.to_string(),
+                    });
+                    holders.insert(owner.to_string());
+                }
+                // burnt
+                Err(Error::Runtime(RuntimeErrorType::NoSuchToken, _)) => {}
+                Err(e) => {
+                    return Err(e.to_string());
+                }
+            }
+        }
+    }
+
+    Ok(snapshot)
+}
+
+/// Read the state of a set of contracts, and the STX balances of the contracts, their token
+/// holders, and `principals`, into a snapshot of `block`.
+fn export_state_snapshot(
+    db: &mut ClarityDatabase,
+    block: String,
+    contract_ids: &[QualifiedContractIdentifier],
+    indexed_keys: &[Vec<(u8, String, String)>],
+    principals: &[PrincipalData],
+) -> Result<StateSnapshot, String> {
+    let mut holders: BTreeSet<String> = principals.iter().map(|p| p.to_string()).collect();
+    let mut contracts = vec![];
+    for (contract_id, keys) in contract_ids.iter().zip(indexed_keys.iter()) {
+        holders.insert(contract_id.to_string());
+        contracts.push(export_contract_snapshot(
+            db,
+            contract_id,
+            keys,
+            &mut holders,
+        )?);
+    }
+
+    let mut stx_balances = BTreeMap::new();
+    for holder in holders.into_iter() {
+        let principal =
+            PrincipalData::parse(&holder).map_err(|_| format!("Invalid principal: {}", &holder))?;
+        let balance = db
+            .get_account_stx_balance(&principal)
+            .map_err(|e| e.to_string())?;
+        stx_balances.insert(holder, balance);
+    }
+
+    Ok(StateSnapshot {
+        block,
+        contracts,
+        stx_balances,
+    })
+}
+
+/// Export a snapshot of a VM state created by this tool.  Reads go through a nested context,
+/// which is rolled back afterwards.
+fn export_vm_state_snapshot(
+    marf: &mut WritableMarfStore,
+    block: String,
+    contract_ids: &[QualifiedContractIdentifier],
+    indexed_keys: &[Vec<(u8, String, String)>],
+    principals: &[PrincipalData],
+) -> Result<StateSnapshot, String> {
+    let mut db = marf.as_clarity_db(&NULL_HEADER_DB, &NULL_BURN_STATE_DB);
+    db.begin();
+    let result = export_state_snapshot(&mut db, block, contract_ids, indexed_keys, principals);
+    db.roll_back()
+        .expect("FATAL: failed to roll back snapshot export");
+    result
+}
+
+/// Export a snapshot of a node's chainstate at the given index block hash.  The node must have
+/// been running with the data map key index enabled.
+fn export_chainstate_snapshot(
+    mainnet: bool,
+    chainstate_path: &str,
+    at_block_hash: &str,
+    contract_ids: &[QualifiedContractIdentifier],
+    principals: &[PrincipalData],
+) -> (i32, Option<serde_json::Value>) {
+    let tip = friendly_expect(
+        StacksBlockId::from_hex(at_block_hash),
+        "Failed to parse index block hash.",
+    );
+    if !fs::metadata(chainstate_path).is_ok() {
+        return (
+            1,
+            Some(json!({ "error": format!("No chainstate at {}", chainstate_path) })),
+        );
+    }
+    let (mut chainstate, _) = friendly_expect(
+        StacksChainState::open(mainnet, default_chain_id(mainnet), chainstate_path, None),
+        "Failed to open chainstate.",
+    );
+
+    let indexed_keys: Result<_, DBError> = chainstate.with_clarity_marf(|marf| {
+        if !DataMapKeyIndex::is_current(marf.sqlite_conn())? {
+            return Ok(None);
+        }
+        let mut indexed_keys = vec![];
+        for contract_id in contract_ids.iter() {
+            indexed_keys.push(DataMapKeyIndex::get_contract_keys(
+                marf.sqlite_conn(),
+                contract_id,
+            )?);
+        }
+        Ok(Some(indexed_keys))
+    });
+    let indexed_keys = match friendly_expect(indexed_keys, "Failed to query chainstate.") {
+        Some(indexed_keys) => indexed_keys,
+        None => {
+            return (
+                1,
+                Some(json!({
+                    "error": "Chainstate does not have a data map key index"
+                })),
+            );
+        }
+    };
+
+    let result = chainstate.with_read_only_clarity_tx(&NULL_BURN_STATE_DB, &tip, |conn| {
+        conn.with_clarity_db_readonly(|db| {
+            export_state_snapshot(
+                db,
+                at_block_hash.to_string(),
+                contract_ids,
+                &indexed_keys,
+                principals,
+            )
+        })
+    });
+    match result {
+        Some(Ok(snapshot)) => (0, Some(serde_json::to_value(&snapshot).unwrap())),
+        Some(Err(error)) => (1, Some(json!({ "error": error }))),
+        None => (
+            1,
+            Some(json!({ "error": format!("No such block {}", at_block_hash) })),
+        ),
+    }
+}
+
+/// Overwrite the state of a deployed contract with the state in a snapshot.
+/// `indexed_keys` are the keys currently recorded for the contract in the data map key index.
+/// Map entries, token balances, and NFTs which are not in the snapshot are removed, unless the
+/// snapshot omits the map or token altogether.
+fn import_contract_snapshot(
+    db: &mut ClarityDatabase,
+    snapshot: &ContractSnapshot,
+    indexed_keys: &[(u8, String, String)],
+) -> Result<(), String> {
+    let contract_id = QualifiedContractIdentifier::parse(&snapshot.contract_id)
+        .map_err(|e| format!("Failed to parse contract identifier: {}", e))?;
+    let epoch = db.get_clarity_epoch_version().map_err(|e| e.to_string())?;
+    let contract = db
+        .get_contract(&contract_id)
+        .map_err(|e| format!("Failed to load contract {}: {}", &contract_id, e))?;
+    let context = contract.contract_context;
+
+    let existing_keys = |store_type: StoreType, name: &str| -> Result<Vec<Value>, String> {
+        let store_type = store_type as u8;
+        indexed_keys
+            .iter()
+            .filter(|(key_store_type, key_name, _)| {
+                *key_store_type == store_type && key_name == name
+            })
+            .map(|(_, _, key)| decode_indexed_key(store_type, key))
+            .collect()
+    };
+
+    for (var_name, json) in snapshot.data_vars.iter() {
+        let metadata = context
+            .meta_data_var
+            .get(var_name.as_str())
+            .ok_or_else(|| format!("No such data var in {}: {}", &contract_id, var_name))?;
+        let value = from_snapshot_json(json, &metadata.value_type)?;
+        db.set_variable(&contract_id, var_name, value, metadata, &epoch)
+            .map_err(|e| e.to_string())?;
+    }
+
+    for (map_name, entries) in snapshot.maps.iter() {
+        let metadata = context
+            .meta_data_map
+            .get(map_name.as_str())
+            .ok_or_else(|| format!("No such map in {}: {}", &contract_id, map_name))?;
+        let entries = entries
+            .iter()
+            .map(|entry| {
+                Ok((
+                    from_snapshot_json(&entry.key, &metadata.key_type)?,
+                    from_snapshot_json(&entry.value, &metadata.value_type)?,
+                ))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        for key in existing_keys(StoreType::DataMap, map_name)?.iter() {
+            if !entries.iter().any(|(entry_key, _)| entry_key == key) {
+                db.delete_entry(&contract_id, map_name, key, metadata, &epoch)
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        for (key, value) in entries.into_iter() {
+            db.set_entry_unknown_descriptor(&contract_id, map_name, key, value, &epoch)
+                .map_err(|e| e.to_string())?;
+        }
+    }
+
+    for (token_name, token) in snapshot.fungible_tokens.iter() {
+        if !context.meta_ft.contains_key(token_name.as_str()) {
+            return Err(format!(
+                "No such fungible token in {}: {}",
+                &contract_id, token_name
+            ));
+        }
+        let supply = u128::from_str(&token.supply)
+            .map_err(|_| format!("Invalid supply for {}: {}", token_name, &token.supply))?;
+        let balances = token
+            .balances
+            .iter()
+            .map(|(holder, balance)| {
+                let holder = PrincipalData::parse(holder)
+                    .map_err(|_| format!("Invalid principal: {}", holder))?;
+                let balance = u128::from_str(balance)
+                    .map_err(|_| format!("Invalid balance for {}: {}", &holder, balance))?;
+                Ok((holder, balance))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let supply_key = ClarityDatabase::make_key_for_trip(
+            &contract_id,
+            StoreType::CirculatingSupply,
+            token_name,
+        );
+        db.put(&supply_key, &supply).map_err(|e| e.to_string())?;
+        for key in existing_keys(StoreType::FungibleToken, token_name)?.into_iter() {
+            let Value::Principal(holder) = key else {
+                continue;
+            };
+            if !balances.iter().any(|(p, _)| p == &holder) {
+                db.set_ft_balance(&contract_id, token_name, &holder, 0)
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        for (holder, balance) in balances.iter() {
+            db.set_ft_balance(&contract_id, token_name, holder, *balance)
+                .map_err(|e| e.to_string())?;
+        }
+    }
+
+    for (token_name, tokens) in snapshot.non_fungible_tokens.iter() {
+        let metadata = context.meta_nft.get(token_name.as_str()).ok_or_else(|| {
+            format!(
+                "No such non-fungible token in {}: {}",
+                &contract_id, token_name
+            )
+        })?;
+        let tokens = tokens
+            .iter()
+            .map(|token| {
+                let id = from_snapshot_json(&token.id, &metadata.key_type)?;
+                let owner = PrincipalData::parse(&token.owner)
+                    .map_err(|_| format!("Invalid principal: {}", &token.owner))?;
+                Ok((id, owner))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        for id in existing_keys(StoreType::NonFungibleToken, token_name)?.iter() {
+            if tokens.iter().any(|(token_id, _)| token_id == id) {
+                continue;
+            }
+            match db.get_nft_owner(&contract_id, token_name, id, &metadata.key_type) {
+                Ok(_) => db
+                    .burn_nft(&contract_id, token_name, id, &metadata.key_type, &epoch)
+                    .map_err(|e| e.to_string())?,
+                Err(Error::Runtime(RuntimeErrorType::NoSuchToken, _)) => {}
+                Err(e) => {
+                    return Err(e.to_string());
+                }
+            }
+        }
+        for (id, owner) in tokens.iter() {
+            db.set_nft_owner(
+                &contract_id,
+                token_name,
+                id,
+                owner,
+                &metadata.key_type,
+                &epoch,
+            )
+            .map_err(|e| e.to_string())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// One line of a `mutate` test script: `SENDER FUNCTION [ARGS...]`
+struct MutationTestCall {
+    sender: PrincipalData,
+    function: String,
+    arguments: Vec<SymbolicExpression>,
+}
+
+/// Split a test script line into whitespace-separated words, keeping parenthesized expressions,
+/// tuples, and string literals together.
+fn split_script_line(line: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for ch in line.chars() {
+        if in_string {
+            word.push(ch);
+            if escaped {
+                escaped = false;
+            } else if ch == '\\' {
+                escaped = true;
+            } else if ch == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+        match ch {
+            '"' => {
+                in_string = true;
+                word.push(ch);
+            }
+            '(' | '{' => {
+                depth += 1;
+                word.push(ch);
+            }
+            ')' | '}' => {
+                depth = depth.saturating_sub(1);
+                word.push(ch);
+            }
+            ch if ch.is_whitespace() && depth == 0 => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            ch => word.push(ch),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// Parse a `mutate` test script.  Blank lines and lines starting with `;;` are skipped.
+fn parse_mutation_script(script: &str) -> Result<Vec<MutationTestCall>, String> {
+    let clarity_version = ClarityVersion::default_for_epoch(DEFAULT_CLI_EPOCH);
+    let mut calls = vec![];
+    for (line_num, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(";;") {
+            continue;
+        }
+        let line_num = line_num + 1;
+        let words = split_script_line(line);
+        if words.len() < 2 {
+            return Err(format!(
+                "line {}: expected SENDER FUNCTION [ARGS...]",
+                line_num
+            ));
+        }
+        let sender = PrincipalData::parse_standard_principal(&words[0])
+            .map_err(|_| format!("line {}: invalid sender: {}", line_num, &words[0]))?;
+        let arguments = words[2..]
+            .iter()
+            .map(|argument| match vm_execute(argument, clarity_version) {
+                Ok(Some(value)) => Ok(SymbolicExpression::atom_value(value)),
+                Ok(None) => Err(format!(
+                    "line {}: failed to parse a value from the argument: {}",
+                    line_num, argument
+                )),
+                Err(e) => Err(format!(
+                    "line {}: error parsing argument \"{}\": {}",
+                    line_num, argument, e
+                )),
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        calls.push(MutationTestCall {
+            sender: PrincipalData::Standard(sender),
+            function: words[1].clone(),
+            arguments,
+        });
+    }
+    Ok(calls)
+}
+
+/// Deploy `source` as `contract_id` and run the test calls against it, returning the outcome of
+/// each call.  Fails if the contract cannot be deployed.
+fn run_mutation_calls(
+    header_db: &CLIHeadersDB,
+    marf: &mut WritableMarfStore,
+    contract_id: &QualifiedContractIdentifier,
+    source: &str,
+    calls: &[MutationTestCall],
+) -> Result<Vec<String>, String> {
+    let mainnet = header_db.is_mainnet();
+    let clarity_version = ClarityVersion::default_for_epoch(DEFAULT_CLI_EPOCH);
+    let mut ast = parse(contract_id, source, clarity_version).map_err(|e| e.to_string())?;
+    run_analysis(contract_id, &mut ast, header_db, marf, true).map_err(|(e, _)| e.to_string())?;
+    let (init_result, _) = with_env_costs(mainnet, header_db, marf, None, |vm_env| {
+        vm_env.initialize_versioned_contract(
+            contract_id.clone(),
+            clarity_version,
+            source,
+            None,
+            ASTRules::PrecheckSize,
+        )
+    });
+    init_result.map_err(|e| e.to_string())?;
+
+    let mut outcomes = vec![];
+    for call in calls.iter() {
+        let (result, _) = with_env_costs(mainnet, header_db, marf, None, |vm_env| {
+            vm_env.execute_transaction(
+                call.sender.clone(),
+                None,
+                contract_id.clone(),
+                &call.function,
+                &call.arguments,
+            )
+        });
+        outcomes.push(match result {
+            Ok((value, ..)) => value.to_string(),
+            Err(e) => format!("runtime error: {}", e),
+        });
+    }
+    Ok(outcomes)
+}
+
+/// Like `run_mutation_calls`, but in a scratch block on top of `chain_tip` which is always
+/// rolled back, so every mutant starts from the same state.
+fn run_mutation_suite(
+    header_db: &CLIHeadersDB,
+    marf_kv: &mut MarfedKV,
+    chain_tip: &StacksBlockId,
+    contract_id: &QualifiedContractIdentifier,
+    source: &str,
+    calls: &[MutationTestCall],
+) -> Result<Vec<String>, String> {
+    let mut marf = marf_kv.begin(chain_tip, &StacksBlockId([2u8; 32]));
+    let result = run_mutation_calls(header_db, &mut marf, contract_id, source, calls);
+    marf.rollback_block();
+    result
+}
+
 /// Returns (process-exit-code, Option<json-output>)
 pub fn invoke_command(invoked_by: &str, args: &[String]) -> (i32, Option<serde_json::Value>) {
     if args.len() < 1 {
@@ -1014,7 +1654,7 @@ pub fn invoke_command(invoked_by: &str, args: &[String]) -> (i32, Option<serde_j
             debug!("Initialize {}", &db_name);
             let mut header_db = CLIHeadersDB::new(&db_name, mainnet);
             let mut marf_kv = friendly_expect(
-                MarfedKV::open(db_name, None, None),
+                MarfedKV::open(db_name, None, Some(cli_marf_opts())),
                 "Failed to open VM database.",
             );
 
@@ -1152,7 +1792,7 @@ pub fn invoke_command(invoked_by: &str, args: &[String]) -> (i32, Option<serde_j
                     let header_db =
                         friendly_expect(CLIHeadersDB::resume(vm_filename), "Failed to open CLI DB");
                     let marf_kv = friendly_expect(
-                        MarfedKV::open(vm_filename, None, None),
+                        MarfedKV::open(vm_filename, None, Some(cli_marf_opts())),
                         "Failed to open VM database.",
                     );
 
@@ -1354,7 +1994,7 @@ pub fn invoke_command(invoked_by: &str, args: &[String]) -> (i32, Option<serde_j
             let header_db =
                 friendly_expect(CLIHeadersDB::resume(vm_filename), "Failed to open CLI DB");
             let marf_kv = friendly_expect(
-                MarfedKV::open(vm_filename, None, None),
+                MarfedKV::open(vm_filename, None, Some(cli_marf_opts())),
                 "Failed to open VM database.",
             );
             let mainnet = header_db.is_mainnet();
@@ -1422,7 +2062,7 @@ pub fn invoke_command(invoked_by: &str, args: &[String]) -> (i32, Option<serde_j
             let header_db =
                 friendly_expect(CLIHeadersDB::resume(vm_filename), "Failed to open CLI DB");
             let marf_kv = friendly_expect(
-                MarfedKV::open(vm_filename, None, None),
+                MarfedKV::open(vm_filename, None, Some(cli_marf_opts())),
                 "Failed to open VM database.",
             );
 
@@ -1519,7 +2159,7 @@ pub fn invoke_command(invoked_by: &str, args: &[String]) -> (i32, Option<serde_j
             let header_db =
                 friendly_expect(CLIHeadersDB::resume(vm_filename), "Failed to open CLI DB");
             let marf_kv = friendly_expect(
-                MarfedKV::open(vm_filename, None, None),
+                MarfedKV::open(vm_filename, None, Some(cli_marf_opts())),
                 "Failed to open VM database.",
             );
             let mainnet = header_db.is_mainnet();
@@ -1638,7 +2278,7 @@ pub fn invoke_command(invoked_by: &str, args: &[String]) -> (i32, Option<serde_j
             let header_db =
                 friendly_expect(CLIHeadersDB::resume(vm_filename), "Failed to open CLI DB");
             let marf_kv = friendly_expect(
-                MarfedKV::open(vm_filename, None, None),
+                MarfedKV::open(vm_filename, None, Some(cli_marf_opts())),
                 "Failed to open VM database.",
             );
             let mainnet = header_db.is_mainnet();
@@ -1750,7 +2390,7 @@ pub fn invoke_command(invoked_by: &str, args: &[String]) -> (i32, Option<serde_j
             let header_db =
                 friendly_expect(CLIHeadersDB::resume(vm_filename), "Failed to open CLI DB");
             let marf_kv = friendly_expect(
-                MarfedKV::open(vm_filename, None, None),
+                MarfedKV::open(vm_filename, None, Some(cli_marf_opts())),
                 "Failed to open VM database.",
             );
             let mainnet = header_db.is_mainnet();
@@ -1895,6 +2535,407 @@ pub fn invoke_command(invoked_by: &str, args: &[String]) -> (i32, Option<serde_j
                 .expect("Failed to produce an lcov output");
             (0, None)
         }
+        "export_snapshot" => {
+            let mut argv: Vec<String> = args.into_iter().map(|x| x.clone()).collect();
+            let at_block_hash = if let Ok(optarg) = consume_arg(&mut argv, &["--at_block"], true) {
+                optarg
+            } else {
+                eprintln!("Expected argument for --at_block");
+                panic_test!();
+            };
+            let chainstate_path =
+                if let Ok(optarg) = consume_arg(&mut argv, &["--chainstate"], true) {
+                    optarg
+                } else {
+                    eprintln!("Expected argument for --chainstate");
+                    panic_test!();
+                };
+            let mainnet = if let Ok(Some(_)) = consume_arg(&mut argv, &["--testnet"], false) {
+                false
+            } else {
+                true
+            };
+            let mut principals = vec![];
+            loop {
+                match consume_arg(&mut argv, &["--principal"], true) {
+                    Ok(Some(principal)) => {
+                        principals.push(friendly_expect(
+                            PrincipalData::parse(&principal),
+                            &format!("Error parsing principal '{}'", &principal),
+                        ));
+                    }
+                    Ok(None) => break,
+                    Err(_) => {
+                        eprintln!("Expected argument for --principal");
+                        panic_test!();
+                    }
+                }
+            }
+            let min_args = if chainstate_path.is_some() { 2 } else { 3 };
+            if argv.len() < min_args || (chainstate_path.is_some() && at_block_hash.is_none()) {
+                eprintln!(
+                    "Usage: {} {} [--at_block BLOCKHASH] [--principal PRINCIPAL]... [vm-state.db] [contract-identifier]...",
+                    invoked_by, argv[0]
+                );
+                eprintln!(
+                    "       {} {} --chainstate PATH [--testnet] --at_block INDEX_BLOCK_HASH [--principal PRINCIPAL]... [contract-identifier]...",
+                    invoked_by, argv[0]
+                );
+                eprintln!("   Prints a JSON snapshot of the state of the given contracts, and of the STX balances of");
+                eprintln!("   their token holders and of any --principal, to stdout.  The VM state must have been");
+                eprintln!("   created with the data map key index enabled (always the case for `initialize`).");
+                eprintln!("   With --chainstate, the snapshot is read from a node's chainstate directory at the given");
+                eprintln!("   index block hash, instead of from a VM state created by this tool.");
+                panic_test!();
+            }
+
+            if let Some(chainstate_path) = chainstate_path {
+                let at_block_hash = at_block_hash.expect("BUG: checked above");
+                let contract_ids: Vec<_> = argv[1..]
+                    .iter()
+                    .map(|contract_id| {
+                        friendly_expect(
+                            QualifiedContractIdentifier::parse(contract_id),
+                            &format!("Error parsing contract identifier '{}'", contract_id),
+                        )
+                    })
+                    .collect();
+                return export_chainstate_snapshot(
+                    mainnet,
+                    &chainstate_path,
+                    &at_block_hash,
+                    &contract_ids,
+                    &principals,
+                );
+            }
+
+            let vm_filename = &argv[1];
+            let contract_ids: Vec<_> = argv[2..]
+                .iter()
+                .map(|contract_id| {
+                    friendly_expect(
+                        QualifiedContractIdentifier::parse(contract_id),
+                        &format!("Error parsing contract identifier '{}'", contract_id),
+                    )
+                })
+                .collect();
+
+            let marf_kv = friendly_expect(
+                MarfedKV::open(vm_filename, None, None),
+                "Failed to open VM database.",
+            );
+            if !friendly_expect(
+                marf_kv.has_data_map_key_index(),
+                "Failed to query VM database.",
+            ) {
+                return (
+                    1,
+                    Some(json!({
+                        "error": "VM database does not have a data map key index"
+                    })),
+                );
+            }
+            let mut indexed_keys = vec![];
+            for contract_id in contract_ids.iter() {
+                indexed_keys.push(friendly_expect(
+                    marf_kv.get_indexed_contract_keys(contract_id),
+                    "Failed to query data map key index.",
+                ));
+            }
+
+            let block = match at_block_hash {
+                Some(ref at_block_hash) => at_block_hash.clone(),
+                None => {
+                    let cli_db_conn = create_or_open_db(&get_cli_db_path(vm_filename));
+                    get_cli_chain_tip(&cli_db_conn).to_hex()
+                }
+            };
+            let result = if let Some(at_block_hash) = at_block_hash {
+                at_block(&at_block_hash, marf_kv, |mut marf| {
+                    let result = export_vm_state_snapshot(
+                        &mut marf,
+                        block,
+                        &contract_ids,
+                        &indexed_keys,
+                        &principals,
+                    );
+                    (marf, result)
+                })
+            } else {
+                at_chaintip(vm_filename, marf_kv, |mut marf| {
+                    let result = export_vm_state_snapshot(
+                        &mut marf,
+                        block,
+                        &contract_ids,
+                        &indexed_keys,
+                        &principals,
+                    );
+                    (marf, result)
+                })
+            };
+
+            match result {
+                Ok(snapshot) => (0, Some(serde_json::to_value(&snapshot).unwrap())),
+                Err(error) => (1, Some(json!({ "error": error }))),
+            }
+        }
+        "import_snapshot" => {
+            if args.len() < 3 {
+                eprintln!(
+                    "Usage: {} {} [snapshot.json] [vm-state.db]",
+                    invoked_by, args[0]
+                );
+                eprintln!("   Loads a snapshot written by `export_snapshot` into the VM state in a new block.");
+                eprintln!("   Contracts which are not yet deployed are launched from the snapshot's source code.");
+                eprintln!("   If snapshot.json is \"-\", it is read from stdin.");
+                panic_test!();
+            }
+
+            let snapshot_json = if args[1] == "-" {
+                let mut buffer = String::new();
+                friendly_expect(
+                    io::stdin().read_to_string(&mut buffer),
+                    "Error reading from stdin.",
+                );
+                buffer
+            } else {
+                friendly_expect(
+                    fs::read_to_string(&args[1]),
+                    &format!("Error reading file: {}", &args[1]),
+                )
+            };
+            let snapshot: StateSnapshot = friendly_expect(
+                serde_json::from_str(&snapshot_json),
+                "Failed to parse snapshot.",
+            );
+
+            let vm_filename = &args[2];
+            let header_db =
+                friendly_expect(CLIHeadersDB::resume(vm_filename), "Failed to open CLI DB");
+            let marf_kv = friendly_expect(
+                MarfedKV::open(vm_filename, None, Some(cli_marf_opts())),
+                "Failed to open VM database.",
+            );
+            let mainnet = header_db.is_mainnet();
+
+            // deploy any missing contracts, in snapshot order
+            let (header_db, marf_kv, deploy_result) =
+                in_block(header_db, marf_kv, |header_db, mut marf| {
+                    let mut result = Ok(());
+                    for contract in snapshot.contracts.iter() {
+                        let contract_id =
+                            match QualifiedContractIdentifier::parse(&contract.contract_id) {
+                                Ok(contract_id) => contract_id,
+                                Err(e) => {
+                                    result =
+                                        Err(format!("Failed to parse contract identifier: {}", e));
+                                    break;
+                                }
+                            };
+                        if marf
+                            .get_clarity_db(&header_db, &NULL_BURN_STATE_DB)
+                            .has_contract(&contract_id)
+                        {
+                            continue;
+                        }
+                        let mut ast =
+                            match parse(&contract_id, &contract.source, contract.clarity_version) {
+                                Ok(ast) => ast,
+                                Err(e) => {
+                                    result =
+                                        Err(format!("Failed to parse {}: {}", &contract_id, e));
+                                    break;
+                                }
+                            };
+                        if let Err((e, _)) =
+                            run_analysis(&contract_id, &mut ast, &header_db, &mut marf, true)
+                        {
+                            result = Err(format!("Failed to analyze {}: {}", &contract_id, e));
+                            break;
+                        }
+                        let (init_result, _) =
+                            with_env_costs(mainnet, &header_db, &mut marf, None, |vm_env| {
+                                vm_env.initialize_versioned_contract(
+                                    contract_id.clone(),
+                                    contract.clarity_version,
+                                    &contract.source,
+                                    None,
+                                    ASTRules::PrecheckSize,
+                                )
+                            });
+                        if let Err(e) = init_result {
+                            result = Err(format!("Failed to initialize {}: {}", &contract_id, e));
+                            break;
+                        }
+                    }
+                    (header_db, marf, result)
+                });
+            if let Err(error) = deploy_result {
+                return (1, Some(json!({ "error": error })));
+            }
+
+            let mut indexed_keys = vec![];
+            for contract in snapshot.contracts.iter() {
+                let contract_id = friendly_expect(
+                    QualifiedContractIdentifier::parse(&contract.contract_id),
+                    "Failed to parse contract identifier.",
+                );
+                indexed_keys.push(friendly_expect(
+                    marf_kv.get_indexed_contract_keys(&contract_id),
+                    "Failed to query data map key index.",
+                ));
+            }
+
+            let (_, _, import_result) = in_block(header_db, marf_kv, |header_db, mut marf| {
+                let result = {
+                    let mut db = marf.as_clarity_db(&header_db, &NULL_BURN_STATE_DB);
+                    db.begin();
+                    let mut result = Ok(());
+                    for (contract, keys) in snapshot.contracts.iter().zip(indexed_keys.iter()) {
+                        result = import_contract_snapshot(&mut db, contract, keys);
+                        if result.is_err() {
+                            break;
+                        }
+                    }
+                    let result = result.and_then(|_| {
+                        for (holder, balance) in snapshot.stx_balances.iter() {
+                            let principal = PrincipalData::parse(holder)
+                                .map_err(|_| format!("Invalid principal: {}", holder))?;
+                            let mut stx_snapshot = db
+                                .get_stx_balance_snapshot_genesis(&principal)
+                                .map_err(|e| e.to_string())?;
+                            stx_snapshot.set_balance(balance.clone());
+                            stx_snapshot.save().map_err(|e| e.to_string())?;
+                        }
+                        Ok(())
+                    });
+                    match result {
+                        Ok(_) => db.commit().map_err(|e| e.to_string()),
+                        Err(e) => {
+                            db.roll_back()
+                                .expect("FATAL: failed to roll back snapshot import");
+                            Err(e)
+                        }
+                    }
+                };
+                (header_db, marf, result)
+            });
+
+            match import_result {
+                Ok(_) => (
+                    0,
+                    Some(json!({
+                        "message": "Snapshot imported.",
+                        "contracts": snapshot.contracts.len(),
+                        "stx_balances": snapshot.stx_balances.len(),
+                    })),
+                ),
+                Err(error) => (1, Some(json!({ "error": error }))),
+            }
+        }
+        "mutate" => {
+            if args.len() < 5 {
+                eprintln!(
+                    "Usage: {} {} [vm-state.db] [contract-identifier] [contract-definition.clar] [test-script]",
+                    invoked_by, args[0]
+                );
+                eprintln!("   Deploys each mutant of the contract in a scratch block and runs the test script against it.");
+                eprintln!("   Each line of the test script is a call: SENDER FUNCTION [ARGS...]");
+                eprintln!("   A mutant is killed if any call's result differs from the unmutated contract's.");
+                eprintln!("   Exits with 1 if any mutant survives.");
+                panic_test!();
+            }
+
+            let vm_filename = &args[1];
+            let contract_identifier = friendly_expect(
+                QualifiedContractIdentifier::parse(&args[2]),
+                "Failed to parse contract identifier.",
+            );
+            let contract_src_file = &args[3];
+            let contract_content: String = friendly_expect(
+                fs::read_to_string(contract_src_file),
+                &format!("Error reading file: {}", contract_src_file),
+            );
+            let script_file = &args[4];
+            let script: String = friendly_expect(
+                fs::read_to_string(script_file),
+                &format!("Error reading file: {}", script_file),
+            );
+            let calls = friendly_expect(
+                parse_mutation_script(&script),
+                "Failed to parse test script.",
+            );
+            let mutants = friendly_expect(
+                generate_mutants(&contract_content).map_err(|e| e.message()),
+                "Failed to parse program.",
+            );
+
+            let header_db =
+                friendly_expect(CLIHeadersDB::resume(vm_filename), "Failed to open CLI DB");
+            let mut marf_kv = friendly_expect(
+                MarfedKV::open(vm_filename, None, Some(cli_marf_opts())),
+                "Failed to open VM database.",
+            );
+            let chain_tip = get_cli_chain_tip(header_db.conn());
+
+            let expected = match run_mutation_suite(
+                &header_db,
+                &mut marf_kv,
+                &chain_tip,
+                &contract_identifier,
+                &contract_content,
+                &calls,
+            ) {
+                Ok(outcomes) => outcomes,
+                Err(error) => {
+                    return (
+                        1,
+                        Some(json!({
+                            "error": {
+                                "initialization": error
+                            }
+                        })),
+                    );
+                }
+            };
+
+            let mut killed = 0;
+            let mut invalid = 0;
+            let mut surviving = vec![];
+            for mutant in mutants.iter() {
+                match run_mutation_suite(
+                    &header_db,
+                    &mut marf_kv,
+                    &chain_tip,
+                    &contract_identifier,
+                    &mutant.source,
+                    &calls,
+                ) {
+                    // mutants which no longer typecheck are not interesting
+                    Err(_) => invalid += 1,
+                    Ok(outcomes) if outcomes != expected => killed += 1,
+                    Ok(_) => surviving.push(json!({
+                        "operator": mutant.operator,
+                        "span": mutant.span,
+                        "original": mutant.original,
+                        "replacement": mutant.replacement,
+                    })),
+                }
+            }
+
+            let result = json!({
+                "mutants": mutants.len(),
+                "killed": killed,
+                "invalid": invalid,
+                "survived": surviving.len(),
+                "surviving": surviving,
+            });
+            if surviving.is_empty() {
+                (0, Some(result))
+            } else {
+                (1, Some(result))
+            }
+        }
         _ => {
             print_usage(invoked_by);
             (1, None)
@@ -2363,4 +3404,194 @@ mod test {
                 })
         );
     }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let db_name = format!("/tmp/db_{}", rand::thread_rng().gen::<i32>());
+        let import_db_name = format!("/tmp/db_{}", rand::thread_rng().gen::<i32>());
+        let contract_file = format!("/tmp/snapshot_{}.clar", rand::thread_rng().gen::<i32>());
+        let snapshot_file = format!("/tmp/snapshot_{}.json", rand::thread_rng().gen::<i32>());
+        let contract_id = "S1G2081040G2081040G2081040G208105NK8PE5.snapshot";
+
+        fs::write(
+            &contract_file,
+            "(define-data-var counter int 0)
+             (define-map owners uint { owner: principal, memo: (buff 4) })
+             (define-fungible-token stackaroos)
+             (define-non-fungible-token badges uint)
+             (define-public (bump (id uint))
+                (begin
+                    (var-set counter (+ (var-get counter) 1))
+                    (map-set owners id { owner: tx-sender, memo: 0x01020304 })
+                    (try! (ft-mint? stackaroos u100 tx-sender))
+                    (nft-mint? badges id tx-sender)))
+             (define-public (unbump (id uint))
+                (begin
+                    (map-delete owners id)
+                    (nft-burn? badges id tx-sender)))",
+        )
+        .unwrap();
+
+        invoke_command("test", &["initialize".to_string(), db_name.clone()]);
+        let invoked = invoke_command(
+            "test",
+            &[
+                "launch".to_string(),
+                contract_id.to_string(),
+                contract_file.clone(),
+                db_name.clone(),
+            ],
+        );
+        assert_eq!(invoked.0, 0, "{:?}", invoked.1);
+
+        for (function, arg) in [("bump", "u1"), ("bump", "u2"), ("unbump", "u1")] {
+            let invoked = invoke_command(
+                "test",
+                &[
+                    "execute".to_string(),
+                    db_name.clone(),
+                    contract_id.to_string(),
+                    function.to_string(),
+                    "SZ2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQ9H6DPR".to_string(),
+                    arg.to_string(),
+                ],
+            );
+            assert_eq!(invoked.0, 0, "{:?}", invoked.1);
+        }
+
+        let (exit, exported) = invoke_command(
+            "test",
+            &[
+                "export_snapshot".to_string(),
+                db_name.clone(),
+                contract_id.to_string(),
+            ],
+        );
+        let exported = exported.unwrap();
+        assert_eq!(exit, 0, "{}", &exported);
+
+        let contract = &exported["contracts"][0];
+        assert_eq!(contract["data_vars"]["counter"], json!("2"));
+        assert_eq!(
+            contract["maps"]["owners"],
+            json!([{
+                "key": "2",
+                "value": {
+                    "owner": "SZ2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQ9H6DPR",
+                    "memo": "0x01020304"
+                }
+            }])
+        );
+        assert_eq!(
+            contract["fungible_tokens"]["stackaroos"],
+            json!({
+                "supply": "200",
+                "balances": { "SZ2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQ9H6DPR": "200" }
+            })
+        );
+        assert_eq!(
+            contract["non_fungible_tokens"]["badges"],
+            json!([{ "id": "2", "owner": "SZ2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQ9H6DPR" }])
+        );
+        assert!(exported["stx_balances"]
+            .get("SZ2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQ9H6DPR")
+            .is_some());
+
+        fs::write(&snapshot_file, serde_json::to_string(&exported).unwrap()).unwrap();
+        invoke_command("test", &["initialize".to_string(), import_db_name.clone()]);
+        let invoked = invoke_command(
+            "test",
+            &[
+                "import_snapshot".to_string(),
+                snapshot_file.clone(),
+                import_db_name.clone(),
+            ],
+        );
+        assert_eq!(invoked.0, 0, "{:?}", invoked.1);
+
+        let (exit, reexported) = invoke_command(
+            "test",
+            &[
+                "export_snapshot".to_string(),
+                import_db_name.clone(),
+                contract_id.to_string(),
+            ],
+        );
+        let reexported = reexported.unwrap();
+        assert_eq!(exit, 0, "{}", &reexported);
+        assert_eq!(exported["contracts"], reexported["contracts"]);
+        assert_eq!(exported["stx_balances"], reexported["stx_balances"]);
+    }
+
+    #[test]
+    fn test_mutate() {
+        let db_name = format!("/tmp/db_{}", rand::thread_rng().gen::<i32>());
+        let contract_file = format!("/tmp/mutate_{}.clar", rand::thread_rng().gen::<i32>());
+        let script_file = format!("/tmp/mutate_{}.txt", rand::thread_rng().gen::<i32>());
+        let contract_id = "S1G2081040G2081040G2081040G208105NK8PE5.mutate";
+
+        fs::write(
+            &contract_file,
+            "(define-map balances principal uint)
+             (define-public (deposit (amount uint))
+                (begin
+                    (asserts! (> amount u0) (err u1))
+                    (map-set balances tx-sender
+                        (+ (default-to u0 (map-get? balances tx-sender)) amount))
+                    (ok amount)))
+             (define-read-only (get-balance (who principal))
+                (ok (default-to u0 (map-get? balances who))))",
+        )
+        .unwrap();
+        // never tries a zero deposit, so removing the assertion goes unnoticed
+        fs::write(
+            &script_file,
+            ";; deposit twice, then check the balance
+             SZ2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQ9H6DPR deposit u10
+             SZ2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQ9H6DPR deposit u5
+             SZ2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQ9H6DPR get-balance 'SZ2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQ9H6DPR",
+        )
+        .unwrap();
+
+        invoke_command("test", &["initialize".to_string(), db_name.clone()]);
+        let (exit, result) = invoke_command(
+            "test",
+            &[
+                "mutate".to_string(),
+                db_name.clone(),
+                contract_id.to_string(),
+                contract_file.clone(),
+                script_file.clone(),
+            ],
+        );
+        let result = result.unwrap();
+        assert_eq!(exit, 1, "{}", &result);
+        assert_eq!(result["mutants"], json!(6));
+        assert_eq!(result["survived"], json!(1));
+        assert_eq!(
+            result["killed"].as_u64().unwrap() + result["invalid"].as_u64().unwrap(),
+            5
+        );
+        let surviving: Vec<_> = result["surviving"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|mutant| mutant["operator"].as_str().unwrap())
+            .collect();
+        assert_eq!(surviving, vec!["remove-assertion"]);
+        assert_eq!(result["surviving"][0]["original"], json!("(> amount u0)"));
+        assert_eq!(result["surviving"][0]["span"]["start_line"], json!(4));
+
+        // the contract itself was never deployed
+        let invoked = invoke_command(
+            "test",
+            &[
+                "launch".to_string(),
+                contract_id.to_string(),
+                contract_file.clone(),
+                db_name.clone(),
+            ],
+        );
+        assert_eq!(invoked.0, 0, "{:?}", invoked.1);
+    }
 }
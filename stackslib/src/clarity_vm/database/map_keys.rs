@@ -0,0 +1,566 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2023 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Non-consensus secondary index of the data map keys written by each contract.
+//!
+//! Clarity data maps cannot be enumerated from the MARF, since the MARF only stores the hash of
+//! each key.  When enabled, this index records every data map key a contract writes in each
+//! block, in a table in the Clarity side-store.  The same is true of fungible token balances and
+//! non-fungible token owners, so their keys (the holder, and the asset identifier, respectively)
+//! are recorded too.  The table is written in the same transaction as
+//! the block's MARF trie, so it is committed, moved, and dropped along with it.
+//!
+//! The index only says which keys were *ever* written; whether or not a key is present at a
+//! given chain tip (and what its value is) must still be determined by reading the MARF at that
+//! tip.
+//!
+//! Version 1 of the index only recorded data map keys.  Migrating it to version 2 keeps those,
+//! but token keys written before the migration are not recorded.
+
+use clarity::vm::database::{ClarityDatabase, StoreType};
+use clarity::vm::types::QualifiedContractIdentifier;
+use clarity::vm::ClarityName;
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, NO_PARAMS};
+use stacks_common::types::chainstate::StacksBlockId;
+
+use crate::util_lib::db::{query_row, query_rows, table_exists, u64_to_sql, Error as DBError};
+
+/// Current version of the index's schema
+pub const DATA_MAP_KEYS_SCHEMA_VERSION: u32 = 2;
+
+const DATA_MAP_KEYS_SCHEMA_VERSION_TABLE: &'static [&'static str] =
+    &["CREATE TABLE IF NOT EXISTS data_map_keys_version(version INTEGER NOT NULL);"];
+
+const DATA_MAP_KEYS_SCHEMA: &'static [&'static str] = &[
+    r#"
+    CREATE TABLE IF NOT EXISTS data_map_keys(
+        -- one of StoreType::{DataMap, FungibleToken, NonFungibleToken}
+        store_type INTEGER NOT NULL,
+        contract_id TEXT NOT NULL,
+        -- name of the data map or token
+        map_name TEXT NOT NULL,
+        -- hex-encoded consensus serialization of the key
+        key_hex TEXT NOT NULL,
+        -- block in which the key was written
+        index_block_hash TEXT NOT NULL,
+        block_height INTEGER NOT NULL,
+
+        PRIMARY KEY(store_type,contract_id,map_name,key_hex,index_block_hash)
+    );"#,
+    "CREATE INDEX IF NOT EXISTS index_data_map_keys_by_block ON data_map_keys(index_block_hash);",
+];
+
+/// Version 1 had no `store_type` column, and only recorded data map keys.  Move its table out of
+/// the way, so the current schema can be created and the old rows copied into it.
+const DATA_MAP_KEYS_SCHEMA_MIGRATE_1_BEGIN: &'static [&'static str] = &[
+    "DROP INDEX IF EXISTS index_data_map_keys_by_block;",
+    "ALTER TABLE data_map_keys RENAME TO data_map_keys_v1;",
+];
+
+const DATA_MAP_KEYS_SCHEMA_MIGRATE_1_COPY: &'static str = "INSERT INTO data_map_keys (store_type,contract_id,map_name,key_hex,index_block_hash,block_height) SELECT ?1,contract_id,map_name,key_hex,index_block_hash,block_height FROM data_map_keys_v1;";
+
+const DATA_MAP_KEYS_SCHEMA_MIGRATE_1_FINISH: &'static [&'static str] =
+    &["DROP TABLE data_map_keys_v1;"];
+
+/// Largest page of keys a caller may request at once
+pub const DATA_MAP_KEYS_MAX_PAGE: u32 = 1024;
+
+pub struct DataMapKeyIndex {}
+
+impl DataMapKeyIndex {
+    /// Create the index table if it doesn't exist already, or migrate it to the current schema
+    /// if it does.
+    pub fn instantiate(conn: &Connection) -> Result<(), DBError> {
+        let version = DataMapKeyIndex::get_schema_version(conn)?;
+        if version == Some(DATA_MAP_KEYS_SCHEMA_VERSION) {
+            return Ok(());
+        }
+        if version == Some(1) {
+            for cmd in DATA_MAP_KEYS_SCHEMA_MIGRATE_1_BEGIN.iter() {
+                conn.execute(cmd, NO_PARAMS)?;
+            }
+        }
+        for cmd in DATA_MAP_KEYS_SCHEMA.iter() {
+            conn.execute(cmd, NO_PARAMS)?;
+        }
+        if version == Some(1) {
+            conn.execute(
+                DATA_MAP_KEYS_SCHEMA_MIGRATE_1_COPY,
+                &[&(StoreType::DataMap as u8)],
+            )?;
+            for cmd in DATA_MAP_KEYS_SCHEMA_MIGRATE_1_FINISH.iter() {
+                conn.execute(cmd, NO_PARAMS)?;
+            }
+        }
+        for cmd in DATA_MAP_KEYS_SCHEMA_VERSION_TABLE.iter() {
+            conn.execute(cmd, NO_PARAMS)?;
+        }
+        conn.execute("DELETE FROM data_map_keys_version", NO_PARAMS)?;
+        conn.execute(
+            "INSERT INTO data_map_keys_version (version) VALUES (?1)",
+            &[&DATA_MAP_KEYS_SCHEMA_VERSION],
+        )?;
+        Ok(())
+    }
+
+    /// Get the schema version of the index, or None if there is no index.
+    /// An index without a version table predates versioning, and is version 1.
+    pub fn get_schema_version(conn: &Connection) -> Result<Option<u32>, DBError> {
+        if !DataMapKeyIndex::exists(conn)? {
+            return Ok(None);
+        }
+        if !table_exists(conn, "data_map_keys_version").map_err(DBError::SqliteError)? {
+            return Ok(Some(1));
+        }
+        let version: Option<u32> =
+            query_row(conn, "SELECT version FROM data_map_keys_version", NO_PARAMS)?;
+        Ok(Some(version.unwrap_or(1)))
+    }
+
+    /// Has this side-store ever been used to index data map keys?
+    pub fn exists(conn: &Connection) -> Result<bool, DBError> {
+        table_exists(conn, "data_map_keys").map_err(DBError::SqliteError)
+    }
+
+    /// Does this side-store have an index with the current schema?
+    pub fn is_current(conn: &Connection) -> Result<bool, DBError> {
+        Ok(DataMapKeyIndex::get_schema_version(conn)? == Some(DATA_MAP_KEYS_SCHEMA_VERSION))
+    }
+
+    /// Decode a MARF key into the data map entry, fungible token balance, or non-fungible token
+    /// owner it represents.
+    /// Returns (store type, contract, map or token name, hex-encoded key) if this is such a key
+    /// (see `ClarityDatabase::make_key_for_quad()`), or None if not.
+    pub fn parse_indexed_key(
+        marf_key: &str,
+    ) -> Option<(u8, QualifiedContractIdentifier, ClarityName, String)> {
+        let mut parts = marf_key.splitn(5, "::");
+        if parts.next()? != "vm" {
+            return None;
+        }
+        let contract_id = QualifiedContractIdentifier::parse(parts.next()?).ok()?;
+        let store_type = parts.next()?.parse::<u8>().ok()?;
+        if store_type != StoreType::DataMap as u8
+            && store_type != StoreType::FungibleToken as u8
+            && store_type != StoreType::NonFungibleToken as u8
+        {
+            return None;
+        }
+        let map_name = ClarityName::try_from(parts.next()?.to_string()).ok()?;
+        let key_hex = parts.next()?.to_string();
+        Some((store_type, contract_id, map_name, key_hex))
+    }
+
+    /// Record the data map keys in a batch of MARF writes.
+    /// `block_id` is the (possibly temporary) index block hash of the block being built.
+    pub fn record_keys(
+        conn: &Connection,
+        block_id: &StacksBlockId,
+        block_height: u32,
+        items: &[(String, String)],
+    ) -> Result<(), DBError> {
+        let height = u64_to_sql(block_height.into())?;
+        for (marf_key, _) in items.iter() {
+            let Some((store_type, contract_id, map_name, key_hex)) =
+                DataMapKeyIndex::parse_indexed_key(marf_key)
+            else {
+                continue;
+            };
+            let args: &[&dyn ToSql] = &[
+                &store_type,
+                &contract_id.to_string(),
+                &map_name.as_str(),
+                &key_hex,
+                block_id,
+                &height,
+            ];
+            conn.execute(
+                "INSERT OR IGNORE INTO data_map_keys (store_type,contract_id,map_name,key_hex,index_block_hash,block_height) VALUES (?1,?2,?3,?4,?5,?6)",
+                args,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Move the keys recorded for a temporary block ID to its final block ID
+    pub fn commit_to(
+        conn: &Connection,
+        from: &StacksBlockId,
+        to: &StacksBlockId,
+    ) -> Result<(), DBError> {
+        let args: &[&dyn ToSql] = &[to, from];
+        conn.execute(
+            "UPDATE OR REPLACE data_map_keys SET index_block_hash = ?1 WHERE index_block_hash = ?2",
+            args,
+        )?;
+        Ok(())
+    }
+
+    /// Forget the keys recorded for a block that will not be stored (i.e. a mined block or
+    /// discarded unconfirmed state)
+    pub fn drop_block(conn: &Connection, block_id: &StacksBlockId) -> Result<(), DBError> {
+        conn.execute(
+            "DELETE FROM data_map_keys WHERE index_block_hash = ?1",
+            &[block_id],
+        )?;
+        Ok(())
+    }
+
+    /// Get up to `limit` distinct keys ever written to the given map (or token, depending on
+    /// `store_type`), in lexicographic order of their hex encoding, starting strictly after
+    /// `after` (if given).
+    /// Keys written on any fork are returned.
+    pub fn get_keys(
+        conn: &Connection,
+        store_type: StoreType,
+        contract_id: &QualifiedContractIdentifier,
+        map_name: &ClarityName,
+        after: Option<&str>,
+        limit: u32,
+    ) -> Result<Vec<String>, DBError> {
+        let limit = u64_to_sql(limit.min(DATA_MAP_KEYS_MAX_PAGE).into())?;
+        let after = after.unwrap_or("");
+        let args: &[&dyn ToSql] = &[
+            &(store_type as u8),
+            &contract_id.to_string(),
+            &map_name.as_str(),
+            &after,
+            &limit,
+        ];
+        query_rows(
+            conn,
+            "SELECT DISTINCT key_hex FROM data_map_keys WHERE store_type = ?1 AND contract_id = ?2 AND map_name = ?3 AND key_hex > ?4 ORDER BY key_hex ASC LIMIT ?5",
+            args,
+        )
+    }
+
+    /// Get the distinct keys written to the given map (or token) in a particular block
+    pub fn get_keys_in_block(
+        conn: &Connection,
+        block_id: &StacksBlockId,
+        store_type: StoreType,
+        contract_id: &QualifiedContractIdentifier,
+        map_name: &ClarityName,
+    ) -> Result<Vec<String>, DBError> {
+        let args: &[&dyn ToSql] = &[
+            block_id,
+            &(store_type as u8),
+            &contract_id.to_string(),
+            &map_name.as_str(),
+        ];
+        query_rows(
+            conn,
+            "SELECT key_hex FROM data_map_keys WHERE index_block_hash = ?1 AND store_type = ?2 AND contract_id = ?3 AND map_name = ?4 ORDER BY key_hex ASC",
+            args,
+        )
+    }
+
+    /// Get every distinct key ever recorded for a contract, as (store type, map or token name,
+    /// hex-encoded key), ordered by store type, then name, then key.
+    pub fn get_contract_keys(
+        conn: &Connection,
+        contract_id: &QualifiedContractIdentifier,
+    ) -> Result<Vec<(u8, String, String)>, DBError> {
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT store_type,map_name,key_hex FROM data_map_keys WHERE contract_id = ?1 ORDER BY store_type,map_name,key_hex ASC",
+        )?;
+        let rows = stmt.query_map(&[&contract_id.to_string()], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?;
+        let keys = rows.collect::<Result<Vec<_>, _>>()?;
+        Ok(keys)
+    }
+
+    /// Make the MARF key for a recorded key
+    pub fn make_marf_key(
+        store_type: StoreType,
+        contract_id: &QualifiedContractIdentifier,
+        map_name: &ClarityName,
+        key_hex: &str,
+    ) -> String {
+        ClarityDatabase::make_key_for_quad(contract_id, store_type, map_name, key_hex)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clarity::vm::Value;
+
+    use super::*;
+
+    #[test]
+    fn test_record_and_list_data_map_keys() {
+        let conn = Connection::open_in_memory().unwrap();
+        DataMapKeyIndex::instantiate(&conn).unwrap();
+        assert!(DataMapKeyIndex::exists(&conn).unwrap());
+
+        let contract_id =
+            QualifiedContractIdentifier::parse("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R.foo")
+                .unwrap();
+        let map_name = ClarityName::from("bar");
+        let keys: Vec<_> = (0..4)
+            .map(|i| Value::UInt(i).serialize_to_hex().unwrap())
+            .collect();
+
+        let mut items: Vec<_> = keys
+            .iter()
+            .map(|key_hex| {
+                (
+                    DataMapKeyIndex::make_marf_key(
+                        StoreType::DataMap,
+                        &contract_id,
+                        &map_name,
+                        key_hex,
+                    ),
+                    "".to_string(),
+                )
+            })
+            .collect();
+
+        // not data map keys
+        items.push((
+            ClarityDatabase::make_key_for_trip(&contract_id, StoreType::Variable, "bar"),
+            "".to_string(),
+        ));
+        items.push(("vm-epoch::epoch-version".to_string(), "".to_string()));
+
+        // a token balance, for a token with the same name
+        let holder_hex = Value::Principal(contract_id.clone().into())
+            .serialize_to_hex()
+            .unwrap();
+        items.push((
+            DataMapKeyIndex::make_marf_key(
+                StoreType::FungibleToken,
+                &contract_id,
+                &map_name,
+                &holder_hex,
+            ),
+            "".to_string(),
+        ));
+
+        assert_eq!(
+            DataMapKeyIndex::parse_indexed_key(&items[0].0),
+            Some((
+                StoreType::DataMap as u8,
+                contract_id.clone(),
+                map_name.clone(),
+                keys[0].clone()
+            ))
+        );
+        assert!(DataMapKeyIndex::parse_indexed_key(&items[4].0).is_none());
+        assert!(DataMapKeyIndex::parse_indexed_key(&items[5].0).is_none());
+        assert_eq!(
+            DataMapKeyIndex::parse_indexed_key(&items[6].0),
+            Some((
+                StoreType::FungibleToken as u8,
+                contract_id.clone(),
+                map_name.clone(),
+                holder_hex.clone()
+            ))
+        );
+
+        let temp_block = StacksBlockId([0x01; 32]);
+        let final_block = StacksBlockId([0x02; 32]);
+        let mined_block = StacksBlockId([0x03; 32]);
+
+        DataMapKeyIndex::record_keys(&conn, &temp_block, 1, &items[0..3]).unwrap();
+        DataMapKeyIndex::commit_to(&conn, &temp_block, &final_block).unwrap();
+
+        // re-writing a key in another block doesn't duplicate it
+        DataMapKeyIndex::record_keys(&conn, &mined_block, 2, &items).unwrap();
+
+        assert_eq!(
+            DataMapKeyIndex::get_keys_in_block(
+                &conn,
+                &final_block,
+                StoreType::DataMap,
+                &contract_id,
+                &map_name
+            )
+            .unwrap()
+            .len(),
+            3
+        );
+        assert!(DataMapKeyIndex::get_keys_in_block(
+            &conn,
+            &temp_block,
+            StoreType::DataMap,
+            &contract_id,
+            &map_name
+        )
+        .unwrap()
+        .is_empty());
+
+        let mut all_keys = keys.clone();
+        all_keys.sort();
+        assert_eq!(
+            DataMapKeyIndex::get_keys(
+                &conn,
+                StoreType::DataMap,
+                &contract_id,
+                &map_name,
+                None,
+                100
+            )
+            .unwrap(),
+            all_keys
+        );
+        assert_eq!(
+            DataMapKeyIndex::get_keys(
+                &conn,
+                StoreType::DataMap,
+                &contract_id,
+                &map_name,
+                Some(&all_keys[1]),
+                2
+            )
+            .unwrap(),
+            all_keys[2..4].to_vec()
+        );
+
+        assert_eq!(
+            DataMapKeyIndex::get_keys(
+                &conn,
+                StoreType::FungibleToken,
+                &contract_id,
+                &map_name,
+                None,
+                100
+            )
+            .unwrap(),
+            vec![holder_hex.clone()]
+        );
+
+        let contract_keys = DataMapKeyIndex::get_contract_keys(&conn, &contract_id).unwrap();
+        assert_eq!(contract_keys.len(), 5);
+        assert_eq!(
+            contract_keys[0],
+            (
+                StoreType::DataMap as u8,
+                "bar".to_string(),
+                all_keys[0].clone()
+            )
+        );
+        assert_eq!(
+            contract_keys[4],
+            (
+                StoreType::FungibleToken as u8,
+                "bar".to_string(),
+                holder_hex
+            )
+        );
+
+        DataMapKeyIndex::drop_block(&conn, &mined_block).unwrap();
+        assert_eq!(
+            DataMapKeyIndex::get_keys(
+                &conn,
+                StoreType::DataMap,
+                &contract_id,
+                &map_name,
+                None,
+                100
+            )
+            .unwrap()
+            .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_migrate_data_map_keys_v1() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE data_map_keys(contract_id TEXT NOT NULL, map_name TEXT NOT NULL, key_hex TEXT NOT NULL, index_block_hash TEXT NOT NULL, block_height INTEGER NOT NULL, PRIMARY KEY(contract_id,map_name,key_hex,index_block_hash));",
+            NO_PARAMS,
+        )
+        .unwrap();
+        conn.execute(
+            "CREATE INDEX index_data_map_keys_by_block ON data_map_keys(index_block_hash);",
+            NO_PARAMS,
+        )
+        .unwrap();
+
+        let contract_id =
+            QualifiedContractIdentifier::parse("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R.foo")
+                .unwrap();
+        let map_name = ClarityName::from("bar");
+        let key_hex = Value::UInt(1).serialize_to_hex().unwrap();
+        let block = StacksBlockId([0x01; 32]);
+        let args: &[&dyn ToSql] = &[
+            &contract_id.to_string(),
+            &map_name.as_str(),
+            &key_hex,
+            &block,
+        ];
+        conn.execute(
+            "INSERT INTO data_map_keys (contract_id,map_name,key_hex,index_block_hash,block_height) VALUES (?1,?2,?3,?4,1)",
+            args,
+        )
+        .unwrap();
+
+        assert_eq!(DataMapKeyIndex::get_schema_version(&conn).unwrap(), Some(1));
+        assert!(!DataMapKeyIndex::is_current(&conn).unwrap());
+
+        DataMapKeyIndex::instantiate(&conn).unwrap();
+        assert_eq!(
+            DataMapKeyIndex::get_schema_version(&conn).unwrap(),
+            Some(DATA_MAP_KEYS_SCHEMA_VERSION)
+        );
+        assert!(DataMapKeyIndex::is_current(&conn).unwrap());
+        assert!(!table_exists(&conn, "data_map_keys_v1").unwrap());
+
+        // old keys are kept as data map keys
+        assert_eq!(
+            DataMapKeyIndex::get_keys_in_block(
+                &conn,
+                &block,
+                StoreType::DataMap,
+                &contract_id,
+                &map_name
+            )
+            .unwrap(),
+            vec![key_hex.clone()]
+        );
+
+        // and token keys can now be recorded alongside them
+        let items = vec![(
+            DataMapKeyIndex::make_marf_key(
+                StoreType::FungibleToken,
+                &contract_id,
+                &map_name,
+                &key_hex,
+            ),
+            "".to_string(),
+        )];
+        DataMapKeyIndex::record_keys(&conn, &block, 1, &items).unwrap();
+        assert_eq!(
+            DataMapKeyIndex::get_contract_keys(&conn, &contract_id)
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // instantiating a current index is a no-op
+        DataMapKeyIndex::instantiate(&conn).unwrap();
+        assert_eq!(
+            DataMapKeyIndex::get_contract_keys(&conn, &contract_id)
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+}
@@ -18,6 +18,7 @@ use crate::chainstate::stacks::index::marf::{MARFOpenOpts, MarfConnection, MarfT
 use crate::chainstate::stacks::index::{
     ClarityMarfTrieId, Error, MARFValue, MarfTrieId, TrieMerkleProof,
 };
+use crate::clarity_vm::database::map_keys::DataMapKeyIndex;
 use crate::clarity_vm::special::handle_contract_call_special_cases;
 use crate::core::{FIRST_BURNCHAIN_CONSENSUS_HASH, FIRST_STACKS_BLOCK_HASH};
 use crate::util_lib::db::{Error as DatabaseError, IndexDBConn};
@@ -32,6 +33,8 @@ use crate::util_lib::db::{Error as DatabaseError, IndexDBConn};
 pub struct MarfedKV {
     chain_tip: StacksBlockId,
     marf: MARF<StacksBlockId>,
+    /// record the data map keys written in each block (see `DataMapKeyIndex`)
+    index_data_map_keys: bool,
 }
 
 impl MarfedKV {
@@ -53,6 +56,7 @@ impl MarfedKV {
 
         let mut marf_opts = marf_opts.unwrap_or(MARFOpenOpts::default());
         marf_opts.external_blobs = true;
+        let index_data_map_keys = marf_opts.index_data_map_keys;
 
         let mut marf: MARF<StacksBlockId> = if unconfirmed {
             MARF::from_path_unconfirmed(&marf_path, marf_opts)
@@ -62,7 +66,10 @@ impl MarfedKV {
                 .map_err(|err| InterpreterError::MarfFailure(err.to_string()))?
         };
 
-        if SqliteConnection::check_schema(&marf.sqlite_conn()).is_ok() {
+        if SqliteConnection::check_schema(&marf.sqlite_conn()).is_ok()
+            && (!index_data_map_keys
+                || DataMapKeyIndex::is_current(&marf.sqlite_conn()).unwrap_or(false))
+        {
             // no need to initialize
             return Ok(marf);
         }
@@ -72,6 +79,10 @@ impl MarfedKV {
             .map_err(|err| InterpreterError::DBError(err.to_string()))?;
 
         SqliteConnection::initialize_conn(&tx)?;
+        if index_data_map_keys {
+            DataMapKeyIndex::instantiate(&tx)
+                .map_err(|err| InterpreterError::DBError(err.to_string()))?;
+        }
         tx.commit()
             .map_err(|err| InterpreterError::SqliteError(IncomparableError { err }))?;
 
@@ -83,13 +94,21 @@ impl MarfedKV {
         miner_tip: Option<&StacksBlockId>,
         marf_opts: Option<MARFOpenOpts>,
     ) -> InterpreterResult<MarfedKV> {
+        let index_data_map_keys = marf_opts
+            .as_ref()
+            .map(|opts| opts.index_data_map_keys)
+            .unwrap_or(false);
         let marf = MarfedKV::setup_db(path_str, false, marf_opts)?;
         let chain_tip = match miner_tip {
             Some(miner_tip) => miner_tip.clone(),
             None => StacksBlockId::sentinel(),
         };
 
-        Ok(MarfedKV { marf, chain_tip })
+        Ok(MarfedKV {
+            marf,
+            chain_tip,
+            index_data_map_keys,
+        })
     }
 
     pub fn open_unconfirmed(
@@ -97,13 +116,21 @@ impl MarfedKV {
         miner_tip: Option<&StacksBlockId>,
         marf_opts: Option<MARFOpenOpts>,
     ) -> InterpreterResult<MarfedKV> {
+        let index_data_map_keys = marf_opts
+            .as_ref()
+            .map(|opts| opts.index_data_map_keys)
+            .unwrap_or(false);
         let marf = MarfedKV::setup_db(path_str, true, marf_opts)?;
         let chain_tip = match miner_tip {
             Some(miner_tip) => miner_tip.clone(),
             None => StacksBlockId::sentinel(),
         };
 
-        Ok(MarfedKV { marf, chain_tip })
+        Ok(MarfedKV {
+            marf,
+            chain_tip,
+            index_data_map_keys,
+        })
     }
 
     // used by benchmarks
@@ -134,7 +161,11 @@ impl MarfedKV {
 
         let chain_tip = StacksBlockId::sentinel();
 
-        MarfedKV { marf, chain_tip }
+        MarfedKV {
+            marf,
+            chain_tip,
+            index_data_map_keys: false,
+        }
     }
 
     pub fn begin_read_only<'a>(
@@ -212,6 +243,7 @@ impl MarfedKV {
         WritableMarfStore {
             chain_tip,
             marf: tx,
+            index_data_map_keys: self.index_data_map_keys,
         }
     }
 
@@ -233,6 +265,7 @@ impl MarfedKV {
         WritableMarfStore {
             chain_tip,
             marf: tx,
+            index_data_map_keys: self.index_data_map_keys,
         }
     }
 
@@ -249,6 +282,20 @@ impl MarfedKV {
         self.marf.sqlite_conn()
     }
 
+    /// Does this store's side-store have a data map key index with the current schema?
+    pub fn has_data_map_key_index(&self) -> Result<bool, DatabaseError> {
+        DataMapKeyIndex::is_current(self.marf.sqlite_conn())
+    }
+
+    /// Get every key recorded for a contract in the data map key index, as (store type, map or
+    /// token name, hex-encoded key).  See `DataMapKeyIndex::get_contract_keys()`.
+    pub fn get_indexed_contract_keys(
+        &self,
+        contract_id: &QualifiedContractIdentifier,
+    ) -> Result<Vec<(u8, String, String)>, DatabaseError> {
+        DataMapKeyIndex::get_contract_keys(self.marf.sqlite_conn(), contract_id)
+    }
+
     pub fn index_conn<'a, C>(&'a self, context: C) -> IndexDBConn<'a, C, StacksBlockId> {
         IndexDBConn {
             index: &self.marf,
@@ -260,6 +307,7 @@ impl MarfedKV {
 pub struct WritableMarfStore<'a> {
     chain_tip: StacksBlockId,
     marf: MarfTransaction<'a, StacksBlockId>,
+    index_data_map_keys: bool,
 }
 
 pub struct ReadOnlyMarfStore<'a> {
@@ -299,24 +347,28 @@ impl<'a> ClarityBackingStore for ReadOnlyMarfStore<'a> {
 
     /// Sets the chain tip at which queries will happen.  Used for `(at-block ..)`
     fn set_block_hash(&mut self, bhh: StacksBlockId) -> InterpreterResult<StacksBlockId> {
-        self.marf
-            .check_ancestor_block_hash(&bhh)
-            .map_err(|e| match e {
-                Error::NotFoundError => {
-                    test_debug!("No such block {:?} (NotFoundError)", &bhh);
-                    RuntimeErrorType::UnknownBlockHeaderHash(BlockHeaderHash(bhh.0))
-                }
-                Error::NonMatchingForks(_bh1, _bh2) => {
-                    test_debug!(
-                        "No such block {:?} (NonMatchingForks({}, {}))",
-                        &bhh,
-                        BlockHeaderHash(_bh1),
-                        BlockHeaderHash(_bh2)
-                    );
-                    RuntimeErrorType::UnknownBlockHeaderHash(BlockHeaderHash(bhh.0))
-                }
-                _ => panic!("ERROR: Unexpected MARF failure: {}", e),
-            })?;
+        let check = self.marf.check_ancestor_block_hash(&bhh);
+        if let Err(Error::PrunedError) = check {
+            // this node has discarded the state that `at-block` wants to read
+            warn!("State at block {} has been pruned", &bhh);
+            return Err(InterpreterError::PrunedState(bhh.to_string()).into());
+        }
+        check.map_err(|e| match e {
+            Error::NotFoundError => {
+                test_debug!("No such block {:?} (NotFoundError)", &bhh);
+                RuntimeErrorType::UnknownBlockHeaderHash(BlockHeaderHash(bhh.0))
+            }
+            Error::NonMatchingForks(_bh1, _bh2) => {
+                test_debug!(
+                    "No such block {:?} (NonMatchingForks({}, {}))",
+                    &bhh,
+                    BlockHeaderHash(_bh1),
+                    BlockHeaderHash(_bh2)
+                );
+                RuntimeErrorType::UnknownBlockHeaderHash(BlockHeaderHash(bhh.0))
+            }
+            _ => panic!("ERROR: Unexpected MARF failure: {}", e),
+        })?;
 
         let result = Ok(self.chain_tip);
         self.chain_tip = bhh;
@@ -392,7 +444,10 @@ impl<'a> ClarityBackingStore for ReadOnlyMarfStore<'a> {
                 Error::NotFoundError => Ok(None),
                 _ => Err(e),
             })
-            .map_err(|_| InterpreterError::Expect("ERROR: Unexpected MARF Failure on GET".into()))?
+            .map_err(|e| match e {
+                Error::PrunedError => InterpreterError::PrunedState(self.chain_tip.to_string()),
+                _ => InterpreterError::Expect("ERROR: Unexpected MARF Failure on GET".into()),
+            })?
             .map(|(marf_value, proof)| {
                 let side_key = marf_value.to_hex();
                 let data =
@@ -422,7 +477,10 @@ impl<'a> ClarityBackingStore for ReadOnlyMarfStore<'a> {
                 }
                 _ => Err(e),
             })
-            .map_err(|_| InterpreterError::Expect("ERROR: Unexpected MARF Failure on GET".into()))?
+            .map_err(|e| match e {
+                Error::PrunedError => InterpreterError::PrunedState(self.chain_tip.to_string()),
+                _ => InterpreterError::Expect("ERROR: Unexpected MARF Failure on GET".into()),
+            })?
             .map(|marf_value| {
                 let side_key = marf_value.to_hex();
                 trace!("MarfedKV get side-key for {:?}: {:?}", key, &side_key);
@@ -463,6 +521,10 @@ impl<'a> WritableMarfStore<'a> {
     pub fn rollback_unconfirmed(self) -> InterpreterResult<()> {
         debug!("Drop unconfirmed MARF trie {}", &self.chain_tip);
         SqliteConnection::drop_metadata(self.marf.sqlite_tx(), &self.chain_tip)?;
+        if self.index_data_map_keys {
+            DataMapKeyIndex::drop_block(self.marf.sqlite_tx(), &self.chain_tip)
+                .map_err(|e| InterpreterError::DBError(e.to_string()))?;
+        }
         self.marf.drop_unconfirmed();
         Ok(())
     }
@@ -470,6 +532,10 @@ impl<'a> WritableMarfStore<'a> {
     pub fn commit_to(self, final_bhh: &StacksBlockId) -> InterpreterResult<()> {
         debug!("commit_to({})", final_bhh);
         SqliteConnection::commit_metadata_to(self.marf.sqlite_tx(), &self.chain_tip, final_bhh)?;
+        if self.index_data_map_keys {
+            DataMapKeyIndex::commit_to(self.marf.sqlite_tx(), &self.chain_tip, final_bhh)
+                .map_err(|e| InterpreterError::DBError(e.to_string()))?;
+        }
 
         let _ = self.marf.commit_to(final_bhh).map_err(|e| {
             error!("Failed to commit to MARF block {}: {:?}", &final_bhh, &e);
@@ -507,6 +573,10 @@ impl<'a> WritableMarfStore<'a> {
         //    _if_ for some reason, we do want to be able to access that mined chain state in the future,
         //    we should probably commit the data to a different table which does not have uniqueness constraints.
         SqliteConnection::drop_metadata(self.marf.sqlite_tx(), &self.chain_tip)?;
+        if self.index_data_map_keys {
+            DataMapKeyIndex::drop_block(self.marf.sqlite_tx(), &self.chain_tip)
+                .map_err(|e| InterpreterError::DBError(e.to_string()))?;
+        }
         let _ = self.marf.commit_mined(will_move_to).map_err(|e| {
             error!(
                 "Failed to commit to mined MARF block {}: {:?}",
@@ -524,24 +594,28 @@ impl<'a> WritableMarfStore<'a> {
 
 impl<'a> ClarityBackingStore for WritableMarfStore<'a> {
     fn set_block_hash(&mut self, bhh: StacksBlockId) -> InterpreterResult<StacksBlockId> {
-        self.marf
-            .check_ancestor_block_hash(&bhh)
-            .map_err(|e| match e {
-                Error::NotFoundError => {
-                    test_debug!("No such block {:?} (NotFoundError)", &bhh);
-                    RuntimeErrorType::UnknownBlockHeaderHash(BlockHeaderHash(bhh.0))
-                }
-                Error::NonMatchingForks(_bh1, _bh2) => {
-                    test_debug!(
-                        "No such block {:?} (NonMatchingForks({}, {}))",
-                        &bhh,
-                        BlockHeaderHash(_bh1),
-                        BlockHeaderHash(_bh2)
-                    );
-                    RuntimeErrorType::UnknownBlockHeaderHash(BlockHeaderHash(bhh.0))
-                }
-                _ => panic!("ERROR: Unexpected MARF failure: {}", e),
-            })?;
+        let check = self.marf.check_ancestor_block_hash(&bhh);
+        if let Err(Error::PrunedError) = check {
+            // this node has discarded the state that `at-block` wants to read
+            warn!("State at block {} has been pruned", &bhh);
+            return Err(InterpreterError::PrunedState(bhh.to_string()).into());
+        }
+        check.map_err(|e| match e {
+            Error::NotFoundError => {
+                test_debug!("No such block {:?} (NotFoundError)", &bhh);
+                RuntimeErrorType::UnknownBlockHeaderHash(BlockHeaderHash(bhh.0))
+            }
+            Error::NonMatchingForks(_bh1, _bh2) => {
+                test_debug!(
+                    "No such block {:?} (NonMatchingForks({}, {}))",
+                    &bhh,
+                    BlockHeaderHash(_bh1),
+                    BlockHeaderHash(_bh2)
+                );
+                RuntimeErrorType::UnknownBlockHeaderHash(BlockHeaderHash(bhh.0))
+            }
+            _ => panic!("ERROR: Unexpected MARF failure: {}", e),
+        })?;
 
         let result = Ok(self.chain_tip);
         self.chain_tip = bhh;
@@ -568,7 +642,10 @@ impl<'a> ClarityBackingStore for WritableMarfStore<'a> {
                 }
                 _ => Err(e),
             })
-            .map_err(|_| InterpreterError::Expect("ERROR: Unexpected MARF Failure on GET".into()))?
+            .map_err(|e| match e {
+                Error::PrunedError => InterpreterError::PrunedState(self.chain_tip.to_string()),
+                _ => InterpreterError::Expect("ERROR: Unexpected MARF Failure on GET".into()),
+            })?
             .map(|marf_value| {
                 let side_key = marf_value.to_hex();
                 trace!("MarfedKV get side-key for {:?}: {:?}", key, &side_key);
@@ -590,7 +667,10 @@ impl<'a> ClarityBackingStore for WritableMarfStore<'a> {
                 Error::NotFoundError => Ok(None),
                 _ => Err(e),
             })
-            .map_err(|_| InterpreterError::Expect("ERROR: Unexpected MARF Failure on GET".into()))?
+            .map_err(|e| match e {
+                Error::PrunedError => InterpreterError::PrunedState(self.chain_tip.to_string()),
+                _ => InterpreterError::Expect("ERROR: Unexpected MARF Failure on GET".into()),
+            })?
             .map(|(marf_value, proof)| {
                 let side_key = marf_value.to_hex();
                 let data =
@@ -667,6 +747,16 @@ impl<'a> ClarityBackingStore for WritableMarfStore<'a> {
     }
 
     fn put_all(&mut self, items: Vec<(String, String)>) -> InterpreterResult<()> {
+        if self.index_data_map_keys {
+            let block_height = self.get_open_chain_tip_height();
+            DataMapKeyIndex::record_keys(
+                self.marf.sqlite_tx(),
+                &self.chain_tip,
+                block_height,
+                &items,
+            )
+            .map_err(|e| InterpreterError::DBError(e.to_string()))?;
+        }
         let mut keys = Vec::new();
         let mut values = Vec::new();
         for (key, value) in items.into_iter() {
@@ -31,7 +31,9 @@ use crate::clarity_vm::special::handle_contract_call_special_cases;
 use crate::core::{StacksEpoch, StacksEpochId};
 use crate::util_lib::db::{DBConn, FromColumn, FromRow};
 
+pub mod map_keys;
 pub mod marf;
+pub mod state_diff;
 
 pub struct HeadersDBConn<'a>(pub &'a Connection);
 
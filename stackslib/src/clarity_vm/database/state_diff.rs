@@ -0,0 +1,382 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Differences between the Clarity state at two blocks, in terms of Clarity keys.
+//!
+//! The MARF only stores the hash of each key, so the keys of a diff are recovered by hashing
+//! every key the Clarity side-store knows about: the contracts, data vars, and tokens recorded
+//! in the contract metadata, and (if the data map key index is enabled) every data map key,
+//! token holder, and NFT identifier, plus the accounts of all contracts and token holders.
+//! Keys that cannot be recovered this way (such as the STX balances of principals that never
+//! held a token) are reported by their hash alone.
+
+use std::fmt;
+
+use clarity::vm::database::clarity_store::make_contract_hash_key;
+use clarity::vm::database::{ClarityDatabase, SqliteConnection, StoreType};
+use clarity::vm::types::{PrincipalData, QualifiedContractIdentifier, Value};
+use rusqlite::{Connection, NO_PARAMS};
+use stacks_common::types::chainstate::StacksBlockId;
+
+use crate::chainstate::stacks::index::diff::{resolve_keys, TrieLeafDiff};
+use crate::chainstate::stacks::index::marf::{MarfConnection, MARF};
+use crate::chainstate::stacks::index::MARFValue;
+use crate::chainstate::stacks::Error as ChainstateError;
+use crate::clarity_vm::database::map_keys::DataMapKeyIndex;
+use crate::util_lib::db::{query_rows, Error as DBError};
+
+/// The Clarity state stored under a MARF key
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClarityKey {
+    /// The hash of a contract's code
+    Contract(QualifiedContractIdentifier),
+    /// A data var, data map entry, token balance, token supply, or NFT owner
+    ContractData {
+        contract: QualifiedContractIdentifier,
+        store_type: u8,
+        name: String,
+        /// hex-encoded key of a data map entry, token balance, or NFT owner
+        key: Option<String>,
+    },
+    /// A field of a principal's account
+    Account { principal: String, store_type: u8 },
+    /// Any other key (such as the MARF's block height bookkeeping)
+    Other(String),
+}
+
+fn store_type_name(store_type: u8) -> String {
+    let name = match store_type {
+        x if x == StoreType::DataMap as u8 => "data-map",
+        x if x == StoreType::Variable as u8 => "data-var",
+        x if x == StoreType::FungibleToken as u8 => "ft-balance",
+        x if x == StoreType::CirculatingSupply as u8 => "ft-supply",
+        x if x == StoreType::NonFungibleToken as u8 => "nft-owner",
+        x if x == StoreType::Nonce as u8 => "nonce",
+        x if x == StoreType::STXBalance as u8 => "stx-balance",
+        x if x == StoreType::PoxSTXLockup as u8 => "stx-locked",
+        x if x == StoreType::PoxUnlockHeight as u8 => "unlock-height",
+        x => return format!("store-type-{}", x),
+    };
+    name.to_string()
+}
+
+impl ClarityKey {
+    /// Work out what a MARF key in the Clarity state refers to
+    pub fn parse(key: &str) -> ClarityKey {
+        ClarityKey::try_parse(key).unwrap_or_else(|| ClarityKey::Other(key.to_string()))
+    }
+
+    fn try_parse(key: &str) -> Option<ClarityKey> {
+        if let Some(contract) = key.strip_prefix("clarity-contract::") {
+            let contract = QualifiedContractIdentifier::parse(contract).ok()?;
+            return Some(ClarityKey::Contract(contract));
+        }
+        if let Some(account) = key.strip_prefix("vm-account::") {
+            let (principal, store_type) = account.rsplit_once("::")?;
+            return Some(ClarityKey::Account {
+                principal: principal.to_string(),
+                store_type: store_type.parse().ok()?,
+            });
+        }
+        let mut parts = key.splitn(5, "::");
+        if parts.next()? != "vm" {
+            return None;
+        }
+        let contract = QualifiedContractIdentifier::parse(parts.next()?).ok()?;
+        let store_type = parts.next()?.parse().ok()?;
+        let name = parts.next()?.to_string();
+        let key = parts.next().map(|key| key.to_string());
+        Some(ClarityKey::ContractData {
+            contract,
+            store_type,
+            name,
+            key,
+        })
+    }
+}
+
+impl fmt::Display for ClarityKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClarityKey::Contract(contract) => write!(f, "{} contract", contract),
+            ClarityKey::ContractData {
+                contract,
+                store_type,
+                name,
+                key,
+            } => {
+                write!(f, "{} {} {}", contract, store_type_name(*store_type), name)?;
+                if let Some(key) = key {
+                    match Value::try_deserialize_hex_untyped(key) {
+                        Ok(value) => write!(f, " {}", value)?,
+                        Err(_) => write!(f, " 0x{}", key)?,
+                    }
+                }
+                Ok(())
+            }
+            ClarityKey::Account {
+                principal,
+                store_type,
+            } => write!(f, "{} {}", principal, store_type_name(*store_type)),
+            ClarityKey::Other(key) => write!(f, "{}", key),
+        }
+    }
+}
+
+/// A difference in the Clarity state between two blocks
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClarityStateDiff {
+    /// The difference in the MARF
+    pub diff: TrieLeafDiff,
+    /// What the key refers to, if it could be recovered
+    pub key: Option<ClarityKey>,
+    /// The value at the first block, if present and in the side-store
+    pub from_value: Option<String>,
+    /// The value at the second block, if present and in the side-store
+    pub to_value: Option<String>,
+}
+
+/// Render a side-store value as a Clarity value, if it is one
+fn fmt_side_store_value(
+    f: &mut fmt::Formatter,
+    value: Option<&String>,
+    marf_value: Option<&MARFValue>,
+) -> fmt::Result {
+    match (value, marf_value) {
+        (Some(value), _) => match Value::try_deserialize_hex_untyped(value) {
+            Ok(value) => write!(f, "{}", value),
+            Err(_) => write!(f, "{}", value),
+        },
+        (None, Some(marf_value)) => write!(f, "{}", marf_value),
+        (None, None) => write!(f, "(none)"),
+    }
+}
+
+impl fmt::Display for ClarityStateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.key.as_ref(), self.diff.key.as_ref()) {
+            (Some(ClarityKey::Other(_)), Some(key)) | (None, Some(key)) => write!(f, "{}", key)?,
+            (Some(key), _) => write!(f, "{}", key)?,
+            (None, None) => write!(f, "{}", self.diff.path)?,
+        }
+        write!(f, ": ")?;
+        fmt_side_store_value(f, self.from_value.as_ref(), self.diff.from.as_ref())?;
+        write!(f, " -> ")?;
+        fmt_side_store_value(f, self.to_value.as_ref(), self.diff.to.as_ref())
+    }
+}
+
+/// The account keys of a principal
+fn account_keys(principal: &PrincipalData) -> [String; 4] {
+    [
+        ClarityDatabase::make_key_for_account_balance(principal),
+        ClarityDatabase::make_key_for_account_nonce(principal),
+        ClarityDatabase::make_key_for_account_stx_locked(principal),
+        ClarityDatabase::make_key_for_account_unlock_height(principal),
+    ]
+}
+
+/// The keys that can be derived from the contract metadata: every contract's code hash and
+/// account, and every data var and token supply.
+fn contract_keys(conn: &Connection) -> Result<Vec<String>, DBError> {
+    let metadata_keys: Vec<String> = query_rows(
+        conn,
+        "SELECT DISTINCT key FROM metadata_table WHERE key LIKE 'clr-meta::%::vm-metadata::%'",
+        NO_PARAMS,
+    )?;
+
+    let mut keys = vec![];
+    for metadata_key in metadata_keys.iter() {
+        let Some(rest) = metadata_key.strip_prefix("clr-meta::") else {
+            continue;
+        };
+        let Some((contract, field)) = rest.split_once("::vm-metadata::") else {
+            continue;
+        };
+        let Ok(contract) = QualifiedContractIdentifier::parse(contract) else {
+            continue;
+        };
+        let Some((store_type, name)) = field.split_once("::") else {
+            continue;
+        };
+        let Ok(store_type) = store_type.parse::<u8>() else {
+            continue;
+        };
+        if store_type == StoreType::Contract as u8 && name == "contract" {
+            keys.push(make_contract_hash_key(&contract));
+            keys.extend(account_keys(&PrincipalData::Contract(contract)));
+        } else if store_type == StoreType::VariableMeta as u8 {
+            keys.push(ClarityDatabase::make_key_for_trip(
+                &contract,
+                StoreType::Variable,
+                name,
+            ));
+        } else if store_type == StoreType::FungibleTokenMeta as u8 {
+            keys.push(ClarityDatabase::make_key_for_trip(
+                &contract,
+                StoreType::CirculatingSupply,
+                name,
+            ));
+        }
+    }
+    Ok(keys)
+}
+
+/// The keys recorded by the data map key index (if it is enabled), plus the accounts of every
+/// token holder.
+fn indexed_keys(conn: &Connection) -> Result<Vec<String>, DBError> {
+    if !DataMapKeyIndex::is_current(conn)? {
+        return Ok(vec![]);
+    }
+    let mut keys = vec![];
+    let mut stmt = conn
+        .prepare("SELECT DISTINCT store_type, contract_id, map_name, key_hex FROM data_map_keys")?;
+    let mut rows = stmt.query(NO_PARAMS)?;
+    while let Some(row) = rows.next()? {
+        let store_type: u8 = row.get(0)?;
+        let contract_id: String = row.get(1)?;
+        let map_name: String = row.get(2)?;
+        let key_hex: String = row.get(3)?;
+        // see ClarityDatabase::make_key_for_quad()
+        keys.push(format!(
+            "vm::{}::{}::{}::{}",
+            contract_id, store_type, map_name, key_hex
+        ));
+        if store_type == StoreType::FungibleToken as u8 {
+            if let Ok(Value::Principal(holder)) = Value::try_deserialize_hex_untyped(&key_hex) {
+                keys.extend(account_keys(&holder));
+            }
+        }
+    }
+    Ok(keys)
+}
+
+/// Find every difference in the Clarity state between the blocks `from` and `to`, and recover
+/// the Clarity keys and values involved wherever the side-store has them.
+pub fn diff_clarity_state(
+    marf: &mut MARF<StacksBlockId>,
+    from: &StacksBlockId,
+    to: &StacksBlockId,
+) -> Result<Vec<ClarityStateDiff>, ChainstateError> {
+    let mut diffs = marf.diff(from, to)?;
+    let conn = marf.sqlite_conn();
+
+    resolve_keys(&mut diffs, contract_keys(conn)?);
+    if diffs.iter().any(|diff| diff.key.is_none()) {
+        resolve_keys(&mut diffs, indexed_keys(conn)?);
+    }
+
+    let mut state_diffs = Vec::with_capacity(diffs.len());
+    for diff in diffs.into_iter() {
+        let from_value = match diff.from.as_ref() {
+            Some(value) => SqliteConnection::get(conn, &value.to_hex())?,
+            None => None,
+        };
+        let to_value = match diff.to.as_ref() {
+            Some(value) => SqliteConnection::get(conn, &value.to_hex())?,
+            None => None,
+        };
+        state_diffs.push(ClarityStateDiff {
+            key: diff.key.as_deref().map(ClarityKey::parse),
+            diff,
+            from_value,
+            to_value,
+        });
+    }
+    Ok(state_diffs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_clarity_keys() {
+        let contract =
+            QualifiedContractIdentifier::parse("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R.foo")
+                .unwrap();
+        let principal = PrincipalData::Contract(contract.clone());
+        let key_hex = Value::UInt(1).serialize_to_hex().unwrap();
+
+        let key = ClarityKey::parse(&make_contract_hash_key(&contract));
+        assert_eq!(key, ClarityKey::Contract(contract.clone()));
+        assert_eq!(
+            key.to_string(),
+            "ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R.foo contract"
+        );
+
+        let key = ClarityKey::parse(&ClarityDatabase::make_key_for_trip(
+            &contract,
+            StoreType::Variable,
+            "bar",
+        ));
+        assert_eq!(
+            key,
+            ClarityKey::ContractData {
+                contract: contract.clone(),
+                store_type: StoreType::Variable as u8,
+                name: "bar".to_string(),
+                key: None,
+            }
+        );
+        assert_eq!(
+            key.to_string(),
+            "ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R.foo data-var bar"
+        );
+
+        let key = ClarityKey::parse(&ClarityDatabase::make_key_for_quad(
+            &contract,
+            StoreType::DataMap,
+            "baz",
+            &key_hex,
+        ));
+        assert_eq!(
+            key,
+            ClarityKey::ContractData {
+                contract: contract.clone(),
+                store_type: StoreType::DataMap as u8,
+                name: "baz".to_string(),
+                key: Some(key_hex.clone()),
+            }
+        );
+        assert_eq!(
+            key.to_string(),
+            "ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R.foo data-map baz u1"
+        );
+
+        let key = ClarityKey::parse(&ClarityDatabase::make_key_for_account_balance(&principal));
+        assert_eq!(
+            key,
+            ClarityKey::Account {
+                principal: principal.to_string(),
+                store_type: StoreType::STXBalance as u8,
+            }
+        );
+        assert_eq!(
+            key.to_string(),
+            "ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R.foo stx-balance"
+        );
+
+        assert_eq!(
+            ClarityKey::parse("__MARF_BLOCK_HEIGHT_SELF"),
+            ClarityKey::Other("__MARF_BLOCK_HEIGHT_SELF".to_string())
+        );
+        assert_eq!(
+            ClarityKey::parse("vm::not-a-contract::1::bar"),
+            ClarityKey::Other("vm::not-a-contract::1::bar".to_string())
+        );
+    }
+}
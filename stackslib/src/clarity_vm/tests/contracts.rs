@@ -141,7 +141,7 @@ fn test_get_burn_block_info_eval() {
         assert_eq!(
             Value::Optional(OptionalData {
                 data: Some(Box::new(Sequence(Buffer(BuffData {
-                    data: test_sim_height_to_hash(0, 0).to_vec()
+                    data: test_sim_height_to_hash(0, 0).to_vec().into()
                 }))))
             }),
             tx.eval_read_only(&contract_identifier, "(test-func u0)")
@@ -150,7 +150,7 @@ fn test_get_burn_block_info_eval() {
         assert_eq!(
             Value::Optional(OptionalData {
                 data: Some(Box::new(Sequence(Buffer(BuffData {
-                    data: test_sim_height_to_hash(1, 0).to_vec()
+                    data: test_sim_height_to_hash(1, 0).to_vec().into()
                 }))))
             }),
             tx.eval_read_only(&contract_identifier, "(test-func u1)")
@@ -159,7 +159,7 @@ fn test_get_burn_block_info_eval() {
         assert_eq!(
             Value::Optional(OptionalData {
                 data: Some(Box::new(Sequence(Buffer(BuffData {
-                    data: test_sim_height_to_hash(2, 0).to_vec()
+                    data: test_sim_height_to_hash(2, 0).to_vec().into()
                 }))))
             }),
             tx.eval_read_only(&contract_identifier, "(test-func u2)")
@@ -0,0 +1,322 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+extern crate blockstack_lib;
+#[macro_use]
+extern crate stacks_common;
+
+#[macro_use(slog_info, slog_warn)]
+extern crate slog;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::{env, fs, process};
+
+use blockstack_lib::burnchains::bitcoin::indexer::{
+    BitcoinIndexer, BitcoinIndexerConfig, BitcoinIndexerRuntime,
+};
+use blockstack_lib::burnchains::bitcoin::BitcoinNetworkType;
+use blockstack_lib::burnchains::Burnchain;
+use blockstack_lib::chainstate::burn::db::sortdb::SortitionDB;
+use blockstack_lib::chainstate::stacks::db::StacksChainState;
+use blockstack_lib::core::{MemPoolDB, CHAIN_ID_MAINNET, CHAIN_ID_TESTNET};
+use blockstack_lib::cost_estimates::metrics::UnitMetric;
+use blockstack_lib::cost_estimates::UnitEstimator;
+use blockstack_lib::net::atlas::{AtlasConfig, AtlasDB};
+use blockstack_lib::net::connection::ConnectionOptions;
+use blockstack_lib::net::db::PeerDB;
+use blockstack_lib::net::p2p::PeerNetwork;
+use blockstack_lib::net::seeder::{seeder_connection_opts, DNSSeedConfig, DNSSeeder};
+use blockstack_lib::net::stackerdb::StackerDBs;
+use blockstack_lib::net::{Neighbor, NeighborKey, RPCHandlerArgs};
+use blockstack_lib::util_lib::strings::UrlString;
+use stacks_common::types::net::PeerAddress;
+use stacks_common::util::secp256k1::Secp256k1PublicKey;
+
+const USAGE: &str = "stacks-dns-seeder [options]
+
+Crawl the Stacks peer network, and serve the addresses of the peers that were recently
+reachable as the A and AAAA records of a DNS zone.
+
+The seeder reads the chain state of a synced node, so it can handshake with peers, and keeps
+its own peer database (and the other databases a P2P network needs) in a directory of its own.
+
+Required:
+  --zone NAME              DNS zone to serve, e.g. seed.example.com.  Delegate it to this host
+                           with an NS record.
+  --chain-dir DIR          chain state directory of a synced node, e.g. /stacks/mainnet
+  --seeder-dir DIR         directory for the seeder's own databases
+  --bootstrap PUBKEY@HOST:PORT
+                           peer to start crawling from.  Give this more than once to start from
+                           several peers.  Only needed the first time the seeder runs.
+
+Options:
+  --testnet                crawl the testnet instead of mainnet
+  --dns-bind ADDR          address to serve DNS on (default: 0.0.0.0:53)
+  --p2p-bind ADDR          address to bind the crawler's P2P socket to (default: 0.0.0.0:20444)
+  --peer-port PORT         only serve peers listening on this port, or 0 for any port (default:
+                           20444)
+  --ttl SECS               TTL of the records served (default: 600)
+  --max-peer-age SECS      only serve peers contacted this recently (default: 10800)
+  --max-answers N          most addresses in a response (default: 25)
+";
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("{}\n\n{}", msg, USAGE);
+    process::exit(1);
+}
+
+fn parse_or_exit<T: std::str::FromStr>(flag: &str, value: &str) -> T {
+    value
+        .parse()
+        .unwrap_or_else(|_| usage_error(&format!("Invalid value for {}: '{}'", flag, value)))
+}
+
+/// Parse a bootstrap peer given as PUBKEY@HOST:PORT
+fn parse_bootstrap_peer(peer: &str, network_id: u32, peer_version: u32) -> Neighbor {
+    let Some((pubkey_str, hostport)) = peer.split_once('@') else {
+        usage_error(&format!(
+            "Invalid bootstrap peer '{}': expected PUBKEY@HOST:PORT",
+            peer
+        ));
+    };
+    let pubkey = Secp256k1PublicKey::from_hex(pubkey_str)
+        .unwrap_or_else(|_| usage_error(&format!("Invalid public key '{}'", pubkey_str)));
+    let addr = hostport
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .unwrap_or_else(|| usage_error(&format!("Failed to resolve '{}'", hostport)));
+    let key = NeighborKey {
+        peer_version,
+        network_id,
+        addrbytes: PeerAddress::from_socketaddr(&addr),
+        port: addr.port(),
+    };
+    Neighbor::empty(&key, &pubkey, 9999999)
+}
+
+fn main() {
+    let argv: Vec<String> = env::args().collect();
+
+    let mut zone = None;
+    let mut chain_dir = None;
+    let mut seeder_dir = None;
+    let mut bootstrap_peers = vec![];
+    let mut mainnet = true;
+    let mut dns_bind: SocketAddr = "0.0.0.0:53".parse().unwrap();
+    let mut p2p_bind: SocketAddr = "0.0.0.0:20444".parse().unwrap();
+    let mut peer_port = 20444u16;
+    let mut ttl = None;
+    let mut max_peer_age = None;
+    let mut max_answers = None;
+
+    let mut i = 1;
+    while i < argv.len() {
+        let flag = argv[i].as_str();
+        if flag == "--testnet" {
+            mainnet = false;
+            i += 1;
+            continue;
+        }
+        if flag == "--help" || flag == "-h" {
+            println!("{}", USAGE);
+            process::exit(0);
+        }
+        let Some(value) = argv.get(i + 1) else {
+            usage_error(&format!("Missing value for {}", flag));
+        };
+        match flag {
+            "--zone" => zone = Some(value.clone()),
+            "--chain-dir" => chain_dir = Some(value.clone()),
+            "--seeder-dir" => seeder_dir = Some(value.clone()),
+            "--bootstrap" => bootstrap_peers.push(value.clone()),
+            "--dns-bind" => dns_bind = parse_or_exit(flag, value),
+            "--p2p-bind" => p2p_bind = parse_or_exit(flag, value),
+            "--peer-port" => peer_port = parse_or_exit(flag, value),
+            "--ttl" => ttl = Some(parse_or_exit(flag, value)),
+            "--max-peer-age" => max_peer_age = Some(parse_or_exit(flag, value)),
+            "--max-answers" => max_answers = Some(parse_or_exit(flag, value)),
+            _ => usage_error(&format!("Unrecognized option '{}'", flag)),
+        }
+        i += 2;
+    }
+
+    let zone = zone.unwrap_or_else(|| usage_error("Missing --zone"));
+    let chain_dir = chain_dir.unwrap_or_else(|| usage_error("Missing --chain-dir"));
+    let seeder_dir = seeder_dir.unwrap_or_else(|| usage_error("Missing --seeder-dir"));
+
+    let mut dns_config = DNSSeedConfig::new(&zone);
+    dns_config.peer_port = if peer_port == 0 {
+        None
+    } else {
+        Some(peer_port)
+    };
+    if let Some(ttl) = ttl {
+        dns_config.ttl = ttl;
+    }
+    if let Some(max_peer_age) = max_peer_age {
+        dns_config.max_peer_age = max_peer_age;
+    }
+    if let Some(max_answers) = max_answers {
+        dns_config.max_answers = max_answers;
+    }
+
+    let (network_name, network_type, chain_id) = if mainnet {
+        ("mainnet", BitcoinNetworkType::Mainnet, CHAIN_ID_MAINNET)
+    } else {
+        ("testnet", BitcoinNetworkType::Testnet, CHAIN_ID_TESTNET)
+    };
+
+    // the node's chain state, which gives us the chain view to handshake with
+    let chain_path = PathBuf::from(&chain_dir);
+    let burnchain_path = chain_path.join("burnchain");
+    let burnchain = Burnchain::new(burnchain_path.to_str().unwrap(), "bitcoin", network_name)
+        .unwrap_or_else(|e| panic!("Failed to instantiate burnchain: {:?}", &e));
+    let sortdb = SortitionDB::open(
+        &burnchain.get_db_path(),
+        false,
+        burnchain.pox_constants.clone(),
+    )
+    .unwrap_or_else(|e| panic!("Failed to open sortition DB in {}: {:?}", &chain_dir, &e));
+    let (mut chainstate, _) = StacksChainState::open(
+        mainnet,
+        chain_id,
+        chain_path.join("chainstate").to_str().unwrap(),
+        None,
+    )
+    .unwrap_or_else(|e| panic!("Failed to open chainstate in {}: {:?}", &chain_dir, &e));
+    let epochs =
+        SortitionDB::get_stacks_epochs(sortdb.conn()).expect("Failed to load Stacks epochs");
+    let chain_view = {
+        let tip = SortitionDB::get_canonical_burn_chain_tip(sortdb.conn())
+            .expect("Failed to get sortition tip");
+        SortitionDB::get_burnchain_view(&sortdb.index_conn(), &burnchain, &tip)
+            .expect("Failed to load burnchain view")
+    };
+    let indexer = BitcoinIndexer {
+        config: BitcoinIndexerConfig {
+            spv_headers_path: chain_path
+                .join("headers.sqlite")
+                .to_str()
+                .unwrap()
+                .to_string(),
+            ..BitcoinIndexerConfig::default(burnchain.first_block_height)
+        },
+        runtime: BitcoinIndexerRuntime::new(network_type),
+        should_keep_running: None,
+    };
+
+    // the seeder's own state
+    fs::create_dir_all(&seeder_dir)
+        .unwrap_or_else(|e| panic!("Failed to create {}: {:?}", &seeder_dir, &e));
+    let seeder_path = PathBuf::from(&seeder_dir);
+    let db_path = |name: &str| seeder_path.join(name).to_str().unwrap().to_string();
+
+    let connection_opts = seeder_connection_opts(ConnectionOptions::default());
+    let bootstrap_neighbors: Vec<_> = bootstrap_peers
+        .iter()
+        .map(|peer| parse_bootstrap_peer(peer, chain_id, burnchain.peer_version))
+        .collect();
+    let mut peerdb = PeerDB::connect(
+        &db_path("peer.sqlite"),
+        true,
+        chain_id,
+        burnchain.network_id,
+        None,
+        connection_opts.private_key_lifetime,
+        PeerAddress::from_socketaddr(&p2p_bind),
+        p2p_bind.port(),
+        UrlString::try_from("").unwrap(),
+        &[],
+        Some(&bootstrap_neighbors),
+        &[],
+    )
+    .unwrap_or_else(|e| panic!("Failed to open peer DB in {}: {:?}", &seeder_dir, &e));
+    {
+        // always allow bootstrap peers, and pick up any changed keys
+        let tx = peerdb.tx_begin().unwrap();
+        for neighbor in bootstrap_neighbors.iter() {
+            PeerDB::update_peer(&tx, neighbor).unwrap();
+            PeerDB::set_allow_peer(
+                &tx,
+                neighbor.addr.network_id,
+                &neighbor.addr.addrbytes,
+                neighbor.addr.port,
+                -1,
+            )
+            .unwrap();
+        }
+        tx.commit().unwrap();
+    }
+    let local_peer = PeerDB::get_local_peer(peerdb.conn()).unwrap();
+    let atlasdb = AtlasDB::connect(AtlasConfig::new(mainnet), &db_path("atlas.sqlite"), true)
+        .unwrap_or_else(|e| panic!("Failed to open Atlas DB in {}: {:?}", &seeder_dir, &e));
+    let stackerdbs = StackerDBs::connect(&db_path("stacker_db.sqlite"), true)
+        .unwrap_or_else(|e| panic!("Failed to open StackerDBs in {}: {:?}", &seeder_dir, &e));
+    let mut mempool = MemPoolDB::open_db(
+        &db_path("mempool.sqlite"),
+        Box::new(UnitEstimator),
+        Box::new(UnitMetric),
+    )
+    .unwrap_or_else(|e| panic!("Failed to open mempool in {}: {:?}", &seeder_dir, &e));
+
+    let mut p2p = PeerNetwork::new(
+        peerdb,
+        atlasdb,
+        stackerdbs,
+        local_peer,
+        burnchain.peer_version,
+        burnchain.clone(),
+        chain_view,
+        connection_opts,
+        HashMap::new(),
+        epochs,
+    );
+    p2p.bind(&p2p_bind, &"127.0.0.1:0".parse().unwrap())
+        .unwrap_or_else(|e| panic!("Failed to bind P2P socket to {}: {:?}", &p2p_bind, &e));
+
+    let mut seeder = DNSSeeder::bind(dns_config, &dns_bind)
+        .unwrap_or_else(|e| panic!("Failed to bind DNS socket to {}: {:?}", &dns_bind, &e));
+
+    info!(
+        "Serving {} on {}, crawling from {}",
+        &seeder.config.zone, &dns_bind, &p2p_bind
+    );
+
+    let handler_args = RPCHandlerArgs::default();
+    loop {
+        // no block downloads (download_backpressure), and keep walking the whole graph (not ibd)
+        if let Err(e) = p2p.run(
+            &indexer,
+            &sortdb,
+            &mut chainstate,
+            &mut mempool,
+            None,
+            true,
+            false,
+            100,
+            &handler_args,
+        ) {
+            warn!("P2P network pass failed: {:?}", &e);
+        }
+        if let Err(e) = seeder.serve(&p2p) {
+            warn!("Failed to answer DNS queries: {:?}", &e);
+        }
+    }
+}
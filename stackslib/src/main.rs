@@ -37,6 +37,8 @@ use std::convert::TryFrom;
 use std::fs::{File, OpenOptions};
 use std::io::prelude::*;
 use std::io::BufReader;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
 use std::{env, fs, io, process, thread};
 
 use blockstack_lib::burnchains::bitcoin::indexer::{
@@ -49,6 +51,7 @@ use blockstack_lib::burnchains::{
 };
 use blockstack_lib::chainstate::burn::db::sortdb::SortitionDB;
 use blockstack_lib::chainstate::burn::ConsensusHash;
+use blockstack_lib::chainstate::snapshot::{export_snapshot, SnapshotPaths};
 use blockstack_lib::chainstate::stacks::db::blocks::{DummyEventDispatcher, StagingBlock};
 use blockstack_lib::chainstate::stacks::db::{
     ChainStateBootData, StacksChainState, StacksHeaderInfo,
@@ -57,19 +60,24 @@ use blockstack_lib::chainstate::stacks::index::marf::{MARFOpenOpts, MarfConnecti
 use blockstack_lib::chainstate::stacks::index::ClarityMarfTrieId;
 use blockstack_lib::chainstate::stacks::miner::*;
 use blockstack_lib::chainstate::stacks::{StacksBlockHeader, *};
+use blockstack_lib::chainstate::verify::verify_chainstate;
 use blockstack_lib::clarity::vm::costs::ExecutionCost;
 use blockstack_lib::clarity::vm::types::StacksAddressExtensions;
 use blockstack_lib::clarity::vm::ClarityVersion;
 use blockstack_lib::clarity_cli;
 use blockstack_lib::clarity_cli::vm_execute;
+use blockstack_lib::clarity_vm::database::state_diff::diff_clarity_state;
 use blockstack_lib::core::{MemPoolDB, *};
 use blockstack_lib::cost_estimates::metrics::UnitMetric;
 use blockstack_lib::cost_estimates::UnitEstimator;
+use blockstack_lib::net::capture::{
+    read_capture_file, replay_capture, CaptureDirection, CaptureFilter,
+};
 use blockstack_lib::net::db::LocalPeer;
 use blockstack_lib::net::p2p::PeerNetwork;
 use blockstack_lib::net::relay::Relayer;
 use blockstack_lib::net::StacksMessage;
-use blockstack_lib::util_lib::db::sqlite_open;
+use blockstack_lib::util_lib::db::{sqlite_open, table_exists};
 use blockstack_lib::util_lib::strings::UrlString;
 use libstackerdb::StackerDBChunkData;
 use rusqlite::types::ToSql;
@@ -984,6 +992,56 @@ simulating a miner.
         return;
     }
 
+    if argv[1] == "marf-diff" {
+        if argv.len() < 5 {
+            eprintln!(
+                "Usage: {} marf-diff MARF_PATH FROM_BLOCK_ID TO_BLOCK_ID",
+                argv[0]
+            );
+            eprintln!(
+                "       MARF_PATH is a MARF database, such as chainstate/vm/clarity/marf.sqlite"
+            );
+            process::exit(1);
+        }
+        let marf_path = &argv[2];
+        let from = StacksBlockId::from_hex(&argv[3]).expect("Bad FROM_BLOCK_ID");
+        let to = StacksBlockId::from_hex(&argv[4]).expect("Bad TO_BLOCK_ID");
+
+        if fs::metadata(marf_path).is_err() {
+            eprintln!("No such file or directory: {}", marf_path);
+            process::exit(1);
+        }
+
+        let mut marf_opts = MARFOpenOpts::default();
+        marf_opts.external_blobs = fs::metadata(format!("{}.blobs", marf_path)).is_ok();
+        let mut marf = MARF::from_path(marf_path, marf_opts).expect("Failed to open MARF");
+
+        // the Clarity MARF's side-store lets us say which Clarity state changed
+        let is_clarity_marf =
+            table_exists(marf.sqlite_conn(), "data_table").expect("Failed to query MARF DB");
+        let (num_diffs, num_unknown) = if is_clarity_marf {
+            let diffs = diff_clarity_state(&mut marf, &from, &to).expect("Failed to diff MARF");
+            for diff in diffs.iter() {
+                println!("{}", diff);
+            }
+            (
+                diffs.len(),
+                diffs.iter().filter(|diff| diff.key.is_none()).count(),
+            )
+        } else {
+            let diffs = marf.diff(&from, &to).expect("Failed to diff MARF");
+            for diff in diffs.iter() {
+                println!("{}", diff);
+            }
+            (
+                diffs.len(),
+                diffs.iter().filter(|diff| diff.key.is_none()).count(),
+            )
+        };
+        eprintln!("{} keys differ, {} of them unknown", num_diffs, num_unknown);
+        return;
+    }
+
     if argv[1] == "get-ancestors" {
         let path = &argv[2];
         let tip = BlockHeaderHash::from_hex(&argv[3]).unwrap();
@@ -1117,6 +1175,290 @@ simulating a miner.
         process::exit(0);
     }
 
+    if argv[1] == "export-snapshot" {
+        if argv.len() < 4 {
+            eprintln!(
+                "Usage: {} export-snapshot <network-dir> <output-dir> [reward-cycle]
+
+Export a snapshot of the node state in <network-dir> (such as /path/to/working-dir/mainnet)
+to <output-dir>, pinned to the start of [reward-cycle] (default: the latest reward cycle that
+started before the burnchain tip).  The node can keep running.  The snapshot only holds the
+state up to that point, and its manifest records each file's SHA-256 hash.
+",
+                &argv[0]
+            );
+            process::exit(1);
+        }
+        let paths = SnapshotPaths::from_network_dir(&argv[2]);
+        let reward_cycle: Option<u64> = argv
+            .get(4)
+            .map(|rc| rc.parse().expect("Failed to parse [reward-cycle] argument"));
+        let db_config = paths
+            .load_db_config()
+            .expect("Failed to load chainstate DB config");
+        let pox_constants = if db_config.mainnet {
+            PoxConstants::mainnet_default()
+        } else {
+            PoxConstants::testnet_default()
+        };
+
+        match export_snapshot(&paths, &argv[3], &pox_constants, reward_cycle) {
+            Ok(manifest) => {
+                println!("{}", serde_json::to_string_pretty(&manifest).unwrap());
+            }
+            Err(e) => {
+                eprintln!("Failed to export snapshot: {}", &e);
+                process::exit(1);
+            }
+        }
+        process::exit(0);
+    }
+
+    if argv[1] == "verify-chainstate" {
+        if argv.len() < 3 {
+            eprintln!(
+                "Usage: {} verify-chainstate <network-dir>
+
+Check the integrity of the node state in <network-dir> (such as /path/to/working-dir/mainnet):
+walk the canonical burnchain and Stacks forks, and report the lowest sortition, block, or
+microblock stream whose stored data is inconsistent.  The node must not be running.
+Exits with status 2 if an inconsistency is found.
+",
+                &argv[0]
+            );
+            process::exit(1);
+        }
+        let paths = SnapshotPaths::from_network_dir(&argv[2]);
+        let db_config = paths
+            .load_db_config()
+            .expect("Failed to load chainstate DB config");
+        let pox_constants = if db_config.mainnet {
+            PoxConstants::mainnet_default()
+        } else {
+            PoxConstants::testnet_default()
+        };
+        let mut sortdb = SortitionDB::open(&paths.sortdb_path(), false, pox_constants)
+            .expect("Failed to open sortition DB");
+        let (mut chainstate, _) = StacksChainState::open(
+            db_config.mainnet,
+            db_config.chain_id,
+            &paths.chainstate,
+            None,
+        )
+        .expect("Failed to open chainstate");
+
+        let report = match verify_chainstate(&mut sortdb, &mut chainstate) {
+            Ok(report) => report,
+            Err(e) => {
+                eprintln!("Failed to verify chainstate: {:?}", &e);
+                process::exit(1);
+            }
+        };
+        println!(
+            "Checked {} sortitions, {} Stacks blocks ({} pruned), and {} microblocks",
+            report.sortitions_checked,
+            report.blocks_checked,
+            report.blocks_pruned,
+            report.microblocks_checked
+        );
+        match report.inconsistency {
+            Some(inconsistency) => {
+                println!("First inconsistency: {}", &inconsistency);
+                process::exit(2);
+            }
+            None => {
+                println!("No inconsistencies found");
+            }
+        }
+        process::exit(0);
+    }
+
+    if argv[1] == "migrate-block-store" {
+        if argv.len() < 3 {
+            eprintln!(
+                "Usage: {} migrate-block-store <chainstate-dir> [--compress]
+
+Move the anchored blocks in <chainstate-dir> (such as /path/to/working-dir/mainnet/chainstate)
+from one file per block into a single packed, indexed block file.  With --compress, blocks
+are compressed with zstd.  The node must not be running.  If interrupted, run it again.
+",
+                &argv[0]
+            );
+            process::exit(1);
+        }
+        let compress = match argv.get(3).map(|arg| arg.as_str()) {
+            None => false,
+            Some("--compress") => true,
+            Some(arg) => {
+                eprintln!("Unrecognized argument '{}'", arg);
+                process::exit(1);
+            }
+        };
+        let blocks_path = StacksChainState::blocks_path(PathBuf::from(&argv[2]));
+        if !blocks_path.is_dir() {
+            eprintln!("No blocks directory at {}", blocks_path.display());
+            process::exit(1);
+        }
+        let blocks_dir = blocks_path.to_str().expect("Non-UTF-8 chainstate path");
+
+        match StacksChainState::migrate_block_store(blocks_dir, compress) {
+            Ok(stats) => {
+                println!(
+                    "Migrated {} blocks ({} empty): {} bytes of block files are now {} bytes",
+                    stats.blocks_migrated,
+                    stats.empty_blocks_migrated,
+                    stats.bytes_before,
+                    stats.bytes_after
+                );
+            }
+            Err(e) => {
+                eprintln!("Failed to migrate block store: {:?}", &e);
+                process::exit(1);
+            }
+        }
+        process::exit(0);
+    }
+
+    if argv[1] == "capture-dump" {
+        if argv.len() < 3 {
+            eprintln!(
+                "Usage: {} capture-dump <capture-file>... [filters] [--full]
+
+Print the P2P messages recorded in one or more capture files (see the p2p_capture_path
+connection option).  Give rotated files oldest first.  With --full, print each decoded
+message in full instead of a one-line summary.
+
+{}",
+                &argv[0], CAPTURE_FILTER_USAGE
+            );
+            process::exit(1);
+        }
+        let (files, filter, flags) = parse_capture_args(&argv[2..], &["--full"]);
+        let full = flags.contains(&"--full");
+        for file in files.iter() {
+            let records = match read_capture_file(file) {
+                Ok(records) => records,
+                Err(e) => {
+                    eprintln!("Failed to read capture file {}: {:?}", file, &e);
+                    process::exit(1);
+                }
+            };
+            for record in filter.apply(records) {
+                let header = format!(
+                    "{} convo={} {} {}",
+                    record.timestamp_ms, record.conversation_id, record.direction, &record.peer
+                );
+                match record.decode_message() {
+                    Ok(msg) if full => println!("{}: {:#?}", &header, &msg),
+                    Ok(msg) => println!(
+                        "{}: seq={} {}",
+                        &header,
+                        msg.preamble.seq,
+                        msg.payload.get_message_description()
+                    ),
+                    Err(e) => println!(
+                        "{}: undecodable ({:?}): {}",
+                        &header,
+                        &e,
+                        to_hex(&record.message_bytes)
+                    ),
+                }
+            }
+        }
+        process::exit(0);
+    }
+
+    if argv[1] == "capture-replay" {
+        if argv.len() < 4 {
+            eprintln!(
+                "Usage: {} capture-replay <capture-file> <host:port> [filters] [--pace] [--timeout MS]
+
+Replay the P2P messages of one conversation from <capture-file> to the node listening on
+<host:port>, over a new connection, and print what the node sends back.  Only inbound messages
+are replayed unless --direction is given, and the filters must select a single conversation.
+With --pace, the original gaps between messages are reproduced.  Replies are collected until
+none arrive for --timeout milliseconds (default: 5000).  Messages are sent as captured, so the
+node should be set up to match the chain view the capture was made under.
+
+{}",
+                &argv[0], CAPTURE_FILTER_USAGE
+            );
+            process::exit(1);
+        }
+        let (positional, mut filter, flags) =
+            parse_capture_args(&argv[2..], &["--pace", "--timeout"]);
+        if positional.len() != 2 {
+            eprintln!("Expected a capture file and a host:port");
+            process::exit(1);
+        }
+        let addr = match positional[1]
+            .to_socket_addrs()
+            .map(|mut addrs| addrs.next())
+        {
+            Ok(Some(addr)) => addr,
+            _ => {
+                eprintln!("Could not resolve '{}'", &positional[1]);
+                process::exit(1);
+            }
+        };
+        let paced = flags.contains(&"--pace");
+        let timeout_ms = flags
+            .iter()
+            .position(|flag| *flag == "--timeout")
+            .map(|i| {
+                flags
+                    .get(i + 1)
+                    .and_then(|ms| ms.parse::<u64>().ok())
+                    .expect("--timeout requires a number of milliseconds")
+            })
+            .unwrap_or(5000);
+        if filter.direction.is_none() {
+            filter.direction = Some(CaptureDirection::Inbound);
+        }
+
+        let records = match read_capture_file(&positional[0]) {
+            Ok(records) => filter.apply(records),
+            Err(e) => {
+                eprintln!("Failed to read capture file {}: {:?}", &positional[0], &e);
+                process::exit(1);
+            }
+        };
+        let conversation_ids: HashSet<u64> = records.iter().map(|r| r.conversation_id).collect();
+        if conversation_ids.len() != 1 {
+            eprintln!(
+                "Filters must select exactly one conversation, but selected {:?}",
+                &conversation_ids
+            );
+            process::exit(1);
+        }
+
+        match replay_capture(&records, &addr, paced, timeout_ms) {
+            Ok(report) => {
+                println!(
+                    "Sent {} messages ({} skipped); received {} replies",
+                    report.messages_sent,
+                    report.skipped,
+                    report.replies.len()
+                );
+                for reply in report.replies.iter() {
+                    println!(
+                        "reply: seq={} {}",
+                        reply.preamble.seq,
+                        reply.payload.get_message_description()
+                    );
+                }
+                if report.undecoded_bytes > 0 {
+                    println!("{} trailing bytes did not decode", report.undecoded_bytes);
+                }
+            }
+            Err(e) => {
+                eprintln!("Failed to replay capture: {:?}", &e);
+                process::exit(1);
+            }
+        }
+        process::exit(0);
+    }
+
     if argv[1] == "replay-chainstate" {
         if argv.len() < 7 {
             eprintln!("Usage: {} OLD_CHAINSTATE_PATH OLD_SORTITION_DB_PATH OLD_BURNCHAIN_DB_PATH NEW_CHAINSTATE_PATH NEW_BURNCHAIN_DB_PATH", &argv[0]);
@@ -1428,6 +1770,84 @@ simulating a miner.
     }
 }
 
+const CAPTURE_FILTER_USAGE: &str = "Filters:
+    --direction inbound|outbound    only messages received from, or sent to, the peer
+    --conversation N                only messages of conversation N
+    --peer ADDR:PORT                only messages with the peer at ADDR:PORT
+    --peer-key HASH160              only messages with the peer whose public key hash is HASH160
+    --type NAME[,NAME...]           only these message types (e.g. Handshake,GetBlocksInv)
+    --since MS, --until MS          only messages captured within this time range (ms since epoch)";
+
+/// Split the arguments of a `capture-*` command into positional arguments, a `CaptureFilter`, and
+/// the command-specific flags in `extra_flags` (with their values, if any).  Exits on bad input.
+fn parse_capture_args<'a>(
+    args: &'a [String],
+    extra_flags: &[&'static str],
+) -> (Vec<String>, CaptureFilter, Vec<&'a str>) {
+    let mut positional = vec![];
+    let mut filter = CaptureFilter::default();
+    let mut flags = vec![];
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if !arg.starts_with("--") {
+            positional.push(args[i].clone());
+            i += 1;
+            continue;
+        }
+        if extra_flags.contains(&arg) {
+            flags.push(arg);
+            if arg == "--timeout" {
+                if let Some(value) = args.get(i + 1) {
+                    flags.push(value.as_str());
+                    i += 1;
+                }
+            }
+            i += 1;
+            continue;
+        }
+        let Some(value) = args.get(i + 1) else {
+            eprintln!("Missing value for {}", arg);
+            process::exit(1);
+        };
+        match arg {
+            "--direction" => {
+                filter.direction = Some(CaptureDirection::from_name(value).unwrap_or_else(|| {
+                    eprintln!("Unrecognized direction '{}'", value);
+                    process::exit(1);
+                }));
+            }
+            "--conversation" => {
+                filter.conversation_id =
+                    Some(value.parse().expect("Failed to parse --conversation"));
+            }
+            "--peer" => {
+                let addr: SocketAddr = value.parse().expect("Failed to parse --peer as ADDR:PORT");
+                filter.peer = Some((PeerAddress::from_socketaddr(&addr), addr.port()));
+            }
+            "--peer-key" => {
+                filter.peer_key_hash =
+                    Some(Hash160::from_hex(value).expect("Failed to parse --peer-key"));
+            }
+            "--type" => {
+                filter.message_names = value.split(',').map(|name| name.to_string()).collect();
+            }
+            "--since" => {
+                filter.since_ms = Some(value.parse().expect("Failed to parse --since"));
+            }
+            "--until" => {
+                filter.until_ms = Some(value.parse().expect("Failed to parse --until"));
+            }
+            _ => {
+                eprintln!("Unrecognized argument '{}'", arg);
+                process::exit(1);
+            }
+        }
+        i += 2;
+    }
+    (positional, filter, flags)
+}
+
 fn tip_mine() {
     let argv: Vec<String> = env::args().collect();
     if argv.len() < 6 {
@@ -166,6 +166,19 @@ pub fn increment_btc_ops_sent_counter() {
     prometheus::BTC_OPS_SENT_COUNTER.inc();
 }
 
+/// Log the fee rate chosen for a block commit, along with the fee policy that chose it and why.
+#[allow(unused_variables)]
+pub fn set_btc_commit_fee_rate(policy: &str, reason: &str, fee_rate: u64) {
+    #[cfg(feature = "monitoring_prom")]
+    {
+        prometheus::BTC_COMMIT_FEE_RATE_GAUGE
+            .set(i64::try_from(fee_rate).unwrap_or_else(|_| i64::MAX));
+        prometheus::BTC_FEE_DECISIONS_COUNTER_VEC
+            .with_label_values(&[policy, reason])
+            .inc();
+    }
+}
+
 pub fn increment_stx_blocks_processed_counter() {
     #[cfg(feature = "monitoring_prom")]
     prometheus::STX_BLOCKS_PROCESSED_COUNTER.inc();
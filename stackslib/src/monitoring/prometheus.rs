@@ -71,6 +71,17 @@ lazy_static! {
         "Total number of ops (key registrations, block commits, user burn supports) submitted to the burnchain"
     )).unwrap();
 
+    pub static ref BTC_COMMIT_FEE_RATE_GAUGE: IntGauge = register_int_gauge!(opts!(
+        "stacks_node_btc_commit_fee_rate",
+        "Fee rate (satoshis per vbyte) chosen for the last block commit submitted to the burnchain"
+    )).unwrap();
+
+    pub static ref BTC_FEE_DECISIONS_COUNTER_VEC: IntCounterVec = register_int_counter_vec!(
+        "stacks_node_btc_fee_decisions_total",
+        "Block commit fee rate decisions, by fee policy and the reason for the chosen rate",
+        &["policy", "reason"]
+    ).unwrap();
+
     pub static ref STX_BLOCKS_PROCESSED_COUNTER: IntCounter = register_int_counter!(opts!(
         "stacks_node_stx_blocks_processed_total",
         "Total number of stacks blocks processed"
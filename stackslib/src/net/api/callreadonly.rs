@@ -16,7 +16,7 @@
 
 use std::io::{Read, Write};
 
-use clarity::vm::analysis::CheckErrors;
+use clarity::vm::analysis::{AnalysisDatabase, CheckErrors};
 use clarity::vm::ast::parser::v1::CLARITY_NAME_REGEX;
 use clarity::vm::clarity::ClarityConnection;
 use clarity::vm::costs::{ExecutionCost, LimitedCostTracker};
@@ -27,14 +27,14 @@ use clarity::vm::representations::{
     CONTRACT_NAME_REGEX_STRING, PRINCIPAL_DATA_REGEX_STRING, STANDARD_PRINCIPAL_REGEX_STRING,
 };
 use clarity::vm::types::{
-    PrincipalData, QualifiedContractIdentifier, StandardPrincipalData,
+    FunctionType, PrincipalData, QualifiedContractIdentifier, StandardPrincipalData,
     BOUND_VALUE_SERIALIZATION_HEX,
 };
 use clarity::vm::{ClarityName, ClarityVersion, ContractName, SymbolicExpression, Value};
 use regex::{Captures, Regex};
 use stacks_common::types::chainstate::{StacksAddress, StacksBlockId};
 use stacks_common::types::net::PeerHost;
-use stacks_common::types::Address;
+use stacks_common::types::{Address, StacksEpochId};
 use stacks_common::util::hash::{to_hex, Sha256Sum};
 
 use crate::burnchains::Burnchain;
@@ -61,7 +61,14 @@ pub struct CallReadOnlyRequestBody {
     pub sender: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sponsor: Option<String>,
+    /// Hex-encoded consensus-serialized arguments
+    #[serde(default)]
     pub arguments: Vec<String>,
+    /// Typed JSON arguments, decoded against the function's argument types.
+    /// Mutually exclusive with `arguments`.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub json_arguments: Option<Vec<serde_json::Value>>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -73,6 +80,10 @@ pub struct CallReadOnlyResponse {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cause: Option<String>,
+    /// The result as typed JSON, if requested with `encoding=json`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub json: Option<serde_json::Value>,
 }
 
 #[derive(Clone)]
@@ -86,6 +97,7 @@ pub struct RPCCallReadOnlyRequestHandler {
     pub sender: Option<PrincipalData>,
     pub sponsor: Option<PrincipalData>,
     pub arguments: Option<Vec<Value>>,
+    pub json_arguments: Option<Vec<serde_json::Value>>,
 }
 
 impl RPCCallReadOnlyRequestHandler {
@@ -98,10 +110,55 @@ impl RPCCallReadOnlyRequestHandler {
             sender: None,
             sponsor: None,
             arguments: None,
+            json_arguments: None,
         }
     }
 }
 
+/// Decode typed JSON arguments to a public or read-only function, using the function's
+/// argument types.
+fn decode_json_arguments(
+    analysis_db: &mut AnalysisDatabase,
+    contract_identifier: &QualifiedContractIdentifier,
+    function: &ClarityName,
+    epoch: &StacksEpochId,
+    json_arguments: &[serde_json::Value],
+) -> Result<Vec<Value>, String> {
+    let function_type = match analysis_db.get_read_only_function_type(
+        contract_identifier,
+        function.as_str(),
+        epoch,
+    ) {
+        Ok(Some(function_type)) => function_type,
+        Ok(None) => analysis_db
+            .get_public_function_type(contract_identifier, function.as_str(), epoch)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("No such public or read-only function: {}", function))?,
+        Err(e) => {
+            return Err(e.to_string());
+        }
+    };
+    let FunctionType::Fixed(fixed_function) = function_type else {
+        return Err(format!("Unsupported function type for {}", function));
+    };
+    if fixed_function.args.len() != json_arguments.len() {
+        return Err(format!(
+            "Expected {} arguments, got {}",
+            fixed_function.args.len(),
+            json_arguments.len()
+        ));
+    }
+    fixed_function
+        .args
+        .iter()
+        .zip(json_arguments.iter())
+        .map(|(arg, json)| {
+            Value::from_typed_json(json, &arg.signature)
+                .map_err(|e| format!("Failed to decode argument `{}`: {:?}", &arg.name, &e))
+        })
+        .collect()
+}
+
 /// Decode the HTTP request
 impl HttpRequest for RPCCallReadOnlyRequestHandler {
     fn verb(&self) -> &'static str {
@@ -155,7 +212,14 @@ impl HttpRequest for RPCCallReadOnlyRequestHandler {
             None
         };
 
-        // arguments must be valid Clarity values
+        if body.json_arguments.is_some() && !body.arguments.is_empty() {
+            return Err(Error::DecodeError(
+                "Only one of `arguments` and `json_arguments` may be given".into(),
+            ));
+        }
+
+        // arguments must be valid Clarity values.
+        // JSON arguments can only be decoded once the function signature is known.
         let arguments = body
             .arguments
             .into_iter()
@@ -168,6 +232,7 @@ impl HttpRequest for RPCCallReadOnlyRequestHandler {
         self.sender = Some(sender);
         self.sponsor = sponsor;
         self.arguments = Some(arguments);
+        self.json_arguments = body.json_arguments;
 
         Ok(HttpRequestContents::new().query_string(query))
     }
@@ -182,6 +247,7 @@ impl RPCRequestHandler for RPCCallReadOnlyRequestHandler {
         self.sender = None;
         self.sponsor = None;
         self.arguments = None;
+        self.json_arguments = None;
     }
 
     /// Make the response
@@ -215,6 +281,47 @@ impl RPCRequestHandler for RPCCallReadOnlyRequestHandler {
             .arguments
             .take()
             .ok_or(NetError::SendError("Missing `arguments`".into()))?;
+        let json_encoding = contents.get_json_encoding();
+
+        let arguments = if let Some(json_arguments) = self.json_arguments.take() {
+            let decoded =
+                node.with_node_state(|_network, sortdb, chainstate, _mempool, _rpc_args| {
+                    chainstate.maybe_read_only_clarity_tx(
+                        &sortdb.index_conn(),
+                        &tip,
+                        |clarity_tx| {
+                            let epoch = clarity_tx.get_epoch();
+                            clarity_tx.with_analysis_db_readonly(|analysis_db| {
+                                decode_json_arguments(
+                                    analysis_db,
+                                    &contract_identifier,
+                                    &function,
+                                    &epoch,
+                                    &json_arguments,
+                                )
+                            })
+                        },
+                    )
+                });
+            match decoded {
+                Ok(Some(Ok(arguments))) => arguments,
+                Ok(Some(Err(msg))) => {
+                    return StacksHttpResponse::new_error(&preamble, &HttpBadRequest::new(msg))
+                        .try_into_contents()
+                        .map_err(NetError::from);
+                }
+                Ok(None) | Err(_) => {
+                    return StacksHttpResponse::new_error(
+                        &preamble,
+                        &HttpNotFound::new("Chain tip not found".to_string()),
+                    )
+                    .try_into_contents()
+                    .map_err(NetError::from);
+                }
+            }
+        } else {
+            arguments
+        };
 
         // run the read-only call
         let data_resp =
@@ -284,11 +391,20 @@ impl RPCRequestHandler for RPCCallReadOnlyRequestHandler {
                 let hex_result = data
                     .serialize_to_hex()
                     .map_err(|e| NetError::SerializeError(format!("{:?}", &e)))?;
+                let json_result = if json_encoding {
+                    Some(
+                        data.to_typed_json()
+                            .map_err(|e| NetError::SerializeError(format!("{:?}", &e)))?,
+                    )
+                } else {
+                    None
+                };
 
                 CallReadOnlyResponse {
                     okay: true,
                     result: Some(format!("0x{}", hex_result)),
                     cause: None,
+                    json: json_result,
                 }
             }
             Ok(Some(Err(e))) => match e {
@@ -299,12 +415,14 @@ impl RPCRequestHandler for RPCCallReadOnlyRequestHandler {
                         okay: false,
                         result: None,
                         cause: Some("NotReadOnly".to_string()),
+                        json: None,
                     }
                 }
                 _ => CallReadOnlyResponse {
                     okay: false,
                     result: None,
                     cause: Some(e.to_string()),
+                    json: None,
                 },
             },
             Ok(None) | Err(_) => {
@@ -360,6 +478,7 @@ impl StacksHttpRequest {
                     sender: sender.to_string(),
                     sponsor: sponsor.map(|s| s.to_string()),
                     arguments: function_args.into_iter().map(|v| v.to_string()).collect(),
+                    json_arguments: None,
                 })
                 .expect("FATAL: failed to encode infallible data"),
             ),
@@ -368,6 +487,43 @@ impl StacksHttpRequest {
     }
 }
 
+impl StacksHttpRequest {
+    /// Make a new request to run a read-only function with typed JSON arguments.
+    /// The result will also be returned as typed JSON.
+    pub fn new_callreadonlyfunction_json(
+        host: PeerHost,
+        contract_addr: StacksAddress,
+        contract_name: ContractName,
+        sender: PrincipalData,
+        sponsor: Option<PrincipalData>,
+        function_name: ClarityName,
+        function_args: Vec<serde_json::Value>,
+        tip_req: TipRequest,
+    ) -> StacksHttpRequest {
+        StacksHttpRequest::new_for_peer(
+            host,
+            "POST".into(),
+            format!(
+                "/v2/contracts/call-read/{}/{}/{}",
+                &contract_addr, &contract_name, &function_name
+            ),
+            HttpRequestContents::new()
+                .for_tip(tip_req)
+                .query_arg("encoding".into(), "json".into())
+                .payload_json(
+                    serde_json::to_value(CallReadOnlyRequestBody {
+                        sender: sender.to_string(),
+                        sponsor: sponsor.map(|s| s.to_string()),
+                        arguments: vec![],
+                        json_arguments: Some(function_args),
+                    })
+                    .expect("FATAL: failed to encode infallible data"),
+                ),
+        )
+        .expect("FATAL: failed to construct request from infallible data")
+    }
+}
+
 impl StacksHttpResponse {
     pub fn decode_call_readonly_response(self) -> Result<CallReadOnlyResponse, NetError> {
         let contents = self.get_http_payload_ok()?;
@@ -0,0 +1,299 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use regex::{Captures, Regex};
+use stacks_common::types::net::{PeerAddress, PeerHost};
+use stacks_common::util::get_epoch_time_secs;
+use stacks_common::util::hash::Hash160;
+
+use crate::net::api::is_authorized;
+use crate::net::api::postadminpeers::cidr_to_string;
+use crate::net::chat::{ConversationP2P, NeighborStats};
+use crate::net::db::PeerDB;
+use crate::net::http::{
+    parse_json, Error, HttpRequest, HttpRequestContents, HttpRequestPreamble, HttpResponse,
+    HttpResponseContents, HttpResponsePayload, HttpResponsePreamble, HttpUnauthorized,
+};
+use crate::net::httpcore::{
+    HttpPreambleExtensions, RPCRequestHandler, StacksHttpRequest, StacksHttpResponse,
+};
+use crate::net::p2p::PeerNetwork;
+use crate::net::{Error as NetError, Neighbor, StacksNodeState};
+
+#[derive(Clone)]
+pub struct RPCGetAdminPeersRequestHandler {
+    auth_token: Option<String>,
+}
+
+impl RPCGetAdminPeersRequestHandler {
+    pub fn new(auth_token: Option<String>) -> Self {
+        Self { auth_token }
+    }
+}
+
+/// The counters kept in a connected peer's `NeighborStats`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RPCAdminPeerStats {
+    pub first_contact_time: u64,
+    pub last_contact_time: u64,
+    pub last_send_time: u64,
+    pub last_recv_time: u64,
+    pub last_handshake_time: u64,
+    pub bytes_tx: u64,
+    pub bytes_rx: u64,
+    pub msgs_tx: u64,
+    pub msgs_rx: u64,
+    pub msgs_rx_unsolicited: u64,
+    pub msgs_err: u64,
+    /// fraction of recent requests this peer answered; see `NeighborStats::get_health_score()`
+    pub health_score: f64,
+}
+
+impl From<&NeighborStats> for RPCAdminPeerStats {
+    fn from(stats: &NeighborStats) -> RPCAdminPeerStats {
+        RPCAdminPeerStats {
+            first_contact_time: stats.first_contact_time,
+            last_contact_time: stats.last_contact_time,
+            last_send_time: stats.last_send_time,
+            last_recv_time: stats.last_recv_time,
+            last_handshake_time: stats.last_handshake_time,
+            bytes_tx: stats.bytes_tx,
+            bytes_rx: stats.bytes_rx,
+            msgs_tx: stats.msgs_tx,
+            msgs_rx: stats.msgs_rx,
+            msgs_rx_unsolicited: stats.msgs_rx_unsolicited,
+            msgs_err: stats.msgs_err,
+            health_score: stats.get_health_score(),
+        }
+    }
+}
+
+/// A currently-open peer connection
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RPCAdminConnectedPeer {
+    pub event_id: usize,
+    pub network_id: u32,
+    pub peer_version: u32,
+    #[serde(rename = "ip")]
+    pub addrbytes: PeerAddress,
+    pub port: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_key_hash: Option<Hash160>,
+    pub authenticated: bool,
+    pub outbound: bool,
+    pub stats: RPCAdminPeerStats,
+}
+
+impl RPCAdminConnectedPeer {
+    pub fn from_convo(event_id: usize, convo: &ConversationP2P) -> RPCAdminConnectedPeer {
+        let nk = convo.to_neighbor_key();
+        RPCAdminConnectedPeer {
+            event_id,
+            network_id: nk.network_id,
+            peer_version: nk.peer_version,
+            addrbytes: nk.addrbytes,
+            port: nk.port,
+            public_key_hash: convo.get_public_key_hash(),
+            authenticated: convo.is_authenticated(),
+            outbound: convo.is_outbound(),
+            stats: RPCAdminPeerStats::from(&convo.stats),
+        }
+    }
+}
+
+/// A peer in the PeerDB, with its allow and deny deadlines
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RPCAdminPeerDBEntry {
+    pub network_id: u32,
+    pub peer_version: u32,
+    #[serde(rename = "ip")]
+    pub addrbytes: PeerAddress,
+    pub port: u16,
+    pub public_key_hash: Hash160,
+    /// allow deadline; negative means "forever"
+    pub allowed: i64,
+    /// deny deadline; negative means "forever"
+    pub denied: i64,
+}
+
+impl From<Neighbor> for RPCAdminPeerDBEntry {
+    fn from(neighbor: Neighbor) -> RPCAdminPeerDBEntry {
+        RPCAdminPeerDBEntry {
+            network_id: neighbor.addr.network_id,
+            peer_version: neighbor.addr.peer_version,
+            addrbytes: neighbor.addr.addrbytes,
+            port: neighbor.addr.port,
+            public_key_hash: Hash160::from_node_public_key(&neighbor.public_key),
+            allowed: neighbor.allowed,
+            denied: neighbor.denied,
+        }
+    }
+}
+
+/// Struct given back from a call to `/v2/admin/peers`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RPCAdminPeersInfo {
+    pub connected: Vec<RPCAdminConnectedPeer>,
+    pub bootstrap: Vec<RPCAdminPeerDBEntry>,
+    pub always_allowed: Vec<RPCAdminPeerDBEntry>,
+    pub denied: Vec<RPCAdminPeerDBEntry>,
+    pub allowed_cidrs: Vec<String>,
+    pub denied_cidrs: Vec<String>,
+}
+
+impl RPCAdminPeersInfo {
+    /// Load connection and PeerDB state from the peer network
+    pub fn from_p2p(network: &PeerNetwork) -> Result<RPCAdminPeersInfo, NetError> {
+        let network_id = network.get_local_peer().network_id;
+        let peerdb_conn = network.peerdb_conn();
+
+        let mut connected = vec![];
+        for event_id in network.iter_peer_event_ids() {
+            let Some(convo) = network.get_p2p_convo(*event_id) else {
+                continue;
+            };
+            connected.push(RPCAdminConnectedPeer::from_convo(*event_id, convo));
+        }
+        connected.sort_by_key(|peer| peer.event_id);
+
+        let bootstrap = PeerDB::get_bootstrap_peers(peerdb_conn, network_id)?
+            .into_iter()
+            .map(RPCAdminPeerDBEntry::from)
+            .collect();
+        let always_allowed = PeerDB::get_always_allowed_peers(peerdb_conn, network_id)?
+            .into_iter()
+            .map(RPCAdminPeerDBEntry::from)
+            .collect();
+        let denied = PeerDB::get_denied_peers(peerdb_conn, network_id, get_epoch_time_secs())?
+            .into_iter()
+            .map(RPCAdminPeerDBEntry::from)
+            .collect();
+        let allowed_cidrs = PeerDB::get_allowed_cidrs(peerdb_conn)?
+            .iter()
+            .map(|(prefix, mask)| cidr_to_string(prefix, *mask))
+            .collect();
+        let denied_cidrs = PeerDB::get_denied_cidrs(peerdb_conn)?
+            .iter()
+            .map(|(prefix, mask)| cidr_to_string(prefix, *mask))
+            .collect();
+
+        Ok(RPCAdminPeersInfo {
+            connected,
+            bootstrap,
+            always_allowed,
+            denied,
+            allowed_cidrs,
+            denied_cidrs,
+        })
+    }
+}
+
+/// Decode the HTTP request
+impl HttpRequest for RPCGetAdminPeersRequestHandler {
+    fn verb(&self) -> &'static str {
+        "GET"
+    }
+
+    fn path_regex(&self) -> Regex {
+        Regex::new(r#"^/v2/admin/peers$"#).unwrap()
+    }
+
+    /// Try to decode this request.
+    /// There's nothing to load here, so just make sure the request is well-formed.
+    fn try_parse_request(
+        &mut self,
+        preamble: &HttpRequestPreamble,
+        _captures: &Captures,
+        query: Option<&str>,
+        _body: &[u8],
+    ) -> Result<HttpRequestContents, Error> {
+        if preamble.get_content_length() != 0 {
+            return Err(Error::DecodeError(
+                "Invalid Http request: expected 0-length body for GetAdminPeers".to_string(),
+            ));
+        }
+        Ok(HttpRequestContents::new().query_string(query))
+    }
+}
+
+impl RPCRequestHandler for RPCGetAdminPeersRequestHandler {
+    /// Reset internal state
+    fn restart(&mut self) {}
+
+    /// Make the response
+    fn try_handle_request(
+        &mut self,
+        preamble: HttpRequestPreamble,
+        _contents: HttpRequestContents,
+        node: &mut StacksNodeState,
+    ) -> Result<(HttpResponsePreamble, HttpResponseContents), NetError> {
+        if !is_authorized(&preamble, self.auth_token.as_deref()) {
+            return StacksHttpResponse::new_error(
+                &preamble,
+                &HttpUnauthorized::new("Missing or invalid authorization token".to_string()),
+            )
+            .try_into_contents()
+            .map_err(NetError::from);
+        }
+
+        let peers_info =
+            node.with_node_state(|network, _sortdb, _chainstate, _mempool, _rpc_args| {
+                RPCAdminPeersInfo::from_p2p(network)
+            })?;
+
+        let mut preamble = HttpResponsePreamble::ok_json(&preamble);
+        preamble.set_canonical_stacks_tip_height(Some(node.canonical_stacks_tip_height()));
+        let body = HttpResponseContents::try_from_json(&peers_info)?;
+        Ok((preamble, body))
+    }
+}
+
+/// Decode the HTTP response
+impl HttpResponse for RPCGetAdminPeersRequestHandler {
+    fn try_parse_response(
+        &self,
+        preamble: &HttpResponsePreamble,
+        body: &[u8],
+    ) -> Result<HttpResponsePayload, Error> {
+        let peers_info: RPCAdminPeersInfo = parse_json(preamble, body)?;
+        Ok(HttpResponsePayload::try_from_json(peers_info)?)
+    }
+}
+
+impl StacksHttpRequest {
+    /// Make a new request to list the node's peers, authorized with `auth_token`
+    pub fn new_get_admin_peers(host: PeerHost, auth_token: &str) -> StacksHttpRequest {
+        let mut request = StacksHttpRequest::new_for_peer(
+            host,
+            "GET".into(),
+            "/v2/admin/peers".into(),
+            HttpRequestContents::new(),
+        )
+        .expect("FATAL: failed to construct request from infallible data");
+        request.add_header("Authorization".into(), auth_token.to_string());
+        request
+    }
+}
+
+impl StacksHttpResponse {
+    pub fn decode_admin_peers(self) -> Result<RPCAdminPeersInfo, NetError> {
+        let contents = self.get_http_payload_ok()?;
+        let response_json: serde_json::Value = contents.try_into()?;
+        let peers_info = serde_json::from_value(response_json)
+            .map_err(|_e| Error::DecodeError("Failed to decode JSON".to_string()))?;
+        Ok(peers_info)
+    }
+}
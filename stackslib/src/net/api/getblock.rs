@@ -14,9 +14,8 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::fs::OpenOptions;
-use std::io::{Read, Seek, SeekFrom, Write};
-use std::{fs, io};
+use std::cmp;
+use std::io::{Read, Write};
 
 use regex::{Captures, Regex};
 use serde::de::Error as de_Error;
@@ -55,13 +54,15 @@ impl RPCBlocksRequestHandler {
 pub struct StacksBlockStream {
     /// index block hash of the block to download
     pub index_block_hash: StacksBlockId,
-    /// offset into whatever is being read (the blob, or the file in the chunk store)
+    /// offset into the block's bytes
     pub offset: u64,
     /// total number of bytes read.
     pub total_bytes: u64,
 
     /// connection to the underlying chainstate
     blocks_path: String,
+    /// the block's bytes, loaded from the block store when the first chunk is generated
+    block_bytes: Option<Vec<u8>>,
 }
 
 impl StacksBlockStream {
@@ -76,6 +77,7 @@ impl StacksBlockStream {
             offset: 0,
             total_bytes: 0,
             blocks_path,
+            block_bytes: None,
         })
     }
 }
@@ -207,52 +209,33 @@ impl HttpChunkGenerator for StacksBlockStream {
     }
 
     fn generate_next_chunk(&mut self) -> Result<Vec<u8>, String> {
-        let block_path =
-            StacksChainState::get_index_block_path(&self.blocks_path, &self.index_block_hash)
+        if self.block_bytes.is_none() {
+            // Blocks are at most MAX_MESSAGE_LEN bytes, so it's fine to hold one in RAM while it
+            // streams.  Loading it once means we don't hold onto a file descriptor or a
+            // block store connection for the lifetime of the stream.
+            let block_bytes = StacksChainState::open_block_store(&self.blocks_path)
+                .and_then(|store| store.load_block_bytes(&self.index_block_hash))
                 .map_err(|e| {
-                    let msg = format!(
-                        "Failed to load block path for {}: {:?}",
-                        &self.index_block_hash, &e
-                    );
+                    let msg = match e {
+                        ChainError::DBError(DBError::NotFoundError) => {
+                            format!("Block data not found for {}", &self.index_block_hash)
+                        }
+                        e => format!("Failed to load block {}: {:?}", &self.index_block_hash, &e),
+                    };
                     warn!("{}", &msg);
                     msg
                 })?;
-
-        // The reason we open a file on each call to stream data is because we don't want to
-        // exhaust the supply of file descriptors.  Maybe a future version of this code will do
-        // something like cache the set of open files so we don't have to keep re-opening them.
-        let mut file_fd = fs::OpenOptions::new()
-            .read(true)
-            .write(false)
-            .create(false)
-            .truncate(false)
-            .open(&block_path)
-            .map_err(|e| {
-                if e.kind() == io::ErrorKind::NotFound {
-                    let msg = format!("Blook file not found for {}", &self.index_block_hash);
-                    warn!("{}", &msg);
-                    msg
-                } else {
-                    let msg = format!("Failed to open block {}: {:?}", &self.index_block_hash, &e);
-                    warn!("{}", &msg);
-                    msg
-                }
-            })?;
-
-        file_fd.seek(SeekFrom::Start(self.offset)).map_err(|e| {
-            let msg = format!("Failed to read block {}: {:?}", &self.index_block_hash, &e);
-            warn!("{}", &msg);
-            msg
-        })?;
-
-        let mut buf = vec![0u8; self.hint_chunk_size()];
-        let num_read = file_fd.read(&mut buf).map_err(|e| {
-            let msg = format!("Failed to read block {}: {:?}", &self.index_block_hash, &e);
-            warn!("{}", &msg);
-            msg
-        })?;
-
-        buf.truncate(num_read);
+            self.block_bytes = Some(block_bytes);
+        }
+        let block_bytes = self
+            .block_bytes
+            .as_ref()
+            .expect("unreachable: block loaded");
+
+        let start = cmp::min(self.offset as usize, block_bytes.len());
+        let end = cmp::min(start + self.hint_chunk_size(), block_bytes.len());
+        let buf = block_bytes[start..end].to_vec();
+        let num_read = buf.len();
 
         self.offset += num_read as u64;
         self.total_bytes += num_read as u64;
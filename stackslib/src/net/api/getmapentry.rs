@@ -40,9 +40,9 @@ use crate::chainstate::stacks::db::StacksChainState;
 use crate::chainstate::stacks::Error as ChainError;
 use crate::core::mempool::MemPoolDB;
 use crate::net::http::{
-    parse_json, Error, HttpContentType, HttpNotFound, HttpRequest, HttpRequestContents,
-    HttpRequestPayload, HttpRequestPreamble, HttpResponse, HttpResponseContents,
-    HttpResponsePayload, HttpResponsePreamble, HttpServerError,
+    parse_json, Error, HttpBadRequest, HttpContentType, HttpNotFound, HttpRequest,
+    HttpRequestContents, HttpRequestPayload, HttpRequestPreamble, HttpResponse,
+    HttpResponseContents, HttpResponsePayload, HttpResponsePreamble, HttpServerError,
 };
 use crate::net::httpcore::{
     request, HttpPreambleExtensions, HttpRequestContentsExtensions, RPCRequestHandler, StacksHttp,
@@ -60,6 +60,10 @@ pub struct MapEntryResponse {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub marf_proof: Option<String>,
+    /// The entry as typed JSON, if requested with `encoding=json`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub json: Option<serde_json::Value>,
 }
 
 #[derive(Clone)]
@@ -67,6 +71,8 @@ pub struct RPCGetMapEntryRequestHandler {
     pub contract_identifier: Option<QualifiedContractIdentifier>,
     pub map_name: Option<ClarityName>,
     pub key: Option<Value>,
+    /// Typed JSON key, which can only be decoded once the map's key type is known
+    pub json_key: Option<serde_json::Value>,
 }
 impl RPCGetMapEntryRequestHandler {
     pub fn new() -> Self {
@@ -74,6 +80,7 @@ impl RPCGetMapEntryRequestHandler {
             contract_identifier: None,
             map_name: None,
             key: None,
+            json_key: None,
         }
     }
 }
@@ -95,6 +102,7 @@ impl HttpRequest for RPCGetMapEntryRequestHandler {
     /// Try to decode this request.
     /// The body must be a hex string, encoded as a JSON string.
     /// So, something like `"123abc"`.  It encodes the map key as a serialized Clarity value.
+    /// If `encoding=json` is given, the body is instead the map key as typed JSON.
     fn try_parse_request(
         &mut self,
         preamble: &HttpRequestPreamble,
@@ -119,18 +127,26 @@ impl HttpRequest for RPCGetMapEntryRequestHandler {
         let contract_identifier = request::get_contract_address(captures, "address", "contract")?;
         let map_name = request::get_clarity_name(captures, "map")?;
 
+        let contents = HttpRequestContents::new().query_string(query);
+
         let mut body_ptr = body;
-        let value_hex: String = serde_json::from_reader(&mut body_ptr)
-            .map_err(|_e| Error::DecodeError("Failed to parse JSON body".into()))?;
+        if contents.get_json_encoding() {
+            let json_key: serde_json::Value = serde_json::from_reader(&mut body_ptr)
+                .map_err(|_e| Error::DecodeError("Failed to parse JSON body".into()))?;
+            self.json_key = Some(json_key);
+        } else {
+            let value_hex: String = serde_json::from_reader(&mut body_ptr)
+                .map_err(|_e| Error::DecodeError("Failed to parse JSON body".into()))?;
 
-        let value = Value::try_deserialize_hex_untyped(&value_hex)
-            .map_err(|_e| Error::DecodeError("Failed to deserialize key value".into()))?;
+            let value = Value::try_deserialize_hex_untyped(&value_hex)
+                .map_err(|_e| Error::DecodeError("Failed to deserialize key value".into()))?;
+            self.key = Some(value);
+        }
 
         self.contract_identifier = Some(contract_identifier);
         self.map_name = Some(map_name);
-        self.key = Some(value);
 
-        Ok(HttpRequestContents::new().query_string(query))
+        Ok(contents)
     }
 }
 
@@ -141,6 +157,7 @@ impl RPCRequestHandler for RPCGetMapEntryRequestHandler {
         self.contract_identifier = None;
         self.map_name = None;
         self.key = None;
+        self.json_key = None;
     }
 
     /// Make the response
@@ -158,10 +175,11 @@ impl RPCRequestHandler for RPCGetMapEntryRequestHandler {
             .map_name
             .take()
             .ok_or(NetError::SendError("`map_name` not set".into()))?;
-        let key = self
-            .key
-            .take()
-            .ok_or(NetError::SendError("`key` not set".into()))?;
+        let key = self.key.take();
+        let json_key = self.json_key.take();
+        if key.is_none() && json_key.is_none() {
+            return Err(NetError::SendError("`key` not set".into()));
+        }
 
         let tip = match node.load_stacks_chain_tip(&preamble, &contents) {
             Ok(tip) => tip,
@@ -170,9 +188,7 @@ impl RPCRequestHandler for RPCGetMapEntryRequestHandler {
             }
         };
         let with_proof = contents.get_with_proof();
-        let key =
-            ClarityDatabase::make_key_for_data_map_entry(&contract_identifier, &map_name, &key)
-                .map_err(|e| NetError::SerializeError(format!("{:?}", &e)))?;
+        let json_encoding = contents.get_json_encoding();
         let none_response = Value::none()
             .serialize_to_hex()
             .map_err(|e| NetError::SerializeError(format!("{:?}", &e)))?;
@@ -181,6 +197,25 @@ impl RPCRequestHandler for RPCGetMapEntryRequestHandler {
             node.with_node_state(|_network, sortdb, chainstate, _mempool, _rpc_args| {
                 chainstate.maybe_read_only_clarity_tx(&sortdb.index_conn(), &tip, |clarity_tx| {
                     clarity_tx.with_clarity_db_readonly(|clarity_db| {
+                        let key = if let Some(key) = key {
+                            key
+                        } else if let Some(json_key) = json_key {
+                            let key_type = clarity_db
+                                .load_map(&contract_identifier, &map_name)
+                                .map_err(|e| e.to_string())?
+                                .key_type;
+                            Value::from_typed_json(&json_key, &key_type)
+                                .map_err(|e| format!("Failed to decode key: {:?}", &e))?
+                        } else {
+                            return Err("`key` not set".to_string());
+                        };
+                        let key = ClarityDatabase::make_key_for_data_map_entry(
+                            &contract_identifier,
+                            &map_name,
+                            &key,
+                        )
+                        .map_err(|e| format!("{:?}", &e))?;
+
                         let (value_hex, marf_proof): (String, _) = if with_proof {
                             clarity_db
                                 .get_with_proof(&key)
@@ -203,14 +238,32 @@ impl RPCRequestHandler for RPCGetMapEntryRequestHandler {
                                 })
                         };
 
+                        let json = if json_encoding {
+                            let value = Value::try_deserialize_hex_untyped(&value_hex)
+                                .and_then(|value| value.to_typed_json())
+                                .map_err(|e| format!("{:?}", &e))?;
+                            Some(value)
+                        } else {
+                            None
+                        };
+
                         let data = format!("0x{}", value_hex);
-                        MapEntryResponse { data, marf_proof }
+                        Ok(MapEntryResponse {
+                            data,
+                            marf_proof,
+                            json,
+                        })
                     })
                 })
             });
 
         let data_resp = match data_resp {
-            Ok(Some(data)) => data,
+            Ok(Some(Ok(data))) => data,
+            Ok(Some(Err(msg))) => {
+                return StacksHttpResponse::new_error(&preamble, &HttpBadRequest::new(msg))
+                    .try_into_contents()
+                    .map_err(NetError::from);
+            }
             Ok(None) | Err(_) => {
                 return StacksHttpResponse::new_error(
                     &preamble,
@@ -270,6 +323,35 @@ impl StacksHttpRequest {
     }
 }
 
+impl StacksHttpRequest {
+    /// Make a new request for a data map entry, with its key given as typed JSON.
+    /// The entry will also be returned as typed JSON.
+    pub fn new_getmapentry_json(
+        host: PeerHost,
+        contract_addr: StacksAddress,
+        contract_name: ContractName,
+        map_name: ClarityName,
+        key: serde_json::Value,
+        tip_req: TipRequest,
+        with_proof: bool,
+    ) -> StacksHttpRequest {
+        StacksHttpRequest::new_for_peer(
+            host,
+            "POST".into(),
+            format!(
+                "/v2/map_entry/{}/{}/{}",
+                &contract_addr, &contract_name, &map_name
+            ),
+            HttpRequestContents::new()
+                .for_tip(tip_req)
+                .query_arg("proof".into(), if with_proof { "1" } else { "0" }.into())
+                .query_arg("encoding".into(), "json".into())
+                .payload_json(key),
+        )
+        .expect("FATAL: failed to construct request from infallible data")
+    }
+}
+
 impl StacksHttpResponse {
     pub fn decode_map_entry_response(self) -> Result<MapEntryResponse, NetError> {
         let contents = self.get_http_payload_ok()?;
@@ -0,0 +1,424 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2023 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use clarity::vm::ast::parser::v1::CLARITY_NAME_REGEX;
+use clarity::vm::clarity::ClarityConnection;
+use clarity::vm::database::StoreType;
+use clarity::vm::representations::{CONTRACT_NAME_REGEX_STRING, STANDARD_PRINCIPAL_REGEX_STRING};
+use clarity::vm::types::QualifiedContractIdentifier;
+use clarity::vm::{ClarityName, ContractName, Value};
+use regex::{Captures, Regex};
+use stacks_common::types::chainstate::StacksAddress;
+use stacks_common::types::net::PeerHost;
+use stacks_common::util::hash::to_hex;
+
+use crate::chainstate::stacks::index::marf::MarfConnection;
+use crate::clarity_vm::database::map_keys::{DataMapKeyIndex, DATA_MAP_KEYS_MAX_PAGE};
+use crate::net::http::{
+    parse_json, Error, HttpBadRequest, HttpNotFound, HttpRequest, HttpRequestContents,
+    HttpRequestPreamble, HttpResponse, HttpResponseContents, HttpResponsePayload,
+    HttpResponsePreamble, HttpServerError,
+};
+use crate::net::httpcore::{
+    request, HttpPreambleExtensions, HttpRequestContentsExtensions, RPCRequestHandler,
+    StacksHttpRequest, StacksHttpResponse,
+};
+use crate::net::{Error as NetError, StacksNodeState, TipRequest};
+
+/// Default number of entries returned in one page
+pub const DEFAULT_MAP_KEYS_PAGE_SIZE: u32 = 100;
+/// Most keys read from the index to fill one page.  Keys written on other forks, or deleted
+/// since, count against this too, so a page can come back short but with a `next_cursor`.
+pub const MAX_MAP_KEYS_SCANNED: u32 = 4 * DATA_MAP_KEYS_MAX_PAGE;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MapKeyEntry {
+    /// hex-encoded serialized Clarity value of the map key
+    pub key: String,
+    /// hex-encoded serialized Clarity value of the map entry, as an optional
+    pub data: String,
+    #[serde(rename = "proof")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub marf_proof: Option<String>,
+    /// the map key as typed JSON, if requested with `encoding=json`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_json: Option<serde_json::Value>,
+    /// the map entry as typed JSON, if requested with `encoding=json`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub json: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MapKeysResponse {
+    pub entries: Vec<MapKeyEntry>,
+    /// pass this as `cursor=` to get the next page.  Absent if there are no more entries.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct RPCGetMapKeysRequestHandler {
+    pub contract_identifier: Option<QualifiedContractIdentifier>,
+    pub map_name: Option<ClarityName>,
+    /// hex-encoded key after which to start listing
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+}
+impl RPCGetMapKeysRequestHandler {
+    pub fn new() -> Self {
+        Self {
+            contract_identifier: None,
+            map_name: None,
+            cursor: None,
+            limit: None,
+        }
+    }
+}
+
+/// Re-encode a hex-encoded serialized Clarity value as typed JSON
+fn to_typed_json_opt(value_hex: &str) -> Option<serde_json::Value> {
+    Value::try_deserialize_hex_untyped(value_hex)
+        .and_then(|value| value.to_typed_json())
+        .ok()
+}
+
+/// Decode the HTTP request
+impl HttpRequest for RPCGetMapKeysRequestHandler {
+    fn verb(&self) -> &'static str {
+        "GET"
+    }
+
+    fn path_regex(&self) -> Regex {
+        Regex::new(&format!(
+            "^/v2/map_keys/(?P<address>{})/(?P<contract>{})/(?P<map>{})$",
+            *STANDARD_PRINCIPAL_REGEX_STRING, *CONTRACT_NAME_REGEX_STRING, *CLARITY_NAME_REGEX
+        ))
+        .unwrap()
+    }
+
+    /// Try to decode this request.
+    /// The optional `cursor=` query argument is the hex-encoded key returned as `next_cursor` in
+    /// the previous page, and `limit=` bounds the number of entries returned.
+    fn try_parse_request(
+        &mut self,
+        preamble: &HttpRequestPreamble,
+        captures: &Captures,
+        query: Option<&str>,
+        _body: &[u8],
+    ) -> Result<HttpRequestContents, Error> {
+        if preamble.get_content_length() != 0 {
+            return Err(Error::DecodeError(
+                "Invalid Http request: expected 0-length body".to_string(),
+            ));
+        }
+
+        let contract_identifier = request::get_contract_address(captures, "address", "contract")?;
+        let map_name = request::get_clarity_name(captures, "map")?;
+
+        let contents = HttpRequestContents::new().query_string(query);
+
+        let cursor = match contents.get_query_arg("cursor") {
+            Some(cursor) => {
+                let cursor = cursor.strip_prefix("0x").unwrap_or(cursor).to_lowercase();
+                // must be a well-formed serialized value
+                Value::try_deserialize_hex_untyped(&cursor)
+                    .map_err(|_e| Error::DecodeError("Invalid cursor".to_string()))?;
+                Some(cursor)
+            }
+            None => None,
+        };
+
+        let limit = match contents.get_query_arg("limit") {
+            Some(limit) => {
+                let limit = limit
+                    .parse::<u32>()
+                    .map_err(|_e| Error::DecodeError("Invalid limit".to_string()))?;
+                if limit == 0 || limit > DATA_MAP_KEYS_MAX_PAGE {
+                    return Err(Error::DecodeError(format!(
+                        "Invalid limit: must be between 1 and {}",
+                        DATA_MAP_KEYS_MAX_PAGE
+                    )));
+                }
+                limit
+            }
+            None => DEFAULT_MAP_KEYS_PAGE_SIZE,
+        };
+
+        self.contract_identifier = Some(contract_identifier);
+        self.map_name = Some(map_name);
+        self.cursor = cursor;
+        self.limit = Some(limit);
+
+        Ok(contents)
+    }
+}
+
+/// Handle the HTTP request
+impl RPCRequestHandler for RPCGetMapKeysRequestHandler {
+    /// Reset internal state
+    fn restart(&mut self) {
+        self.contract_identifier = None;
+        self.map_name = None;
+        self.cursor = None;
+        self.limit = None;
+    }
+
+    /// Make the response
+    fn try_handle_request(
+        &mut self,
+        preamble: HttpRequestPreamble,
+        contents: HttpRequestContents,
+        node: &mut StacksNodeState,
+    ) -> Result<(HttpResponsePreamble, HttpResponseContents), NetError> {
+        let contract_identifier = self
+            .contract_identifier
+            .take()
+            .ok_or(NetError::SendError("`contract_identifier` not set".into()))?;
+        let map_name = self
+            .map_name
+            .take()
+            .ok_or(NetError::SendError("`map_name` not set".into()))?;
+        let limit = self
+            .limit
+            .take()
+            .ok_or(NetError::SendError("`limit` not set".into()))?;
+        let mut cursor = self.cursor.take();
+
+        let tip = match node.load_stacks_chain_tip(&preamble, &contents) {
+            Ok(tip) => tip,
+            Err(error_resp) => {
+                return error_resp.try_into_contents().map_err(NetError::from);
+            }
+        };
+        let with_proof = contents.get_with_proof();
+        let json_encoding = contents.get_json_encoding();
+        let none_hex = Value::none()
+            .serialize_to_hex()
+            .map_err(|e| NetError::SerializeError(format!("{:?}", &e)))?;
+
+        let page_resp =
+            node.with_node_state(|_network, sortdb, chainstate, _mempool, _rpc_args| {
+                if !chainstate.indexes_data_map_keys() {
+                    return Err(StacksHttpResponse::new_error(
+                        &preamble,
+                        &HttpBadRequest::new("Data map key index is not enabled".to_string()),
+                    ));
+                }
+
+                let limit = usize::try_from(limit).expect("FATAL: u32 does not fit into usize");
+                let mut entries = vec![];
+                let mut exhausted = false;
+                let mut scanned: u32 = 0;
+
+                // keys written on other forks, or deleted since, are skipped, so we may need to read
+                // several batches of candidate keys to fill a page.
+                while entries.len() < limit && scanned < MAX_MAP_KEYS_SCANNED {
+                    let batch_size = DATA_MAP_KEYS_MAX_PAGE.min(MAX_MAP_KEYS_SCANNED - scanned);
+                    let candidates = chainstate
+                        .with_clarity_marf(|marf| {
+                            DataMapKeyIndex::get_keys(
+                                marf.sqlite_conn(),
+                                StoreType::DataMap,
+                                &contract_identifier,
+                                &map_name,
+                                cursor.as_deref(),
+                                batch_size,
+                            )
+                        })
+                        .map_err(|e| {
+                            StacksHttpResponse::new_error(
+                                &preamble,
+                                &HttpServerError::new(format!(
+                                    "Failed to query data map key index: {:?}",
+                                    &e
+                                )),
+                            )
+                        })?;
+
+                    if candidates.len() < usize::try_from(batch_size).unwrap_or(usize::MAX) {
+                        exhausted = true;
+                    }
+                    if candidates.is_empty() {
+                        break;
+                    }
+
+                    let read_res = chainstate.maybe_read_only_clarity_tx(
+                        &sortdb.index_conn(),
+                        &tip,
+                        |clarity_tx| {
+                            clarity_tx.with_clarity_db_readonly(|clarity_db| {
+                                let mut batch = vec![];
+                                let mut last_key = None;
+                                for key_hex in candidates.into_iter() {
+                                    if entries.len() + batch.len() >= limit {
+                                        break;
+                                    }
+                                    let marf_key = DataMapKeyIndex::make_marf_key(
+                                        StoreType::DataMap,
+                                        &contract_identifier,
+                                        &map_name,
+                                        &key_hex,
+                                    );
+                                    let value_opt: Option<(String, _)> = if with_proof {
+                                        clarity_db
+                                            .get_with_proof(&marf_key)
+                                            .map_err(|e| format!("{:?}", &e))?
+                                            .map(|(a, b)| (a, Some(format!("0x{}", to_hex(&b)))))
+                                    } else {
+                                        clarity_db
+                                            .get(&marf_key)
+                                            .map_err(|e| format!("{:?}", &e))?
+                                            .map(|a| (a, None))
+                                    };
+                                    if let Some((value_hex, marf_proof)) = value_opt {
+                                        // deleted entries are stored as `none`
+                                        if value_hex != none_hex {
+                                            let (key_json, json) = if json_encoding {
+                                                (
+                                                    to_typed_json_opt(&key_hex),
+                                                    to_typed_json_opt(&value_hex),
+                                                )
+                                            } else {
+                                                (None, None)
+                                            };
+                                            batch.push(MapKeyEntry {
+                                                key: format!("0x{}", &key_hex),
+                                                data: format!("0x{}", value_hex),
+                                                marf_proof,
+                                                key_json,
+                                                json,
+                                            });
+                                        }
+                                    }
+                                    last_key = Some(key_hex);
+                                    scanned += 1;
+                                }
+                                Ok::<_, String>((batch, last_key))
+                            })
+                        },
+                    );
+
+                    let (batch, last_key) = match read_res {
+                        Ok(Some(Ok(res))) => res,
+                        Ok(Some(Err(e))) => {
+                            return Err(StacksHttpResponse::new_error(
+                                &preamble,
+                                &HttpServerError::new(format!(
+                                    "Failed to read data map entry: {}",
+                                    &e
+                                )),
+                            ));
+                        }
+                        Ok(None) | Err(_) => {
+                            return Err(StacksHttpResponse::new_error(
+                                &preamble,
+                                &HttpNotFound::new("Chain tip not found".to_string()),
+                            ));
+                        }
+                    };
+                    entries.extend(batch);
+                    if last_key.is_some() {
+                        cursor = last_key;
+                    }
+                    if exhausted {
+                        break;
+                    }
+                }
+
+                let next_cursor = if exhausted && entries.len() < limit {
+                    None
+                } else {
+                    cursor.map(|key_hex| format!("0x{}", &key_hex))
+                };
+                Ok(MapKeysResponse {
+                    entries,
+                    next_cursor,
+                })
+            });
+
+        let page_resp = match page_resp {
+            Ok(page) => page,
+            Err(error_resp) => {
+                return error_resp.try_into_contents().map_err(NetError::from);
+            }
+        };
+
+        let mut preamble = HttpResponsePreamble::ok_json(&preamble);
+        preamble.set_canonical_stacks_tip_height(Some(node.canonical_stacks_tip_height()));
+        let body = HttpResponseContents::try_from_json(&page_resp)?;
+        Ok((preamble, body))
+    }
+}
+
+/// Decode the HTTP response
+impl HttpResponse for RPCGetMapKeysRequestHandler {
+    fn try_parse_response(
+        &self,
+        preamble: &HttpResponsePreamble,
+        body: &[u8],
+    ) -> Result<HttpResponsePayload, Error> {
+        let map_keys: MapKeysResponse = parse_json(preamble, body)?;
+        Ok(HttpResponsePayload::try_from_json(map_keys)?)
+    }
+}
+
+impl StacksHttpRequest {
+    /// Make a new request for a page of data map keys
+    pub fn new_getmapkeys(
+        host: PeerHost,
+        contract_addr: StacksAddress,
+        contract_name: ContractName,
+        map_name: ClarityName,
+        cursor: Option<String>,
+        limit: Option<u32>,
+        tip_req: TipRequest,
+        with_proof: bool,
+    ) -> StacksHttpRequest {
+        let mut contents = HttpRequestContents::new()
+            .for_tip(tip_req)
+            .query_arg("proof".into(), if with_proof { "1" } else { "0" }.into());
+        if let Some(cursor) = cursor {
+            contents = contents.query_arg("cursor".into(), cursor);
+        }
+        if let Some(limit) = limit {
+            contents = contents.query_arg("limit".into(), format!("{}", limit));
+        }
+        StacksHttpRequest::new_for_peer(
+            host,
+            "GET".into(),
+            format!(
+                "/v2/map_keys/{}/{}/{}",
+                &contract_addr, &contract_name, &map_name
+            ),
+            contents,
+        )
+        .expect("FATAL: failed to construct request from infallible data")
+    }
+}
+
+impl StacksHttpResponse {
+    pub fn decode_map_keys_response(self) -> Result<MapKeysResponse, NetError> {
+        let contents = self.get_http_payload_ok()?;
+        let contents_json: serde_json::Value = contents.try_into()?;
+        let resp: MapKeysResponse = serde_json::from_value(contents_json)
+            .map_err(|_e| NetError::DeserializeError("Failed to load from JSON".to_string()))?;
+        Ok(resp)
+    }
+}
@@ -0,0 +1,219 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use regex::{Captures, Regex};
+use stacks_common::types::net::PeerHost;
+
+use crate::burnchains::Txid;
+use crate::net::api::is_authorized;
+use crate::net::http::{
+    parse_json, Error, HttpBadRequest, HttpNotFound, HttpRequest, HttpRequestContents,
+    HttpRequestPreamble, HttpResponse, HttpResponseContents, HttpResponsePayload,
+    HttpResponsePreamble, HttpUnauthorized,
+};
+use crate::net::httpcore::{
+    HttpPreambleExtensions, RPCRequestHandler, StacksHttpRequest, StacksHttpResponse,
+};
+use crate::net::{BurnchainOpStatus, Error as NetError, StacksNodeState};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GetStackStxStatusResponse {
+    pub request_id: u64,
+    /// one of "pending", "sent", or "failed"
+    pub status: String,
+    /// burnchain txid of the pre-stx operation paid for by the node, once sent
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_stx_txid: Option<Txid>,
+    /// burnchain txid of the stack-stx operation, once sent
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stack_stx_txid: Option<Txid>,
+    /// why the operations could not be built or sent, if they failed
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl GetStackStxStatusResponse {
+    pub fn new(request_id: u64, status: BurnchainOpStatus) -> GetStackStxStatusResponse {
+        let (status, txids, error) = match status {
+            BurnchainOpStatus::Pending => ("pending", None, None),
+            BurnchainOpStatus::Sent(pre_stx_txid, stack_stx_txid) => {
+                ("sent", Some((pre_stx_txid, stack_stx_txid)), None)
+            }
+            BurnchainOpStatus::Failed(error) => ("failed", None, Some(error)),
+        };
+        GetStackStxStatusResponse {
+            request_id,
+            status: status.to_string(),
+            pre_stx_txid: txids.map(|(pre_stx_txid, _)| pre_stx_txid),
+            stack_stx_txid: txids.map(|(_, stack_stx_txid)| stack_stx_txid),
+            error,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RPCGetStackStxStatusRequestHandler {
+    auth_token: Option<String>,
+    pub request_id: Option<u64>,
+}
+
+impl RPCGetStackStxStatusRequestHandler {
+    pub fn new(auth_token: Option<String>) -> Self {
+        Self {
+            auth_token,
+            request_id: None,
+        }
+    }
+}
+
+/// Decode the HTTP request
+impl HttpRequest for RPCGetStackStxStatusRequestHandler {
+    fn verb(&self) -> &'static str {
+        "GET"
+    }
+
+    fn path_regex(&self) -> Regex {
+        Regex::new(r#"^/v2/burn_ops/stack_stx/(?P<request_id>[0-9]{1,20})$"#).unwrap()
+    }
+
+    /// Try to decode this request.
+    fn try_parse_request(
+        &mut self,
+        preamble: &HttpRequestPreamble,
+        captures: &Captures,
+        query: Option<&str>,
+        _body: &[u8],
+    ) -> Result<HttpRequestContents, Error> {
+        if preamble.get_content_length() != 0 {
+            return Err(Error::DecodeError(
+                "Invalid Http request: expected 0-length body for GetStackStxStatus".to_string(),
+            ));
+        }
+
+        let request_id = captures
+            .name("request_id")
+            .and_then(|request_id| request_id.as_str().parse::<u64>().ok())
+            .ok_or_else(|| Error::DecodeError("Failed to parse request ID".to_string()))?;
+
+        self.request_id = Some(request_id);
+        Ok(HttpRequestContents::new().query_string(query))
+    }
+}
+
+impl RPCRequestHandler for RPCGetStackStxStatusRequestHandler {
+    /// Reset internal state
+    fn restart(&mut self) {
+        self.request_id = None;
+    }
+
+    /// Make the response
+    fn try_handle_request(
+        &mut self,
+        preamble: HttpRequestPreamble,
+        _contents: HttpRequestContents,
+        node: &mut StacksNodeState,
+    ) -> Result<(HttpResponsePreamble, HttpResponseContents), NetError> {
+        if !is_authorized(&preamble, self.auth_token.as_deref()) {
+            return StacksHttpResponse::new_error(
+                &preamble,
+                &HttpUnauthorized::new("Missing or invalid authorization token".to_string()),
+            )
+            .try_into_contents()
+            .map_err(NetError::from);
+        }
+
+        let request_id = self
+            .request_id
+            .take()
+            .ok_or(NetError::SendError("`request_id` not set".into()))?;
+
+        let data_resp =
+            node.with_node_state(|_network, _sortdb, _chainstate, _mempool, rpc_args| {
+                let Some(submitter) = rpc_args.burnchain_op_submitter else {
+                    return Err(StacksHttpResponse::new_error(
+                        &preamble,
+                        &HttpBadRequest::new(
+                            "Burnchain operation submission not supported on this node".to_string(),
+                        ),
+                    ));
+                };
+                let Some(status) = submitter.get_stack_stx_status(request_id) else {
+                    return Err(StacksHttpResponse::new_error(
+                        &preamble,
+                        &HttpNotFound::new(format!("No stack-stx request {}", request_id)),
+                    ));
+                };
+                Ok(GetStackStxStatusResponse::new(request_id, status))
+            });
+
+        let data_resp = match data_resp {
+            Ok(data) => data,
+            Err(response) => {
+                return response.try_into_contents().map_err(NetError::from);
+            }
+        };
+
+        let mut preamble = HttpResponsePreamble::ok_json(&preamble);
+        preamble.set_canonical_stacks_tip_height(Some(node.canonical_stacks_tip_height()));
+        let body = HttpResponseContents::try_from_json(&data_resp)?;
+        Ok((preamble, body))
+    }
+}
+
+/// Decode the HTTP response
+impl HttpResponse for RPCGetStackStxStatusRequestHandler {
+    fn try_parse_response(
+        &self,
+        preamble: &HttpResponsePreamble,
+        body: &[u8],
+    ) -> Result<HttpResponsePayload, Error> {
+        let response: GetStackStxStatusResponse = parse_json(preamble, body)?;
+        Ok(HttpResponsePayload::try_from_json(response)?)
+    }
+}
+
+impl StacksHttpRequest {
+    /// Make a new request for the status of a queued stack-stx operation, authorized with
+    /// `auth_token`
+    pub fn new_get_stack_stx_status(
+        host: PeerHost,
+        auth_token: &str,
+        request_id: u64,
+    ) -> StacksHttpRequest {
+        let mut request = StacksHttpRequest::new_for_peer(
+            host,
+            "GET".into(),
+            format!("/v2/burn_ops/stack_stx/{}", request_id),
+            HttpRequestContents::new(),
+        )
+        .expect("FATAL: failed to construct request from infallible data");
+        request.add_header("Authorization".into(), auth_token.to_string());
+        request
+    }
+}
+
+impl StacksHttpResponse {
+    pub fn decode_stack_stx_status(self) -> Result<GetStackStxStatusResponse, NetError> {
+        let contents = self.get_http_payload_ok()?;
+        let response_json: serde_json::Value = contents.try_into()?;
+        let response: GetStackStxStatusResponse = serde_json::from_value(response_json)
+            .map_err(|_e| Error::DecodeError("Failed to decode JSON".to_string()))?;
+        Ok(response)
+    }
+}
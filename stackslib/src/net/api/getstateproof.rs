@@ -0,0 +1,355 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use clarity::vm::clarity::ClarityConnection;
+use clarity::vm::database::{ClarityDatabase, StoreType};
+use clarity::vm::representations::{CONTRACT_NAME_REGEX_STRING, STANDARD_PRINCIPAL_REGEX_STRING};
+use clarity::vm::types::{QualifiedContractIdentifier, BOUND_VALUE_SERIALIZATION_HEX};
+use clarity::vm::{ClarityName, ContractName, Value};
+use libmarfproof::ProofHeader;
+use regex::{Captures, Regex};
+use stacks_common::codec::StacksMessageCodec;
+use stacks_common::types::chainstate::{StacksAddress, StacksBlockId, TrieHash};
+use stacks_common::types::net::PeerHost;
+use stacks_common::util::hash::to_hex;
+
+use crate::chainstate::stacks::db::StacksChainState;
+use crate::net::http::{
+    parse_json, Error, HttpContentType, HttpNotFound, HttpRequest, HttpRequestContents,
+    HttpRequestPreamble, HttpResponse, HttpResponseContents, HttpResponsePayload,
+    HttpResponsePreamble, HttpServerError,
+};
+use crate::net::httpcore::{
+    request, HttpPreambleExtensions, HttpRequestContentsExtensions, RPCRequestHandler,
+    StacksHttpRequest, StacksHttpResponse,
+};
+use crate::net::{Error as NetError, StacksNodeState, TipRequest};
+
+/// Maximum number of keys that can be proven in one request
+pub const STATE_PROOF_MAX_KEYS: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateProofMapEntry {
+    pub map: String,
+    /// hex-encoded serialized Clarity value of the map key
+    pub key: String,
+}
+
+/// The keys to prove
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateProofRequestBody {
+    #[serde(default)]
+    pub data_vars: Vec<String>,
+    #[serde(default)]
+    pub map_entries: Vec<StateProofMapEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateProofEntry {
+    /// the MARF key
+    pub key: String,
+    /// hex-encoded value stored under the key, or `None` if there is no such key
+    pub data: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateProofResponse {
+    pub index_block_hash: StacksBlockId,
+    /// the `state_index_root` of the block's header, which the proof is checked against
+    pub state_root: TrieHash,
+    /// one entry per requested key: data vars first, then map entries
+    pub entries: Vec<StateProofEntry>,
+    /// hex-encoded `libmarfproof::MarfBatchProof` covering every entry
+    pub proof: String,
+}
+
+#[derive(Clone)]
+pub struct RPCGetStateProofRequestHandler {
+    pub contract_identifier: Option<QualifiedContractIdentifier>,
+    /// MARF keys of the requested data vars and map entries
+    pub keys: Option<Vec<String>>,
+}
+impl RPCGetStateProofRequestHandler {
+    pub fn new() -> Self {
+        Self {
+            contract_identifier: None,
+            keys: None,
+        }
+    }
+}
+
+/// Decode the HTTP request
+impl HttpRequest for RPCGetStateProofRequestHandler {
+    fn verb(&self) -> &'static str {
+        "POST"
+    }
+
+    fn path_regex(&self) -> Regex {
+        Regex::new(&format!(
+            "^/v2/state_proof/(?P<address>{})/(?P<contract>{})$",
+            *STANDARD_PRINCIPAL_REGEX_STRING, *CONTRACT_NAME_REGEX_STRING
+        ))
+        .unwrap()
+    }
+
+    /// Try to decode this request.
+    /// The body is a JSON `StateProofRequestBody`, naming the data vars and map entries whose
+    /// values are to be proven.
+    fn try_parse_request(
+        &mut self,
+        preamble: &HttpRequestPreamble,
+        captures: &Captures,
+        query: Option<&str>,
+        body: &[u8],
+    ) -> Result<HttpRequestContents, Error> {
+        let content_len = preamble.get_content_length();
+        let max_len = u32::try_from(STATE_PROOF_MAX_KEYS)
+            .unwrap_or(u32::MAX)
+            .saturating_mul(BOUND_VALUE_SERIALIZATION_HEX + 256);
+        if !(content_len > 0 && content_len < max_len) {
+            return Err(Error::DecodeError(format!(
+                "Invalid Http request: invalid body length for GetStateProof ({})",
+                content_len
+            )));
+        }
+
+        if preamble.content_type != Some(HttpContentType::JSON) {
+            return Err(Error::DecodeError(
+                "Invalid content-type: expected application/json".into(),
+            ));
+        }
+
+        let contract_identifier = request::get_contract_address(captures, "address", "contract")?;
+
+        let body: StateProofRequestBody = serde_json::from_slice(body)
+            .map_err(|_e| Error::DecodeError("Failed to parse JSON body".into()))?;
+        let num_keys = body.data_vars.len() + body.map_entries.len();
+        if num_keys == 0 || num_keys > STATE_PROOF_MAX_KEYS {
+            return Err(Error::DecodeError(format!(
+                "Invalid number of keys: must be between 1 and {}",
+                STATE_PROOF_MAX_KEYS
+            )));
+        }
+
+        let mut keys = Vec::with_capacity(num_keys);
+        for var_name in body.data_vars.into_iter() {
+            let var_name = ClarityName::try_from(var_name)
+                .map_err(|_e| Error::DecodeError("Invalid data var name".into()))?;
+            keys.push(ClarityDatabase::make_key_for_trip(
+                &contract_identifier,
+                StoreType::Variable,
+                &var_name,
+            ));
+        }
+        for entry in body.map_entries.into_iter() {
+            let map_name = ClarityName::try_from(entry.map)
+                .map_err(|_e| Error::DecodeError("Invalid map name".into()))?;
+            let key_hex = entry.key.strip_prefix("0x").unwrap_or(&entry.key);
+            let key = Value::try_deserialize_hex_untyped(key_hex)
+                .map_err(|_e| Error::DecodeError("Failed to deserialize key value".into()))?;
+            let key =
+                ClarityDatabase::make_key_for_data_map_entry(&contract_identifier, &map_name, &key)
+                    .map_err(|_e| Error::DecodeError("Failed to serialize key value".into()))?;
+            keys.push(key);
+        }
+
+        self.contract_identifier = Some(contract_identifier);
+        self.keys = Some(keys);
+
+        Ok(HttpRequestContents::new().query_string(query))
+    }
+}
+
+/// Handle the HTTP request
+impl RPCRequestHandler for RPCGetStateProofRequestHandler {
+    /// Reset internal state
+    fn restart(&mut self) {
+        self.contract_identifier = None;
+        self.keys = None;
+    }
+
+    /// Make the response
+    fn try_handle_request(
+        &mut self,
+        preamble: HttpRequestPreamble,
+        contents: HttpRequestContents,
+        node: &mut StacksNodeState,
+    ) -> Result<(HttpResponsePreamble, HttpResponseContents), NetError> {
+        let keys = self
+            .keys
+            .take()
+            .ok_or(NetError::SendError("`keys` not set".into()))?;
+
+        let tip = match node.load_stacks_chain_tip(&preamble, &contents) {
+            Ok(tip) => tip,
+            Err(error_resp) => {
+                return error_resp.try_into_contents().map_err(NetError::from);
+            }
+        };
+
+        let proof_resp =
+            node.with_node_state(|_network, sortdb, chainstate, _mempool, _rpc_args| {
+                let server_error = |msg: String| {
+                    StacksHttpResponse::new_error(&preamble, &HttpServerError::new(msg))
+                };
+
+                // light clients check the proof against the header, so unconfirmed state
+                // can't be proven
+                let tip_header =
+                    StacksChainState::get_stacks_block_header_info_by_index_block_hash(
+                        chainstate.db(),
+                        &tip,
+                    )
+                    .map_err(|e| server_error(format!("Failed to load tip header: {:?}", &e)))?
+                    .ok_or_else(|| {
+                        StacksHttpResponse::new_error(
+                            &preamble,
+                            &HttpNotFound::new(format!("No anchored block {}", &tip)),
+                        )
+                    })?;
+
+                let read_res = chainstate.maybe_read_only_clarity_tx(
+                    &sortdb.index_conn(),
+                    &tip,
+                    |clarity_tx| {
+                        clarity_tx.with_clarity_db_readonly(|clarity_db| {
+                            keys.iter()
+                                .map(|key| StateProofEntry {
+                                    key: key.clone(),
+                                    data: clarity_db
+                                        .get::<String>(key)
+                                        .ok()
+                                        .flatten()
+                                        .map(|value_hex| format!("0x{}", value_hex)),
+                                })
+                                .collect::<Vec<_>>()
+                        })
+                    },
+                );
+                let entries = match read_res {
+                    Ok(Some(entries)) => entries,
+                    Ok(None) | Err(_) => {
+                        return Err(StacksHttpResponse::new_error(
+                            &preamble,
+                            &HttpNotFound::new("Chain tip not found".to_string()),
+                        ));
+                    }
+                };
+
+                let mut proof = chainstate
+                    .with_clarity_marf(|marf| marf.get_batch_proof(&tip, &keys))
+                    .map_err(|e| server_error(format!("Failed to generate proof: {:?}", &e)))?;
+
+                // bind each ancestor trie to its block ID
+                for trie in proof.tries.iter_mut().skip(1) {
+                    let header_info =
+                        StacksChainState::get_stacks_block_header_info_by_index_block_hash(
+                            chainstate.db(),
+                            &trie.block_id,
+                        )
+                        .map_err(|e| server_error(format!("Failed to load header: {:?}", &e)))?
+                        .ok_or_else(|| {
+                            server_error(format!("No header for block {}", &trie.block_id))
+                        })?;
+
+                    // the boot block's hash does not commit to its header, so the client must
+                    // already know its state root
+                    if header_info.anchored_header.total_work.work == 0 {
+                        continue;
+                    }
+                    trie.header = Some(ProofHeader {
+                        header: header_info.anchored_header.serialize_to_vec(),
+                        consensus_hash: header_info.consensus_hash,
+                    });
+                }
+
+                Ok(StateProofResponse {
+                    index_block_hash: tip.clone(),
+                    state_root: tip_header.anchored_header.state_index_root,
+                    entries,
+                    proof: format!("0x{}", to_hex(&proof.serialize_to_vec())),
+                })
+            });
+
+        let proof_resp = match proof_resp {
+            Ok(proof_resp) => proof_resp,
+            Err(error_resp) => {
+                return error_resp.try_into_contents().map_err(NetError::from);
+            }
+        };
+
+        let mut preamble = HttpResponsePreamble::ok_json(&preamble);
+        preamble.set_canonical_stacks_tip_height(Some(node.canonical_stacks_tip_height()));
+        let body = HttpResponseContents::try_from_json(&proof_resp)?;
+        Ok((preamble, body))
+    }
+}
+
+/// Decode the HTTP response
+impl HttpResponse for RPCGetStateProofRequestHandler {
+    fn try_parse_response(
+        &self,
+        preamble: &HttpResponsePreamble,
+        body: &[u8],
+    ) -> Result<HttpResponsePayload, Error> {
+        let state_proof: StateProofResponse = parse_json(preamble, body)?;
+        Ok(HttpResponsePayload::try_from_json(state_proof)?)
+    }
+}
+
+impl StacksHttpRequest {
+    /// Make a new request for a proof of a contract's data vars and map entries
+    pub fn new_getstateproof(
+        host: PeerHost,
+        contract_addr: StacksAddress,
+        contract_name: ContractName,
+        data_vars: Vec<ClarityName>,
+        map_entries: Vec<(ClarityName, Value)>,
+        tip_req: TipRequest,
+    ) -> StacksHttpRequest {
+        let body = StateProofRequestBody {
+            data_vars: data_vars.into_iter().map(|name| name.to_string()).collect(),
+            map_entries: map_entries
+                .into_iter()
+                .map(|(map, key)| StateProofMapEntry {
+                    map: map.to_string(),
+                    key: key
+                        .serialize_to_hex()
+                        .expect("FATAL: invalid key could not be serialized"),
+                })
+                .collect(),
+        };
+        StacksHttpRequest::new_for_peer(
+            host,
+            "POST".into(),
+            format!("/v2/state_proof/{}/{}", &contract_addr, &contract_name),
+            HttpRequestContents::new().for_tip(tip_req).payload_json(
+                serde_json::to_value(body).expect("FATAL: failed to encode request body"),
+            ),
+        )
+        .expect("FATAL: failed to construct request from infallible data")
+    }
+}
+
+impl StacksHttpResponse {
+    pub fn decode_state_proof_response(self) -> Result<StateProofResponse, NetError> {
+        let contents = self.get_http_payload_ok()?;
+        let contents_json: serde_json::Value = contents.try_into()?;
+        let resp: StateProofResponse = serde_json::from_value(contents_json)
+            .map_err(|_e| NetError::DeserializeError("Failed to load from JSON".to_string()))?;
+        Ok(resp)
+    }
+}
@@ -19,6 +19,7 @@ use std::convert::From;
 use clarity::vm::costs::ExecutionCost;
 use stacks_common::codec::read_next;
 use stacks_common::types::chainstate::{BlockHeaderHash, StacksBlockId};
+use stacks_common::util::hash::Sha256Sum;
 
 use crate::burnchains::Txid;
 use crate::chainstate::stacks::{StacksMicroblock, StacksTransaction};
@@ -35,6 +36,7 @@ use crate::stacks_common::codec::StacksMessageCodec;
 
 pub mod callreadonly;
 pub mod getaccount;
+pub mod getadminpeers;
 pub mod getattachment;
 pub mod getattachmentsinv;
 pub mod getblock;
@@ -46,6 +48,7 @@ pub mod getheaders;
 pub mod getinfo;
 pub mod getistraitimplemented;
 pub mod getmapentry;
+pub mod getmapkeys;
 pub mod getmicroblocks_confirmed;
 pub mod getmicroblocks_indexed;
 pub mod getmicroblocks_unconfirmed;
@@ -53,14 +56,18 @@ pub mod getneighbors;
 pub mod getpoxinfo;
 pub mod getstackerdbchunk;
 pub mod getstackerdbmetadata;
+pub mod getstackstx;
+pub mod getstateproof;
 pub mod getstxtransfercost;
 pub mod gettransaction_unconfirmed;
 pub mod liststackerdbreplicas;
+pub mod postadminpeers;
 pub mod postblock;
 pub mod postfeerate;
 pub mod postmempoolquery;
 pub mod postmicroblock;
 pub mod poststackerdbchunk;
+pub mod poststackstx;
 pub mod posttransaction;
 
 #[cfg(test)]
@@ -75,6 +82,9 @@ impl StacksHttp {
             self.read_only_call_limit.clone(),
         ));
         self.register_rpc_endpoint(getaccount::RPCGetAccountRequestHandler::new());
+        self.register_rpc_endpoint(getadminpeers::RPCGetAdminPeersRequestHandler::new(
+            self.auth_token.clone(),
+        ));
         self.register_rpc_endpoint(getattachment::RPCGetAttachmentRequestHandler::new());
         self.register_rpc_endpoint(getattachmentsinv::RPCGetAttachmentsInvRequestHandler::new());
         self.register_rpc_endpoint(getblock::RPCBlocksRequestHandler::new());
@@ -88,6 +98,7 @@ impl StacksHttp {
             getistraitimplemented::RPCGetIsTraitImplementedRequestHandler::new(),
         );
         self.register_rpc_endpoint(getmapentry::RPCGetMapEntryRequestHandler::new());
+        self.register_rpc_endpoint(getmapkeys::RPCGetMapKeysRequestHandler::new());
         self.register_rpc_endpoint(
             getmicroblocks_confirmed::RPCMicroblocksConfirmedRequestHandler::new(),
         );
@@ -101,6 +112,10 @@ impl StacksHttp {
         self.register_rpc_endpoint(getstxtransfercost::RPCGetStxTransferCostRequestHandler::new());
         self.register_rpc_endpoint(getstackerdbchunk::RPCGetStackerDBChunkRequestHandler::new());
         self.register_rpc_endpoint(getpoxinfo::RPCPoxInfoRequestHandler::new());
+        self.register_rpc_endpoint(getstackstx::RPCGetStackStxStatusRequestHandler::new(
+            self.auth_token.clone(),
+        ));
+        self.register_rpc_endpoint(getstateproof::RPCGetStateProofRequestHandler::new());
         self.register_rpc_endpoint(
             getstackerdbmetadata::RPCGetStackerDBMetadataRequestHandler::new(),
         );
@@ -110,11 +125,17 @@ impl StacksHttp {
         self.register_rpc_endpoint(
             liststackerdbreplicas::RPCListStackerDBReplicasRequestHandler::new(),
         );
+        self.register_rpc_endpoint(postadminpeers::RPCPostAdminPeersRequestHandler::new(
+            self.auth_token.clone(),
+        ));
         self.register_rpc_endpoint(postblock::RPCPostBlockRequestHandler::new());
         self.register_rpc_endpoint(postfeerate::RPCPostFeeRateRequestHandler::new());
         self.register_rpc_endpoint(postmempoolquery::RPCMempoolQueryRequestHandler::new());
         self.register_rpc_endpoint(postmicroblock::RPCPostMicroblockRequestHandler::new());
         self.register_rpc_endpoint(poststackerdbchunk::RPCPostStackerDBChunkRequestHandler::new());
+        self.register_rpc_endpoint(poststackstx::RPCPostStackStxRequestHandler::new(
+            self.auth_token.clone(),
+        ));
         self.register_rpc_endpoint(posttransaction::RPCPostTransactionRequestHandler::new());
     }
 }
@@ -128,3 +149,22 @@ impl From<NetError> for Error {
         }
     }
 }
+
+/// Does the request carry `auth_token` in its `Authorization` header?  No request is authorized
+/// if the node has no auth token.
+/// The header and token are hashed, and the hashes compared in constant time, so the time taken
+/// does not reveal how much of the token a client got right.
+pub fn is_authorized(preamble: &HttpRequestPreamble, auth_token: Option<&str>) -> bool {
+    let (Some(auth_token), Some(given)) = (auth_token, preamble.get_header("authorization".into()))
+    else {
+        return false;
+    };
+    let expected = Sha256Sum::from_data(auth_token.as_bytes());
+    let given = Sha256Sum::from_data(given.as_bytes());
+    let diff = expected
+        .as_bytes()
+        .iter()
+        .zip(given.as_bytes().iter())
+        .fold(0u8, |diff, (a, b)| diff | (a ^ b));
+    diff == 0
+}
@@ -0,0 +1,449 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::net::{IpAddr, SocketAddr};
+
+use regex::{Captures, Regex};
+use stacks_common::codec::MAX_PAYLOAD_LEN;
+use stacks_common::types::net::{PeerAddress, PeerHost};
+use stacks_common::util::get_epoch_time_secs;
+use stacks_common::util::secp256k1::Secp256k1PublicKey;
+
+use crate::net::api::is_authorized;
+use crate::net::db::PeerDB;
+use crate::net::http::{
+    parse_json, Error, HttpBadRequest, HttpContentType, HttpRequest, HttpRequestContents,
+    HttpRequestPreamble, HttpResponse, HttpResponseContents, HttpResponsePayload,
+    HttpResponsePreamble, HttpServerError, HttpUnauthorized,
+};
+use crate::net::httpcore::{
+    HttpPreambleExtensions, RPCRequestHandler, StacksHttpRequest, StacksHttpResponse,
+};
+use crate::net::p2p::PeerNetwork;
+use crate::net::{Error as NetError, Neighbor, NeighborKey, StacksNodeState, DENY_BAN_DURATION};
+
+/// Parse a CIDR string like `1.2.3.0/24` or `fe80::/10` into the (prefix, mask) pair used by the
+/// PeerDB.  IPv4 prefixes are mapped into the IPv6 address space, so their mask grows by 96 bits.
+/// Bits of the prefix beyond the mask are cleared.
+pub fn parse_cidr(cidr: &str) -> Option<(PeerAddress, u32)> {
+    let (ip_str, mask_str) = cidr.split_once('/')?;
+    let ip = ip_str.parse::<IpAddr>().ok()?;
+    let mask = mask_str.parse::<u32>().ok()?;
+    let mask = match ip {
+        IpAddr::V4(..) if mask > 0 && mask <= 32 => mask + 96,
+        IpAddr::V6(..) if mask > 0 && mask <= 128 => mask,
+        _ => {
+            return None;
+        }
+    };
+    let prefix = PeerAddress::from_ip(&ip);
+    let prefix_int =
+        u128::from_be_bytes(prefix.as_bytes().to_owned()) & !((1u128 << (128 - mask)) - 1);
+    Some((PeerAddress(prefix_int.to_be_bytes()), mask))
+}
+
+/// Render a PeerDB (prefix, mask) pair as a CIDR string.  This is the inverse of `parse_cidr()`.
+pub fn cidr_to_string(prefix: &PeerAddress, mask: u32) -> String {
+    if prefix.is_ipv4() && mask > 96 {
+        format!("{}/{}", prefix.to_socketaddr(0).ip(), mask - 96)
+    } else {
+        format!("{}/{}", prefix.to_socketaddr(0).ip(), mask)
+    }
+}
+
+/// Does the given address fall within the given (prefix, mask) pair?
+fn cidr_contains(prefix: &PeerAddress, mask: u32, addr: &PeerAddress) -> bool {
+    let addr_mask = !((1u128 << (128 - mask)) - 1);
+    let prefix_int = u128::from_be_bytes(prefix.as_bytes().to_owned()) & addr_mask;
+    let addr_int = u128::from_be_bytes(addr.as_bytes().to_owned()) & addr_mask;
+    prefix_int == addr_int
+}
+
+/// JSON body for `POST /v2/admin/peers/:action`.
+/// Exactly one of `peer` and `cidr` must be given for `ban`, `unban` and `allow`.  `disconnect`
+/// and `bootstrap` only take a `peer`.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct PostAdminPeersRequestBody {
+    /// peer address, as `IP:PORT`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer: Option<String>,
+    /// address prefix, as `IP/MASK`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cidr: Option<String>,
+    /// how long to ban a peer for, in seconds.  Defaults to `DENY_BAN_DURATION`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+    /// hex-encoded public key of a bootstrap peer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PostAdminPeersResponse {
+    pub action: String,
+    /// number of peer connections that were closed as a result of this action
+    pub disconnected: u64,
+}
+
+/// Peer management actions that can be taken through `POST /v2/admin/peers/:action`
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdminPeerCommand {
+    /// Deny a peer until the given number of seconds from now, and disconnect it
+    Ban(PeerAddress, u16, u64),
+    /// Deny an address prefix, and disconnect all peers in it
+    BanCidr(PeerAddress, u32),
+    /// Clear a peer's deny deadline
+    Unban(PeerAddress, u16),
+    /// Remove a denied address prefix
+    UnbanCidr(PeerAddress, u32),
+    /// Always allow a peer
+    Allow(PeerAddress, u16),
+    /// Always allow an address prefix
+    AllowCidr(PeerAddress, u32),
+    /// Add a bootstrap peer
+    Bootstrap(PeerAddress, u16, Secp256k1PublicKey),
+    /// Close all connections to a peer without banning it
+    Disconnect(PeerAddress, u16),
+}
+
+impl AdminPeerCommand {
+    /// Build a command from the action in the request path and the request body
+    pub fn try_from_request(
+        action: &str,
+        body: &PostAdminPeersRequestBody,
+    ) -> Result<AdminPeerCommand, Error> {
+        let peer = match body.peer.as_ref() {
+            Some(peer) => {
+                let addr = peer
+                    .parse::<SocketAddr>()
+                    .map_err(|_e| Error::DecodeError(format!("Invalid peer address '{}'", peer)))?;
+                Some((PeerAddress::from_socketaddr(&addr), addr.port()))
+            }
+            None => None,
+        };
+        let cidr = match body.cidr.as_ref() {
+            Some(cidr) => Some(
+                parse_cidr(cidr)
+                    .ok_or_else(|| Error::DecodeError(format!("Invalid CIDR '{}'", cidr)))?,
+            ),
+            None => None,
+        };
+        if peer.is_some() && cidr.is_some() {
+            return Err(Error::DecodeError(
+                "Only one of `peer` and `cidr` may be given".into(),
+            ));
+        }
+        if body.duration.is_some() && (action != "ban" || peer.is_none()) {
+            return Err(Error::DecodeError(
+                "`duration` is only supported when banning a peer".into(),
+            ));
+        }
+        if body.public_key.is_some() && action != "bootstrap" {
+            return Err(Error::DecodeError(
+                "`public_key` is only supported when adding a bootstrap peer".into(),
+            ));
+        }
+
+        let cmd = match (action, peer, cidr) {
+            ("ban", Some((addr, port)), None) => {
+                AdminPeerCommand::Ban(addr, port, body.duration.unwrap_or(DENY_BAN_DURATION))
+            }
+            ("ban", None, Some((prefix, mask))) => AdminPeerCommand::BanCidr(prefix, mask),
+            ("unban", Some((addr, port)), None) => AdminPeerCommand::Unban(addr, port),
+            ("unban", None, Some((prefix, mask))) => AdminPeerCommand::UnbanCidr(prefix, mask),
+            ("allow", Some((addr, port)), None) => AdminPeerCommand::Allow(addr, port),
+            ("allow", None, Some((prefix, mask))) => AdminPeerCommand::AllowCidr(prefix, mask),
+            ("bootstrap", Some((addr, port)), None) => {
+                let public_key = body
+                    .public_key
+                    .as_ref()
+                    .and_then(|pubk| Secp256k1PublicKey::from_hex(pubk).ok())
+                    .ok_or_else(|| Error::DecodeError("Missing or invalid `public_key`".into()))?;
+                AdminPeerCommand::Bootstrap(addr, port, public_key)
+            }
+            ("disconnect", Some((addr, port)), None) => AdminPeerCommand::Disconnect(addr, port),
+            ("bootstrap", ..) | ("disconnect", ..) => {
+                return Err(Error::DecodeError(format!(
+                    "`{}` requires a `peer`",
+                    action
+                )));
+            }
+            _ => {
+                return Err(Error::DecodeError(format!(
+                    "`{}` requires either a `peer` or a `cidr`",
+                    action
+                )));
+            }
+        };
+        Ok(cmd)
+    }
+
+    /// Apply this command to the PeerDB, and close any connections it affects.
+    /// Returns the number of connections closed.
+    pub fn apply(&self, network: &mut PeerNetwork) -> Result<u64, NetError> {
+        let network_id = network.get_local_peer().network_id;
+        let peer_version = network.peer_version;
+
+        let tx = network.peerdb_tx_begin()?;
+        match self {
+            AdminPeerCommand::Ban(addr, port, duration) => {
+                debug!("Admin ban of {:?}:{} for {}s", addr, port, duration);
+                PeerDB::set_deny_peer(
+                    &tx,
+                    network_id,
+                    addr,
+                    *port,
+                    get_epoch_time_secs().saturating_add(*duration),
+                )?;
+            }
+            AdminPeerCommand::BanCidr(prefix, mask) => {
+                debug!("Admin ban of {}", cidr_to_string(prefix, *mask));
+                PeerDB::add_deny_cidr(&tx, prefix, *mask)?;
+            }
+            AdminPeerCommand::Unban(addr, port) => {
+                debug!("Admin unban of {:?}:{}", addr, port);
+                // don't preemptively insert a peer we never heard of
+                if PeerDB::has_peer(&tx, network_id, addr, *port)? {
+                    PeerDB::set_deny_peer(&tx, network_id, addr, *port, 0)?;
+                }
+            }
+            AdminPeerCommand::UnbanCidr(prefix, mask) => {
+                debug!("Admin unban of {}", cidr_to_string(prefix, *mask));
+                PeerDB::remove_deny_cidr(&tx, prefix, *mask)?;
+            }
+            AdminPeerCommand::Allow(addr, port) => {
+                debug!("Admin allow of {:?}:{}", addr, port);
+                PeerDB::set_allow_peer(&tx, network_id, addr, *port, -1)?;
+            }
+            AdminPeerCommand::AllowCidr(prefix, mask) => {
+                debug!("Admin allow of {}", cidr_to_string(prefix, *mask));
+                PeerDB::add_allow_cidr(&tx, prefix, *mask)?;
+            }
+            AdminPeerCommand::Bootstrap(addr, port, public_key) => {
+                let nk = NeighborKey {
+                    peer_version,
+                    network_id,
+                    addrbytes: addr.clone(),
+                    port: *port,
+                };
+                // same expiry as the bootstrap peers given in the node config
+                let neighbor = Neighbor::empty(&nk, public_key, 9999999);
+                PeerDB::add_bootstrap_peer(&tx, &neighbor)?;
+            }
+            AdminPeerCommand::Disconnect(..) => {}
+        }
+        tx.commit()?;
+
+        let mut to_disconnect = vec![];
+        for event_id in network.iter_peer_event_ids() {
+            let Some(convo) = network.get_p2p_convo(*event_id) else {
+                continue;
+            };
+            let nk = convo.to_neighbor_key();
+            let matches = match self {
+                AdminPeerCommand::Ban(addr, port, _) | AdminPeerCommand::Disconnect(addr, port) => {
+                    nk.addrbytes == *addr && nk.port == *port
+                }
+                AdminPeerCommand::BanCidr(prefix, mask) => {
+                    cidr_contains(prefix, *mask, &nk.addrbytes)
+                }
+                _ => false,
+            };
+            if matches {
+                to_disconnect.push(*event_id);
+            }
+        }
+
+        let num_disconnected = to_disconnect.len() as u64;
+        for event_id in to_disconnect.into_iter() {
+            debug!("Admin disconnect of event {}", event_id);
+            network.deregister_peer(event_id);
+        }
+        Ok(num_disconnected)
+    }
+}
+
+#[derive(Clone)]
+pub struct RPCPostAdminPeersRequestHandler {
+    auth_token: Option<String>,
+    pub action: Option<String>,
+    pub command: Option<AdminPeerCommand>,
+}
+
+impl RPCPostAdminPeersRequestHandler {
+    pub fn new(auth_token: Option<String>) -> Self {
+        Self {
+            auth_token,
+            action: None,
+            command: None,
+        }
+    }
+}
+
+/// Decode the HTTP request
+impl HttpRequest for RPCPostAdminPeersRequestHandler {
+    fn verb(&self) -> &'static str {
+        "POST"
+    }
+
+    fn path_regex(&self) -> Regex {
+        Regex::new(r#"^/v2/admin/peers/(?P<action>ban|unban|allow|bootstrap|disconnect)$"#).unwrap()
+    }
+
+    /// Try to decode this request.
+    fn try_parse_request(
+        &mut self,
+        preamble: &HttpRequestPreamble,
+        captures: &Captures,
+        query: Option<&str>,
+        body: &[u8],
+    ) -> Result<HttpRequestContents, Error> {
+        let content_len = preamble.get_content_length();
+        if !(content_len > 0 && content_len < MAX_PAYLOAD_LEN) {
+            return Err(Error::DecodeError(format!(
+                "Invalid Http request: invalid body length for PostAdminPeers ({})",
+                content_len
+            )));
+        }
+
+        if preamble.content_type != Some(HttpContentType::JSON) {
+            return Err(Error::DecodeError(
+                "Invalid content-type: expected application/json".to_string(),
+            ));
+        }
+
+        let action = captures
+            .name("action")
+            .ok_or_else(|| Error::DecodeError("Failed to match path to action".to_string()))?
+            .as_str()
+            .to_string();
+
+        let body: PostAdminPeersRequestBody = serde_json::from_slice(body)
+            .map_err(|e| Error::DecodeError(format!("Failed to parse JSON body: {}", e)))?;
+
+        self.command = Some(AdminPeerCommand::try_from_request(&action, &body)?);
+        self.action = Some(action);
+        Ok(HttpRequestContents::new().query_string(query))
+    }
+}
+
+impl RPCRequestHandler for RPCPostAdminPeersRequestHandler {
+    /// Reset internal state
+    fn restart(&mut self) {
+        self.action = None;
+        self.command = None;
+    }
+
+    /// Make the response
+    fn try_handle_request(
+        &mut self,
+        preamble: HttpRequestPreamble,
+        _contents: HttpRequestContents,
+        node: &mut StacksNodeState,
+    ) -> Result<(HttpResponsePreamble, HttpResponseContents), NetError> {
+        if !is_authorized(&preamble, self.auth_token.as_deref()) {
+            return StacksHttpResponse::new_error(
+                &preamble,
+                &HttpUnauthorized::new("Missing or invalid authorization token".to_string()),
+            )
+            .try_into_contents()
+            .map_err(NetError::from);
+        }
+
+        let action = self
+            .action
+            .take()
+            .ok_or(NetError::SendError("`action` not set".into()))?;
+        let command = self
+            .command
+            .take()
+            .ok_or(NetError::SendError("`command` not set".into()))?;
+
+        let data_resp = node.with_node_state(
+            |network, _sortdb, _chainstate, _mempool, _rpc_args| match command.apply(network) {
+                Ok(disconnected) => Ok(PostAdminPeersResponse {
+                    action,
+                    disconnected,
+                }),
+                Err(NetError::DBError(e)) => Err(StacksHttpResponse::new_error(
+                    &preamble,
+                    &HttpServerError::new(format!("Failed to update peer DB: {:?}", &e)),
+                )),
+                Err(e) => Err(StacksHttpResponse::new_error(
+                    &preamble,
+                    &HttpBadRequest::new(format!("Failed to apply {}: {:?}", &action, &e)),
+                )),
+            },
+        );
+
+        let data_resp = match data_resp {
+            Ok(data) => data,
+            Err(response) => {
+                return response.try_into_contents().map_err(NetError::from);
+            }
+        };
+
+        let mut preamble = HttpResponsePreamble::ok_json(&preamble);
+        preamble.set_canonical_stacks_tip_height(Some(node.canonical_stacks_tip_height()));
+        let body = HttpResponseContents::try_from_json(&data_resp)?;
+        Ok((preamble, body))
+    }
+}
+
+/// Decode the HTTP response
+impl HttpResponse for RPCPostAdminPeersRequestHandler {
+    fn try_parse_response(
+        &self,
+        preamble: &HttpResponsePreamble,
+        body: &[u8],
+    ) -> Result<HttpResponsePayload, Error> {
+        let response: PostAdminPeersResponse = parse_json(preamble, body)?;
+        Ok(HttpResponsePayload::try_from_json(response)?)
+    }
+}
+
+impl StacksHttpResponse {
+    pub fn decode_post_admin_peers_response(self) -> Result<PostAdminPeersResponse, NetError> {
+        let contents = self.get_http_payload_ok()?;
+        let response_json: serde_json::Value = contents.try_into()?;
+        let response: PostAdminPeersResponse = serde_json::from_value(response_json)
+            .map_err(|_e| Error::DecodeError("Failed to decode JSON".to_string()))?;
+        Ok(response)
+    }
+}
+
+impl StacksHttpRequest {
+    /// Make a new request to run a peer management action, authorized with `auth_token`
+    pub fn new_post_admin_peers(
+        host: PeerHost,
+        auth_token: &str,
+        action: &str,
+        request: PostAdminPeersRequestBody,
+    ) -> StacksHttpRequest {
+        let mut request = StacksHttpRequest::new_for_peer(
+            host,
+            "POST".into(),
+            format!("/v2/admin/peers/{}", action),
+            HttpRequestContents::new().payload_json(
+                serde_json::to_value(request)
+                    .expect("FATAL: failed to encode admin peers request to JSON"),
+            ),
+        )
+        .expect("FATAL: failed to construct request from infallible data");
+        request.add_header("Authorization".into(), auth_token.to_string());
+        request
+    }
+}
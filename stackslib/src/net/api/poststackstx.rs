@@ -0,0 +1,302 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2023 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use regex::{Captures, Regex};
+use stacks_common::codec::MAX_PAYLOAD_LEN;
+use stacks_common::types::chainstate::StacksAddress;
+use stacks_common::types::net::PeerHost;
+
+use crate::burnchains::bitcoin::address::BitcoinAddress;
+use crate::burnchains::Address;
+use crate::chainstate::burn::operations::StackStxOp;
+use crate::chainstate::stacks::address::PoxAddress;
+use crate::net::api::is_authorized;
+use crate::net::http::{
+    parse_json, Error, HttpBadRequest, HttpContentType, HttpRequest, HttpRequestContents,
+    HttpRequestPreamble, HttpResponse, HttpResponseContents, HttpResponsePayload,
+    HttpResponsePreamble, HttpServerError, HttpUnauthorized,
+};
+use crate::net::httpcore::{
+    HttpPreambleExtensions, RPCRequestHandler, StacksHttpRequest, StacksHttpResponse,
+};
+use crate::net::{Error as NetError, StacksNodeState};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PostStackStxRequestBody {
+    /// Stacks address of the stacker.  It must be the address of the stacker key in the node's
+    /// config, which signs the operation.
+    pub sender: String,
+    /// Bitcoin address to receive PoX rewards
+    pub reward_addr: String,
+    pub stacked_ustx: u128,
+    pub num_cycles: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PostStackStxResponse {
+    pub sender: String,
+    pub reward_addr: String,
+    pub stacked_ustx: u128,
+    pub num_cycles: u8,
+    /// pass this to /v2/burn_ops/stack_stx/{request_id} to learn whether the operation was sent
+    pub request_id: u64,
+}
+
+#[derive(Clone)]
+pub struct RPCPostStackStxRequestHandler {
+    auth_token: Option<String>,
+    pub sender: Option<StacksAddress>,
+    pub reward_addr: Option<PoxAddress>,
+    pub stacked_ustx: Option<u128>,
+    pub num_cycles: Option<u8>,
+}
+
+impl RPCPostStackStxRequestHandler {
+    pub fn new(auth_token: Option<String>) -> Self {
+        Self {
+            auth_token,
+            sender: None,
+            reward_addr: None,
+            stacked_ustx: None,
+            num_cycles: None,
+        }
+    }
+}
+
+/// Decode the HTTP request
+impl HttpRequest for RPCPostStackStxRequestHandler {
+    fn verb(&self) -> &'static str {
+        "POST"
+    }
+
+    fn path_regex(&self) -> Regex {
+        Regex::new(r#"^/v2/burn_ops/stack_stx$"#).unwrap()
+    }
+
+    /// Try to decode this request.
+    /// There's nothing to load here, so just make sure the request is well-formed.
+    fn try_parse_request(
+        &mut self,
+        preamble: &HttpRequestPreamble,
+        _captures: &Captures,
+        query: Option<&str>,
+        body: &[u8],
+    ) -> Result<HttpRequestContents, Error> {
+        let content_len = preamble.get_content_length();
+        if !(content_len > 0 && content_len < MAX_PAYLOAD_LEN) {
+            return Err(Error::DecodeError(format!(
+                "Invalid Http request: invalid body length for PostStackStx ({})",
+                content_len
+            )));
+        }
+
+        if preamble.content_type != Some(HttpContentType::JSON) {
+            return Err(Error::DecodeError(
+                "Invalid content-type: expected application/json".to_string(),
+            ));
+        }
+
+        let body: PostStackStxRequestBody = serde_json::from_slice(body)
+            .map_err(|e| Error::DecodeError(format!("Failed to parse JSON body: {}", e)))?;
+
+        let sender = StacksAddress::from_string(&body.sender)
+            .ok_or_else(|| Error::DecodeError("Failed to parse sender address".into()))?;
+        let reward_addr = BitcoinAddress::from_string(&body.reward_addr)
+            .and_then(|addr| PoxAddress::try_from_bitcoin_address(&addr))
+            .ok_or_else(|| Error::DecodeError("Failed to parse reward address".into()))?;
+
+        self.sender = Some(sender);
+        self.reward_addr = Some(reward_addr);
+        self.stacked_ustx = Some(body.stacked_ustx);
+        self.num_cycles = Some(body.num_cycles);
+        Ok(HttpRequestContents::new().query_string(query))
+    }
+}
+
+impl RPCRequestHandler for RPCPostStackStxRequestHandler {
+    /// Reset internal state
+    fn restart(&mut self) {
+        self.sender = None;
+        self.reward_addr = None;
+        self.stacked_ustx = None;
+        self.num_cycles = None;
+    }
+
+    /// Make the response
+    fn try_handle_request(
+        &mut self,
+        preamble: HttpRequestPreamble,
+        _contents: HttpRequestContents,
+        node: &mut StacksNodeState,
+    ) -> Result<(HttpResponsePreamble, HttpResponseContents), NetError> {
+        if !is_authorized(&preamble, self.auth_token.as_deref()) {
+            return StacksHttpResponse::new_error(
+                &preamble,
+                &HttpUnauthorized::new("Missing or invalid authorization token".to_string()),
+            )
+            .try_into_contents()
+            .map_err(NetError::from);
+        }
+
+        let sender = self
+            .sender
+            .take()
+            .ok_or(NetError::SendError("`sender` not set".into()))?;
+        let reward_addr = self
+            .reward_addr
+            .take()
+            .ok_or(NetError::SendError("`reward_addr` not set".into()))?;
+        let stacked_ustx = self
+            .stacked_ustx
+            .take()
+            .ok_or(NetError::SendError("`stacked_ustx` not set".into()))?;
+        let num_cycles = self
+            .num_cycles
+            .take()
+            .ok_or(NetError::SendError("`num_cycles` not set".into()))?;
+
+        let data_resp =
+            node.with_node_state(|_network, _sortdb, _chainstate, _mempool, rpc_args| {
+                let Some(submitter) = rpc_args.burnchain_op_submitter else {
+                    debug!("Burnchain operation submission not configured on this stacks node");
+                    return Err(StacksHttpResponse::new_error(
+                        &preamble,
+                        &HttpBadRequest::new(
+                            "Burnchain operation submission not supported on this node".to_string(),
+                        ),
+                    ));
+                };
+
+                // the stacker key signs the stack-stx op, which must spend a pre-stx output
+                // sent to the sender's address, so the two must match.
+                match submitter.stacker_address() {
+                    Some(stacker_addr) if stacker_addr == sender => {}
+                    Some(_) => {
+                        return Err(StacksHttpResponse::new_error(
+                            &preamble,
+                            &HttpBadRequest::new(format!(
+                                "Sender {} is not this node's stacker",
+                                &sender
+                            )),
+                        ));
+                    }
+                    None => {
+                        return Err(StacksHttpResponse::new_error(
+                            &preamble,
+                            &HttpBadRequest::new(
+                                "No stacker key is configured on this node".to_string(),
+                            ),
+                        ));
+                    }
+                }
+
+                let op = StackStxOp::new(&sender, &reward_addr, stacked_ustx, num_cycles);
+                op.check().map_err(|e| {
+                    StacksHttpResponse::new_error(
+                        &preamble,
+                        &HttpBadRequest::new(format!("Invalid stack-stx operation: {}", &e)),
+                    )
+                })?;
+
+                let request_id = submitter.submit_stack_stx(op).map_err(|e| {
+                    StacksHttpResponse::new_error(
+                        &preamble,
+                        &HttpServerError::new(format!(
+                            "Failed to submit stack-stx operation: {}",
+                            e
+                        )),
+                    )
+                })?;
+
+                Ok(PostStackStxResponse {
+                    sender: sender.to_string(),
+                    reward_addr: reward_addr.clone().to_b58(),
+                    stacked_ustx,
+                    num_cycles,
+                    request_id,
+                })
+            });
+
+        let data_resp = match data_resp {
+            Ok(data) => data,
+            Err(response) => {
+                return response.try_into_contents().map_err(NetError::from);
+            }
+        };
+
+        // the operation is only queued, so it is accepted rather than done
+        let mut preamble = HttpResponsePreamble::from_http_request_preamble(
+            &preamble,
+            202,
+            "Accepted",
+            None,
+            HttpContentType::JSON,
+        );
+        preamble.set_canonical_stacks_tip_height(Some(node.canonical_stacks_tip_height()));
+        let body = HttpResponseContents::try_from_json(&data_resp)?;
+        Ok((preamble, body))
+    }
+}
+
+/// Decode the HTTP response
+impl HttpResponse for RPCPostStackStxRequestHandler {
+    fn try_parse_response(
+        &self,
+        preamble: &HttpResponsePreamble,
+        body: &[u8],
+    ) -> Result<HttpResponsePayload, Error> {
+        let response: PostStackStxResponse = parse_json(preamble, body)?;
+        Ok(HttpResponsePayload::try_from_json(response)?)
+    }
+}
+
+impl StacksHttpResponse {
+    pub fn decode_stack_stx_response(self) -> Result<PostStackStxResponse, NetError> {
+        let (preamble, contents) = self.destruct();
+        if preamble.status_code != 202 {
+            return Err(NetError::RecvError(format!(
+                "HTTP status {}",
+                &preamble.status_code
+            )));
+        }
+        let response_json: serde_json::Value = contents.try_into()?;
+        let response: PostStackStxResponse = serde_json::from_value(response_json)
+            .map_err(|_e| Error::DecodeError("Failed to decode JSON".to_string()))?;
+        Ok(response)
+    }
+}
+
+impl StacksHttpRequest {
+    /// Make a new request to submit a stack-stx operation, authorized with `auth_token`
+    pub fn new_post_stack_stx(
+        host: PeerHost,
+        auth_token: &str,
+        request: PostStackStxRequestBody,
+    ) -> StacksHttpRequest {
+        let mut request = StacksHttpRequest::new_for_peer(
+            host,
+            "POST".into(),
+            "/v2/burn_ops/stack_stx".into(),
+            HttpRequestContents::new().payload_json(
+                serde_json::to_value(request)
+                    .expect("FATAL: failed to encode stack-stx request to JSON"),
+            ),
+        )
+        .expect("FATAL: failed to construct request from infallible data");
+        request.add_header("Authorization".into(), auth_token.to_string());
+        request
+    }
+}
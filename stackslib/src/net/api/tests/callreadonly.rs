@@ -101,6 +101,7 @@ fn test_try_parse_request() {
     assert!(handler.sender.is_none());
     assert!(handler.sponsor.is_none());
     assert!(handler.arguments.is_none());
+    assert!(handler.json_arguments.is_none());
 }
 
 #[test]
@@ -184,6 +185,36 @@ fn test_try_make_response() {
     );
     requests.push(request);
 
+    // query confirmed tip with typed JSON arguments
+    let request = StacksHttpRequest::new_callreadonlyfunction_json(
+        addr.into(),
+        StacksAddress::from_string("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R").unwrap(),
+        "hello-world".try_into().unwrap(),
+        StacksAddress::from_string("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R")
+            .unwrap()
+            .to_account_principal(),
+        None,
+        "ro-confirmed".try_into().unwrap(),
+        vec![],
+        TipRequest::UseLatestAnchoredTip,
+    );
+    requests.push(request);
+
+    // query with the wrong number of typed JSON arguments
+    let request = StacksHttpRequest::new_callreadonlyfunction_json(
+        addr.into(),
+        StacksAddress::from_string("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R").unwrap(),
+        "hello-world".try_into().unwrap(),
+        StacksAddress::from_string("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R")
+            .unwrap()
+            .to_account_principal(),
+        None,
+        "ro-confirmed".try_into().unwrap(),
+        vec![serde_json::json!("1")],
+        TipRequest::UseLatestAnchoredTip,
+    );
+    requests.push(request);
+
     let mut responses = test_rpc(function_name!(), requests);
 
     // confirmed tip
@@ -270,4 +301,27 @@ fn test_try_make_response() {
 
     let (preamble, payload) = response.destruct();
     assert_eq!(preamble.status_code, 404);
+
+    // typed JSON result
+    let response = responses.remove(0);
+    debug!(
+        "Response:\n{}\n",
+        std::str::from_utf8(&response.try_serialize().unwrap()).unwrap()
+    );
+
+    let resp = response.decode_call_readonly_response().unwrap();
+
+    assert!(resp.okay);
+    assert_eq!(resp.result.unwrap(), "0x0100000000000000000000000000000001");
+    assert_eq!(resp.json, Some(serde_json::json!("1")));
+
+    // bad typed JSON arguments
+    let response = responses.remove(0);
+    debug!(
+        "Response:\n{}\n",
+        std::str::from_utf8(&response.try_serialize().unwrap()).unwrap()
+    );
+
+    let (preamble, payload) = response.destruct();
+    assert_eq!(preamble.status_code, 400);
 }
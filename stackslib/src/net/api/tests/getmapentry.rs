@@ -146,6 +146,30 @@ fn test_try_make_response() {
     );
     requests.push(request);
 
+    // query existing with a typed JSON key
+    let request = StacksHttpRequest::new_getmapentry_json(
+        addr.into(),
+        StacksAddress::from_string("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R").unwrap(),
+        "hello-world".try_into().unwrap(),
+        "test-map".try_into().unwrap(),
+        serde_json::json!("1"),
+        TipRequest::UseLatestAnchoredTip,
+        false,
+    );
+    requests.push(request);
+
+    // query with a typed JSON key of the wrong type
+    let request = StacksHttpRequest::new_getmapentry_json(
+        addr.into(),
+        StacksAddress::from_string("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R").unwrap(),
+        "hello-world".try_into().unwrap(),
+        "test-map".try_into().unwrap(),
+        serde_json::json!(true),
+        TipRequest::UseLatestAnchoredTip,
+        false,
+    );
+    requests.push(request);
+
     let mut responses = test_rpc(function_name!(), requests);
 
     // latest data
@@ -211,6 +235,28 @@ fn test_try_make_response() {
     let resp = response.decode_map_entry_response().unwrap();
     assert_eq!(resp.data, "0x09");
     assert_eq!(resp.marf_proof, Some("".to_string()));
+
+    // typed JSON key
+    let response = responses.remove(0);
+    debug!(
+        "Response:\n{}\n",
+        std::str::from_utf8(&response.try_serialize().unwrap()).unwrap()
+    );
+
+    let resp = response.decode_map_entry_response().unwrap();
+    assert_eq!(resp.data, "0x0a0100000000000000000000000000000002");
+    assert_eq!(resp.json, Some(serde_json::json!({"some": "2"})));
+    assert!(resp.marf_proof.is_none());
+
+    // mistyped JSON key
+    let response = responses.remove(0);
+    debug!(
+        "Response:\n{}\n",
+        std::str::from_utf8(&response.try_serialize().unwrap()).unwrap()
+    );
+
+    let (preamble, payload) = response.destruct();
+    assert_eq!(preamble.status_code, 400);
 }
 
 /*
@@ -0,0 +1,261 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2023 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use clarity::vm::types::QualifiedContractIdentifier;
+use stacks_common::types::chainstate::{StacksAddress, StacksBlockId};
+use stacks_common::types::Address;
+
+use super::test_rpc;
+use crate::net::api::*;
+use crate::net::connection::ConnectionOptions;
+use crate::net::httpcore::{
+    HttpPreambleExtensions, HttpRequestContentsExtensions, RPCRequestHandler, StacksHttp,
+    StacksHttpRequest,
+};
+use crate::net::{ProtocolFamily, TipRequest};
+
+#[test]
+fn test_try_parse_request() {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 33333);
+    let mut http = StacksHttp::new(addr.clone(), &ConnectionOptions::default());
+
+    let request = StacksHttpRequest::new_getmapkeys(
+        addr.into(),
+        StacksAddress::from_string("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R").unwrap(),
+        "hello-world-unconfirmed".try_into().unwrap(),
+        "test-map".into(),
+        Some("0x0100000000000000000000000000000001".to_string()),
+        Some(10),
+        TipRequest::SpecificTip(StacksBlockId([0x22; 32])),
+        false,
+    );
+    assert_eq!(
+        request.contents().tip_request(),
+        TipRequest::SpecificTip(StacksBlockId([0x22; 32]))
+    );
+    assert_eq!(request.contents().get_with_proof(), false);
+
+    let bytes = request.try_serialize().unwrap();
+
+    debug!("Request:\n{}\n", std::str::from_utf8(&bytes).unwrap());
+
+    let (parsed_preamble, offset) = http.read_preamble(&bytes).unwrap();
+    let mut handler = getmapkeys::RPCGetMapKeysRequestHandler::new();
+    let mut parsed_request = http
+        .handle_try_parse_request(
+            &mut handler,
+            &parsed_preamble.expect_request(),
+            &bytes[offset..],
+        )
+        .unwrap();
+
+    // consumed path args and query
+    assert_eq!(
+        handler.contract_identifier,
+        Some(
+            QualifiedContractIdentifier::parse(
+                "ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R.hello-world-unconfirmed"
+            )
+            .unwrap()
+        )
+    );
+    assert_eq!(handler.map_name, Some("test-map".into()));
+    assert_eq!(
+        handler.cursor,
+        Some("0100000000000000000000000000000001".to_string())
+    );
+    assert_eq!(handler.limit, Some(10));
+
+    // parsed request consumes headers that would not be in a constructed reqeuest
+    parsed_request.clear_headers();
+    let (preamble, contents) = parsed_request.destruct();
+
+    assert_eq!(&preamble, request.preamble());
+
+    handler.restart();
+    assert!(handler.contract_identifier.is_none());
+    assert!(handler.map_name.is_none());
+    assert!(handler.cursor.is_none());
+    assert!(handler.limit.is_none());
+
+    // bad cursor
+    let request = StacksHttpRequest::new_getmapkeys(
+        addr.into(),
+        StacksAddress::from_string("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R").unwrap(),
+        "hello-world".try_into().unwrap(),
+        "test-map".into(),
+        Some("0xnothex".to_string()),
+        None,
+        TipRequest::UseLatestAnchoredTip,
+        false,
+    );
+    let bytes = request.try_serialize().unwrap();
+    let (parsed_preamble, offset) = http.read_preamble(&bytes).unwrap();
+    let mut handler = getmapkeys::RPCGetMapKeysRequestHandler::new();
+    assert!(http
+        .handle_try_parse_request(
+            &mut handler,
+            &parsed_preamble.expect_request(),
+            &bytes[offset..],
+        )
+        .is_err());
+
+    // bad limit
+    let request = StacksHttpRequest::new_getmapkeys(
+        addr.into(),
+        StacksAddress::from_string("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R").unwrap(),
+        "hello-world".try_into().unwrap(),
+        "test-map".into(),
+        None,
+        Some(0),
+        TipRequest::UseLatestAnchoredTip,
+        false,
+    );
+    let bytes = request.try_serialize().unwrap();
+    let (parsed_preamble, offset) = http.read_preamble(&bytes).unwrap();
+    let mut handler = getmapkeys::RPCGetMapKeysRequestHandler::new();
+    assert!(http
+        .handle_try_parse_request(
+            &mut handler,
+            &parsed_preamble.expect_request(),
+            &bytes[offset..],
+        )
+        .is_err());
+}
+
+#[test]
+fn test_try_make_response() {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 33333);
+
+    let mut requests = vec![];
+
+    // list existing
+    let request = StacksHttpRequest::new_getmapkeys(
+        addr.into(),
+        StacksAddress::from_string("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R").unwrap(),
+        "hello-world".try_into().unwrap(),
+        "test-map".try_into().unwrap(),
+        None,
+        None,
+        TipRequest::UseLatestAnchoredTip,
+        true,
+    );
+    requests.push(request);
+
+    // list existing unconfirmed
+    let request = StacksHttpRequest::new_getmapkeys(
+        addr.into(),
+        StacksAddress::from_string("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R").unwrap(),
+        "hello-world-unconfirmed".try_into().unwrap(),
+        "test-map-unconfirmed".try_into().unwrap(),
+        None,
+        None,
+        TipRequest::UseLatestUnconfirmedTip,
+        false,
+    );
+    requests.push(request);
+
+    // list past the last key
+    let request = StacksHttpRequest::new_getmapkeys(
+        addr.into(),
+        StacksAddress::from_string("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R").unwrap(),
+        "hello-world".try_into().unwrap(),
+        "test-map".try_into().unwrap(),
+        Some("0x0100000000000000000000000000000001".to_string()),
+        None,
+        TipRequest::UseLatestAnchoredTip,
+        true,
+    );
+    requests.push(request);
+
+    // list non-existant map
+    let request = StacksHttpRequest::new_getmapkeys(
+        addr.into(),
+        StacksAddress::from_string("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R").unwrap(),
+        "hello-world".try_into().unwrap(),
+        "does-not-exist".try_into().unwrap(),
+        None,
+        None,
+        TipRequest::UseLatestAnchoredTip,
+        true,
+    );
+    requests.push(request);
+
+    let mut responses = test_rpc(function_name!(), requests);
+
+    // latest data
+    let response = responses.remove(0);
+    debug!(
+        "Response:\n{}\n",
+        std::str::from_utf8(&response.try_serialize().unwrap()).unwrap()
+    );
+
+    assert_eq!(
+        response.preamble().get_canonical_stacks_tip_height(),
+        Some(1)
+    );
+
+    let resp = response.decode_map_keys_response().unwrap();
+    assert_eq!(resp.entries.len(), 1);
+    assert_eq!(resp.entries[0].key, "0x0100000000000000000000000000000001");
+    assert_eq!(
+        resp.entries[0].data,
+        "0x0a0100000000000000000000000000000002"
+    );
+    assert!(resp.entries[0].marf_proof.is_some());
+    assert!(resp.next_cursor.is_none());
+
+    // unconfirmed data
+    let response = responses.remove(0);
+    debug!(
+        "Response:\n{}\n",
+        std::str::from_utf8(&response.try_serialize().unwrap()).unwrap()
+    );
+
+    let resp = response.decode_map_keys_response().unwrap();
+    assert_eq!(resp.entries.len(), 1);
+    assert_eq!(resp.entries[0].key, "0x0000000000000000000000000000000003");
+    assert_eq!(
+        resp.entries[0].data,
+        "0x0a0000000000000000000000000000000004"
+    );
+    assert!(resp.entries[0].marf_proof.is_none());
+    assert!(resp.next_cursor.is_none());
+
+    // nothing after the last key
+    let response = responses.remove(0);
+    debug!(
+        "Response:\n{}\n",
+        std::str::from_utf8(&response.try_serialize().unwrap()).unwrap()
+    );
+
+    let resp = response.decode_map_keys_response().unwrap();
+    assert!(resp.entries.is_empty());
+    assert!(resp.next_cursor.is_none());
+
+    // no such map
+    let response = responses.remove(0);
+    debug!(
+        "Response:\n{}\n",
+        std::str::from_utf8(&response.try_serialize().unwrap()).unwrap()
+    );
+
+    let resp = response.decode_map_keys_response().unwrap();
+    assert!(resp.entries.is_empty());
+    assert!(resp.next_cursor.is_none());
+}
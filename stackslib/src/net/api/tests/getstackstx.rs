@@ -0,0 +1,150 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use stacks_common::types::chainstate::StacksAddress;
+
+use super::{test_rpc, TestRPC, TEST_AUTH_TOKEN};
+use crate::burnchains::Txid;
+use crate::chainstate::burn::operations::StackStxOp;
+use crate::net::api::*;
+use crate::net::connection::ConnectionOptions;
+use crate::net::httpcore::{RPCRequestHandler, StacksHttp, StacksHttpRequest};
+use crate::net::{BurnchainOpStatus, BurnchainOpSubmitter, ProtocolFamily};
+
+/// Submitter which knows about requests 0 (pending), 1 (sent), and 2 (failed)
+struct MockOpSubmitter;
+
+impl BurnchainOpSubmitter for MockOpSubmitter {
+    fn stacker_address(&self) -> Option<StacksAddress> {
+        None
+    }
+
+    fn submit_stack_stx(&self, _op: StackStxOp) -> Result<u64, String> {
+        Err("not supported".to_string())
+    }
+
+    fn get_stack_stx_status(&self, request_id: u64) -> Option<BurnchainOpStatus> {
+        match request_id {
+            0 => Some(BurnchainOpStatus::Pending),
+            1 => Some(BurnchainOpStatus::Sent(Txid([0x01; 32]), Txid([0x02; 32]))),
+            2 => Some(BurnchainOpStatus::Failed("bitcoind is down".to_string())),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_try_parse_request() {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 33333);
+    let mut http = StacksHttp::new(addr.clone(), &ConnectionOptions::default());
+
+    let request = StacksHttpRequest::new_get_stack_stx_status(addr.into(), "secret-token", 123);
+    let bytes = request.try_serialize().unwrap();
+
+    debug!("Request:\n{}\n", std::str::from_utf8(&bytes).unwrap());
+
+    let (parsed_preamble, offset) = http.read_preamble(&bytes).unwrap();
+    let mut handler =
+        getstackstx::RPCGetStackStxStatusRequestHandler::new(Some("secret-token".into()));
+    let mut parsed_request = http
+        .handle_try_parse_request(
+            &mut handler,
+            &parsed_preamble.expect_request(),
+            &bytes[offset..],
+        )
+        .unwrap();
+
+    assert_eq!(handler.request_id, Some(123));
+
+    // parsed request consumes headers that would not be in a constructed reqeuest
+    parsed_request.clear_headers();
+    let (preamble, _contents) = parsed_request.destruct();
+
+    let mut expected_request = request.clone();
+    expected_request.clear_headers();
+    assert_eq!(&preamble, expected_request.preamble());
+
+    handler.restart();
+    assert!(handler.request_id.is_none());
+}
+
+#[test]
+fn test_try_make_response() {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 33333);
+
+    let mut requests = vec![];
+
+    // this is not the test peer's auth token, so this is always rejected
+    let request = StacksHttpRequest::new_get_stack_stx_status(addr.into(), "secret-token", 0);
+    requests.push(request);
+
+    // no submitter
+    let request = StacksHttpRequest::new_get_stack_stx_status(addr.into(), TEST_AUTH_TOKEN, 0);
+    requests.push(request);
+
+    let mut responses = test_rpc(function_name!(), requests);
+
+    let response = responses.remove(0);
+    let (preamble, _body) = response.destruct();
+    assert_eq!(preamble.status_code, 401);
+
+    let response = responses.remove(0);
+    let (preamble, _body) = response.destruct();
+    assert_eq!(preamble.status_code, 400);
+}
+
+#[test]
+fn test_try_make_response_with_submitter() {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 33333);
+
+    let requests = (0..4)
+        .map(|request_id| {
+            StacksHttpRequest::new_get_stack_stx_status(addr.into(), TEST_AUTH_TOKEN, request_id)
+        })
+        .collect();
+
+    let mut responses =
+        TestRPC::setup(function_name!()).run_with_op_submitter(requests, Some(&MockOpSubmitter));
+
+    let response = responses.remove(0);
+    debug!(
+        "Response:\n{}\n",
+        std::str::from_utf8(&response.try_serialize().unwrap()).unwrap()
+    );
+    let resp = response.decode_stack_stx_status().unwrap();
+    assert_eq!(resp.request_id, 0);
+    assert_eq!(resp.status, "pending");
+    assert!(resp.pre_stx_txid.is_none());
+    assert!(resp.stack_stx_txid.is_none());
+
+    let resp = responses.remove(0).decode_stack_stx_status().unwrap();
+    assert_eq!(resp.request_id, 1);
+    assert_eq!(resp.status, "sent");
+    assert_eq!(resp.pre_stx_txid, Some(Txid([0x01; 32])));
+    assert_eq!(resp.stack_stx_txid, Some(Txid([0x02; 32])));
+
+    let resp = responses.remove(0).decode_stack_stx_status().unwrap();
+    assert_eq!(resp.request_id, 2);
+    assert_eq!(resp.status, "failed");
+    assert_eq!(resp.error, Some("bitcoind is down".to_string()));
+
+    // unknown request
+    let response = responses.remove(0);
+    let (preamble, _body) = response.destruct();
+    assert_eq!(preamble.status_code, 404);
+}
@@ -0,0 +1,186 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use clarity::vm::types::QualifiedContractIdentifier;
+use clarity::vm::Value;
+use libmarfproof::{data_map_entry_key, data_var_key, MarfBatchProof};
+use stacks_common::codec::StacksMessageCodec;
+use stacks_common::types::chainstate::{StacksAddress, StacksBlockId};
+use stacks_common::types::Address;
+use stacks_common::util::hash::hex_bytes;
+
+use super::test_rpc;
+use crate::net::api::*;
+use crate::net::connection::ConnectionOptions;
+use crate::net::httpcore::{
+    HttpPreambleExtensions, HttpRequestContentsExtensions, RPCRequestHandler, StacksHttp,
+    StacksHttpRequest,
+};
+use crate::net::{ProtocolFamily, TipRequest};
+
+#[test]
+fn test_try_parse_request() {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 33333);
+    let mut http = StacksHttp::new(addr.clone(), &ConnectionOptions::default());
+
+    let request = StacksHttpRequest::new_getstateproof(
+        addr.into(),
+        StacksAddress::from_string("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R").unwrap(),
+        "hello-world".try_into().unwrap(),
+        vec!["bar".into()],
+        vec![("test-map".into(), Value::UInt(1))],
+        TipRequest::SpecificTip(StacksBlockId([0x22; 32])),
+    );
+    assert_eq!(
+        request.contents().tip_request(),
+        TipRequest::SpecificTip(StacksBlockId([0x22; 32]))
+    );
+
+    let bytes = request.try_serialize().unwrap();
+
+    debug!("Request:\n{}\n", std::str::from_utf8(&bytes).unwrap());
+
+    let (parsed_preamble, offset) = http.read_preamble(&bytes).unwrap();
+    let mut handler = getstateproof::RPCGetStateProofRequestHandler::new();
+    let mut parsed_request = http
+        .handle_try_parse_request(
+            &mut handler,
+            &parsed_preamble.expect_request(),
+            &bytes[offset..],
+        )
+        .unwrap();
+
+    // consumed path args and body
+    assert_eq!(
+        handler.contract_identifier,
+        Some(
+            QualifiedContractIdentifier::parse(
+                "ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R.hello-world"
+            )
+            .unwrap()
+        )
+    );
+    let contract_id = "ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R.hello-world";
+    assert_eq!(
+        handler.keys,
+        Some(vec![
+            data_var_key(contract_id, "bar"),
+            data_map_entry_key(
+                contract_id,
+                "test-map",
+                &Value::UInt(1).serialize_to_hex().unwrap()
+            ),
+        ])
+    );
+
+    // parsed request consumes headers that would not be in a constructed reqeuest
+    parsed_request.clear_headers();
+    let (preamble, contents) = parsed_request.destruct();
+
+    assert_eq!(&preamble, request.preamble());
+
+    handler.restart();
+    assert!(handler.contract_identifier.is_none());
+    assert!(handler.keys.is_none());
+}
+
+#[test]
+fn test_try_make_response() {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 33333);
+
+    let mut requests = vec![];
+
+    // prove a data var, an existing map entry, and a missing map entry
+    let request = StacksHttpRequest::new_getstateproof(
+        addr.into(),
+        StacksAddress::from_string("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R").unwrap(),
+        "hello-world".try_into().unwrap(),
+        vec!["bar".into()],
+        vec![
+            ("test-map".into(), Value::UInt(1)),
+            ("test-map".into(), Value::UInt(2)),
+        ],
+        TipRequest::UseLatestAnchoredTip,
+    );
+    requests.push(request);
+
+    // unconfirmed state has no header to check a proof against
+    let request = StacksHttpRequest::new_getstateproof(
+        addr.into(),
+        StacksAddress::from_string("ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R").unwrap(),
+        "hello-world-unconfirmed".try_into().unwrap(),
+        vec!["bar-unconfirmed".into()],
+        vec![],
+        TipRequest::UseLatestUnconfirmedTip,
+    );
+    requests.push(request);
+
+    let mut responses = test_rpc(function_name!(), requests);
+
+    let response = responses.remove(0);
+    debug!(
+        "Response:\n{}\n",
+        std::str::from_utf8(&response.try_serialize().unwrap()).unwrap()
+    );
+
+    assert_eq!(
+        response.preamble().get_canonical_stacks_tip_height(),
+        Some(1)
+    );
+
+    let resp = response.decode_state_proof_response().unwrap();
+    assert_eq!(resp.entries.len(), 3);
+    assert_eq!(
+        resp.entries[1].data,
+        Some("0x0a0100000000000000000000000000000002".to_string())
+    );
+    assert_eq!(resp.entries[2].data, None);
+
+    let proof_bytes = hex_bytes(resp.proof.strip_prefix("0x").unwrap()).unwrap();
+    let proof = MarfBatchProof::consensus_deserialize(&mut &proof_bytes[..]).unwrap();
+    assert_eq!(proof.tries[0].block_id, resp.index_block_hash);
+
+    // the boot block's header doesn't commit to its trie, but at height 1 the tip's trie
+    // commits to the boot block's root hash as its only ancestor
+    let mut trusted_roots = HashMap::new();
+    for trie in proof.tries.iter().skip(1) {
+        if trie.header.is_none() {
+            trusted_roots.insert(
+                trie.block_id.clone(),
+                proof.tries[0].ancestor_hashes.last().unwrap().clone(),
+            );
+        }
+    }
+    let state = proof.verify(&resp.state_root, &trusted_roots).unwrap();
+    for entry in resp.entries.iter() {
+        state
+            .check_value(&entry.key, entry.data.as_deref())
+            .unwrap();
+    }
+
+    // unconfirmed tip
+    let response = responses.remove(0);
+    debug!(
+        "Response:\n{}\n",
+        std::str::from_utf8(&response.try_serialize().unwrap()).unwrap()
+    );
+
+    let (preamble, payload) = response.destruct();
+    assert_eq!(preamble.status_code, 404);
+}
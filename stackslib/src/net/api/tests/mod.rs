@@ -32,6 +32,7 @@ use crate::burnchains::bitcoin::indexer::BitcoinIndexer;
 use crate::burnchains::Txid;
 use crate::chainstate::burn::db::sortdb::SortitionDB;
 use crate::chainstate::stacks::db::StacksChainState;
+use crate::chainstate::stacks::index::marf::MARFOpenOpts;
 use crate::chainstate::stacks::miner::{BlockBuilderSettings, StacksMicroblockBuilder};
 use crate::chainstate::stacks::{
     CoinbasePayload, StacksBlock, StacksBlockBuilder, StacksBlockHeader, StacksMicroblock,
@@ -45,11 +46,13 @@ use crate::net::relay::Relayer;
 use crate::net::rpc::ConversationHttp;
 use crate::net::test::{TestPeer, TestPeerConfig};
 use crate::net::{
-    Attachment, AttachmentInstance, RPCHandlerArgs, StackerDBConfig, StacksNodeState, UrlString,
+    Attachment, AttachmentInstance, BurnchainOpSubmitter, RPCHandlerArgs, StackerDBConfig,
+    StacksNodeState, UrlString,
 };
 
 mod callreadonly;
 mod getaccount;
+mod getadminpeers;
 mod getattachment;
 mod getattachmentsinv;
 mod getblock;
@@ -61,6 +64,7 @@ mod getheaders;
 mod getinfo;
 mod getistraitimplemented;
 mod getmapentry;
+mod getmapkeys;
 mod getmicroblocks_confirmed;
 mod getmicroblocks_indexed;
 mod getmicroblocks_unconfirmed;
@@ -68,16 +72,23 @@ mod getneighbors;
 mod getpoxinfo;
 mod getstackerdbchunk;
 mod getstackerdbmetadata;
+mod getstackstx;
+mod getstateproof;
 mod getstxtransfercost;
 mod gettransaction_unconfirmed;
 mod liststackerdbreplicas;
+mod postadminpeers;
 mod postblock;
 mod postfeerate;
 mod postmempoolquery;
 mod postmicroblock;
 mod poststackerdbchunk;
+mod poststackstx;
 mod posttransaction;
 
+/// Auth token configured on the peer that answers RPC requests
+const TEST_AUTH_TOKEN: &'static str = "test-auth-token";
+
 const TEST_CONTRACT: &'static str = "
     (define-trait test-trait
         (
@@ -261,6 +272,15 @@ impl<'a> TestRPC<'a> {
         };
         peer_2_config.connection_opts.maximum_call_argument_size = 4096;
 
+        // peer 2 answers the requests, so give it an auth token for the authenticated endpoints
+        peer_2_config.connection_opts.auth_token = Some(TEST_AUTH_TOKEN.to_string());
+
+        // index data map keys, so /v2/map_keys can be served
+        let mut marf_opts = MARFOpenOpts::default();
+        marf_opts.index_data_map_keys = true;
+        peer_1_config.marf_opts = Some(marf_opts.clone());
+        peer_2_config.marf_opts = Some(marf_opts);
+
         // stacker DBs get initialized thru reconfiguration when the above block gets processed
         peer_1_config.add_stacker_db(
             QualifiedContractIdentifier::new(addr1.clone().into(), "hello-world".into()),
@@ -812,6 +832,17 @@ impl<'a> TestRPC<'a> {
     /// Run zero or more HTTP requests on this setup RPC test harness.
     /// Return the list of responses.
     pub fn run(self, requests: Vec<StacksHttpRequest>) -> Vec<StacksHttpResponse> {
+        self.run_with_op_submitter(requests, None)
+    }
+
+    /// Run zero or more HTTP requests on this setup RPC test harness, with the given burnchain
+    /// operation submitter available to the answering peer.
+    /// Return the list of responses.
+    pub fn run_with_op_submitter(
+        self,
+        requests: Vec<StacksHttpRequest>,
+        op_submitter: Option<&dyn BurnchainOpSubmitter>,
+    ) -> Vec<StacksHttpResponse> {
         let mut peer_1 = self.peer_1;
         let mut peer_2 = self.peer_2;
         let peer_1_indexer = self.peer_1_indexer;
@@ -873,7 +904,10 @@ impl<'a> TestRPC<'a> {
                 .unwrap();
 
             {
-                let rpc_args = RPCHandlerArgs::default();
+                let rpc_args = RPCHandlerArgs {
+                    burnchain_op_submitter: op_submitter,
+                    ..RPCHandlerArgs::default()
+                };
                 let mut node_state = StacksNodeState::new(
                     &mut peer_2.network,
                     &peer_2_sortdb,
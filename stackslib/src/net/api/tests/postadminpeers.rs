@@ -0,0 +1,278 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use stacks_common::types::net::PeerAddress;
+use stacks_common::util::secp256k1::{Secp256k1PrivateKey, Secp256k1PublicKey};
+
+use super::{test_rpc, TEST_AUTH_TOKEN};
+use crate::net::api::postadminpeers::{
+    cidr_to_string, parse_cidr, AdminPeerCommand, PostAdminPeersRequestBody,
+};
+use crate::net::api::*;
+use crate::net::connection::ConnectionOptions;
+use crate::net::httpcore::{RPCRequestHandler, StacksHttp, StacksHttpRequest};
+use crate::net::{ProtocolFamily, DENY_BAN_DURATION};
+
+fn peer_body(peer: &str) -> PostAdminPeersRequestBody {
+    PostAdminPeersRequestBody {
+        peer: Some(peer.to_string()),
+        ..PostAdminPeersRequestBody::default()
+    }
+}
+
+fn cidr_body(cidr: &str) -> PostAdminPeersRequestBody {
+    PostAdminPeersRequestBody {
+        cidr: Some(cidr.to_string()),
+        ..PostAdminPeersRequestBody::default()
+    }
+}
+
+#[test]
+fn test_parse_cidr() {
+    let (prefix, mask) = parse_cidr("1.2.3.4/24").unwrap();
+    assert_eq!(prefix, PeerAddress::from_ipv4(1, 2, 3, 0));
+    assert_eq!(mask, 120);
+    assert_eq!(cidr_to_string(&prefix, mask), "1.2.3.0/24");
+
+    let (prefix, mask) = parse_cidr("fe80::1/10").unwrap();
+    assert_eq!(mask, 10);
+    assert_eq!(cidr_to_string(&prefix, mask), "fe80::/10");
+
+    assert!(parse_cidr("1.2.3.4").is_none());
+    assert!(parse_cidr("1.2.3.4/0").is_none());
+    assert!(parse_cidr("1.2.3.4/33").is_none());
+    assert!(parse_cidr("fe80::/129").is_none());
+    assert!(parse_cidr("not-an-ip/8").is_none());
+}
+
+#[test]
+fn test_command_from_request() {
+    let pubk = Secp256k1PublicKey::from_private(&Secp256k1PrivateKey::new());
+
+    assert_eq!(
+        AdminPeerCommand::try_from_request("ban", &peer_body("1.2.3.4:20444")).unwrap(),
+        AdminPeerCommand::Ban(PeerAddress::from_ipv4(1, 2, 3, 4), 20444, DENY_BAN_DURATION)
+    );
+    let mut body = peer_body("1.2.3.4:20444");
+    body.duration = Some(60);
+    assert_eq!(
+        AdminPeerCommand::try_from_request("ban", &body).unwrap(),
+        AdminPeerCommand::Ban(PeerAddress::from_ipv4(1, 2, 3, 4), 20444, 60)
+    );
+    assert_eq!(
+        AdminPeerCommand::try_from_request("unban", &cidr_body("10.0.0.0/8")).unwrap(),
+        AdminPeerCommand::UnbanCidr(PeerAddress::from_ipv4(10, 0, 0, 0), 104)
+    );
+    assert_eq!(
+        AdminPeerCommand::try_from_request("allow", &peer_body("1.2.3.4:20444")).unwrap(),
+        AdminPeerCommand::Allow(PeerAddress::from_ipv4(1, 2, 3, 4), 20444)
+    );
+
+    let mut body = peer_body("1.2.3.4:20444");
+    body.public_key = Some(pubk.to_hex());
+    assert_eq!(
+        AdminPeerCommand::try_from_request("bootstrap", &body).unwrap(),
+        AdminPeerCommand::Bootstrap(PeerAddress::from_ipv4(1, 2, 3, 4), 20444, pubk.clone())
+    );
+
+    // bootstrap peers need a key
+    assert!(AdminPeerCommand::try_from_request("bootstrap", &peer_body("1.2.3.4:20444")).is_err());
+    // only single peers can be disconnected
+    assert!(AdminPeerCommand::try_from_request("disconnect", &cidr_body("10.0.0.0/8")).is_err());
+    // can't ban a CIDR for a fixed duration
+    let mut body = cidr_body("10.0.0.0/8");
+    body.duration = Some(60);
+    assert!(AdminPeerCommand::try_from_request("ban", &body).is_err());
+    // need exactly one of peer and cidr
+    let mut body = cidr_body("10.0.0.0/8");
+    body.peer = Some("1.2.3.4:20444".into());
+    assert!(AdminPeerCommand::try_from_request("ban", &body).is_err());
+    assert!(
+        AdminPeerCommand::try_from_request("ban", &PostAdminPeersRequestBody::default()).is_err()
+    );
+    assert!(AdminPeerCommand::try_from_request("ban", &peer_body("1.2.3.4")).is_err());
+}
+
+#[test]
+fn test_try_parse_request() {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 33333);
+    let mut http = StacksHttp::new(addr.clone(), &ConnectionOptions::default());
+
+    let request = StacksHttpRequest::new_post_admin_peers(
+        addr.into(),
+        "secret-token",
+        "ban",
+        cidr_body("10.0.0.0/8"),
+    );
+    assert_eq!(
+        request.get_headers().get("authorization"),
+        Some(&"secret-token".to_string())
+    );
+    let bytes = request.try_serialize().unwrap();
+
+    debug!("Request:\n{}\n", std::str::from_utf8(&bytes).unwrap());
+
+    let (parsed_preamble, offset) = http.read_preamble(&bytes).unwrap();
+    let mut handler =
+        postadminpeers::RPCPostAdminPeersRequestHandler::new(Some("secret-token".into()));
+    let mut parsed_request = http
+        .handle_try_parse_request(
+            &mut handler,
+            &parsed_preamble.expect_request(),
+            &bytes[offset..],
+        )
+        .unwrap();
+
+    assert_eq!(handler.action, Some("ban".to_string()));
+    assert_eq!(
+        handler.command,
+        Some(AdminPeerCommand::BanCidr(
+            PeerAddress::from_ipv4(10, 0, 0, 0),
+            104
+        ))
+    );
+
+    // parsed request consumes headers that would not be in a constructed reqeuest
+    parsed_request.clear_headers();
+    let (preamble, _contents) = parsed_request.destruct();
+
+    let mut expected_request = request.clone();
+    expected_request.clear_headers();
+    assert_eq!(&preamble, expected_request.preamble());
+
+    handler.restart();
+    assert!(handler.action.is_none());
+    assert!(handler.command.is_none());
+
+    // unknown actions don't match
+    let request = StacksHttpRequest::new_post_admin_peers(
+        addr.into(),
+        "secret-token",
+        "explode",
+        cidr_body("10.0.0.0/8"),
+    );
+    let bytes = request.try_serialize().unwrap();
+    let (parsed_preamble, offset) = http.read_preamble(&bytes).unwrap();
+    assert!(http
+        .handle_try_parse_request(
+            &mut handler,
+            &parsed_preamble.expect_request(),
+            &bytes[offset..],
+        )
+        .is_err());
+}
+
+#[test]
+fn test_try_make_response() {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 33333);
+    let pubk = Secp256k1PublicKey::from_private(&Secp256k1PrivateKey::new());
+
+    let mut requests = vec![];
+
+    // wrong token
+    requests.push(StacksHttpRequest::new_post_admin_peers(
+        addr.into(),
+        "secret-token",
+        "ban",
+        peer_body("1.2.3.4:20444"),
+    ));
+
+    // ban a peer and a prefix, and add a bootstrap peer
+    requests.push(StacksHttpRequest::new_post_admin_peers(
+        addr.into(),
+        TEST_AUTH_TOKEN,
+        "ban",
+        peer_body("1.2.3.4:20444"),
+    ));
+    requests.push(StacksHttpRequest::new_post_admin_peers(
+        addr.into(),
+        TEST_AUTH_TOKEN,
+        "ban",
+        cidr_body("10.0.0.0/8"),
+    ));
+    let mut body = peer_body("5.6.7.8:20444");
+    body.public_key = Some(pubk.to_hex());
+    requests.push(StacksHttpRequest::new_post_admin_peers(
+        addr.into(),
+        TEST_AUTH_TOKEN,
+        "bootstrap",
+        body,
+    ));
+    requests.push(StacksHttpRequest::new_get_admin_peers(
+        addr.into(),
+        TEST_AUTH_TOKEN,
+    ));
+
+    // undo the bans
+    requests.push(StacksHttpRequest::new_post_admin_peers(
+        addr.into(),
+        TEST_AUTH_TOKEN,
+        "unban",
+        peer_body("1.2.3.4:20444"),
+    ));
+    requests.push(StacksHttpRequest::new_post_admin_peers(
+        addr.into(),
+        TEST_AUTH_TOKEN,
+        "unban",
+        cidr_body("10.0.0.0/8"),
+    ));
+    requests.push(StacksHttpRequest::new_get_admin_peers(
+        addr.into(),
+        TEST_AUTH_TOKEN,
+    ));
+
+    let mut responses = test_rpc(function_name!(), requests);
+
+    let response = responses.remove(0);
+    let (preamble, _body) = response.destruct();
+    assert_eq!(preamble.status_code, 401);
+
+    for action in ["ban", "ban", "bootstrap"] {
+        let response = responses.remove(0);
+        debug!(
+            "Response:\n{}\n",
+            std::str::from_utf8(&response.try_serialize().unwrap()).unwrap()
+        );
+        let resp = response.decode_post_admin_peers_response().unwrap();
+        assert_eq!(resp.action, action);
+        assert_eq!(resp.disconnected, 0);
+    }
+
+    let resp = responses.remove(0).decode_admin_peers().unwrap();
+    assert_eq!(resp.denied.len(), 1);
+    assert_eq!(resp.denied[0].addrbytes, PeerAddress::from_ipv4(1, 2, 3, 4));
+    assert_eq!(resp.denied[0].port, 20444);
+    assert_eq!(resp.denied_cidrs, vec!["10.0.0.0/8".to_string()]);
+    assert_eq!(resp.bootstrap.len(), 2);
+    assert!(resp
+        .bootstrap
+        .iter()
+        .any(|peer| peer.addrbytes == PeerAddress::from_ipv4(5, 6, 7, 8)));
+
+    for _ in 0..2 {
+        let resp = responses
+            .remove(0)
+            .decode_post_admin_peers_response()
+            .unwrap();
+        assert_eq!(resp.action, "unban");
+    }
+
+    let resp = responses.remove(0).decode_admin_peers().unwrap();
+    assert!(resp.denied.is_empty());
+    assert!(resp.denied_cidrs.is_empty());
+}
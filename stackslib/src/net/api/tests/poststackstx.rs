@@ -0,0 +1,230 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2023 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use stacks_common::address::C32_ADDRESS_VERSION_TESTNET_SINGLESIG;
+use stacks_common::types::chainstate::StacksAddress;
+use stacks_common::types::Address;
+use stacks_common::util::hash::Hash160;
+
+use super::{test_rpc, TestRPC, TEST_AUTH_TOKEN};
+use crate::chainstate::burn::operations::StackStxOp;
+use crate::chainstate::stacks::address::PoxAddress;
+use crate::net::api::*;
+use crate::net::connection::ConnectionOptions;
+use crate::net::http::HttpRequestPreamble;
+use crate::net::httpcore::{RPCRequestHandler, StacksHttp, StacksHttpRequest};
+use crate::net::{BurnchainOpStatus, BurnchainOpSubmitter, ProtocolFamily};
+
+const STACKER: &'static str = "ST2DS4MSWSGJ3W9FBC6BVT0Y92S345HY8N3T6AV7R";
+
+/// Submitter which pretends to send operations for `STACKER`
+struct MockOpSubmitter {
+    result: Result<u64, String>,
+}
+
+impl BurnchainOpSubmitter for MockOpSubmitter {
+    fn stacker_address(&self) -> Option<StacksAddress> {
+        StacksAddress::from_string(STACKER)
+    }
+
+    fn submit_stack_stx(&self, op: StackStxOp) -> Result<u64, String> {
+        assert_eq!(op.sender.to_string(), STACKER);
+        self.result.clone()
+    }
+
+    fn get_stack_stx_status(&self, _request_id: u64) -> Option<BurnchainOpStatus> {
+        None
+    }
+}
+
+fn make_request_body(sender: &str) -> poststackstx::PostStackStxRequestBody {
+    let reward_addr = PoxAddress::Standard(StacksAddress::from_string(STACKER).unwrap(), None);
+    poststackstx::PostStackStxRequestBody {
+        sender: sender.to_string(),
+        reward_addr: reward_addr.to_b58(),
+        stacked_ustx: 1_000_000_000,
+        num_cycles: 6,
+    }
+}
+
+#[test]
+fn test_is_authorized() {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 33333);
+    let mut preamble = HttpRequestPreamble::new_for_peer(
+        addr.into(),
+        "POST".into(),
+        "/v2/burn_ops/stack_stx".into(),
+    );
+    assert!(!is_authorized(&preamble, Some("secret-token")));
+
+    preamble.add_header("Authorization".into(), "secret-token".into());
+    assert!(is_authorized(&preamble, Some("secret-token")));
+    assert!(!is_authorized(&preamble, Some("secret-token2")));
+    assert!(!is_authorized(&preamble, Some("secret-toke")));
+    assert!(!is_authorized(&preamble, None));
+}
+
+#[test]
+fn test_try_parse_request() {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 33333);
+    let mut http = StacksHttp::new(addr.clone(), &ConnectionOptions::default());
+
+    let request = StacksHttpRequest::new_post_stack_stx(
+        addr.into(),
+        "secret-token",
+        make_request_body(STACKER),
+    );
+    assert_eq!(
+        request.get_headers().get("authorization"),
+        Some(&"secret-token".to_string())
+    );
+    let bytes = request.try_serialize().unwrap();
+
+    debug!("Request:\n{}\n", std::str::from_utf8(&bytes).unwrap());
+
+    let (parsed_preamble, offset) = http.read_preamble(&bytes).unwrap();
+    let mut handler = poststackstx::RPCPostStackStxRequestHandler::new(Some("secret-token".into()));
+    let mut parsed_request = http
+        .handle_try_parse_request(
+            &mut handler,
+            &parsed_preamble.expect_request(),
+            &bytes[offset..],
+        )
+        .unwrap();
+
+    assert_eq!(handler.sender, StacksAddress::from_string(STACKER));
+    assert_eq!(
+        handler.reward_addr,
+        Some(PoxAddress::Standard(
+            StacksAddress::from_string(STACKER).unwrap(),
+            None
+        ))
+    );
+    assert_eq!(handler.stacked_ustx, Some(1_000_000_000));
+    assert_eq!(handler.num_cycles, Some(6));
+
+    // parsed request consumes headers that would not be in a constructed reqeuest
+    parsed_request.clear_headers();
+    let (preamble, _contents) = parsed_request.destruct();
+
+    let mut expected_request = request.clone();
+    expected_request.clear_headers();
+    assert_eq!(&preamble, expected_request.preamble());
+
+    handler.restart();
+    assert!(handler.sender.is_none());
+    assert!(handler.reward_addr.is_none());
+    assert!(handler.stacked_ustx.is_none());
+    assert!(handler.num_cycles.is_none());
+}
+
+#[test]
+fn test_try_make_response() {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 33333);
+
+    let mut requests = vec![];
+
+    // this is not the test peer's auth token, so this is always rejected
+    let request = StacksHttpRequest::new_post_stack_stx(
+        addr.into(),
+        "secret-token",
+        make_request_body(STACKER),
+    );
+    requests.push(request);
+
+    // no submitter
+    let request = StacksHttpRequest::new_post_stack_stx(
+        addr.into(),
+        TEST_AUTH_TOKEN,
+        make_request_body(STACKER),
+    );
+    requests.push(request);
+
+    let mut responses = test_rpc(function_name!(), requests);
+
+    let response = responses.remove(0);
+    debug!(
+        "Response:\n{}\n",
+        std::str::from_utf8(&response.try_serialize().unwrap()).unwrap()
+    );
+
+    let (preamble, _body) = response.destruct();
+    assert_eq!(preamble.status_code, 401);
+
+    let response = responses.remove(0);
+    let (preamble, _body) = response.destruct();
+    assert_eq!(preamble.status_code, 400);
+}
+
+#[test]
+fn test_try_make_response_with_submitter() {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 33333);
+    let other_stacker =
+        StacksAddress::new(C32_ADDRESS_VERSION_TESTNET_SINGLESIG, Hash160([0x11; 20])).to_string();
+
+    let mut requests = vec![];
+
+    // the configured stacker
+    let request = StacksHttpRequest::new_post_stack_stx(
+        addr.into(),
+        TEST_AUTH_TOKEN,
+        make_request_body(STACKER),
+    );
+    requests.push(request);
+
+    // some other stacker, whose key the node doesn't have
+    let request = StacksHttpRequest::new_post_stack_stx(
+        addr.into(),
+        TEST_AUTH_TOKEN,
+        make_request_body(&other_stacker),
+    );
+    requests.push(request);
+
+    let submitter = MockOpSubmitter { result: Ok(7) };
+    let mut responses =
+        TestRPC::setup(function_name!()).run_with_op_submitter(requests, Some(&submitter));
+
+    let response = responses.remove(0);
+    debug!(
+        "Response:\n{}\n",
+        std::str::from_utf8(&response.try_serialize().unwrap()).unwrap()
+    );
+    let resp = response.decode_stack_stx_response().unwrap();
+    assert_eq!(resp.sender, STACKER);
+    assert_eq!(resp.request_id, 7);
+
+    let response = responses.remove(0);
+    let (preamble, _body) = response.destruct();
+    assert_eq!(preamble.status_code, 400);
+
+    // submission failures are reported
+    let request = StacksHttpRequest::new_post_stack_stx(
+        addr.into(),
+        TEST_AUTH_TOKEN,
+        make_request_body(STACKER),
+    );
+    let submitter = MockOpSubmitter {
+        result: Err("bitcoind is down".to_string()),
+    };
+    let mut responses = TestRPC::setup(&format!("{}-fail", function_name!()))
+        .run_with_op_submitter(vec![request], Some(&submitter));
+
+    let response = responses.remove(0);
+    let (preamble, _body) = response.destruct();
+    assert_eq!(preamble.status_code, 500);
+}
@@ -0,0 +1,767 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Capture and replay of P2P traffic.
+//!
+//! When `ConnectionOptions::p2p_capture_path` is set, the `PeerNetwork` opens a
+//! `MessageCapture` and hands each new `ConversationP2P` a `ConversationCapture`.  Every
+//! `StacksMessage` the conversation receives or sends is then appended to the capture file as a
+//! `CaptureRecord`, tagged with the time, the conversation, the direction and the remote peer's
+//! address and public key hash.  Messages are recorded after decryption, so captures of encrypted
+//! sessions are readable too.
+//!
+//! A capture file starts with `CAPTURE_FILE_MAGIC` and is followed by length-prefixed records.
+//! Once the file would grow past its size limit it is rotated: `capture` becomes `capture.1`,
+//! `capture.1` becomes `capture.2`, and so on, and the oldest file beyond the limit is removed.
+//!
+//! Capturing never interferes with the node: write failures are logged and the message is
+//! dropped from the capture.
+//!
+//! Captures can be read back with `read_capture_file()`, narrowed with a `CaptureFilter`, and
+//! replayed against a running node with `replay_capture()`.  `stacks-inspect capture-dump` and
+//! `stacks-inspect capture-replay` wrap these.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{fmt, thread};
+
+use stacks_common::codec::{
+    read_next, write_next, Error as codec_error, StacksMessageCodec, MAX_MESSAGE_LEN,
+};
+use stacks_common::types::net::PeerAddress;
+use stacks_common::util::get_epoch_time_ms;
+use stacks_common::util::hash::Hash160;
+
+use crate::net::{Error as net_error, NeighborAddress, StacksMessage, StacksMessageType};
+
+/// First bytes of every capture file
+pub const CAPTURE_FILE_MAGIC: [u8; 8] = *b"STXCAP01";
+/// Encoded size of a `CaptureRecord`'s header: timestamp, conversation ID, direction and peer
+const CAPTURE_RECORD_HEADER_LEN: u32 = 8 + 8 + 1 + 16 + 2 + 20;
+
+/// Which way a captured message travelled
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CaptureDirection {
+    /// Received from the remote peer
+    Inbound = 0,
+    /// Sent to the remote peer
+    Outbound = 1,
+}
+
+impl CaptureDirection {
+    pub fn from_u8(b: u8) -> Option<CaptureDirection> {
+        match b {
+            0 => Some(CaptureDirection::Inbound),
+            1 => Some(CaptureDirection::Outbound),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<CaptureDirection> {
+        match s {
+            "inbound" | "in" => Some(CaptureDirection::Inbound),
+            "outbound" | "out" => Some(CaptureDirection::Outbound),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CaptureDirection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CaptureDirection::Inbound => write!(f, "inbound"),
+            CaptureDirection::Outbound => write!(f, "outbound"),
+        }
+    }
+}
+
+/// One captured message
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureRecord {
+    /// When the message was received or sent, in milliseconds since the epoch
+    pub timestamp_ms: u64,
+    /// Identifies the conversation within the capture.  Unique per `MessageCapture`.
+    pub conversation_id: u64,
+    pub direction: CaptureDirection,
+    /// The remote peer.  The public key hash is all 0's if the peer had not yet handshaked.
+    pub peer: NeighborAddress,
+    /// The message, as encoded on the wire
+    pub message_bytes: Vec<u8>,
+}
+
+impl CaptureRecord {
+    /// Decode the captured message
+    pub fn decode_message(&self) -> Result<StacksMessage, net_error> {
+        read_next::<StacksMessage, _>(&mut &self.message_bytes[..])
+            .map_err(|e| net_error::DeserializeError(format!("{:?}", &e)))
+    }
+}
+
+impl StacksMessageCodec for CaptureRecord {
+    fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), codec_error> {
+        let len = CAPTURE_RECORD_HEADER_LEN
+            .checked_add(u32::try_from(self.message_bytes.len()).map_err(|_| {
+                codec_error::SerializeError("Captured message is too big".to_string())
+            })?)
+            .ok_or_else(|| {
+                codec_error::SerializeError("Captured message is too big".to_string())
+            })?;
+        write_next(fd, &len)?;
+        write_next(fd, &self.timestamp_ms)?;
+        write_next(fd, &self.conversation_id)?;
+        write_next(fd, &(self.direction as u8))?;
+        write_next(fd, &self.peer)?;
+        fd.write_all(&self.message_bytes)
+            .map_err(codec_error::WriteError)?;
+        Ok(())
+    }
+
+    fn consensus_deserialize<R: Read>(fd: &mut R) -> Result<CaptureRecord, codec_error> {
+        let len: u32 = read_next(fd)?;
+        if len < CAPTURE_RECORD_HEADER_LEN || len > MAX_MESSAGE_LEN + CAPTURE_RECORD_HEADER_LEN {
+            return Err(codec_error::DeserializeError(format!(
+                "Invalid capture record length {}",
+                len
+            )));
+        }
+        let timestamp_ms: u64 = read_next(fd)?;
+        let conversation_id: u64 = read_next(fd)?;
+        let direction_byte: u8 = read_next(fd)?;
+        let direction = CaptureDirection::from_u8(direction_byte).ok_or_else(|| {
+            codec_error::DeserializeError(format!("Invalid capture direction {}", direction_byte))
+        })?;
+        let peer: NeighborAddress = read_next(fd)?;
+
+        let mut message_bytes = vec![0u8; (len - CAPTURE_RECORD_HEADER_LEN) as usize];
+        fd.read_exact(&mut message_bytes)
+            .map_err(codec_error::ReadError)?;
+
+        Ok(CaptureRecord {
+            timestamp_ms,
+            conversation_id,
+            direction,
+            peer,
+            message_bytes,
+        })
+    }
+}
+
+/// An open capture file, plus the state needed to rotate it
+struct CaptureFile {
+    path: String,
+    file: File,
+    /// Bytes written to `file` so far
+    len: u64,
+    /// Rotate once the file would grow past this many bytes
+    max_bytes: u64,
+    /// Number of files to keep, including the one being written
+    max_files: u32,
+    next_conversation_id: u64,
+}
+
+impl CaptureFile {
+    fn rotated_path(path: &str, i: u32) -> String {
+        format!("{}.{}", path, i)
+    }
+
+    /// Shift `path` to `path.1`, `path.1` to `path.2` and so on, dropping whatever would land
+    /// beyond `max_files`.
+    fn rotate_files(path: &str, max_files: u32) -> Result<(), net_error> {
+        let keep = max_files.saturating_sub(1);
+        if keep == 0 {
+            return match fs::remove_file(path) {
+                Ok(()) => Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                Err(_) => Err(net_error::FilesystemError),
+            };
+        }
+        let _ = fs::remove_file(Self::rotated_path(path, keep));
+        for i in (1..keep).rev() {
+            let from = Self::rotated_path(path, i);
+            if fs::metadata(&from).is_ok() {
+                fs::rename(&from, Self::rotated_path(path, i + 1))
+                    .map_err(|_| net_error::FilesystemError)?;
+            }
+        }
+        if fs::metadata(path).is_ok() {
+            fs::rename(path, Self::rotated_path(path, 1))
+                .map_err(|_| net_error::FilesystemError)?;
+        }
+        Ok(())
+    }
+
+    /// Start a fresh capture file at `path`
+    fn create(path: &str) -> Result<File, net_error> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|_| net_error::FilesystemError)?;
+        file.write_all(&CAPTURE_FILE_MAGIC)
+            .map_err(net_error::WriteError)?;
+        Ok(file)
+    }
+
+    fn open(path: &str, max_bytes: u64, max_files: u32) -> Result<CaptureFile, net_error> {
+        Self::rotate_files(path, max_files)?;
+        let file = Self::create(path)?;
+        Ok(CaptureFile {
+            path: path.to_string(),
+            file,
+            len: CAPTURE_FILE_MAGIC.len() as u64,
+            max_bytes,
+            max_files,
+            next_conversation_id: 0,
+        })
+    }
+
+    fn append(&mut self, record_bytes: &[u8]) -> Result<(), net_error> {
+        let record_len = record_bytes.len() as u64;
+        if self.len > CAPTURE_FILE_MAGIC.len() as u64
+            && self.len.saturating_add(record_len) > self.max_bytes
+        {
+            Self::rotate_files(&self.path, self.max_files)?;
+            self.file = Self::create(&self.path)?;
+            self.len = CAPTURE_FILE_MAGIC.len() as u64;
+        }
+        self.file
+            .write_all(record_bytes)
+            .map_err(net_error::WriteError)?;
+        self.len = self.len.saturating_add(record_len);
+        Ok(())
+    }
+}
+
+/// Handle to a rotating capture file, shared by all of a `PeerNetwork`'s conversations
+#[derive(Clone)]
+pub struct MessageCapture {
+    inner: Arc<Mutex<CaptureFile>>,
+}
+
+impl fmt::Debug for MessageCapture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.inner.lock() {
+            Ok(inner) => write!(f, "MessageCapture({})", &inner.path),
+            Err(_) => write!(f, "MessageCapture(<poisoned>)"),
+        }
+    }
+}
+
+impl MessageCapture {
+    /// Start capturing to `path`.  Any capture already at `path` is rotated out of the way.
+    /// The file is rotated whenever it would exceed `max_bytes`, and at most `max_files` files
+    /// (including `path` itself) are kept.
+    pub fn open(path: &str, max_bytes: u64, max_files: u32) -> Result<MessageCapture, net_error> {
+        let capture_file = CaptureFile::open(path, max_bytes, max_files)?;
+        Ok(MessageCapture {
+            inner: Arc::new(Mutex::new(capture_file)),
+        })
+    }
+
+    /// Get a recorder for a new conversation
+    pub fn new_conversation(&self) -> ConversationCapture {
+        let conversation_id = match self.inner.lock() {
+            Ok(mut inner) => {
+                let id = inner.next_conversation_id;
+                inner.next_conversation_id = inner.next_conversation_id.wrapping_add(1);
+                id
+            }
+            Err(_) => 0,
+        };
+        ConversationCapture {
+            capture: self.clone(),
+            conversation_id,
+        }
+    }
+
+    fn write_record(&self, record: &CaptureRecord) -> Result<(), net_error> {
+        let mut bytes = vec![];
+        record
+            .consensus_serialize(&mut bytes)
+            .map_err(|e| net_error::SerializeError(format!("{:?}", &e)))?;
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|_| net_error::SerializeError("Capture file lock poisoned".to_string()))?;
+        inner.append(&bytes)
+    }
+}
+
+/// Records the messages of a single conversation into a `MessageCapture`
+#[derive(Debug, Clone)]
+pub struct ConversationCapture {
+    capture: MessageCapture,
+    conversation_id: u64,
+}
+
+impl ConversationCapture {
+    pub fn conversation_id(&self) -> u64 {
+        self.conversation_id
+    }
+
+    /// Append a message to the capture.  Failures are logged, not returned.
+    pub fn record(&self, direction: CaptureDirection, peer: &NeighborAddress, msg: &StacksMessage) {
+        let mut message_bytes = vec![];
+        if let Err(e) = msg.consensus_serialize(&mut message_bytes) {
+            warn!(
+                "Failed to encode {} for capture: {:?}",
+                msg.payload.get_message_name(),
+                &e
+            );
+            return;
+        }
+        let record = CaptureRecord {
+            timestamp_ms: u64::try_from(get_epoch_time_ms()).unwrap_or(u64::MAX),
+            conversation_id: self.conversation_id,
+            direction,
+            peer: peer.clone(),
+            message_bytes,
+        };
+        if let Err(e) = self.capture.write_record(&record) {
+            warn!(
+                "Failed to capture {} {} for conversation {}: {:?}",
+                direction,
+                msg.payload.get_message_name(),
+                self.conversation_id,
+                &e
+            );
+        }
+    }
+}
+
+/// Read every record in a capture file.  A truncated final record (e.g. from a node that was
+/// killed mid-write) is ignored.
+pub fn read_capture_file(path: &str) -> Result<Vec<CaptureRecord>, net_error> {
+    let bytes = fs::read(path).map_err(|_| net_error::FilesystemError)?;
+    if bytes.len() < CAPTURE_FILE_MAGIC.len()
+        || bytes[0..CAPTURE_FILE_MAGIC.len()] != CAPTURE_FILE_MAGIC
+    {
+        return Err(net_error::DeserializeError(format!(
+            "{} is not a capture file",
+            path
+        )));
+    }
+
+    let mut cursor = &bytes[CAPTURE_FILE_MAGIC.len()..];
+    let mut records = vec![];
+    while !cursor.is_empty() {
+        match CaptureRecord::consensus_deserialize(&mut cursor) {
+            Ok(record) => records.push(record),
+            Err(codec_error::ReadError(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                warn!("Capture file {} ends with a truncated record", path);
+                break;
+            }
+            Err(e) => {
+                return Err(net_error::DeserializeError(format!(
+                    "Corrupt record in {}: {:?}",
+                    path, &e
+                )));
+            }
+        }
+    }
+    Ok(records)
+}
+
+/// Selects records from a capture.  Unset fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct CaptureFilter {
+    pub direction: Option<CaptureDirection>,
+    pub conversation_id: Option<u64>,
+    /// Remote peer address and port
+    pub peer: Option<(PeerAddress, u16)>,
+    /// Remote peer public key hash
+    pub peer_key_hash: Option<Hash160>,
+    /// Message names, as given by `StacksMessageType::get_message_name()`
+    pub message_names: Vec<String>,
+    pub since_ms: Option<u64>,
+    pub until_ms: Option<u64>,
+}
+
+impl CaptureFilter {
+    pub fn matches(&self, record: &CaptureRecord) -> bool {
+        if let Some(direction) = self.direction {
+            if record.direction != direction {
+                return false;
+            }
+        }
+        if let Some(conversation_id) = self.conversation_id {
+            if record.conversation_id != conversation_id {
+                return false;
+            }
+        }
+        if let Some((ref addrbytes, port)) = self.peer {
+            if record.peer.addrbytes != *addrbytes || record.peer.port != port {
+                return false;
+            }
+        }
+        if let Some(ref key_hash) = self.peer_key_hash {
+            if record.peer.public_key_hash != *key_hash {
+                return false;
+            }
+        }
+        if let Some(since_ms) = self.since_ms {
+            if record.timestamp_ms < since_ms {
+                return false;
+            }
+        }
+        if let Some(until_ms) = self.until_ms {
+            if record.timestamp_ms > until_ms {
+                return false;
+            }
+        }
+        if !self.message_names.is_empty() {
+            let Ok(msg) = record.decode_message() else {
+                return false;
+            };
+            let name = msg.payload.get_message_name();
+            if !self.message_names.iter().any(|n| n == name) {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn apply(&self, records: Vec<CaptureRecord>) -> Vec<CaptureRecord> {
+        records.into_iter().filter(|r| self.matches(r)).collect()
+    }
+}
+
+/// Outcome of `replay_capture()`
+#[derive(Debug, Clone, Default)]
+pub struct ReplayReport {
+    /// Number of captured messages written to the node
+    pub messages_sent: usize,
+    /// Number of captured messages not replayed (session keys, which only make sense to the
+    /// node they were negotiated with)
+    pub skipped: usize,
+    /// Messages the node sent back
+    pub replies: Vec<StacksMessage>,
+    /// Trailing reply bytes that did not decode to a whole message
+    pub undecoded_bytes: usize,
+}
+
+/// Replay captured messages to the node listening at `addr`, over a single connection, in
+/// capture order.  The records should come from a single conversation (see
+/// `CaptureFilter::conversation_id`), and are usually its inbound messages.  If `paced` is set,
+/// the original gaps between messages are reproduced.  Once every message is sent, replies are
+/// collected until `reply_timeout_ms` passes without more data or the node hangs up.
+///
+/// Messages are sent as captured, so the node will judge them against its own chain view; this
+/// is meant for driving a node in a test harness that is set up to match the capture.
+pub fn replay_capture(
+    records: &[CaptureRecord],
+    addr: &SocketAddr,
+    paced: bool,
+    reply_timeout_ms: u64,
+) -> Result<ReplayReport, net_error> {
+    let mut sock = TcpStream::connect(addr).map_err(|e| {
+        warn!("Failed to connect to {}: {:?}", addr, &e);
+        net_error::ConnectionError
+    })?;
+    sock.set_nodelay(true).map_err(net_error::WriteError)?;
+
+    let mut report = ReplayReport::default();
+    let mut last_timestamp_ms = None;
+    for record in records.iter() {
+        if let Ok(msg) = record.decode_message() {
+            if let StacksMessageType::SessionKey(_) = msg.payload {
+                report.skipped += 1;
+                continue;
+            }
+        }
+        if paced {
+            if let Some(last_ms) = last_timestamp_ms {
+                let gap_ms = record.timestamp_ms.saturating_sub(last_ms);
+                if gap_ms > 0 {
+                    thread::sleep(Duration::from_millis(gap_ms));
+                }
+            }
+            last_timestamp_ms = Some(record.timestamp_ms);
+        }
+        sock.write_all(&record.message_bytes)
+            .map_err(net_error::WriteError)?;
+        report.messages_sent += 1;
+    }
+    sock.flush().map_err(net_error::WriteError)?;
+
+    sock.set_read_timeout(Some(Duration::from_millis(reply_timeout_ms.max(1))))
+        .map_err(net_error::ReadError)?;
+    let mut reply_bytes = vec![];
+    let mut buf = [0u8; 65536];
+    loop {
+        match sock.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => reply_bytes.extend_from_slice(&buf[0..n]),
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                break;
+            }
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => break,
+            Err(e) => return Err(net_error::ReadError(e)),
+        }
+    }
+
+    let mut cursor = &reply_bytes[..];
+    while !cursor.is_empty() {
+        let mut attempt = cursor;
+        match read_next::<StacksMessage, _>(&mut attempt) {
+            Ok(msg) => {
+                report.replies.push(msg);
+                cursor = attempt;
+            }
+            Err(_) => break,
+        }
+    }
+    report.undecoded_bytes = cursor.len();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+
+    use stacks_common::types::chainstate::BurnchainHeaderHash;
+    use stacks_common::util::secp256k1::Secp256k1PrivateKey;
+
+    use super::*;
+    use crate::net::PingData;
+
+    fn make_ping(seq: u32) -> StacksMessage {
+        let mut msg = StacksMessage::new(
+            0x18000000,
+            0x80000000,
+            12345,
+            &BurnchainHeaderHash([0x11; 32]),
+            12339,
+            &BurnchainHeaderHash([0x22; 32]),
+            StacksMessageType::Ping(PingData { nonce: seq }),
+        );
+        msg.sign(seq, &Secp256k1PrivateKey::new()).unwrap();
+        msg
+    }
+
+    fn make_peer(port: u16) -> NeighborAddress {
+        NeighborAddress {
+            addrbytes: PeerAddress::from_ipv4(127, 0, 0, 1),
+            port,
+            public_key_hash: Hash160([port as u8; 20]),
+        }
+    }
+
+    fn test_path(name: &str) -> String {
+        let path = format!("/tmp/stacks-node-tests/capture/{}", name);
+        if fs::metadata(&path).is_ok() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+        fs::create_dir_all(&path).unwrap();
+        format!("{}/capture", &path)
+    }
+
+    #[test]
+    fn capture_roundtrip_and_rotate() {
+        let path = test_path("capture_roundtrip_and_rotate");
+        let record_len = {
+            let mut bytes = vec![];
+            make_ping(0).consensus_serialize(&mut bytes).unwrap();
+            bytes.len() as u64 + 4 + CAPTURE_RECORD_HEADER_LEN as u64
+        };
+
+        // room for three records per file
+        let capture = MessageCapture::open(&path, 8 + 3 * record_len, 3).unwrap();
+        let convo_1 = capture.new_conversation();
+        let convo_2 = capture.new_conversation();
+        assert_eq!(convo_1.conversation_id(), 0);
+        assert_eq!(convo_2.conversation_id(), 1);
+
+        let mut sent = vec![];
+        for i in 0..10 {
+            let msg = make_ping(i);
+            let (convo, direction, peer) = if i % 2 == 0 {
+                (&convo_1, CaptureDirection::Inbound, make_peer(20000))
+            } else {
+                (&convo_2, CaptureDirection::Outbound, make_peer(20001))
+            };
+            convo.record(direction, &peer, &msg);
+            sent.push((convo.conversation_id(), direction, peer, msg));
+        }
+
+        // 10 records at 3 per file: the current file has 1, and .1 and .2 have 3 each.  The
+        // oldest 3 were rotated out.
+        assert!(fs::metadata(&format!("{}.3", &path)).is_err());
+        let mut records = vec![];
+        for p in [format!("{}.2", &path), format!("{}.1", &path), path.clone()] {
+            records.append(&mut read_capture_file(&p).unwrap());
+        }
+        assert_eq!(records.len(), 7);
+        for (record, (conversation_id, direction, peer, msg)) in
+            records.iter().zip(sent[3..].iter())
+        {
+            assert_eq!(record.conversation_id, *conversation_id);
+            assert_eq!(record.direction, *direction);
+            assert_eq!(record.peer, *peer);
+            assert_eq!(record.decode_message().unwrap(), *msg);
+        }
+
+        // a truncated tail is dropped
+        let mut bytes = fs::read(&format!("{}.1", &path)).unwrap();
+        bytes.truncate(bytes.len() - 5);
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(read_capture_file(&path).unwrap().len(), 2);
+
+        // reopening rotates the old capture away
+        let _capture = MessageCapture::open(&path, 1024, 3).unwrap();
+        assert_eq!(read_capture_file(&path).unwrap().len(), 0);
+        assert_eq!(read_capture_file(&format!("{}.1", &path)).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn capture_filter() {
+        let records: Vec<_> = (0..6)
+            .map(|i| {
+                let mut message_bytes = vec![];
+                let msg = if i < 3 {
+                    make_ping(i)
+                } else {
+                    let mut msg = StacksMessage::new(
+                        0x18000000,
+                        0x80000000,
+                        12345,
+                        &BurnchainHeaderHash([0x11; 32]),
+                        12339,
+                        &BurnchainHeaderHash([0x22; 32]),
+                        StacksMessageType::GetNeighbors,
+                    );
+                    msg.sign(i, &Secp256k1PrivateKey::new()).unwrap();
+                    msg
+                };
+                msg.consensus_serialize(&mut message_bytes).unwrap();
+                CaptureRecord {
+                    timestamp_ms: 1000 + (i as u64) * 100,
+                    conversation_id: (i % 2) as u64,
+                    direction: if i % 3 == 0 {
+                        CaptureDirection::Outbound
+                    } else {
+                        CaptureDirection::Inbound
+                    },
+                    peer: make_peer(20000 + (i % 2) as u16),
+                    message_bytes,
+                }
+            })
+            .collect();
+
+        let count = |filter: CaptureFilter| filter.apply(records.clone()).len();
+        assert_eq!(count(CaptureFilter::default()), 6);
+        assert_eq!(
+            count(CaptureFilter {
+                direction: Some(CaptureDirection::Outbound),
+                ..CaptureFilter::default()
+            }),
+            2
+        );
+        assert_eq!(
+            count(CaptureFilter {
+                conversation_id: Some(1),
+                ..CaptureFilter::default()
+            }),
+            3
+        );
+        assert_eq!(
+            count(CaptureFilter {
+                peer: Some((PeerAddress::from_ipv4(127, 0, 0, 1), 20000)),
+                ..CaptureFilter::default()
+            }),
+            3
+        );
+        assert_eq!(
+            count(CaptureFilter {
+                peer_key_hash: Some(Hash160([(20001u16 as u8); 20])),
+                ..CaptureFilter::default()
+            }),
+            3
+        );
+        assert_eq!(
+            count(CaptureFilter {
+                message_names: vec!["GetNeighbors".to_string()],
+                ..CaptureFilter::default()
+            }),
+            3
+        );
+        assert_eq!(
+            count(CaptureFilter {
+                since_ms: Some(1100),
+                until_ms: Some(1300),
+                direction: Some(CaptureDirection::Inbound),
+                ..CaptureFilter::default()
+            }),
+            2
+        );
+    }
+
+    #[test]
+    fn capture_replay() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let pong = make_ping(99);
+
+        let mut pong_bytes = vec![];
+        pong.consensus_serialize(&mut pong_bytes).unwrap();
+        let server = thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut received = vec![];
+            for _ in 0..2 {
+                let msg: StacksMessage = read_next(&mut sock).unwrap();
+                received.push(msg);
+            }
+            sock.write_all(&pong_bytes).unwrap();
+            // a partial message at the end
+            sock.write_all(&pong_bytes[0..10]).unwrap();
+            received
+        });
+
+        let records: Vec<_> = (0..2)
+            .map(|i| {
+                let mut message_bytes = vec![];
+                make_ping(i)
+                    .consensus_serialize(&mut message_bytes)
+                    .unwrap();
+                CaptureRecord {
+                    timestamp_ms: 1000 + (i as u64) * 10,
+                    conversation_id: 0,
+                    direction: CaptureDirection::Inbound,
+                    peer: make_peer(20000),
+                    message_bytes,
+                }
+            })
+            .collect();
+
+        let report = replay_capture(&records, &addr, true, 1000).unwrap();
+        let received = server.join().unwrap();
+
+        assert_eq!(report.messages_sent, 2);
+        assert_eq!(report.skipped, 0);
+        assert_eq!(report.replies, vec![pong]);
+        assert_eq!(report.undecoded_bytes, 10);
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0], records[0].decode_message().unwrap());
+        assert_eq!(received[1], records[1].decode_message().unwrap());
+    }
+}
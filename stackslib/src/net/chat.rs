@@ -34,16 +34,20 @@ use crate::burnchains::{Burnchain, BurnchainView, PublicKey};
 use crate::chainstate::burn::db::sortdb;
 use crate::chainstate::burn::db::sortdb::{BlockHeaderCache, SortitionDB};
 use crate::chainstate::stacks::db::StacksChainState;
-use crate::chainstate::stacks::StacksPublicKey;
+use crate::chainstate::stacks::{Error as chainstate_error, StacksPublicKey};
 use crate::core::{StacksEpoch, PEER_VERSION_EPOCH_2_2, PEER_VERSION_EPOCH_2_3};
 use crate::monitoring;
 use crate::net::asn::ASEntry4;
+use crate::net::capture::{CaptureDirection, ConversationCapture};
 use crate::net::codec::*;
 use crate::net::connection::{ConnectionOptions, ConnectionP2P, ReplyHandleP2P};
 use crate::net::db::{PeerDB, *};
+use crate::net::encryption::SessionCipher;
 use crate::net::neighbors::MAX_NEIGHBOR_BLOCK_DELAY;
 use crate::net::p2p::PeerNetwork;
+use crate::net::ratelimit::{BandwidthClass, BandwidthLimiter};
 use crate::net::relay::*;
+use crate::net::reputation::ReputationEvent;
 use crate::net::stackerdb::StackerDBs;
 use crate::net::{
     Error as net_error, GetBlocksInv, GetPoxInv, Neighbor, NeighborKey, StacksMessage, StacksP2P,
@@ -129,6 +133,8 @@ pub struct NeighborStats {
     /// (timestamp, num bytes)
     pub stackerdb_push_rx_counts: VecDeque<(u64, u64)>,
     pub relayed_messages: HashMap<NeighborAddress, RelayStats>,
+    /// reputation points earned or lost since they were last written to the peer DB
+    pub reputation_delta: i64,
 }
 
 impl NeighborStats {
@@ -153,6 +159,7 @@ impl NeighborStats {
             transaction_push_rx_counts: VecDeque::new(),
             stackerdb_push_rx_counts: VecDeque::new(),
             relayed_messages: HashMap::new(),
+            reputation_delta: 0,
         }
     }
 
@@ -170,6 +177,16 @@ impl NeighborStats {
         }
     }
 
+    /// Tally a reputation event for this peer
+    pub fn add_reputation_event(&mut self, event: ReputationEvent) -> () {
+        self.reputation_delta = self.reputation_delta.saturating_add(event.points());
+    }
+
+    /// Take the reputation points tallied since the last call
+    pub fn take_reputation_delta(&mut self) -> i64 {
+        mem::replace(&mut self.reputation_delta, 0)
+    }
+
     /// Record that we recently received a block of the given size.
     /// Keeps track of the last `NUM_BANDWIDTH_POINTS` such events, so we can estimate the current
     /// bandwidth consumed by block pushes.
@@ -366,6 +383,9 @@ pub struct ConversationP2P {
 
     /// system epochs
     epochs: Vec<StacksEpoch>,
+
+    /// if set, records every message sent and received
+    message_capture: Option<ConversationCapture>,
 }
 
 impl fmt::Display for ConversationP2P {
@@ -575,6 +595,8 @@ impl ConversationP2P {
             db_smart_contracts: vec![],
 
             epochs: epochs,
+
+            message_capture: None,
         }
     }
 
@@ -582,6 +604,38 @@ impl ConversationP2P {
         self.connection.set_public_key(pubkey_opt);
     }
 
+    /// Set our private key, with which the remote peer may begin an encrypted session with us.
+    /// Only set if we advertise `ServiceFlags::ENCRYPTION`.
+    pub fn set_local_private_key(&mut self, privkey_opt: Option<Secp256k1PrivateKey>) -> () {
+        self.connection.set_local_private_key(privkey_opt);
+    }
+
+    /// Record this conversation's messages to the given capture
+    pub fn set_message_capture(&mut self, capture_opt: Option<ConversationCapture>) -> () {
+        self.message_capture = capture_opt;
+    }
+
+    /// Limit how fast this conversation may receive and send data
+    pub fn set_bandwidth_limiters(
+        &mut self,
+        inbound: BandwidthLimiter,
+        outbound: BandwidthLimiter,
+    ) -> () {
+        self.connection.set_bandwidth_limiters(inbound, outbound);
+    }
+
+    /// Did the last recv() or send() stop early because we ran out of bandwidth?
+    pub fn is_throttled(&self) -> bool {
+        self.connection.is_throttled()
+    }
+
+    /// Record a message to the capture, if we have one
+    fn capture_message(&self, direction: CaptureDirection, msg: &StacksMessage) {
+        if let Some(capture) = self.message_capture.as_ref() {
+            capture.record(direction, &self.to_neighbor_address(), msg);
+        }
+    }
+
     pub fn to_neighbor_key(&self) -> NeighborKey {
         NeighborKey {
             peer_version: self.peer_version,
@@ -649,6 +703,16 @@ impl ConversationP2P {
         self.connection.ref_public_key()
     }
 
+    /// Is the remote peer's traffic to us encrypted?
+    pub fn is_inbound_encrypted(&self) -> bool {
+        self.connection.is_inbound_encrypted()
+    }
+
+    /// Is our traffic to the remote peer encrypted (or about to be)?
+    pub fn is_outbound_encrypted(&self) -> bool {
+        self.connection.is_outbound_encrypted()
+    }
+
     pub fn get_burnchain_tip_height(&self) -> u64 {
         self.burnchain_tip_height
     }
@@ -677,6 +741,24 @@ impl ConversationP2P {
         (peer_services & (ServiceFlags::STACKERDB as u16)) != 0
     }
 
+    /// Does the given services bitfield support encrypted sessions?  It will if it has the
+    /// ENCRYPTION bit set
+    pub fn supports_encryption(peer_services: u16) -> bool {
+        (peer_services & (ServiceFlags::ENCRYPTION as u16)) != 0
+    }
+
+    /// Does the given services bitfield support compact blocks?  It will if it has the
+    /// COMPACT_BLOCKS bit set
+    pub fn supports_compact_blocks(peer_services: u16) -> bool {
+        (peer_services & (ServiceFlags::COMPACT_BLOCKS as u16)) != 0
+    }
+
+    /// Does the given services bitfield support transaction announcements?  It will if it has the
+    /// TX_ANNOUNCE bit set
+    pub fn supports_tx_announce(peer_services: u16) -> bool {
+        (peer_services & (ServiceFlags::TX_ANNOUNCE as u16)) != 0
+    }
+
     /// Does this remote neighbor support a particular StackerDB?
     pub fn replicates_stackerdb(&self, db: &QualifiedContractIdentifier) -> bool {
         for cid in self.db_smart_contracts.iter() {
@@ -985,9 +1067,11 @@ impl ConversationP2P {
         let _name = msg.payload.get_message_description();
         let _seq = msg.request_id();
 
-        let mut handle = self.connection.make_relay_handle(self.conn_id)?;
+        let class = BandwidthClass::of_p2p_message(&msg.payload);
+        let mut handle = self.connection.make_relay_handle(self.conn_id, class)?;
         let buf = msg.serialize_to_vec();
         handle.write_all(&buf).map_err(net_error::WriteError)?;
+        self.capture_message(CaptureDirection::Outbound, &msg);
 
         self.stats.msgs_tx += 1;
 
@@ -1009,11 +1093,13 @@ impl ConversationP2P {
         let _name = msg.get_message_name();
         let _seq = msg.request_id();
 
+        let class = BandwidthClass::of_p2p_message(&msg.payload);
         let mut handle =
             self.connection
-                .make_request_handle(msg.request_id(), ttl, self.conn_id)?;
+                .make_request_handle(msg.request_id(), ttl, self.conn_id, class)?;
         let buf = msg.serialize_to_vec();
         handle.write_all(&buf).map_err(net_error::WriteError)?;
+        self.capture_message(CaptureDirection::Outbound, &msg);
 
         self.stats.msgs_tx += 1;
 
@@ -1148,6 +1234,64 @@ impl ConversationP2P {
         Ok(updated)
     }
 
+    /// Begin our encrypted session with the remote peer, if we both support it and we haven't
+    /// already.  We can only do this once we know the remote peer's public key, so this happens
+    /// right after the handshake.  The `SessionKey` message is queued behind any handshake reply,
+    /// and everything queued after it is encrypted.
+    fn try_begin_session(
+        &mut self,
+        local_peer: &LocalPeer,
+        chain_view: &BurnchainView,
+    ) -> Result<(), net_error> {
+        if !ConversationP2P::supports_encryption(local_peer.services)
+            || !ConversationP2P::supports_encryption(self.peer_services)
+            || self.connection.is_outbound_encrypted()
+        {
+            return Ok(());
+        }
+        let Some(remote_public_key) = self.connection.get_public_key() else {
+            return Ok(());
+        };
+
+        let (session_key_data, session) =
+            SessionCipher::begin(&local_peer.private_key, &remote_public_key)?;
+        let msg = self.sign_message(
+            chain_view,
+            &local_peer.private_key,
+            StacksMessageType::SessionKey(session_key_data),
+        )?;
+
+        let mut handle = self.connection.make_session_handle(self.conn_id, session)?;
+        let buf = msg.serialize_to_vec();
+        handle.write_all(&buf).map_err(net_error::WriteError)?;
+        self.capture_message(CaptureDirection::Outbound, &msg);
+        self.reply_handles.push_back(handle);
+
+        self.stats.msgs_tx += 1;
+        debug!("{:?}: Begin encrypted session", &self);
+        Ok(())
+    }
+
+    /// Handle an inbound SessionKey.  The connection will have already switched over to
+    /// decrypting the rest of the remote peer's stream; make sure that the session was begun by
+    /// the node we handshaked with.
+    fn handle_session_key(&mut self, data: &SessionKeyData) -> Result<(), net_error> {
+        let Some(pubk) = self.connection.ref_public_key() else {
+            return Err(net_error::InvalidMessage);
+        };
+        if StacksPublicKeyBuffer::from_public_key(pubk) != data.static_public_key {
+            debug!(
+                "{:?}: Encrypted session begun by {}, not by handshaked key {}",
+                &self,
+                &data.static_public_key.to_hex(),
+                &to_hex(&pubk.to_bytes_compressed())
+            );
+            return Err(net_error::InvalidMessage);
+        }
+        debug!("{:?}: Remote peer began encrypted session", &self);
+        Ok(())
+    }
+
     /// Update connection state from stacker DB handshake data.
     /// Just synchronizes the announced smart contracts for which this node replicates data.
     pub fn update_from_stacker_db_handshake_data(
@@ -1656,6 +1800,72 @@ impl ConversationP2P {
         )
     }
 
+    /// Create a response to an inbound GetBlockTxs request, but unsigned.
+    /// Replies with the requested transactions from the block, or a NACK if we don't have the
+    /// block or the request doesn't fit it.
+    pub fn make_getblocktxs_response(
+        chainstate: &StacksChainState,
+        getblocktxs: &GetBlockTxsData,
+    ) -> Result<StacksMessageType, net_error> {
+        let block = match StacksChainState::load_block(
+            &chainstate.blocks_path,
+            &getblocktxs.consensus_hash,
+            &getblocktxs.block_hash,
+        ) {
+            Ok(Some(block)) => block,
+            Ok(None) | Err(chainstate_error::DBError(db_error::NotFoundError)) => {
+                debug!(
+                    "No such block {}/{}",
+                    &getblocktxs.consensus_hash, &getblocktxs.block_hash
+                );
+                return Ok(StacksMessageType::Nack(NackData::new(
+                    NackErrorCodes::InvalidMessage,
+                )));
+            }
+            Err(e) => {
+                return Err(net_error::from(e));
+            }
+        };
+
+        let mut txs = Vec::with_capacity(getblocktxs.indexes.len());
+        for index in getblocktxs.indexes.iter() {
+            let Some(tx) = block.txs.get(*index as usize) else {
+                debug!(
+                    "Block {}/{} has no transaction {}",
+                    &getblocktxs.consensus_hash, &getblocktxs.block_hash, index
+                );
+                return Ok(StacksMessageType::Nack(NackData::new(
+                    NackErrorCodes::InvalidMessage,
+                )));
+            };
+            txs.push(tx.clone());
+        }
+
+        Ok(StacksMessageType::BlockTxs(BlockTxsData {
+            consensus_hash: getblocktxs.consensus_hash.clone(),
+            block_hash: getblocktxs.block_hash.clone(),
+            txs,
+        }))
+    }
+
+    /// Handle an inbound GetBlockTxs request.
+    /// Returns a reply handle to the generated message (possibly a nack)
+    fn handle_getblocktxs(
+        &mut self,
+        network: &PeerNetwork,
+        chainstate: &StacksChainState,
+        preamble: &Preamble,
+        getblocktxs: &GetBlockTxsData,
+    ) -> Result<ReplyHandleP2P, net_error> {
+        let response = ConversationP2P::make_getblocktxs_response(chainstate, getblocktxs)?;
+        self.sign_and_reply(
+            network.get_local_peer(),
+            network.get_chain_view(),
+            preamble,
+            response,
+        )
+    }
+
     /// Create a response an inbound GetPoxInv request, but unsigned.
     /// Returns a reply handle to the generated message (possibly a nack)
     pub fn make_getpoxinv_response(
@@ -2106,6 +2316,19 @@ impl ConversationP2P {
             StacksMessageType::GetBlocksInv(ref get_blocks_inv) => {
                 self.handle_getblocksinv(network, sortdb, chainstate, &msg.preamble, get_blocks_inv)
             }
+            StacksMessageType::GetBlockTxs(ref getblocktxs) => {
+                self.handle_getblocktxs(network, chainstate, &msg.preamble, getblocktxs)
+            }
+            StacksMessageType::CompactBlock(_) | StacksMessageType::BlockTxs(_) => {
+                // not handled here, but account for them like pushed blocks
+                match self.validate_blocks_push(network, &msg.preamble, msg.relayers.clone())? {
+                    Some(handle) => Ok(handle),
+                    None => {
+                        // will forward upstream
+                        return Ok(Some(msg));
+                    }
+                }
+            }
             StacksMessageType::Blocks(_) => {
                 monitoring::increment_stx_blocks_received_counter();
 
@@ -2300,6 +2523,8 @@ impl ConversationP2P {
                     );
                     self.stats.msgs_err += 1;
                     self.stats.add_healthpoint(false);
+                    self.stats
+                        .add_reputation_event(ReputationEvent::InvalidMessage);
                     return Err(e);
                 }
                 _ => {
@@ -2359,6 +2584,11 @@ impl ConversationP2P {
                 test_debug!("{:?}: Got Pong", &self);
                 Ok(None)
             }
+            StacksMessageType::SessionKey(ref data) => {
+                test_debug!("{:?}: Got SessionKey", &self);
+                consume = true;
+                self.handle_session_key(data).and_then(|_| Ok(None))
+            }
             StacksMessageType::NatPunchRequest(ref nonce) => {
                 if cfg!(test) && self.connection.options.disable_natpunch {
                     return Err(net_error::InvalidMessage);
@@ -2449,6 +2679,12 @@ impl ConversationP2P {
                     Ok(None)
                 }
             }
+            StacksMessageType::SessionKey(_) => {
+                // the connection is now decrypting a session from a peer we haven't handshaked
+                // with, so it can't continue.
+                debug!("{:?}: Got unauthenticated SessionKey", &self);
+                return Err(net_error::InvalidMessage);
+            }
             StacksMessageType::HandshakeReject => {
                 test_debug!("{:?}: Got unauthenticated HandshakeReject", &self);
 
@@ -2585,6 +2821,7 @@ impl ConversationP2P {
                 }
                 Some(m) => m,
             };
+            self.capture_message(CaptureDirection::Inbound, &msg);
 
             if !self.validate_inbound_message(&msg, network.get_chain_view())? {
                 continue;
@@ -2617,6 +2854,10 @@ impl ConversationP2P {
                 self.reply_handles.push_back(reply_handle);
             }
 
+            // if this message completed a handshake, then we may now encrypt the rest of the
+            // conversation
+            self.try_begin_session(network.get_local_peer(), network.get_chain_view())?;
+
             self.update_stats(&msg, update_stats);
 
             let _msgtype = msg.payload.get_message_description().to_owned();
@@ -2678,6 +2919,7 @@ impl ConversationP2P {
         let num_drained = self.connection.drain_timeouts();
         for _ in 0..num_drained {
             self.stats.add_healthpoint(false);
+            self.stats.add_reputation_event(ReputationEvent::Timeout);
         }
     }
 
@@ -2716,6 +2958,7 @@ mod test {
     use crate::chainstate::*;
     use crate::core::*;
     use crate::net::atlas::{AtlasConfig, AtlasDB};
+    use crate::net::capture::{read_capture_file, MessageCapture};
     use crate::net::connection::*;
     use crate::net::db::*;
     use crate::net::p2p::*;
@@ -4457,6 +4700,412 @@ mod test {
         }
     }
 
+    #[test]
+    fn convo_ping_capture() {
+        let conn_opts = ConnectionOptions::default();
+        let socketaddr_1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+        let socketaddr_2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)), 8081);
+
+        let first_burn_hash = BurnchainHeaderHash::from_hex(
+            "0000000000000000000000000000000000000000000000000000000000000000",
+        )
+        .unwrap();
+
+        let burnchain = testing_burnchain_config();
+
+        let mut chain_view = BurnchainView {
+            burn_block_height: 12348,
+            burn_block_hash: BurnchainHeaderHash([0x11; 32]),
+            burn_stable_block_height: 12341,
+            burn_stable_block_hash: BurnchainHeaderHash([0x22; 32]),
+            last_burn_block_hashes: HashMap::new(),
+            rc_consensus_hash: ConsensusHash([0x33; 20]),
+        };
+        chain_view.make_test_data();
+
+        let first_burn_hash = BurnchainHeaderHash::from_hex(
+            "0000000000000000000000000000000000000000000000000000000000000000",
+        )
+        .unwrap();
+
+        let test_name_1 = "convo_ping_capture_1";
+        let test_name_2 = "convo_ping_capture_2";
+
+        let (mut peerdb_1, mut sortdb_1, stackerdbs_1, pox_id_1, mut chainstate_1) =
+            make_test_chain_dbs(
+                test_name_1,
+                &burnchain,
+                0x9abcdef0,
+                12350,
+                "http://peer1.com".into(),
+                &vec![],
+                &vec![],
+                DEFAULT_SERVICES,
+            );
+        let (mut peerdb_2, mut sortdb_2, stackerdbs_2, pox_id_2, mut chainstate_2) =
+            make_test_chain_dbs(
+                test_name_2,
+                &burnchain,
+                0x9abcdef0,
+                12351,
+                "http://peer2.com".into(),
+                &vec![],
+                &vec![],
+                DEFAULT_SERVICES,
+            );
+
+        let mut net_1 = db_setup(
+            &test_name_1,
+            &burnchain,
+            0x9abcdef0,
+            &mut peerdb_1,
+            &mut sortdb_1,
+            &socketaddr_1,
+            &chain_view,
+        );
+        let mut net_2 = db_setup(
+            &test_name_2,
+            &burnchain,
+            0x9abcdef0,
+            &mut peerdb_2,
+            &mut sortdb_2,
+            &socketaddr_2,
+            &chain_view,
+        );
+
+        let local_peer_1 = PeerDB::get_local_peer(&peerdb_1.conn()).unwrap();
+        let local_peer_2 = PeerDB::get_local_peer(&peerdb_2.conn()).unwrap();
+
+        let mut convo_1 = ConversationP2P::new(
+            123,
+            456,
+            &burnchain,
+            &socketaddr_2,
+            &conn_opts,
+            true,
+            0,
+            StacksEpoch::unit_test_pre_2_05(0),
+        );
+        let mut convo_2 = ConversationP2P::new(
+            123,
+            456,
+            &burnchain,
+            &socketaddr_1,
+            &conn_opts,
+            true,
+            0,
+            StacksEpoch::unit_test_pre_2_05(0),
+        );
+
+        let capture_path = "/tmp/stacks-node-tests/convo_ping_capture.capture";
+        let capture = MessageCapture::open(capture_path, 1024 * 1024, 1).unwrap();
+        convo_1.set_message_capture(Some(capture.new_conversation()));
+        convo_2.set_message_capture(Some(capture.new_conversation()));
+
+        // convo_1 sends a handshake to convo_2
+        let handshake_data_1 = HandshakeData::from_local_peer(&local_peer_1);
+        let handshake_1 = convo_1
+            .sign_message(
+                &chain_view,
+                &local_peer_1.private_key,
+                StacksMessageType::Handshake(handshake_data_1.clone()),
+            )
+            .unwrap();
+        let mut rh_handshake_1 = convo_1
+            .send_signed_request(handshake_1.clone(), 1000000)
+            .unwrap();
+
+        // convo_1 sends a ping to convo_2
+        let ping_data_1 = PingData::new();
+        let ping_1 = convo_1
+            .sign_message(
+                &chain_view,
+                &local_peer_1.private_key,
+                StacksMessageType::Ping(ping_data_1.clone()),
+            )
+            .unwrap();
+        let mut rh_ping_1 = convo_1
+            .send_signed_request(ping_1.clone(), 1000000)
+            .unwrap();
+
+        // convo_2 receives the handshake and ping and processes both, and since no one is waiting for the handshake, will forward
+        // it along to the chat caller (us)
+        test_debug!("send handshake {:?}", &handshake_1);
+        test_debug!("send ping {:?}", &ping_1);
+        convo_send_recv(
+            &mut convo_1,
+            vec![&mut rh_handshake_1, &mut rh_ping_1],
+            &mut convo_2,
+        );
+        let unhandled_2 = convo_2
+            .chat(&mut net_2, &sortdb_2, &mut chainstate_2)
+            .unwrap();
+
+        // convo_1 has a handshakeaccept
+        test_debug!("reply handshake-accept");
+        test_debug!("send pong");
+        convo_send_recv(
+            &mut convo_2,
+            vec![&mut rh_handshake_1, &mut rh_ping_1],
+            &mut convo_1,
+        );
+        let unhandled_1 = convo_1
+            .chat(&mut net_1, &sortdb_1, &mut chainstate_1)
+            .unwrap();
+
+        let reply_handshake_1 = rh_handshake_1.recv(0).unwrap();
+        let reply_ping_1 = rh_ping_1.recv(0).unwrap();
+
+        assert_eq!(unhandled_1.len(), 0);
+        assert_eq!(unhandled_2.len(), 1); // only the handshake is given back.  the ping is consumed
+
+        // convo 2 returns the handshake from convo 1
+        match unhandled_2[0].payload {
+            StacksMessageType::Handshake(ref data) => {
+                assert_eq!(handshake_data_1, *data);
+            }
+            _ => {
+                assert!(false);
+            }
+        };
+
+        // convo 2 replied to convo 1 with a matching pong
+        match reply_ping_1.payload {
+            StacksMessageType::Pong(ref data) => {
+                assert_eq!(data.nonce, ping_data_1.nonce);
+            }
+            _ => {
+                assert!(false);
+            }
+        }
+
+        // each side recorded what it sent and received
+        let records = read_capture_file(capture_path).unwrap();
+        let summary: Vec<_> = records
+            .iter()
+            .map(|record| {
+                (
+                    record.conversation_id,
+                    record.direction,
+                    record.decode_message().unwrap().payload.get_message_name(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (0, CaptureDirection::Outbound, "Handshake"),
+                (0, CaptureDirection::Outbound, "Ping"),
+                (1, CaptureDirection::Inbound, "Handshake"),
+                (1, CaptureDirection::Outbound, "HandshakeAccept"),
+                (1, CaptureDirection::Inbound, "Ping"),
+                (1, CaptureDirection::Outbound, "Pong"),
+                (0, CaptureDirection::Inbound, "HandshakeAccept"),
+                (0, CaptureDirection::Inbound, "Pong"),
+            ]
+        );
+        assert_eq!(records[0].peer.port, socketaddr_2.port());
+        assert_eq!(records[2].peer.port, socketaddr_1.port());
+    }
+
+    #[test]
+    fn convo_handshake_encrypted_ping() {
+        let conn_opts = ConnectionOptions::default();
+        let socketaddr_1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+        let socketaddr_2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)), 8081);
+
+        let burnchain = testing_burnchain_config();
+
+        let mut chain_view = BurnchainView {
+            burn_block_height: 12348,
+            burn_block_hash: BurnchainHeaderHash([0x11; 32]),
+            burn_stable_block_height: 12341,
+            burn_stable_block_hash: BurnchainHeaderHash([0x22; 32]),
+            last_burn_block_hashes: HashMap::new(),
+            rc_consensus_hash: ConsensusHash([0x33; 20]),
+        };
+        chain_view.make_test_data();
+
+        let test_name_1 = "convo_handshake_encrypted_ping_1";
+        let test_name_2 = "convo_handshake_encrypted_ping_2";
+
+        let services = DEFAULT_SERVICES | (ServiceFlags::ENCRYPTION as u16);
+        let (mut peerdb_1, mut sortdb_1, stackerdbs_1, pox_id_1, mut chainstate_1) =
+            make_test_chain_dbs(
+                test_name_1,
+                &burnchain,
+                0x9abcdef0,
+                12350,
+                "http://peer1.com".into(),
+                &vec![],
+                &vec![],
+                services,
+            );
+        let (mut peerdb_2, mut sortdb_2, stackerdbs_2, pox_id_2, mut chainstate_2) =
+            make_test_chain_dbs(
+                test_name_2,
+                &burnchain,
+                0x9abcdef0,
+                12351,
+                "http://peer2.com".into(),
+                &vec![],
+                &vec![],
+                services,
+            );
+
+        let mut net_1 = db_setup(
+            &test_name_1,
+            &burnchain,
+            0x9abcdef0,
+            &mut peerdb_1,
+            &mut sortdb_1,
+            &socketaddr_1,
+            &chain_view,
+        );
+        let mut net_2 = db_setup(
+            &test_name_2,
+            &burnchain,
+            0x9abcdef0,
+            &mut peerdb_2,
+            &mut sortdb_2,
+            &socketaddr_2,
+            &chain_view,
+        );
+
+        let local_peer_1 = PeerDB::get_local_peer(&peerdb_1.conn()).unwrap();
+        let local_peer_2 = PeerDB::get_local_peer(&peerdb_2.conn()).unwrap();
+
+        let mut convo_1 = ConversationP2P::new(
+            123,
+            456,
+            &burnchain,
+            &socketaddr_2,
+            &conn_opts,
+            true,
+            0,
+            StacksEpoch::unit_test_pre_2_05(0),
+        );
+        let mut convo_2 = ConversationP2P::new(
+            123,
+            456,
+            &burnchain,
+            &socketaddr_1,
+            &conn_opts,
+            true,
+            0,
+            StacksEpoch::unit_test_pre_2_05(0),
+        );
+        convo_1.set_local_private_key(Some(local_peer_1.private_key.clone()));
+        convo_2.set_local_private_key(Some(local_peer_2.private_key.clone()));
+
+        // convo_1 sends a handshake and a ping to convo_2
+        let handshake_data_1 = HandshakeData::from_local_peer(&local_peer_1);
+        let handshake_1 = convo_1
+            .sign_message(
+                &chain_view,
+                &local_peer_1.private_key,
+                StacksMessageType::Handshake(handshake_data_1.clone()),
+            )
+            .unwrap();
+        let mut rh_handshake_1 = convo_1
+            .send_signed_request(handshake_1.clone(), 1000000)
+            .unwrap();
+
+        let ping_data_1 = PingData::new();
+        let ping_1 = convo_1
+            .sign_message(
+                &chain_view,
+                &local_peer_1.private_key,
+                StacksMessageType::Ping(ping_data_1.clone()),
+            )
+            .unwrap();
+        let mut rh_ping_1 = convo_1
+            .send_signed_request(ping_1.clone(), 1000000)
+            .unwrap();
+
+        // both sent in plaintext, since convo_1 doesn't know convo_2 supports encryption yet
+        convo_send_recv(
+            &mut convo_1,
+            vec![&mut rh_handshake_1, &mut rh_ping_1],
+            &mut convo_2,
+        );
+        assert!(!convo_1.is_outbound_encrypted());
+        assert!(!convo_2.is_inbound_encrypted());
+
+        // convo_2 accepts the handshake, begins its session, and encrypts the pong
+        convo_2
+            .chat(&mut net_2, &sortdb_2, &mut chainstate_2)
+            .unwrap();
+        assert!(convo_2.is_outbound_encrypted());
+
+        convo_send_recv(
+            &mut convo_2,
+            vec![&mut rh_handshake_1, &mut rh_ping_1],
+            &mut convo_1,
+        );
+        assert!(convo_1.is_inbound_encrypted());
+
+        // convo_1 processes the handshake-accept, the session key, and the pong, and begins its
+        // own session
+        let unhandled_1 = convo_1
+            .chat(&mut net_1, &sortdb_1, &mut chainstate_1)
+            .unwrap();
+        assert_eq!(unhandled_1.len(), 0);
+        assert!(convo_1.is_outbound_encrypted());
+
+        match rh_handshake_1.recv(0).unwrap().payload {
+            StacksMessageType::HandshakeAccept(ref data) => {
+                assert_eq!(data.handshake.services, services);
+            }
+            _ => {
+                assert!(false);
+            }
+        }
+        match rh_ping_1.recv(0).unwrap().payload {
+            StacksMessageType::Pong(ref data) => {
+                assert_eq!(data.nonce, ping_data_1.nonce);
+            }
+            _ => {
+                assert!(false);
+            }
+        }
+
+        // convo_1 sends another ping, which gets encrypted
+        let ping_data_2 = PingData::new();
+        let ping_2 = convo_1
+            .sign_message(
+                &chain_view,
+                &local_peer_1.private_key,
+                StacksMessageType::Ping(ping_data_2.clone()),
+            )
+            .unwrap();
+        let mut rh_ping_2 = convo_1
+            .send_signed_request(ping_2.clone(), 1000000)
+            .unwrap();
+
+        convo_send_recv(&mut convo_1, vec![&mut rh_ping_2], &mut convo_2);
+        assert!(convo_2.is_inbound_encrypted());
+
+        let unhandled_2 = convo_2
+            .chat(&mut net_2, &sortdb_2, &mut chainstate_2)
+            .unwrap();
+        assert_eq!(unhandled_2.len(), 0);
+
+        convo_send_recv(&mut convo_2, vec![&mut rh_ping_2], &mut convo_1);
+        convo_1
+            .chat(&mut net_1, &sortdb_1, &mut chainstate_1)
+            .unwrap();
+
+        match rh_ping_2.recv(0).unwrap().payload {
+            StacksMessageType::Pong(ref data) => {
+                assert_eq!(data.nonce, ping_data_2.nonce);
+            }
+            _ => {
+                assert!(false);
+            }
+        }
+    }
+
     #[test]
     fn convo_handshake_ping_loop() {
         let conn_opts = ConnectionOptions::default();
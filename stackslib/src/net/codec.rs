@@ -42,10 +42,14 @@ use stacks_common::util::secp256k1::{
 use crate::burnchains::{BurnchainView, PrivateKey, PublicKey};
 use crate::chainstate::burn::ConsensusHash;
 use crate::chainstate::stacks::{
-    StacksBlock, StacksMicroblock, StacksPublicKey, StacksTransaction, MAX_BLOCK_LEN,
+    StacksBlock, StacksBlockHeader, StacksMicroblock, StacksPublicKey, StacksTransaction,
+    MAX_BLOCK_LEN,
 };
+use crate::core::mempool::TxTag;
 use crate::core::PEER_VERSION_TESTNET;
 use crate::net::db::LocalPeer;
+use crate::net::encryption::SessionDecoder;
+use crate::net::ratelimit::BandwidthClass;
 use crate::net::{Error as net_error, *};
 
 impl Preamble {
@@ -843,6 +847,199 @@ impl StacksMessageCodec for StackerDBPushChunkData {
     }
 }
 
+impl StacksMessageCodec for SessionKeyData {
+    fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), codec_error> {
+        write_next(fd, &self.ephemeral_public_key)?;
+        write_next(fd, &self.static_public_key)?;
+        Ok(())
+    }
+
+    fn consensus_deserialize<R: Read>(fd: &mut R) -> Result<SessionKeyData, codec_error> {
+        let ephemeral_public_key: StacksPublicKeyBuffer = read_next(fd)?;
+        let static_public_key: StacksPublicKeyBuffer = read_next(fd)?;
+        Ok(SessionKeyData {
+            ephemeral_public_key,
+            static_public_key,
+        })
+    }
+}
+
+impl StacksMessageCodec for PrefilledTransaction {
+    fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), codec_error> {
+        write_next(fd, &self.index)?;
+        write_next(fd, &self.tx)?;
+        Ok(())
+    }
+
+    fn consensus_deserialize<R: Read>(fd: &mut R) -> Result<PrefilledTransaction, codec_error> {
+        let index: u16 = read_next(fd)?;
+        let tx: StacksTransaction = read_next(fd)?;
+        Ok(PrefilledTransaction { index, tx })
+    }
+}
+
+/// Transaction indexes in compact-block messages must be strictly increasing
+fn check_tx_indexes_increasing<I: Iterator<Item = u16>>(indexes: I) -> bool {
+    let mut last_index: Option<u16> = None;
+    for index in indexes {
+        if let Some(last_index) = last_index {
+            if index <= last_index {
+                return false;
+            }
+        }
+        last_index = Some(index);
+    }
+    true
+}
+
+impl StacksMessageCodec for CompactBlockData {
+    fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), codec_error> {
+        write_next(fd, &self.consensus_hash)?;
+        write_next(fd, &self.header)?;
+        write_next(fd, &self.tx_tags)?;
+        write_next(fd, &self.prefilled_txs)?;
+        Ok(())
+    }
+
+    fn consensus_deserialize<R: Read>(fd: &mut R) -> Result<CompactBlockData, codec_error> {
+        let consensus_hash: ConsensusHash = read_next(fd)?;
+        let header: StacksBlockHeader = read_next(fd)?;
+        let tx_tags: Vec<TxTag> = read_next_at_most(fd, COMPACT_BLOCK_MAX_TXS)?;
+        let prefilled_txs: Vec<PrefilledTransaction> = {
+            let mut bound_read = BoundReader::from_reader(fd, MAX_BLOCK_LEN as u64);
+            read_next_at_most(&mut bound_read, COMPACT_BLOCK_MAX_TXS)
+        }?;
+
+        // a block has at least one transaction (the coinbase)
+        if tx_tags.len() == 0 {
+            return Err(codec_error::DeserializeError(
+                "Invalid CompactBlockData: no transactions".to_string(),
+            ));
+        }
+
+        if !check_tx_indexes_increasing(prefilled_txs.iter().map(|ptx| ptx.index)) {
+            return Err(codec_error::DeserializeError(
+                "Invalid CompactBlockData: prefilled transactions out of order".to_string(),
+            ));
+        }
+
+        if let Some(last_ptx) = prefilled_txs.last() {
+            if (last_ptx.index as usize) >= tx_tags.len() {
+                return Err(codec_error::DeserializeError(
+                    "Invalid CompactBlockData: prefilled transaction index out of range"
+                        .to_string(),
+                ));
+            }
+        }
+
+        Ok(CompactBlockData {
+            consensus_hash,
+            header,
+            tx_tags,
+            prefilled_txs,
+        })
+    }
+}
+
+impl StacksMessageCodec for GetBlockTxsData {
+    fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), codec_error> {
+        write_next(fd, &self.consensus_hash)?;
+        write_next(fd, &self.block_hash)?;
+        write_next(fd, &self.indexes)?;
+        Ok(())
+    }
+
+    fn consensus_deserialize<R: Read>(fd: &mut R) -> Result<GetBlockTxsData, codec_error> {
+        let consensus_hash: ConsensusHash = read_next(fd)?;
+        let block_hash: BlockHeaderHash = read_next(fd)?;
+        let indexes: Vec<u16> = read_next_at_most(fd, COMPACT_BLOCK_MAX_TXS)?;
+
+        if indexes.len() == 0 {
+            return Err(codec_error::DeserializeError(
+                "Invalid GetBlockTxsData: no indexes".to_string(),
+            ));
+        }
+
+        if !check_tx_indexes_increasing(indexes.iter().copied()) {
+            return Err(codec_error::DeserializeError(
+                "Invalid GetBlockTxsData: indexes out of order".to_string(),
+            ));
+        }
+
+        Ok(GetBlockTxsData {
+            consensus_hash,
+            block_hash,
+            indexes,
+        })
+    }
+}
+
+impl StacksMessageCodec for BlockTxsData {
+    fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), codec_error> {
+        write_next(fd, &self.consensus_hash)?;
+        write_next(fd, &self.block_hash)?;
+        write_next(fd, &self.txs)?;
+        Ok(())
+    }
+
+    fn consensus_deserialize<R: Read>(fd: &mut R) -> Result<BlockTxsData, codec_error> {
+        let consensus_hash: ConsensusHash = read_next(fd)?;
+        let block_hash: BlockHeaderHash = read_next(fd)?;
+        let txs: Vec<StacksTransaction> = {
+            let mut bound_read = BoundReader::from_reader(fd, MAX_BLOCK_LEN as u64);
+            read_next_at_most(&mut bound_read, COMPACT_BLOCK_MAX_TXS)
+        }?;
+
+        Ok(BlockTxsData {
+            consensus_hash,
+            block_hash,
+            txs,
+        })
+    }
+}
+
+impl StacksMessageCodec for TxInvData {
+    fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), codec_error> {
+        write_next(fd, &self.seed)?;
+        write_next(fd, &self.tx_tags)?;
+        Ok(())
+    }
+
+    fn consensus_deserialize<R: Read>(fd: &mut R) -> Result<TxInvData, codec_error> {
+        let seed: [u8; 32] = read_next(fd)?;
+        let tx_tags: Vec<TxTag> = read_next_at_most(fd, TX_INV_MAX_TAGS)?;
+
+        if tx_tags.len() == 0 {
+            return Err(codec_error::DeserializeError(
+                "Invalid TxInvData: no transactions".to_string(),
+            ));
+        }
+
+        Ok(TxInvData { seed, tx_tags })
+    }
+}
+
+impl StacksMessageCodec for GetTxsData {
+    fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), codec_error> {
+        write_next(fd, &self.seed)?;
+        write_next(fd, &self.tx_tags)?;
+        Ok(())
+    }
+
+    fn consensus_deserialize<R: Read>(fd: &mut R) -> Result<GetTxsData, codec_error> {
+        let seed: [u8; 32] = read_next(fd)?;
+        let tx_tags: Vec<TxTag> = read_next_at_most(fd, TX_INV_MAX_TAGS)?;
+
+        if tx_tags.len() == 0 {
+            return Err(codec_error::DeserializeError(
+                "Invalid GetTxsData: no transactions".to_string(),
+            ));
+        }
+
+        Ok(GetTxsData { seed, tx_tags })
+    }
+}
+
 impl StacksMessageCodec for RelayData {
     fn consensus_serialize<W: Write>(&self, fd: &mut W) -> Result<(), codec_error> {
         write_next(fd, &self.peer)?;
@@ -891,6 +1088,12 @@ impl StacksMessageType {
             StacksMessageType::StackerDBGetChunk(ref _m) => StacksMessageID::StackerDBGetChunk,
             StacksMessageType::StackerDBChunk(ref _m) => StacksMessageID::StackerDBChunk,
             StacksMessageType::StackerDBPushChunk(ref _m) => StacksMessageID::StackerDBPushChunk,
+            StacksMessageType::SessionKey(ref _m) => StacksMessageID::SessionKey,
+            StacksMessageType::CompactBlock(ref _m) => StacksMessageID::CompactBlock,
+            StacksMessageType::GetBlockTxs(ref _m) => StacksMessageID::GetBlockTxs,
+            StacksMessageType::BlockTxs(ref _m) => StacksMessageID::BlockTxs,
+            StacksMessageType::TxInv(ref _m) => StacksMessageID::TxInv,
+            StacksMessageType::GetTxs(ref _m) => StacksMessageID::GetTxs,
         }
     }
 
@@ -923,6 +1126,12 @@ impl StacksMessageType {
             StacksMessageType::StackerDBGetChunk(ref _m) => "StackerDBGetChunk",
             StacksMessageType::StackerDBChunk(ref _m) => "StackerDBChunk",
             StacksMessageType::StackerDBPushChunk(ref _m) => "StackerDBPushChunk",
+            StacksMessageType::SessionKey(ref _m) => "SessionKey",
+            StacksMessageType::CompactBlock(ref _m) => "CompactBlock",
+            StacksMessageType::GetBlockTxs(ref _m) => "GetBlockTxs",
+            StacksMessageType::BlockTxs(ref _m) => "BlockTxs",
+            StacksMessageType::TxInv(ref _m) => "TxInv",
+            StacksMessageType::GetTxs(ref _m) => "GetTxs",
         }
     }
 
@@ -1024,6 +1233,44 @@ impl StacksMessageType {
                     m.chunk_data.data.len()
                 )
             }
+            StacksMessageType::SessionKey(ref m) => {
+                format!(
+                    "SessionKey({},{})",
+                    &m.ephemeral_public_key.to_hex(),
+                    &m.static_public_key.to_hex()
+                )
+            }
+            StacksMessageType::CompactBlock(ref m) => {
+                format!(
+                    "CompactBlock({},{},{} txs,{} prefilled)",
+                    &m.consensus_hash,
+                    &m.header.block_hash(),
+                    m.tx_tags.len(),
+                    m.prefilled_txs.len()
+                )
+            }
+            StacksMessageType::GetBlockTxs(ref m) => {
+                format!(
+                    "GetBlockTxs({},{},{} txs)",
+                    &m.consensus_hash,
+                    &m.block_hash,
+                    m.indexes.len()
+                )
+            }
+            StacksMessageType::BlockTxs(ref m) => {
+                format!(
+                    "BlockTxs({},{},{} txs)",
+                    &m.consensus_hash,
+                    &m.block_hash,
+                    m.txs.len()
+                )
+            }
+            StacksMessageType::TxInv(ref m) => {
+                format!("TxInv({},{} txs)", &to_hex(&m.seed), m.tx_tags.len())
+            }
+            StacksMessageType::GetTxs(ref m) => {
+                format!("GetTxs({},{} txs)", &to_hex(&m.seed), m.tx_tags.len())
+            }
         }
     }
 }
@@ -1073,6 +1320,12 @@ impl StacksMessageCodec for StacksMessageID {
             x if x == StacksMessageID::StackerDBPushChunk as u8 => {
                 StacksMessageID::StackerDBPushChunk
             }
+            x if x == StacksMessageID::SessionKey as u8 => StacksMessageID::SessionKey,
+            x if x == StacksMessageID::CompactBlock as u8 => StacksMessageID::CompactBlock,
+            x if x == StacksMessageID::GetBlockTxs as u8 => StacksMessageID::GetBlockTxs,
+            x if x == StacksMessageID::BlockTxs as u8 => StacksMessageID::BlockTxs,
+            x if x == StacksMessageID::TxInv as u8 => StacksMessageID::TxInv,
+            x if x == StacksMessageID::GetTxs as u8 => StacksMessageID::GetTxs,
             _ => {
                 return Err(codec_error::DeserializeError(
                     "Unknown message ID".to_string(),
@@ -1115,6 +1368,12 @@ impl StacksMessageCodec for StacksMessageType {
             StacksMessageType::StackerDBGetChunk(ref m) => write_next(fd, m)?,
             StacksMessageType::StackerDBChunk(ref m) => write_next(fd, m)?,
             StacksMessageType::StackerDBPushChunk(ref m) => write_next(fd, m)?,
+            StacksMessageType::SessionKey(ref m) => write_next(fd, m)?,
+            StacksMessageType::CompactBlock(ref m) => write_next(fd, m)?,
+            StacksMessageType::GetBlockTxs(ref m) => write_next(fd, m)?,
+            StacksMessageType::BlockTxs(ref m) => write_next(fd, m)?,
+            StacksMessageType::TxInv(ref m) => write_next(fd, m)?,
+            StacksMessageType::GetTxs(ref m) => write_next(fd, m)?,
         }
         Ok(())
     }
@@ -1217,6 +1476,30 @@ impl StacksMessageCodec for StacksMessageType {
                 let m: StackerDBPushChunkData = read_next(fd)?;
                 StacksMessageType::StackerDBPushChunk(m)
             }
+            StacksMessageID::SessionKey => {
+                let m: SessionKeyData = read_next(fd)?;
+                StacksMessageType::SessionKey(m)
+            }
+            StacksMessageID::CompactBlock => {
+                let m: CompactBlockData = read_next(fd)?;
+                StacksMessageType::CompactBlock(m)
+            }
+            StacksMessageID::GetBlockTxs => {
+                let m: GetBlockTxsData = read_next(fd)?;
+                StacksMessageType::GetBlockTxs(m)
+            }
+            StacksMessageID::BlockTxs => {
+                let m: BlockTxsData = read_next(fd)?;
+                StacksMessageType::BlockTxs(m)
+            }
+            StacksMessageID::TxInv => {
+                let m: TxInvData = read_next(fd)?;
+                StacksMessageType::TxInv(m)
+            }
+            StacksMessageID::GetTxs => {
+                let m: GetTxsData = read_next(fd)?;
+                StacksMessageType::GetTxs(m)
+            }
             StacksMessageID::Reserved => {
                 return Err(codec_error::DeserializeError(
                     "Unsupported message ID 'reserved'".to_string(),
@@ -1490,6 +1773,49 @@ impl ProtocolFamily for StacksP2P {
     ) -> Result<(), net_error> {
         message.consensus_serialize(fd).map_err(|e| e.into())
     }
+
+    /// A SessionKey message switches the rest of the remote peer's stream over to an encrypted
+    /// session.  It must be originated (not relayed) and signed by the node key it names, since
+    /// the inbox may not have the remote peer's public key yet when it arrives right after a
+    /// HandshakeAccept.  The conversation checks that this key is the one it handshaked with.
+    fn begin_session(
+        &mut self,
+        message: &StacksMessage,
+        local_private_key: Option<&Secp256k1PrivateKey>,
+    ) -> Result<Option<SessionDecoder>, net_error> {
+        let StacksMessageType::SessionKey(ref data) = message.payload else {
+            return Ok(None);
+        };
+        let Some(local_private_key) = local_private_key else {
+            debug!("Got a SessionKey message, but encrypted sessions are not enabled");
+            return Err(net_error::InvalidMessage);
+        };
+        if message.relayers.len() > 0 {
+            debug!("Got a relayed SessionKey message");
+            return Err(net_error::InvalidMessage);
+        }
+        message.verify_secp256k1(&data.static_public_key)?;
+        SessionDecoder::accept(local_private_key, data).map(Some)
+    }
+
+    /// A P2P message's class is determined by its type ID, which follows the relayers
+    fn recv_class(
+        &mut self,
+        _preamble: &Preamble,
+        payload_prefix: &[u8],
+    ) -> Option<BandwidthClass> {
+        let mut cursor = io::Cursor::new(payload_prefix);
+        let _relayers: Vec<RelayData> =
+            read_next_at_most::<_, RelayData>(&mut cursor, MAX_RELAYERS_LEN).ok()?;
+        let message_id: StacksMessageID = read_next(&mut cursor).ok()?;
+        Some(BandwidthClass::of_p2p_message_id(message_id))
+    }
+
+    /// P2P replies are matched to requests by sequence number, so messages can go out in any
+    /// order
+    fn can_reorder_messages(&mut self) -> bool {
+        true
+    }
 }
 
 #[cfg(test)]
@@ -2306,6 +2632,131 @@ pub mod test {
         check_codec_and_corruption::<StackerDBPushChunkData>(&push_data, &bytes);
     }
 
+    #[test]
+    fn codec_SessionKeyData() {
+        let data = SessionKeyData {
+            ephemeral_public_key: StacksPublicKeyBuffer([0x02; 33]),
+            static_public_key: StacksPublicKeyBuffer([0x03; 33]),
+        };
+        let mut bytes = vec![0x02; 33];
+        bytes.append(&mut vec![0x03; 33]);
+
+        check_codec_and_corruption::<SessionKeyData>(&data, &bytes);
+    }
+
+    #[test]
+    fn codec_GetBlockTxsData() {
+        let data = GetBlockTxsData {
+            consensus_hash: ConsensusHash([0x11; 20]),
+            block_hash: BlockHeaderHash([0x22; 32]),
+            indexes: vec![1, 2, 0x0304],
+        };
+        let mut bytes = vec![0x11; 20];
+        bytes.append(&mut vec![0x22; 32]);
+        bytes.append(&mut vec![
+            0x00, 0x00, 0x00, 0x03, 0x00, 0x01, 0x00, 0x02, 0x03, 0x04,
+        ]);
+
+        check_codec_and_corruption::<GetBlockTxsData>(&data, &bytes);
+
+        // indexes must be strictly increasing
+        let bad_data = GetBlockTxsData {
+            consensus_hash: ConsensusHash([0x11; 20]),
+            block_hash: BlockHeaderHash([0x22; 32]),
+            indexes: vec![2, 2],
+        };
+        let bad_bytes = bad_data.serialize_to_vec();
+        assert!(GetBlockTxsData::consensus_deserialize(&mut &bad_bytes[..]).is_err());
+
+        // must ask for something
+        let empty_data = GetBlockTxsData {
+            consensus_hash: ConsensusHash([0x11; 20]),
+            block_hash: BlockHeaderHash([0x22; 32]),
+            indexes: vec![],
+        };
+        let empty_bytes = empty_data.serialize_to_vec();
+        assert!(GetBlockTxsData::consensus_deserialize(&mut &empty_bytes[..]).is_err());
+    }
+
+    #[test]
+    fn codec_CompactBlockData() {
+        let data = CompactBlockData {
+            consensus_hash: ConsensusHash([0x11; 20]),
+            header: StacksBlockHeader::genesis_block_header(),
+            tx_tags: vec![TxTag([0x01; 8]), TxTag([0x02; 8])],
+            prefilled_txs: vec![],
+        };
+        let mut bytes = vec![0x11; 20];
+        bytes.append(&mut data.header.serialize_to_vec());
+        bytes.append(&mut vec![0x00, 0x00, 0x00, 0x02]);
+        bytes.append(&mut vec![0x01; 8]);
+        bytes.append(&mut vec![0x02; 8]);
+        bytes.append(&mut vec![0x00, 0x00, 0x00, 0x00]);
+
+        check_codec_and_corruption::<CompactBlockData>(&data, &bytes);
+
+        // a block has at least one transaction
+        let empty_data = CompactBlockData {
+            consensus_hash: ConsensusHash([0x11; 20]),
+            header: StacksBlockHeader::genesis_block_header(),
+            tx_tags: vec![],
+            prefilled_txs: vec![],
+        };
+        let empty_bytes = empty_data.serialize_to_vec();
+        assert!(CompactBlockData::consensus_deserialize(&mut &empty_bytes[..]).is_err());
+    }
+
+    #[test]
+    fn codec_TxInvData() {
+        let data = TxInvData {
+            seed: [0x11; 32],
+            tx_tags: vec![TxTag([0x01; 8]), TxTag([0x02; 8])],
+        };
+        let mut bytes = vec![0x11; 32];
+        bytes.append(&mut vec![0x00, 0x00, 0x00, 0x02]);
+        bytes.append(&mut vec![0x01; 8]);
+        bytes.append(&mut vec![0x02; 8]);
+
+        check_codec_and_corruption::<TxInvData>(&data, &bytes);
+
+        // must announce something
+        let empty_data = TxInvData {
+            seed: [0x11; 32],
+            tx_tags: vec![],
+        };
+        let empty_bytes = empty_data.serialize_to_vec();
+        assert!(TxInvData::consensus_deserialize(&mut &empty_bytes[..]).is_err());
+
+        // can't announce too many at once
+        let big_data = TxInvData {
+            seed: [0x11; 32],
+            tx_tags: vec![TxTag([0x01; 8]); (TX_INV_MAX_TAGS + 1) as usize],
+        };
+        let big_bytes = big_data.serialize_to_vec();
+        assert!(TxInvData::consensus_deserialize(&mut &big_bytes[..]).is_err());
+    }
+
+    #[test]
+    fn codec_GetTxsData() {
+        let data = GetTxsData {
+            seed: [0x22; 32],
+            tx_tags: vec![TxTag([0x03; 8])],
+        };
+        let mut bytes = vec![0x22; 32];
+        bytes.append(&mut vec![0x00, 0x00, 0x00, 0x01]);
+        bytes.append(&mut vec![0x03; 8]);
+
+        check_codec_and_corruption::<GetTxsData>(&data, &bytes);
+
+        // must ask for something
+        let empty_data = GetTxsData {
+            seed: [0x22; 32],
+            tx_tags: vec![],
+        };
+        let empty_bytes = empty_data.serialize_to_vec();
+        assert!(GetTxsData::consensus_deserialize(&mut &empty_bytes[..]).is_err());
+    }
+
     #[test]
     fn codec_StacksMessage() {
         let payloads: Vec<StacksMessageType> = vec![
@@ -2479,6 +2930,34 @@ pub mod test {
                     data: vec![0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]
                 }
             }),
+            StacksMessageType::SessionKey(SessionKeyData {
+                ephemeral_public_key: StacksPublicKeyBuffer([0x02; 33]),
+                static_public_key: StacksPublicKeyBuffer([0x03; 33]),
+            }),
+            StacksMessageType::CompactBlock(CompactBlockData {
+                consensus_hash: ConsensusHash([0x11; 20]),
+                header: StacksBlockHeader::genesis_block_header(),
+                tx_tags: vec![TxTag([0x01; 8]), TxTag([0x02; 8])],
+                prefilled_txs: vec![],
+            }),
+            StacksMessageType::GetBlockTxs(GetBlockTxsData {
+                consensus_hash: ConsensusHash([0x11; 20]),
+                block_hash: BlockHeaderHash([0x22; 32]),
+                indexes: vec![1, 2, 3],
+            }),
+            StacksMessageType::BlockTxs(BlockTxsData {
+                consensus_hash: ConsensusHash([0x11; 20]),
+                block_hash: BlockHeaderHash([0x22; 32]),
+                txs: vec![],
+            }),
+            StacksMessageType::TxInv(TxInvData {
+                seed: [0x11; 32],
+                tx_tags: vec![TxTag([0x01; 8]), TxTag([0x02; 8])],
+            }),
+            StacksMessageType::GetTxs(GetTxsData {
+                seed: [0x11; 32],
+                tx_tags: vec![TxTag([0x02; 8])],
+            }),
         ];
 
         let mut maximal_relayers: Vec<RelayData> = vec![];
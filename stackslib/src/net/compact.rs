@@ -0,0 +1,690 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Compact block relay.
+//!
+//! When both peers advertise `ServiceFlags::COMPACT_BLOCKS`, a pushed anchored block is sent as a
+//! `CompactBlock`: the block header, plus an 8-byte `TxTag` for each transaction.  The tags are
+//! seeded with the block hash, so they differ from block to block.  The coinbase (and any
+//! transaction whose tag collides with an earlier one) is sent in full.
+//!
+//! The recipient matches the tags against the recent transactions in its mempool.  If it finds
+//! them all, it has the block.  Otherwise, it asks the sender for the missing ones with
+//! `GetBlockTxs`, and finishes the block when the `BlockTxs` reply arrives.  A rebuilt block is
+//! handed to the rest of the peer network as if it had arrived in a `Blocks` message.
+
+use std::collections::{HashMap, HashSet};
+
+use stacks_common::codec::StacksMessageCodec;
+use stacks_common::types::chainstate::{BlockHeaderHash, StacksBlockId};
+use stacks_common::util::get_epoch_time_secs;
+
+use crate::burnchains::Txid;
+use crate::chainstate::burn::ConsensusHash;
+use crate::chainstate::stacks::db::StacksChainState;
+use crate::chainstate::stacks::{
+    StacksBlock, StacksBlockHeader, StacksTransaction, TransactionPayload,
+};
+use crate::core::mempool::{MemPoolDB, TxTag};
+use crate::net::chat::ConversationP2P;
+use crate::net::p2p::PeerNetwork;
+use crate::net::{
+    BlockTxsData, BlocksData, BlocksDatum, CompactBlockData, Error as net_error, GetBlockTxsData,
+    NeighborKey, PrefilledTransaction, StacksMessage, StacksMessageType,
+};
+
+/// How long we'll wait for a peer to send us the transactions we asked for, before we give up on
+/// rebuilding its compact block.  The block downloader will fetch it eventually.
+pub const COMPACT_BLOCK_TXS_TIMEOUT: u64 = 30;
+
+/// How many compact blocks from a single peer we'll wait on at once
+pub const MAX_PENDING_COMPACT_BLOCKS: usize = 8;
+
+impl CompactBlockData {
+    /// Make a compact block out of a whole block.  The coinbase is always sent in full, since no
+    /// one will have it in their mempool.  So is any transaction whose tag collides with an
+    /// earlier transaction's tag, so the recipient can't mix them up.
+    pub fn from_block(consensus_hash: &ConsensusHash, block: &StacksBlock) -> CompactBlockData {
+        let block_hash = block.block_hash();
+        let mut tx_tags = Vec::with_capacity(block.txs.len());
+        let mut prefilled_txs = vec![];
+        let mut seen_tags = HashSet::new();
+
+        for (i, tx) in block.txs.iter().enumerate() {
+            let tx_tag = TxTag::from(block_hash.as_bytes(), &tx.txid());
+            let is_coinbase = matches!(tx.payload, TransactionPayload::Coinbase(..));
+            if is_coinbase || !seen_tags.insert(tx_tag.clone()) {
+                prefilled_txs.push(PrefilledTransaction {
+                    index: i as u16,
+                    tx: tx.clone(),
+                });
+            }
+            tx_tags.push(tx_tag);
+        }
+
+        CompactBlockData {
+            consensus_hash: consensus_hash.clone(),
+            header: block.header.clone(),
+            tx_tags,
+            prefilled_txs,
+        }
+    }
+}
+
+/// A block being rebuilt from a compact block
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartialBlock {
+    pub consensus_hash: ConsensusHash,
+    pub header: StacksBlockHeader,
+    tx_tags: Vec<TxTag>,
+    txs: Vec<Option<StacksTransaction>>,
+}
+
+impl PartialBlock {
+    /// Start rebuilding a block from a compact block, taking whatever transactions we can from the
+    /// mempool.  A tag that matches more than one mempool transaction is left missing.
+    pub fn from_compact_block(
+        compact_block: &CompactBlockData,
+        mempool: &MemPoolDB,
+    ) -> Result<PartialBlock, net_error> {
+        let block_hash = compact_block.header.block_hash();
+        let mut txs: Vec<Option<StacksTransaction>> = vec![None; compact_block.tx_tags.len()];
+        for prefilled_tx in compact_block.prefilled_txs.iter() {
+            let tx_tag = TxTag::from(block_hash.as_bytes(), &prefilled_tx.tx.txid());
+            if compact_block.tx_tags.get(prefilled_tx.index as usize) != Some(&tx_tag) {
+                return Err(net_error::InvalidMessage);
+            }
+            txs[prefilled_tx.index as usize] = Some(prefilled_tx.tx.clone());
+        }
+
+        // index of each tag we still need
+        let mut wanted: HashMap<TxTag, usize> = HashMap::new();
+        for (i, tx_tag) in compact_block.tx_tags.iter().enumerate() {
+            if txs[i].is_none() {
+                wanted.insert(tx_tag.clone(), i);
+            }
+        }
+
+        // index of each tag we found in the mempool, if we found exactly one match
+        let mut found: HashMap<usize, Option<Txid>> = HashMap::new();
+        if wanted.len() > 0 {
+            for txid in mempool.get_bloom_txids()?.into_iter() {
+                let tx_tag = TxTag::from(block_hash.as_bytes(), &txid);
+                if let Some(i) = wanted.get(&tx_tag) {
+                    if found.contains_key(i) {
+                        found.insert(*i, None);
+                    } else {
+                        found.insert(*i, Some(txid));
+                    }
+                }
+            }
+        }
+
+        for (i, txid_opt) in found.into_iter() {
+            let Some(txid) = txid_opt else {
+                continue;
+            };
+            if let Some(tx_info) = MemPoolDB::get_tx(mempool.conn(), &txid)? {
+                txs[i] = Some(tx_info.tx);
+            }
+        }
+
+        Ok(PartialBlock {
+            consensus_hash: compact_block.consensus_hash.clone(),
+            header: compact_block.header.clone(),
+            tx_tags: compact_block.tx_tags.clone(),
+            txs,
+        })
+    }
+
+    pub fn block_hash(&self) -> BlockHeaderHash {
+        self.header.block_hash()
+    }
+
+    pub fn index_block_hash(&self) -> StacksBlockId {
+        StacksBlockId::new(&self.consensus_hash, &self.block_hash())
+    }
+
+    /// Indexes of the transactions we don't have yet
+    pub fn missing_indexes(&self) -> Vec<u16> {
+        self.txs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, tx_opt)| {
+                if tx_opt.is_none() {
+                    Some(i as u16)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.txs.iter().all(|tx_opt| tx_opt.is_some())
+    }
+
+    /// Make the request for the transactions we don't have yet
+    pub fn make_getblocktxs(&self) -> GetBlockTxsData {
+        GetBlockTxsData {
+            consensus_hash: self.consensus_hash.clone(),
+            block_hash: self.block_hash(),
+            indexes: self.missing_indexes(),
+        }
+    }
+
+    /// Fill in the missing transactions from a `BlockTxs` reply.  The reply must have exactly the
+    /// missing transactions, in block order.
+    pub fn fill(&mut self, block_txs: BlockTxsData) -> Result<(), net_error> {
+        let block_hash = self.block_hash();
+        if block_txs.consensus_hash != self.consensus_hash || block_txs.block_hash != block_hash {
+            return Err(net_error::InvalidMessage);
+        }
+
+        let missing = self.missing_indexes();
+        if missing.len() != block_txs.txs.len() {
+            return Err(net_error::InvalidMessage);
+        }
+
+        for (i, tx) in missing.iter().zip(block_txs.txs.iter()) {
+            let tx_tag = TxTag::from(block_hash.as_bytes(), &tx.txid());
+            if self.tx_tags[*i as usize] != tx_tag {
+                return Err(net_error::InvalidMessage);
+            }
+        }
+
+        for (i, tx) in missing.into_iter().zip(block_txs.txs.into_iter()) {
+            self.txs[i as usize] = Some(tx);
+        }
+        Ok(())
+    }
+
+    /// Finish rebuilding the block.  Fails if we don't have all the transactions, or if the
+    /// transactions we picked don't match the header's Merkle root.
+    pub fn into_block(self) -> Result<StacksBlock, net_error> {
+        let mut txs = Vec::with_capacity(self.txs.len());
+        for tx_opt in self.txs.into_iter() {
+            txs.push(tx_opt.ok_or(net_error::InvalidMessage)?);
+        }
+
+        // run the same well-formedness checks as a block decoded from a `Blocks` message
+        // (including the Merkle root check)
+        let block_bytes = StacksBlock {
+            header: self.header,
+            txs,
+        }
+        .serialize_to_vec();
+        let block = StacksBlock::consensus_deserialize(&mut &block_bytes[..])?;
+        Ok(block)
+    }
+}
+
+/// A compact block that we're waiting on transactions for
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingCompactBlock {
+    /// the `CompactBlock` message we received
+    pub message: StacksMessage,
+    pub partial_block: PartialBlock,
+    /// when we sent the `GetBlockTxs`
+    pub request_time: u64,
+}
+
+impl PeerNetwork {
+    /// Make the message to push a block to a neighbor.  This is a compact block if we and the
+    /// neighbor both support them, and the whole block otherwise.
+    pub fn make_block_push_payload(
+        &self,
+        recipient: &NeighborKey,
+        consensus_hash: ConsensusHash,
+        block: StacksBlock,
+    ) -> StacksMessageType {
+        let peer_supports_compact_blocks = self
+            .events
+            .get(recipient)
+            .and_then(|event_id| self.peers.get(event_id))
+            .map(|convo| ConversationP2P::supports_compact_blocks(convo.peer_services))
+            .unwrap_or(false);
+
+        if ConversationP2P::supports_compact_blocks(self.local_peer.services)
+            && peer_supports_compact_blocks
+        {
+            StacksMessageType::CompactBlock(CompactBlockData::from_block(&consensus_hash, &block))
+        } else {
+            StacksMessageType::Blocks(BlocksData {
+                blocks: vec![BlocksDatum(consensus_hash, block)],
+            })
+        }
+    }
+
+    /// Rebuild blocks from the `CompactBlock` and `BlockTxs` messages in a batch of unsolicited
+    /// messages.  Each rebuilt block replaces the message that completed it with a `Blocks`
+    /// message, so the rest of the peer network handles it like any other pushed block.  Compact
+    /// blocks that are still missing transactions are held back until the transactions arrive.
+    pub fn process_compact_blocks(
+        &mut self,
+        chainstate: &StacksChainState,
+        mempool: &MemPoolDB,
+        unsolicited: &mut HashMap<usize, Vec<StacksMessage>>,
+    ) {
+        self.expire_pending_compact_blocks();
+
+        for (event_id, messages) in unsolicited.iter_mut() {
+            let mut remaining = Vec::with_capacity(messages.len());
+            for message in messages.drain(..) {
+                let next_message = match message.payload {
+                    StacksMessageType::CompactBlock(..) => {
+                        self.handle_compact_block(*event_id, chainstate, mempool, message)
+                    }
+                    StacksMessageType::BlockTxs(..) => self.handle_block_txs(*event_id, message),
+                    _ => Some(message),
+                };
+                if let Some(next_message) = next_message {
+                    remaining.push(next_message);
+                }
+            }
+            *messages = remaining;
+        }
+    }
+
+    /// Start rebuilding a compact block.  Returns the rebuilt block's message if the mempool had
+    /// all of its transactions.  Otherwise, asks the sender for the missing ones.
+    fn handle_compact_block(
+        &mut self,
+        event_id: usize,
+        chainstate: &StacksChainState,
+        mempool: &MemPoolDB,
+        message: StacksMessage,
+    ) -> Option<StacksMessage> {
+        let StacksMessageType::CompactBlock(ref compact_block) = message.payload else {
+            return Some(message);
+        };
+        let index_block_hash = StacksBlockId::new(
+            &compact_block.consensus_hash,
+            &compact_block.header.block_hash(),
+        );
+
+        if self
+            .pending_compact_blocks
+            .contains_key(&(event_id, index_block_hash.clone()))
+        {
+            debug!(
+                "{:?}: Already rebuilding compact block {} from event {}",
+                &self.local_peer, &index_block_hash, event_id
+            );
+            return None;
+        }
+
+        match StacksChainState::has_block_indexed(&chainstate.blocks_path, &index_block_hash) {
+            Ok(true) => {
+                debug!(
+                    "{:?}: Already have compact block {}",
+                    &self.local_peer, &index_block_hash
+                );
+                return None;
+            }
+            Ok(false) => {}
+            Err(e) => {
+                debug!(
+                    "{:?}: Failed to check for block {}: {:?}",
+                    &self.local_peer, &index_block_hash, &e
+                );
+            }
+        }
+
+        let partial_block = match PartialBlock::from_compact_block(compact_block, mempool) {
+            Ok(partial_block) => partial_block,
+            Err(e) => {
+                warn!(
+                    "{:?}: Failed to start rebuilding compact block {} from event {}: {:?}",
+                    &self.local_peer, &index_block_hash, event_id, &e
+                );
+                return None;
+            }
+        };
+
+        if partial_block.is_complete() {
+            debug!(
+                "{:?}: Rebuilt compact block {} entirely from the mempool",
+                &self.local_peer, &index_block_hash
+            );
+            return self.finish_compact_block(message, partial_block);
+        }
+
+        let num_pending = self
+            .pending_compact_blocks
+            .keys()
+            .filter(|(pending_event_id, _)| *pending_event_id == event_id)
+            .count();
+        if num_pending >= MAX_PENDING_COMPACT_BLOCKS {
+            debug!(
+                "{:?}: Too many compact blocks pending from event {}; dropping {}",
+                &self.local_peer, event_id, &index_block_hash
+            );
+            return None;
+        }
+
+        let getblocktxs = partial_block.make_getblocktxs();
+        debug!(
+            "{:?}: Compact block {} is missing {} of {} transactions; asking event {}",
+            &self.local_peer,
+            &index_block_hash,
+            getblocktxs.indexes.len(),
+            compact_block.tx_tags.len(),
+            event_id
+        );
+        if let Err(e) = self.request_block_txs(event_id, getblocktxs) {
+            debug!(
+                "{:?}: Failed to ask event {} for transactions in {}: {:?}",
+                &self.local_peer, event_id, &index_block_hash, &e
+            );
+            return None;
+        }
+
+        self.pending_compact_blocks.insert(
+            (event_id, index_block_hash),
+            PendingCompactBlock {
+                message,
+                partial_block,
+                request_time: get_epoch_time_secs(),
+            },
+        );
+        None
+    }
+
+    /// Finish rebuilding a compact block with the transactions we asked for.  Returns the rebuilt
+    /// block's message on success.
+    fn handle_block_txs(
+        &mut self,
+        event_id: usize,
+        message: StacksMessage,
+    ) -> Option<StacksMessage> {
+        let StacksMessageType::BlockTxs(block_txs) = message.payload else {
+            return Some(message);
+        };
+        let index_block_hash = StacksBlockId::new(&block_txs.consensus_hash, &block_txs.block_hash);
+
+        let Some(mut pending) = self
+            .pending_compact_blocks
+            .remove(&(event_id, index_block_hash.clone()))
+        else {
+            debug!(
+                "{:?}: Got unrequested transactions for {} from event {}",
+                &self.local_peer, &index_block_hash, event_id
+            );
+            return None;
+        };
+
+        if let Err(e) = pending.partial_block.fill(block_txs) {
+            warn!(
+                "{:?}: Event {} sent invalid transactions for compact block {}: {:?}",
+                &self.local_peer, event_id, &index_block_hash, &e
+            );
+            return None;
+        }
+
+        self.finish_compact_block(pending.message, pending.partial_block)
+    }
+
+    /// Turn a `CompactBlock` message into a `Blocks` message with the rebuilt block.  The
+    /// preamble is left as-is (it was already authenticated when we received the message).
+    fn finish_compact_block(
+        &self,
+        mut message: StacksMessage,
+        partial_block: PartialBlock,
+    ) -> Option<StacksMessage> {
+        let consensus_hash = partial_block.consensus_hash.clone();
+        let index_block_hash = partial_block.index_block_hash();
+        match partial_block.into_block() {
+            Ok(block) => {
+                message.payload = StacksMessageType::Blocks(BlocksData {
+                    blocks: vec![BlocksDatum(consensus_hash, block)],
+                });
+                Some(message)
+            }
+            Err(e) => {
+                // most likely a tag collision with a different mempool transaction.  The block
+                // downloader will fetch the block itself.
+                info!(
+                    "{:?}: Failed to rebuild compact block {}: {:?}",
+                    &self.local_peer, &index_block_hash, &e
+                );
+                None
+            }
+        }
+    }
+
+    /// Ask a neighbor for the transactions we're missing from its compact block
+    fn request_block_txs(
+        &mut self,
+        event_id: usize,
+        getblocktxs: GetBlockTxsData,
+    ) -> Result<(), net_error> {
+        let neighbor_key = self
+            .peers
+            .get(&event_id)
+            .map(|convo| convo.to_neighbor_key())
+            .ok_or(net_error::PeerNotConnected)?;
+        let message = self.sign_for_p2p(event_id, StacksMessageType::GetBlockTxs(getblocktxs))?;
+        self.relay_signed_message(&neighbor_key, message)
+    }
+
+    /// Give up on compact blocks whose transactions never arrived
+    fn expire_pending_compact_blocks(&mut self) {
+        let now = get_epoch_time_secs();
+        let local_peer = &self.local_peer;
+        self.pending_compact_blocks
+            .retain(|(event_id, index_block_hash), pending| {
+                if pending.request_time + COMPACT_BLOCK_TXS_TIMEOUT < now {
+                    debug!(
+                        "{:?}: Timed out waiting for event {} to send transactions for {}",
+                        local_peer, event_id, index_block_hash
+                    );
+                    return false;
+                }
+                true
+            });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use stacks_common::types::chainstate::StacksAddress;
+    use stacks_common::util::hash::{Hash160, MerkleTree, Sha512Trunc256Sum};
+
+    use super::*;
+    use crate::chainstate::stacks::db::test::{chainstate_path, instantiate_chainstate};
+    use crate::chainstate::stacks::test::codec_all_transactions;
+    use crate::chainstate::stacks::{
+        TransactionAnchorMode, TransactionPostConditionMode, TransactionVersion,
+    };
+
+    /// Make a block out of a coinbase and some other transactions
+    fn make_test_block() -> StacksBlock {
+        let all_txs = codec_all_transactions(
+            &TransactionVersion::Testnet,
+            0x80000000,
+            &TransactionAnchorMode::Any,
+            &TransactionPostConditionMode::Allow,
+        );
+        let coinbase = all_txs
+            .iter()
+            .find(|tx| matches!(tx.payload, TransactionPayload::Coinbase(..)))
+            .unwrap()
+            .clone();
+
+        let mut txs = vec![coinbase];
+        let mut txids = HashSet::new();
+        for tx in all_txs.into_iter() {
+            if matches!(tx.payload, TransactionPayload::Coinbase(..)) || !txids.insert(tx.txid()) {
+                continue;
+            }
+            txs.push(tx);
+            if txs.len() >= 10 {
+                break;
+            }
+        }
+
+        let txid_vecs = txs.iter().map(|tx| tx.txid().as_bytes().to_vec()).collect();
+        let mut header = StacksBlockHeader::genesis_block_header();
+        header.tx_merkle_root = MerkleTree::<Sha512Trunc256Sum>::new(&txid_vecs).root();
+        StacksBlock { header, txs }
+    }
+
+    /// Put the given transactions into the mempool
+    fn add_to_mempool(
+        chainstate: &mut StacksChainState,
+        mempool: &mut MemPoolDB,
+        txs: &[StacksTransaction],
+    ) {
+        let mut mempool_tx = mempool.tx_begin().unwrap();
+        for (i, tx) in txs.iter().enumerate() {
+            let origin_address = StacksAddress {
+                version: 22,
+                bytes: Hash160::from_data(&i.to_be_bytes()),
+            };
+            MemPoolDB::try_add_tx(
+                &mut mempool_tx,
+                chainstate,
+                &ConsensusHash([0x1; 20]),
+                &BlockHeaderHash([0x2; 32]),
+                tx.txid(),
+                tx.serialize_to_vec(),
+                tx.get_tx_fee(),
+                100,
+                &origin_address,
+                tx.get_origin_nonce(),
+                &origin_address,
+                tx.get_origin_nonce(),
+                None,
+            )
+            .unwrap();
+        }
+        mempool_tx.commit().unwrap();
+    }
+
+    #[test]
+    fn test_compact_block_from_block() {
+        let block = make_test_block();
+        let compact_block = CompactBlockData::from_block(&ConsensusHash([0x11; 20]), &block);
+
+        assert_eq!(compact_block.header, block.header);
+        assert_eq!(compact_block.tx_tags.len(), block.txs.len());
+        for (tx_tag, tx) in compact_block.tx_tags.iter().zip(block.txs.iter()) {
+            assert_eq!(
+                *tx_tag,
+                TxTag::from(block.block_hash().as_bytes(), &tx.txid())
+            );
+        }
+
+        // only the coinbase is sent in full
+        assert_eq!(compact_block.prefilled_txs.len(), 1);
+        assert_eq!(compact_block.prefilled_txs[0].index, 0);
+        assert_eq!(compact_block.prefilled_txs[0].tx, block.txs[0]);
+
+        // much smaller than the block
+        assert!(compact_block.serialize_to_vec().len() < block.serialize_to_vec().len());
+    }
+
+    #[test]
+    fn test_rebuild_compact_block_from_mempool() {
+        let mut chainstate = instantiate_chainstate(false, 0x80000000, function_name!());
+        let chainstate_path = chainstate_path(function_name!());
+        let mut mempool = MemPoolDB::open_test(false, 0x80000000, &chainstate_path).unwrap();
+
+        let block = make_test_block();
+        let compact_block = CompactBlockData::from_block(&ConsensusHash([0x11; 20]), &block);
+
+        add_to_mempool(&mut chainstate, &mut mempool, &block.txs[1..]);
+
+        let partial_block = PartialBlock::from_compact_block(&compact_block, &mempool).unwrap();
+        assert!(partial_block.is_complete());
+        assert_eq!(partial_block.missing_indexes(), Vec::<u16>::new());
+        assert_eq!(partial_block.into_block().unwrap(), block);
+    }
+
+    #[test]
+    fn test_rebuild_compact_block_with_missing_txs() {
+        let mut chainstate = instantiate_chainstate(false, 0x80000000, function_name!());
+        let chainstate_path = chainstate_path(function_name!());
+        let mut mempool = MemPoolDB::open_test(false, 0x80000000, &chainstate_path).unwrap();
+
+        let block = make_test_block();
+        let compact_block = CompactBlockData::from_block(&ConsensusHash([0x11; 20]), &block);
+
+        // only have every other transaction
+        let have_txs: Vec<_> = block.txs.iter().skip(1).step_by(2).cloned().collect();
+        add_to_mempool(&mut chainstate, &mut mempool, &have_txs);
+
+        let mut partial_block = PartialBlock::from_compact_block(&compact_block, &mempool).unwrap();
+        assert!(!partial_block.is_complete());
+
+        let expected_missing: Vec<u16> = (2..block.txs.len() as u16).step_by(2).collect();
+        assert_eq!(partial_block.missing_indexes(), expected_missing);
+
+        let getblocktxs = partial_block.make_getblocktxs();
+        assert_eq!(getblocktxs.consensus_hash, ConsensusHash([0x11; 20]));
+        assert_eq!(getblocktxs.block_hash, block.block_hash());
+        assert_eq!(getblocktxs.indexes, expected_missing);
+
+        let missing_txs: Vec<_> = expected_missing
+            .iter()
+            .map(|i| block.txs[*i as usize].clone())
+            .collect();
+
+        // wrong block
+        let mut bad_partial_block = partial_block.clone();
+        assert!(bad_partial_block
+            .fill(BlockTxsData {
+                consensus_hash: ConsensusHash([0x22; 20]),
+                block_hash: block.block_hash(),
+                txs: missing_txs.clone(),
+            })
+            .is_err());
+
+        // wrong number of transactions
+        assert!(bad_partial_block
+            .fill(BlockTxsData {
+                consensus_hash: ConsensusHash([0x11; 20]),
+                block_hash: block.block_hash(),
+                txs: missing_txs[1..].to_vec(),
+            })
+            .is_err());
+
+        // wrong transactions
+        let mut wrong_txs = missing_txs.clone();
+        wrong_txs.reverse();
+        assert!(bad_partial_block
+            .fill(BlockTxsData {
+                consensus_hash: ConsensusHash([0x11; 20]),
+                block_hash: block.block_hash(),
+                txs: wrong_txs,
+            })
+            .is_err());
+
+        // incomplete blocks can't be finished
+        assert!(bad_partial_block.into_block().is_err());
+
+        partial_block
+            .fill(BlockTxsData {
+                consensus_hash: ConsensusHash([0x11; 20]),
+                block_hash: block.block_hash(),
+                txs: missing_txs,
+            })
+            .unwrap();
+        assert!(partial_block.is_complete());
+        assert_eq!(partial_block.into_block().unwrap(), block);
+    }
+}
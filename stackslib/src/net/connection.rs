@@ -17,6 +17,7 @@
 use std::collections::VecDeque;
 use std::convert::TryFrom;
 use std::io::{Read, Write};
+use std::net::SocketAddr;
 use std::ops::{Deref, DerefMut};
 use std::sync::mpsc::{
     sync_channel, Receiver, RecvError, RecvTimeoutError, SyncSender, TryRecvError, TrySendError,
@@ -32,20 +33,22 @@ use stacks_common::codec::{StacksMessageCodec, MAX_MESSAGE_LEN};
 use stacks_common::types::net::PeerAddress;
 use stacks_common::util::hash::to_hex;
 use stacks_common::util::pipe::*;
-use stacks_common::util::secp256k1::Secp256k1PublicKey;
-use stacks_common::util::{get_epoch_time_secs, log, sleep_ms};
+use stacks_common::util::secp256k1::{Secp256k1PrivateKey, Secp256k1PublicKey};
+use stacks_common::util::{get_epoch_time_ms, get_epoch_time_secs, log, sleep_ms};
 
 use crate::chainstate::burn::ConsensusHash;
 use crate::core::mempool::MAX_BLOOM_COUNTER_TXS;
 use crate::monitoring::{update_inbound_bandwidth, update_outbound_bandwidth};
 use crate::net::codec::*;
 use crate::net::download::BLOCK_DOWNLOAD_INTERVAL;
+use crate::net::encryption::{SessionCipher, SessionDecoder};
 use crate::net::inv::{INV_REWARD_CYCLES, INV_SYNC_INTERVAL};
 use crate::net::neighbors::{
     MAX_NEIGHBOR_AGE, NEIGHBOR_REQUEST_TIMEOUT, NEIGHBOR_WALK_INTERVAL, NUM_INITIAL_WALKS,
     WALK_MAX_DURATION, WALK_MIN_DURATION, WALK_RESET_INTERVAL, WALK_RESET_PROB, WALK_RETRY_COUNT,
     WALK_STATE_TIMEOUT,
 };
+use crate::net::ratelimit::{BandwidthClass, BandwidthLimiter};
 use crate::net::{
     Error as net_error, MessageSequence, Preamble, ProtocolFamily, RelayData, StacksHttp, StacksP2P,
 };
@@ -291,12 +294,24 @@ impl<P: ProtocolFamily> Write for NetworkReplyHandle<P> {
 struct InflightMessage<P: ProtocolFamily> {
     pipe_read: Option<PipeRead>,
     notify: Option<ReceiverNotify<P>>,
+    // if set, seal all bytes sent after this message with this session cipher
+    session: Option<SessionCipher>,
+    // priority class of this message's bytes, for bandwidth limiting
+    class: BandwidthClass,
 }
 
+/// How many bytes of a message's payload to read at a time, until we know its bandwidth class
+const RECV_CLASS_PROBE_LEN: usize = 64;
+
 #[derive(Debug)]
 struct ConnectionInbox<P: ProtocolFamily> {
     public_key: Option<Secp256k1PublicKey>,
 
+    // our private key, if we accept encrypted sessions
+    local_private_key: Option<Secp256k1PrivateKey>,
+    // decoder for the remote peer's encrypted session, once it begins one
+    session: Option<SessionDecoder>,
+
     // completely-parsed incoming messages that do _not_ get sent out to a waiting receiver
     inbox: VecDeque<P::Message>,
     inbox_maxlen: usize,
@@ -306,6 +321,15 @@ struct ConnectionInbox<P: ProtocolFamily> {
     buf: Vec<u8>,
     message_ptr: usize, // index into buf where the message begins
     payload_ptr: usize, // for payloads of unknown length, this points to where to read next
+
+    // inbound bandwidth limit
+    limiter: BandwidthLimiter,
+    // class of the message being received, once the protocol can tell it from the message
+    message_class: Option<BandwidthClass>,
+    // class of the bytes we expect to receive, if the protocol can't tell
+    class: Option<BandwidthClass>,
+    // whether or not the last read stopped because we ran out of bandwidth
+    throttled: bool,
 }
 
 #[derive(Debug)]
@@ -318,8 +342,16 @@ struct ConnectionOutbox<P: ProtocolFamily> {
     socket_out_buf: Vec<u8>,
     socket_out_ptr: usize,
 
+    // cipher for our encrypted session, once we begin one
+    session: Option<SessionCipher>,
+
     // in-flight messages
     inflight: VecDeque<ReceiverNotify<P>>,
+
+    // outbound bandwidth limit
+    limiter: BandwidthLimiter,
+    // whether or not the last write stopped because we ran out of bandwidth
+    throttled: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -393,6 +425,43 @@ pub struct ConnectionOptions {
     pub socket_send_buffer_size: u32,
     /// whether or not to announce or accept neighbors that are behind private networks
     pub private_neighbors: bool,
+    /// token that clients must send in the `Authorization` header to use the authenticated RPC
+    /// endpoints.  These endpoints are disabled if it is not set.
+    pub auth_token: Option<String>,
+    /// whether or not to advertise `ServiceFlags::ENCRYPTION`, and encrypt traffic with peers that
+    /// also advertise it.
+    pub p2p_encryption: bool,
+    /// most transactions to announce to each peer per second, if it supports transaction
+    /// announcements.  The rest wait their turn.  If 0, transactions are always pushed in full.
+    pub max_tx_announcements_per_sec: u64,
+    /// if set, record every P2P message sent and received to a capture file at this path
+    pub p2p_capture_path: Option<String>,
+    /// rotate the capture file once it would grow past this many bytes
+    pub p2p_capture_max_bytes: u64,
+    /// number of capture files to keep, including the one being written
+    pub p2p_capture_max_files: u32,
+    /// if set, make outbound P2P and HTTP connections (except to loopback addresses) through this
+    /// SOCKS5 proxy, such as a local Tor daemon.
+    pub socks5_proxy: Option<SocketAddr>,
+    /// most bytes per second to receive from all P2P peers together.  0 means no limit.  Block
+    /// and StackerDB traffic may use the whole budget, other messages 3/4 of it, and transaction
+    /// gossip half of it.  The same goes for all the bandwidth limits below.
+    pub max_p2p_inbound_bandwidth: u64,
+    /// most bytes per second to send to all P2P peers together.  0 means no limit.
+    pub max_p2p_outbound_bandwidth: u64,
+    /// most bytes per second to receive from any one P2P peer.  0 means no limit.
+    pub max_p2p_peer_inbound_bandwidth: u64,
+    /// most bytes per second to send to any one P2P peer.  0 means no limit.
+    pub max_p2p_peer_outbound_bandwidth: u64,
+    /// most bytes per second to receive over all HTTP connections together, both to our RPC
+    /// server and from the nodes we query.  0 means no limit.
+    pub max_http_inbound_bandwidth: u64,
+    /// most bytes per second to send over all HTTP connections together.  0 means no limit.
+    pub max_http_outbound_bandwidth: u64,
+    /// most bytes per second to receive over any one HTTP connection.  0 means no limit.
+    pub max_http_peer_inbound_bandwidth: u64,
+    /// most bytes per second to send over any one HTTP connection.  0 means no limit.
+    pub max_http_peer_outbound_bandwidth: u64,
 
     // fault injection
     pub disable_neighbor_walk: bool,
@@ -485,6 +554,21 @@ impl std::default::Default for ConnectionOptions {
             socket_recv_buffer_size: 131072, // Linux default
             socket_send_buffer_size: 16384, // Linux default
             private_neighbors: true,
+            auth_token: None,
+            p2p_encryption: false,
+            max_tx_announcements_per_sec: 100,
+            p2p_capture_path: None,
+            p2p_capture_max_bytes: 64 * 1024 * 1024,
+            p2p_capture_max_files: 4,
+            socks5_proxy: None,
+            max_p2p_inbound_bandwidth: 0,
+            max_p2p_outbound_bandwidth: 0,
+            max_p2p_peer_inbound_bandwidth: 0,
+            max_p2p_peer_outbound_bandwidth: 0,
+            max_http_inbound_bandwidth: 0,
+            max_http_outbound_bandwidth: 0,
+            max_http_peer_inbound_bandwidth: 0,
+            max_http_peer_outbound_bandwidth: 0,
 
             // no faults on by default
             disable_neighbor_walk: false,
@@ -522,12 +606,18 @@ impl<P: ProtocolFamily> ConnectionInbox<P> {
     ) -> ConnectionInbox<P> {
         ConnectionInbox {
             public_key: public_key_opt,
+            local_private_key: None,
+            session: None,
             inbox: VecDeque::with_capacity(max_messages),
             inbox_maxlen: max_messages,
             preamble: None,
             buf: vec![],
             message_ptr: 0,
             payload_ptr: 0,
+            limiter: BandwidthLimiter::unlimited(),
+            message_class: None,
+            class: None,
+            throttled: false,
         }
     }
 
@@ -584,6 +674,7 @@ impl<P: ProtocolFamily> ConnectionInbox<P> {
 
                 self.message_ptr = preamble_len;
                 self.payload_ptr = preamble_len;
+                self.message_class = None;
                 Some(preamble)
             }
             Err(net_error::DeserializeError(errmsg)) => {
@@ -794,7 +885,74 @@ impl<P: ProtocolFamily> ConnectionInbox<P> {
     ///
     /// Returns nothing on success, and enqueues zero or more messages into our inbox.
     /// Returns net_error::InvalidMessage if a message could not be parsed or authenticated.
+    ///
+    /// If the remote peer has begun an encrypted session, `buf` is decrypted first.  If it begins
+    /// one partway through `buf`, the remainder of `buf` is decrypted and consumed as well.
     fn consume_messages(&mut self, protocol: &mut P, buf: &[u8]) -> Result<(), net_error> {
+        let ciphertext_opt = match self.session.as_mut() {
+            Some(session) => {
+                let plaintext = session.open(buf)?;
+                self.consume_plaintext_messages(protocol, &plaintext)?
+            }
+            None => self.consume_plaintext_messages(protocol, buf)?,
+        };
+
+        if let Some(ciphertext) = ciphertext_opt {
+            let plaintext = self
+                .session
+                .as_mut()
+                .expect("BUG: began a session without a session decoder")
+                .open(&ciphertext)?;
+
+            if self
+                .consume_plaintext_messages(protocol, &plaintext)?
+                .is_some()
+            {
+                // unreachable, since begin_session() rejects a second session
+                return Err(net_error::InvalidMessage);
+            }
+        }
+        Ok(())
+    }
+
+    /// Determine whether or not the given just-consumed message begins the remote peer's
+    /// encrypted session, and if so, install its decoder.  A peer may only begin one session per
+    /// connection.
+    fn begin_session(&mut self, protocol: &mut P, message: &P::Message) -> Result<bool, net_error> {
+        match protocol.begin_session(message, self.local_private_key.as_ref())? {
+            Some(decoder) => {
+                if self.session.is_some() {
+                    debug!("Remote peer tried to begin a second encrypted session");
+                    return Err(net_error::InvalidMessage);
+                }
+                test_debug!("Remote peer began an encrypted session");
+                self.session = Some(decoder);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Take all buffered-but-unparsed bytes, plus the unconsumed remainder of the input buffer.
+    /// Called once the remote peer begins an encrypted session, since these bytes are ciphertext.
+    fn take_unparsed_bytes(&mut self, rest: &[u8]) -> Vec<u8> {
+        let mut bytes = std::mem::replace(&mut self.buf, vec![]);
+        bytes.extend_from_slice(rest);
+
+        self.preamble = None;
+        self.message_ptr = 0;
+        self.payload_ptr = 0;
+        bytes
+    }
+
+    /// Consume plaintext messages, as in consume_messages().
+    /// Returns Some(ciphertext) if one of the messages began an encrypted session, in which case
+    /// the returned bytes (everything after that message) have not been consumed yet.
+    fn consume_plaintext_messages(
+        &mut self,
+        protocol: &mut P,
+        buf: &[u8],
+    ) -> Result<Option<Vec<u8>>, net_error> {
         let mut offset = 0;
         loop {
             if self.inbox.len() > self.inbox_maxlen {
@@ -823,6 +981,7 @@ impl<P: ProtocolFamily> ConnectionInbox<P> {
             }
 
             let mut consumed_message = false;
+            let mut began_session = false;
             let bytes_consumed_message = {
                 let mut preamble_opt = self.preamble.take();
                 let bytes_consumed = if let Some(ref mut preamble) = preamble_opt {
@@ -837,6 +996,7 @@ impl<P: ProtocolFamily> ConnectionInbox<P> {
                                 message.request_id(),
                                 bytes_consumed
                             );
+                            began_session = self.begin_session(protocol, &message)?;
                             self.inbox.push_back(message);
                             consumed_message = true;
                         }
@@ -857,6 +1017,9 @@ impl<P: ProtocolFamily> ConnectionInbox<P> {
             }
 
             offset += bytes_consumed_message;
+            if began_session {
+                return Ok(Some(self.take_unparsed_bytes(&buf[offset..])));
+            }
             if offset == buf.len() {
                 break;
             }
@@ -867,6 +1030,7 @@ impl<P: ProtocolFamily> ConnectionInbox<P> {
         if self.buf.len() > 0 {
             loop {
                 let mut consumed_message = false;
+                let mut began_session = false;
 
                 if self.preamble.is_none() {
                     let (preamble_opt, _bytes_consumed) = self.consume_preamble(protocol, &[])?;
@@ -888,6 +1052,7 @@ impl<P: ProtocolFamily> ConnectionInbox<P> {
                             Some(message) => {
                                 // queue up
                                 test_debug!("Consumed buffered message '{}' (request {}) from {} input buffer bytes", message.get_message_name(), message.request_id(), _bytes_consumed);
+                                began_session = self.begin_session(protocol, &message)?;
                                 self.inbox.push_back(message);
                                 consumed_message = true;
                             }
@@ -900,6 +1065,9 @@ impl<P: ProtocolFamily> ConnectionInbox<P> {
                         // next message
                         self.preamble = None;
                     }
+                    if began_session {
+                        return Ok(Some(self.take_unparsed_bytes(&[])));
+                    }
                 }
 
                 if !consumed_message {
@@ -909,7 +1077,52 @@ impl<P: ProtocolFamily> ConnectionInbox<P> {
             }
         }
 
-        Ok(())
+        Ok(None)
+    }
+
+    /// Get the bandwidth class of the message being received, if the protocol can tell it from
+    /// what we have of the message so far.  Otherwise, fall back to the class we were told to
+    /// expect, if any.
+    fn recv_class(&mut self, protocol: &mut P) -> Option<BandwidthClass> {
+        match self.preamble.as_ref() {
+            Some(preamble) => {
+                if self.message_class.is_none() {
+                    let payload_prefix = self.buf.get(self.message_ptr..).unwrap_or(&[]);
+                    self.message_class = protocol.recv_class(preamble, payload_prefix);
+                }
+            }
+            None => {
+                self.message_class = None;
+            }
+        }
+        self.message_class.or(self.class)
+    }
+
+    /// Get the class to read the next bytes at, and how many bytes to read at most.
+    /// Until we know what kind of message is arriving, we read it as if it were high-priority,
+    /// so that high-priority messages can start arriving even when the buckets are low.  But we
+    /// only read enough of it to find out what it is: the rest of its preamble, and then a few
+    /// bytes at a time of its payload.
+    fn recv_limits(&mut self, protocol: &mut P) -> (BandwidthClass, usize) {
+        if let Some(class) = self.recv_class(protocol) {
+            return (class, usize::MAX);
+        }
+        let Some(preamble) = self.preamble.as_ref() else {
+            let preamble_left = protocol
+                .preamble_size_hint()
+                .saturating_sub(self.buf.len())
+                .max(1);
+            return (BandwidthClass::High, preamble_left);
+        };
+        match protocol.payload_len(preamble) {
+            Some(payload_len) => {
+                let payload_read = self.buf.len().saturating_sub(self.message_ptr);
+                let payload_left = payload_len.saturating_sub(payload_read).max(1);
+                (BandwidthClass::High, payload_left.min(RECV_CLASS_PROBE_LEN))
+            }
+            // can't tell where this message ends, so don't bother
+            None => (BandwidthClass::Normal, usize::MAX),
+        }
     }
 
     /// Read bytes from an input stream, buffer them up, try to parse the buffer
@@ -923,13 +1136,31 @@ impl<P: ProtocolFamily> ConnectionInbox<P> {
         let mut blocked = false;
         let mut total_read = 0;
         let mut socket_closed = false;
+        self.throttled = false;
         while !blocked {
             // get the next bytes
             // NOTE: it's important that buf not be too big, since up to buf.len()-1 bytes may need
             // to be copied if a message boundary isn't aligned with buf (which is usually the
             // case).
             let mut buf = [0u8; 4096];
-            let num_read = match fd.read(&mut buf) {
+            let mut read_len = buf.len();
+            if self.limiter.is_limited() {
+                let (class, max_len) = self.recv_limits(protocol);
+                let budget = self
+                    .limiter
+                    .budget(class, get_epoch_time_ms())
+                    .unwrap_or(u64::MAX);
+                if budget == 0 {
+                    // out of bandwidth for now.  The rest stays in the socket until the buckets
+                    // refill.
+                    self.throttled = true;
+                    break;
+                }
+                read_len = read_len
+                    .min(max_len)
+                    .min(usize::try_from(budget).unwrap_or(usize::MAX));
+            }
+            let num_read = match fd.read(&mut buf[0..read_len]) {
                 Ok(0) => {
                     // remote fd is closed, but do try to consume all remaining bytes in the buffer
                     socket_closed = true;
@@ -965,6 +1196,8 @@ impl<P: ProtocolFamily> ConnectionInbox<P> {
             }
 
             if num_read > 0 {
+                self.limiter.consume(num_read as u64);
+
                 // decode into message stream
                 self.consume_messages(protocol, &buf[0..num_read])?;
             }
@@ -996,7 +1229,10 @@ impl<P: ProtocolFamily> ConnectionOutbox<P> {
             pending_message_fd: None,
             socket_out_buf: vec![],
             socket_out_ptr: 0,
+            session: None,
             inflight: VecDeque::new(),
+            limiter: BandwidthLimiter::unlimited(),
+            throttled: false,
         }
     }
 
@@ -1030,14 +1266,26 @@ impl<P: ProtocolFamily> ConnectionOutbox<P> {
                 if receiver_notify.notify.is_some() {
                     self.inflight.push_back(receiver_notify.notify.unwrap());
                 }
+                if receiver_notify.session.is_some() {
+                    // everything after this message gets encrypted
+                    test_debug!("Begin encrypted session");
+                    self.session = receiver_notify.session;
+                }
             }
         }
     }
 
+    /// Queue a message to be sent.
+    /// If `reorder` is true, it goes ahead of any lower-class messages that haven't started
+    /// sending yet, unless they begin an encrypted session (since everything queued behind such
+    /// a message must be sent encrypted).
     fn queue_message(
         &mut self,
         pipe_read: PipeRead,
         recv_notify: Option<ReceiverNotify<P>>,
+        session: Option<SessionCipher>,
+        class: BandwidthClass,
+        reorder: bool,
     ) -> Result<(), net_error> {
         if self.outbox.len() > self.outbox_maxlen {
             test_debug!(
@@ -1051,11 +1299,33 @@ impl<P: ProtocolFamily> ConnectionOutbox<P> {
         let inflight = InflightMessage {
             pipe_read: Some(pipe_read),
             notify: recv_notify,
+            session,
+            class,
         };
-        self.outbox.push_back(inflight);
+        let mut index = self.outbox.len();
+        if reorder {
+            while index > 0 {
+                let prev = &self.outbox[index - 1];
+                if prev.class >= class || prev.session.is_some() || prev.pipe_read.is_none() {
+                    break;
+                }
+                index -= 1;
+            }
+        }
+        self.outbox.insert(index, inflight);
         Ok(())
     }
 
+    /// Get the class to send the bytes of the current message at.  This is the highest class of
+    /// all queued messages, since they all have to wait for the current message to finish.
+    fn send_class(&self) -> BandwidthClass {
+        self.outbox
+            .iter()
+            .map(|inflight| inflight.class)
+            .max()
+            .unwrap_or(BandwidthClass::Normal)
+    }
+
     /// Write queued messages to the given W
     /// Returns number of bytes sent out to fd.
     fn send_bytes<W: Write>(&mut self, fd: &mut W) -> Result<usize, net_error> {
@@ -1063,6 +1333,7 @@ impl<P: ProtocolFamily> ConnectionOutbox<P> {
         let mut blocked = false;
         let mut disconnected = false;
         let mut message_eof = false;
+        self.throttled = false;
         while !blocked && !disconnected && !message_eof {
             if self.pending_message_fd.is_none() {
                 self.pending_message_fd = self.begin_next_message();
@@ -1111,7 +1382,15 @@ impl<P: ProtocolFamily> ConnectionOutbox<P> {
                         },
                     };
 
-                    self.socket_out_buf.extend_from_slice(&buf[0..nr_input]);
+                    match self.session.as_mut() {
+                        Some(session) if nr_input > 0 => {
+                            let frame = session.seal(&buf[0..nr_input])?;
+                            self.socket_out_buf.extend_from_slice(&frame);
+                        }
+                        _ => {
+                            self.socket_out_buf.extend_from_slice(&buf[0..nr_input]);
+                        }
+                    }
 
                     test_debug!(
                         "Connection buffered {} bytes from pipe ({} total, ptr = {}, blocked = {})",
@@ -1131,8 +1410,19 @@ impl<P: ProtocolFamily> ConnectionOutbox<P> {
 
             if self.socket_out_ptr < self.socket_out_buf.len() {
                 // have pending bytes.
-                // send as many bytes as we can
-                let num_written_res = fd.write(&self.socket_out_buf[self.socket_out_ptr..]);
+                // send as many bytes as we can, and as the bandwidth limit allows
+                let mut send_end = self.socket_out_buf.len();
+                if let Some(budget) = self.limiter.budget(self.send_class(), get_epoch_time_ms()) {
+                    if budget == 0 {
+                        // out of bandwidth for now.  The rest stays buffered until the buckets
+                        // refill.
+                        self.throttled = true;
+                        break;
+                    }
+                    send_end = send_end
+                        .min(self.socket_out_ptr + usize::try_from(budget).unwrap_or(usize::MAX));
+                }
+                let num_written_res = fd.write(&self.socket_out_buf[self.socket_out_ptr..send_end]);
                 let num_written = match num_written_res {
                     Ok(0) => {
                         // indicates that the remote peer is no longer receiving
@@ -1157,6 +1447,7 @@ impl<P: ProtocolFamily> ConnectionOutbox<P> {
                 }?;
 
                 self.socket_out_ptr += num_written;
+                self.limiter.consume(num_written as u64);
 
                 test_debug!(
                     "Connection wrote {} bytes to socket (buffer len = {}, ptr = {})",
@@ -1325,6 +1616,7 @@ impl<P: ProtocolFamily + Clone> NetworkConnection<P> {
         request_id: u32,
         timeout: u64,
         socket_event_id: usize,
+        class: BandwidthClass,
     ) -> Result<NetworkReplyHandle<P>, net_error> {
         let (send_ch, recv_ch) = sync_channel(1);
         let recv_notify = ReceiverNotify::new(request_id, send_ch, timeout + get_epoch_time_secs());
@@ -1333,7 +1625,9 @@ impl<P: ProtocolFamily + Clone> NetworkConnection<P> {
         let mut recv_handle = NetworkReplyHandle::new(recv_ch, pipe_write, socket_event_id);
         recv_handle.set_deadline(timeout + get_epoch_time_secs());
 
-        self.outbox.queue_message(pipe_read, Some(recv_notify))?;
+        let reorder = self.protocol.can_reorder_messages();
+        self.outbox
+            .queue_message(pipe_read, Some(recv_notify), None, class, reorder)?;
         Ok(recv_handle)
     }
 
@@ -1342,9 +1636,39 @@ impl<P: ProtocolFamily + Clone> NetworkConnection<P> {
     pub fn make_relay_handle(
         &mut self,
         socket_event_id: usize,
+        class: BandwidthClass,
+    ) -> Result<NetworkReplyHandle<P>, net_error> {
+        let (pipe_read, pipe_write) = Pipe::new();
+        let reorder = self.protocol.can_reorder_messages();
+        self.outbox
+            .queue_message(pipe_read, None, None, class, reorder)?;
+
+        let send_handle = NetworkReplyHandle::new_relay(pipe_write, socket_event_id);
+        Ok(send_handle)
+    }
+
+    /// Forward a message that begins our encrypted session, and expect no reply.
+    /// All bytes sent after this message will be sealed with `session`.
+    /// Returns a Write-able handle into which the message should be written, and flushed.
+    pub fn make_session_handle(
+        &mut self,
+        socket_event_id: usize,
+        session: SessionCipher,
     ) -> Result<NetworkReplyHandle<P>, net_error> {
+        if self.is_outbound_encrypted() {
+            // can only begin one session per connection
+            return Err(net_error::InvalidState);
+        }
+
         let (pipe_read, pipe_write) = Pipe::new();
-        self.outbox.queue_message(pipe_read, None)?;
+        let reorder = self.protocol.can_reorder_messages();
+        self.outbox.queue_message(
+            pipe_read,
+            None,
+            Some(session),
+            BandwidthClass::Normal,
+            reorder,
+        )?;
 
         let send_handle = NetworkReplyHandle::new_relay(pipe_write, socket_event_id);
         Ok(send_handle)
@@ -1360,6 +1684,29 @@ impl<P: ProtocolFamily + Clone> NetworkConnection<P> {
         self.inbox.recv_bytes(&mut self.protocol, fd)
     }
 
+    /// Limit how fast this connection may receive and send data
+    pub fn set_bandwidth_limiters(
+        &mut self,
+        inbound: BandwidthLimiter,
+        outbound: BandwidthLimiter,
+    ) {
+        self.inbox.limiter = inbound;
+        self.outbox.limiter = outbound;
+    }
+
+    /// Set the priority class of the data we expect to receive next, for messages whose class
+    /// the protocol can't tell from the message itself (i.e. HTTP responses)
+    pub fn set_recv_class(&mut self, class: BandwidthClass) {
+        self.inbox.class = Some(class);
+    }
+
+    /// Did the last read or write stop early because we ran out of bandwidth?
+    /// If so, the socket must be serviced again once the buckets refill, even if it doesn't
+    /// become ready again.
+    pub fn is_throttled(&self) -> bool {
+        self.inbox.throttled || self.outbox.throttled
+    }
+
     /// how many inbox messages pending?
     pub fn inbox_len(&self) -> usize {
         self.inbox.num_messages()
@@ -1380,6 +1727,22 @@ impl<P: ProtocolFamily + Clone> NetworkConnection<P> {
         self.inbox.public_key = pubk;
     }
 
+    /// Set our private key, with which we accept the remote peer's encrypted session.  If not
+    /// set, the remote peer may not begin one.
+    pub fn set_local_private_key(&mut self, privk: Option<Secp256k1PrivateKey>) -> () {
+        self.inbox.local_private_key = privk;
+    }
+
+    /// Has the remote peer begun an encrypted session?
+    pub fn is_inbound_encrypted(&self) -> bool {
+        self.inbox.session.is_some()
+    }
+
+    /// Have we begun (or queued the start of) an encrypted session?
+    pub fn is_outbound_encrypted(&self) -> bool {
+        self.outbox.session.is_some() || self.outbox.outbox.iter().any(|m| m.session.is_some())
+    }
+
     /// Get a copy of the public key
     pub fn get_public_key(&self) -> Option<Secp256k1PublicKey> {
         match self.inbox.public_key {
@@ -1429,6 +1792,8 @@ mod test {
 
     use super::*;
     use crate::chainstate::stacks::test::make_codec_test_block;
+    use crate::core::mempool::TxTag;
+    use crate::net::encryption::SessionCipher;
     use crate::net::http::*;
     use crate::net::test::{make_tcp_sockets, NetCursor};
     use crate::net::*;
@@ -1459,7 +1824,7 @@ mod test {
 
         let mut pipes = vec![]; // keep pipes in-scope
         for i in 0..conn.options.outbox_maxlen {
-            let pipe = conn.make_relay_handle(0).unwrap();
+            let pipe = conn.make_relay_handle(0, BandwidthClass::Normal).unwrap();
             pipes.push(pipe);
         }
 
@@ -1618,7 +1983,7 @@ mod test {
         let mut handles = vec![]; // keep pipes in-scope
         for i in 0..conn.options.outbox_maxlen {
             let handle = conn
-                .make_request_handle(messages[i].request_id(), 60, 0)
+                .make_request_handle(messages[i].request_id(), 60, 0, BandwidthClass::Normal)
                 .unwrap();
             handles.push(handle);
         }
@@ -1827,7 +2192,7 @@ mod test {
         let mut pipes = vec![]; // keep pipes in-scope
         for i in 0..5 {
             test_debug!("Write ping {}", i);
-            let mut pipe = conn.make_relay_handle(0).unwrap();
+            let mut pipe = conn.make_relay_handle(0, BandwidthClass::Normal).unwrap();
             ping.consensus_serialize(&mut pipe).unwrap();
             pipes.push(pipe);
         }
@@ -1995,7 +2360,7 @@ mod test {
                 tmp.len()
             };
 
-            let mut pipe = conn.make_relay_handle(0).unwrap();
+            let mut pipe = conn.make_relay_handle(0, BandwidthClass::Normal).unwrap();
             ping.consensus_serialize(&mut pipe).unwrap();
             pipes.push(pipe);
             ping_vec.push(ping);
@@ -2097,7 +2462,9 @@ mod test {
                     tmp.len()
                 };
 
-                let mut handle = conn.make_request_handle(ping.request_id(), 60, 0).unwrap();
+                let mut handle = conn
+                    .make_request_handle(ping.request_id(), 60, 0, BandwidthClass::Normal)
+                    .unwrap();
                 ping.consensus_serialize(&mut handle).unwrap();
 
                 handle_vec.push(handle);
@@ -2211,7 +2578,9 @@ mod test {
             };
 
             // 1-second timeout
-            let mut handle = conn.make_request_handle(ping.request_id(), 1, 0).unwrap();
+            let mut handle = conn
+                .make_request_handle(ping.request_id(), 1, 0, BandwidthClass::Normal)
+                .unwrap();
             ping.consensus_serialize(&mut handle).unwrap();
 
             handle_vec.push(handle);
@@ -2276,4 +2645,313 @@ mod test {
 
         pinger.join().unwrap();
     }
+
+    /// Send some pings from `sender` to a byte buffer, where all but the first are sent in an
+    /// encrypted session begun with `session_key_privkey`.  Returns the wire bytes and the
+    /// messages sent.
+    fn send_encrypted_pings(
+        sender: &mut ConnectionP2P,
+        privkey: &Secp256k1PrivateKey,
+        session_key_privkey: &Secp256k1PrivateKey,
+        remote_pubkey: &Secp256k1PublicKey,
+    ) -> (Vec<u8>, Vec<StacksMessage>) {
+        let make_message = |i: u32, payload: StacksMessageType, signer: &Secp256k1PrivateKey| {
+            let mut msg = StacksMessage::new(
+                0x12345678,
+                0x9abcdef0,
+                12345 + (i as u64),
+                &BurnchainHeaderHash([0x11; 32]),
+                12339 + (i as u64),
+                &BurnchainHeaderHash([0x22; 32]),
+                payload,
+            );
+            msg.sign(i, signer).unwrap();
+            msg
+        };
+
+        let mut msgs = vec![];
+        let mut handles = vec![];
+
+        let ping = make_message(0, StacksMessageType::Ping(PingData { nonce: 0 }), privkey);
+        let mut handle = sender.make_relay_handle(0, BandwidthClass::Normal).unwrap();
+        ping.consensus_serialize(&mut handle).unwrap();
+        handles.push(handle);
+        msgs.push(ping);
+
+        let (session_key_data, session) =
+            SessionCipher::begin(session_key_privkey, remote_pubkey).unwrap();
+        let session_key = make_message(
+            1,
+            StacksMessageType::SessionKey(session_key_data),
+            session_key_privkey,
+        );
+        let mut handle = sender.make_session_handle(0, session).unwrap();
+        session_key.consensus_serialize(&mut handle).unwrap();
+        handles.push(handle);
+        msgs.push(session_key);
+
+        // only one session per connection
+        let (_, session) = SessionCipher::begin(session_key_privkey, remote_pubkey).unwrap();
+        assert!(sender.make_session_handle(0, session).is_err());
+
+        for i in 2..5 {
+            let ping = make_message(i, StacksMessageType::Ping(PingData { nonce: i }), privkey);
+            let mut handle = sender.make_relay_handle(0, BandwidthClass::Normal).unwrap();
+            ping.consensus_serialize(&mut handle).unwrap();
+            handles.push(handle);
+            msgs.push(ping);
+        }
+
+        let mut wire = vec![];
+        while handles.len() > 0 || sender.outbox_len() > 0 {
+            handles.retain_mut(|h| !h.try_flush().unwrap());
+            sender.send_data(&mut wire).unwrap();
+        }
+        (wire, msgs)
+    }
+
+    #[test]
+    fn connection_encrypted_session_send_recv() {
+        let privkey_1 = Secp256k1PrivateKey::new();
+        let privkey_2 = Secp256k1PrivateKey::new();
+        let pubkey_1 = Secp256k1PublicKey::from_private(&privkey_1);
+        let pubkey_2 = Secp256k1PublicKey::from_private(&privkey_2);
+
+        let mut conn_opts = ConnectionOptions::default();
+        conn_opts.inbox_maxlen = 10;
+        conn_opts.outbox_maxlen = 10;
+
+        let mut conn_1 = ConnectionP2P::new(StacksP2P::new(), &conn_opts, Some(pubkey_2.clone()));
+        let (wire, msgs) = send_encrypted_pings(&mut conn_1, &privkey_1, &privkey_1, &pubkey_2);
+        assert!(conn_1.is_outbound_encrypted());
+
+        // encrypted pings are not visible on the wire
+        for msg in msgs[2..].iter() {
+            let plaintext = msg.serialize_to_vec();
+            assert!(!wire.windows(plaintext.len()).any(|w| w == &plaintext[..]));
+        }
+
+        // receiver doesn't know conn_1's public key yet (i.e. it hasn't processed the
+        // handshake), but can still decode everything in one pass, or one byte at a time.
+        for chunk_size in [wire.len(), 1] {
+            let mut conn_2 = ConnectionP2P::new(StacksP2P::new(), &conn_opts, None);
+            conn_2.set_local_private_key(Some(privkey_2.clone()));
+            for chunk in wire.chunks(chunk_size) {
+                let mut fd = io::Cursor::new(chunk);
+                conn_2.recv_data(&mut fd).unwrap();
+            }
+            assert!(conn_2.is_inbound_encrypted());
+            assert_eq!(conn_2.drain_inbox(), msgs);
+        }
+
+        // a receiver that doesn't accept encrypted sessions rejects it
+        let mut conn_2 = ConnectionP2P::new(StacksP2P::new(), &conn_opts, None);
+        let mut fd = io::Cursor::new(&wire[..]);
+        assert!(conn_2.recv_data(&mut fd).is_err());
+
+        // a receiver that isn't the intended recipient can't decode it
+        let mut conn_3 = ConnectionP2P::new(StacksP2P::new(), &conn_opts, None);
+        conn_3.set_local_private_key(Some(Secp256k1PrivateKey::new()));
+        let mut fd = io::Cursor::new(&wire[..]);
+        assert!(conn_3.recv_data(&mut fd).is_err());
+
+        // a session begun by a node other than the one we handshaked with is rejected
+        let mut conn_4 = ConnectionP2P::new(StacksP2P::new(), &conn_opts, None);
+        let (wire, _) = send_encrypted_pings(
+            &mut conn_4,
+            &privkey_1,
+            &Secp256k1PrivateKey::new(),
+            &pubkey_2,
+        );
+        let mut conn_5 = ConnectionP2P::new(StacksP2P::new(), &conn_opts, Some(pubkey_1));
+        conn_5.set_local_private_key(Some(privkey_2.clone()));
+        let mut fd = io::Cursor::new(&wire[..]);
+        assert!(conn_5.recv_data(&mut fd).is_err());
+    }
+
+    #[test]
+    fn connection_bandwidth_limited_send() {
+        let privkey = Secp256k1PrivateKey::new();
+
+        let mut conn_opts = ConnectionOptions::default();
+        conn_opts.inbox_maxlen = 100;
+        conn_opts.outbox_maxlen = 100;
+
+        let make_pings = |conn: &mut ConnectionP2P, class: BandwidthClass| {
+            let mut msgs = vec![];
+            let mut handles = vec![];
+            for i in 0..20 {
+                let mut ping = StacksMessage::new(
+                    0x12345678,
+                    0x9abcdef0,
+                    12345,
+                    &BurnchainHeaderHash([0x11; 32]),
+                    12339,
+                    &BurnchainHeaderHash([0x22; 32]),
+                    StacksMessageType::Ping(PingData { nonce: i }),
+                );
+                ping.sign(i, &privkey).unwrap();
+
+                let mut handle = conn.make_relay_handle(0, class).unwrap();
+                ping.consensus_serialize(&mut handle).unwrap();
+                handles.push(handle);
+                msgs.push(ping);
+            }
+            (msgs, handles)
+        };
+
+        // normal-priority traffic can use 3/4 of the bucket.  The buckets refill by a byte a
+        // millisecond, so allow for some slack while the test runs.
+        let mut conn_1 = ConnectionP2P::new(StacksP2P::new(), &conn_opts, None);
+        conn_1.set_bandwidth_limiters(
+            BandwidthLimiter::unlimited(),
+            BandwidthLimiter::new(1000, None),
+        );
+        let (msgs, mut handles) = make_pings(&mut conn_1, BandwidthClass::Normal);
+        let ping_size = msgs[0].serialize_to_vec().len();
+        assert!(ping_size * msgs.len() > 1000);
+
+        let mut wire = vec![];
+        while !conn_1.is_throttled() {
+            handles.retain_mut(|h| !h.try_flush().unwrap());
+            conn_1.send_data(&mut wire).unwrap();
+        }
+        assert!(wire.len() >= 750 && wire.len() < 850);
+
+        // high-priority traffic can use all of it
+        let mut conn_2 = ConnectionP2P::new(StacksP2P::new(), &conn_opts, None);
+        conn_2.set_bandwidth_limiters(
+            BandwidthLimiter::unlimited(),
+            BandwidthLimiter::new(1000, None),
+        );
+        let (_, mut handles) = make_pings(&mut conn_2, BandwidthClass::High);
+
+        let mut high_wire = vec![];
+        while !conn_2.is_throttled() {
+            handles.retain_mut(|h| !h.try_flush().unwrap());
+            conn_2.send_data(&mut high_wire).unwrap();
+        }
+        assert!(high_wire.len() >= 1000 && high_wire.len() < 1100);
+    }
+
+    /// Make a signed P2P message that's bigger than the 1000-byte buckets used below
+    fn make_big_message(
+        payload: StacksMessageType,
+        privkey: &Secp256k1PrivateKey,
+    ) -> StacksMessage {
+        let mut msg = StacksMessage::new(
+            0x12345678,
+            0x9abcdef0,
+            12345,
+            &BurnchainHeaderHash([0x11; 32]),
+            12339,
+            &BurnchainHeaderHash([0x22; 32]),
+            payload,
+        );
+        msg.sign(1, privkey).unwrap();
+        assert!(msg.serialize_to_vec().len() > 1100);
+        msg
+    }
+
+    fn make_big_tx_inv(privkey: &Secp256k1PrivateKey) -> StacksMessage {
+        make_big_message(
+            StacksMessageType::TxInv(TxInvData {
+                seed: [0x33; 32],
+                tx_tags: vec![TxTag([0x44; 8]); 256],
+            }),
+            privkey,
+        )
+    }
+
+    fn make_big_blocks_available(privkey: &Secp256k1PrivateKey) -> StacksMessage {
+        make_big_message(
+            StacksMessageType::BlocksAvailable(BlocksAvailableData {
+                available: vec![
+                    (ConsensusHash([0x55; 20]), BurnchainHeaderHash([0x66; 32]));
+                    BLOCKS_AVAILABLE_MAX_LEN as usize
+                ],
+            }),
+            privkey,
+        )
+    }
+
+    #[test]
+    fn connection_bandwidth_limited_recv_by_message_class() {
+        let privkey = Secp256k1PrivateKey::new();
+        let pubkey = Secp256k1PublicKey::from_private(&privkey);
+        let conn_opts = ConnectionOptions::default();
+
+        // a message is read at its own class once we know what it is.  Transaction gossip stops
+        // at half the bucket, and blocks can use all of it.
+        for (msg, min_read, max_read) in [
+            (make_big_tx_inv(&privkey), 500, 600),
+            (make_big_blocks_available(&privkey), 1000, 1100),
+        ] {
+            let wire = msg.serialize_to_vec();
+            let mut conn = ConnectionP2P::new(StacksP2P::new(), &conn_opts, Some(pubkey.clone()));
+            conn.set_bandwidth_limiters(
+                BandwidthLimiter::new(1000, None),
+                BandwidthLimiter::unlimited(),
+            );
+            let mut fd = io::Cursor::new(&wire[..]);
+            let num_read = conn.recv_data(&mut fd).unwrap();
+            assert!(num_read >= min_read && num_read < max_read);
+            assert!(conn.is_throttled());
+
+            // the rest stays in the socket
+            assert_eq!(fd.position() as usize, num_read);
+        }
+    }
+
+    #[test]
+    fn connection_bandwidth_limited_send_by_priority() {
+        let privkey = Secp256k1PrivateKey::new();
+        let conn_opts = ConnectionOptions::default();
+        let tx_inv = make_big_tx_inv(&privkey).serialize_to_vec();
+        let blocks_available = make_big_blocks_available(&privkey).serialize_to_vec();
+
+        let queue = |conn: &mut ConnectionP2P, bytes: &[u8], class: BandwidthClass| {
+            let mut handle = conn.make_relay_handle(0, class).unwrap();
+            handle.write_all(bytes).unwrap();
+            while !handle.try_flush().unwrap() {}
+        };
+        let send_until_throttled = |conn: &mut ConnectionP2P, wire: &mut Vec<u8>| {
+            while !conn.is_throttled() {
+                conn.send_data(wire).unwrap();
+            }
+        };
+
+        // a high-priority message goes ahead of a low-priority one that hasn't started yet
+        let mut conn = ConnectionP2P::new(StacksP2P::new(), &conn_opts, None);
+        conn.set_bandwidth_limiters(
+            BandwidthLimiter::unlimited(),
+            BandwidthLimiter::new(1000, None),
+        );
+        queue(&mut conn, &tx_inv, BandwidthClass::Low);
+        queue(&mut conn, &blocks_available, BandwidthClass::High);
+
+        let mut wire = vec![];
+        send_until_throttled(&mut conn, &mut wire);
+        assert!(wire.len() >= 1000 && wire.len() < 1100);
+        assert_eq!(wire[..], blocks_available[..wire.len()]);
+
+        // a low-priority message that has started sending can't be preempted, but it gets to
+        // finish at the priority of the message waiting on it
+        let mut conn = ConnectionP2P::new(StacksP2P::new(), &conn_opts, None);
+        conn.set_bandwidth_limiters(
+            BandwidthLimiter::unlimited(),
+            BandwidthLimiter::new(1000, None),
+        );
+        queue(&mut conn, &tx_inv, BandwidthClass::Low);
+
+        let mut wire = vec![];
+        send_until_throttled(&mut conn, &mut wire);
+        assert!(wire.len() >= 500 && wire.len() < 600);
+
+        queue(&mut conn, &blocks_available, BandwidthClass::High);
+        conn.send_data(&mut wire).unwrap();
+        send_until_throttled(&mut conn, &mut wire);
+        assert!(wire.len() >= 1000 && wire.len() < 1100);
+        assert_eq!(wire[..], tx_inv[..wire.len()]);
+    }
 }
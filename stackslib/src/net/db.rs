@@ -38,6 +38,10 @@ use crate::burnchains::{PrivateKey, PublicKey};
 use crate::chainstate::stacks::{StacksPrivateKey, StacksPublicKey};
 use crate::core::NETWORK_P2P_PORT;
 use crate::net::asn::ASEntry4;
+use crate::net::reputation::{
+    decay_reputation, reputation_exclude_until, REPUTATION_EXPIRE_SECS, REPUTATION_MAX,
+    REPUTATION_MIN,
+};
 use crate::net::{Neighbor, NeighborAddress, NeighborKey, ServiceFlags};
 use crate::util_lib::db::{
     query_count, query_row, query_rows, sqlite_open, tx_begin_immediate, tx_busy_handler,
@@ -45,7 +49,7 @@ use crate::util_lib::db::{
 };
 use crate::util_lib::strings::UrlString;
 
-pub const PEERDB_VERSION: &'static str = "2";
+pub const PEERDB_VERSION: &'static str = "3";
 
 const NUM_SLOTS: usize = 8;
 
@@ -401,6 +405,30 @@ const PEERDB_SCHEMA_2: &'static [&'static str] = &[
     "#,
 ];
 
+// Reputation is keyed by a peer's public key, which it proves it owns in its handshake (unlike
+// the address it advertises), in the same hex encoding as the frontier's `public_key` column.
+// It is kept in its own table so that it outlives the peer's frontier slot.  `score` is as of
+// `last_update`; it decays towards 0 with time.  `exclude_until` is when the decayed score will
+// rise above `REPUTATION_BAD_THRESHOLD`, so queries can skip bad peers without computing the decay.
+const PEERDB_SCHEMA_3: &'static [&'static str] = &[
+    r#"
+    CREATE TABLE peer_reputation(
+        network_id INTEGER NOT NULL,
+        public_key TEXT NOT NULL,
+        score REAL NOT NULL,
+        last_update INTEGER NOT NULL,
+        exclude_until INTEGER NOT NULL,
+        PRIMARY KEY(network_id,public_key)
+    );
+    "#,
+    r#"
+    CREATE INDEX IF NOT EXISTS index_peer_reputation_by_last_update ON peer_reputation(last_update);
+    "#,
+    r#"
+    UPDATE db_config SET version = 3;
+    "#,
+];
+
 #[derive(Debug)]
 pub struct PeerDB {
     pub conn: Connection,
@@ -526,6 +554,14 @@ impl PeerDB {
         Ok(())
     }
 
+    fn apply_schema_3(tx: &Transaction) -> Result<(), db_error> {
+        test_debug!("Apply schema 3 to peer DB");
+        for row_text in PEERDB_SCHEMA_3 {
+            tx.execute_batch(row_text).map_err(db_error::SqliteError)?;
+        }
+        Ok(())
+    }
+
     fn apply_schema_migrations(tx: &Transaction) -> Result<String, db_error> {
         test_debug!("Apply any schema migrations");
         let expected_version = PEERDB_VERSION.to_string();
@@ -538,6 +574,8 @@ impl PeerDB {
                     }
                     if version == "1" {
                         PeerDB::apply_schema_2(tx)?;
+                    } else if version == "2" {
+                        PeerDB::apply_schema_3(tx)?;
                     } else if version == expected_version {
                         return Ok(ret.expect("unreachable"));
                     } else {
@@ -692,6 +730,7 @@ impl PeerDB {
             PeerDB::refresh_allows(&tx)?;
             PeerDB::refresh_denies(&tx)?;
             PeerDB::clear_initial_peers(&tx)?;
+            PeerDB::expire_peer_reputations(&tx, get_epoch_time_secs())?;
             if let Some(privkey) = privkey_opt {
                 PeerDB::set_local_private_key(&tx, &privkey, key_expires)?;
             }
@@ -1032,6 +1071,19 @@ impl PeerDB {
         Ok(allow_rows)
     }
 
+    /// Get the peers whose deny deadline has not yet passed.
+    /// Peers denied through a CIDR prefix are included.
+    pub fn get_denied_peers(
+        conn: &DBConn,
+        network_id: u32,
+        now: u64,
+    ) -> Result<Vec<Neighbor>, db_error> {
+        let sql = "SELECT * FROM frontier WHERE (denied < 0 OR denied > ?1) AND network_id = ?2";
+        let args: &[&dyn ToSql] = &[&u64_to_sql(now)?, &network_id];
+        let denied_rows = query_rows::<Neighbor, _>(conn, sql, args)?;
+        Ok(denied_rows)
+    }
+
     /// Insert or replace stacker DB contract IDs for a peer, given its slot
     pub fn insert_or_replace_stacker_dbs(
         tx: &Transaction,
@@ -1155,6 +1207,36 @@ impl PeerDB {
         Ok(())
     }
 
+    /// Add a bootstrap peer at runtime.  The peer is inserted (or updated) and marked as an
+    /// initial peer.  Like the bootstrap peers given at startup, it is cleared on the next
+    /// `connect()` unless it is also passed in `initial_neighbors`.
+    pub fn add_bootstrap_peer(tx: &Transaction, neighbor: &Neighbor) -> Result<(), db_error> {
+        let mut neighbor = neighbor.clone();
+        neighbor.last_contact_time = get_epoch_time_secs();
+
+        debug!("Add bootstrap peer {:?}", &neighbor.addr);
+        if !PeerDB::try_insert_peer(tx, &neighbor, &[])? {
+            let mut slots = PeerDB::peer_slots(
+                tx,
+                neighbor.addr.network_id,
+                &neighbor.addr.addrbytes,
+                neighbor.addr.port,
+            )?;
+            let slot = slots.pop().expect("BUG: no slots");
+            warn!(
+                "Forcing replacement of peer at slot {} for bootstrap peer {:?}",
+                slot, &neighbor.addr
+            );
+            PeerDB::insert_or_replace_peer(tx, &neighbor, slot)?;
+        }
+        PeerDB::set_initial_peer(
+            tx,
+            neighbor.addr.network_id,
+            &neighbor.addr.addrbytes,
+            neighbor.addr.port,
+        )
+    }
+
     /// clear all initial peers
     fn clear_initial_peers(tx: &Transaction) -> Result<(), db_error> {
         tx.execute("UPDATE frontier SET initial = 0", NO_PARAMS)
@@ -1598,6 +1680,32 @@ impl PeerDB {
         Ok(())
     }
 
+    /// Remove a denied CIDR prefix.
+    /// Peers that were only denied by this prefix are un-denied; peers that are still covered by
+    /// another denied prefix stay denied, and peers with their own deny deadline keep it.
+    pub fn remove_deny_cidr(
+        tx: &Transaction,
+        prefix: &PeerAddress,
+        mask: u32,
+    ) -> Result<(), db_error> {
+        assert!(mask > 0 && mask <= 128);
+        PeerDB::remove_cidr_prefix(tx, "denied_prefixes", prefix, mask)?;
+
+        debug!("Remove deny {}/{}", &prefix, mask);
+        let prefix_txt = PeerDB::cidr_prefix_to_string(prefix, mask);
+        let args: &[&dyn ToSql] = &[&i64::MAX, &mask, &prefix_txt];
+        tx.execute(
+            "UPDATE frontier SET denied = 0 WHERE denied = ?1 AND SUBSTR(addrbytes,1,?2) = SUBSTR(?3,1,?2)",
+            args,
+        )
+        .map_err(db_error::SqliteError)?;
+
+        for (prefix, mask) in PeerDB::get_denied_cidrs(tx)?.into_iter() {
+            PeerDB::apply_cidr_filter(tx, &prefix, mask, "denied", i64::MAX)?;
+        }
+        Ok(())
+    }
+
     /// Get random neighbors, optionally always including allowed neighbors
     pub fn get_random_neighbors(
         conn: &DBConn,
@@ -1657,13 +1765,19 @@ impl PeerDB {
             return Ok(ret);
         }
 
-        // fill in with non-allowed, randomly-chosen, fresh peers
+        // fill in with non-allowed, randomly-chosen, fresh peers that don't have a bad reputation
         let random_peers_qry = if always_include_allowed {
             "SELECT * FROM frontier WHERE network_id = ?1 AND last_contact_time >= ?2 AND ?3 < expire_block_height AND denied < ?4 AND \
-                 (allowed >= 0 AND allowed <= ?5) AND (peer_version & 0x000000ff) >= ?6 ORDER BY RANDOM() LIMIT ?7"
+                 (allowed >= 0 AND allowed <= ?5) AND (peer_version & 0x000000ff) >= ?6 AND \
+                 NOT EXISTS (SELECT 1 FROM peer_reputation WHERE peer_reputation.network_id = frontier.network_id AND \
+                    peer_reputation.public_key = frontier.public_key AND peer_reputation.exclude_until > ?5) \
+                 ORDER BY RANDOM() LIMIT ?7"
         } else {
             "SELECT * FROM frontier WHERE network_id = ?1 AND last_contact_time >= ?2 AND ?3 < expire_block_height AND denied < ?4 AND \
-                 (allowed < 0 OR (allowed >= 0 AND allowed <= ?5)) AND (peer_version & 0x000000ff) >= ?6 ORDER BY RANDOM() LIMIT ?7"
+                 (allowed < 0 OR (allowed >= 0 AND allowed <= ?5)) AND (peer_version & 0x000000ff) >= ?6 AND \
+                 NOT EXISTS (SELECT 1 FROM peer_reputation WHERE peer_reputation.network_id = frontier.network_id AND \
+                    peer_reputation.public_key = frontier.public_key AND peer_reputation.exclude_until > ?5) \
+                 ORDER BY RANDOM() LIMIT ?7"
         };
 
         let random_peers_args: &[&dyn ToSql] = &[
@@ -1717,6 +1831,98 @@ impl PeerDB {
         )
     }
 
+    /// Get the peers that we have handshaked with since `min_contact_time`, in random order.
+    /// -- never includes denied peers, or peers whose reputation is too bad to walk to
+    /// -- only includes peers with the same major version as `peer_version`, that support at
+    /// least `network_epoch`
+    pub fn get_healthy_peers(
+        conn: &DBConn,
+        network_id: u32,
+        peer_version: u32,
+        network_epoch: u8,
+        min_contact_time: u64,
+        block_height: u64,
+    ) -> Result<Vec<Neighbor>, db_error> {
+        let now_secs = util::get_epoch_time_secs();
+        let qry = "SELECT * FROM frontier WHERE network_id = ?1 AND last_contact_time >= ?2 AND ?3 < expire_block_height AND denied < ?4 AND \
+                 (peer_version & 0xff000000) = ?5 AND (peer_version & 0x000000ff) >= ?6 AND \
+                 NOT EXISTS (SELECT 1 FROM peer_reputation WHERE peer_reputation.network_id = frontier.network_id AND \
+                    peer_reputation.public_key = frontier.public_key AND peer_reputation.exclude_until > ?4) \
+                 ORDER BY RANDOM()";
+        let args: &[&dyn ToSql] = &[
+            &network_id,
+            &u64_to_sql(min_contact_time)?,
+            &u64_to_sql(block_height)?,
+            &u64_to_sql(now_secs)?,
+            &(peer_version & 0xff000000),
+            &network_epoch,
+        ];
+        query_rows::<Neighbor, _>(conn, qry, args)
+    }
+
+    /// Get the reputation score as of `now` of the peer with the given public key.  Peers we have
+    /// no record of have a score of 0.
+    pub fn get_peer_reputation(
+        conn: &DBConn,
+        network_id: u32,
+        public_key: &StacksPublicKey,
+        now: u64,
+    ) -> Result<f64, db_error> {
+        let qry = "SELECT score, last_update FROM peer_reputation WHERE network_id = ?1 AND public_key = ?2";
+        let args: &[&dyn ToSql] = &[&network_id, &to_hex(&public_key.to_bytes_compressed())];
+        let row_opt = conn
+            .query_row(qry, args, |row| {
+                let score: f64 = row.get(0)?;
+                let last_update: i64 = row.get(1)?;
+                Ok((score, last_update))
+            })
+            .optional()
+            .map_err(db_error::SqliteError)?;
+
+        Ok(row_opt
+            .map(|(score, last_update)| {
+                decay_reputation(score, now.saturating_sub(last_update.max(0) as u64))
+            })
+            .unwrap_or(0.0))
+    }
+
+    /// Add `delta` points to the reputation score as of `now` of the peer with the given public
+    /// key, and return the new score.
+    pub fn update_peer_reputation(
+        tx: &Transaction,
+        network_id: u32,
+        public_key: &StacksPublicKey,
+        delta: i64,
+        now: u64,
+    ) -> Result<f64, db_error> {
+        let old_score = PeerDB::get_peer_reputation(tx, network_id, public_key, now)?;
+        let score = (old_score + (delta as f64)).clamp(REPUTATION_MIN, REPUTATION_MAX);
+        let args: &[&dyn ToSql] = &[
+            &network_id,
+            &to_hex(&public_key.to_bytes_compressed()),
+            &score,
+            &u64_to_sql(now)?,
+            &u64_to_sql(reputation_exclude_until(score, now))?,
+        ];
+        tx.execute(
+            "INSERT OR REPLACE INTO peer_reputation (network_id, public_key, score, last_update, exclude_until) VALUES (?1, ?2, ?3, ?4, ?5)",
+            args,
+        )
+        .map_err(db_error::SqliteError)?;
+        Ok(score)
+    }
+
+    /// Forget reputations that have decayed to (nearly) nothing
+    pub fn expire_peer_reputations(tx: &Transaction, now: u64) -> Result<(), db_error> {
+        let cutoff = now.saturating_sub(REPUTATION_EXPIRE_SECS);
+        tx.execute(
+            "DELETE FROM peer_reputation WHERE last_update < ?1",
+            &[&u64_to_sql(cutoff)?],
+        )
+        .map_err(db_error::SqliteError)?;
+        Ok(())
+    }
+
     /// Add an IPv4 <--> ASN mapping
     /// Used during db instantiation
     fn asn4_insert(tx: &Transaction, asn4: &ASEntry4) -> Result<(), db_error> {
@@ -1817,6 +2023,7 @@ mod test {
     use stacks_common::util::hash::Hash160;
 
     use super::*;
+    use crate::net::reputation::{ReputationEvent, REPUTATION_HALF_LIFE_SECS};
     use crate::net::{Neighbor, NeighborKey};
 
     /// Test storage, retrieval, and mutation of LocalPeer, including its stacker DB contract IDs
@@ -2952,6 +3159,140 @@ mod test {
         assert_eq!(n20.len(), 0);
     }
 
+    #[test]
+    fn test_peer_reputation() {
+        let now_secs = util::get_epoch_time_secs();
+        let mut initial_neighbors = vec![];
+        for i in 0..4 {
+            initial_neighbors.push(Neighbor {
+                addr: NeighborKey {
+                    peer_version: 0x18000000,
+                    network_id: 0x9abcdef0,
+                    addrbytes: PeerAddress([i as u8; 16]),
+                    port: i,
+                },
+                public_key: Secp256k1PublicKey::from_private(&Secp256k1PrivateKey::new()),
+                expire_block: 23456,
+                last_contact_time: now_secs,
+                allowed: -1,
+                denied: -1,
+                asn: 34567,
+                org: 45678,
+                in_degree: 1,
+                out_degree: 1,
+            });
+        }
+
+        let mut db = PeerDB::connect_memory(
+            0x9abcdef0,
+            12345,
+            0,
+            "http://foo.com".into(),
+            &vec![],
+            &initial_neighbors,
+        )
+        .unwrap();
+
+        let network_id = 0x9abcdef0;
+        let bad = &initial_neighbors[0].addr;
+        let bad_key = &initial_neighbors[0].public_key;
+        let good = &initial_neighbors[1].addr;
+        let good_key = &initial_neighbors[1].public_key;
+
+        // unknown peers are neutral
+        assert_eq!(
+            PeerDB::get_peer_reputation(db.conn(), network_id, bad_key, now_secs).unwrap(),
+            0.0
+        );
+
+        {
+            let tx = db.tx_begin().unwrap();
+            for _ in 0..2 {
+                PeerDB::update_peer_reputation(
+                    &tx,
+                    network_id,
+                    bad_key,
+                    ReputationEvent::Banned.points(),
+                    now_secs,
+                )
+                .unwrap();
+            }
+            PeerDB::update_peer_reputation(
+                &tx,
+                network_id,
+                good_key,
+                ReputationEvent::BlockDelivered.points(),
+                now_secs,
+            )
+            .unwrap();
+            tx.commit().unwrap();
+        }
+
+        // scores add up, and decay with time
+        let bad_score = |db: &PeerDB, when| {
+            PeerDB::get_peer_reputation(db.conn(), network_id, bad_key, when).unwrap()
+        };
+        assert_eq!(bad_score(&db, now_secs), -400.0);
+        assert_eq!(bad_score(&db, now_secs + REPUTATION_HALF_LIFE_SECS), -200.0);
+
+        // a peer with another key does not share the reputation, whatever address it claims
+        let other_key = Secp256k1PublicKey::from_private(&Secp256k1PrivateKey::new());
+        assert_eq!(
+            PeerDB::get_peer_reputation(db.conn(), network_id, &other_key, now_secs).unwrap(),
+            0.0
+        );
+        assert_eq!(
+            PeerDB::get_peer_reputation(db.conn(), network_id, good_key, now_secs).unwrap(),
+            20.0
+        );
+
+        // the neighbor walk skips the badly-reputed peer, but the well-reputed peer is fine
+        for _ in 0..10 {
+            let neighbors =
+                PeerDB::get_random_walk_neighbors(db.conn(), 0x9abcdef0, 0x00, 0, 4, 23455)
+                    .unwrap();
+            assert_eq!(neighbors.len(), 3);
+            assert!(neighbors.iter().all(|n| n.addr != *bad));
+            assert!(neighbors.iter().any(|n| n.addr == *good));
+        }
+
+        // making up for misbehavior restores the peer
+        {
+            let tx = db.tx_begin().unwrap();
+            let score =
+                PeerDB::update_peer_reputation(&tx, network_id, bad_key, 350, now_secs).unwrap();
+            assert_eq!(score, -50.0);
+            tx.commit().unwrap();
+        }
+        let neighbors =
+            PeerDB::get_random_walk_neighbors(db.conn(), 0x9abcdef0, 0x00, 0, 4, 23455).unwrap();
+        assert_eq!(neighbors.len(), 4);
+
+        // scores are bounded
+        {
+            let tx = db.tx_begin().unwrap();
+            let score =
+                PeerDB::update_peer_reputation(&tx, network_id, good_key, i64::MAX, now_secs)
+                    .unwrap();
+            assert_eq!(score, REPUTATION_MAX);
+            tx.commit().unwrap();
+        }
+
+        // old reputations are forgotten
+        {
+            let tx = db.tx_begin().unwrap();
+            PeerDB::expire_peer_reputations(&tx, now_secs + REPUTATION_EXPIRE_SECS).unwrap();
+            tx.commit().unwrap();
+        }
+        assert_eq!(bad_score(&db, now_secs), -50.0);
+        {
+            let tx = db.tx_begin().unwrap();
+            PeerDB::expire_peer_reputations(&tx, now_secs + REPUTATION_EXPIRE_SECS + 1).unwrap();
+            tx.commit().unwrap();
+        }
+        assert_eq!(bad_score(&db, now_secs), 0.0);
+    }
+
     /// Verifies that PeerDB::asn4_lookup() correctly classifies IPv4 address into their AS numbers
     #[test]
     fn asn4_insert_lookup() {
@@ -3461,6 +3802,143 @@ mod test {
         assert_eq!(n2.allowed, 0);
     }
 
+    /// Tests that PeerDB::remove_deny_cidr() only un-denies the peers that are no longer covered
+    /// by any denied CIDR prefix, and leaves per-peer deny deadlines alone.
+    #[test]
+    fn test_peer_remove_deny_cidr() {
+        let mut neighbor_1 = Neighbor {
+            addr: NeighborKey {
+                peer_version: 0x12345678,
+                network_id: 0x9abcdef0,
+                addrbytes: PeerAddress([
+                    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c,
+                    0x0d, 0x0e, 0x0f,
+                ]),
+                port: 12345,
+            },
+            public_key: Secp256k1PublicKey::from_hex(
+                "02fa66b66f8971a8cd4d20ffded09674e030f0f33883f337f34b95ad4935bac0e3",
+            )
+            .unwrap(),
+            expire_block: 23456,
+            last_contact_time: 1552509642,
+            allowed: 0,
+            denied: 0,
+            asn: 34567,
+            org: 45678,
+            in_degree: 1,
+            out_degree: 1,
+        };
+
+        let mut neighbor_2 = neighbor_1.clone();
+        neighbor_2.addr.addrbytes = PeerAddress([
+            0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d,
+            0x1e, 0x1f,
+        ]);
+        neighbor_2.public_key = Secp256k1PublicKey::from_hex(
+            "02287c1f1b280b5dde764b146976f6bad3fb485a3df9b1ad2d8ddc5719e7e91ff2",
+        )
+        .unwrap();
+
+        let mut db = PeerDB::connect_memory(
+            0x9abcdef0,
+            12345,
+            0,
+            "http://foo.com".into(),
+            &vec![],
+            &vec![neighbor_1.clone(), neighbor_2.clone()],
+        )
+        .unwrap();
+
+        let get_denied = |db: &PeerDB| {
+            let n1 = PeerDB::get_peer(
+                db.conn(),
+                neighbor_1.addr.network_id,
+                &neighbor_1.addr.addrbytes,
+                neighbor_1.addr.port,
+            )
+            .unwrap()
+            .unwrap();
+            let n2 = PeerDB::get_peer(
+                db.conn(),
+                neighbor_2.addr.network_id,
+                &neighbor_2.addr.addrbytes,
+                neighbor_2.addr.port,
+            )
+            .unwrap()
+            .unwrap();
+            (n1.denied, n2.denied)
+        };
+
+        {
+            // 000/3 covers both peers; 00010000/8 covers only peer 2
+            let tx = db.tx_begin().unwrap();
+            PeerDB::add_deny_cidr(&tx, &PeerAddress([0x00; 16]), 3).unwrap();
+            PeerDB::add_deny_cidr(&tx, &neighbor_2.addr.addrbytes, 8).unwrap();
+            tx.commit().unwrap();
+        }
+        assert_eq!(get_denied(&db), (i64::MAX, i64::MAX));
+        assert_eq!(
+            PeerDB::get_denied_peers(db.conn(), 0x9abcdef0, 1000)
+                .unwrap()
+                .len(),
+            2
+        );
+
+        {
+            let tx = db.tx_begin().unwrap();
+            PeerDB::remove_deny_cidr(&tx, &PeerAddress([0x00; 16]), 3).unwrap();
+            tx.commit().unwrap();
+        }
+        assert_eq!(get_denied(&db), (0, i64::MAX));
+        assert_eq!(PeerDB::get_denied_cidrs(db.conn()).unwrap().len(), 1);
+
+        {
+            // a per-peer ban survives removal of an unrelated prefix
+            let tx = db.tx_begin().unwrap();
+            PeerDB::set_deny_peer(
+                &tx,
+                neighbor_1.addr.network_id,
+                &neighbor_1.addr.addrbytes,
+                neighbor_1.addr.port,
+                5000,
+            )
+            .unwrap();
+            PeerDB::remove_deny_cidr(&tx, &neighbor_2.addr.addrbytes, 8).unwrap();
+            tx.commit().unwrap();
+        }
+        assert_eq!(get_denied(&db), (5000, 0));
+        assert!(PeerDB::get_denied_cidrs(db.conn()).unwrap().is_empty());
+
+        let denied = PeerDB::get_denied_peers(db.conn(), 0x9abcdef0, 1000).unwrap();
+        assert_eq!(denied.len(), 1);
+        assert_eq!(denied[0].addr.addrbytes, neighbor_1.addr.addrbytes);
+        assert!(PeerDB::get_denied_peers(db.conn(), 0x9abcdef0, 5000)
+            .unwrap()
+            .is_empty());
+
+        // add a new bootstrap peer at runtime
+        neighbor_1.addr.port = 23456;
+        {
+            let tx = db.tx_begin().unwrap();
+            PeerDB::add_bootstrap_peer(&tx, &neighbor_1).unwrap();
+            tx.commit().unwrap();
+        }
+        assert!(PeerDB::is_initial_peer(
+            db.conn(),
+            neighbor_1.addr.network_id,
+            &neighbor_1.addr.addrbytes,
+            23456
+        )
+        .unwrap());
+        assert_eq!(
+            PeerDB::get_bootstrap_peers(db.conn(), 0x9abcdef0)
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+
     /// Test PeerDB::connect() with different private keys.  Verify that LocalPeer reflects the
     /// latest key.
     #[test]
@@ -0,0 +1,394 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Encrypted P2P sessions.
+//!
+//! Each direction of a P2P connection is encrypted independently.  Once both peers advertise
+//! `ServiceFlags::ENCRYPTION`, each one sends a signed `SessionKey` message in plaintext, and
+//! every byte it writes after that message is sealed into a frame:
+//!
+//! ```text
+//! | length (u32, big-endian) | AES-256-GCM ciphertext and tag (length bytes) |
+//! ```
+//!
+//! The session key is derived from two ECDH shared secrets (in the style of the Noise `K`
+//! pattern): one between the sender's single-use ephemeral key and the receiver's node key, and
+//! one between the sender's and receiver's node keys.  Only the holder of the receiver's node
+//! private key can decrypt the stream, and only the holder of the sender's node private key can
+//! produce it.  Frame nonces are a per-direction counter, so frames cannot be replayed,
+//! reordered, or dropped without the receiver noticing.
+
+use std::fmt;
+
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, KeyInit};
+use secp256k1::ecdh::SharedSecret;
+use secp256k1::{PublicKey as LibSecp256k1PublicKey, SecretKey as LibSecp256k1PrivateKey};
+use sha2::{Digest, Sha256};
+use stacks_common::types::{PrivateKey, StacksPublicKeyBuffer};
+use stacks_common::util::secp256k1::{Secp256k1PrivateKey, Secp256k1PublicKey};
+
+use crate::net::{Error as net_error, SessionKeyData};
+
+/// Domain separator for the session key derivation
+const SESSION_KDF_TAG: &[u8] = b"stacks-p2p-session-v1";
+
+/// Length of the frame length prefix
+pub const SESSION_FRAME_HEADER_LEN: usize = 4;
+
+/// Length of the AES-GCM authentication tag at the end of each frame
+pub const SESSION_TAG_LEN: usize = 16;
+
+/// Largest ciphertext a single frame may carry.  Senders seal at most one socket write's worth of
+/// plaintext per frame, which is well under this.
+pub const MAX_SESSION_FRAME_LEN: usize = 65536 + SESSION_TAG_LEN;
+
+fn to_lib_private_key(privk: &Secp256k1PrivateKey) -> Result<LibSecp256k1PrivateKey, net_error> {
+    LibSecp256k1PrivateKey::from_slice(&privk.to_bytes()[0..32])
+        .map_err(|e| net_error::SigningError(format!("Invalid private key: {:?}", &e)))
+}
+
+fn to_lib_public_key(pubk: &StacksPublicKeyBuffer) -> Result<LibSecp256k1PublicKey, net_error> {
+    LibSecp256k1PublicKey::from_slice(pubk.as_bytes())
+        .map_err(|e| net_error::VerifyingError(format!("Invalid public key: {:?}", &e)))
+}
+
+/// Derive the session key for one direction of a connection.
+/// * `ephemeral_secret` is ECDH(sender ephemeral key, receiver node key)
+/// * `static_secret` is ECDH(sender node key, receiver node key)
+fn derive_session_key(
+    ephemeral_secret: &SharedSecret,
+    static_secret: &SharedSecret,
+    data: &SessionKeyData,
+    receiver_public_key: &StacksPublicKeyBuffer,
+) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(SESSION_KDF_TAG);
+    hasher.update(&ephemeral_secret.secret_bytes());
+    hasher.update(&static_secret.secret_bytes());
+    hasher.update(data.ephemeral_public_key.as_bytes());
+    hasher.update(data.static_public_key.as_bytes());
+    hasher.update(receiver_public_key.as_bytes());
+
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&hasher.finalize()[..]);
+    key
+}
+
+/// Frame nonce: four zero bytes followed by the big-endian frame counter
+fn frame_nonce(counter: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
+
+/// Sending half of an encrypted session.  Seals outbound bytes into frames.
+pub struct SessionCipher {
+    cipher: Aes256Gcm,
+    counter: u64,
+}
+
+impl fmt::Debug for SessionCipher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SessionCipher(counter={})", self.counter)
+    }
+}
+
+impl SessionCipher {
+    /// Start a new session to the peer with the given node public key.
+    /// Returns the `SessionKeyData` to send to the peer, and the cipher with which to seal all
+    /// bytes sent after it.
+    pub fn begin(
+        local_private_key: &Secp256k1PrivateKey,
+        remote_public_key: &Secp256k1PublicKey,
+    ) -> Result<(SessionKeyData, SessionCipher), net_error> {
+        let ephemeral_private_key = Secp256k1PrivateKey::new();
+
+        let data = SessionKeyData {
+            ephemeral_public_key: StacksPublicKeyBuffer::from_public_key(
+                &Secp256k1PublicKey::from_private(&ephemeral_private_key),
+            ),
+            static_public_key: StacksPublicKeyBuffer::from_public_key(
+                &Secp256k1PublicKey::from_private(local_private_key),
+            ),
+        };
+
+        let receiver_public_key = StacksPublicKeyBuffer::from_public_key(remote_public_key);
+        let receiver_lib_public_key = to_lib_public_key(&receiver_public_key)?;
+        let ephemeral_secret = SharedSecret::new(
+            &receiver_lib_public_key,
+            &to_lib_private_key(&ephemeral_private_key)?,
+        );
+        let static_secret = SharedSecret::new(
+            &receiver_lib_public_key,
+            &to_lib_private_key(local_private_key)?,
+        );
+
+        let key = derive_session_key(
+            &ephemeral_secret,
+            &static_secret,
+            &data,
+            &receiver_public_key,
+        );
+        let cipher = SessionCipher {
+            cipher: Aes256Gcm::new(GenericArray::from_slice(&key)),
+            counter: 0,
+        };
+        Ok((data, cipher))
+    }
+
+    /// Seal a chunk of plaintext into a frame
+    pub fn seal(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, net_error> {
+        if plaintext.len() + SESSION_TAG_LEN > MAX_SESSION_FRAME_LEN {
+            return Err(net_error::OverflowError(format!(
+                "Cannot seal {} bytes into one session frame",
+                plaintext.len()
+            )));
+        }
+        if self.counter == u64::MAX {
+            return Err(net_error::OverflowError(
+                "Session frame counter exhausted".to_string(),
+            ));
+        }
+
+        let nonce = frame_nonce(self.counter);
+        let ciphertext = self
+            .cipher
+            .encrypt(GenericArray::from_slice(&nonce), plaintext)
+            .map_err(|_e| net_error::SigningError("Failed to seal session frame".to_string()))?;
+        self.counter += 1;
+
+        let mut frame = Vec::with_capacity(SESSION_FRAME_HEADER_LEN + ciphertext.len());
+        frame.extend_from_slice(&(ciphertext.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&ciphertext);
+        Ok(frame)
+    }
+}
+
+/// Receiving half of an encrypted session.  Buffers inbound bytes and opens complete frames.
+pub struct SessionDecoder {
+    cipher: Aes256Gcm,
+    counter: u64,
+    buf: Vec<u8>,
+}
+
+impl fmt::Debug for SessionDecoder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "SessionDecoder(counter={}, buffered={})",
+            self.counter,
+            self.buf.len()
+        )
+    }
+}
+
+impl SessionDecoder {
+    /// Accept a session begun by a remote peer, whose `SessionKey` message carried `data`.
+    /// The caller is responsible for checking that the message was signed by
+    /// `data.static_public_key`.
+    pub fn accept(
+        local_private_key: &Secp256k1PrivateKey,
+        data: &SessionKeyData,
+    ) -> Result<SessionDecoder, net_error> {
+        let local_lib_private_key = to_lib_private_key(local_private_key)?;
+        let receiver_public_key = StacksPublicKeyBuffer::from_public_key(
+            &Secp256k1PublicKey::from_private(local_private_key),
+        );
+
+        let ephemeral_secret = SharedSecret::new(
+            &to_lib_public_key(&data.ephemeral_public_key)?,
+            &local_lib_private_key,
+        );
+        let static_secret = SharedSecret::new(
+            &to_lib_public_key(&data.static_public_key)?,
+            &local_lib_private_key,
+        );
+
+        let key = derive_session_key(
+            &ephemeral_secret,
+            &static_secret,
+            data,
+            &receiver_public_key,
+        );
+        Ok(SessionDecoder {
+            cipher: Aes256Gcm::new(GenericArray::from_slice(&key)),
+            counter: 0,
+            buf: vec![],
+        })
+    }
+
+    /// Buffer up ciphertext, and return the plaintext of every frame completed by it.
+    /// Fails if a frame is too big or does not authenticate, in which case the connection
+    /// cannot be recovered.
+    pub fn open(&mut self, bytes: &[u8]) -> Result<Vec<u8>, net_error> {
+        self.buf.extend_from_slice(bytes);
+
+        let mut plaintext = vec![];
+        let mut offset = 0;
+        while self.buf.len() - offset >= SESSION_FRAME_HEADER_LEN {
+            let mut len_bytes = [0u8; SESSION_FRAME_HEADER_LEN];
+            len_bytes.copy_from_slice(&self.buf[offset..(offset + SESSION_FRAME_HEADER_LEN)]);
+            let frame_len = u32::from_be_bytes(len_bytes) as usize;
+            if frame_len < SESSION_TAG_LEN || frame_len > MAX_SESSION_FRAME_LEN {
+                return Err(net_error::DeserializeError(format!(
+                    "Invalid session frame length {}",
+                    frame_len
+                )));
+            }
+
+            let frame_start = offset + SESSION_FRAME_HEADER_LEN;
+            if self.buf.len() - frame_start < frame_len {
+                // not enough data yet
+                break;
+            }
+            if self.counter == u64::MAX {
+                return Err(net_error::OverflowError(
+                    "Session frame counter exhausted".to_string(),
+                ));
+            }
+
+            let nonce = frame_nonce(self.counter);
+            let mut frame_plaintext = self
+                .cipher
+                .decrypt(
+                    GenericArray::from_slice(&nonce),
+                    &self.buf[frame_start..(frame_start + frame_len)],
+                )
+                .map_err(|_e| {
+                    net_error::VerifyingError("Failed to open session frame".to_string())
+                })?;
+            self.counter += 1;
+
+            plaintext.append(&mut frame_plaintext);
+            offset = frame_start + frame_len;
+        }
+
+        self.buf.drain(0..offset);
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn session_pair() -> (SessionCipher, SessionDecoder) {
+        let sender_key = Secp256k1PrivateKey::new();
+        let receiver_key = Secp256k1PrivateKey::new();
+
+        let (data, cipher) = SessionCipher::begin(
+            &sender_key,
+            &Secp256k1PublicKey::from_private(&receiver_key),
+        )
+        .unwrap();
+        assert_eq!(
+            data.static_public_key,
+            StacksPublicKeyBuffer::from_public_key(&Secp256k1PublicKey::from_private(&sender_key))
+        );
+
+        let decoder = SessionDecoder::accept(&receiver_key, &data).unwrap();
+        (cipher, decoder)
+    }
+
+    #[test]
+    fn test_session_roundtrip() {
+        let (mut cipher, mut decoder) = session_pair();
+
+        let mut stream = vec![];
+        for i in 0..10u8 {
+            stream.append(&mut cipher.seal(&vec![i; (i as usize) * 100 + 1]).unwrap());
+        }
+
+        // feed the stream one odd-sized piece at a time
+        let mut plaintext = vec![];
+        for piece in stream.chunks(37) {
+            plaintext.append(&mut decoder.open(piece).unwrap());
+        }
+
+        let mut expected = vec![];
+        for i in 0..10u8 {
+            expected.append(&mut vec![i; (i as usize) * 100 + 1]);
+        }
+        assert_eq!(plaintext, expected);
+        assert_eq!(decoder.buf.len(), 0);
+    }
+
+    #[test]
+    fn test_session_wrong_receiver() {
+        let sender_key = Secp256k1PrivateKey::new();
+        let receiver_key = Secp256k1PrivateKey::new();
+        let eavesdropper_key = Secp256k1PrivateKey::new();
+
+        let (data, mut cipher) = SessionCipher::begin(
+            &sender_key,
+            &Secp256k1PublicKey::from_private(&receiver_key),
+        )
+        .unwrap();
+        let mut decoder = SessionDecoder::accept(&eavesdropper_key, &data).unwrap();
+
+        let frame = cipher.seal(b"hello world").unwrap();
+        assert!(decoder.open(&frame).is_err());
+    }
+
+    #[test]
+    fn test_session_impersonated_sender() {
+        let sender_key = Secp256k1PrivateKey::new();
+        let receiver_key = Secp256k1PrivateKey::new();
+        let impostor_key = Secp256k1PrivateKey::new();
+
+        // impostor claims to be the sender, but doesn't have its private key
+        let (mut data, mut cipher) = SessionCipher::begin(
+            &impostor_key,
+            &Secp256k1PublicKey::from_private(&receiver_key),
+        )
+        .unwrap();
+        data.static_public_key =
+            StacksPublicKeyBuffer::from_public_key(&Secp256k1PublicKey::from_private(&sender_key));
+
+        let mut decoder = SessionDecoder::accept(&receiver_key, &data).unwrap();
+        let frame = cipher.seal(b"hello world").unwrap();
+        assert!(decoder.open(&frame).is_err());
+    }
+
+    #[test]
+    fn test_session_tampering() {
+        // flipped bit
+        let (mut cipher, mut decoder) = session_pair();
+        let mut frame = cipher.seal(b"hello world").unwrap();
+        let last = frame.len() - 1;
+        frame[last] ^= 0x01;
+        assert!(decoder.open(&frame).is_err());
+
+        // replayed frame
+        let (mut cipher, mut decoder) = session_pair();
+        let frame = cipher.seal(b"hello world").unwrap();
+        assert_eq!(decoder.open(&frame).unwrap(), b"hello world".to_vec());
+        assert!(decoder.open(&frame).is_err());
+
+        // dropped frame
+        let (mut cipher, mut decoder) = session_pair();
+        let _ = cipher.seal(b"hello").unwrap();
+        let frame = cipher.seal(b"world").unwrap();
+        assert!(decoder.open(&frame).is_err());
+
+        // oversized frame
+        let (_, mut decoder) = session_pair();
+        let bad_len = ((MAX_SESSION_FRAME_LEN + 1) as u32).to_be_bytes();
+        assert!(decoder.open(&bad_len).is_err());
+    }
+}
@@ -50,6 +50,7 @@ use crate::net::http::{
     HttpResponseContents, HttpResponsePayload, HttpResponsePreamble, HttpServerError, HttpVersion,
 };
 use crate::net::p2p::PeerNetwork;
+use crate::net::ratelimit::BandwidthClass;
 use crate::net::server::HttpPeer;
 use crate::net::{Error as NetError, MessageSequence, ProtocolFamily, StacksNodeState, UrlString};
 
@@ -302,6 +303,8 @@ pub trait HttpRequestContentsExtensions {
     fn tip_request(&self) -> TipRequest;
     /// Determine if we should return a MARF proof
     fn get_with_proof(&self) -> bool;
+    /// Determine if Clarity values should also be returned as typed JSON
+    fn get_json_encoding(&self) -> bool;
 }
 
 impl HttpRequestContentsExtensions for HttpRequestContents {
@@ -337,6 +340,14 @@ impl HttpRequestContentsExtensions for HttpRequestContents {
             .unwrap_or("1".into());
         &proof_value == "1"
     }
+
+    /// Get the encoding= query parameter value
+    fn get_json_encoding(&self) -> bool {
+        self.get_query_arg("encoding")
+            .map(|x| x == "json")
+            // default to consensus serialization only
+            .unwrap_or(false)
+    }
 }
 
 /// Work around Clone blanket implementations not being object-safe
@@ -872,6 +883,8 @@ pub struct StacksHttp {
     pub maximum_call_argument_size: u32,
     /// Maximum execution budget of a read-only call
     pub read_only_call_limit: ExecutionCost,
+    /// Token required by authenticated endpoints
+    pub auth_token: Option<String>,
 }
 
 impl StacksHttp {
@@ -887,6 +900,7 @@ impl StacksHttp {
             request_handlers: vec![],
             maximum_call_argument_size: conn_opts.maximum_call_argument_size,
             read_only_call_limit: conn_opts.read_only_call_limit.clone(),
+            auth_token: conn_opts.auth_token.clone(),
         };
         http.register_rpc_methods();
         http
@@ -1564,6 +1578,21 @@ impl ProtocolFamily for StacksHttp {
             StacksHttpMessage::Error(_, ref resp) => resp.send(fd),
         }
     }
+
+    /// A request's class is determined by its path.  A response has the class of the request
+    /// it answers, which the connection is told when the request is sent.
+    fn recv_class(
+        &mut self,
+        preamble: &StacksHttpPreamble,
+        _payload_prefix: &[u8],
+    ) -> Option<BandwidthClass> {
+        match preamble {
+            StacksHttpPreamble::Request(ref http_request_preamble) => Some(
+                BandwidthClass::of_http_request(&http_request_preamble.path_and_query_str),
+            ),
+            StacksHttpPreamble::Response(_) => None,
+        }
+    }
 }
 
 impl PeerNetwork {
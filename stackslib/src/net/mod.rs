@@ -56,7 +56,7 @@ use stacks_common::util::hash::{
     hex_bytes, to_hex, Hash160, Sha256Sum, DOUBLE_SHA256_ENCODED_SIZE, HASH160_ENCODED_SIZE,
 };
 use stacks_common::util::secp256k1::{
-    MessageSignature, Secp256k1PublicKey, MESSAGE_SIGNATURE_ENCODED_SIZE,
+    MessageSignature, Secp256k1PrivateKey, Secp256k1PublicKey, MESSAGE_SIGNATURE_ENCODED_SIZE,
 };
 use stacks_common::util::{get_epoch_time_secs, log};
 use {rusqlite, serde_json, url};
@@ -64,6 +64,7 @@ use {rusqlite, serde_json, url};
 use crate::burnchains::affirmation::AffirmationMap;
 use crate::burnchains::{Error as burnchain_error, Txid};
 use crate::chainstate::burn::db::sortdb::SortitionDB;
+use crate::chainstate::burn::operations::StackStxOp;
 use crate::chainstate::burn::ConsensusHash;
 use crate::chainstate::coordinator::Error as coordinator_error;
 use crate::chainstate::stacks::db::blocks::MemPoolRejection;
@@ -100,6 +101,9 @@ pub mod asn;
 /// Implements the Atlas network. This network uses the infrastructure created in `src/net` to
 /// discover peers, query attachment inventories, and download attachments.
 pub mod atlas;
+/// Implements optional capture of P2P messages to rotating files, and reading, filtering and
+/// replaying those captures.
+pub mod capture;
 /// Implements the `ConversationP2P` object, a host-to-host session abstraction which allows
 /// the node to recieve `StacksMessage` instances. The downstream consumer of this API is `PeerNetwork`.
 /// To use OSI terminology, this module implements the session & presentation layers of the P2P network.
@@ -111,12 +115,17 @@ pub mod chat;
 /// Implements serialization and deserialization for `StacksMessage` types.
 /// Also has functionality to sign, verify, and ensure well-formedness of messages.
 pub mod codec;
+/// Implements compact block relay: blocks are sent as a header plus short transaction tags, and
+/// rebuilt from the recipient's mempool.
+pub mod compact;
 pub mod connection;
 pub mod db;
 /// Implements `DNSResolver`, a simple DNS resolver state machine. Also implements `DNSClient`,
 /// which serves as an API for `DNSResolver`.  
 pub mod dns;
 pub mod download;
+/// Implements the optional encrypted session layer for P2P connections.
+pub mod encryption;
 pub mod http;
 /// Links http crate to Stacks
 pub mod httpcore;
@@ -129,12 +138,32 @@ pub mod p2p;
 /// p2p server and the http server.
 pub mod poll;
 pub mod prune;
+/// Implements token-bucket bandwidth limits for P2P and HTTP connections, with priority classes
+/// so block and StackerDB traffic can preempt transaction gossip.
+pub mod ratelimit;
 pub mod relay;
+/// Implements persistent peer reputation scores, which steer the neighbor walk and frontier
+/// pruning away from misbehaving peers.
+pub mod reputation;
 pub mod rpc;
+/// Implements a DNS seeder, which crawls the peer graph and serves the reachable peers it finds
+/// from a minimal authoritative DNS server.
+pub mod seeder;
 pub mod server;
+/// Implements a deterministic simulated transport, so multi-node tests can run on a virtual
+/// clock with configurable latency, loss and partitions.
+#[cfg(any(test, feature = "testing"))]
+pub mod simnet;
+/// Implements outbound connections through a SOCKS5 proxy such as Tor.
+pub mod socks5;
 pub mod stackerdb;
+/// Implements transaction announcements: transactions are announced to peers by short tags, and
+/// peers fetch only the ones they are missing.
+pub mod txrelay;
 
+use crate::net::encryption::SessionDecoder;
 pub use crate::net::neighbors::{NeighborComms, PeerNetworkComms};
+use crate::net::ratelimit::BandwidthClass;
 use crate::net::stackerdb::{StackerDBConfig, StackerDBSync, StackerDBSyncResult, StackerDBs};
 
 #[cfg(test)]
@@ -603,6 +632,32 @@ impl PeerHostExtensions for PeerHost {
     }
 }
 
+/// Progress of a burnchain operation requested through an RPC endpoint
+#[derive(Debug, Clone, PartialEq)]
+pub enum BurnchainOpStatus {
+    /// Queued, but not sent yet
+    Pending,
+    /// Sent, with the (pre-stx, stack-stx) txids
+    Sent(Txid, Txid),
+    /// Could not be built or sent, and why
+    Failed(String),
+}
+
+/// Carries out burnchain operations requested through authenticated RPC endpoints.  The node
+/// hands them off to whichever thread owns its burnchain client, so these must not block.
+pub trait BurnchainOpSubmitter {
+    /// Stacks address of the stacker key in the node's config, if there is one.  Stack-stx
+    /// operations can only be sent for this stacker.
+    fn stacker_address(&self) -> Option<StacksAddress>;
+    /// Queue a stack-stx operation to be signed with the configured stacker key, and sent along
+    /// with the pre-stx operation that it spends.  The node pays for the pre-stx operation.
+    /// Returns an ID with which to poll `get_stack_stx_status()`.
+    fn submit_stack_stx(&self, op: StackStxOp) -> Result<u64, String>;
+    /// Status of a stack-stx operation queued by `submit_stack_stx()`, or None if the ID is
+    /// unknown (or so old that it has been forgotten).
+    fn get_stack_stx_status(&self, request_id: u64) -> Option<BurnchainOpStatus>;
+}
+
 /// Runtime arguments to an RPC handler
 #[derive(Default)]
 pub struct RPCHandlerArgs<'a> {
@@ -618,6 +673,8 @@ pub struct RPCHandlerArgs<'a> {
     pub fee_estimator: Option<&'a dyn FeeEstimator>,
     /// tx runtime cost metric
     pub cost_metric: Option<&'a dyn CostMetric>,
+    /// submitter for burnchain operations
+    pub burnchain_op_submitter: Option<&'a dyn BurnchainOpSubmitter>,
 }
 
 impl<'a> RPCHandlerArgs<'a> {
@@ -785,7 +842,29 @@ impl<'a> StacksNodeState<'a> {
                         }
                     }
                 }
-                TipRequest::SpecificTip(tip) => Ok(tip.clone()),
+                TipRequest::SpecificTip(tip) => match chainstate.is_state_pruned(&tip) {
+                    Ok(false) => Ok(tip.clone()),
+                    Ok(true) => {
+                        let pruned_below_height =
+                            StacksChainState::get_pruned_below_height(chainstate.db())
+                                .ok()
+                                .flatten()
+                                .unwrap_or(0);
+                        return Err(StacksHttpResponse::new_error(
+                            preamble,
+                            &HttpNotFound::new(format!(
+                                "State at tip {} has been pruned; this node only keeps the state of Stacks blocks at height {} or higher",
+                                tip, pruned_below_height
+                            )),
+                        ));
+                    }
+                    Err(e) => {
+                        return Err(StacksHttpResponse::new_error(
+                            preamble,
+                            &HttpServerError::new(format!("Failed to load chain tip: {:?}", &e)),
+                        ));
+                    }
+                },
                 TipRequest::UseLatestAnchoredTip => match chainstate.get_stacks_chain_tip(sortdb) {
                     Ok(Some(tip)) => Ok(StacksBlockHeader::make_index_block_hash(
                         &tip.consensus_hash,
@@ -955,6 +1034,9 @@ pub enum ServiceFlags {
     RELAY = 0x01,
     RPC = 0x02,
     STACKERDB = 0x04,
+    ENCRYPTION = 0x08,
+    COMPACT_BLOCKS = 0x10,
+    TX_ANNOUNCE = 0x20,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -1050,6 +1132,73 @@ pub struct StackerDBPushChunkData {
     pub chunk_data: StackerDBChunkData,
 }
 
+/// Sent by a peer to switch the rest of its outbound byte stream over to an encrypted session.
+/// Only sent once both peers advertise `ServiceFlags::ENCRYPTION`.  The session key is derived
+/// from the ephemeral key and the sender's and receiver's node keys (see `net::encryption`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionKeyData {
+    /// single-use public key for this session
+    pub ephemeral_public_key: StacksPublicKeyBuffer,
+    /// the sender's node public key
+    pub static_public_key: StacksPublicKeyBuffer,
+}
+
+/// A transaction sent in full as part of a compact block, along with its index in the block
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrefilledTransaction {
+    pub index: u16,
+    pub tx: StacksTransaction,
+}
+
+/// An anchored block, sent as its header plus a short tag for each transaction.  The recipient
+/// rebuilds the block from its mempool, and asks for the transactions it doesn't have with
+/// `GetBlockTxs`.  Only sent to peers that advertise `ServiceFlags::COMPACT_BLOCKS`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactBlockData {
+    /// consensus hash of the sortition that chose this block
+    pub consensus_hash: ConsensusHash,
+    pub header: StacksBlockHeader,
+    /// one tag per transaction in the block, in block order.  Each tag is seeded with the block
+    /// hash (see `net::compact`).  Prefilled transactions have a tag too.
+    pub tx_tags: Vec<TxTag>,
+    /// transactions the sender expects the recipient not to have (at least the coinbase)
+    pub prefilled_txs: Vec<PrefilledTransaction>,
+}
+
+/// Request for the transactions at the given indexes of a block
+#[derive(Debug, Clone, PartialEq)]
+pub struct GetBlockTxsData {
+    pub consensus_hash: ConsensusHash,
+    pub block_hash: BlockHeaderHash,
+    /// indexes into the block's transaction list, in ascending order
+    pub indexes: Vec<u16>,
+}
+
+/// Reply to a `GetBlockTxs`, with the requested transactions in the requested order
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockTxsData {
+    pub consensus_hash: ConsensusHash,
+    pub block_hash: BlockHeaderHash,
+    pub txs: Vec<StacksTransaction>,
+}
+
+/// A batch of transaction announcements.  Each transaction is identified by a short tag, seeded
+/// with `seed` (see `net::txrelay`).  The recipient asks for the ones it doesn't have with
+/// `GetTxs`.  Only sent to peers that advertise `ServiceFlags::TX_ANNOUNCE`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxInvData {
+    pub seed: [u8; 32],
+    pub tx_tags: Vec<TxTag>,
+}
+
+/// Request for announced transactions, identified by the tags and seed they were announced with.
+/// The sender of the `TxInv` replies with a `Transaction` message for each one it still has.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GetTxsData {
+    pub seed: [u8; 32],
+    pub tx_tags: Vec<TxTag>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct RelayData {
     pub peer: NeighborAddress,
@@ -1085,6 +1234,15 @@ pub enum StacksMessageType {
     StackerDBGetChunk(StackerDBGetChunkData),
     StackerDBChunk(StackerDBChunkData),
     StackerDBPushChunk(StackerDBPushChunkData),
+    // encrypted transport
+    SessionKey(SessionKeyData),
+    // compact block relay
+    CompactBlock(CompactBlockData),
+    GetBlockTxs(GetBlockTxsData),
+    BlockTxs(BlockTxsData),
+    // transaction announcements
+    TxInv(TxInvData),
+    GetTxs(GetTxsData),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -1116,6 +1274,15 @@ pub enum StacksMessageID {
     StackerDBGetChunk = 23,
     StackerDBChunk = 24,
     StackerDBPushChunk = 25,
+    // encrypted transport
+    SessionKey = 26,
+    // compact block relay
+    CompactBlock = 27,
+    GetBlockTxs = 28,
+    BlockTxs = 29,
+    // transaction announcements
+    TxInv = 30,
+    GetTxs = 31,
     // reserved
     Reserved = 255,
 }
@@ -1182,6 +1349,36 @@ pub trait ProtocolFamily {
     /// and writing out a Preamble for its Message.
     fn write_message<W: Write>(&mut self, fd: &mut W, message: &Self::Message)
         -> Result<(), Error>;
+
+    /// Given a just-parsed message and the local node's private key (if it accepts encrypted
+    /// sessions), determine whether or not the remote peer is switching the rest of its byte
+    /// stream over to an encrypted session.  If so, return the decoder for it.  Protocols without
+    /// encrypted sessions never do this.
+    fn begin_session(
+        &mut self,
+        _message: &Self::Message,
+        _local_private_key: Option<&Secp256k1PrivateKey>,
+    ) -> Result<Option<SessionDecoder>, Error> {
+        Ok(None)
+    }
+
+    /// Given the preamble of a message being received and as much of its payload as has arrived
+    /// so far, determine the message's bandwidth class.  Returns None if it can't be told (yet),
+    /// in which case the connection falls back to the class it was told to expect.
+    fn recv_class(
+        &mut self,
+        _preamble: &Self::Preamble,
+        _payload_prefix: &[u8],
+    ) -> Option<BandwidthClass> {
+        None
+    }
+
+    /// Can queued messages be sent in a different order than they were queued in?  If so,
+    /// higher-priority messages are sent first.  Protocols that pair replies with requests by
+    /// order (i.e. HTTP) can't allow this.
+    fn can_reorder_messages(&mut self) -> bool {
+        false
+    }
 }
 
 // these implement the ProtocolFamily trait
@@ -1212,6 +1409,14 @@ pub const GETPOXINV_MAX_BITLEN: u64 = 8;
 // message.
 pub const BLOCKS_PUSHED_MAX: u32 = 32;
 
+// maximum number of transactions a compact block can describe.  Transaction indexes in
+// compact-block messages are u16s.
+pub const COMPACT_BLOCK_MAX_TXS: u32 = u16::MAX as u32;
+
+// maximum number of transactions that can be announced or requested in one TxInv or GetTxs
+// message
+pub const TX_INV_MAX_TAGS: u32 = 1024;
+
 /// neighbor identifier
 #[derive(Clone, Eq, PartialOrd, Ord)]
 pub struct NeighborKey {
@@ -1595,6 +1800,7 @@ pub mod test {
     use crate::chainstate::stacks::db::accounts::MinerReward;
     use crate::chainstate::stacks::db::{StacksChainState, *};
     use crate::chainstate::stacks::events::StacksTransactionReceipt;
+    use crate::chainstate::stacks::index::marf::MARFOpenOpts;
     use crate::chainstate::stacks::miner::*;
     use crate::chainstate::stacks::tests::chain_histories::mine_smart_contract_block_contract_call_microblock;
     use crate::chainstate::stacks::tests::*;
@@ -1611,6 +1817,7 @@ pub mod test {
     use crate::net::p2p::*;
     use crate::net::poll::*;
     use crate::net::relay::*;
+    use crate::net::simnet::SimNetwork;
     use crate::net::Error as net_error;
     use crate::util_lib::boot::boot_code_test_addr;
     use crate::util_lib::strings::*;
@@ -1910,6 +2117,10 @@ pub mod test {
         pub stacker_db_configs: Vec<Option<StackerDBConfig>>,
         /// What services should this peer support?
         pub services: u16,
+        /// Options for opening the Clarity state MARF
+        pub marf_opts: Option<MARFOpenOpts>,
+        /// If some(), bind to this simulated network instead of real sockets
+        pub simnet: Option<SimNetwork>,
     }
 
     impl TestPeerConfig {
@@ -1971,6 +2182,8 @@ pub mod test {
                 services: (ServiceFlags::RELAY as u16)
                     | (ServiceFlags::RPC as u16)
                     | (ServiceFlags::STACKERDB as u16),
+                marf_opts: None,
+                simnet: None,
             }
         }
 
@@ -2315,7 +2528,7 @@ pub mod test {
                 config.network_id,
                 &chainstate_path,
                 Some(&mut boot_data),
-                None,
+                config.marf_opts.clone(),
             )
             .unwrap();
 
@@ -2412,7 +2625,12 @@ pub mod test {
             );
             peer_network.set_stacker_db_configs(config.get_stacker_db_configs());
 
-            peer_network.bind(&local_addr, &http_local_addr).unwrap();
+            match config.simnet.as_ref() {
+                Some(sim) => peer_network
+                    .bind_simulated(sim, &local_addr, &http_local_addr)
+                    .unwrap(),
+                None => peer_network.bind(&local_addr, &http_local_addr).unwrap(),
+            }
             let relayer = Relayer::from_p2p(&mut peer_network, relayer_stacker_dbs);
             let mempool = MemPoolDB::open_test(false, config.network_id, &chainstate_path).unwrap();
             let indexer = BitcoinIndexer::new_unit_test(&config.burnchain.working_dir);
@@ -25,7 +25,6 @@ use std::{cmp, mem};
 use clarity::vm::ast::ASTRules;
 use clarity::vm::database::BurnStateDB;
 use clarity::vm::types::QualifiedContractIdentifier;
-use mio::net as mio_net;
 use rand::prelude::*;
 use rand::thread_rng;
 use stacks_common::types::chainstate::{PoxId, SortitionId};
@@ -48,7 +47,9 @@ use crate::chainstate::stacks::{StacksBlockHeader, MAX_BLOCK_LEN, MAX_TRANSACTIO
 use crate::monitoring::{update_inbound_neighbors, update_outbound_neighbors};
 use crate::net::asn::ASEntry4;
 use crate::net::atlas::{AtlasDB, AttachmentInstance, AttachmentsDownloader};
+use crate::net::capture::MessageCapture;
 use crate::net::chat::{ConversationP2P, NeighborStats};
+use crate::net::compact::PendingCompactBlock;
 use crate::net::connection::{ConnectionOptions, NetworkReplyHandle, ReplyHandleP2P};
 use crate::net::db::{LocalPeer, PeerDB};
 use crate::net::download::BlockDownloader;
@@ -56,11 +57,18 @@ use crate::net::http::HttpRequestContents;
 use crate::net::httpcore::StacksHttpRequest;
 use crate::net::inv::*;
 use crate::net::neighbors::*;
-use crate::net::poll::{NetworkPollState, NetworkState};
+use crate::net::poll::{NetworkPollState, NetworkSocket, NetworkState};
 use crate::net::prune::*;
+use crate::net::ratelimit::{
+    make_shared_bucket, ready_or_throttled, BandwidthLimiter, SharedTokenBucket,
+};
 use crate::net::relay::{RelayerStats, *, *};
+use crate::net::reputation::ReputationEvent;
 use crate::net::server::*;
+#[cfg(any(test, feature = "testing"))]
+use crate::net::simnet::SimNetwork;
 use crate::net::stackerdb::{StackerDBConfig, StackerDBSync, StackerDBTx, StackerDBs};
+use crate::net::txrelay::{RequestedTx, TxAnnounceState};
 use crate::net::{Error as net_error, Neighbor, NeighborKey, RPCHandlerArgs, *};
 use crate::util_lib::db::{DBConn, DBTx, Error as db_error};
 
@@ -229,9 +237,9 @@ pub struct PeerNetwork {
 
     // ongoing p2p conversations (either they reached out to us, or we to them)
     pub peers: PeerMap,
-    pub sockets: HashMap<usize, mio_net::TcpStream>,
+    pub sockets: HashMap<usize, NetworkSocket>,
     pub events: HashMap<NeighborKey, usize>,
-    pub connecting: HashMap<usize, (mio_net::TcpStream, bool, u64)>, // (socket, outbound?, connection sent timestamp)
+    pub connecting: HashMap<usize, (NetworkSocket, bool, u64)>, // (socket, outbound?, connection sent timestamp)
     pub bans: HashSet<usize>,
 
     // ongoing messages the network is sending via the p2p interface
@@ -342,6 +350,22 @@ pub struct PeerNetwork {
     // can't process yet, but might be able to process on the next chain view update
     pub pending_messages: HashMap<usize, Vec<StacksMessage>>,
 
+    // compact blocks we're rebuilding, keyed by the event ID of the peer that sent them, and the
+    // transactions we've asked that peer for
+    pub pending_compact_blocks: HashMap<(usize, StacksBlockId), PendingCompactBlock>,
+
+    // transaction announcement state for each peer, keyed by event ID, and the announced
+    // transactions we've asked for, keyed by the seed and tag they were announced with
+    pub tx_announcements: HashMap<usize, TxAnnounceState>,
+    pub requested_txs: HashMap<([u8; 32], TxTag), RequestedTx>,
+
+    // where we record every conversation's messages, if capturing is enabled
+    message_capture: Option<MessageCapture>,
+
+    // bandwidth budgets shared by all P2P conversations, if there are global limits
+    inbound_bandwidth: Option<SharedTokenBucket>,
+    outbound_bandwidth: Option<SharedTokenBucket>,
+
     // fault injection -- force disconnects
     fault_last_disconnect: u64,
 }
@@ -368,7 +392,8 @@ impl PeerNetwork {
             SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0),
         );
         let pub_ip = connection_opts.public_ip_address.clone();
-        let pub_ip_learned = pub_ip.is_none();
+        // behind a proxy, the address our peers see is the proxy's, so don't go learn it
+        let pub_ip_learned = pub_ip.is_none() && connection_opts.socks5_proxy.is_none();
         local_peer.public_ip_address = pub_ip.clone();
 
         if connection_opts.disable_inbound_handshakes {
@@ -382,6 +407,30 @@ impl PeerNetwork {
         let first_burn_header_hash = burnchain.first_block_hash.clone();
         let first_burn_header_ts = burnchain.first_block_timestamp;
 
+        let message_capture = match connection_opts.p2p_capture_path.as_ref() {
+            Some(path) => match MessageCapture::open(
+                path,
+                connection_opts.p2p_capture_max_bytes,
+                connection_opts.p2p_capture_max_files,
+            ) {
+                Ok(capture) => {
+                    info!("{:?}: Capturing P2P messages to {}", &local_peer, path);
+                    Some(capture)
+                }
+                Err(e) => {
+                    warn!(
+                        "{:?}: Failed to open P2P capture file {}: {:?}",
+                        &local_peer, path, &e
+                    );
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let inbound_bandwidth = make_shared_bucket(connection_opts.max_p2p_inbound_bandwidth);
+        let outbound_bandwidth = make_shared_bucket(connection_opts.max_p2p_outbound_bandwidth);
+
         let mut stacker_db_configs = HashMap::new();
         let mut stacker_db_sync_map = HashMap::new();
         for (contract_id, (stacker_db_config, stacker_db_sync)) in stacker_db_syncs.into_iter() {
@@ -488,6 +537,14 @@ impl PeerNetwork {
             antientropy_start_reward_cycle: 0,
 
             pending_messages: HashMap::new(),
+            pending_compact_blocks: HashMap::new(),
+            tx_announcements: HashMap::new(),
+            requested_txs: HashMap::new(),
+
+            message_capture,
+
+            inbound_bandwidth,
+            outbound_bandwidth,
 
             fault_last_disconnect: 0,
         };
@@ -527,8 +584,28 @@ impl PeerNetwork {
 
     /// start serving.
     pub fn bind(&mut self, my_addr: &SocketAddr, http_addr: &SocketAddr) -> Result<(), net_error> {
-        let mut net = NetworkState::new(self.connection_opts.max_sockets)?;
+        let net = NetworkState::new(self.connection_opts.max_sockets)?;
+        self.bind_network(net, my_addr, http_addr)
+    }
 
+    /// start serving on a simulated network instead of real sockets.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn bind_simulated(
+        &mut self,
+        sim: &SimNetwork,
+        my_addr: &SocketAddr,
+        http_addr: &SocketAddr,
+    ) -> Result<(), net_error> {
+        let net = NetworkState::new_simulated(sim, self.connection_opts.max_sockets)?;
+        self.bind_network(net, my_addr, http_addr)
+    }
+
+    fn bind_network(
+        &mut self,
+        mut net: NetworkState,
+        my_addr: &SocketAddr,
+        http_addr: &SocketAddr,
+    ) -> Result<(), net_error> {
         let (p2p_handle, bound_p2p_addr) = net.bind(my_addr)?;
         let (http_handle, bound_http_addr) = net.bind(http_addr)?;
 
@@ -539,6 +616,7 @@ impl PeerNetwork {
             bound_http_addr
         );
 
+        net.set_socks5_proxy(self.connection_opts.socks5_proxy.clone());
         self.network = Some(net);
         self.p2p_network_handle = p2p_handle;
         self.http_network_handle = http_handle;
@@ -748,7 +826,7 @@ impl PeerNetwork {
     /// Return (number of bytes sent, whether or not there's more to send)
     fn do_saturate_p2p_socket(
         convo: &mut ConversationP2P,
-        client_sock: &mut mio::net::TcpStream,
+        client_sock: &mut NetworkSocket,
         handle: &mut ReplyHandleP2P,
     ) -> Result<(usize, bool), net_error> {
         let mut total_sent = 0;
@@ -883,6 +961,16 @@ impl PeerNetwork {
         for nk in neighbor_keys.drain(..) {
             if let Some(event_id) = self.events.get(&nk) {
                 let event_id = *event_id;
+
+                // send a lone block as a compact block if the neighbor supports them
+                let payload = match message_payload {
+                    StacksMessageType::Blocks(ref data) if data.blocks.len() == 1 => {
+                        let BlocksDatum(ref consensus_hash, ref block) = data.blocks[0];
+                        self.make_block_push_payload(&nk, consensus_hash.clone(), block.clone())
+                    }
+                    _ => message_payload.clone(),
+                };
+
                 if let Some(convo) = self.peers.get_mut(&event_id) {
                     // safety check -- don't send to someone who has already been a relayer
                     let mut do_relay = true;
@@ -905,11 +993,32 @@ impl PeerNetwork {
                         continue;
                     }
 
+                    // announce a transaction, rather than push it, if the neighbor supports it
+                    if let StacksMessageType::Transaction(ref tx) = message_payload {
+                        if self.connection_opts.max_tx_announcements_per_sec > 0
+                            && ConversationP2P::supports_tx_announce(self.local_peer.services)
+                            && ConversationP2P::supports_tx_announce(convo.peer_services)
+                            && self
+                                .tx_announcements
+                                .entry(event_id)
+                                .or_insert_with(TxAnnounceState::new)
+                                .queue_tx(tx.txid(), relay_hints.clone())
+                        {
+                            debug!(
+                                "{:?}: Will announce tx {} to {:?}",
+                                &self.local_peer,
+                                &tx.txid(),
+                                &nk
+                            );
+                            continue;
+                        }
+                    }
+
                     match convo.sign_and_forward(
                         &self.local_peer,
                         &self.chain_view,
                         relay_hints.clone(),
-                        message_payload.clone(),
+                        payload,
                     ) {
                         Ok(rh) => {
                             debug!(
@@ -965,7 +1074,7 @@ impl PeerNetwork {
     /// Count how many connections to a given IP address we have
     pub fn count_ip_connections(
         ipaddr: &SocketAddr,
-        sockets: &HashMap<usize, mio_net::TcpStream>,
+        sockets: &HashMap<usize, NetworkSocket>,
     ) -> u64 {
         let mut ret = 0;
         for (_, socket) in sockets.iter() {
@@ -1054,7 +1163,7 @@ impl PeerNetwork {
                 return Err(net_error::NotConnected);
             }
             Some(ref mut network) => {
-                let sock = NetworkState::connect(
+                let sock = network.connect_socket(
                     &neighbor.addrbytes.to_socketaddr(neighbor.port),
                     self.connection_opts.socket_send_buffer_size,
                     self.connection_opts.socket_recv_buffer_size,
@@ -1390,6 +1499,20 @@ impl PeerNetwork {
                 neighbor_key.port,
                 penalty,
             )?;
+
+            if let Some(public_key) = self
+                .peers
+                .get(&event_id)
+                .and_then(PeerNetwork::reputation_key)
+            {
+                PeerDB::update_peer_reputation(
+                    &tx,
+                    self.local_peer.network_id,
+                    public_key,
+                    ReputationEvent::Banned.points(),
+                    now,
+                )?;
+            }
         }
 
         tx.commit()?;
@@ -1572,7 +1695,7 @@ impl PeerNetwork {
     fn register_peer(
         &mut self,
         event_id: usize,
-        socket: mio_net::TcpStream,
+        socket: NetworkSocket,
         outbound: bool,
     ) -> Result<(), net_error> {
         let client_addr = match socket.peer_addr() {
@@ -1636,6 +1759,22 @@ impl PeerNetwork {
             self.epochs.clone(),
         );
         new_convo.set_public_key(pubkey_opt);
+        if ConversationP2P::supports_encryption(self.local_peer.services) {
+            new_convo.set_local_private_key(Some(self.local_peer.private_key.clone()));
+        }
+        if let Some(capture) = self.message_capture.as_ref() {
+            new_convo.set_message_capture(Some(capture.new_conversation()));
+        }
+        new_convo.set_bandwidth_limiters(
+            BandwidthLimiter::new(
+                self.connection_opts.max_p2p_peer_inbound_bandwidth,
+                self.inbound_bandwidth.clone(),
+            ),
+            BandwidthLimiter::new(
+                self.connection_opts.max_p2p_peer_outbound_bandwidth,
+                self.outbound_bandwidth.clone(),
+            ),
+        );
 
         debug!(
             "{:?}: Registered {} as event {} ({:?},outbound={})",
@@ -1682,7 +1821,7 @@ impl PeerNetwork {
     }
 
     /// Deregister a socket from our p2p network instance.
-    fn deregister_socket(&mut self, event_id: usize, socket: mio_net::TcpStream) -> () {
+    fn deregister_socket(&mut self, event_id: usize, socket: NetworkSocket) -> () {
         match self.network {
             Some(ref mut network) => {
                 let _ = network.deregister(event_id, &socket);
@@ -1695,6 +1834,13 @@ impl PeerNetwork {
     pub fn deregister_peer(&mut self, event_id: usize) -> () {
         debug!("{:?}: Disconnect event {}", &self.local_peer, event_id);
 
+        if let Err(e) = self.flush_peer_reputation(event_id) {
+            warn!(
+                "{:?}: Failed to save reputation of event {}: {:?}",
+                &self.local_peer, event_id, &e
+            );
+        }
+
         let mut nk_remove: Vec<NeighborKey> = vec![];
         for (neighbor_key, ev_id) in self.events.iter() {
             if *ev_id == event_id {
@@ -1736,6 +1882,11 @@ impl PeerNetwork {
         self.relay_handles.remove(&event_id);
         self.peers.remove(&event_id);
         self.pending_messages.remove(&event_id);
+        self.pending_compact_blocks
+            .retain(|(pending_event_id, _), _| *pending_event_id != event_id);
+        self.tx_announcements.remove(&event_id);
+        self.requested_txs
+            .retain(|_, requested| requested.event_id != event_id);
     }
 
     /// Deregister by neighbor key
@@ -1880,7 +2031,7 @@ impl PeerNetwork {
     /// so `todo` can take a mutable ref to the PeerNetwork
     fn with_p2p_convo<F, R>(&mut self, event_id: usize, todo: F) -> Result<R, net_error>
     where
-        F: FnOnce(&mut PeerNetwork, &mut ConversationP2P, &mut mio_net::TcpStream) -> R,
+        F: FnOnce(&mut PeerNetwork, &mut ConversationP2P, &mut NetworkSocket) -> R,
     {
         // "check out" the conversation and client socket.
         // If one of them is missing, then "check in" the other so we can properly deregister the
@@ -2015,7 +2166,15 @@ impl PeerNetwork {
         let mut to_remove = vec![];
         let mut unhandled: HashMap<usize, Vec<StacksMessage>> = HashMap::new();
 
-        for event_id in &poll_state.ready {
+        let ready = ready_or_throttled(
+            &poll_state.ready,
+            self.peers
+                .iter()
+                .filter(|(_, convo)| convo.is_throttled())
+                .map(|(event_id, _)| *event_id),
+        );
+
+        for event_id in &ready {
             let (mut convo_unhandled, alive) =
                 match self.process_p2p_conversation(*event_id, sortdb, chainstate) {
                     Ok((convo_unhandled, alive)) => (convo_unhandled, alive),
@@ -2249,6 +2408,11 @@ impl PeerNetwork {
         // begin re-key
         let mut msgs = HashMap::new();
         for (event_id, convo) in self.peers.iter_mut() {
+            if ConversationP2P::supports_encryption(self.local_peer.services) {
+                // established sessions keep their keys, but new ones use the new key
+                convo.set_local_private_key(Some(self.local_peer.private_key.clone()));
+            }
+
             let nk = convo.to_neighbor_key();
             let handshake_data = HandshakeData::from_local_peer(&self.local_peer);
             let handshake = StacksMessageType::Handshake(handshake_data);
@@ -5415,7 +5579,7 @@ impl PeerNetwork {
         let unauthenticated_inbounds = self.find_unauthenticated_inbound_convos();
 
         // run existing conversations, clear out broken ones, and get back messages forwarded to us
-        let (error_events, unsolicited_messages) =
+        let (error_events, mut unsolicited_messages) =
             self.process_ready_sockets(sortdb, chainstate, &mut poll_state);
         for error_event in error_events {
             debug!(
@@ -5424,6 +5588,10 @@ impl PeerNetwork {
             );
             self.deregister_peer(error_event);
         }
+        // rebuild compact blocks before handling pushed blocks
+        self.process_compact_blocks(chainstate, mempool, &mut unsolicited_messages);
+        // fetch announced transactions we're missing, and announce our own
+        self.process_tx_announcements(mempool, ibd, &mut unsolicited_messages);
         let unhandled_messages =
             self.handle_unsolicited_messages(sortdb, chainstate, unsolicited_messages, ibd, true);
         network_result.consume_unsolicited(unhandled_messages);
@@ -5521,6 +5689,14 @@ impl PeerNetwork {
         // update our relay statistics, so we know who to forward messages to
         self.update_relayer_stats(&network_result);
 
+        // save what we learned about our peers' behavior
+        if let Err(e) = self.flush_peer_reputations() {
+            warn!(
+                "{:?}: Failed to save peer reputations: {:?}",
+                &self.local_peer, &e
+            );
+        }
+
         // finally, handle network I/O requests from other threads, and get back reply handles to them.
         // do this after processing new sockets, so we don't accidentally re-use an event ID.
         self.dispatch_requests();
@@ -22,17 +22,103 @@ use std::{io, net, time};
 
 use mio::{net as mio_net, PollOpt, Ready, Token};
 use rand::RngCore;
-use stacks_common::types::net::PeerAddress;
+use stacks_common::types::net::PeerHost;
 use stacks_common::util::{log, sleep_ms};
 use {mio, rand};
 
+#[cfg(any(test, feature = "testing"))]
+use crate::net::simnet::{SimHostId, SimNetwork, SimSocket};
+use crate::net::socks5::{self, Socks5Socket};
 use crate::net::{Error as net_error, Neighbor, NeighborKey};
 use crate::util_lib::db::{DBConn, Error as db_error};
 
 const SERVER: Token = mio::Token(0);
 
+/// A connection to a remote peer.  This is a TCP socket (possibly through a SOCKS5 proxy),
+/// unless the network is simulated.
+#[derive(Debug)]
+pub enum NetworkSocket {
+    Tcp(mio_net::TcpStream),
+    Socks5(Socks5Socket),
+    #[cfg(any(test, feature = "testing"))]
+    Sim(SimSocket),
+}
+
+impl NetworkSocket {
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            NetworkSocket::Tcp(sock) => sock.peer_addr(),
+            NetworkSocket::Socks5(sock) => sock.peer_addr(),
+            #[cfg(any(test, feature = "testing"))]
+            NetworkSocket::Sim(sock) => sock.peer_addr(),
+        }
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            NetworkSocket::Tcp(sock) => sock.shutdown(how),
+            NetworkSocket::Socks5(sock) => sock.shutdown(how),
+            #[cfg(any(test, feature = "testing"))]
+            NetworkSocket::Sim(sock) => sock.shutdown(how),
+        }
+    }
+}
+
+impl Read for NetworkSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            NetworkSocket::Tcp(sock) => sock.read(buf),
+            NetworkSocket::Socks5(sock) => sock.read(buf),
+            #[cfg(any(test, feature = "testing"))]
+            NetworkSocket::Sim(sock) => sock.read(buf),
+        }
+    }
+}
+
+impl Write for NetworkSocket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            NetworkSocket::Tcp(sock) => sock.write(buf),
+            NetworkSocket::Socks5(sock) => sock.write(buf),
+            #[cfg(any(test, feature = "testing"))]
+            NetworkSocket::Sim(sock) => sock.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            NetworkSocket::Tcp(sock) => sock.flush(),
+            NetworkSocket::Socks5(sock) => sock.flush(),
+            #[cfg(any(test, feature = "testing"))]
+            NetworkSocket::Sim(sock) => sock.flush(),
+        }
+    }
+}
+
+/// A server socket that accepts connections from remote peers
+#[derive(Debug)]
+enum NetworkListener {
+    Tcp(mio_net::TcpListener),
+    #[cfg(any(test, feature = "testing"))]
+    Sim(SimNetwork, SocketAddr),
+}
+
+impl NetworkListener {
+    fn accept(&self) -> io::Result<(NetworkSocket, SocketAddr)> {
+        match self {
+            NetworkListener::Tcp(listener) => listener
+                .accept()
+                .map(|(sock, addr)| (NetworkSocket::Tcp(sock), addr)),
+            #[cfg(any(test, feature = "testing"))]
+            NetworkListener::Sim(sim, listen_addr) => sim
+                .accept(listen_addr)
+                .map(|(sock, addr)| (NetworkSocket::Sim(sock), addr)),
+        }
+    }
+}
+
 pub struct NetworkPollState {
-    pub new: HashMap<usize, mio_net::TcpStream>,
+    pub new: HashMap<usize, NetworkSocket>,
     pub ready: Vec<usize>,
 }
 
@@ -49,7 +135,7 @@ impl NetworkPollState {
 #[derive(Debug)]
 pub struct NetworkServerState {
     addr: SocketAddr,
-    server_socket: mio_net::TcpListener,
+    server_socket: NetworkListener,
     server_event: mio::Token,
 }
 
@@ -62,6 +148,11 @@ pub struct NetworkState {
     servers: Vec<NetworkServerState>,
     count: usize,
     event_map: HashMap<usize, usize>, // map socket events to their registered server socket (including server sockets)
+    /// SOCKS5 proxy to make outbound connections through, if any
+    socks5_proxy: Option<SocketAddr>,
+    /// simulated network this poller's sockets live on, and the host it is on that network
+    #[cfg(any(test, feature = "testing"))]
+    sim: Option<(SimNetwork, SimHostId)>,
 }
 
 impl NetworkState {
@@ -80,9 +171,28 @@ impl NetworkState {
             servers: vec![],
             count: 1,
             event_map: HashMap::new(),
+            socks5_proxy: None,
+            #[cfg(any(test, feature = "testing"))]
+            sim: None,
         })
     }
 
+    /// Make outbound connections through the given SOCKS5 proxy (or directly, if None)
+    pub fn set_socks5_proxy(&mut self, proxy: Option<SocketAddr>) {
+        self.socks5_proxy = proxy;
+    }
+
+    /// Make a poller whose sockets are simulated.  It joins `sim` as a new host.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn new_simulated(
+        sim: &SimNetwork,
+        event_capacity: usize,
+    ) -> Result<NetworkState, net_error> {
+        let mut network_state = NetworkState::new(event_capacity)?;
+        network_state.sim = Some((sim.clone(), sim.add_host()));
+        Ok(network_state)
+    }
+
     pub fn num_events(&self) -> usize {
         self.event_map.len()
     }
@@ -126,6 +236,18 @@ impl NetworkState {
     /// Bind to the given socket address.
     /// Returns the handle to the poll state and the bound address, used to key network poll events.
     pub fn bind(&mut self, addr: &SocketAddr) -> Result<(usize, SocketAddr), net_error> {
+        #[cfg(any(test, feature = "testing"))]
+        if let Some((sim, host)) = self.sim.clone() {
+            let next_server_event = self.next_event_id()?;
+            let local_addr = sim.bind(host, addr, next_server_event)?;
+            self.add_server(
+                NetworkListener::Sim(sim, local_addr.clone()),
+                local_addr.clone(),
+                next_server_event,
+            );
+            return Ok((next_server_event, local_addr));
+        }
+
         let server = NetworkState::bind_address(addr)?;
         let next_server_event = self.next_event_id()?;
 
@@ -148,9 +270,24 @@ impl NetworkState {
             net_error::BindError
         })?;
 
+        self.add_server(
+            NetworkListener::Tcp(server),
+            local_addr.clone(),
+            next_server_event,
+        );
+        Ok((next_server_event, local_addr))
+    }
+
+    /// Track a newly-bound server socket
+    fn add_server(
+        &mut self,
+        server_socket: NetworkListener,
+        local_addr: SocketAddr,
+        next_server_event: usize,
+    ) {
         let network_server = NetworkServerState {
-            addr: local_addr.clone(),
-            server_socket: server,
+            addr: local_addr,
+            server_socket,
             server_event: mio::Token(next_server_event),
         };
 
@@ -161,8 +298,6 @@ impl NetworkState {
 
         self.servers.push(network_server);
         self.event_map.insert(next_server_event, 0); // server events always mapped to 0
-
-        Ok((next_server_event, local_addr))
     }
 
     /// Register a socket for read/write notifications with this poller.
@@ -173,7 +308,7 @@ impl NetworkState {
         &mut self,
         server_event_id: usize,
         hint_event_id: usize,
-        sock: &mio_net::TcpStream,
+        sock: &NetworkSocket,
     ) -> Result<usize, net_error> {
         let hint_event_id = hint_event_id % (self.event_capacity + self.servers.len());
         if let Some(x) = self.event_map.get(&server_event_id) {
@@ -205,15 +340,30 @@ impl NetworkState {
             self.servers.len()
         );
 
-        self.poll
-            .register(sock, mio::Token(event_id), Ready::all(), PollOpt::edge())
-            .map_err(|e| {
-                error!(
-                    "Failed to register socket on server {} event ID {} ({}): {:?}",
-                    server_event_id, event_id, hint_event_id, &e
-                );
-                net_error::RegisterError
-            })?;
+        let res = match sock {
+            NetworkSocket::Tcp(sock) => {
+                self.poll
+                    .register(sock, mio::Token(event_id), Ready::all(), PollOpt::edge())
+            }
+            NetworkSocket::Socks5(sock) => self.poll.register(
+                sock.stream(),
+                mio::Token(event_id),
+                Ready::all(),
+                PollOpt::edge(),
+            ),
+            #[cfg(any(test, feature = "testing"))]
+            NetworkSocket::Sim(sock) => {
+                sock.register(event_id);
+                Ok(())
+            }
+        };
+        res.map_err(|e| {
+            error!(
+                "Failed to register socket on server {} event ID {} ({}): {:?}",
+                server_event_id, event_id, hint_event_id, &e
+            );
+            net_error::RegisterError
+        })?;
 
         self.event_map.insert(event_id, server_event_id);
 
@@ -230,11 +380,7 @@ impl NetworkState {
     }
 
     /// Deregister a socket event
-    pub fn deregister(
-        &mut self,
-        event_id: usize,
-        sock: &mio_net::TcpStream,
-    ) -> Result<(), net_error> {
+    pub fn deregister(&mut self, event_id: usize, sock: &NetworkSocket) -> Result<(), net_error> {
         assert!(
             self.event_map.contains_key(&event_id),
             "BUG: no such socket {}",
@@ -242,9 +388,22 @@ impl NetworkState {
         );
         self.event_map.remove(&event_id);
 
-        if let Err(e) = self.poll.deregister(sock) {
-            warn!("Failed to deregister socket {}: {:?}", event_id, &e);
-        };
+        match sock {
+            NetworkSocket::Tcp(sock) => {
+                if let Err(e) = self.poll.deregister(sock) {
+                    warn!("Failed to deregister socket {}: {:?}", event_id, &e);
+                };
+            }
+            NetworkSocket::Socks5(sock) => {
+                if let Err(e) = self.poll.deregister(sock.stream()) {
+                    warn!("Failed to deregister socket {}: {:?}", event_id, &e);
+                };
+            }
+            #[cfg(any(test, feature = "testing"))]
+            NetworkSocket::Sim(sock) => {
+                sock.deregister();
+            }
+        }
 
         debug!(
             "Socket deregistered: {}, {:?} (Events total: {}, max: {})",
@@ -304,6 +463,17 @@ impl NetworkState {
         addr: &SocketAddr,
         socket_send_buffer: u32,
         socket_recv_buffer: u32,
+    ) -> Result<NetworkSocket, net_error> {
+        let stream = NetworkState::connect_stream(addr, socket_send_buffer, socket_recv_buffer)?;
+        test_debug!("New socket connected to {:?}: {:?}", addr, &stream);
+        Ok(NetworkSocket::Tcp(stream))
+    }
+
+    /// Start connecting a TCP stream to `addr`
+    fn connect_stream(
+        addr: &SocketAddr,
+        socket_send_buffer: u32,
+        socket_recv_buffer: u32,
     ) -> Result<mio_net::TcpStream, net_error> {
         let stream = mio_net::TcpStream::connect(addr).map_err(|_e| {
             test_debug!("Failed to convert to mio stream: {:?}", &_e);
@@ -362,13 +532,54 @@ impl NetworkState {
                 })?;
         }
 
-        test_debug!("New socket connected to {:?}: {:?}", addr, &stream);
         Ok(stream)
     }
 
-    /// Poll all server sockets.
-    /// Returns a map between network server handles (returned by bind()) and their new polling state
-    pub fn poll(&mut self, timeout: u64) -> Result<HashMap<usize, NetworkPollState>, net_error> {
+    /// Connect to a remote peer over this poller's network, but don't register it with the poll
+    /// handle.  Like `connect()`, the connection completes asynchronously.  If a SOCKS5 proxy is
+    /// set, the connection goes through it (unless `addr` is a loopback address), and the proxy
+    /// handshake happens as part of the socket's first reads and writes.
+    pub fn connect_socket(
+        &self,
+        addr: &SocketAddr,
+        socket_send_buffer: u32,
+        socket_recv_buffer: u32,
+    ) -> Result<NetworkSocket, net_error> {
+        #[cfg(any(test, feature = "testing"))]
+        if let Some((sim, host)) = self.sim.as_ref() {
+            let sock = sim.connect(*host, addr);
+            test_debug!("New simulated socket connected to {:?}: {:?}", addr, &sock);
+            return Ok(NetworkSocket::Sim(sock));
+        }
+
+        let target = PeerHost::from_socketaddr(addr);
+        match self.socks5_proxy.as_ref() {
+            Some(proxy) if socks5::is_proxied(&target) => {
+                let stream =
+                    NetworkState::connect_stream(proxy, socket_send_buffer, socket_recv_buffer)?;
+                let sock = Socks5Socket::new(stream, addr.clone()).map_err(|e| {
+                    debug!("Failed to set up SOCKS5 connection to {:?}: {:?}", addr, &e);
+                    net_error::ConnectionError
+                })?;
+                test_debug!(
+                    "New socket connected to {:?} through SOCKS5 proxy {:?}: {:?}",
+                    addr,
+                    proxy,
+                    &sock
+                );
+                Ok(NetworkSocket::Socks5(sock))
+            }
+            _ => NetworkState::connect(addr, socket_send_buffer, socket_recv_buffer),
+        }
+    }
+
+    /// Wait for readiness events, and return the tokens of the sockets they are for
+    fn poll_tokens(&mut self, timeout: u64) -> Result<Vec<mio::Token>, net_error> {
+        #[cfg(any(test, feature = "testing"))]
+        if let Some((sim, host)) = self.sim.as_ref() {
+            return Ok(sim.poll(*host).into_iter().map(mio::Token).collect());
+        }
+
         self.events.clear();
         self.poll
             .poll(&mut self.events, Some(Duration::from_millis(timeout)))
@@ -377,6 +588,14 @@ impl NetworkState {
                 net_error::PollError
             })?;
 
+        Ok(self.events.iter().map(|event| event.token()).collect())
+    }
+
+    /// Poll all server sockets.
+    /// Returns a map between network server handles (returned by bind()) and their new polling state
+    pub fn poll(&mut self, timeout: u64) -> Result<HashMap<usize, NetworkPollState>, net_error> {
+        let tokens = self.poll_tokens(timeout)?;
+
         let mut poll_states = HashMap::new();
         for server in self.servers.iter() {
             // pre-populate with server tokens
@@ -386,8 +605,7 @@ impl NetworkState {
 
         let mut new_events = HashSet::new();
 
-        for event in &self.events {
-            let token = event.token();
+        for token in tokens.into_iter() {
             let mut is_server_event = false;
 
             for server in self.servers.iter() {
@@ -586,6 +804,80 @@ mod test {
         assert_eq!(Err(net_error::TooManyPeers), res);
     }
 
+    #[test]
+    fn test_connect_socket_socks5() {
+        // a SOCKS5 proxy that accepts one CONNECT, and then answers "hello" with "world"
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        let proxy = std::thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            sock.read_exact(&mut greeting).unwrap();
+            sock.write_all(&[5, 0]).unwrap();
+
+            let mut header = [0u8; 4];
+            sock.read_exact(&mut header).unwrap();
+            assert_eq!(header, [5, 1, 0, 1]);
+            let mut rest = vec![0u8; 6];
+            sock.read_exact(&mut rest).unwrap();
+            sock.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+
+            let mut request = [0u8; 5];
+            sock.read_exact(&mut request).unwrap();
+            assert_eq!(&request, b"hello");
+            sock.write_all(b"world").unwrap();
+            rest
+        });
+
+        let mut ns = NetworkState::new(100).unwrap();
+        let (server_event_id, local_addr) = ns
+            .bind(&"127.0.0.1:0".parse::<SocketAddr>().unwrap())
+            .unwrap();
+        let remote_addr = "1.2.3.4:20444".parse::<SocketAddr>().unwrap();
+
+        ns.set_socks5_proxy(Some(proxy_addr));
+
+        // loopback addresses are not proxied
+        let sock = ns.connect_socket(&local_addr, 4096, 4096).unwrap();
+        assert!(matches!(sock, NetworkSocket::Tcp(_)));
+
+        let mut sock = ns.connect_socket(&remote_addr, 4096, 4096).unwrap();
+        assert!(matches!(sock, NetworkSocket::Socks5(_)));
+        let event_id = ns.register(server_event_id, 1, &sock).unwrap();
+
+        // the socket reports the remote address as its peer once connected to the proxy
+        let mut sent = false;
+        let mut received = vec![];
+        for _ in 0..500 {
+            if !sent && sock.peer_addr().is_ok() {
+                assert_eq!(sock.peer_addr().unwrap(), remote_addr);
+                match sock.write(b"hello") {
+                    Ok(count) => {
+                        assert_eq!(count, 5);
+                        sent = true;
+                    }
+                    Err(e) => assert_eq!(e.kind(), ErrorKind::WouldBlock),
+                }
+            }
+            let mut buf = [0u8; 16];
+            match sock.read(&mut buf) {
+                Ok(count) => received.extend_from_slice(&buf[0..count]),
+                Err(e) => assert_eq!(e.kind(), ErrorKind::WouldBlock),
+            }
+            if received.len() >= 5 {
+                break;
+            }
+            sleep_ms(10);
+        }
+        assert_eq!(&received, b"world");
+
+        let mut expected_rest = vec![1, 2, 3, 4];
+        expected_rest.extend_from_slice(&20444u16.to_be_bytes());
+        assert_eq!(proxy.join().unwrap(), expected_rest);
+
+        ns.deregister(event_id, &sock).unwrap();
+    }
+
     #[test]
     fn test_register_deregister_stress() {
         let mut ns = NetworkState::new(20).unwrap();
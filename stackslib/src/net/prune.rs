@@ -29,6 +29,7 @@ use crate::net::db::{LocalPeer, PeerDB};
 use crate::net::neighbors::*;
 use crate::net::p2p::*;
 use crate::net::poll::{NetworkPollState, NetworkState};
+use crate::net::reputation::REPUTATION_BAD_THRESHOLD;
 use crate::net::Error as net_error;
 /// This module contains the logic for pruning client and neighbor connections
 use crate::net::*;
@@ -104,12 +105,28 @@ impl PeerNetwork {
         Ok(org_neighbor)
     }
 
-    /// Sort function for a neighbor list in order to compare by by uptime and health.
+    /// Sort function for a neighbor list in order to compare by by reputation, uptime and health.
+    /// Neighbors with a bad reputation sort first.
     /// Bucket uptime geometrically by powers of 2 -- a node that's been up for X seconds is
     /// likely to be up for X more seconds, so we only really want to distinguish between nodes that
     /// have wildly different uptimes.
-    /// Within uptime buckets, sort by health.
-    fn compare_neighbor_uptime_health(stats1: &NeighborStats, stats2: &NeighborStats) -> Ordering {
+    /// Within uptime buckets, sort by health, and then by reputation.
+    fn compare_neighbor_uptime_health(
+        stats1: &NeighborStats,
+        reputation_1: f64,
+        stats2: &NeighborStats,
+        reputation_2: f64,
+    ) -> Ordering {
+        let bad_1 = reputation_1 <= REPUTATION_BAD_THRESHOLD;
+        let bad_2 = reputation_2 <= REPUTATION_BAD_THRESHOLD;
+        if bad_1 != bad_2 {
+            return if bad_1 {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            };
+        }
+
         let now = get_epoch_time_secs();
         let uptime_1 = (now - stats1.first_contact_time) as f64;
         let uptime_2 = (now - stats2.first_contact_time) as f64;
@@ -135,6 +152,14 @@ impl PeerNetwork {
             return Ordering::Greater;
         }
 
+        // same health; sort by reputation
+        if reputation_1 < reputation_2 {
+            return Ordering::Less;
+        }
+        if reputation_1 > reputation_2 {
+            return Ordering::Greater;
+        }
+
         // flip a coin
         let mut rng = thread_rng();
         if rng.next_u32() % 2 == 0 {
@@ -181,6 +206,7 @@ impl PeerNetwork {
         }
 
         let mut org_neighbors = self.org_neighbor_distribution(self.peerdb.conn(), preserve)?;
+        let reputations = self.get_neighbor_reputations()?;
         let mut ret = vec![];
         let orgs: Vec<u32> = org_neighbors
             .keys()
@@ -196,11 +222,17 @@ impl PeerNetwork {
             // likely to be up for X more seconds, so we only really want to distinguish between nodes that
             // have wildly different uptimes.
             // Within uptime buckets, sort by health.
+            // Neighbors with bad reputations go first, no matter what.
             match org_neighbors.get_mut(&org) {
                 None => {}
                 Some(ref mut neighbor_infos) => {
-                    neighbor_infos.sort_by(|&(ref _nk1, ref stats1), &(ref _nk2, ref stats2)| {
-                        PeerNetwork::compare_neighbor_uptime_health(stats1, stats2)
+                    neighbor_infos.sort_by(|&(ref nk1, ref stats1), &(ref nk2, ref stats2)| {
+                        PeerNetwork::compare_neighbor_uptime_health(
+                            stats1,
+                            reputations.get(nk1).copied().unwrap_or(0.0),
+                            stats2,
+                            reputations.get(nk2).copied().unwrap_or(0.0),
+                        )
                     });
                 }
             }
@@ -341,11 +373,29 @@ impl PeerNetwork {
             }
         }
 
-        // sort in order by first-contact time (oldest first)
+        // sort in order by first-contact time (oldest first), but put neighbors with bad
+        // reputations last
+        let reputations = self.get_neighbor_reputations().unwrap_or_else(|e| {
+            warn!(
+                "{:?}: Failed to load neighbor reputations: {:?}",
+                &self.local_peer, &e
+            );
+            HashMap::new()
+        });
+        let is_bad = |nk: &NeighborKey| {
+            reputations.get(nk).copied().unwrap_or(0.0) <= REPUTATION_BAD_THRESHOLD
+        };
         for (_, stats_list) in ip_neighbor.iter_mut() {
             stats_list.sort_by(
-                |&(ref _e1, ref _nk1, ref stats1), &(ref _e2, ref _nk2, ref stats2)| {
-                    if stats1.first_contact_time < stats2.first_contact_time {
+                |&(ref _e1, ref nk1, ref stats1), &(ref _e2, ref nk2, ref stats2)| {
+                    let (bad_1, bad_2) = (is_bad(nk1), is_bad(nk2));
+                    if bad_1 != bad_2 {
+                        if bad_2 {
+                            Ordering::Less
+                        } else {
+                            Ordering::Greater
+                        }
+                    } else if stats1.first_contact_time < stats2.first_contact_time {
                         Ordering::Less
                     } else if stats1.first_contact_time > stats2.first_contact_time {
                         Ordering::Greater
@@ -377,6 +427,38 @@ impl PeerNetwork {
         to_remove
     }
 
+    /// Find connected neighbors whose reputation has gone bad.
+    /// Returns the list of neighbor keys to remove.
+    fn prune_frontier_reputation(&mut self, preserve: &HashSet<usize>) -> Vec<NeighborKey> {
+        let mut to_remove = vec![];
+        for (nk, event_id) in self.events.iter() {
+            if preserve.contains(event_id) {
+                continue;
+            }
+            let Some(convo) = self.peers.get(event_id) else {
+                continue;
+            };
+            match self.get_convo_reputation(convo) {
+                Ok(reputation) => {
+                    if reputation <= REPUTATION_BAD_THRESHOLD {
+                        debug!(
+                            "{:?}: Prune {:?} because its reputation is {}",
+                            &self.local_peer, nk, reputation
+                        );
+                        to_remove.push(nk.clone());
+                    }
+                }
+                Err(e) => {
+                    warn!(
+                        "{:?}: Failed to load reputation of {:?}: {:?}",
+                        &self.local_peer, nk, &e
+                    );
+                }
+            }
+        }
+        to_remove
+    }
+
     /// Dump our peer table
     #[cfg(test)]
     pub fn dump_peer_table(&mut self) -> (Vec<String>, Vec<String>) {
@@ -407,6 +489,19 @@ impl PeerNetwork {
             &self.local_peer, num_inbound, num_outbound
         );
 
+        let pruned_by_reputation = self.prune_frontier_reputation(preserve);
+
+        debug!(
+            "{:?}: remove {} peers by reputation",
+            &self.local_peer,
+            pruned_by_reputation.len()
+        );
+
+        for prune in pruned_by_reputation.iter() {
+            debug!("{:?}: prune by reputation: {:?}", &self.local_peer, prune);
+            self.deregister_neighbor(&prune);
+        }
+
         let pruned_by_ip = self.prune_frontier_inbound_ip(preserve);
 
         debug!(
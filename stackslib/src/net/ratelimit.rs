@@ -0,0 +1,381 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Bandwidth rate limiting.
+//!
+//! Each connection can carry a `BandwidthLimiter` for each direction.  A limiter draws on up to
+//! two token buckets: one for the connection alone, and one shared by every connection of the
+//! same kind (P2P or HTTP) and direction.  A bucket holds up to one second's worth of bytes, and
+//! a connection may only move as many bytes as both of its buckets allow.  Once they run dry, the
+//! connection stops reading or writing until they refill.
+//!
+//! Traffic is sorted into `BandwidthClass`es.  Lower classes may not draw a bucket all the way
+//! down; they must leave a reserve for the classes above them.  So when a bucket is under
+//! pressure, block and StackerDB traffic keeps flowing while transaction gossip and mempool sync
+//! wait their turn.
+//!
+//! A rate of 0 means no limit, which is the default.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use stacks_common::util::get_epoch_time_ms;
+
+use crate::net::{StacksMessageID, StacksMessageType};
+
+/// Priority class of a message, for the purposes of bandwidth limiting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BandwidthClass {
+    /// Transaction gossip and mempool sync
+    Low,
+    /// Everything not otherwise classified, such as handshakes, neighbor walks and inventories
+    Normal,
+    /// Blocks, microblocks and StackerDB chunks
+    High,
+}
+
+impl BandwidthClass {
+    /// Percentage of a bucket's capacity that traffic of this class must leave untouched
+    pub fn reserve_percent(&self) -> u64 {
+        match self {
+            BandwidthClass::Low => 50,
+            BandwidthClass::Normal => 25,
+            BandwidthClass::High => 0,
+        }
+    }
+
+    /// Classify a P2P message by its payload
+    pub fn of_p2p_message(payload: &StacksMessageType) -> BandwidthClass {
+        BandwidthClass::of_p2p_message_id(payload.get_message_id())
+    }
+
+    /// Classify a P2P message by its type
+    pub fn of_p2p_message_id(message_id: StacksMessageID) -> BandwidthClass {
+        match message_id {
+            StacksMessageID::Blocks
+            | StacksMessageID::Microblocks
+            | StacksMessageID::BlocksAvailable
+            | StacksMessageID::MicroblocksAvailable
+            | StacksMessageID::CompactBlock
+            | StacksMessageID::GetBlockTxs
+            | StacksMessageID::BlockTxs
+            | StacksMessageID::StackerDBHandshakeAccept
+            | StacksMessageID::StackerDBGetChunkInv
+            | StacksMessageID::StackerDBChunkInv
+            | StacksMessageID::StackerDBGetChunk
+            | StacksMessageID::StackerDBChunk
+            | StacksMessageID::StackerDBPushChunk => BandwidthClass::High,
+            StacksMessageID::Transaction | StacksMessageID::TxInv | StacksMessageID::GetTxs => {
+                BandwidthClass::Low
+            }
+            _ => BandwidthClass::Normal,
+        }
+    }
+
+    /// Classify an HTTP request by its path.  The response to a request has the same class as
+    /// the request itself.
+    pub fn of_http_request(path: &str) -> BandwidthClass {
+        if path.starts_with("/v2/blocks/")
+            || path.starts_with("/v2/microblocks/")
+            || path.starts_with("/v2/stackerdb/")
+        {
+            BandwidthClass::High
+        } else if path.starts_with("/v2/mempool/query") || path.starts_with("/v2/transactions") {
+            BandwidthClass::Low
+        } else {
+            BandwidthClass::Normal
+        }
+    }
+}
+
+/// A token bucket, where each token is a byte.  It refills at `rate` bytes per second, up to one
+/// second's worth of bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenBucket {
+    rate: u64,
+    capacity: u64,
+    tokens: u64,
+    last_refill_ms: u128,
+}
+
+impl TokenBucket {
+    /// Make a full bucket that refills at `rate` bytes per second
+    pub fn new(rate: u64, now_ms: u128) -> TokenBucket {
+        TokenBucket {
+            rate,
+            capacity: rate,
+            tokens: rate,
+            last_refill_ms: now_ms,
+        }
+    }
+
+    /// Add the tokens accrued since the last refill.
+    /// `last_refill_ms` only moves once at least one token accrues, so slow buckets still fill.
+    fn refill(&mut self, now_ms: u128) {
+        if self.tokens >= self.capacity {
+            self.last_refill_ms = now_ms;
+            return;
+        }
+        let elapsed_ms = now_ms.saturating_sub(self.last_refill_ms);
+        let accrued = elapsed_ms.saturating_mul(self.rate as u128) / 1000;
+        if accrued > 0 {
+            let accrued = u64::try_from(accrued).unwrap_or(u64::MAX);
+            self.tokens = self.tokens.saturating_add(accrued).min(self.capacity);
+            self.last_refill_ms = now_ms;
+        }
+    }
+
+    /// How many bytes may traffic of the given class move right now?
+    pub fn available(&mut self, class: BandwidthClass, now_ms: u128) -> u64 {
+        self.refill(now_ms);
+        let reserve = self.capacity.saturating_mul(class.reserve_percent()) / 100;
+        self.tokens.saturating_sub(reserve)
+    }
+
+    /// Take `num_bytes` tokens out of the bucket
+    pub fn consume(&mut self, num_bytes: u64) {
+        self.tokens = self.tokens.saturating_sub(num_bytes);
+    }
+
+    /// How many tokens are in the bucket, as of the last refill?
+    pub fn tokens(&self) -> u64 {
+        self.tokens
+    }
+
+    /// Refill rate, in bytes per second
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+}
+
+/// Token bucket shared by all connections of one kind and direction
+pub type SharedTokenBucket = Arc<Mutex<TokenBucket>>;
+
+/// Make a bucket to be shared across connections.  Returns None if `rate` is 0 (unlimited).
+pub fn make_shared_bucket(rate: u64) -> Option<SharedTokenBucket> {
+    if rate == 0 {
+        return None;
+    }
+    Some(Arc::new(Mutex::new(TokenBucket::new(
+        rate,
+        get_epoch_time_ms(),
+    ))))
+}
+
+/// Bandwidth limit for one direction of one connection
+#[derive(Debug, Clone, Default)]
+pub struct BandwidthLimiter {
+    /// this connection's own bucket, if it has a limit
+    peer: Option<TokenBucket>,
+    /// the bucket shared with all other connections of this kind and direction, if there is a
+    /// global limit
+    global: Option<SharedTokenBucket>,
+}
+
+impl BandwidthLimiter {
+    /// Make a limiter with a per-connection limit of `peer_rate` bytes per second (0 for no
+    /// limit), and an optional shared global bucket.
+    pub fn new(peer_rate: u64, global: Option<SharedTokenBucket>) -> BandwidthLimiter {
+        let peer = if peer_rate > 0 {
+            Some(TokenBucket::new(peer_rate, get_epoch_time_ms()))
+        } else {
+            None
+        };
+        BandwidthLimiter { peer, global }
+    }
+
+    /// Make a limiter that never limits
+    pub fn unlimited() -> BandwidthLimiter {
+        BandwidthLimiter::default()
+    }
+
+    /// Does this limiter limit anything?
+    pub fn is_limited(&self) -> bool {
+        self.peer.is_some() || self.global.is_some()
+    }
+
+    /// How many bytes may traffic of the given class move right now?
+    /// Returns None if there is no limit.
+    pub fn budget(&mut self, class: BandwidthClass, now_ms: u128) -> Option<u64> {
+        let mut budget = None;
+        if let Some(peer) = self.peer.as_mut() {
+            budget = Some(peer.available(class, now_ms));
+        }
+        if let Some(global) = self.global.as_ref() {
+            let global_available = match global.lock() {
+                Ok(mut bucket) => bucket.available(class, now_ms),
+                Err(_) => {
+                    // poisoned; don't let that stall the network
+                    return budget;
+                }
+            };
+            budget = Some(budget.map_or(global_available, |b| b.min(global_available)));
+        }
+        budget
+    }
+
+    /// Account for `num_bytes` bytes moved
+    pub fn consume(&mut self, num_bytes: u64) {
+        if let Some(peer) = self.peer.as_mut() {
+            peer.consume(num_bytes);
+        }
+        if let Some(global) = self.global.as_ref() {
+            if let Ok(mut bucket) = global.lock() {
+                bucket.consume(num_bytes);
+            }
+        }
+    }
+}
+
+/// Get the sockets to service: the ones the poller reported `ready`, plus those of the
+/// `throttled` conversations.  Sockets are edge-triggered, so a conversation that stopped reading
+/// or writing because it ran out of bandwidth won't be reported ready again once its buckets
+/// refill; it has to be revisited regardless.
+pub fn ready_or_throttled(ready: &[usize], throttled: impl Iterator<Item = usize>) -> Vec<usize> {
+    let mut seen: HashSet<usize> = ready.iter().copied().collect();
+    let mut sockets = ready.to_vec();
+    for event_id in throttled {
+        if seen.insert(event_id) {
+            sockets.push(event_id);
+        }
+    }
+    sockets
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket_refill() {
+        let mut bucket = TokenBucket::new(1000, 0);
+        assert_eq!(bucket.available(BandwidthClass::High, 0), 1000);
+
+        bucket.consume(1000);
+        assert_eq!(bucket.available(BandwidthClass::High, 0), 0);
+
+        // half a second later, half the bucket is back
+        assert_eq!(bucket.available(BandwidthClass::High, 500), 500);
+
+        // never fills past capacity
+        assert_eq!(bucket.available(BandwidthClass::High, 10_000), 1000);
+
+        // consuming more than is there just empties it
+        bucket.consume(5000);
+        assert_eq!(bucket.tokens(), 0);
+    }
+
+    #[test]
+    fn test_token_bucket_slow_refill() {
+        // a bucket slower than one byte per millisecond still fills when polled often
+        let mut bucket = TokenBucket::new(10, 0);
+        bucket.consume(10);
+        for now_ms in 1..100 {
+            assert_eq!(bucket.available(BandwidthClass::High, now_ms), 0);
+        }
+        assert_eq!(bucket.available(BandwidthClass::High, 100), 1);
+        assert_eq!(bucket.available(BandwidthClass::High, 1100), 10);
+    }
+
+    #[test]
+    fn test_token_bucket_class_reserves() {
+        let mut bucket = TokenBucket::new(1000, 0);
+        assert_eq!(bucket.available(BandwidthClass::Low, 0), 500);
+        assert_eq!(bucket.available(BandwidthClass::Normal, 0), 750);
+        assert_eq!(bucket.available(BandwidthClass::High, 0), 1000);
+
+        // low-priority traffic drains the bucket down to its reserve...
+        bucket.consume(500);
+        assert_eq!(bucket.available(BandwidthClass::Low, 0), 0);
+
+        // ...but higher-priority traffic can still go
+        assert_eq!(bucket.available(BandwidthClass::Normal, 0), 250);
+        assert_eq!(bucket.available(BandwidthClass::High, 0), 500);
+    }
+
+    #[test]
+    fn test_bandwidth_limiter() {
+        let mut unlimited = BandwidthLimiter::unlimited();
+        assert!(!unlimited.is_limited());
+        assert_eq!(unlimited.budget(BandwidthClass::Low, 0), None);
+
+        // the global bucket is shared between limiters
+        let global = make_shared_bucket(1000).unwrap();
+        let mut limiter_1 = BandwidthLimiter::new(0, Some(global.clone()));
+        let mut limiter_2 = BandwidthLimiter::new(100, Some(global.clone()));
+        assert!(limiter_1.is_limited());
+
+        let now_ms = global.lock().unwrap().last_refill_ms;
+        assert_eq!(limiter_1.budget(BandwidthClass::High, now_ms), Some(1000));
+
+        // the per-peer bucket is smaller than the global one
+        assert_eq!(limiter_2.budget(BandwidthClass::High, now_ms), Some(100));
+
+        limiter_1.consume(950);
+        assert_eq!(limiter_1.budget(BandwidthClass::High, now_ms), Some(50));
+        assert_eq!(limiter_2.budget(BandwidthClass::High, now_ms), Some(50));
+        assert_eq!(limiter_2.budget(BandwidthClass::Low, now_ms), Some(0));
+
+        assert!(make_shared_bucket(0).is_none());
+    }
+
+    #[test]
+    fn test_bandwidth_classes() {
+        assert_eq!(
+            BandwidthClass::of_p2p_message(&StacksMessageType::GetNeighbors),
+            BandwidthClass::Normal
+        );
+        assert_eq!(
+            BandwidthClass::of_http_request("/v2/blocks/upload/0000"),
+            BandwidthClass::High
+        );
+        assert_eq!(
+            BandwidthClass::of_http_request("/v2/stackerdb/SP000000000000000000002Q6VF78/foo"),
+            BandwidthClass::High
+        );
+        assert_eq!(
+            BandwidthClass::of_http_request("/v2/mempool/query?page_id=00"),
+            BandwidthClass::Low
+        );
+        assert_eq!(
+            BandwidthClass::of_http_request("/v2/transactions"),
+            BandwidthClass::Low
+        );
+        assert_eq!(
+            BandwidthClass::of_http_request("/v2/info"),
+            BandwidthClass::Normal
+        );
+        assert_eq!(
+            BandwidthClass::of_p2p_message_id(StacksMessageID::StackerDBChunk),
+            BandwidthClass::High
+        );
+        assert_eq!(
+            BandwidthClass::of_p2p_message_id(StacksMessageID::TxInv),
+            BandwidthClass::Low
+        );
+        assert!(BandwidthClass::Low < BandwidthClass::Normal);
+        assert!(BandwidthClass::Normal < BandwidthClass::High);
+    }
+
+    #[test]
+    fn test_ready_or_throttled() {
+        assert_eq!(
+            ready_or_throttled(&[3, 1, 2], [2, 4, 4, 1, 5].into_iter()),
+            vec![3, 1, 2, 4, 5]
+        );
+        assert_eq!(ready_or_throttled(&[], [7].into_iter()), vec![7]);
+    }
+}
@@ -49,6 +49,7 @@ use crate::net::db::*;
 use crate::net::httpcore::*;
 use crate::net::p2p::*;
 use crate::net::poll::*;
+use crate::net::reputation::ReputationEvent;
 use crate::net::rpc::*;
 use crate::net::stackerdb::{
     StackerDBConfig, StackerDBEventDispatcher, StackerDBSyncResult, StackerDBs,
@@ -288,6 +289,17 @@ impl RelayerStats {
         // add_relayed_message() and merge_relay_stats()
     }
 
+    /// Has anyone sent this message to us recently?
+    pub fn has_relayed_message<R: RelayPayload>(&self, msg: &R) -> bool {
+        let h = msg.get_digest();
+        let now = get_epoch_time_secs();
+        self.recent_messages.values().any(|relayed| {
+            relayed
+                .iter()
+                .any(|(ts, msg_hash)| ts + (MAX_RECENT_MESSAGE_AGE as u64) >= now && *msg_hash == h)
+        })
+    }
+
     /// See if anyone has sent this message to us already, and if so, return the set of neighbors
     /// that did so already (and how many times)
     pub fn count_relay_dups<R: RelayPayload>(&self, msg: &R) -> HashMap<NeighborKey, usize> {
@@ -1969,6 +1981,8 @@ impl Relayer {
                 );
             }
 
+            // neighbors that support transaction announcements are sent the txid, and only fetch
+            // the transaction if they don't have it (see net::txrelay)
             for (relayers, tx) in new_txs.into_iter() {
                 debug!("{:?}: Broadcast tx {}", &_local_peer, &tx.txid());
                 mempool_txs_added.push(tx.clone());
@@ -2125,7 +2139,7 @@ impl PeerNetwork {
         }
     }
 
-    /// Try to push a block to a peer.
+    /// Try to push a block to a peer, as a compact block if the peer supports them.
     /// Absorb and log errors.
     fn push_block_to_peer(
         &mut self,
@@ -2135,10 +2149,8 @@ impl PeerNetwork {
     ) -> () {
         let blk_hash = block.block_hash();
         let ch = consensus_hash.clone();
-        let payload = BlocksData {
-            blocks: vec![BlocksDatum(consensus_hash, block)],
-        };
-        let message = match self.sign_for_neighbor(recipient, StacksMessageType::Blocks(payload)) {
+        let payload = self.make_block_push_payload(recipient, consensus_hash, block);
+        let message = match self.sign_for_neighbor(recipient, payload) {
             Ok(m) => m,
             Err(e) => {
                 warn!(
@@ -2406,8 +2418,26 @@ impl PeerNetwork {
         Ok((num_inbound, num_outbound))
     }
 
+    /// Record a pushed message in the relayer stats, and credit or debit the pushing peer's
+    /// reputation depending on whether or not someone else pushed it to us first.
+    fn add_pushed_message<R: RelayPayload>(
+        &mut self,
+        nk: &NeighborKey,
+        msg: &R,
+        delivered: ReputationEvent,
+    ) -> () {
+        let event = if self.relayer_stats.has_relayed_message(msg) {
+            ReputationEvent::UselessRelay
+        } else {
+            delivered
+        };
+        self.add_reputation_event(nk, event);
+        self.relayer_stats.add_relayed_message(nk.clone(), msg);
+    }
+
     /// Update accounting information for relayed messages from a network result.
-    /// This influences selecting next-hop neighbors to get data from us.
+    /// This influences selecting next-hop neighbors to get data from us, as well as the
+    /// reputations of the neighbors that pushed the data.
     pub fn update_relayer_stats(&mut self, network_result: &NetworkResult) -> () {
         // synchronize
         for (_, convo) in self.peers.iter_mut() {
@@ -2418,7 +2448,7 @@ impl PeerNetwork {
         for (nk, blocks_data) in network_result.pushed_blocks.iter() {
             for block_msg in blocks_data.iter() {
                 for BlocksDatum(_, block) in block_msg.blocks.iter() {
-                    self.relayer_stats.add_relayed_message((*nk).clone(), block);
+                    self.add_pushed_message(nk, block, ReputationEvent::BlockDelivered);
                 }
             }
         }
@@ -2426,15 +2456,14 @@ impl PeerNetwork {
         for (nk, microblocks_data) in network_result.pushed_microblocks.iter() {
             for (_, microblock_msg) in microblocks_data.iter() {
                 for mblock in microblock_msg.microblocks.iter() {
-                    self.relayer_stats
-                        .add_relayed_message((*nk).clone(), mblock);
+                    self.add_pushed_message(nk, mblock, ReputationEvent::TransactionDelivered);
                 }
             }
         }
 
         for (nk, txs) in network_result.pushed_transactions.iter() {
             for (_, tx) in txs.iter() {
-                self.relayer_stats.add_relayed_message((*nk).clone(), tx);
+                self.add_pushed_message(nk, tx, ReputationEvent::TransactionDelivered);
             }
         }
     }
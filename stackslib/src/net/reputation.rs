@@ -0,0 +1,238 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Persistent peer reputation.
+//!
+//! Each peer has a reputation score in the `PeerDB`, keyed by the public key it authenticated
+//! with in its handshake, so a peer cannot spend another peer's reputation by advertising its
+//! address.  Events from a peer that has not handshaked yet are not recorded.  Conversations tally
+//! `ReputationEvent`s in their `NeighborStats` as they happen, and the `PeerNetwork` adds the
+//! tallies to the stored scores once per pass (and when a peer disconnects).  Scores decay
+//! exponentially towards 0, with a half-life of `REPUTATION_HALF_LIFE_SECS`, so old misbehavior
+//! is eventually forgiven and old good service eventually stops counting.
+//!
+//! Peers whose score is at or below `REPUTATION_BAD_THRESHOLD` are not chosen by the neighbor
+//! walk, and are disconnected when the frontier is pruned.  Otherwise, the pruner prefers to keep
+//! the better-reputed of two otherwise-comparable peers.
+
+use std::collections::HashMap;
+
+use stacks_common::util::get_epoch_time_secs;
+
+use crate::chainstate::stacks::StacksPublicKey;
+use crate::net::chat::ConversationP2P;
+use crate::net::db::PeerDB;
+use crate::net::p2p::PeerNetwork;
+use crate::net::{Error as net_error, NeighborKey};
+
+/// Time it takes for a reputation score to decay to half its value
+pub const REPUTATION_HALF_LIFE_SECS: u64 = 86_400;
+/// Highest possible reputation score
+pub const REPUTATION_MAX: f64 = 1000.0;
+/// Lowest possible reputation score
+pub const REPUTATION_MIN: f64 = -1000.0;
+/// Peers at or below this score are skipped by the neighbor walk and pruned from the frontier
+pub const REPUTATION_BAD_THRESHOLD: f64 = -100.0;
+/// Stored scores older than this have decayed below 1 point in magnitude, and are forgotten
+pub const REPUTATION_EXPIRE_SECS: u64 = 10 * REPUTATION_HALF_LIFE_SECS;
+
+/// Something a peer did that affects its reputation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReputationEvent {
+    /// The peer sent a message that failed validation
+    InvalidMessage,
+    /// The peer was banned (e.g. for sending an invalid block or transaction)
+    Banned,
+    /// The peer did not reply to a request in time
+    Timeout,
+    /// The peer pushed us data that another peer had already pushed
+    UselessRelay,
+    /// The peer was first to push us a block
+    BlockDelivered,
+    /// The peer was first to push us a transaction or microblock
+    TransactionDelivered,
+}
+
+impl ReputationEvent {
+    /// Points this event adds to (or removes from) a peer's reputation
+    pub fn points(&self) -> i64 {
+        match self {
+            ReputationEvent::InvalidMessage => -20,
+            ReputationEvent::Banned => -200,
+            ReputationEvent::Timeout => -5,
+            ReputationEvent::UselessRelay => -1,
+            ReputationEvent::BlockDelivered => 20,
+            ReputationEvent::TransactionDelivered => 2,
+        }
+    }
+}
+
+/// Decay a reputation score by `elapsed_secs` worth of half-lives
+pub fn decay_reputation(score: f64, elapsed_secs: u64) -> f64 {
+    score * 0.5f64.powf((elapsed_secs as f64) / (REPUTATION_HALF_LIFE_SECS as f64))
+}
+
+/// When a peer with `score` as of `now` will have decayed back above
+/// `REPUTATION_BAD_THRESHOLD`.  Returns 0 if the score is not bad to begin with.
+pub fn reputation_exclude_until(score: f64, now: u64) -> u64 {
+    if score > REPUTATION_BAD_THRESHOLD {
+        return 0;
+    }
+    // score * 0.5^(t / half-life) > threshold  <=>  t > half-life * log2(score / threshold)
+    let half_lives = (score / REPUTATION_BAD_THRESHOLD).log2();
+    now.saturating_add((half_lives * (REPUTATION_HALF_LIFE_SECS as f64)).ceil() as u64 + 1)
+}
+
+impl PeerNetwork {
+    /// The key under which a conversation's peer's reputation is stored: the public key it
+    /// authenticated with.  None if it has not handshaked yet.
+    pub fn reputation_key(convo: &ConversationP2P) -> Option<&StacksPublicKey> {
+        convo.ref_public_key()
+    }
+
+    /// Tally a reputation event for a connected neighbor.  It gets written to the peer DB on the
+    /// next call to `flush_peer_reputations()`.
+    pub fn add_reputation_event(&mut self, neighbor_key: &NeighborKey, event: ReputationEvent) {
+        let Some(event_id) = self.events.get(neighbor_key) else {
+            return;
+        };
+        if let Some(convo) = self.peers.get_mut(event_id) {
+            convo.stats.add_reputation_event(event);
+        }
+    }
+
+    /// Get a conversation's peer's current reputation, including events not yet flushed
+    pub fn get_convo_reputation(&self, convo: &ConversationP2P) -> Result<f64, net_error> {
+        let score = match PeerNetwork::reputation_key(convo) {
+            Some(public_key) => PeerDB::get_peer_reputation(
+                self.peerdb.conn(),
+                self.local_peer.network_id,
+                public_key,
+                get_epoch_time_secs(),
+            )?,
+            None => 0.0,
+        };
+        Ok((score + (convo.stats.reputation_delta as f64)).clamp(REPUTATION_MIN, REPUTATION_MAX))
+    }
+
+    /// Get the current reputation of each connected neighbor, keyed by its connection's neighbor
+    /// key (as in `self.events`)
+    pub fn get_neighbor_reputations(&self) -> Result<HashMap<NeighborKey, f64>, net_error> {
+        let mut reputations = HashMap::new();
+        for (nk, event_id) in self.events.iter() {
+            if let Some(convo) = self.peers.get(event_id) {
+                reputations.insert(nk.clone(), self.get_convo_reputation(convo)?);
+            }
+        }
+        Ok(reputations)
+    }
+
+    /// Write the reputation events tallied by the given conversations to the peer DB
+    fn flush_convo_reputations(&mut self, event_ids: &[usize]) -> Result<(), net_error> {
+        let now = get_epoch_time_secs();
+        let mut updates = vec![];
+        for event_id in event_ids.iter() {
+            if let Some(convo) = self.peers.get_mut(event_id) {
+                let delta = convo.stats.take_reputation_delta();
+                if delta == 0 {
+                    continue;
+                }
+                if let Some(public_key) = PeerNetwork::reputation_key(convo) {
+                    updates.push((public_key.clone(), delta));
+                }
+            }
+        }
+        if updates.is_empty() {
+            return Ok(());
+        }
+
+        let tx = self.peerdb.tx_begin()?;
+        for (public_key, delta) in updates.into_iter() {
+            let score = PeerDB::update_peer_reputation(
+                &tx,
+                self.local_peer.network_id,
+                &public_key,
+                delta,
+                now,
+            )?;
+            debug!(
+                "{:?}: reputation of {} changed by {} to {}",
+                &self.local_peer,
+                &public_key.to_hex(),
+                delta,
+                score
+            );
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Write all tallied reputation events to the peer DB
+    pub fn flush_peer_reputations(&mut self) -> Result<(), net_error> {
+        let event_ids: Vec<usize> = self.peers.keys().copied().collect();
+        self.flush_convo_reputations(&event_ids)
+    }
+
+    /// Write a single conversation's tallied reputation events to the peer DB, e.g. because it is
+    /// about to be disconnected
+    pub fn flush_peer_reputation(&mut self, event_id: usize) -> Result<(), net_error> {
+        self.flush_convo_reputations(&[event_id])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decay_reputation() {
+        assert_eq!(decay_reputation(100.0, 0), 100.0);
+        assert_eq!(decay_reputation(100.0, REPUTATION_HALF_LIFE_SECS), 50.0);
+        assert_eq!(
+            decay_reputation(-100.0, 2 * REPUTATION_HALF_LIFE_SECS),
+            -25.0
+        );
+        assert!(decay_reputation(REPUTATION_MIN, REPUTATION_EXPIRE_SECS).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_reputation_exclude_until() {
+        let now = 1_000_000;
+        assert_eq!(reputation_exclude_until(0.0, now), 0);
+        assert_eq!(
+            reputation_exclude_until(REPUTATION_BAD_THRESHOLD + 1.0, now),
+            0
+        );
+
+        // exactly at the threshold: excluded until the very next second
+        assert_eq!(
+            reputation_exclude_until(REPUTATION_BAD_THRESHOLD, now),
+            now + 1
+        );
+
+        // twice the threshold: excluded for one half-life
+        let until = reputation_exclude_until(2.0 * REPUTATION_BAD_THRESHOLD, now);
+        assert_eq!(until, now + REPUTATION_HALF_LIFE_SECS + 1);
+        assert!(
+            decay_reputation(2.0 * REPUTATION_BAD_THRESHOLD, until - now - 2)
+                <= REPUTATION_BAD_THRESHOLD
+        );
+        assert!(
+            decay_reputation(2.0 * REPUTATION_BAD_THRESHOLD, until - now)
+                > REPUTATION_BAD_THRESHOLD
+        );
+    }
+}
@@ -73,6 +73,7 @@ use crate::net::httpcore::{
     StacksHttp, StacksHttpMessage, StacksHttpRequest, StacksHttpResponse, HTTP_REQUEST_ID_RESERVED,
 };
 use crate::net::p2p::{PeerMap, PeerNetwork};
+use crate::net::ratelimit::{BandwidthClass, BandwidthLimiter};
 use crate::net::relay::Relayer;
 use crate::net::stackerdb::{StackerDBTx, StackerDBs};
 use crate::net::{Error as net_error, StacksMessageType, StacksNodeState};
@@ -178,6 +179,20 @@ impl ConversationHttp {
         }
     }
 
+    /// Limit how fast this conversation may receive and send data
+    pub fn set_bandwidth_limiters(
+        &mut self,
+        inbound: BandwidthLimiter,
+        outbound: BandwidthLimiter,
+    ) -> () {
+        self.connection.set_bandwidth_limiters(inbound, outbound);
+    }
+
+    /// Did the last recv() or send() stop early because we ran out of bandwidth?
+    pub fn is_throttled(&self) -> bool {
+        self.connection.is_throttled()
+    }
+
     /// How many ongoing requests do we have on this conversation?
     pub fn num_pending_outbound(&self) -> usize {
         self.reply_streams.len()
@@ -207,10 +222,15 @@ impl ConversationHttp {
             self.conn_id,
             &req
         );
+        // the response is as important as the request
+        let class = BandwidthClass::of_http_request(req.request_path());
+        self.connection.set_recv_class(class);
+
         let mut handle = self.connection.make_request_handle(
             HTTP_REQUEST_ID_RESERVED,
             get_epoch_time_secs() + self.timeout,
             self.conn_id,
+            class,
         )?;
         let stacks_msg = StacksHttpMessage::Request(req);
         self.connection.send_message(&mut handle, &stacks_msg)?;
@@ -265,7 +285,9 @@ impl ConversationHttp {
 
         // make the relay handle. There may not have been a valid request in the first place, so
         // we'll use a relay handle (not a reply handle) to push out the error.
-        let mut reply = self.connection.make_relay_handle(self.conn_id)?;
+        let mut reply = self
+            .connection
+            .make_relay_handle(self.conn_id, BandwidthClass::Normal)?;
 
         // queue up the HTTP headers, and then stream back the body.
         preamble.consensus_serialize(&mut reply)?;
@@ -284,10 +306,11 @@ impl ConversationHttp {
     ) -> Result<Option<StacksMessageType>, net_error> {
         // NOTE: This may set node.relay_message
         let keep_alive = req.preamble().keep_alive;
+        let class = BandwidthClass::of_http_request(req.request_path());
         let (mut response_preamble, response_body) =
             self.connection.protocol.try_handle_request(req, node)?;
 
-        let mut reply = self.connection.make_relay_handle(self.conn_id)?;
+        let mut reply = self.connection.make_relay_handle(self.conn_id, class)?;
         let relay_msg_opt = node.take_relay_message();
 
         // make sure content-length is properly set, based on how we're about to stream data back
@@ -651,7 +674,7 @@ impl ConversationHttp {
                 break;
             }
         }
-        monitoring::update_inbound_rpc_bandwidth(total_sz as i64);
+        monitoring::update_outbound_rpc_bandwidth(total_sz as i64);
         Ok(total_sz)
     }
 
@@ -0,0 +1,771 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! DNS seeding.
+//!
+//! A DNS seeder is a `PeerNetwork` that does nothing but walk the peer graph.  Every peer the
+//! walk handshakes with gets its last-contact time refreshed in the `PeerDB`, so the peers that
+//! were reachable recently, and that speak a compatible protocol version, can be read straight
+//! out of the frontier (see `PeerNetwork::get_seed_peers()`).  A `DNSSeeder` serves those peers'
+//! addresses as A and AAAA records from a minimal authoritative DNS responder, so new nodes can
+//! bootstrap by resolving a single name.
+//!
+//! The responder only answers queries for the apex of its zone.  DNS answers cannot carry port
+//! numbers, so a seeder is normally configured to only serve peers on the network's default
+//! port.  The `stacks-dns-seeder` binary runs a seeder next to a synced node's chain state.
+
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+
+use stacks_common::types::net::PeerAddress;
+use stacks_common::util::get_epoch_time_secs;
+
+use crate::net::connection::ConnectionOptions;
+use crate::net::db::PeerDB;
+use crate::net::p2p::PeerNetwork;
+use crate::net::Error as net_error;
+
+/// Default TTL of the records a seeder serves, in seconds
+pub const DNS_SEED_DEFAULT_TTL: u32 = 600;
+/// Default number of addresses in a response.  Responses are also capped at 512 bytes.
+pub const DNS_SEED_DEFAULT_MAX_ANSWERS: usize = 25;
+/// Default age, in seconds, past which a peer we last handshaked with is no longer served
+pub const DNS_SEED_DEFAULT_MAX_PEER_AGE: u64 = 3 * 3600;
+/// Most queries answered per call to `DNSSeeder::serve()`
+pub const DNS_SEED_MAX_QUERIES_PER_PASS: usize = 1024;
+
+/// Largest UDP response we send, since we don't speak EDNS
+const DNS_MAX_UDP_RESPONSE: usize = 512;
+const DNS_HEADER_LEN: usize = 12;
+const DNS_MAX_NAME_LEN: usize = 255;
+const DNS_MAX_LABEL_LEN: usize = 63;
+
+const DNS_FLAG_QR: u16 = 0x8000;
+const DNS_FLAG_AA: u16 = 0x0400;
+const DNS_FLAG_RD: u16 = 0x0100;
+const DNS_OPCODE_MASK: u16 = 0x7800;
+
+/// DNS response codes
+pub const DNS_RCODE_NOERROR: u8 = 0;
+pub const DNS_RCODE_FORMERR: u8 = 1;
+pub const DNS_RCODE_NXDOMAIN: u8 = 3;
+pub const DNS_RCODE_NOTIMP: u8 = 4;
+pub const DNS_RCODE_REFUSED: u8 = 5;
+
+/// DNS record types and classes
+pub const DNS_TYPE_A: u16 = 1;
+pub const DNS_TYPE_AAAA: u16 = 28;
+pub const DNS_TYPE_ANY: u16 = 255;
+pub const DNS_CLASS_IN: u16 = 1;
+pub const DNS_CLASS_ANY: u16 = 255;
+
+/// How a DNS seeder picks and serves peers
+#[derive(Debug, Clone, PartialEq)]
+pub struct DNSSeedConfig {
+    /// name of the zone this seeder is authoritative for, such as `seed.example.com`
+    pub zone: String,
+    /// TTL of the records served, in seconds
+    pub ttl: u32,
+    /// most addresses to put in one response
+    pub max_answers: usize,
+    /// only serve peers we have handshaked with in the last this-many seconds
+    pub max_peer_age: u64,
+    /// only serve peers listening on this port, since DNS answers can't carry ports.  If `None`,
+    /// peers on any port are served.
+    pub peer_port: Option<u16>,
+    /// whether or not to serve peers with private (or loopback) addresses
+    pub private_peers: bool,
+}
+
+impl DNSSeedConfig {
+    pub fn new(zone: &str) -> DNSSeedConfig {
+        DNSSeedConfig {
+            zone: normalize_dns_name(zone),
+            ttl: DNS_SEED_DEFAULT_TTL,
+            max_answers: DNS_SEED_DEFAULT_MAX_ANSWERS,
+            max_peer_age: DNS_SEED_DEFAULT_MAX_PEER_AGE,
+            peer_port: None,
+            private_peers: false,
+        }
+    }
+}
+
+/// Lower-case a DNS name and strip its trailing dot, for comparison
+fn normalize_dns_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Connection options for a `PeerNetwork` that only crawls the peer graph.  Starting from
+/// `opts`, this walks more often and restarts walks more readily, so the seeder sees as much of
+/// the network as it can, and turns off block and transaction traffic that a seeder doesn't
+/// need.
+pub fn seeder_connection_opts(mut opts: ConnectionOptions) -> ConnectionOptions {
+    opts.walk_interval = 5;
+    opts.walk_reset_prob = 0.25;
+    opts.disable_block_download = true;
+    opts.disable_block_advertisement = true;
+    opts.disable_block_push = true;
+    opts.disable_microblock_push = true;
+    opts.inv_sync_interval = 86_400;
+    opts.mempool_sync_interval = 86_400;
+    opts.antientropy_retry = 86_400;
+    opts
+}
+
+impl PeerNetwork {
+    /// Get the addresses of the peers a DNS seeder should serve, in random order: the peers we
+    /// have handshaked with recently that run a compatible protocol version, are not denied or
+    /// badly-reputed, and have an address a DNS record can carry.  Each address appears once.
+    pub fn get_seed_peers(&self, config: &DNSSeedConfig) -> Result<Vec<PeerAddress>, net_error> {
+        let epoch = self.get_current_epoch();
+        let neighbors = PeerDB::get_healthy_peers(
+            self.peerdb.conn(),
+            self.local_peer.network_id,
+            self.peer_version,
+            epoch.network_epoch,
+            get_epoch_time_secs().saturating_sub(config.max_peer_age),
+            self.chain_view.burn_block_height,
+        )?;
+
+        let mut seen = HashSet::new();
+        let mut addrs = vec![];
+        for neighbor in neighbors.into_iter() {
+            if let Some(port) = config.peer_port {
+                if neighbor.addr.port != port {
+                    continue;
+                }
+            }
+            let addr = neighbor.addr.addrbytes;
+            if addr.is_anynet() {
+                continue;
+            }
+            if !config.private_peers && addr.is_in_private_range() {
+                continue;
+            }
+            if seen.insert(addr.clone()) {
+                addrs.push(addr);
+            }
+        }
+        Ok(addrs)
+    }
+}
+
+/// The question of a DNS query
+#[derive(Debug, Clone, PartialEq)]
+pub struct DNSQuestion {
+    /// queried name, lower-cased and without a trailing dot
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+    /// the question as it appeared on the wire, which is echoed back verbatim (resolvers may
+    /// randomize the case of the name and check that it comes back unchanged)
+    wire: Vec<u8>,
+}
+
+/// Why a DNS query could not be answered normally
+#[derive(Debug, Clone, PartialEq)]
+pub enum DNSQueryError {
+    /// too short to even have a header, or not a query.  Nothing is sent back.
+    Ignored,
+    /// the header was readable, but the rest was not
+    Malformed,
+    /// an opcode other than QUERY
+    NotImplemented,
+}
+
+/// Decode the header flags and single question of a DNS query
+pub fn parse_dns_query(buf: &[u8]) -> Result<(u16, u16, DNSQuestion), DNSQueryError> {
+    if buf.len() < DNS_HEADER_LEN {
+        return Err(DNSQueryError::Ignored);
+    }
+    let flags = u16::from_be_bytes([buf[2], buf[3]]);
+    if flags & DNS_FLAG_QR != 0 {
+        return Err(DNSQueryError::Ignored);
+    }
+    if flags & DNS_OPCODE_MASK != 0 {
+        return Err(DNSQueryError::NotImplemented);
+    }
+    let qdcount = u16::from_be_bytes([buf[4], buf[5]]);
+    if qdcount != 1 {
+        return Err(DNSQueryError::Malformed);
+    }
+
+    let mut labels = vec![];
+    let mut ptr = DNS_HEADER_LEN;
+    loop {
+        let len = *buf.get(ptr).ok_or(DNSQueryError::Malformed)? as usize;
+        ptr += 1;
+        if len == 0 {
+            break;
+        }
+        // compression pointers (and the reserved label types) have no business in a question
+        if len > DNS_MAX_LABEL_LEN {
+            return Err(DNSQueryError::Malformed);
+        }
+        let label = buf.get(ptr..ptr + len).ok_or(DNSQueryError::Malformed)?;
+        labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+        ptr += len;
+        if ptr - DNS_HEADER_LEN > DNS_MAX_NAME_LEN {
+            return Err(DNSQueryError::Malformed);
+        }
+    }
+    let tail = buf.get(ptr..ptr + 4).ok_or(DNSQueryError::Malformed)?;
+    let question = DNSQuestion {
+        name: labels.join("."),
+        qtype: u16::from_be_bytes([tail[0], tail[1]]),
+        qclass: u16::from_be_bytes([tail[2], tail[3]]),
+        wire: buf[DNS_HEADER_LEN..ptr + 4].to_vec(),
+    };
+    let id = u16::from_be_bytes([buf[0], buf[1]]);
+    Ok((id, flags, question))
+}
+
+/// Encode a DNS response header
+fn dns_response_header(
+    id: u16,
+    query_flags: u16,
+    authoritative: bool,
+    rcode: u8,
+    qdcount: u16,
+    ancount: u16,
+) -> Vec<u8> {
+    let mut flags = DNS_FLAG_QR | (query_flags & (DNS_OPCODE_MASK | DNS_FLAG_RD)) | (rcode as u16);
+    if authoritative {
+        flags |= DNS_FLAG_AA;
+    }
+    let mut header = Vec::with_capacity(DNS_MAX_UDP_RESPONSE);
+    header.extend_from_slice(&id.to_be_bytes());
+    header.extend_from_slice(&flags.to_be_bytes());
+    header.extend_from_slice(&qdcount.to_be_bytes());
+    header.extend_from_slice(&ancount.to_be_bytes());
+    header.extend_from_slice(&[0, 0, 0, 0]);
+    header
+}
+
+/// Build the response to the DNS query in `query`, answering with (up to `config.max_answers`
+/// of) `peers`.  Returns None if nothing should be sent back.
+pub fn make_dns_response(
+    config: &DNSSeedConfig,
+    query: &[u8],
+    peers: &[PeerAddress],
+) -> Option<Vec<u8>> {
+    let (id, flags, question) = match parse_dns_query(query) {
+        Ok(parsed) => parsed,
+        Err(DNSQueryError::Ignored) => {
+            return None;
+        }
+        Err(DNSQueryError::Malformed) => {
+            let id = u16::from_be_bytes([query[0], query[1]]);
+            let flags = u16::from_be_bytes([query[2], query[3]]);
+            return Some(dns_response_header(
+                id,
+                flags,
+                false,
+                DNS_RCODE_FORMERR,
+                0,
+                0,
+            ));
+        }
+        Err(DNSQueryError::NotImplemented) => {
+            let id = u16::from_be_bytes([query[0], query[1]]);
+            let flags = u16::from_be_bytes([query[2], query[3]]);
+            return Some(dns_response_header(
+                id,
+                flags,
+                false,
+                DNS_RCODE_NOTIMP,
+                0,
+                0,
+            ));
+        }
+    };
+
+    let in_zone =
+        question.name == config.zone || question.name.ends_with(&format!(".{}", &config.zone));
+    let answer_class = question.qclass == DNS_CLASS_IN || question.qclass == DNS_CLASS_ANY;
+    if !in_zone || !answer_class {
+        let mut response = dns_response_header(id, flags, false, DNS_RCODE_REFUSED, 1, 0);
+        response.extend_from_slice(&question.wire);
+        return Some(response);
+    }
+    if question.name != config.zone {
+        let mut response = dns_response_header(id, flags, true, DNS_RCODE_NXDOMAIN, 1, 0);
+        response.extend_from_slice(&question.wire);
+        return Some(response);
+    }
+
+    let want_v4 = question.qtype == DNS_TYPE_A || question.qtype == DNS_TYPE_ANY;
+    let want_v6 = question.qtype == DNS_TYPE_AAAA || question.qtype == DNS_TYPE_ANY;
+
+    let mut answers = vec![];
+    let mut len = DNS_HEADER_LEN + question.wire.len();
+    for peer in peers.iter() {
+        if answers.len() >= config.max_answers {
+            break;
+        }
+        let (rtype, rdata) = match peer.ipv4_octets() {
+            Some(octets) if want_v4 => (DNS_TYPE_A, octets.to_vec()),
+            None if want_v6 => (DNS_TYPE_AAAA, peer.as_bytes().to_vec()),
+            _ => {
+                continue;
+            }
+        };
+        // name (a pointer to the question) + type + class + TTL + rdata length + rdata
+        let record_len = 2 + 2 + 2 + 4 + 2 + rdata.len();
+        if len + record_len > DNS_MAX_UDP_RESPONSE {
+            break;
+        }
+        len += record_len;
+
+        let mut record = Vec::with_capacity(record_len);
+        record.extend_from_slice(&[0xc0, DNS_HEADER_LEN as u8]);
+        record.extend_from_slice(&rtype.to_be_bytes());
+        record.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+        record.extend_from_slice(&config.ttl.to_be_bytes());
+        record.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        record.extend_from_slice(&rdata);
+        answers.push(record);
+    }
+
+    let mut response =
+        dns_response_header(id, flags, true, DNS_RCODE_NOERROR, 1, answers.len() as u16);
+    response.extend_from_slice(&question.wire);
+    for record in answers.into_iter() {
+        response.extend_from_slice(&record);
+    }
+    Some(response)
+}
+
+/// A minimal authoritative DNS server for a seeder's zone, which answers with the peers the
+/// seeder's `PeerNetwork` found to be healthy.
+pub struct DNSSeeder {
+    pub config: DNSSeedConfig,
+    socket: UdpSocket,
+    /// number of queries answered so far
+    pub num_queries: u64,
+}
+
+impl DNSSeeder {
+    /// Start serving DNS on `addr` (usually port 53)
+    pub fn bind(config: DNSSeedConfig, addr: &SocketAddr) -> Result<DNSSeeder, net_error> {
+        let socket = UdpSocket::bind(addr).map_err(|e| {
+            warn!("Failed to bind DNS seeder to {}: {:?}", addr, &e);
+            net_error::BindError
+        })?;
+        socket.set_nonblocking(true).map_err(|e| {
+            warn!("Failed to make DNS seeder socket non-blocking: {:?}", &e);
+            net_error::BindError
+        })?;
+        Ok(DNSSeeder {
+            config,
+            socket,
+            num_queries: 0,
+        })
+    }
+
+    /// The address this seeder serves DNS on
+    pub fn local_addr(&self) -> Result<SocketAddr, net_error> {
+        self.socket.local_addr().map_err(|e| {
+            warn!("Failed to get DNS seeder socket address: {:?}", &e);
+            net_error::BindError
+        })
+    }
+
+    /// Answer every query that is waiting to be read, with peers from `network`.  Does not
+    /// block.  Returns the number of queries answered.
+    pub fn serve(&mut self, network: &PeerNetwork) -> Result<usize, net_error> {
+        let mut queries = vec![];
+        let mut buf = [0u8; 1500];
+        while queries.len() < DNS_SEED_MAX_QUERIES_PER_PASS {
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, from)) => {
+                    queries.push((buf[0..len].to_vec(), from));
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    break;
+                }
+                Err(e) => {
+                    // e.g. an ICMP error from an earlier reply; not fatal
+                    debug!("DNS seeder: failed to receive a query: {:?}", &e);
+                    continue;
+                }
+            }
+        }
+        if queries.is_empty() {
+            return Ok(0);
+        }
+
+        let peers = network.get_seed_peers(&self.config)?;
+        let mut answered = 0;
+        for (query, from) in queries.into_iter() {
+            let Some(response) = make_dns_response(&self.config, &query, &peers) else {
+                continue;
+            };
+            if let Err(e) = self.socket.send_to(&response, &from) {
+                debug!("DNS seeder: failed to reply to {}: {:?}", &from, &e);
+                continue;
+            }
+            answered += 1;
+        }
+        self.num_queries += answered as u64;
+        Ok(answered)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::net::test::*;
+    use crate::util_lib::test::*;
+
+    /// Encode a DNS query for `name`
+    fn make_query(id: u16, flags: u16, name: &str, qtype: u16, qclass: u16) -> Vec<u8> {
+        let mut buf = vec![];
+        buf.extend_from_slice(&id.to_be_bytes());
+        buf.extend_from_slice(&flags.to_be_bytes());
+        buf.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+        for label in name.split('.') {
+            buf.push(label.len() as u8);
+            buf.extend_from_slice(label.as_bytes());
+        }
+        buf.push(0);
+        buf.extend_from_slice(&qtype.to_be_bytes());
+        buf.extend_from_slice(&qclass.to_be_bytes());
+        buf
+    }
+
+    /// Decode a response's ID, flags, rcode and answer addresses
+    fn parse_response(buf: &[u8]) -> (u16, u16, u8, Vec<PeerAddress>) {
+        let id = u16::from_be_bytes([buf[0], buf[1]]);
+        let flags = u16::from_be_bytes([buf[2], buf[3]]);
+        let qdcount = u16::from_be_bytes([buf[4], buf[5]]);
+        let ancount = u16::from_be_bytes([buf[6], buf[7]]);
+        let mut ptr = DNS_HEADER_LEN;
+        for _ in 0..qdcount {
+            while buf[ptr] != 0 {
+                ptr += 1 + buf[ptr] as usize;
+            }
+            ptr += 5;
+        }
+        let mut addrs = vec![];
+        for _ in 0..ancount {
+            assert_eq!(&buf[ptr..ptr + 2], &[0xc0, 0x0c]);
+            let rtype = u16::from_be_bytes([buf[ptr + 2], buf[ptr + 3]]);
+            let rdlen = u16::from_be_bytes([buf[ptr + 10], buf[ptr + 11]]) as usize;
+            let rdata = &buf[ptr + 12..ptr + 12 + rdlen];
+            match rtype {
+                DNS_TYPE_A => addrs.push(PeerAddress::from_ipv4(
+                    rdata[0], rdata[1], rdata[2], rdata[3],
+                )),
+                DNS_TYPE_AAAA => addrs.push(PeerAddress::from_slice(rdata).unwrap()),
+                _ => panic!("unexpected record type {}", rtype),
+            }
+            ptr += 12 + rdlen;
+        }
+        assert_eq!(ptr, buf.len());
+        (id, flags, (flags & 0x000f) as u8, addrs)
+    }
+
+    #[test]
+    fn test_parse_dns_query() {
+        let query = make_query(0x1234, DNS_FLAG_RD, "SeEd.Example.COM", DNS_TYPE_A, 1);
+        let (id, flags, question) = parse_dns_query(&query).unwrap();
+        assert_eq!(id, 0x1234);
+        assert_eq!(flags, DNS_FLAG_RD);
+        assert_eq!(question.name, "seed.example.com");
+        assert_eq!(question.qtype, DNS_TYPE_A);
+        assert_eq!(question.qclass, DNS_CLASS_IN);
+        assert_eq!(question.wire, query[DNS_HEADER_LEN..].to_vec());
+
+        // too short, or a response
+        assert_eq!(parse_dns_query(&query[0..11]), Err(DNSQueryError::Ignored));
+        let response = make_query(0x1234, DNS_FLAG_QR, "seed.example.com", DNS_TYPE_A, 1);
+        assert_eq!(parse_dns_query(&response), Err(DNSQueryError::Ignored));
+
+        // not a QUERY
+        let notify = make_query(0x1234, 4 << 11, "seed.example.com", DNS_TYPE_A, 1);
+        assert_eq!(parse_dns_query(&notify), Err(DNSQueryError::NotImplemented));
+
+        // truncated question
+        assert_eq!(
+            parse_dns_query(&query[0..query.len() - 1]),
+            Err(DNSQueryError::Malformed)
+        );
+
+        // two questions
+        let mut two = query.clone();
+        two[5] = 2;
+        assert_eq!(parse_dns_query(&two), Err(DNSQueryError::Malformed));
+
+        // compression pointer in the question
+        let mut compressed = query[0..DNS_HEADER_LEN].to_vec();
+        compressed.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1]);
+        assert_eq!(parse_dns_query(&compressed), Err(DNSQueryError::Malformed));
+
+        // overlong name
+        let long_name = vec!["a".repeat(63); 5].join(".");
+        let long = make_query(0x1234, 0, &long_name, DNS_TYPE_A, 1);
+        assert_eq!(parse_dns_query(&long), Err(DNSQueryError::Malformed));
+    }
+
+    #[test]
+    fn test_make_dns_response() {
+        let config = DNSSeedConfig::new("Seed.Example.com.");
+        assert_eq!(config.zone, "seed.example.com");
+
+        let v4_1 = PeerAddress::from_ipv4(1, 2, 3, 4);
+        let v4_2 = PeerAddress::from_ipv4(5, 6, 7, 8);
+        let v6 = PeerAddress([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        let peers = vec![v4_1.clone(), v6.clone(), v4_2.clone()];
+
+        // A records, with the question's case preserved and RD echoed
+        let query = make_query(7, DNS_FLAG_RD, "sEEd.example.com", DNS_TYPE_A, DNS_CLASS_IN);
+        let response = make_dns_response(&config, &query, &peers).unwrap();
+        let (id, flags, rcode, addrs) = parse_response(&response);
+        assert_eq!(id, 7);
+        assert_eq!(
+            flags & (DNS_FLAG_QR | DNS_FLAG_AA | DNS_FLAG_RD),
+            DNS_FLAG_QR | DNS_FLAG_AA | DNS_FLAG_RD
+        );
+        assert_eq!(rcode, DNS_RCODE_NOERROR);
+        assert_eq!(addrs, vec![v4_1.clone(), v4_2.clone()]);
+        assert_eq!(
+            &response[DNS_HEADER_LEN..query.len()],
+            &query[DNS_HEADER_LEN..]
+        );
+
+        // AAAA records
+        let query = make_query(8, 0, "seed.example.com", DNS_TYPE_AAAA, DNS_CLASS_IN);
+        let (_, _, rcode, addrs) =
+            parse_response(&make_dns_response(&config, &query, &peers).unwrap());
+        assert_eq!(rcode, DNS_RCODE_NOERROR);
+        assert_eq!(addrs, vec![v6.clone()]);
+
+        // both
+        let query = make_query(9, 0, "seed.example.com", DNS_TYPE_ANY, DNS_CLASS_ANY);
+        let (_, _, rcode, addrs) =
+            parse_response(&make_dns_response(&config, &query, &peers).unwrap());
+        assert_eq!(rcode, DNS_RCODE_NOERROR);
+        assert_eq!(addrs, peers);
+
+        // other types at the apex have no data
+        let query = make_query(10, 0, "seed.example.com", 16, DNS_CLASS_IN);
+        let (_, flags, rcode, addrs) =
+            parse_response(&make_dns_response(&config, &query, &peers).unwrap());
+        assert_ne!(flags & DNS_FLAG_AA, 0);
+        assert_eq!(rcode, DNS_RCODE_NOERROR);
+        assert!(addrs.is_empty());
+
+        // names below the apex don't exist
+        let query = make_query(11, 0, "www.seed.example.com", DNS_TYPE_A, DNS_CLASS_IN);
+        let (_, flags, rcode, addrs) =
+            parse_response(&make_dns_response(&config, &query, &peers).unwrap());
+        assert_ne!(flags & DNS_FLAG_AA, 0);
+        assert_eq!(rcode, DNS_RCODE_NXDOMAIN);
+        assert!(addrs.is_empty());
+
+        // names outside the zone, and other classes, are refused
+        for query in [
+            make_query(12, 0, "example.com", DNS_TYPE_A, DNS_CLASS_IN),
+            make_query(12, 0, "notseed.example.com", DNS_TYPE_A, DNS_CLASS_IN),
+            make_query(12, 0, "seed.example.com", DNS_TYPE_A, 3),
+        ] {
+            let (_, flags, rcode, addrs) =
+                parse_response(&make_dns_response(&config, &query, &peers).unwrap());
+            assert_eq!(flags & DNS_FLAG_AA, 0);
+            assert_eq!(rcode, DNS_RCODE_REFUSED);
+            assert!(addrs.is_empty());
+        }
+
+        // errors
+        let notify = make_query(13, 4 << 11, "seed.example.com", DNS_TYPE_A, 1);
+        let (id, _, rcode, _) =
+            parse_response(&make_dns_response(&config, &notify, &peers).unwrap());
+        assert_eq!(id, 13);
+        assert_eq!(rcode, DNS_RCODE_NOTIMP);
+
+        let query = make_query(14, 0, "seed.example.com", DNS_TYPE_A, 1);
+        let (id, _, rcode, _) = parse_response(
+            &make_dns_response(&config, &query[0..query.len() - 2], &peers).unwrap(),
+        );
+        assert_eq!(id, 14);
+        assert_eq!(rcode, DNS_RCODE_FORMERR);
+
+        assert!(make_dns_response(&config, &query[0..4], &peers).is_none());
+
+        // answers are capped by max_answers, and by the UDP message size
+        let many: Vec<_> = (0..100u8)
+            .map(|i| PeerAddress::from_ipv4(10, 0, 0, i))
+            .collect();
+        let mut small_config = config.clone();
+        small_config.max_answers = 3;
+        let query = make_query(15, 0, "seed.example.com", DNS_TYPE_A, DNS_CLASS_IN);
+        let (_, _, _, addrs) =
+            parse_response(&make_dns_response(&small_config, &query, &many).unwrap());
+        assert_eq!(addrs, many[0..3].to_vec());
+
+        let mut big_config = config.clone();
+        big_config.max_answers = 100;
+        let response = make_dns_response(&big_config, &query, &many).unwrap();
+        assert!(response.len() <= DNS_MAX_UDP_RESPONSE);
+        let (_, _, _, addrs) = parse_response(&response);
+        assert_eq!(addrs.len(), (DNS_MAX_UDP_RESPONSE - query.len()) / 16);
+    }
+
+    #[test]
+    fn test_dns_seeder_crawl() {
+        with_timeout(600, || {
+            let mut seeder_config = TestPeerConfig::from_port(33010);
+            seeder_config.connection_opts = seeder_connection_opts(seeder_config.connection_opts);
+            // don't wait between walks, as the other test peers don't
+            seeder_config.connection_opts.walk_interval = 0;
+            let mut peer_1_config = TestPeerConfig::from_port(33012);
+            let peer_2_config = TestPeerConfig::from_port(33014);
+
+            // the seeder only knows about peer 1, and has to find peer 2 through it
+            seeder_config.add_neighbor(&peer_1_config.to_neighbor());
+            peer_1_config.add_neighbor(&peer_2_config.to_neighbor());
+
+            let mut seeder = TestPeer::new(seeder_config);
+            let mut peer_1 = TestPeer::new(peer_1_config);
+            let mut peer_2 = TestPeer::new(peer_2_config);
+
+            let mut dns_config = DNSSeedConfig::new("seed.test");
+            dns_config.private_peers = true;
+
+            // the peer DB treats bootstrap peers as freshly contacted, so before the crawl, peer 1
+            // is the only healthy peer
+            let healthy = PeerDB::get_healthy_peers(
+                seeder.get_peerdb_conn(),
+                seeder.network.local_peer.network_id,
+                seeder.network.peer_version,
+                seeder.network.get_current_epoch().network_epoch,
+                get_epoch_time_secs() - 3600,
+                seeder.network.chain_view.burn_block_height,
+            )
+            .unwrap();
+            let healthy_ports: Vec<_> = healthy.iter().map(|n| n.addr.port).collect();
+            assert_eq!(healthy_ports, vec![33012]);
+
+            let neighbor_1 = peer_1.to_neighbor();
+            let neighbor_2 = peer_2.to_neighbor();
+            let local_addr = PeerAddress::from_ipv4(127, 0, 0, 1);
+
+            loop {
+                let _ = seeder.step();
+                let _ = peer_1.step();
+                let _ = peer_2.step();
+
+                let conn = seeder.get_peerdb_conn();
+                let seen = |n: &crate::net::Neighbor| {
+                    PeerDB::get_peer(conn, n.addr.network_id, &n.addr.addrbytes, n.addr.port)
+                        .unwrap()
+                        .map(|p| p.last_contact_time > 0)
+                        .unwrap_or(false)
+                };
+                if seen(&neighbor_1) && seen(&neighbor_2) {
+                    break;
+                }
+            }
+
+            // both peers are healthy, and both are served from the same address
+            let healthy = PeerDB::get_healthy_peers(
+                seeder.get_peerdb_conn(),
+                seeder.network.local_peer.network_id,
+                seeder.network.peer_version,
+                seeder.network.get_current_epoch().network_epoch,
+                get_epoch_time_secs() - 3600,
+                seeder.network.chain_view.burn_block_height,
+            )
+            .unwrap();
+            let mut healthy_ports: Vec<_> = healthy.iter().map(|n| n.addr.port).collect();
+            healthy_ports.sort();
+            assert_eq!(healthy_ports, vec![33012, 33014]);
+
+            assert_eq!(
+                seeder.network.get_seed_peers(&dns_config).unwrap(),
+                vec![local_addr.clone()]
+            );
+
+            // private addresses are not served by default
+            let mut public_config = dns_config.clone();
+            public_config.private_peers = false;
+            assert!(seeder
+                .network
+                .get_seed_peers(&public_config)
+                .unwrap()
+                .is_empty());
+
+            // peers on other ports are not served if a port is required
+            let mut port_config = dns_config.clone();
+            port_config.peer_port = Some(33016);
+            assert!(seeder
+                .network
+                .get_seed_peers(&port_config)
+                .unwrap()
+                .is_empty());
+
+            // peers that are too old are not served
+            let mut age_config = dns_config.clone();
+            age_config.max_peer_age = 0;
+            let tx = seeder.network.peerdb.tx_begin().unwrap();
+            for n in healthy.iter() {
+                let mut stale = n.clone();
+                stale.last_contact_time = get_epoch_time_secs() - 10;
+                PeerDB::update_peer(&tx, &stale).unwrap();
+            }
+            tx.commit().unwrap();
+            assert!(seeder
+                .network
+                .get_seed_peers(&age_config)
+                .unwrap()
+                .is_empty());
+            assert_eq!(
+                seeder.network.get_seed_peers(&dns_config).unwrap(),
+                vec![local_addr.clone()]
+            );
+
+            // serve them over DNS
+            let mut dns = DNSSeeder::bind(dns_config, &"127.0.0.1:0".parse().unwrap()).unwrap();
+            let dns_addr = dns.local_addr().unwrap();
+            let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+            client
+                .set_read_timeout(Some(Duration::from_secs(10)))
+                .unwrap();
+
+            let query = make_query(0xbeef, DNS_FLAG_RD, "seed.test", DNS_TYPE_A, DNS_CLASS_IN);
+            client.send_to(&query, &dns_addr).unwrap();
+            client.send_to(&query[0..4], &dns_addr).unwrap();
+            let mut served = 0;
+            while served == 0 {
+                served = dns.serve(&seeder.network).unwrap();
+            }
+            assert_eq!(served, 1);
+            assert_eq!(dns.num_queries, 1);
+
+            let mut buf = [0u8; 1500];
+            let (len, from) = client.recv_from(&mut buf).unwrap();
+            assert_eq!(from, dns_addr);
+            let (id, _, rcode, addrs) = parse_response(&buf[0..len]);
+            assert_eq!(id, 0xbeef);
+            assert_eq!(rcode, DNS_RCODE_NOERROR);
+            assert_eq!(addrs, vec![local_addr]);
+        })
+    }
+}
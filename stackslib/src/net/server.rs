@@ -18,7 +18,6 @@ use std::collections::{HashMap, VecDeque};
 use std::io::{Error as io_error, ErrorKind, Read, Write};
 use std::sync::mpsc::{sync_channel, Receiver, RecvError, SendError, SyncSender, TryRecvError};
 
-use mio::net as mio_net;
 use stacks_common::types::net::{PeerAddress, PeerHost};
 use stacks_common::util::get_epoch_time_secs;
 
@@ -33,6 +32,9 @@ use crate::net::http::*;
 use crate::net::httpcore::*;
 use crate::net::p2p::{PeerMap, PeerNetwork};
 use crate::net::poll::*;
+use crate::net::ratelimit::{
+    make_shared_bucket, ready_or_throttled, BandwidthLimiter, SharedTokenBucket,
+};
 use crate::net::rpc::*;
 use crate::net::{Error as net_error, *};
 
@@ -40,13 +42,13 @@ use crate::net::{Error as net_error, *};
 pub struct HttpPeer {
     /// ongoing http conversations (either they reached out to us, or we to them)
     pub peers: HashMap<usize, ConversationHttp>,
-    pub sockets: HashMap<usize, mio_net::TcpStream>,
+    pub sockets: HashMap<usize, NetworkSocket>,
 
     /// outbound connections that are pending connection
     pub connecting: HashMap<
         usize,
         (
-            mio_net::TcpStream,
+            NetworkSocket,
             Option<UrlString>,
             Option<StacksHttpRequest>,
             u64,
@@ -61,6 +63,10 @@ pub struct HttpPeer {
 
     /// connection options
     pub connection_opts: ConnectionOptions,
+
+    /// bandwidth budgets shared by all HTTP conversations, if there are global limits
+    inbound_bandwidth: Option<SharedTokenBucket>,
+    outbound_bandwidth: Option<SharedTokenBucket>,
 }
 
 impl HttpPeer {
@@ -77,6 +83,9 @@ impl HttpPeer {
             http_server_handle: server_handle,
             http_server_addr: server_addr,
 
+            inbound_bandwidth: make_shared_bucket(conn_opts.max_http_inbound_bandwidth),
+            outbound_bandwidth: make_shared_bucket(conn_opts.max_http_outbound_bandwidth),
+
             connection_opts: conn_opts,
         }
     }
@@ -107,10 +116,7 @@ impl HttpPeer {
     pub fn get_conversation_and_socket(
         &mut self,
         event_id: usize,
-    ) -> (
-        Option<&mut ConversationHttp>,
-        Option<&mut mio::net::TcpStream>,
-    ) {
+    ) -> (Option<&mut ConversationHttp>, Option<&mut NetworkSocket>) {
         (
             self.peers.get_mut(&event_id),
             self.sockets.get_mut(&event_id),
@@ -139,7 +145,7 @@ impl HttpPeer {
             return Err(net_error::AlreadyConnected(event_id, http_nk));
         }
 
-        let sock = NetworkState::connect(
+        let sock = network_state.connect_socket(
             &addr,
             network.connection_opts.socket_send_buffer_size,
             network.connection_opts.socket_recv_buffer_size,
@@ -213,7 +219,7 @@ impl HttpPeer {
         network_state: &mut NetworkState,
         node_state: &mut StacksNodeState,
         event_id: usize,
-        mut socket: mio_net::TcpStream,
+        mut socket: NetworkSocket,
         outbound_url: Option<UrlString>,
         initial_request: Option<StacksHttpRequest>,
     ) -> Result<(), net_error> {
@@ -252,6 +258,16 @@ impl HttpPeer {
             event_id,
             send_buffer_size,
         );
+        new_convo.set_bandwidth_limiters(
+            BandwidthLimiter::new(
+                self.connection_opts.max_http_peer_inbound_bandwidth,
+                self.inbound_bandwidth.clone(),
+            ),
+            BandwidthLimiter::new(
+                self.connection_opts.max_http_peer_outbound_bandwidth,
+                self.outbound_bandwidth.clone(),
+            ),
+        );
 
         debug!(
             "Registered HTTP {:?} as event {} (outbound={:?})",
@@ -339,7 +355,7 @@ impl HttpPeer {
     /// Saturate a conversation's socket -- either sends the whole request, or fills the socket
     /// buffer.
     pub fn saturate_http_socket(
-        client_sock: &mut mio::net::TcpStream,
+        client_sock: &mut NetworkSocket,
         convo: &mut ConversationHttp,
     ) -> Result<(), net_error> {
         // saturate the socket
@@ -416,7 +432,7 @@ impl HttpPeer {
     fn process_http_conversation(
         node_state: &mut StacksNodeState,
         event_id: usize,
-        client_sock: &mut mio_net::TcpStream,
+        client_sock: &mut NetworkSocket,
         convo: &mut ConversationHttp,
     ) -> Result<(bool, Vec<StacksMessageType>), net_error> {
         // get incoming bytes and update the state of this conversation.
@@ -551,7 +567,16 @@ impl HttpPeer {
     ) -> (Vec<StacksMessageType>, Vec<usize>) {
         let mut to_remove = vec![];
         let mut msgs = vec![];
-        for event_id in &poll_state.ready {
+
+        let ready = ready_or_throttled(
+            &poll_state.ready,
+            self.peers
+                .iter()
+                .filter(|(_, convo)| convo.is_throttled())
+                .map(|(event_id, _)| *event_id),
+        );
+
+        for event_id in &ready {
             if !self.sockets.contains_key(&event_id) {
                 test_debug!("Rogue socket event {}", event_id);
                 to_remove.push(*event_id);
@@ -0,0 +1,557 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Outbound connections through a SOCKS5 proxy (RFC 1928), such as Tor.
+//!
+//! When `ConnectionOptions::socks5_proxy` is set, the `PeerNetwork` dials neighbors and HTTP
+//! endpoints by connecting to the proxy and asking it to CONNECT to the remote address, so the
+//! remote end never learns this node's IP address.  Loopback addresses are never proxied.
+//!
+//! P2P and HTTP sockets are non-blocking, so a `Socks5Socket` runs the proxy handshake as part
+//! of the connection's first reads and writes, and reports `WouldBlock` until it is done.
+//! Blocking callers, like the Bitcoin indexer, use `connect_tcp()` instead.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use mio::net as mio_net;
+use stacks_common::types::net::PeerHost;
+
+const SOCKS5_VERSION: u8 = 0x05;
+const SOCKS5_AUTH_NONE: u8 = 0x00;
+const SOCKS5_CMD_CONNECT: u8 = 0x01;
+const SOCKS5_ATYP_IPV4: u8 = 0x01;
+const SOCKS5_ATYP_DOMAIN: u8 = 0x03;
+const SOCKS5_ATYP_IPV6: u8 = 0x04;
+const SOCKS5_REPLY_SUCCEEDED: u8 = 0x00;
+
+/// Should a connection to `target` go through the proxy?  Everything but loopback addresses
+/// does.
+pub fn is_proxied(target: &PeerHost) -> bool {
+    match target {
+        PeerHost::IP(addr, port) => !addr.to_socketaddr(*port).ip().is_loopback(),
+        PeerHost::DNS(name, _) => !name.eq_ignore_ascii_case("localhost"),
+    }
+}
+
+/// Human-readable meaning of a SOCKS5 reply code
+fn reply_reason(code: u8) -> &'static str {
+    match code {
+        0x01 => "general SOCKS server failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Socks5State {
+    SendGreeting,
+    RecvMethod,
+    SendConnect,
+    RecvReply,
+    Done,
+}
+
+/// The client side of a SOCKS5 handshake, which can be driven over a non-blocking socket
+#[derive(Debug)]
+pub struct Socks5Handshake {
+    state: Socks5State,
+    /// CONNECT request to send once the proxy accepts our (lack of) authentication
+    connect_request: Vec<u8>,
+    /// bytes being sent or received in the current state
+    buf: Vec<u8>,
+    /// number of bytes in `buf` sent so far
+    sent: usize,
+}
+
+impl Socks5Handshake {
+    /// Start a handshake that asks the proxy to connect to `target`
+    pub fn new(target: &PeerHost) -> io::Result<Socks5Handshake> {
+        Ok(Socks5Handshake {
+            state: Socks5State::SendGreeting,
+            connect_request: Socks5Handshake::make_connect_request(target)?,
+            buf: vec![SOCKS5_VERSION, 1, SOCKS5_AUTH_NONE],
+            sent: 0,
+        })
+    }
+
+    fn make_connect_request(target: &PeerHost) -> io::Result<Vec<u8>> {
+        let mut request = vec![SOCKS5_VERSION, SOCKS5_CMD_CONNECT, 0x00];
+        match target {
+            PeerHost::DNS(hostname, _) => {
+                if hostname.is_empty() || hostname.len() > 255 {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Invalid SOCKS5 destination hostname '{}'", hostname),
+                    ));
+                }
+                request.push(SOCKS5_ATYP_DOMAIN);
+                request.push(hostname.len() as u8);
+                request.extend_from_slice(hostname.as_bytes());
+            }
+            PeerHost::IP(addr, port) => match addr.to_socketaddr(*port).ip() {
+                IpAddr::V4(ip) => {
+                    request.push(SOCKS5_ATYP_IPV4);
+                    request.extend_from_slice(&ip.octets());
+                }
+                IpAddr::V6(ip) => {
+                    request.push(SOCKS5_ATYP_IPV6);
+                    request.extend_from_slice(&ip.octets());
+                }
+            },
+        }
+        request.extend_from_slice(&target.port().to_be_bytes());
+        Ok(request)
+    }
+
+    /// Has the proxy connected us to the target?
+    pub fn is_done(&self) -> bool {
+        self.state == Socks5State::Done
+    }
+
+    /// How many bytes the message we are receiving will be, given what we have of it so far
+    fn expected_len(&self) -> io::Result<usize> {
+        if self.state == Socks5State::RecvMethod {
+            return Ok(2);
+        }
+        // VER REP RSV ATYP BND.ADDR BND.PORT, where a domain BND.ADDR starts with its length
+        if self.buf.len() < 5 {
+            return Ok(5);
+        }
+        match self.buf[3] {
+            SOCKS5_ATYP_IPV4 => Ok(4 + 4 + 2),
+            SOCKS5_ATYP_DOMAIN => Ok(4 + 1 + (self.buf[4] as usize) + 2),
+            SOCKS5_ATYP_IPV6 => Ok(4 + 16 + 2),
+            atyp => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("SOCKS5 proxy replied with unknown address type {}", atyp),
+            )),
+        }
+    }
+
+    /// Check a fully-received message, and move on to the next state
+    fn finish_recv(&mut self) -> io::Result<()> {
+        if self.buf[0] != SOCKS5_VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Not a SOCKS5 proxy (version {})", self.buf[0]),
+            ));
+        }
+        if self.state == Socks5State::RecvMethod {
+            if self.buf[1] != SOCKS5_AUTH_NONE {
+                return Err(io::Error::new(
+                    ErrorKind::PermissionDenied,
+                    "SOCKS5 proxy requires authentication",
+                ));
+            }
+            self.state = Socks5State::SendConnect;
+            self.buf = self.connect_request.clone();
+        } else {
+            if self.buf[1] != SOCKS5_REPLY_SUCCEEDED {
+                return Err(io::Error::new(
+                    ErrorKind::ConnectionRefused,
+                    format!(
+                        "SOCKS5 proxy could not connect: {}",
+                        reply_reason(self.buf[1])
+                    ),
+                ));
+            }
+            self.state = Socks5State::Done;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+
+    /// Advance the handshake as far as possible without blocking.  Returns true once the proxy
+    /// has connected us to the target, and false if the socket would block.  Never reads past
+    /// the end of the proxy's reply, so any bytes after it belong to the target.
+    pub fn advance<S: Read + Write>(&mut self, sock: &mut S) -> io::Result<bool> {
+        loop {
+            match self.state {
+                Socks5State::SendGreeting | Socks5State::SendConnect => {
+                    while self.sent < self.buf.len() {
+                        match sock.write(&self.buf[self.sent..]) {
+                            Ok(0) => {
+                                return Err(io::Error::new(
+                                    ErrorKind::WriteZero,
+                                    "SOCKS5 proxy closed the connection",
+                                ));
+                            }
+                            Ok(count) => self.sent += count,
+                            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                            Err(e) => return Err(e),
+                        }
+                    }
+                    self.state = if self.state == Socks5State::SendGreeting {
+                        Socks5State::RecvMethod
+                    } else {
+                        Socks5State::RecvReply
+                    };
+                    self.buf.clear();
+                    self.sent = 0;
+                }
+                Socks5State::RecvMethod | Socks5State::RecvReply => {
+                    loop {
+                        let expected = self.expected_len()?;
+                        if self.buf.len() >= expected {
+                            break;
+                        }
+                        let mut chunk = vec![0u8; expected - self.buf.len()];
+                        match sock.read(&mut chunk) {
+                            Ok(0) => {
+                                return Err(io::Error::new(
+                                    ErrorKind::UnexpectedEof,
+                                    "SOCKS5 proxy closed the connection",
+                                ));
+                            }
+                            Ok(count) => self.buf.extend_from_slice(&chunk[0..count]),
+                            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                            Err(e) => return Err(e),
+                        }
+                    }
+                    self.finish_recv()?;
+                }
+                Socks5State::Done => {
+                    return Ok(true);
+                }
+            }
+        }
+    }
+}
+
+/// A non-blocking socket to a remote peer, through a SOCKS5 proxy.  Reads and writes first drive
+/// the proxy handshake, and fail with `WouldBlock` until it completes.
+#[derive(Debug)]
+pub struct Socks5Socket {
+    /// connection to the proxy
+    stream: mio_net::TcpStream,
+    /// the address the proxy is connecting us to
+    target: SocketAddr,
+    handshake: Socks5Handshake,
+}
+
+impl Socks5Socket {
+    /// Wrap a (possibly still connecting) socket to the proxy, which will be asked to connect to
+    /// `target`
+    pub fn new(stream: mio_net::TcpStream, target: SocketAddr) -> io::Result<Socks5Socket> {
+        let handshake = Socks5Handshake::new(&PeerHost::from_socketaddr(&target))?;
+        Ok(Socks5Socket {
+            stream,
+            target,
+            handshake,
+        })
+    }
+
+    /// The socket to the proxy, for registering with a poller
+    pub fn stream(&self) -> &mio_net::TcpStream {
+        &self.stream
+    }
+
+    /// The address of the remote peer (not the proxy).  Fails if the proxy connection has not
+    /// been established.
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr().map(|_| self.target)
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.stream.shutdown(how)
+    }
+
+    fn try_handshake(&mut self) -> io::Result<()> {
+        if self.handshake.advance(&mut self.stream)? {
+            Ok(())
+        } else {
+            Err(io::Error::from(ErrorKind::WouldBlock))
+        }
+    }
+}
+
+impl Read for Socks5Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.try_handshake()?;
+        self.stream.read(buf)
+    }
+}
+
+impl Write for Socks5Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.try_handshake()?;
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.handshake.is_done() {
+            return Ok(());
+        }
+        self.stream.flush()
+    }
+}
+
+/// Connect to `target` through the SOCKS5 proxy at `proxy`, blocking until the proxy has
+/// connected us or `timeout` passes.
+pub fn socks5_connect(
+    proxy: &SocketAddr,
+    target: &PeerHost,
+    timeout: Duration,
+) -> io::Result<TcpStream> {
+    let mut handshake = Socks5Handshake::new(target)?;
+    let mut sock = TcpStream::connect_timeout(proxy, timeout)?;
+    sock.set_read_timeout(Some(timeout))?;
+    sock.set_write_timeout(Some(timeout))?;
+
+    // a blocking socket only "would block" if it timed out
+    if !handshake.advance(&mut sock)? {
+        return Err(io::Error::new(
+            ErrorKind::TimedOut,
+            "Timed out waiting for SOCKS5 proxy",
+        ));
+    }
+
+    sock.set_read_timeout(None)?;
+    sock.set_write_timeout(None)?;
+    Ok(sock)
+}
+
+/// Open a blocking connection to `target`.  It goes through the SOCKS5 proxy at `proxy` if one
+/// is given and `target` is not a loopback address, in which case `timeout` bounds the proxy
+/// handshake.
+pub fn connect_tcp(
+    proxy: Option<&SocketAddr>,
+    target: &PeerHost,
+    timeout: Duration,
+) -> io::Result<TcpStream> {
+    match proxy {
+        Some(proxy) if is_proxied(target) => socks5_connect(proxy, target, timeout),
+        _ => {
+            let (host, port) = target.to_host_port();
+            TcpStream::connect((host.as_str(), port))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use stacks_common::types::net::PeerAddress;
+
+    use super::*;
+
+    /// A socket that hands out what it is given to read a byte at a time, and would block
+    /// between bytes.  Once the input runs out, it would block forever, or hits EOF if `eof` is
+    /// set.
+    struct TrickleSocket {
+        input: Vec<u8>,
+        output: Vec<u8>,
+        blocked: bool,
+        eof: bool,
+    }
+
+    impl TrickleSocket {
+        fn new(input: Vec<u8>, eof: bool) -> TrickleSocket {
+            TrickleSocket {
+                input,
+                output: vec![],
+                blocked: false,
+                eof,
+            }
+        }
+    }
+
+    impl Read for TrickleSocket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.blocked = !self.blocked;
+            if self.input.is_empty() && self.eof {
+                return Ok(0);
+            }
+            if self.blocked || self.input.is_empty() {
+                return Err(io::Error::from(ErrorKind::WouldBlock));
+            }
+            buf[0] = self.input.remove(0);
+            Ok(1)
+        }
+    }
+
+    impl Write for TrickleSocket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.blocked = !self.blocked;
+            if self.blocked {
+                return Err(io::Error::from(ErrorKind::WouldBlock));
+            }
+            self.output.push(buf[0]);
+            Ok(1)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Accept one connection, check the CONNECT request, send `reply_code` and then `payload`
+    fn spawn_proxy(
+        expected_request: Vec<u8>,
+        reply_code: u8,
+        payload: &'static [u8],
+    ) -> (SocketAddr, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            sock.read_exact(&mut greeting).unwrap();
+            assert_eq!(greeting, [5, 1, 0]);
+            sock.write_all(&[5, 0]).unwrap();
+
+            let mut request = vec![0u8; expected_request.len()];
+            sock.read_exact(&mut request).unwrap();
+            assert_eq!(request, expected_request);
+            sock.write_all(&[5, reply_code, 0, 1, 10, 0, 0, 1, 0x4f, 0x1c])
+                .unwrap();
+            sock.write_all(payload).unwrap();
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn test_connect_requests() {
+        let request = |host: PeerHost| Socks5Handshake::make_connect_request(&host).unwrap();
+        assert_eq!(
+            request(PeerHost::IP(PeerAddress::from_ipv4(1, 2, 3, 4), 20444)),
+            vec![5, 1, 0, 1, 1, 2, 3, 4, 0x4f, 0xdc]
+        );
+
+        let ipv6 = "[2001:db8::1]:8333".parse::<SocketAddr>().unwrap();
+        let mut expected = vec![5, 1, 0, 4, 0x20, 0x01, 0x0d, 0xb8];
+        expected.extend_from_slice(&[0; 11]);
+        expected.extend_from_slice(&[1, 0x20, 0x8d]);
+        assert_eq!(request(PeerHost::from_socketaddr(&ipv6)), expected);
+
+        let mut expected = vec![5, 1, 0, 3, 15];
+        expected.extend_from_slice(b"bitcoin.example");
+        expected.extend_from_slice(&[0x20, 0x8c]);
+        assert_eq!(
+            request(PeerHost::DNS("bitcoin.example".to_string(), 8332)),
+            expected
+        );
+
+        assert!(
+            Socks5Handshake::make_connect_request(&PeerHost::DNS("a".repeat(256), 80)).is_err()
+        );
+    }
+
+    #[test]
+    fn test_is_proxied() {
+        assert!(!is_proxied(&PeerHost::IP(
+            PeerAddress::from_ipv4(127, 0, 0, 1),
+            8332
+        )));
+        assert!(!is_proxied(&PeerHost::DNS("localhost".to_string(), 8332)));
+        assert!(is_proxied(&PeerHost::IP(
+            PeerAddress::from_ipv4(10, 0, 0, 1),
+            8332
+        )));
+        assert!(is_proxied(&PeerHost::DNS(
+            "bitcoin.example".to_string(),
+            8332
+        )));
+    }
+
+    #[test]
+    fn test_nonblocking_handshake() {
+        let target = PeerHost::IP(PeerAddress::from_ipv4(1, 2, 3, 4), 20444);
+        let mut handshake = Socks5Handshake::new(&target).unwrap();
+
+        // method reply, then a CONNECT reply with a domain-name bound address, then 3 bytes from
+        // the target
+        let mut input = vec![5, 0, 5, 0, 0, 3, 4];
+        input.extend_from_slice(b"host");
+        input.extend_from_slice(&[0, 80]);
+        input.extend_from_slice(&[1, 2, 3]);
+        let mut sock = TrickleSocket::new(input, false);
+
+        let mut calls = 0;
+        while !handshake.advance(&mut sock).unwrap() {
+            calls += 1;
+            assert!(calls < 1000);
+        }
+        assert!(handshake.is_done());
+        assert_eq!(
+            sock.output,
+            vec![5, 1, 0, 5, 1, 0, 1, 1, 2, 3, 4, 0x4f, 0xdc]
+        );
+        // the target's bytes were left unread
+        assert_eq!(sock.input, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_handshake_errors() {
+        let handshake_error = |input: Vec<u8>| {
+            let target = PeerHost::IP(PeerAddress::from_ipv4(1, 2, 3, 4), 20444);
+            let mut handshake = Socks5Handshake::new(&target).unwrap();
+            let mut sock = TrickleSocket::new(input, true);
+            loop {
+                match handshake.advance(&mut sock) {
+                    Ok(done) => assert!(!done),
+                    Err(e) => return e.kind(),
+                }
+            }
+        };
+
+        // needs authentication
+        assert_eq!(handshake_error(vec![5, 2]), ErrorKind::PermissionDenied);
+        // not SOCKS5
+        assert_eq!(handshake_error(vec![4, 0]), ErrorKind::InvalidData);
+        // can't connect
+        assert_eq!(
+            handshake_error(vec![5, 0, 5, 4, 0, 1, 0, 0, 0, 0, 0, 0]),
+            ErrorKind::ConnectionRefused
+        );
+        // bad address type
+        assert_eq!(
+            handshake_error(vec![5, 0, 5, 0, 0, 9, 0]),
+            ErrorKind::InvalidData
+        );
+        // proxy hangs up
+        assert_eq!(handshake_error(vec![5, 0, 5, 0]), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_socks5_connect() {
+        let target = PeerHost::DNS("peer.example".to_string(), 20444);
+        let expected_request = Socks5Handshake::make_connect_request(&target).unwrap();
+
+        let (proxy, handle) = spawn_proxy(expected_request.clone(), 0, b"hello");
+        let mut sock = connect_tcp(Some(&proxy), &target, Duration::from_secs(5)).unwrap();
+        let mut payload = [0u8; 5];
+        sock.read_exact(&mut payload).unwrap();
+        assert_eq!(&payload, b"hello");
+        handle.join().unwrap();
+
+        // proxy refuses
+        let (proxy, handle) = spawn_proxy(expected_request, 5, b"");
+        let err = connect_tcp(Some(&proxy), &target, Duration::from_secs(5)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+        handle.join().unwrap();
+    }
+}
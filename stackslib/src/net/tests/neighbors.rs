@@ -28,6 +28,7 @@ use crate::net::asn::*;
 use crate::net::chat::*;
 use crate::net::db::*;
 use crate::net::neighbors::*;
+use crate::net::simnet::*;
 use crate::net::test::*;
 use crate::net::{Error as net_error, *};
 use crate::util_lib::test::*;
@@ -1996,6 +1997,98 @@ fn test_walk_ring_pingback(
     test_walk_ring_ex(peer_configs, neighbor_count, true)
 }
 
+#[test]
+fn test_walk_ring_20_simulated() {
+    with_timeout(600, || {
+        // 20 peers in a ring on a simulated network with latency, jitter and some loss.  The
+        // network starts out split in two halves, which are joined after a minute of virtual
+        // time.
+        let sim = SimNetwork::new(20);
+        sim.set_default_link(LinkConfig {
+            latency_ms: 40,
+            jitter_ms: 20,
+            loss: 0.01,
+            retransmit_ms: 200,
+        });
+
+        let mut peer_configs = vec![];
+        let PEER_COUNT: usize = 20;
+        let NEIGHBOR_COUNT: usize = 3;
+
+        for i in 0..PEER_COUNT {
+            let mut conf = setup_peer_config(i, 34000, NEIGHBOR_COUNT, PEER_COUNT);
+
+            conf.allowed = 0;
+            conf.denied = 0;
+            conf.simnet = Some(sim.clone());
+
+            peer_configs.push(conf);
+        }
+
+        for i in 0..PEER_COUNT {
+            let n = (i + 1) % PEER_COUNT;
+            let neighbor = peer_configs[n].to_neighbor();
+            peer_configs[i].add_neighbor(&neighbor);
+            let neighbor = peer_configs[i].to_neighbor();
+            peer_configs[n].add_neighbor(&neighbor);
+        }
+
+        let mut peers = vec![];
+        for conf in peer_configs.into_iter() {
+            peers.push(TestPeer::new(conf));
+        }
+
+        let hosts: Vec<_> = peers
+            .iter()
+            .map(|peer| {
+                let nk = peer.config.to_neighbor().addr;
+                sim.host_of(&nk.addrbytes.to_socketaddr(nk.port)).unwrap()
+            })
+            .collect();
+        for i in 0..PEER_COUNT / 2 {
+            for j in PEER_COUNT / 2..PEER_COUNT {
+                sim.partition(hosts[i], hosts[j]);
+            }
+        }
+
+        let heal_time_ms = sim.now_ms() + 60_000;
+        let mut healed = false;
+        run_topology_test_ex(
+            &mut peers,
+            NEIGHBOR_COUNT,
+            TEST_IN_OUT_DEGREES,
+            |peers: &Vec<TestPeer>| {
+                if !healed {
+                    if sim.now_ms() < heal_time_ms {
+                        return false;
+                    }
+                    for i in 0..PEER_COUNT / 2 {
+                        for j in PEER_COUNT / 2..PEER_COUNT {
+                            sim.heal(hosts[i], hosts[j]);
+                        }
+                    }
+                    healed = true;
+                }
+                peers.iter().all(|peer| {
+                    let all_neighbors = PeerDB::get_all_peers(peer.network.peerdb.conn()).unwrap();
+                    all_neighbors.len() >= PEER_COUNT - 1
+                })
+            },
+            true,
+        );
+
+        // traffic really did cross the simulated links
+        let events = sim.events();
+        assert!(events
+            .iter()
+            .any(|event| matches!(event.kind, SimEventKind::Heal { .. })));
+        assert!(events
+            .iter()
+            .any(|event| matches!(event.kind, SimEventKind::Retransmit { .. })));
+        assert!(sim.now_ms() >= heal_time_ms);
+    })
+}
+
 #[test]
 #[ignore]
 fn test_walk_line_allowed_15() {
@@ -0,0 +1,610 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020-2024 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Transaction announcements.
+//!
+//! When both peers advertise `ServiceFlags::TX_ANNOUNCE`, a transaction we relay is not pushed to
+//! the peer in full.  Instead, its txid is queued for the peer, and the queue is flushed at most
+//! once a second as a `TxInv` message carrying an 8-byte `TxTag` per transaction.  At most
+//! `ConnectionOptions::max_tx_announcements_per_sec` transactions go out per flush; the rest wait
+//! for the next one.
+//!
+//! Tags are seeded with the sender's stable burnchain block hash.  Nodes on the same chain view
+//! tag a transaction the same way, so a node can tell when two peers announce the same one and
+//! only ask one of them for it.  To keep peers from making us hash our mempool under arbitrary
+//! seeds, we only accept seeds that are one of our recent burnchain block hashes.
+//!
+//! The recipient matches the tags against the recent transactions in its mempool (the same set
+//! that the mempool's bloom counter tracks), and asks for the ones it doesn't have and hasn't
+//! already asked someone else for with `GetTxs`.  The announcer answers with an ordinary
+//! `Transaction` message for each, so they are stored and relayed onward like any other pushed
+//! transaction.  Anything lost along the way is picked up by mempool sync.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use stacks_common::util::{get_epoch_time_ms, get_epoch_time_secs};
+
+use crate::burnchains::Txid;
+use crate::core::mempool::{MemPoolDB, TxTag};
+use crate::net::p2p::PeerNetwork;
+use crate::net::{
+    Error as net_error, GetTxsData, RelayData, StacksMessage, StacksMessageType, TxInvData,
+    TX_INV_MAX_TAGS,
+};
+
+/// How often we send a `TxInv` to a peer, at most
+pub const TX_ANNOUNCE_INTERVAL_MS: u128 = 1000;
+
+/// How many transactions can wait to be announced to a single peer.  Past this, we push them in
+/// full.
+pub const MAX_TX_ANNOUNCE_QUEUE: usize = 4096;
+
+/// How long we'll answer a peer's `GetTxs` for a transaction we announced to it
+pub const TX_ANNOUNCE_TIMEOUT: u64 = 60;
+
+/// How long we'll wait for a transaction we asked for, before letting another peer's
+/// announcement of it through
+pub const TX_REQUEST_TIMEOUT: u64 = 30;
+
+/// How many transactions we'll be waiting on from a single peer at once
+pub const MAX_TX_REQUESTS_PER_PEER: usize = TX_INV_MAX_TAGS as usize;
+
+/// How many announced transactions we'll remember per peer, so we don't announce them back
+pub const MAX_KNOWN_TX_TAGS: usize = 8192;
+
+/// A transaction we announced to a peer, which the peer may ask us for
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnnouncedTx {
+    pub txid: Txid,
+    /// the transaction's relayers, to send along with it
+    pub relay_hints: Vec<RelayData>,
+    pub announce_time: u64,
+}
+
+/// A transaction we asked a peer for
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestedTx {
+    /// event ID of the peer we asked
+    pub event_id: usize,
+    /// when we sent the `GetTxs`
+    pub request_time: u64,
+}
+
+/// Transaction announcement state for one peer
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TxAnnounceState {
+    /// transactions waiting to be announced, oldest first, with their relayers
+    queue: VecDeque<(Txid, Vec<RelayData>)>,
+    /// when we last sent this peer a `TxInv`, in milliseconds
+    last_announce_ms: u128,
+    /// transactions we announced, by the seed and tag we announced them with
+    announced: HashMap<([u8; 32], TxTag), AnnouncedTx>,
+    /// seeds and tags of transactions this peer announced to us
+    known: HashSet<([u8; 32], TxTag)>,
+}
+
+impl TxAnnounceState {
+    pub fn new() -> TxAnnounceState {
+        TxAnnounceState::default()
+    }
+
+    /// Queue a transaction to be announced.  Returns false if the queue is full, in which case
+    /// the caller should push the transaction instead.
+    pub fn queue_tx(&mut self, txid: Txid, relay_hints: Vec<RelayData>) -> bool {
+        if self.queue.len() >= MAX_TX_ANNOUNCE_QUEUE {
+            return false;
+        }
+        if self
+            .queue
+            .iter()
+            .any(|(queued_txid, _)| *queued_txid == txid)
+        {
+            return true;
+        }
+        self.queue.push_back((txid, relay_hints));
+        true
+    }
+
+    /// How many transactions are waiting to be announced?
+    pub fn num_queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Remember that the peer announced these transactions to us.  We forget them all once there
+    /// are too many to remember.
+    pub fn add_known(&mut self, seed: &[u8; 32], tx_tags: &[TxTag]) {
+        if self.known.len() + tx_tags.len() > MAX_KNOWN_TX_TAGS {
+            self.known.clear();
+        }
+        for tx_tag in tx_tags.iter() {
+            self.known.insert((seed.clone(), tx_tag.clone()));
+        }
+    }
+
+    /// Did the peer announce this transaction to us?
+    pub fn knows_tx(&self, seed: &[u8; 32], txid: &Txid) -> bool {
+        self.known
+            .contains(&(seed.clone(), TxTag::from(seed, txid)))
+    }
+
+    /// Make the next `TxInv` for this peer out of the queue, and remember what was in it.  Returns
+    /// None if it's too soon to announce again, or if there's nothing the peer doesn't know about.
+    pub fn make_txinv(
+        &mut self,
+        seed: &[u8; 32],
+        max_txs: usize,
+        now_ms: u128,
+    ) -> Option<TxInvData> {
+        if self.queue.len() == 0 || self.last_announce_ms + TX_ANNOUNCE_INTERVAL_MS > now_ms {
+            return None;
+        }
+
+        let announce_time = (now_ms / 1000) as u64;
+        let mut tx_tags = vec![];
+        while tx_tags.len() < max_txs {
+            let Some((txid, relay_hints)) = self.queue.pop_front() else {
+                break;
+            };
+            if self.knows_tx(seed, &txid) {
+                continue;
+            }
+            let tx_tag = TxTag::from(seed, &txid);
+            self.announced.insert(
+                (seed.clone(), tx_tag.clone()),
+                AnnouncedTx {
+                    txid,
+                    relay_hints,
+                    announce_time,
+                },
+            );
+            tx_tags.push(tx_tag);
+        }
+
+        if tx_tags.len() == 0 {
+            return None;
+        }
+
+        self.last_announce_ms = now_ms;
+        Some(TxInvData {
+            seed: seed.clone(),
+            tx_tags,
+        })
+    }
+
+    /// Look up a transaction we announced to this peer
+    pub fn get_announced(&self, seed: &[u8; 32], tx_tag: &TxTag) -> Option<&AnnouncedTx> {
+        self.announced.get(&(seed.clone(), tx_tag.clone()))
+    }
+
+    /// Forget announcements the peer can no longer ask us about
+    pub fn expire(&mut self, now: u64) {
+        self.announced
+            .retain(|_, announced| announced.announce_time + TX_ANNOUNCE_TIMEOUT >= now);
+    }
+}
+
+/// Tag each of the given transactions with the given seed
+pub fn make_tx_tag_set(seed: &[u8; 32], txids: &[Txid]) -> HashSet<TxTag> {
+    txids.iter().map(|txid| TxTag::from(seed, txid)).collect()
+}
+
+impl PeerNetwork {
+    /// The seed we tag the transactions we announce with.  This is the stable burnchain block
+    /// hash, so that nodes on the same chain view tag each transaction the same way.
+    pub fn tx_announce_seed(&self) -> [u8; 32] {
+        self.chain_view.burn_stable_block_hash.0.clone()
+    }
+
+    /// Handle the `TxInv` and `GetTxs` messages in a batch of unsolicited messages, and send out
+    /// any transaction announcements that are due.  Both messages are consumed.  We ignore
+    /// announcements during initial block download, since we don't store transactions then.
+    pub fn process_tx_announcements(
+        &mut self,
+        mempool: &MemPoolDB,
+        ibd: bool,
+        unsolicited: &mut HashMap<usize, Vec<StacksMessage>>,
+    ) {
+        self.expire_tx_announcements();
+
+        // the recent transactions in our mempool, tagged with each seed we've seen in this batch
+        let mut recent_txids: Option<Vec<Txid>> = None;
+        let mut tag_sets: HashMap<[u8; 32], HashSet<TxTag>> = HashMap::new();
+
+        for (event_id, messages) in unsolicited.iter_mut() {
+            let mut remaining = Vec::with_capacity(messages.len());
+            for message in messages.drain(..) {
+                match message.payload {
+                    StacksMessageType::TxInv(ref txinv) => {
+                        if ibd {
+                            continue;
+                        }
+                        if let Err(e) = self.handle_txinv(
+                            *event_id,
+                            mempool,
+                            &mut recent_txids,
+                            &mut tag_sets,
+                            txinv,
+                        ) {
+                            debug!(
+                                "{:?}: Failed to handle transaction announcements from event {}: {:?}",
+                                &self.local_peer, event_id, &e
+                            );
+                        }
+                    }
+                    StacksMessageType::GetTxs(ref gettxs) => {
+                        self.handle_gettxs(*event_id, mempool, gettxs);
+                    }
+                    StacksMessageType::Transaction(ref tx) => {
+                        self.requested_txs_arrived(&tx.txid());
+                        remaining.push(message);
+                    }
+                    _ => {
+                        remaining.push(message);
+                    }
+                }
+            }
+            *messages = remaining;
+        }
+
+        self.announce_queued_txs();
+    }
+
+    /// Ask a peer for the transactions it announced that we don't have, and that we haven't
+    /// already asked someone else for.
+    fn handle_txinv(
+        &mut self,
+        event_id: usize,
+        mempool: &MemPoolDB,
+        recent_txids: &mut Option<Vec<Txid>>,
+        tag_sets: &mut HashMap<[u8; 32], HashSet<TxTag>>,
+        txinv: &TxInvData,
+    ) -> Result<(), net_error> {
+        if !self
+            .chain_view
+            .last_burn_block_hashes
+            .values()
+            .any(|burn_block_hash| burn_block_hash.0 == txinv.seed)
+        {
+            debug!(
+                "{:?}: Event {} announced transactions with an unrecognized seed",
+                &self.local_peer, event_id
+            );
+            return Ok(());
+        }
+
+        self.tx_announcements
+            .entry(event_id)
+            .or_insert_with(TxAnnounceState::new)
+            .add_known(&txinv.seed, &txinv.tx_tags);
+
+        if recent_txids.is_none() {
+            *recent_txids = Some(mempool.get_bloom_txids()?);
+        }
+        let have_tags = tag_sets.entry(txinv.seed.clone()).or_insert_with(|| {
+            make_tx_tag_set(&txinv.seed, recent_txids.as_ref().unwrap_or(&vec![]))
+        });
+
+        let num_requested = self
+            .requested_txs
+            .values()
+            .filter(|requested| requested.event_id == event_id)
+            .count();
+
+        let mut wanted = vec![];
+        let mut wanted_set = HashSet::new();
+        for tx_tag in txinv.tx_tags.iter() {
+            if num_requested + wanted.len() >= MAX_TX_REQUESTS_PER_PEER {
+                debug!(
+                    "{:?}: Already waiting on {} transactions from event {}",
+                    &self.local_peer, num_requested, event_id
+                );
+                break;
+            }
+            if have_tags.contains(tx_tag)
+                || wanted_set.contains(tx_tag)
+                || self
+                    .requested_txs
+                    .contains_key(&(txinv.seed.clone(), tx_tag.clone()))
+            {
+                continue;
+            }
+            wanted_set.insert(tx_tag.clone());
+            wanted.push(tx_tag.clone());
+        }
+
+        debug!(
+            "{:?}: Event {} announced {} transactions; asking for {}",
+            &self.local_peer,
+            event_id,
+            txinv.tx_tags.len(),
+            wanted.len()
+        );
+        if wanted.len() == 0 {
+            return Ok(());
+        }
+
+        let gettxs = GetTxsData {
+            seed: txinv.seed.clone(),
+            tx_tags: wanted,
+        };
+        self.send_tx_relay_message(event_id, StacksMessageType::GetTxs(gettxs.clone()))?;
+
+        let request_time = get_epoch_time_secs();
+        for tx_tag in gettxs.tx_tags.into_iter() {
+            self.requested_txs.insert(
+                (gettxs.seed.clone(), tx_tag),
+                RequestedTx {
+                    event_id,
+                    request_time,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Send a peer the transactions it asked for, as `Transaction` messages.  Tags we didn't
+    /// announce to it, or transactions that have since left our mempool, are skipped.
+    fn handle_gettxs(&mut self, event_id: usize, mempool: &MemPoolDB, gettxs: &GetTxsData) {
+        let Some(state) = self.tx_announcements.get(&event_id) else {
+            debug!(
+                "{:?}: Event {} asked for transactions we never announced to it",
+                &self.local_peer, event_id
+            );
+            return;
+        };
+
+        let mut to_send = vec![];
+        for tx_tag in gettxs.tx_tags.iter() {
+            if let Some(announced) = state.get_announced(&gettxs.seed, tx_tag) {
+                to_send.push((announced.txid.clone(), announced.relay_hints.clone()));
+            }
+        }
+
+        debug!(
+            "{:?}: Event {} asked for {} transactions; sending {}",
+            &self.local_peer,
+            event_id,
+            gettxs.tx_tags.len(),
+            to_send.len()
+        );
+
+        for (txid, relay_hints) in to_send.into_iter() {
+            let tx = match MemPoolDB::get_tx(mempool.conn(), &txid) {
+                Ok(Some(tx_info)) => tx_info.tx,
+                Ok(None) => {
+                    debug!(
+                        "{:?}: Transaction {} is no longer in the mempool",
+                        &self.local_peer, &txid
+                    );
+                    continue;
+                }
+                Err(e) => {
+                    warn!(
+                        "{:?}: Failed to load transaction {}: {:?}",
+                        &self.local_peer, &txid, &e
+                    );
+                    continue;
+                }
+            };
+
+            let Some(convo) = self.peers.get_mut(&event_id) else {
+                return;
+            };
+            match convo.sign_and_forward(
+                &self.local_peer,
+                &self.chain_view,
+                relay_hints,
+                StacksMessageType::Transaction(tx),
+            ) {
+                Ok(rh) => {
+                    self.add_relay_handle(event_id, rh);
+                }
+                Err(e) => {
+                    debug!(
+                        "{:?}: Failed to send transaction {} to event {}: {:?}",
+                        &self.local_peer, &txid, event_id, &e
+                    );
+                    return;
+                }
+            }
+        }
+    }
+
+    /// We got a transaction, so stop waiting for it
+    fn requested_txs_arrived(&mut self, txid: &Txid) {
+        if self.requested_txs.len() == 0 {
+            return;
+        }
+        let seeds: HashSet<[u8; 32]> = self
+            .requested_txs
+            .keys()
+            .map(|(seed, _)| seed.clone())
+            .collect();
+        for seed in seeds.into_iter() {
+            let tx_tag = TxTag::from(&seed, txid);
+            self.requested_txs.remove(&(seed, tx_tag));
+        }
+    }
+
+    /// Send a `TxInv` to each peer whose announcements are due
+    fn announce_queued_txs(&mut self) {
+        let seed = self.tx_announce_seed();
+        let max_txs = self
+            .connection_opts
+            .max_tx_announcements_per_sec
+            .min(TX_INV_MAX_TAGS as u64) as usize;
+        let now_ms = get_epoch_time_ms();
+
+        let mut txinvs = vec![];
+        for (event_id, state) in self.tx_announcements.iter_mut() {
+            if let Some(txinv) = state.make_txinv(&seed, max_txs, now_ms) {
+                txinvs.push((*event_id, txinv));
+            }
+        }
+
+        for (event_id, txinv) in txinvs.into_iter() {
+            let num_txs = txinv.tx_tags.len();
+            if let Err(e) = self.send_tx_relay_message(event_id, StacksMessageType::TxInv(txinv)) {
+                debug!(
+                    "{:?}: Failed to announce {} transactions to event {}: {:?}",
+                    &self.local_peer, num_txs, event_id, &e
+                );
+            }
+        }
+    }
+
+    /// Send a `TxInv` or `GetTxs` to a peer
+    fn send_tx_relay_message(
+        &mut self,
+        event_id: usize,
+        payload: StacksMessageType,
+    ) -> Result<(), net_error> {
+        let neighbor_key = self
+            .peers
+            .get(&event_id)
+            .map(|convo| convo.to_neighbor_key())
+            .ok_or(net_error::PeerNotConnected)?;
+        let message = self.sign_for_p2p(event_id, payload)?;
+        self.relay_signed_message(&neighbor_key, message)
+    }
+
+    /// Forget requests that were never answered, and announcements that can no longer be asked
+    /// about
+    fn expire_tx_announcements(&mut self) {
+        let now = get_epoch_time_secs();
+        let local_peer = &self.local_peer;
+        self.requested_txs.retain(|(_, tx_tag), requested| {
+            if requested.request_time + TX_REQUEST_TIMEOUT < now {
+                debug!(
+                    "{:?}: Timed out waiting for event {} to send transaction {}",
+                    local_peer, requested.event_id, tx_tag
+                );
+                return false;
+            }
+            true
+        });
+        for state in self.tx_announcements.values_mut() {
+            state.expire(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn make_txids(count: u8) -> Vec<Txid> {
+        (0..count).map(|i| Txid([i; 32])).collect()
+    }
+
+    #[test]
+    fn test_tx_announce_queue() {
+        let mut state = TxAnnounceState::new();
+        let seed = [0x11; 32];
+        let txids = make_txids(10);
+
+        for txid in txids.iter() {
+            assert!(state.queue_tx(txid.clone(), vec![]));
+        }
+        // no duplicates
+        assert!(state.queue_tx(txids[0].clone(), vec![]));
+        assert_eq!(state.num_queued(), 10);
+
+        // announcements are batched and rate-limited
+        let txinv = state.make_txinv(&seed, 4, 10_000).unwrap();
+        assert_eq!(txinv.seed, seed);
+        assert_eq!(
+            txinv.tx_tags,
+            txids[0..4]
+                .iter()
+                .map(|txid| TxTag::from(&seed, txid))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(state.num_queued(), 6);
+        assert!(state.make_txinv(&seed, 4, 10_500).is_none());
+
+        // we remember what we announced, so we can send it when asked
+        for txid in txids[0..4].iter() {
+            let announced = state
+                .get_announced(&seed, &TxTag::from(&seed, txid))
+                .unwrap();
+            assert_eq!(announced.txid, *txid);
+            assert_eq!(announced.announce_time, 10);
+        }
+        assert!(state
+            .get_announced(&seed, &TxTag::from(&seed, &txids[4]))
+            .is_none());
+        assert!(state
+            .get_announced(&[0x22; 32], &TxTag::from(&seed, &txids[0]))
+            .is_none());
+
+        // don't announce what the peer announced to us
+        let known_tags: Vec<_> = txids[4..6]
+            .iter()
+            .map(|txid| TxTag::from(&seed, txid))
+            .collect();
+        state.add_known(&seed, &known_tags);
+        assert!(state.knows_tx(&seed, &txids[4]));
+        assert!(!state.knows_tx(&[0x22; 32], &txids[4]));
+
+        let txinv = state.make_txinv(&seed, 100, 11_000).unwrap();
+        assert_eq!(
+            txinv.tx_tags,
+            txids[6..10]
+                .iter()
+                .map(|txid| TxTag::from(&seed, txid))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(state.num_queued(), 0);
+        assert!(state.make_txinv(&seed, 100, 20_000).is_none());
+
+        // announcements expire
+        state.expire(10 + TX_ANNOUNCE_TIMEOUT);
+        assert!(state
+            .get_announced(&seed, &TxTag::from(&seed, &txids[0]))
+            .is_some());
+        state.expire(11 + TX_ANNOUNCE_TIMEOUT);
+        assert!(state
+            .get_announced(&seed, &TxTag::from(&seed, &txids[0]))
+            .is_none());
+        assert!(state
+            .get_announced(&seed, &TxTag::from(&seed, &txids[6]))
+            .is_some());
+    }
+
+    #[test]
+    fn test_tx_announce_queue_full() {
+        let mut state = TxAnnounceState::new();
+        for i in 0..MAX_TX_ANNOUNCE_QUEUE {
+            let mut txid_bytes = [0u8; 32];
+            txid_bytes[0..8].copy_from_slice(&(i as u64).to_be_bytes());
+            assert!(state.queue_tx(Txid(txid_bytes), vec![]));
+        }
+        assert!(!state.queue_tx(Txid([0xff; 32]), vec![]));
+        assert_eq!(state.num_queued(), MAX_TX_ANNOUNCE_QUEUE);
+    }
+
+    #[test]
+    fn test_make_tx_tag_set() {
+        let txids = make_txids(5);
+        let tags = make_tx_tag_set(&[0x11; 32], &txids);
+        assert_eq!(tags.len(), 5);
+        for txid in txids.iter() {
+            assert!(tags.contains(&TxTag::from(&[0x11; 32], txid)));
+            assert!(!tags.contains(&TxTag::from(&[0x22; 32], txid)));
+        }
+    }
+}
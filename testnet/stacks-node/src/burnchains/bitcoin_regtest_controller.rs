@@ -2,7 +2,7 @@ use std::cmp;
 use std::io::Cursor;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use async_h1::client;
 use async_std::io::ReadExt;
@@ -14,6 +14,7 @@ use serde_json::value::RawValue;
 use stacks::burnchains::bitcoin::address::{
     BitcoinAddress, LegacyBitcoinAddress, LegacyBitcoinAddressType, SegwitBitcoinAddress,
 };
+use stacks::burnchains::bitcoin::esplora::{EsploraIndexer, EsploraIndexerConfig};
 use stacks::burnchains::bitcoin::indexer::{
     BitcoinIndexer, BitcoinIndexerConfig, BitcoinIndexerRuntime,
 };
@@ -28,15 +29,14 @@ use stacks::burnchains::{
 use stacks::chainstate::burn::db::sortdb::SortitionDB;
 use stacks::chainstate::burn::operations::{
     BlockstackOperationType, DelegateStxOp, LeaderBlockCommitOp, LeaderKeyRegisterOp, PreStxOp,
-    TransferStxOp, UserBurnSupportOp,
+    StackStxOp, TransferStxOp, UserBurnSupportOp,
 };
-#[cfg(test)]
 use stacks::chainstate::burn::Opcodes;
 use stacks::chainstate::coordinator::comm::CoordinatorChannels;
-#[cfg(test)]
 use stacks::chainstate::stacks::address::PoxAddress;
 use stacks::core::{StacksEpoch, StacksEpochId};
 use stacks::monitoring::{increment_btc_blocks_received_counter, increment_btc_ops_sent_counter};
+use stacks::net::socks5;
 use stacks_common::codec::StacksMessageCodec;
 use stacks_common::deps_common::bitcoin::blockdata::opcodes;
 use stacks_common::deps_common::bitcoin::blockdata::script::{Builder, Script};
@@ -49,13 +49,19 @@ use stacks_common::deps_common::bitcoin::network::serialize::deserialize as btc_
 use stacks_common::deps_common::bitcoin::network::serialize::RawEncoder;
 use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
 use stacks_common::types::chainstate::BurnchainHeaderHash;
+use stacks_common::types::net::PeerHost;
 use stacks_common::util::hash::{hex_bytes, Hash160};
 use stacks_common::util::secp256k1::Secp256k1PublicKey;
 use stacks_common::util::sleep_ms;
 
 use super::super::operations::BurnchainOpSigner;
 use super::super::Config;
+use super::fee_policy::{
+    choose_initial_fee_rate, choose_rbf_fee_rate, make_fee_policy, rbf_limit_reached,
+    BurnchainFeePolicy,
+};
 use super::{BurnchainController, BurnchainTip, Error as BurnchainControllerError};
+use crate::config::BurnchainIndexerName;
 
 /// The number of bitcoin blocks that can have
 ///  passed since the UTXO cache was last refreshed before
@@ -66,6 +72,9 @@ const DUST_UTXO_LIMIT: u64 = 5500;
 pub struct BitcoinRegtestController {
     config: Config,
     indexer: BitcoinIndexer,
+    /// If set, burnchain headers and blocks are synced through this instead of `indexer`.
+    /// Both write the same headers DB, so `indexer` is still used to read headers.
+    esplora_indexer: Option<EsploraIndexer>,
     db: Option<SortitionDB>,
     burnchain_db: Option<BurnchainDB>,
     chain_tip: Option<BurnchainTip>,
@@ -74,6 +83,7 @@ pub struct BitcoinRegtestController {
     ongoing_block_commit: Option<OngoingBlockCommit>,
     should_keep_running: Option<Arc<AtomicBool>>,
     allow_rbf: bool,
+    fee_policy: Box<dyn BurnchainFeePolicy>,
 }
 
 #[derive(Clone)]
@@ -100,6 +110,10 @@ struct LeaderBlockCommitFees {
     spent_in_attempts: u64,
     is_rbf_enabled: bool,
     final_size: u64,
+    /// fee rate of the first commit in this series of replacements
+    base_fee_rate: u64,
+    /// burnchain height at which the first commit in this series of replacements was sent
+    first_sent_at_height: u64,
 }
 
 #[cfg(test)]
@@ -141,6 +155,7 @@ pub fn make_bitcoin_indexer(
             first_block: burnchain_params.first_block_height,
             magic_bytes: burnchain_config.magic_bytes,
             epochs: burnchain_config.epochs,
+            socks5_proxy: config.connection_options.socks5_proxy.clone(),
         }
     };
 
@@ -154,6 +169,35 @@ pub fn make_bitcoin_indexer(
     burnchain_indexer
 }
 
+/// Helper method to create an EsploraIndexer, if the config asks for one
+pub fn make_esplora_indexer(
+    config: &Config,
+    should_keep_running: Option<Arc<AtomicBool>>,
+) -> Option<EsploraIndexer> {
+    if config.burnchain.indexer != BurnchainIndexerName::Esplora {
+        return None;
+    }
+    let (network, network_id) = config.burnchain.get_bitcoin_network();
+    let burnchain_params = BurnchainParameters::from_params(&config.burnchain.chain, &network)
+        .expect("Bitcoin network unsupported");
+    let indexer_config = EsploraIndexerConfig {
+        url: config
+            .burnchain
+            .esplora_url
+            .clone()
+            .expect("FATAL: burnchain.indexer is esplora, but no burnchain.esplora_url is set"),
+        timeout: config.burnchain.timeout.into(),
+        spv_headers_path: config.get_spv_headers_file_path(),
+        first_block: burnchain_params.first_block_height,
+        magic_bytes: config.burnchain.magic_bytes.clone(),
+        epochs: config.burnchain.epochs.clone(),
+        network_id,
+    };
+    let indexer = EsploraIndexer::new(indexer_config, should_keep_running)
+        .unwrap_or_else(|e| panic!("FATAL: failed to set up Esplora indexer: {}", e));
+    Some(indexer)
+}
+
 pub fn get_satoshis_per_byte(config: &Config) -> u64 {
     config.get_burnchain_config().satoshis_per_byte
 }
@@ -167,22 +211,28 @@ pub fn get_max_rbf(config: &Config) -> u64 {
 }
 
 impl LeaderBlockCommitFees {
+    /// Fees for a replacement of this commit, paying `fee_rate` satoshis per vbyte
     pub fn fees_from_previous_tx(
         &self,
         payload: &LeaderBlockCommitOp,
         config: &Config,
+        fee_rate: u64,
     ) -> LeaderBlockCommitFees {
-        let mut fees = LeaderBlockCommitFees::estimated_fees_from_payload(payload, config);
+        let mut fees =
+            LeaderBlockCommitFees::estimated_fees_from_payload(payload, config, fee_rate);
         fees.spent_in_attempts = cmp::max(1, self.spent_in_attempts);
         fees.final_size = self.final_size;
-        fees.fee_rate = self.fee_rate + get_rbf_fee_increment(&config);
         fees.is_rbf_enabled = true;
+        fees.base_fee_rate = self.base_fee_rate;
+        fees.first_sent_at_height = self.first_sent_at_height;
         fees
     }
 
+    /// Fees for a new commit, paying `fee_rate` satoshis per vbyte
     pub fn estimated_fees_from_payload(
         payload: &LeaderBlockCommitOp,
         config: &Config,
+        fee_rate: u64,
     ) -> LeaderBlockCommitFees {
         let sunset_fee = if payload.sunset_burn > 0 {
             cmp::max(payload.sunset_burn, DUST_UTXO_LIMIT)
@@ -194,7 +244,6 @@ impl LeaderBlockCommitFees {
         let value_per_transfer = payload.burn_fee / number_of_transfers;
         let sortition_fee = value_per_transfer * number_of_transfers;
         let spent_in_attempts = 0;
-        let fee_rate = get_satoshis_per_byte(config);
         let default_tx_size = config.burnchain.block_commit_tx_estimated_size;
 
         LeaderBlockCommitFees {
@@ -206,6 +255,8 @@ impl LeaderBlockCommitFees {
             spent_in_attempts,
             is_rbf_enabled: false,
             final_size: 0,
+            base_fee_rate: fee_rate,
+            first_sent_at_height: 0,
         }
     }
 
@@ -303,6 +354,7 @@ impl BitcoinRegtestController {
                 first_block: burnchain_params.first_block_height,
                 magic_bytes: burnchain_config.magic_bytes,
                 epochs: burnchain_config.epochs,
+                socks5_proxy: config.connection_options.socks5_proxy.clone(),
             }
         };
 
@@ -314,10 +366,13 @@ impl BitcoinRegtestController {
             should_keep_running: should_keep_running.clone(),
         };
 
+        let esplora_indexer = make_esplora_indexer(&config, should_keep_running.clone());
+        let fee_policy = make_fee_policy(config.burnchain.fee_policy);
         Self {
             use_coordinator: coordinator_channel,
             config,
             indexer: burnchain_indexer,
+            esplora_indexer,
             db: None,
             burnchain_db: None,
             chain_tip: None,
@@ -325,6 +380,7 @@ impl BitcoinRegtestController {
             ongoing_block_commit: None,
             should_keep_running,
             allow_rbf: true,
+            fee_policy,
         }
     }
 
@@ -349,6 +405,7 @@ impl BitcoinRegtestController {
                 first_block: burnchain_params.first_block_height,
                 magic_bytes: burnchain_config.magic_bytes,
                 epochs: burnchain_config.epochs,
+                socks5_proxy: config.connection_options.socks5_proxy.clone(),
             }
         };
 
@@ -360,10 +417,12 @@ impl BitcoinRegtestController {
             should_keep_running: None,
         };
 
+        let fee_policy = make_fee_policy(config.burnchain.fee_policy);
         Self {
             use_coordinator: None,
             config,
             indexer: burnchain_indexer,
+            esplora_indexer: None,
             db: None,
             burnchain_db: None,
             chain_tip: None,
@@ -371,6 +430,7 @@ impl BitcoinRegtestController {
             ongoing_block_commit: None,
             should_keep_running: None,
             allow_rbf: true,
+            fee_policy,
         }
     }
 
@@ -494,13 +554,24 @@ impl BitcoinRegtestController {
                 return Err(BurnchainControllerError::CoordinatorClosed);
             }
 
-            match burnchain.sync_with_indexer(
-                &mut self.indexer,
-                coordinator_comms.clone(),
-                target_block_height_opt,
-                Some(burnchain.pox_constants.reward_cycle_length as u64),
-                self.should_keep_running.clone(),
-            ) {
+            let max_blocks = Some(burnchain.pox_constants.reward_cycle_length as u64);
+            let sync_result = match self.esplora_indexer.as_mut() {
+                Some(esplora_indexer) => burnchain.sync_with_indexer(
+                    esplora_indexer,
+                    coordinator_comms.clone(),
+                    target_block_height_opt,
+                    max_blocks,
+                    self.should_keep_running.clone(),
+                ),
+                None => burnchain.sync_with_indexer(
+                    &mut self.indexer,
+                    coordinator_comms.clone(),
+                    target_block_height_opt,
+                    max_blocks,
+                    self.should_keep_running.clone(),
+                ),
+            };
+            match sync_result {
                 Ok(x) => {
                     increment_btc_blocks_received_counter();
 
@@ -894,7 +965,6 @@ impl BitcoinRegtestController {
         let transaction = match operation {
             BlockstackOperationType::LeaderBlockCommit(_)
             | BlockstackOperationType::LeaderKeyRegister(_)
-            | BlockstackOperationType::StackStx(_)
             | BlockstackOperationType::DelegateStx(_)
             | BlockstackOperationType::UserBurnSupport(_) => {
                 unimplemented!();
@@ -905,6 +975,9 @@ impl BitcoinRegtestController {
             BlockstackOperationType::TransferStx(payload) => {
                 self.build_transfer_stacks_tx(epoch_id, payload, op_signer, utxo)
             }
+            BlockstackOperationType::StackStx(payload) => {
+                self.build_stack_stx_tx(epoch_id, payload, op_signer, utxo)
+            }
         }?;
 
         let ser_transaction = SerializedTx::new(transaction.clone());
@@ -1081,17 +1154,161 @@ impl BitcoinRegtestController {
         Some(tx)
     }
 
-    #[cfg(not(test))]
-    fn build_pre_stacks_tx(
+    /// Build a stack stx tx.
+    ///   A stack-stx op must spend the second output of a pre-stx op whose output is the stacker's
+    ///   address, so `utxo_to_use` should be that output.  Use `submit_stack_stx()` to send both.
+    fn build_stack_stx_tx(
         &mut self,
-        _epoch_id: StacksEpochId,
-        _payload: PreStxOp,
-        _signer: &mut BurnchainOpSigner,
+        epoch_id: StacksEpochId,
+        payload: StackStxOp,
+        signer: &mut BurnchainOpSigner,
+        utxo_to_use: Option<UTXO>,
     ) -> Option<Transaction> {
-        unimplemented!()
+        let public_key = signer.get_public_key();
+        let max_tx_size = 250;
+
+        let (mut tx, mut utxos) = if let Some(utxo) = utxo_to_use {
+            (
+                Transaction {
+                    input: vec![],
+                    output: vec![],
+                    version: 1,
+                    lock_time: 0,
+                },
+                UTXOSet {
+                    bhh: BurnchainHeaderHash::zero(),
+                    utxos: vec![utxo],
+                },
+            )
+        } else {
+            self.prepare_tx(
+                epoch_id,
+                &public_key,
+                DUST_UTXO_LIMIT + max_tx_size * get_satoshis_per_byte(&self.config),
+                None,
+                None,
+                0,
+            )?
+        };
+
+        // Serialize the payload
+        let op_bytes = {
+            let mut bytes = self.config.burnchain.magic_bytes.as_bytes().to_vec();
+            payload.consensus_serialize(&mut bytes).ok()?;
+            bytes
+        };
+
+        let consensus_output = TxOut {
+            value: 0,
+            script_pubkey: Builder::new()
+                .push_opcode(opcodes::All::OP_RETURN)
+                .push_slice(&op_bytes)
+                .into_script(),
+        };
+
+        tx.output = vec![consensus_output];
+        tx.output
+            .push(payload.reward_addr.to_bitcoin_tx_out(DUST_UTXO_LIMIT));
+
+        self.finalize_tx(
+            epoch_id,
+            &mut tx,
+            DUST_UTXO_LIMIT,
+            0,
+            max_tx_size,
+            get_satoshis_per_byte(&self.config),
+            &mut utxos,
+            signer,
+        )?;
+
+        increment_btc_ops_sent_counter();
+
+        info!(
+            "Miner node: submitting stack-stx op - {}",
+            public_key.to_hex()
+        );
+
+        Some(tx)
+    }
+
+    /// Build and send a pre-stx or stack-stx operation, optionally spending the given UTXO
+    /// instead of one of the signer's.  No other operations can be sent this way.
+    /// Returns the sent transaction on success.
+    fn submit_stacking_op(
+        &mut self,
+        epoch_id: StacksEpochId,
+        operation: BlockstackOperationType,
+        op_signer: &mut BurnchainOpSigner,
+        utxo: Option<UTXO>,
+    ) -> Result<Transaction, String> {
+        let opcode = operation.opcode();
+        let transaction = match operation {
+            BlockstackOperationType::PreStx(payload) => {
+                self.build_pre_stacks_tx(epoch_id, payload, op_signer)
+            }
+            BlockstackOperationType::StackStx(payload) => {
+                self.build_stack_stx_tx(epoch_id, payload, op_signer, utxo)
+            }
+            BlockstackOperationType::LeaderBlockCommit(_)
+            | BlockstackOperationType::LeaderKeyRegister(_)
+            | BlockstackOperationType::TransferStx(_)
+            | BlockstackOperationType::DelegateStx(_)
+            | BlockstackOperationType::UserBurnSupport(_) => {
+                return Err(format!("Cannot submit {:?} operations", opcode));
+            }
+        }
+        .ok_or_else(|| format!("Failed to build {:?} transaction", opcode))?;
+
+        let ser_transaction = SerializedTx::new(transaction.clone());
+        self.send_transaction(ser_transaction)
+            .ok_or_else(|| format!("Failed to send {:?} transaction", opcode))?;
+        Ok(transaction)
+    }
+
+    /// Send a pre-stx op paid for by `payer` whose output is the stacker's address, and then a
+    /// stack-stx op signed by `stacker` that spends it.  Both transactions can be mined in the
+    /// same burnchain block.
+    /// Returns the (pre-stx, stack-stx) txids on success.
+    pub fn submit_stack_stx(
+        &mut self,
+        epoch_id: StacksEpochId,
+        payload: StackStxOp,
+        payer: &mut BurnchainOpSigner,
+        stacker: &mut BurnchainOpSigner,
+    ) -> Result<(Txid, Txid), String> {
+        let pre_stx_op = PreStxOp {
+            output: payload.sender.clone(),
+            // to be filled in
+            txid: Txid([0u8; 32]),
+            vtxindex: 0,
+            block_height: 0,
+            burn_header_hash: BurnchainHeaderHash([0u8; 32]),
+        };
+        let pre_stx_tx = self.submit_stacking_op(
+            epoch_id,
+            BlockstackOperationType::PreStx(pre_stx_op),
+            payer,
+            None,
+        )?;
+        let pre_stx_utxo = UTXO {
+            txid: pre_stx_tx.txid(),
+            vout: 1,
+            script_pub_key: pre_stx_tx.output[1].script_pubkey.clone(),
+            amount: pre_stx_tx.output[1].value,
+            confirmations: 0,
+        };
+        let stack_stx_tx = self.submit_stacking_op(
+            epoch_id,
+            BlockstackOperationType::StackStx(payload),
+            stacker,
+            Some(pre_stx_utxo),
+        )?;
+        Ok((
+            SerializedTx::new(pre_stx_tx).txid(),
+            SerializedTx::new(stack_stx_tx).txid(),
+        ))
     }
 
-    #[cfg(test)]
     fn build_pre_stacks_tx(
         &mut self,
         epoch_id: StacksEpochId,
@@ -1152,17 +1369,27 @@ impl BitcoinRegtestController {
         signer: &mut BurnchainOpSigner,
         utxos_to_include: Option<UTXOSet>,
         utxos_to_exclude: Option<UTXOSet>,
-        previous_fees: Option<LeaderBlockCommitFees>,
+        rbf_fees: Option<LeaderBlockCommitFees>,
         previous_txids: &Vec<Txid>,
     ) -> Option<Transaction> {
-        let mut estimated_fees = match previous_fees {
-            Some(fees) => fees.fees_from_previous_tx(&payload, &self.config),
-            None => LeaderBlockCommitFees::estimated_fees_from_payload(&payload, &self.config),
-        };
-
         let _ = self.sortdb_mut();
         let burn_chain_tip = self.burnchain_db.as_ref()?.get_canonical_chain_tip().ok()?;
 
+        let mut estimated_fees = match rbf_fees {
+            Some(fees) => fees,
+            None => {
+                let fee_rate =
+                    choose_initial_fee_rate(self.fee_policy.as_mut(), &self.config).fee_rate;
+                let mut fees = LeaderBlockCommitFees::estimated_fees_from_payload(
+                    &payload,
+                    &self.config,
+                    fee_rate,
+                );
+                fees.first_sent_at_height = burn_chain_tip.block_height;
+                fees
+            }
+        };
+
         let public_key = signer.get_public_key();
         let (mut tx, mut utxos) = self.prepare_tx(
             epoch_id,
@@ -1295,6 +1522,7 @@ impl BitcoinRegtestController {
         // Did a re-org occur since we fetched our UTXOs, or are the UTXOs so stale that they should be abandoned?
         let mut traversal_depth = 0;
         let mut burn_chain_tip = burnchain_db.get_canonical_chain_tip().ok()?;
+        let burn_chain_tip_height = burn_chain_tip.block_height;
         let mut found_last_mined_at = false;
         while traversal_depth < UTXO_CACHE_STALENESS_LIMIT {
             if &burn_chain_tip.block_hash == &ongoing_op.utxos.bhh {
@@ -1330,13 +1558,12 @@ impl BitcoinRegtestController {
         }
 
         // Stop as soon as the fee_rate is ${self.config.burnchain.max_rbf} percent higher, stop RBF
-        if ongoing_op.fees.fee_rate
-            > (get_satoshis_per_byte(&self.config) * get_max_rbf(&self.config) / 100)
-        {
-            warn!(
-                "RBF'd block commits reached {}% satoshi per byte fee rate, not resubmitting",
-                get_max_rbf(&self.config)
-            );
+        if rbf_limit_reached(
+            self.fee_policy.as_ref(),
+            &self.config,
+            ongoing_op.fees.base_fee_rate,
+            ongoing_op.fees.fee_rate,
+        ) {
             self.ongoing_block_commit = Some(ongoing_op);
             return None;
         }
@@ -1371,14 +1598,31 @@ impl BitcoinRegtestController {
             )
         } else {
             // Case 2) ii): Attempt to RBF
+            let blocks_unconfirmed =
+                burn_chain_tip_height.saturating_sub(ongoing_op.fees.first_sent_at_height);
+            let Some(rbf_fee_rate) = choose_rbf_fee_rate(
+                self.fee_policy.as_mut(),
+                &self.config,
+                ongoing_op.fees.base_fee_rate,
+                ongoing_op.fees.fee_rate,
+                blocks_unconfirmed,
+            ) else {
+                self.ongoing_block_commit = Some(ongoing_op);
+                return None;
+            };
             info!("Attempt to replace by fee an outdated leader block commit");
+            let rbf_fees = ongoing_op.fees.fees_from_previous_tx(
+                &payload,
+                &self.config,
+                rbf_fee_rate.fee_rate,
+            );
             self.send_block_commit_operation(
                 epoch_id,
                 payload,
                 signer,
                 Some(ongoing_op.utxos.clone()),
                 None,
-                Some(ongoing_op.fees.clone()),
+                Some(rbf_fees),
                 &ongoing_op.txids,
             )
         };
@@ -1820,7 +2064,9 @@ impl BitcoinRegtestController {
             BlockstackOperationType::TransferStx(payload) => {
                 self.build_transfer_stacks_tx(epoch_id, payload, op_signer, None)
             }
-            BlockstackOperationType::StackStx(_payload) => unimplemented!(),
+            BlockstackOperationType::StackStx(payload) => {
+                self.build_stack_stx_tx(epoch_id, payload, op_signer, None)
+            }
             BlockstackOperationType::DelegateStx(payload) => {
                 self.build_delegate_stacks_tx(epoch_id, payload, op_signer, None)
             }
@@ -2319,6 +2565,30 @@ impl BitcoinRPCRequest {
         Ok(UTXOSet { bhh, utxos })
     }
 
+    /// Calls `estimatesmartfee` and returns the estimated fee rate in BTC per 1000 vbytes, or None
+    /// if bitcoind does not have enough data to estimate one.
+    pub fn estimate_smart_fee(config: &Config, conf_target: u64) -> RPCResult<Option<f64>> {
+        let payload = BitcoinRPCRequest {
+            method: "estimatesmartfee".to_string(),
+            params: vec![conf_target.into()],
+            id: "stacks".to_string(),
+            jsonrpc: "2.0".to_string(),
+        };
+
+        let json_resp = BitcoinRPCRequest::send(&config, payload)?;
+
+        if let Some(e) = json_resp.get("error") {
+            if !e.is_null() {
+                return Err(RPCError::Bitcoind(json_resp.to_string()));
+            }
+        }
+        let fee_rate = json_resp
+            .get("result")
+            .and_then(|result| result.get("feerate"))
+            .and_then(|fee_rate| fee_rate.as_f64());
+        Ok(fee_rate)
+    }
+
     pub fn send_raw_transaction(config: &Config, tx: String) -> RPCResult<()> {
         let payload = BitcoinRPCRequest {
             method: "sendrawtransaction".to_string(),
@@ -2464,7 +2734,19 @@ impl BitcoinRPCRequest {
         request.set_body(body);
 
         let mut response = async_std::task::block_on(async move {
-            let stream = match TcpStream::connect(config.burnchain.get_rpc_socket_addr()).await {
+            let connect_result = match config.connection_options.socks5_proxy.as_ref() {
+                Some(proxy) => socks5::connect_tcp(
+                    Some(proxy),
+                    &PeerHost::from_host_port(
+                        config.burnchain.peer_host.clone(),
+                        config.burnchain.rpc_port,
+                    ),
+                    Duration::from_secs(config.burnchain.timeout.into()),
+                )
+                .map(TcpStream::from),
+                None => TcpStream::connect(config.burnchain.get_rpc_socket_addr()).await,
+            };
+            let stream = match connect_result {
                 Ok(stream) => stream,
                 Err(err) => {
                     return Err(RPCError::Network(format!(
@@ -0,0 +1,437 @@
+//! Policies for choosing the fee rate of block commits, and for bumping it when a block commit
+//! is replaced by fee.
+//!
+//! Every policy's choice is clamped to the configured `min_satoshis_per_byte` and
+//! `max_satoshis_per_byte`, and replacements stop once the fee rate passes `max_rbf` percent
+//! of the first commit's fee rate.
+
+use std::fmt;
+
+use stacks::monitoring::set_btc_commit_fee_rate;
+
+use super::bitcoin_regtest_controller::{
+    get_max_rbf, get_rbf_fee_increment, get_satoshis_per_byte, BitcoinRPCRequest,
+};
+use crate::config::{BurnchainConfig, BurnchainFeePolicyName};
+use crate::Config;
+
+/// Why a fee policy chose the fee rate it did
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeeRateReason {
+    /// The configured `satoshis_per_byte`
+    Configured,
+    /// bitcoind's `estimatesmartfee`
+    Estimated,
+    /// bitcoind could not estimate a fee rate, so the configured one was used
+    EstimateUnavailable,
+    /// The previous fee rate plus `rbf_fee_increment`
+    Increment,
+    /// Escalated for the number of blocks the commit went unconfirmed
+    Escalated,
+    /// Raised to `min_satoshis_per_byte`
+    ClampedToMin,
+    /// Lowered to `max_satoshis_per_byte`
+    ClampedToMax,
+}
+
+impl FeeRateReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FeeRateReason::Configured => "configured",
+            FeeRateReason::Estimated => "estimated",
+            FeeRateReason::EstimateUnavailable => "estimate_unavailable",
+            FeeRateReason::Increment => "increment",
+            FeeRateReason::Escalated => "escalated",
+            FeeRateReason::ClampedToMin => "clamped_to_min",
+            FeeRateReason::ClampedToMax => "clamped_to_max",
+        }
+    }
+}
+
+impl fmt::Display for FeeRateReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A fee rate (in satoshis per vbyte) and why it was chosen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeeRateDecision {
+    pub fee_rate: u64,
+    pub reason: FeeRateReason,
+}
+
+impl FeeRateDecision {
+    pub fn new(fee_rate: u64, reason: FeeRateReason) -> FeeRateDecision {
+        FeeRateDecision { fee_rate, reason }
+    }
+
+    /// Clamp the fee rate to the configured bounds
+    fn clamp(self, burnchain_config: &BurnchainConfig) -> FeeRateDecision {
+        if let Some(max_rate) = burnchain_config.max_satoshis_per_byte {
+            if self.fee_rate > max_rate {
+                return FeeRateDecision::new(max_rate, FeeRateReason::ClampedToMax);
+            }
+        }
+        if let Some(min_rate) = burnchain_config.min_satoshis_per_byte {
+            if self.fee_rate < min_rate {
+                return FeeRateDecision::new(min_rate, FeeRateReason::ClampedToMin);
+            }
+        }
+        self
+    }
+}
+
+/// Chooses block commit fee rates.  Implementations read their parameters from the burnchain
+/// config on each call, so that changes to the config file are picked up without a restart.
+pub trait BurnchainFeePolicy: Send {
+    /// Name of this policy, for logging and metrics
+    fn name(&self) -> &'static str;
+
+    /// Fee rate for a new block commit
+    fn initial_fee_rate(&mut self, config: &Config) -> FeeRateDecision;
+
+    /// Fee rate for a replacement of a block commit that has gone unconfirmed for
+    /// `blocks_unconfirmed` burnchain blocks.  `base_fee_rate` is the fee rate of the first
+    /// commit, and `previous_fee_rate` is the fee rate of the commit being replaced.
+    fn rbf_fee_rate(
+        &mut self,
+        config: &Config,
+        base_fee_rate: u64,
+        previous_fee_rate: u64,
+        blocks_unconfirmed: u64,
+    ) -> FeeRateDecision;
+}
+
+/// Today's behavior: always use `satoshis_per_byte`, and bump by `rbf_fee_increment`
+pub struct StaticFeePolicy;
+
+impl BurnchainFeePolicy for StaticFeePolicy {
+    fn name(&self) -> &'static str {
+        "static"
+    }
+
+    fn initial_fee_rate(&mut self, config: &Config) -> FeeRateDecision {
+        FeeRateDecision::new(get_satoshis_per_byte(config), FeeRateReason::Configured)
+    }
+
+    fn rbf_fee_rate(
+        &mut self,
+        config: &Config,
+        _base_fee_rate: u64,
+        previous_fee_rate: u64,
+        _blocks_unconfirmed: u64,
+    ) -> FeeRateDecision {
+        FeeRateDecision::new(
+            previous_fee_rate + get_rbf_fee_increment(config),
+            FeeRateReason::Increment,
+        )
+    }
+}
+
+/// Ask bitcoind for a fee rate via `estimatesmartfee`
+pub struct EstimateSmartFeePolicy {
+    /// Returns the estimated fee rate in satoshis per vbyte for the given confirmation target
+    estimator: fn(&Config, u64) -> Option<u64>,
+}
+
+impl EstimateSmartFeePolicy {
+    pub fn new() -> EstimateSmartFeePolicy {
+        EstimateSmartFeePolicy {
+            estimator: estimate_smart_fee_rate,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_estimator(estimator: fn(&Config, u64) -> Option<u64>) -> EstimateSmartFeePolicy {
+        EstimateSmartFeePolicy { estimator }
+    }
+
+    fn estimate(&self, config: &Config) -> FeeRateDecision {
+        let burnchain_config = config.get_burnchain_config();
+        match (self.estimator)(config, burnchain_config.fee_estimate_target_blocks) {
+            Some(fee_rate) => FeeRateDecision::new(fee_rate, FeeRateReason::Estimated),
+            None => FeeRateDecision::new(
+                burnchain_config.satoshis_per_byte,
+                FeeRateReason::EstimateUnavailable,
+            ),
+        }
+    }
+}
+
+impl BurnchainFeePolicy for EstimateSmartFeePolicy {
+    fn name(&self) -> &'static str {
+        "estimate_smart_fee"
+    }
+
+    fn initial_fee_rate(&mut self, config: &Config) -> FeeRateDecision {
+        self.estimate(config)
+    }
+
+    /// Follow the estimate if it rose, but always bump by at least `rbf_fee_increment`
+    fn rbf_fee_rate(
+        &mut self,
+        config: &Config,
+        _base_fee_rate: u64,
+        previous_fee_rate: u64,
+        _blocks_unconfirmed: u64,
+    ) -> FeeRateDecision {
+        let estimate = self.estimate(config);
+        let incremented = previous_fee_rate + get_rbf_fee_increment(config);
+        if estimate.fee_rate > incremented {
+            estimate
+        } else {
+            FeeRateDecision::new(incremented, FeeRateReason::Increment)
+        }
+    }
+}
+
+/// Start at `satoshis_per_byte`, and raise the fee rate by `rbf_escalation_percent` of the first
+/// commit's fee rate for each block that the commit goes unconfirmed.
+pub struct FeedbackFeePolicy;
+
+impl BurnchainFeePolicy for FeedbackFeePolicy {
+    fn name(&self) -> &'static str {
+        "feedback"
+    }
+
+    fn initial_fee_rate(&mut self, config: &Config) -> FeeRateDecision {
+        FeeRateDecision::new(get_satoshis_per_byte(config), FeeRateReason::Configured)
+    }
+
+    fn rbf_fee_rate(
+        &mut self,
+        config: &Config,
+        base_fee_rate: u64,
+        previous_fee_rate: u64,
+        blocks_unconfirmed: u64,
+    ) -> FeeRateDecision {
+        let burnchain_config = config.get_burnchain_config();
+        let escalated = base_fee_rate.saturating_mul(
+            100u64.saturating_add(
+                burnchain_config
+                    .rbf_escalation_percent
+                    .saturating_mul(blocks_unconfirmed),
+            ),
+        ) / 100;
+        let incremented = previous_fee_rate + burnchain_config.rbf_fee_increment;
+        if escalated > incremented {
+            FeeRateDecision::new(escalated, FeeRateReason::Escalated)
+        } else {
+            FeeRateDecision::new(incremented, FeeRateReason::Increment)
+        }
+    }
+}
+
+/// Instantiate the fee policy named in the config
+pub fn make_fee_policy(name: BurnchainFeePolicyName) -> Box<dyn BurnchainFeePolicy> {
+    match name {
+        BurnchainFeePolicyName::Static => Box::new(StaticFeePolicy),
+        BurnchainFeePolicyName::EstimateSmartFee => Box::new(EstimateSmartFeePolicy::new()),
+        BurnchainFeePolicyName::Feedback => Box::new(FeedbackFeePolicy),
+    }
+}
+
+/// Choose the fee rate for a new block commit, within the configured bounds
+pub fn choose_initial_fee_rate(
+    policy: &mut dyn BurnchainFeePolicy,
+    config: &Config,
+) -> FeeRateDecision {
+    let decision = policy
+        .initial_fee_rate(config)
+        .clamp(&config.get_burnchain_config());
+    record_fee_rate_decision(policy.name(), &decision);
+    decision
+}
+
+/// Has a series of block commit replacements gone past `max_rbf` percent of the first commit's
+/// fee rate?  If so, the commit should not be replaced again.
+pub fn rbf_limit_reached(
+    policy: &dyn BurnchainFeePolicy,
+    config: &Config,
+    base_fee_rate: u64,
+    previous_fee_rate: u64,
+) -> bool {
+    let max_rbf = get_max_rbf(config);
+    if previous_fee_rate > base_fee_rate.saturating_mul(max_rbf) / 100 {
+        warn!(
+            "RBF'd block commits reached {}% of the base fee rate, not resubmitting",
+            max_rbf;
+            "policy" => policy.name(),
+            "base_fee_rate" => base_fee_rate,
+            "previous_fee_rate" => previous_fee_rate
+        );
+        return true;
+    }
+    false
+}
+
+/// Choose the fee rate for replacing a block commit, within the configured bounds.
+/// Returns None if the commit should not be replaced -- i.e. because the previous fee rate has
+/// already passed `max_rbf` percent of the base fee rate, or because the bounds leave no room to
+/// raise it.
+pub fn choose_rbf_fee_rate(
+    policy: &mut dyn BurnchainFeePolicy,
+    config: &Config,
+    base_fee_rate: u64,
+    previous_fee_rate: u64,
+    blocks_unconfirmed: u64,
+) -> Option<FeeRateDecision> {
+    if rbf_limit_reached(policy, config, base_fee_rate, previous_fee_rate) {
+        return None;
+    }
+
+    let burnchain_config = config.get_burnchain_config();
+    let decision = policy
+        .rbf_fee_rate(config, base_fee_rate, previous_fee_rate, blocks_unconfirmed)
+        .clamp(&burnchain_config);
+    if decision.fee_rate <= previous_fee_rate {
+        warn!(
+            "Block commit fee rate cannot be raised further, not resubmitting";
+            "policy" => policy.name(),
+            "previous_fee_rate" => previous_fee_rate,
+            "fee_rate" => decision.fee_rate,
+            "reason" => %decision.reason
+        );
+        return None;
+    }
+    record_fee_rate_decision(policy.name(), &decision);
+    Some(decision)
+}
+
+fn record_fee_rate_decision(policy_name: &str, decision: &FeeRateDecision) {
+    debug!(
+        "Chose block commit fee rate";
+        "policy" => policy_name,
+        "fee_rate" => decision.fee_rate,
+        "reason" => %decision.reason
+    );
+    set_btc_commit_fee_rate(policy_name, decision.reason.as_str(), decision.fee_rate);
+}
+
+/// Query bitcoind's `estimatesmartfee` for a fee rate in satoshis per vbyte
+fn estimate_smart_fee_rate(config: &Config, conf_target: u64) -> Option<u64> {
+    match BitcoinRPCRequest::estimate_smart_fee(config, conf_target) {
+        Ok(Some(btc_per_kvb)) => Some(btc_per_kvb_to_sats_per_vb(btc_per_kvb)),
+        Ok(None) => {
+            debug!("bitcoind has no fee estimate for {} blocks", conf_target);
+            None
+        }
+        Err(e) => {
+            warn!("Failed to query estimatesmartfee: {:?}", &e);
+            None
+        }
+    }
+}
+
+/// Convert a fee rate in BTC per 1000 vbytes (as reported by bitcoind) to satoshis per vbyte,
+/// rounding up
+fn btc_per_kvb_to_sats_per_vb(btc_per_kvb: f64) -> u64 {
+    (btc_per_kvb * 100_000.0).ceil() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_config(policy: BurnchainFeePolicyName) -> Config {
+        let mut config = Config::default();
+        config.burnchain.fee_policy = policy;
+        config.burnchain.satoshis_per_byte = 50;
+        config.burnchain.rbf_fee_increment = 5;
+        config.burnchain.max_rbf = 150;
+        config.burnchain.rbf_escalation_percent = 20;
+        config
+    }
+
+    #[test]
+    fn test_btc_per_kvb_to_sats_per_vb() {
+        assert_eq!(btc_per_kvb_to_sats_per_vb(0.00001), 1);
+        assert_eq!(btc_per_kvb_to_sats_per_vb(0.0002), 20);
+        assert_eq!(btc_per_kvb_to_sats_per_vb(0.000201), 21);
+    }
+
+    #[test]
+    fn test_static_policy() {
+        let config = make_config(BurnchainFeePolicyName::Static);
+        let mut policy = make_fee_policy(config.burnchain.fee_policy);
+
+        let initial = choose_initial_fee_rate(policy.as_mut(), &config);
+        assert_eq!(initial, FeeRateDecision::new(50, FeeRateReason::Configured));
+
+        let rbf = choose_rbf_fee_rate(policy.as_mut(), &config, 50, 50, 3).unwrap();
+        assert_eq!(rbf, FeeRateDecision::new(55, FeeRateReason::Increment));
+
+        // 50 * 150% = 75
+        assert!(choose_rbf_fee_rate(policy.as_mut(), &config, 50, 75, 1).is_some());
+        assert!(choose_rbf_fee_rate(policy.as_mut(), &config, 50, 76, 1).is_none());
+    }
+
+    #[test]
+    fn test_estimate_smart_fee_policy() {
+        let config = make_config(BurnchainFeePolicyName::EstimateSmartFee);
+
+        let mut policy = EstimateSmartFeePolicy::with_estimator(|_, _| Some(30));
+        let initial = choose_initial_fee_rate(&mut policy, &config);
+        assert_eq!(initial, FeeRateDecision::new(30, FeeRateReason::Estimated));
+
+        // estimate didn't move, so just bump
+        let rbf = choose_rbf_fee_rate(&mut policy, &config, 30, 30, 1).unwrap();
+        assert_eq!(rbf, FeeRateDecision::new(35, FeeRateReason::Increment));
+
+        let mut policy = EstimateSmartFeePolicy::with_estimator(|_, _| Some(42));
+        let rbf = choose_rbf_fee_rate(&mut policy, &config, 30, 35, 2).unwrap();
+        assert_eq!(rbf, FeeRateDecision::new(42, FeeRateReason::Estimated));
+
+        let mut policy = EstimateSmartFeePolicy::with_estimator(|_, _| None);
+        let initial = choose_initial_fee_rate(&mut policy, &config);
+        assert_eq!(
+            initial,
+            FeeRateDecision::new(50, FeeRateReason::EstimateUnavailable)
+        );
+    }
+
+    #[test]
+    fn test_feedback_policy() {
+        let config = make_config(BurnchainFeePolicyName::Feedback);
+        let mut policy = make_fee_policy(config.burnchain.fee_policy);
+
+        let initial = choose_initial_fee_rate(policy.as_mut(), &config);
+        assert_eq!(initial, FeeRateDecision::new(50, FeeRateReason::Configured));
+
+        // one block unconfirmed: 50 * 120% = 60
+        let rbf = choose_rbf_fee_rate(policy.as_mut(), &config, 50, 50, 1).unwrap();
+        assert_eq!(rbf, FeeRateDecision::new(60, FeeRateReason::Escalated));
+
+        // escalation is slower than the increment, so bump by the increment
+        let rbf = choose_rbf_fee_rate(policy.as_mut(), &config, 50, 60, 1).unwrap();
+        assert_eq!(rbf, FeeRateDecision::new(65, FeeRateReason::Increment));
+
+        // three blocks unconfirmed: 50 * 160% = 80, which is past max_rbf, but is still allowed
+        // as the replacement of a commit within max_rbf
+        let rbf = choose_rbf_fee_rate(policy.as_mut(), &config, 50, 65, 3).unwrap();
+        assert_eq!(rbf, FeeRateDecision::new(80, FeeRateReason::Escalated));
+        assert!(choose_rbf_fee_rate(policy.as_mut(), &config, 50, 80, 4).is_none());
+    }
+
+    #[test]
+    fn test_fee_rate_bounds() {
+        let mut config = make_config(BurnchainFeePolicyName::Feedback);
+        config.burnchain.min_satoshis_per_byte = Some(60);
+        config.burnchain.max_satoshis_per_byte = Some(70);
+        let mut policy = make_fee_policy(config.burnchain.fee_policy);
+
+        let initial = choose_initial_fee_rate(policy.as_mut(), &config);
+        assert_eq!(
+            initial,
+            FeeRateDecision::new(60, FeeRateReason::ClampedToMin)
+        );
+
+        // 60 * 140% = 84
+        let rbf = choose_rbf_fee_rate(policy.as_mut(), &config, 60, 60, 2).unwrap();
+        assert_eq!(rbf, FeeRateDecision::new(70, FeeRateReason::ClampedToMax));
+
+        // no room left under the cap
+        assert!(choose_rbf_fee_rate(policy.as_mut(), &config, 60, 70, 3).is_none());
+    }
+}
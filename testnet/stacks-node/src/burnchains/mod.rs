@@ -1,4 +1,5 @@
 pub mod bitcoin_regtest_controller;
+pub mod fee_policy;
 pub mod mocknet_controller;
 
 use std::fmt;
@@ -8,6 +8,7 @@ use std::sync::{Arc, Mutex};
 use clarity::vm::costs::ExecutionCost;
 use clarity::vm::types::{AssetIdentifier, PrincipalData, QualifiedContractIdentifier};
 use rand::RngCore;
+use stacks::burnchains::bitcoin::esplora::EsploraClient;
 use stacks::burnchains::bitcoin::BitcoinNetworkType;
 use stacks::burnchains::{Burnchain, MagicBytes, BLOCKSTACK_MAGIC_MAINNET};
 use stacks::chainstate::stacks::index::marf::MARFOpenOpts;
@@ -27,6 +28,9 @@ use stacks::cost_estimates::{CostEstimator, FeeEstimator, PessimisticEstimator};
 use stacks::net::atlas::AtlasConfig;
 use stacks::net::connection::ConnectionOptions;
 use stacks::net::{Neighbor, NeighborKey};
+use stacks_common::address::{
+    AddressHashMode, C32_ADDRESS_VERSION_MAINNET_SINGLESIG, C32_ADDRESS_VERSION_TESTNET_SINGLESIG,
+};
 use stacks_common::types::chainstate::StacksAddress;
 use stacks_common::types::net::PeerAddress;
 use stacks_common::types::Address;
@@ -39,6 +43,8 @@ use crate::chain_data::MinerStats;
 pub const DEFAULT_SATS_PER_VB: u64 = 50;
 const DEFAULT_MAX_RBF_RATE: u64 = 150; // 1.5x
 const DEFAULT_RBF_FEE_RATE_INCREMENT: u64 = 5;
+const DEFAULT_FEE_ESTIMATE_TARGET_BLOCKS: u64 = 1;
+const DEFAULT_RBF_ESCALATION_PERCENT: u64 = 25;
 const LEADER_KEY_TX_ESTIM_SIZE: u64 = 290;
 const BLOCK_COMMIT_TX_ESTIM_SIZE: u64 = 350;
 const INV_REWARD_CYCLES_TESTNET: u64 = 6;
@@ -128,6 +134,74 @@ mod tests {
         assert!(Config::from_config_file(ConfigFile::from_str("").unwrap()).is_ok());
     }
 
+    #[test]
+    fn test_esplora_indexer_config() {
+        let parse = |toml: &str| Config::from_config_file(ConfigFile::from_str(toml).unwrap());
+
+        let config = parse(
+            r#"
+            [burnchain]
+            mode = "krypton"
+            indexer = "esplora"
+            esplora_url = "http://127.0.0.1:3002/api"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.burnchain.indexer, BurnchainIndexerName::Esplora);
+        assert_eq!(
+            config.burnchain.esplora_url.as_deref(),
+            Some("http://127.0.0.1:3002/api")
+        );
+
+        assert_eq!(
+            parse(
+                r#"
+                [burnchain]
+                mode = "krypton"
+                indexer = "esplora"
+                "#,
+            )
+            .unwrap_err(),
+            "burnchain.indexer = \"esplora\" requires burnchain.esplora_url"
+        );
+        assert!(parse(
+            r#"
+            [burnchain]
+            mode = "krypton"
+            indexer = "esplora"
+            esplora_url = "https://blockstream.info/api"
+            "#,
+        )
+        .unwrap_err()
+        .starts_with("Invalid burnchain.esplora_url"));
+        assert_eq!(
+            parse(
+                r#"
+                [node]
+                miner = true
+
+                [burnchain]
+                mode = "krypton"
+                indexer = "esplora"
+                esplora_url = "http://127.0.0.1:3002/api"
+                "#,
+            )
+            .unwrap_err(),
+            "burnchain.indexer = \"esplora\" is only supported on follower nodes"
+        );
+        assert_eq!(
+            parse(
+                r#"
+                [burnchain]
+                indexer = "esplora"
+                esplora_url = "http://127.0.0.1:3002/api"
+                "#,
+            )
+            .unwrap_err(),
+            "burnchain.indexer = \"esplora\" is not supported in mocknet mode"
+        );
+    }
+
     #[test]
     fn should_load_legacy_mstx_balances_toml() {
         let config = ConfigFile::from_str(
@@ -746,13 +820,45 @@ impl Config {
                             QualifiedContractIdentifier::parse(contract_id).ok()
                         })
                         .collect(),
+                    index_data_map_keys: node
+                        .index_data_map_keys
+                        .unwrap_or(default_node_config.index_data_map_keys),
+                    prune_reward_cycles: node.prune_reward_cycles,
+                    marf_hash_threads: node
+                        .marf_hash_threads
+                        .unwrap_or(default_node_config.marf_hash_threads)
+                        .max(1),
+                    packed_block_store: node
+                        .packed_block_store
+                        .unwrap_or(default_node_config.packed_block_store),
+                    compress_blocks: node
+                        .compress_blocks
+                        .unwrap_or(default_node_config.compress_blocks),
+                    stacker_key: match node.stacker_key {
+                        Some(key) => Some(Secp256k1PrivateKey::from_hex(&key).map_err(|_e| {
+                            format!("node.stacker_key should be a hex encoded secret key")
+                        })?),
+                        None => default_node_config.stacker_key,
+                    },
                 };
+                if node_config.prune_reward_cycles == Some(0) {
+                    return Err("node.prune_reward_cycles must be at least 1".into());
+                }
+                if node_config.compress_blocks && !node_config.packed_block_store {
+                    return Err("node.compress_blocks requires node.packed_block_store".into());
+                }
                 (node_config, node.bootstrap_node, node.deny_nodes)
             }
             None => (default_node_config, None, None),
         };
 
         let default_burnchain_config = BurnchainConfig::default();
+        // with a SOCKS5 proxy, let the proxy resolve the bitcoind hostname
+        let socks5_proxy_set = config_file
+            .connection_options
+            .as_ref()
+            .map(|opts| opts.socks5_proxy.is_some())
+            .unwrap_or(false);
 
         let burnchain = match config_file.burnchain {
             Some(mut burnchain) => {
@@ -818,6 +924,7 @@ impl Config {
                         .commit_anchor_block_within
                         .unwrap_or(default_burnchain_config.commit_anchor_block_within),
                     peer_host: match burnchain.peer_host {
+                        Some(peer_host) if socks5_proxy_set => peer_host,
                         Some(peer_host) => {
                             // Using std::net::LookupHost would be preferable, but it's
                             // unfortunately unstable at this point.
@@ -880,6 +987,23 @@ impl Config {
                     rbf_fee_increment: burnchain
                         .rbf_fee_increment
                         .unwrap_or(default_burnchain_config.rbf_fee_increment),
+                    fee_policy: burnchain
+                        .fee_policy
+                        .map(BurnchainFeePolicyName::panic_parse)
+                        .unwrap_or(default_burnchain_config.fee_policy),
+                    min_satoshis_per_byte: burnchain.min_satoshis_per_byte,
+                    max_satoshis_per_byte: burnchain.max_satoshis_per_byte,
+                    fee_estimate_target_blocks: burnchain
+                        .fee_estimate_target_blocks
+                        .unwrap_or(default_burnchain_config.fee_estimate_target_blocks),
+                    rbf_escalation_percent: burnchain
+                        .rbf_escalation_percent
+                        .unwrap_or(default_burnchain_config.rbf_escalation_percent),
+                    indexer: burnchain
+                        .indexer
+                        .map(BurnchainIndexerName::panic_parse)
+                        .unwrap_or(default_burnchain_config.indexer),
+                    esplora_url: burnchain.esplora_url.clone(),
                     // will be overwritten below
                     epochs: default_burnchain_config.epochs,
                     ast_precheck_size_height: burnchain.ast_precheck_size_height,
@@ -905,6 +1029,38 @@ impl Config {
                     }
                 }
 
+                if let (Some(min_rate), Some(max_rate)) =
+                    (result.min_satoshis_per_byte, result.max_satoshis_per_byte)
+                {
+                    if min_rate > max_rate {
+                        return Err(
+                            "min_satoshis_per_byte must not exceed max_satoshis_per_byte".into(),
+                        );
+                    }
+                }
+
+                if result.indexer == BurnchainIndexerName::Esplora {
+                    let Some(ref esplora_url) = result.esplora_url else {
+                        return Err(
+                            "burnchain.indexer = \"esplora\" requires burnchain.esplora_url".into(),
+                        );
+                    };
+                    EsploraClient::new(esplora_url, result.timeout.into())
+                        .map_err(|e| format!("Invalid burnchain.esplora_url: {}", e))?;
+                    if node.miner {
+                        return Err(
+                            "burnchain.indexer = \"esplora\" is only supported on follower nodes"
+                                .into(),
+                        );
+                    }
+                    if result.mode == "helium" || result.mode == "mocknet" {
+                        return Err(format!(
+                            "burnchain.indexer = \"esplora\" is not supported in {} mode",
+                            &result.mode
+                        ));
+                    }
+                }
+
                 if let Some(ref conf_epochs) = burnchain.epochs {
                     result.epochs = Some(Self::make_epochs(
                         conf_epochs,
@@ -1091,6 +1247,15 @@ impl Config {
                     }
                     None => None,
                 };
+                let socks5_proxy = match opts.socks5_proxy {
+                    Some(proxy) => Some(proxy.parse::<SocketAddr>().map_err(|e| {
+                        format!(
+                            "Invalid connection_options.socks5_proxy '{}': {}",
+                            &proxy, &e
+                        )
+                    })?),
+                    None => None,
+                };
                 let mut read_only_call_limit = HELIUM_DEFAULT_CONNECTION_OPTIONS
                     .read_only_call_limit
                     .clone();
@@ -1226,6 +1391,29 @@ impl Config {
                     max_sockets: opts.max_sockets.unwrap_or(800) as usize,
                     antientropy_public: opts.antientropy_public.unwrap_or(true),
                     private_neighbors: opts.private_neighbors.unwrap_or(true),
+                    auth_token: opts.auth_token,
+                    p2p_encryption: opts.p2p_encryption.unwrap_or(false),
+                    max_tx_announcements_per_sec: opts.max_tx_announcements_per_sec.unwrap_or(100),
+                    p2p_capture_path: opts.p2p_capture_path,
+                    p2p_capture_max_bytes: opts.p2p_capture_max_bytes.unwrap_or(64 * 1024 * 1024),
+                    p2p_capture_max_files: opts.p2p_capture_max_files.unwrap_or(4),
+                    socks5_proxy,
+                    max_p2p_inbound_bandwidth: opts.max_p2p_inbound_bandwidth.unwrap_or(0),
+                    max_p2p_outbound_bandwidth: opts.max_p2p_outbound_bandwidth.unwrap_or(0),
+                    max_p2p_peer_inbound_bandwidth: opts
+                        .max_p2p_peer_inbound_bandwidth
+                        .unwrap_or(0),
+                    max_p2p_peer_outbound_bandwidth: opts
+                        .max_p2p_peer_outbound_bandwidth
+                        .unwrap_or(0),
+                    max_http_inbound_bandwidth: opts.max_http_inbound_bandwidth.unwrap_or(0),
+                    max_http_outbound_bandwidth: opts.max_http_outbound_bandwidth.unwrap_or(0),
+                    max_http_peer_inbound_bandwidth: opts
+                        .max_http_peer_inbound_bandwidth
+                        .unwrap_or(0),
+                    max_http_peer_outbound_bandwidth: opts
+                        .max_http_peer_outbound_bandwidth
+                        .unwrap_or(0),
                     ..ConnectionOptions::default()
                 }
             }
@@ -1361,6 +1549,21 @@ impl Config {
         }
     }
 
+    /// Stacks address of `node.stacker_key`, if it is set
+    pub fn get_stacker_address(&self) -> Option<StacksAddress> {
+        let stacker_key = self.node.stacker_key.as_ref()?;
+        StacksAddress::from_public_keys(
+            if self.is_mainnet() {
+                C32_ADDRESS_VERSION_MAINNET_SINGLESIG
+            } else {
+                C32_ADDRESS_VERSION_TESTNET_SINGLESIG
+            },
+            &AddressHashMode::SerializeP2PKH,
+            1,
+            &vec![Secp256k1PublicKey::from_private(stacker_key)],
+        )
+    }
+
     pub fn is_node_event_driven(&self) -> bool {
         self.events_observers.len() > 0
     }
@@ -1467,6 +1670,22 @@ pub struct BurnchainConfig {
     pub leader_key_tx_estimated_size: u64,
     pub block_commit_tx_estimated_size: u64,
     pub rbf_fee_increment: u64,
+    /// How block commit fee rates are chosen and bumped
+    pub fee_policy: BurnchainFeePolicyName,
+    /// Lowest fee rate (satoshis per vbyte) any fee policy may choose for a block commit
+    pub min_satoshis_per_byte: Option<u64>,
+    /// Highest fee rate (satoshis per vbyte) any fee policy may choose for a block commit
+    pub max_satoshis_per_byte: Option<u64>,
+    /// Confirmation target (in blocks) passed to `estimatesmartfee`
+    pub fee_estimate_target_blocks: u64,
+    /// How much the feedback fee policy raises the fee rate (as a percentage of the first
+    /// commit's fee rate) for each block that a commit goes unconfirmed
+    pub rbf_escalation_percent: u64,
+    /// Where burnchain headers and blocks are downloaded from
+    pub indexer: BurnchainIndexerName,
+    /// Base URL of the Esplora-compatible REST API to use when `indexer` is `esplora`,
+    /// e.g. `http://127.0.0.1:3002/api`
+    pub esplora_url: Option<String>,
     /// Custom override for the definitions of the epochs. This will only be applied for testnet and
     /// regtest nodes.
     pub epochs: Option<Vec<StacksEpoch>>,
@@ -1502,6 +1721,13 @@ impl BurnchainConfig {
             leader_key_tx_estimated_size: LEADER_KEY_TX_ESTIM_SIZE,
             block_commit_tx_estimated_size: BLOCK_COMMIT_TX_ESTIM_SIZE,
             rbf_fee_increment: DEFAULT_RBF_FEE_RATE_INCREMENT,
+            fee_policy: BurnchainFeePolicyName::default(),
+            min_satoshis_per_byte: None,
+            max_satoshis_per_byte: None,
+            fee_estimate_target_blocks: DEFAULT_FEE_ESTIMATE_TARGET_BLOCKS,
+            rbf_escalation_percent: DEFAULT_RBF_ESCALATION_PERCENT,
+            indexer: BurnchainIndexerName::default(),
+            esplora_url: None,
             epochs: None,
             pox_2_activation: None,
             sunset_start: None,
@@ -1582,6 +1808,13 @@ pub struct BurnchainConfigFile {
     pub block_commit_tx_estimated_size: Option<u64>,
     pub rbf_fee_increment: Option<u64>,
     pub max_rbf: Option<u64>,
+    pub fee_policy: Option<String>,
+    pub min_satoshis_per_byte: Option<u64>,
+    pub max_satoshis_per_byte: Option<u64>,
+    pub fee_estimate_target_blocks: Option<u64>,
+    pub rbf_escalation_percent: Option<u64>,
+    pub indexer: Option<String>,
+    pub esplora_url: Option<String>,
     pub epochs: Option<Vec<StacksEpochConfigFile>>,
     pub pox_2_activation: Option<u32>,
     pub sunset_start: Option<u32>,
@@ -1624,6 +1857,84 @@ pub struct NodeConfig {
     pub chain_liveness_poll_time_secs: u64,
     /// stacker DBs we replicate
     pub stacker_dbs: Vec<QualifiedContractIdentifier>,
+    /// Maintain a (non-consensus) index of the data map keys written by each contract, so data
+    /// maps can be listed over RPC
+    pub index_data_map_keys: bool,
+    /// If set, prune the Clarity state and block data of all but this many of the most recent
+    /// reward cycles when the node boots, and whenever a new reward cycle starts.  Pruned state
+    /// cannot be queried over RPC.  A pruned node halts if it receives a block that uses
+    /// `at-block` to read pruned state, and must then be re-synced without pruning.
+    pub prune_reward_cycles: Option<u64>,
+    /// Number of threads to calculate MARF trie hashes with when a block is committed, if
+    /// `marf_defer_hashing` is set.  Defaults to 1 (no worker threads).
+    pub marf_hash_threads: usize,
+    /// Store anchored blocks in a single packed, indexed file instead of one file per block.
+    /// Only takes effect on a new chainstate; an existing one must be converted with
+    /// `stacks-inspect migrate-block-store`.  Once converted, the packed store is always used.
+    pub packed_block_store: bool,
+    /// Compress blocks in a new packed block store with zstd
+    pub compress_blocks: bool,
+    /// Secret key of a stacker, which signs the stack-stx operations sent via the authenticated
+    /// /v2/burn_ops/stack_stx RPC endpoint.  That endpoint can only stack for this key's address.
+    pub stacker_key: Option<Secp256k1PrivateKey>,
+}
+
+/// Policies for choosing and bumping block commit fee rates
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum BurnchainFeePolicyName {
+    /// Use `satoshis_per_byte`, and bump by `rbf_fee_increment`
+    Static,
+    /// Ask bitcoind's `estimatesmartfee`, falling back to `satoshis_per_byte`
+    EstimateSmartFee,
+    /// Escalate the fee rate by how many blocks a commit has gone unconfirmed
+    Feedback,
+}
+
+impl Default for BurnchainFeePolicyName {
+    fn default() -> Self {
+        BurnchainFeePolicyName::Static
+    }
+}
+
+impl BurnchainFeePolicyName {
+    fn panic_parse(s: String) -> BurnchainFeePolicyName {
+        match s.to_lowercase().as_str() {
+            "static" => BurnchainFeePolicyName::Static,
+            "estimate_smart_fee" => BurnchainFeePolicyName::EstimateSmartFee,
+            "feedback" => BurnchainFeePolicyName::Feedback,
+            _ => panic!(
+                "Bad burnchain fee policy name supplied in configuration file: {}",
+                s
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BurnchainIndexerName {
+    /// Sync headers and blocks from `peer_host:peer_port` over the Bitcoin peer network
+    Bitcoin,
+    /// Sync headers and blocks from the Esplora-compatible REST API at `esplora_url`
+    Esplora,
+}
+
+impl Default for BurnchainIndexerName {
+    fn default() -> Self {
+        BurnchainIndexerName::Bitcoin
+    }
+}
+
+impl BurnchainIndexerName {
+    fn panic_parse(s: String) -> BurnchainIndexerName {
+        match s.to_lowercase().as_str() {
+            "bitcoin" => BurnchainIndexerName::Bitcoin,
+            "esplora" => BurnchainIndexerName::Esplora,
+            _ => panic!(
+                "Bad burnchain indexer name supplied in configuration file: {}",
+                s
+            ),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -1903,6 +2214,12 @@ impl NodeConfig {
             fault_injection_hide_blocks: false,
             chain_liveness_poll_time_secs: 300,
             stacker_dbs: vec![],
+            index_data_map_keys: false,
+            prune_reward_cycles: None,
+            marf_hash_threads: 1,
+            packed_block_store: false,
+            compress_blocks: false,
+            stacker_key: None,
         }
     }
 
@@ -1989,14 +2306,17 @@ impl NodeConfig {
             TrieHashCalculationMode::Immediate
         };
 
-        MARFOpenOpts::new(
+        let mut opts = MARFOpenOpts::new(
             hash_mode,
             &self
                 .marf_cache_strategy
                 .as_ref()
                 .unwrap_or(&"noop".to_string()),
             false,
-        )
+        );
+        opts.index_data_map_keys = self.index_data_map_keys;
+        opts.hash_threads = self.marf_hash_threads;
+        opts
     }
 }
 
@@ -2113,6 +2433,21 @@ pub struct ConnectionOptionsFile {
     pub force_disconnect_interval: Option<u64>,
     pub antientropy_public: Option<bool>,
     pub private_neighbors: Option<bool>,
+    pub auth_token: Option<String>,
+    pub p2p_encryption: Option<bool>,
+    pub max_tx_announcements_per_sec: Option<u64>,
+    pub p2p_capture_path: Option<String>,
+    pub p2p_capture_max_bytes: Option<u64>,
+    pub p2p_capture_max_files: Option<u32>,
+    pub socks5_proxy: Option<String>,
+    pub max_p2p_inbound_bandwidth: Option<u64>,
+    pub max_p2p_outbound_bandwidth: Option<u64>,
+    pub max_p2p_peer_inbound_bandwidth: Option<u64>,
+    pub max_p2p_peer_outbound_bandwidth: Option<u64>,
+    pub max_http_inbound_bandwidth: Option<u64>,
+    pub max_http_outbound_bandwidth: Option<u64>,
+    pub max_http_peer_inbound_bandwidth: Option<u64>,
+    pub max_http_peer_outbound_bandwidth: Option<u64>,
 }
 
 #[derive(Clone, Deserialize, Default, Debug)]
@@ -2146,6 +2481,19 @@ pub struct NodeConfigFile {
     pub chain_liveness_poll_time_secs: Option<u64>,
     /// Stacker DBs we replicate
     pub stacker_dbs: Option<Vec<String>>,
+    /// Maintain an index of the data map keys written by each contract
+    pub index_data_map_keys: Option<bool>,
+    /// Number of recent reward cycles of state to keep; all older state is pruned at boot and at
+    /// the start of each reward cycle
+    pub prune_reward_cycles: Option<u64>,
+    /// Number of threads to calculate MARF trie hashes with
+    pub marf_hash_threads: Option<usize>,
+    /// Store blocks in a packed, indexed file
+    pub packed_block_store: Option<bool>,
+    /// Compress blocks in a packed block store
+    pub compress_blocks: Option<bool>,
+    /// Hex-encoded secret key of the stacker whose stack-stx operations can be sent over RPC
+    pub stacker_key: Option<String>,
 }
 
 #[derive(Clone, Deserialize, Default, Debug)]
@@ -42,11 +42,20 @@ use std::{env, panic, process};
 
 use backtrace::Backtrace;
 use pico_args::Arguments;
+use stacks::burnchains::bitcoin::address::BitcoinAddress;
+use stacks::burnchains::{Address, Txid};
 use stacks::chainstate::burn::db::sortdb::SortitionDB;
 use stacks::chainstate::burn::operations::leader_block_commit::RewardSetInfo;
+use stacks::chainstate::burn::operations::StackStxOp;
 use stacks::chainstate::coordinator::{get_next_recipients, OnChainRewardSetProvider};
+use stacks::chainstate::snapshot::{import_snapshot, SnapshotPaths};
 use stacks::chainstate::stacks::address::PoxAddress;
 use stacks::chainstate::stacks::db::StacksChainState;
+use stacks::chainstate::stacks::{StacksPrivateKey, StacksPublicKey};
+use stacks_common::address::{
+    AddressHashMode, C32_ADDRESS_VERSION_MAINNET_SINGLESIG, C32_ADDRESS_VERSION_TESTNET_SINGLESIG,
+};
+use stacks_common::types::chainstate::{StacksAddress, StacksBlockId};
 
 pub use self::burnchains::{
     BitcoinRegtestController, BurnchainController, BurnchainTip, MocknetController,
@@ -59,6 +68,7 @@ pub use self::run_loop::{helium, neon};
 pub use self::tenure::Tenure;
 use crate::chain_data::MinerStats;
 use crate::neon_node::{BlockMinerThread, TipCandidate};
+use crate::operations::BurnchainOpSigner;
 
 /// Implmentation of `pick_best_tip` CLI option
 fn cli_pick_best_tip(config_path: &str, at_stacks_height: Option<u64>) -> TipCandidate {
@@ -264,6 +274,109 @@ fn cli_get_miner_spend(
     spend_amount
 }
 
+/// Implementation of `stack-stx` CLI option.
+/// Sends a pre-stx operation paid for by the node's keychain, and a stack-stx operation that
+/// spends it and is signed by `stacker_key`.
+fn cli_stack_stx(
+    config_path: &str,
+    stacker_key: &str,
+    reward_addr: &str,
+    stacked_ustx: u128,
+    num_cycles: u8,
+) -> Result<(Txid, Txid), String> {
+    info!("Loading config at path {}", config_path);
+    let config = match ConfigFile::from_path(config_path) {
+        Ok(config_file) => Config::from_config_file(config_file).unwrap(),
+        Err(e) => {
+            warn!("Invalid config file: {}", e);
+            process::exit(1);
+        }
+    };
+    let stacker_key = StacksPrivateKey::from_hex(stacker_key).unwrap_or_else(|_| {
+        warn!("Invalid stacker key");
+        process::exit(1);
+    });
+    let reward_addr = BitcoinAddress::from_string(reward_addr)
+        .and_then(|addr| PoxAddress::try_from_bitcoin_address(&addr))
+        .unwrap_or_else(|| {
+            warn!("Invalid reward address: {}", reward_addr);
+            process::exit(1);
+        });
+    let sender = StacksAddress::from_public_keys(
+        if config.is_mainnet() {
+            C32_ADDRESS_VERSION_MAINNET_SINGLESIG
+        } else {
+            C32_ADDRESS_VERSION_TESTNET_SINGLESIG
+        },
+        &AddressHashMode::SerializeP2PKH,
+        1,
+        &vec![StacksPublicKey::from_private(&stacker_key)],
+    )
+    .unwrap();
+
+    let op = StackStxOp::new(&sender, &reward_addr, stacked_ustx, num_cycles);
+    if let Err(e) = op.check() {
+        warn!("Invalid stack-stx operation: {}", e);
+        process::exit(1);
+    }
+
+    let burnchain = config.get_burnchain();
+    let sortdb = SortitionDB::open(
+        &config.get_burn_db_file_path(),
+        false,
+        burnchain.pox_constants,
+    )
+    .unwrap();
+    let tip = SortitionDB::get_canonical_burn_chain_tip(sortdb.conn()).unwrap();
+    let epoch_id = SortitionDB::get_stacks_epoch(sortdb.conn(), tip.block_height)
+        .unwrap()
+        .expect("FATAL: no epoch defined")
+        .epoch_id;
+
+    let keychain = Keychain::default(config.node.seed.clone());
+    let mut payer = keychain.generate_op_signer();
+    let mut stacker = BurnchainOpSigner::new(stacker_key, false);
+    let mut btc_controller = BitcoinRegtestController::new_dummy(config);
+    btc_controller.submit_stack_stx(epoch_id, op, &mut payer, &mut stacker)
+}
+
+/// Implementation of the `--import-snapshot` option of `start`.
+/// Installs a chainstate snapshot into the node's working directory, and verifies its MARF root
+/// hashes against the Stacks header chain before the node boots from it.  If `trusted_tip` is
+/// given, the snapshot's Stacks tip must be that block.
+fn cli_import_snapshot(config: &Config, snapshot_dir: &str, trusted_tip: Option<&StacksBlockId>) {
+    let paths = SnapshotPaths {
+        chainstate: config.get_chainstate_path_str(),
+        burnchain: config.get_burnchain_path_str(),
+        spv_headers: Some(config.get_spv_headers_file_path()),
+    };
+    let burnchain = config.get_burnchain();
+    match import_snapshot(
+        snapshot_dir,
+        &paths,
+        config.is_mainnet(),
+        config.burnchain.chain_id,
+        &burnchain.pox_constants,
+        Some(config.node.get_marf_opts()),
+        trusted_tip,
+    ) {
+        Ok(manifest) => {
+            info!("Imported chainstate snapshot from {}", snapshot_dir;
+                  "reward_cycle" => manifest.reward_cycle,
+                  "burn_block_height" => manifest.burn_block_height,
+                  "stacks_tip" => %manifest.stacks_tip,
+                  "stacks_block_height" => manifest.stacks_block_height);
+        }
+        Err(e) => {
+            error!(
+                "Failed to import chainstate snapshot from {}: {}",
+                snapshot_dir, e
+            );
+            process::exit(1);
+        }
+    }
+}
+
 fn main() {
     panic::set_hook(Box::new(|panic_info| {
         error!("Process abort due to thread panic: {}", panic_info);
@@ -302,6 +415,16 @@ fn main() {
         );
     }
 
+    let import_snapshot_dir: Option<String> = args
+        .opt_value_from_str("--import-snapshot")
+        .expect("Failed to parse --import-snapshot argument");
+    let snapshot_trusted_tip: Option<StacksBlockId> = args
+        .opt_value_from_str::<_, String>("--snapshot-trusted-tip")
+        .expect("Failed to parse --snapshot-trusted-tip argument")
+        .map(|hex| {
+            StacksBlockId::from_hex(&hex).expect("Failed to parse --snapshot-trusted-tip argument")
+        });
+
     let config_file = match subcommand.as_str() {
         "mocknet" => {
             args.finish();
@@ -409,6 +532,32 @@ fn main() {
             println!("Will spend {}", spend_amount);
             process::exit(0);
         }
+        "stack-stx" => {
+            let config_path: String = args.value_from_str("--config").unwrap();
+            let stacker_key: String = args.value_from_str("--stacker-key").unwrap();
+            let reward_addr: String = args.value_from_str("--reward-addr").unwrap();
+            let stacked_ustx: u128 = args.value_from_str("--amount").unwrap();
+            let num_cycles: u8 = args.value_from_str("--cycles").unwrap();
+            args.finish();
+
+            match cli_stack_stx(
+                &config_path,
+                &stacker_key,
+                &reward_addr,
+                stacked_ustx,
+                num_cycles,
+            ) {
+                Ok((pre_stx_txid, stack_stx_txid)) => {
+                    println!("Sent pre-stx {}", &pre_stx_txid);
+                    println!("Sent stack-stx {}", &stack_stx_txid);
+                    process::exit(0);
+                }
+                Err(e) => {
+                    println!("Failed to send stack-stx: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
         _ => {
             print_help();
             return;
@@ -426,6 +575,10 @@ fn main() {
     debug!("burnchain configuration {:?}", &conf.burnchain);
     debug!("connection configuration {:?}", &conf.connection_options);
 
+    if let Some(snapshot_dir) = import_snapshot_dir {
+        cli_import_snapshot(&conf, &snapshot_dir, snapshot_trusted_tip.as_ref());
+    }
+
     let num_round: u64 = 0; // Infinite number of rounds
 
     if conf.burnchain.mode == "helium" || conf.burnchain.mode == "mocknet" {
@@ -497,12 +650,29 @@ key-for-seed\tOutput the associated secret key for a burnchain signer created wi
 \t\tCan be passed a config file for the seed via the `--config <file>` option *or* by supplying the hex seed on
 \t\tthe command line directly.
 
+stack-stx\tSend a stack-stx operation to the burnchain, along with the pre-stx operation it spends.
+\t\tThe pre-stx operation is paid for by the node's burnchain signer.
+\t\tArguments:
+\t\t  --config: path of the node's config.
+\t\t  --stacker-key: hex-encoded secret key of the stacker.
+\t\t  --reward-addr: Bitcoin address to receive PoX rewards.
+\t\t  --amount: number of uSTX to lock.
+\t\t  --cycles: number of reward cycles to lock for.
+\t\tExample:
+\t\t  stacks-node stack-stx --config /path/to/config.toml --stacker-key <hex> --reward-addr <btc-addr> --amount 100000000000 --cycles 6
+
 help\t\tDisplay this help.
 
 OPTIONAL ARGUMENTS:
 
 \t\t--mine-at-height=<height>: optional argument for a miner to not attempt mining until Stacks block has sync'ed to <height>
 
+\t\t--import-snapshot=<dir>: before starting, import the chainstate snapshot in <dir> (made with `stacks-inspect export-snapshot`)
+\t\tinto a fresh working directory, and verify its MARF root hashes against its Stacks block headers
+
+\t\t--snapshot-trusted-tip=<index-block-hash>: with --import-snapshot, only import the snapshot if its Stacks tip
+\t\tis this block, obtained from a source you trust.  This authenticates the snapshot's Stacks chain and Clarity state.
+
 ", argv[0]);
 }
 
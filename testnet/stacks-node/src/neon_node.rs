@@ -145,7 +145,7 @@ use std::default::Default;
 use std::io::{Read, Write};
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::{Receiver, SyncSender, TrySendError};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TryRecvError, TrySendError};
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
 use std::time::Duration;
@@ -162,7 +162,7 @@ use stacks::chainstate::burn::operations::leader_block_commit::{
     RewardSetInfo, BURN_BLOCK_MINED_AT_MODULUS,
 };
 use stacks::chainstate::burn::operations::{
-    BlockstackOperationType, LeaderBlockCommitOp, LeaderKeyRegisterOp,
+    BlockstackOperationType, LeaderBlockCommitOp, LeaderKeyRegisterOp, StackStxOp,
 };
 use stacks::chainstate::burn::{BlockSnapshot, ConsensusHash};
 use stacks::chainstate::coordinator::comm::CoordinatorChannels;
@@ -193,7 +193,8 @@ use stacks::net::p2p::PeerNetwork;
 use stacks::net::relay::Relayer;
 use stacks::net::stackerdb::{StackerDBConfig, StackerDBSync, StackerDBs};
 use stacks::net::{
-    Error as NetError, NetworkResult, PeerNetworkComms, RPCHandlerArgs, ServiceFlags,
+    BurnchainOpStatus, BurnchainOpSubmitter, Error as NetError, NetworkResult, PeerNetworkComms,
+    RPCHandlerArgs, ServiceFlags,
 };
 use stacks::util_lib::strings::{UrlString, VecDisplay};
 use stacks_common::codec::StacksMessageCodec;
@@ -215,12 +216,15 @@ use crate::burnchains::bitcoin_regtest_controller::{
 use crate::burnchains::make_bitcoin_indexer;
 use crate::chain_data::MinerStats;
 use crate::config::MinerConfig;
+use crate::operations::BurnchainOpSigner;
 use crate::run_loop::neon::{Counters, RunLoop};
 use crate::run_loop::RegisteredKey;
 use crate::syncctl::PoxSyncWatchdogComms;
 use crate::ChainTip;
 
 pub const RELAYER_MAX_BUFFER: usize = 100;
+/// How many burnchain operations submitted over RPC have their status remembered
+const MAX_TRACKED_BURNCHAIN_OPS: usize = 256;
 const VRF_MOCK_MINER_KEY: u64 = 1;
 
 pub const BLOCK_PROCESSOR_STACK_SIZE: usize = 32 * 1024 * 1024; // 32 MB
@@ -272,6 +276,9 @@ pub enum RelayerDirective {
     RunTenure(RegisteredKey, BlockSnapshot, u128), // (vrf key, chain tip, time of issuance in ms)
     /// Try to register a VRF public key
     RegisterKey(BlockSnapshot),
+    /// Submit a stack-stx operation, signed by the configured stacker key, and send back the
+    /// (pre-stx, stack-stx) txids or an error
+    SubmitStackStx(StackStxOp, SyncSender<Result<(Txid, Txid), String>>),
     /// Stop the relayer thread
     Exit,
 }
@@ -667,6 +674,102 @@ impl Globals {
     }
 }
 
+/// A burnchain operation handed to the relayer thread over RPC
+enum SubmittedBurnchainOp {
+    /// The relayer thread will send the result of the operation on this channel
+    Pending(Receiver<Result<(Txid, Txid), String>>),
+    /// The relayer thread has sent (or failed to send) the operation
+    Done(BurnchainOpStatus),
+}
+
+/// Burnchain operations handed to the relayer thread, by request ID
+#[derive(Default)]
+struct SubmittedBurnchainOps {
+    next_request_id: u64,
+    ops: BTreeMap<u64, SubmittedBurnchainOp>,
+}
+
+/// Hands burnchain operations requested over RPC to the relayer thread, which owns the burnchain
+/// controller.  Submission never waits on the relayer thread; callers poll for the result by
+/// request ID instead.
+#[derive(Clone)]
+pub struct RelayerOpSubmitter {
+    /// Writer endpoint to the relayer thread
+    relay_send: SyncSender<RelayerDirective>,
+    /// Address of the configured stacker key
+    stacker_address: Option<StacksAddress>,
+    /// The most recent `MAX_TRACKED_BURNCHAIN_OPS` operations handed to the relayer thread
+    submitted: Arc<Mutex<SubmittedBurnchainOps>>,
+}
+
+impl RelayerOpSubmitter {
+    pub fn new(globals: &Globals, config: &Config) -> RelayerOpSubmitter {
+        RelayerOpSubmitter {
+            relay_send: globals.relay_send.clone(),
+            stacker_address: config.get_stacker_address(),
+            submitted: Arc::new(Mutex::new(SubmittedBurnchainOps::default())),
+        }
+    }
+}
+
+impl BurnchainOpSubmitter for RelayerOpSubmitter {
+    fn stacker_address(&self) -> Option<StacksAddress> {
+        self.stacker_address.clone()
+    }
+
+    fn submit_stack_stx(&self, op: StackStxOp) -> Result<u64, String> {
+        let (result_send, result_recv) = sync_channel(1);
+        let mut submitted = self
+            .submitted
+            .lock()
+            .expect("FATAL: submitted burnchain ops lock poisoned");
+        self.relay_send
+            .try_send(RelayerDirective::SubmitStackStx(op, result_send))
+            .map_err(|e| match e {
+                TrySendError::Full(_) => "relayer thread is busy; try again later".to_string(),
+                TrySendError::Disconnected(_) => "relayer thread is not running".to_string(),
+            })?;
+
+        let request_id = submitted.next_request_id;
+        submitted.next_request_id = submitted.next_request_id.wrapping_add(1);
+        submitted
+            .ops
+            .insert(request_id, SubmittedBurnchainOp::Pending(result_recv));
+        while submitted.ops.len() > MAX_TRACKED_BURNCHAIN_OPS {
+            let Some(oldest) = submitted.ops.keys().next().copied() else {
+                break;
+            };
+            submitted.ops.remove(&oldest);
+        }
+        Ok(request_id)
+    }
+
+    fn get_stack_stx_status(&self, request_id: u64) -> Option<BurnchainOpStatus> {
+        let mut submitted = self
+            .submitted
+            .lock()
+            .expect("FATAL: submitted burnchain ops lock poisoned");
+        let op = submitted.ops.get_mut(&request_id)?;
+        if let SubmittedBurnchainOp::Pending(result_recv) = op {
+            let status = match result_recv.try_recv() {
+                Ok(Ok((pre_stx_txid, stack_stx_txid))) => {
+                    BurnchainOpStatus::Sent(pre_stx_txid, stack_stx_txid)
+                }
+                Ok(Err(e)) => BurnchainOpStatus::Failed(e),
+                Err(TryRecvError::Empty) => return Some(BurnchainOpStatus::Pending),
+                Err(TryRecvError::Disconnected) => BurnchainOpStatus::Failed(
+                    "relayer thread exited before sending the operation".to_string(),
+                ),
+            };
+            *op = SubmittedBurnchainOp::Done(status);
+        }
+        match op {
+            SubmittedBurnchainOp::Pending(_) => Some(BurnchainOpStatus::Pending),
+            SubmittedBurnchainOp::Done(status) => Some(status.clone()),
+        }
+    }
+}
+
 /// Node implementation for both miners and followers.
 /// This struct is used to set up the node proper and launch the p2p thread and relayer thread.
 /// It is further used by the main thread to communicate with these two threads.
@@ -3681,6 +3784,52 @@ impl RelayerThread {
         }
     }
 
+    /// Create and broadcast a stack-stx operation signed by the configured stacker key, along
+    /// with the pre-stx operation it spends.  The node's keychain pays for the pre-stx operation.
+    /// Returns the (pre-stx, stack-stx) txids.
+    pub fn submit_stack_stx(&mut self, op: StackStxOp) -> Result<(Txid, Txid), String> {
+        let Some(stacker_key) = self.config.node.stacker_key.clone() else {
+            return Err("no stacker key is configured".to_string());
+        };
+        // the stack-stx op spends the pre-stx output sent to `op.sender`, so only the stacker
+        // key's own address can stack
+        if self.config.get_stacker_address().as_ref() != Some(&op.sender) {
+            return Err(format!("{} is not the configured stacker", &op.sender));
+        }
+
+        let burn_height = SortitionDB::get_canonical_burn_chain_tip(self.sortdb_ref().conn())
+            .expect("FATAL: failed to query sortition DB for canonical burn chain tip")
+            .block_height;
+        let cur_epoch = SortitionDB::get_stacks_epoch(self.sortdb_ref().conn(), burn_height)
+            .expect("FATAL: failed to query sortition DB")
+            .expect("FATAL: no epoch defined")
+            .epoch_id;
+
+        let sender = op.sender.clone();
+        let mut payer = self.keychain.generate_op_signer();
+        let mut stacker = BurnchainOpSigner::new(stacker_key, false);
+        match self
+            .bitcoin_controller
+            .submit_stack_stx(cur_epoch, op, &mut payer, &mut stacker)
+        {
+            Ok((pre_stx_txid, stack_stx_txid)) => {
+                info!(
+                    "Relayer: submitted stack-stx for {}", &sender;
+                    "pre_stx_txid" => %pre_stx_txid,
+                    "stack_stx_txid" => %stack_stx_txid
+                );
+                Ok((pre_stx_txid, stack_stx_txid))
+            }
+            Err(e) => {
+                warn!(
+                    "Relayer: failed to submit stack-stx for {}: {}",
+                    &sender, &e
+                );
+                Err(e)
+            }
+        }
+    }
+
     /// Remove any block state we've mined for the given burnchain height.
     /// Return the filtered `last_mined_blocks`
     fn clear_stale_mined_blocks(burn_height: u64, last_mined_blocks: MinedBlocks) -> MinedBlocks {
@@ -4235,6 +4384,14 @@ impl RelayerThread {
                 debug!("Relayer: directive Ran tenure");
                 true
             }
+            RelayerDirective::SubmitStackStx(op, result_send) => {
+                debug!("Relayer: directive Submit stack-stx");
+                let result = self.submit_stack_stx(op);
+                // the requester may have given up waiting
+                let _ = result_send.send(result);
+                debug!("Relayer: directive Submitted stack-stx");
+                true
+            }
             RelayerDirective::Exit => false,
         };
         if !continue_running {
@@ -4384,6 +4541,8 @@ pub struct PeerThread {
     num_download_passes: u64,
     /// last burnchain block seen in the PeerNetwork's chain view since the last run
     last_burn_block_height: u64,
+    /// hands burnchain operations requested over RPC to the relayer thread
+    op_submitter: RelayerOpSubmitter,
 }
 
 impl PeerThread {
@@ -4438,10 +4597,13 @@ impl PeerThread {
 
         let poll_timeout = cmp::min(5000, config.miner.first_attempt_time_ms / 2);
 
+        let globals = runloop.get_globals();
+        let op_submitter = RelayerOpSubmitter::new(&globals, &config);
+
         PeerThread {
             config,
             net: Some(net),
-            globals: runloop.get_globals(),
+            globals,
             poll_timeout,
             sortdb: Some(sortdb),
             chainstate: Some(chainstate),
@@ -4451,6 +4613,7 @@ impl PeerThread {
             num_inv_sync_passes: 0,
             num_download_passes: 0,
             last_burn_block_height: 0,
+            op_submitter,
         }
     }
 
@@ -4531,6 +4694,7 @@ impl PeerThread {
         });
 
         // do one pass
+        let op_submitter = self.op_submitter.clone();
         let p2p_res = self.with_chainstate(|p2p_thread, sortdb, chainstate, mempool| {
             // NOTE: handler_args must be created such that it outlives the inner net.run() call and
             // doesn't ref anything within p2p_thread.
@@ -4546,6 +4710,7 @@ impl PeerThread {
                 cost_estimator: Some(cost_estimator.as_ref()),
                 cost_metric: Some(cost_metric.as_ref()),
                 fee_estimator: fee_estimator.map(|boxed_estimator| boxed_estimator.as_ref()),
+                burnchain_op_submitter: Some(&op_submitter),
                 ..RPCHandlerArgs::default()
             };
             p2p_thread.with_network(|_, net| {
@@ -4801,16 +4966,20 @@ impl StacksNode {
             tx.commit().unwrap();
         }
 
-        // update services to indicate we can support mempool sync and stackerdb
+        // update services to indicate we can support mempool sync, stackerdb, compact blocks, and
+        // transaction announcements, and possibly encrypted sessions
         {
+            let mut services = (ServiceFlags::RPC as u16)
+                | (ServiceFlags::RELAY as u16)
+                | (ServiceFlags::STACKERDB as u16)
+                | (ServiceFlags::COMPACT_BLOCKS as u16)
+                | (ServiceFlags::TX_ANNOUNCE as u16);
+            if config.connection_options.p2p_encryption {
+                services |= ServiceFlags::ENCRYPTION as u16;
+            }
+
             let mut tx = peerdb.tx_begin().unwrap();
-            PeerDB::set_local_services(
-                &mut tx,
-                (ServiceFlags::RPC as u16)
-                    | (ServiceFlags::RELAY as u16)
-                    | (ServiceFlags::STACKERDB as u16),
-            )
-            .unwrap();
+            PeerDB::set_local_services(&mut tx, services).unwrap();
             tx.commit().unwrap();
         }
 
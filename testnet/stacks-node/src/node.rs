@@ -912,15 +912,13 @@ impl Node {
         // Handle events
         let receipts = processed_block.tx_receipts;
         let metadata = processed_block.header;
-        let block: StacksBlock = {
-            let block_path = StacksChainState::get_block_path(
-                &self.chain_state.blocks_path,
-                &metadata.consensus_hash,
-                &metadata.anchored_header.block_hash(),
-            )
-            .unwrap();
-            StacksChainState::consensus_load(&block_path).unwrap()
-        };
+        let block: StacksBlock = StacksChainState::load_block(
+            &self.chain_state.blocks_path,
+            &metadata.consensus_hash,
+            &metadata.anchored_header.block_hash(),
+        )
+        .unwrap()
+        .unwrap();
 
         let chain_tip = ChainTip {
             metadata,
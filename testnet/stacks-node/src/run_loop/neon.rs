@@ -1,3 +1,4 @@
+use std::path::PathBuf;
 #[cfg(test)]
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -466,7 +467,11 @@ impl RunLoop {
             get_bulk_initial_names: Some(Box::new(move || get_names(use_test_genesis_data))),
         };
 
-        let (chain_state_db, receipts) = StacksChainState::open_and_exec(
+        if self.config.node.packed_block_store {
+            self.setup_packed_block_store();
+        }
+
+        let (mut chain_state_db, receipts) = StacksChainState::open_and_exec(
             self.config.is_mainnet(),
             self.config.burnchain.chain_id,
             &self.config.get_chainstate_path_str(),
@@ -480,9 +485,91 @@ impl RunLoop {
             &burnchain_config.pox_constants,
             &receipts,
         );
+
+        if let Some(prune_reward_cycles) = self.config.node.prune_reward_cycles {
+            self.prune_chainstate(&mut chain_state_db, burnchain_config, prune_reward_cycles);
+        }
         chain_state_db
     }
 
+    /// Make sure a new chainstate stores its blocks in a packed block store.
+    /// Panics if the chainstate already has blocks stored one file per block.
+    fn setup_packed_block_store(&self) {
+        let chainstate_path = self.config.get_chainstate_path_str();
+        let blocks_path = StacksChainState::blocks_path(PathBuf::from(&chainstate_path));
+        let res = StacksChainState::make_chainstate_dirs(&chainstate_path).and_then(|_| {
+            StacksChainState::create_packed_block_store(
+                &blocks_path.to_string_lossy(),
+                self.config.node.compress_blocks,
+            )
+        });
+        if let Err(e) = res {
+            error!(
+                "Failed to set up packed block store: {:?}. To convert an existing chainstate, stop the node and run `stacks-inspect migrate-block-store {}`",
+                &e, &chainstate_path
+            );
+            panic!("FATAL: failed to set up packed block store");
+        }
+    }
+
+    /// Discard all but the last `prune_reward_cycles` reward cycles of chainstate, and compact
+    /// the Clarity MARF's trie file, including the tries that the chains coordinator pruned while
+    /// the node was running (even if there is nothing new to prune).
+    /// This must happen before any other thread opens the chainstate, since compaction replaces
+    /// the trie file out from under any other open handle.
+    /// NOTE: once pruned, Clarity state below the prune height is gone, so the node cannot process
+    /// a block that uses `at-block` to read pruned state.  If it receives such a block, the chains
+    /// coordinator halts the node, which must then be re-synced without pruning.
+    fn prune_chainstate(
+        &self,
+        chain_state_db: &mut StacksChainState,
+        burnchain_config: &Burnchain,
+        prune_reward_cycles: u64,
+    ) {
+        let sortdb = SortitionDB::open(
+            &self.config.get_burn_db_file_path(),
+            false,
+            burnchain_config.pox_constants.clone(),
+        )
+        .expect("FATAL: failed to open sortition DB");
+
+        match chain_state_db.prune_historical_state(
+            &sortdb,
+            burnchain_config,
+            prune_reward_cycles,
+            self.config.miner.max_reorg_depth,
+            true,
+        ) {
+            Ok(Some(stats)) => {
+                info!(
+                    "Pruned chainstate below Stacks block height {}",
+                    stats.pruned_below_height;
+                    "prune_reward_cycles" => prune_reward_cycles,
+                    "blobs_size_before" => stats.tries.blobs_size_before,
+                    "blobs_size_after" => stats.tries.blobs_size_after
+                );
+            }
+            Ok(None) => match chain_state_db.compact_pruned_state() {
+                Ok(Some(tries)) => {
+                    info!(
+                        "Compacted pruned chainstate";
+                        "blobs_size_before" => tries.blobs_size_before,
+                        "blobs_size_after" => tries.blobs_size_after
+                    );
+                }
+                Ok(None) => {
+                    debug!("No chainstate to prune");
+                }
+                Err(e) => {
+                    panic!("FATAL: failed to compact pruned chainstate: {:?}", &e);
+                }
+            },
+            Err(e) => {
+                panic!("FATAL: failed to prune chainstate: {:?}", &e);
+            }
+        }
+    }
+
     /// Instantiate the Stacks chain state and start the chains coordinator thread.
     /// Returns the coordinator thread handle, and the receiving end of the coordinator's atlas
     /// attachment channel.
@@ -538,6 +625,8 @@ impl RunLoop {
                     require_affirmed_anchor_blocks: moved_config
                         .node
                         .require_affirmed_anchor_blocks,
+                    prune_reward_cycles: moved_config.node.prune_reward_cycles,
+                    prune_max_reorg_depth: moved_config.miner.max_reorg_depth,
                     ..ChainsCoordinatorConfig::new()
                 };
                 ChainsCoordinator::run(
@@ -666,7 +666,8 @@ fn integration_test_get_info() {
                 let body = CallReadOnlyRequestBody {
                     sender: "'SP139Q3N9RXCJCD1XVA4N5RYWQ5K9XQ0T9PKQ8EE5".into(),
                     sponsor: None,
-                    arguments: vec![Value::UInt(3).serialize_to_hex().unwrap()]
+                    arguments: vec![Value::UInt(3).serialize_to_hex().unwrap()],
+                    json_arguments: None
                 };
 
                 let res = client.post(&path)
@@ -688,7 +689,8 @@ fn integration_test_get_info() {
                 let body = CallReadOnlyRequestBody {
                     sender: "'SP139Q3N9RXCJCD1XVA4N5RYWQ5K9XQ0T9PKQ8EE5".into(),
                     sponsor: None,
-                    arguments: vec![]
+                    arguments: vec![],
+                    json_arguments: None
                 };
 
                 let res = client.post(&path)
@@ -715,7 +717,8 @@ fn integration_test_get_info() {
                 let body = CallReadOnlyRequestBody {
                     sender: "'SP139Q3N9RXCJCD1XVA4N5RYWQ5K9XQ0T9PKQ8EE5".into(),
                     sponsor: None,
-                    arguments: vec![]
+                    arguments: vec![],
+                    json_arguments: None
                 };
 
                 let res = client.post(&path)
@@ -734,7 +737,8 @@ fn integration_test_get_info() {
                 let body = CallReadOnlyRequestBody {
                     sender: "'SP139Q3N9RXCJCD1XVA4N5RYWQ5K9XQ0T9PKQ8EE5".into(),
                     sponsor: None,
-                    arguments: vec![Value::UInt(3).serialize_to_hex().unwrap()]
+                    arguments: vec![Value::UInt(3).serialize_to_hex().unwrap()],
+                    json_arguments: None
                 };
 
                 let res = client.post(&path)
@@ -757,7 +761,8 @@ fn integration_test_get_info() {
                 let body = CallReadOnlyRequestBody {
                     sender: "'SP139Q3N9RXCJCD1XVA4N5RYWQ5K9XQ0T9PKQ8EE5".into(),
                     sponsor: None,
-                    arguments: vec![Value::UInt(100).serialize_to_hex().unwrap()]
+                    arguments: vec![Value::UInt(100).serialize_to_hex().unwrap()],
+                    json_arguments: None
                 };
 
                 let res = client.post(&path)
@@ -776,7 +781,8 @@ fn integration_test_get_info() {
                 let body = CallReadOnlyRequestBody {
                     sender: "'SP139Q3N9RXCJCD1XVA4N5RYWQ5K9XQ0T9PKQ8EE5".into(),
                     sponsor: None,
-                    arguments: vec![]
+                    arguments: vec![],
+                    json_arguments: None
                 };
 
                 let res = client.post(&path)